@@ -0,0 +1,388 @@
+//! Active abilities of units besides attacking. An ability is checked with `validate_ability`
+//! when it is ordered and applied by the state machine with `use_ability`. Cooldowns, Overdrive
+//! and smoke run out at the start of the owner's turns in `start_turn`.
+
+use crate::components::abilities::{Abilities, AbilityKind, BlocksVision, Overdrive};
+use crate::components::hexagon::Hexagon;
+use crate::components::node_template::NodeTemplate;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::rules::Ruleset;
+use crate::systems::hexgrid::is_vision_blocked;
+use gdnative::core_types::Color;
+use legion::{Entity, EntityStore, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+
+/// Range Sprint adds for the turn.
+pub const SPRINT_RANGE: i32 = 2;
+/// Damage Overdrive adds until the owner's next turn.
+pub const OVERDRIVE_DAMAGE: i32 = 3;
+/// Integrity a unit loses by using Overdrive.
+pub const OVERDRIVE_SELF_DAMAGE: i32 = 2;
+/// Turns of the owner a smoke cloud lasts.
+pub const SMOKE_ROUNDS: u8 = 2;
+const SMOKE_SCENE: &str = "res://Smoke.tscn";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbilityError {
+    /// The unit has no ability with that index.
+    UnknownAbility,
+    /// Turns left until the ability can be used again.
+    CoolingDown(u8),
+    NotEnoughActionPoints,
+    InvalidTarget,
+    /// Overdrive would destroy the unit.
+    NotEnoughIntegrity,
+}
+
+/// A smoke cloud of an ability in a savegame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedSmoke {
+    pub position: Hexagon,
+    pub player: usize,
+    pub rounds_remaining: u8,
+}
+
+/// Places a smoke cloud of `player` on `hexagon`, with a Godot node if `with_node` is set.
+pub fn spawn_smoke(
+    world: &mut World,
+    hexagon: Hexagon,
+    player: usize,
+    rounds_remaining: u8,
+    with_node: bool,
+) -> Entity {
+    let entity = world.push((
+        hexagon,
+        BlocksVision {
+            player: Some(player),
+            rounds_remaining,
+        },
+    ));
+    if with_node {
+        if let Some(mut entry) = world.entry(entity) {
+            entry.add_component(
+                NodeTemplate::new(SMOKE_SCENE)
+                    .z_index(2)
+                    // Units under the smoke stay visible to their owner.
+                    .modulate(Color::rgba(1.0, 1.0, 1.0, 0.8))
+                    .group("effects")
+                    .parent("Effects")
+                    .y_sort(),
+            );
+        }
+    }
+    entity
+}
+
+/// The smoke clouds of abilities, ordered by their position. The smoke of ground effects is
+/// saved with the ground effects.
+pub fn smoke_clouds<S: EntityStore>(world: &S) -> Vec<SavedSmoke> {
+    let mut clouds: Vec<SavedSmoke> = <(&Hexagon, &BlocksVision)>::query()
+        .iter(world)
+        .filter_map(|(position, smoke)| {
+            smoke.player.map(|player| SavedSmoke {
+                position: *position,
+                player,
+                rounds_remaining: smoke.rounds_remaining,
+            })
+        })
+        .collect();
+    clouds.sort_by_key(|smoke| (smoke.position.get_q(), smoke.position.get_r()));
+    clouds
+}
+
+/// Checks that the unit can use its ability `index` on `target` right now and returns its kind.
+pub fn validate_ability<S: EntityStore>(
+    world: &S,
+    rules: &Ruleset,
+    entity: Entity,
+    index: usize,
+    target: Hexagon,
+) -> Result<AbilityKind, AbilityError> {
+    let entry = world
+        .entry_ref(entity)
+        .map_err(|_| AbilityError::UnknownAbility)?;
+    let ability = entry
+        .get_component::<Abilities>()
+        .ok()
+        .and_then(|abilities| abilities.0.get(index).copied())
+        .ok_or(AbilityError::UnknownAbility)?;
+    let (unit, position) = match (
+        entry.get_component::<Unit>(),
+        entry.get_component::<Hexagon>(),
+    ) {
+        (Ok(unit), Ok(position)) => (*unit, *position),
+        _ => return Err(AbilityError::UnknownAbility),
+    };
+    if ability.cooldown_remaining > 0 {
+        return Err(AbilityError::CoolingDown(ability.cooldown_remaining));
+    }
+    if rules.action_points && unit.action_points < rules.ability_cost {
+        return Err(AbilityError::NotEnoughActionPoints);
+    }
+    match ability.kind {
+        AbilityKind::Sprint => {}
+        AbilityKind::Smoke => {
+            if !position.is_neighbour(&target) || is_vision_blocked(&target, world) {
+                return Err(AbilityError::InvalidTarget);
+            }
+        }
+        AbilityKind::Overdrive => {
+            if unit.integrity <= OVERDRIVE_SELF_DAMAGE {
+                return Err(AbilityError::NotEnoughIntegrity);
+            }
+        }
+    }
+    Ok(ability.kind)
+}
+
+/// Uses the ability `index` of the unit on `target`: applies its effect, pays the action points
+/// if the rules use them and starts the cooldown.
+pub fn use_ability(
+    world: &mut World,
+    rules: &Ruleset,
+    entity: Entity,
+    index: usize,
+    target: Hexagon,
+) -> Result<AbilityKind, AbilityError> {
+    let kind = validate_ability(world, rules, entity, index, target)?;
+    let player = {
+        let mut entry = world.entry(entity).ok_or(AbilityError::UnknownAbility)?;
+        if let Ok(abilities) = entry.get_component_mut::<Abilities>() {
+            abilities.0[index].cooldown_remaining = kind.cooldown();
+        }
+        if let Ok(unit) = entry.get_component_mut::<Unit>() {
+            if rules.action_points {
+                unit.action_points -= rules.ability_cost;
+            }
+            match kind {
+                AbilityKind::Sprint => unit.spend_movement(-SPRINT_RANGE, rules),
+                AbilityKind::Overdrive => {
+                    unit.damage += OVERDRIVE_DAMAGE;
+                    unit.integrity -= OVERDRIVE_SELF_DAMAGE;
+                }
+                AbilityKind::Smoke => {}
+            }
+        }
+        if kind == AbilityKind::Overdrive {
+            let bonus = entry
+                .get_component::<Overdrive>()
+                .map_or(0, |overdrive| overdrive.0);
+            entry.add_component(Overdrive(bonus + OVERDRIVE_DAMAGE));
+        }
+        entry
+            .get_component::<PlayerComponent>()
+            .map_or(0, |player| player.0)
+    };
+    if kind == AbilityKind::Smoke {
+        spawn_smoke(world, target, player, SMOKE_ROUNDS, true);
+    }
+    Ok(kind)
+}
+
+/// Counts down the cooldowns and smoke clouds of `player` at the start of their turn and takes
+/// back the damage of Overdrive. Smoke that runs out is removed together with its node.
+pub fn start_turn(world: &mut World, player: usize) {
+    for (owner, abilities) in <(&PlayerComponent, &mut Abilities)>::query().iter_mut(world) {
+        if owner.0 != player {
+            continue;
+        }
+        for ability in abilities.0.iter_mut() {
+            ability.cooldown_remaining = ability.cooldown_remaining.saturating_sub(1);
+        }
+    }
+
+    let mut overdriven = Vec::new();
+    for (entity, owner, unit, overdrive) in
+        <(Entity, &PlayerComponent, &mut Unit, &Overdrive)>::query().iter_mut(world)
+    {
+        if owner.0 == player {
+            unit.damage -= overdrive.0;
+            overdriven.push(*entity);
+        }
+    }
+    for entity in overdriven {
+        if let Some(mut entry) = world.entry(entity) {
+            entry.remove_component::<Overdrive>();
+        }
+    }
+
+    let mut expired = Vec::new();
+    for (entity, smoke) in <(Entity, &mut BlocksVision)>::query().iter_mut(world) {
+        if smoke.player != Some(player) {
+            continue;
+        }
+        smoke.rounds_remaining = smoke.rounds_remaining.saturating_sub(1);
+        if smoke.rounds_remaining == 0 {
+            expired.push(*entity);
+        }
+    }
+    for entity in expired {
+        world.remove(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::abilities::AbilityInstance;
+    use crate::map_bounds::MapBounds;
+    use crate::systems::hexgrid::is_line_of_sight_clear;
+
+    fn spawn(world: &mut World, kinds: &[AbilityKind]) -> Entity {
+        world.push((
+            PlayerComponent(0),
+            Hexagon::zero(),
+            Unit::new(10, 4, 1, 1, 0, 3, 3, 1),
+            Abilities(kinds.iter().copied().map(AbilityInstance::new).collect()),
+        ))
+    }
+
+    fn unit(world: &World, entity: Entity) -> Unit {
+        *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap()
+    }
+
+    fn smoke_count(world: &World) -> usize {
+        <&BlocksVision>::query().iter(world).count()
+    }
+
+    #[test]
+    fn sprint_adds_range_for_the_turn() {
+        let mut world = World::default();
+        let entity = spawn(&mut world, &[AbilityKind::Sprint]);
+        let rules = Ruleset::default();
+
+        assert_eq!(
+            use_ability(&mut world, &rules, entity, 0, Hexagon::zero()),
+            Ok(AbilityKind::Sprint)
+        );
+        assert_eq!(unit(&world, entity).remaining_range, 3 + SPRINT_RANGE);
+
+        let action_points = Ruleset {
+            action_points: true,
+            ability_cost: 1,
+            ..Ruleset::default()
+        };
+        let entity = spawn(&mut world, &[AbilityKind::Sprint]);
+        if let Some(mut entry) = world.entry(entity) {
+            entry.get_component_mut::<Unit>().unwrap().action_points = 2;
+        }
+        use_ability(&mut world, &action_points, entity, 0, Hexagon::zero()).unwrap();
+        assert_eq!(unit(&world, entity).action_points, 2 - 1 + SPRINT_RANGE);
+    }
+
+    #[test]
+    fn overdrive_trades_integrity_for_damage_until_the_next_turn() {
+        let mut world = World::default();
+        let entity = spawn(&mut world, &[AbilityKind::Overdrive]);
+        let rules = Ruleset::default();
+
+        use_ability(&mut world, &rules, entity, 0, Hexagon::zero()).unwrap();
+        let overdriven = unit(&world, entity);
+        assert_eq!(overdriven.damage, 4 + OVERDRIVE_DAMAGE);
+        assert_eq!(overdriven.integrity, 10 - OVERDRIVE_SELF_DAMAGE);
+
+        start_turn(&mut world, 1);
+        assert_eq!(unit(&world, entity).damage, 4 + OVERDRIVE_DAMAGE);
+        start_turn(&mut world, 0);
+        assert_eq!(unit(&world, entity).damage, 4);
+
+        if let Some(mut entry) = world.entry(entity) {
+            entry.get_component_mut::<Unit>().unwrap().integrity = OVERDRIVE_SELF_DAMAGE;
+            entry.get_component_mut::<Abilities>().unwrap().0[0].cooldown_remaining = 0;
+        }
+        assert_eq!(
+            validate_ability(&world, &rules, entity, 0, Hexagon::zero()),
+            Err(AbilityError::NotEnoughIntegrity)
+        );
+    }
+
+    #[test]
+    fn smoke_blocks_the_line_of_sight_on_a_neighbour() {
+        let mut world = World::default();
+        let entity = spawn(&mut world, &[AbilityKind::Smoke]);
+        let rules = Ruleset::default();
+        let (start, end) = (Hexagon::new_axial(2, -1), Hexagon::new_axial(0, 1));
+        assert!(is_line_of_sight_clear(
+            &start,
+            &end,
+            &MapBounds::default(),
+            &world
+        ));
+
+        assert_eq!(
+            validate_ability(&world, &rules, entity, 0, Hexagon::new_axial(2, 0)),
+            Err(AbilityError::InvalidTarget)
+        );
+        use_ability(&mut world, &rules, entity, 0, Hexagon::new_axial(1, 0)).unwrap();
+
+        assert_eq!(smoke_count(&world), 1);
+        assert!(!is_line_of_sight_clear(
+            &start,
+            &end,
+            &MapBounds::default(),
+            &world
+        ));
+    }
+
+    #[test]
+    fn abilities_cool_down_at_the_start_of_the_owners_turns() {
+        let mut world = World::default();
+        let entity = spawn(&mut world, &[AbilityKind::Sprint, AbilityKind::Overdrive]);
+        let rules = Ruleset::default();
+
+        use_ability(&mut world, &rules, entity, 0, Hexagon::zero()).unwrap();
+        assert_eq!(
+            validate_ability(&world, &rules, entity, 0, Hexagon::zero()),
+            Err(AbilityError::CoolingDown(3))
+        );
+        // The other ability has its own cooldown.
+        assert_eq!(
+            validate_ability(&world, &rules, entity, 1, Hexagon::zero()),
+            Ok(AbilityKind::Overdrive)
+        );
+        assert_eq!(
+            validate_ability(&world, &rules, entity, 2, Hexagon::zero()),
+            Err(AbilityError::UnknownAbility)
+        );
+
+        start_turn(&mut world, 1);
+        start_turn(&mut world, 0);
+        start_turn(&mut world, 0);
+        assert_eq!(
+            validate_ability(&world, &rules, entity, 0, Hexagon::zero()),
+            Err(AbilityError::CoolingDown(1))
+        );
+        start_turn(&mut world, 0);
+        assert_eq!(
+            validate_ability(&world, &rules, entity, 0, Hexagon::zero()),
+            Ok(AbilityKind::Sprint)
+        );
+    }
+
+    #[test]
+    fn smoke_expires_after_the_owners_turns() {
+        let mut world = World::default();
+        let entity = spawn(&mut world, &[AbilityKind::Smoke]);
+        use_ability(
+            &mut world,
+            &Ruleset::default(),
+            entity,
+            0,
+            Hexagon::new_axial(0, 1),
+        )
+        .unwrap();
+
+        start_turn(&mut world, 1);
+        start_turn(&mut world, 0);
+        assert_eq!(smoke_count(&world), 1);
+        start_turn(&mut world, 1);
+        start_turn(&mut world, 0);
+        assert_eq!(smoke_count(&world), 0);
+        assert!(!is_vision_blocked(&Hexagon::new_axial(0, 1), &world));
+    }
+}
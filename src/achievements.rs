@@ -0,0 +1,299 @@
+//! Milestones derived from the events of the simulation, like the first unit destroyed in a match.
+
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::state_machine::GameEvent;
+use gdnative::prelude::*;
+use legion::{EntityStore, IntoQuery};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// Adding a milestone takes a variant here, its id and a match arm in `AchievementTracker::observe`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Achievement {
+    /// The first unit of the match was destroyed.
+    FirstBlood,
+    /// A player dealt damage during a round without taking any.
+    FlawlessRound,
+    /// A player won after being down to a single unit.
+    Comeback,
+}
+
+impl Achievement {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Achievement::FirstBlood => "first_blood",
+            Achievement::FlawlessRound => "flawless_round",
+            Achievement::Comeback => "comeback",
+        }
+    }
+
+    /// Whether the achievement can be earned again in later rounds. Others fire once per match.
+    fn is_per_round(&self) -> bool {
+        matches!(self, Achievement::FlawlessRound)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unlock {
+    pub achievement: Achievement,
+    pub player: Option<usize>,
+    pub round: u32,
+}
+
+impl Unlock {
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("id", self.achievement.id());
+        dict.insert("player", self.player.map_or(-1, |player| player as i64));
+        dict.insert("round", self.round);
+        dict
+    }
+}
+
+/// Consumes the events of every frame and keeps the few counters the milestones need. It is saved
+/// with the game, so loading a match does not unlock anything a second time.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AchievementTracker {
+    unlocked: Vec<Unlock>,
+    /// Round the damage below was counted for.
+    round: u32,
+    dealt_damage: BTreeSet<usize>,
+    took_damage: BTreeSet<usize>,
+    /// Players that had only one unit left at some point.
+    down_to_one_unit: BTreeSet<usize>,
+    #[serde(skip)]
+    unannounced: usize,
+}
+
+impl AchievementTracker {
+    pub fn new() -> Self {
+        AchievementTracker::default()
+    }
+
+    pub fn unlocked(&self) -> &[Unlock] {
+        &self.unlocked
+    }
+
+    /// Updates the counters with the events of one frame. `world` is the state after the frame.
+    pub fn observe<S: EntityStore>(&mut self, world: &S, events: &[GameEvent]) {
+        let mut units: HashMap<usize, usize> = HashMap::new();
+        for (player, _) in <(&PlayerComponent, &Unit)>::query().iter(world) {
+            *units.entry(player.0).or_insert(0) += 1;
+        }
+        for (player, count) in units {
+            if count == 1 {
+                self.down_to_one_unit.insert(player);
+            }
+        }
+
+        let mut last_attacker = None;
+        for event in events {
+            match *event {
+                GameEvent::TurnStarted { round, .. } if round != self.round => {
+                    let flawless: Vec<usize> = self
+                        .dealt_damage
+                        .difference(&self.took_damage)
+                        .copied()
+                        .collect();
+                    for player in flawless {
+                        self.unlock(Achievement::FlawlessRound, Some(player), self.round);
+                    }
+                    self.dealt_damage.clear();
+                    self.took_damage.clear();
+                    self.round = round;
+                }
+                GameEvent::UnitAttacked {
+                    attacker_player,
+                    defender_player,
+                    damage,
+                    ..
+                } => {
+                    last_attacker = attacker_player;
+                    if damage > 0 {
+                        self.dealt_damage.extend(attacker_player);
+                        self.took_damage.extend(defender_player);
+                    }
+                }
+                GameEvent::UnitDestroyed { .. } => {
+                    self.unlock(Achievement::FirstBlood, last_attacker, self.round);
+                }
+                GameEvent::GameOver {
+                    winner: Some(winner),
+                } => {
+                    if self.down_to_one_unit.contains(&winner) {
+                        self.unlock(Achievement::Comeback, Some(winner), self.round);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Records the unlock unless it already happened.
+    fn unlock(&mut self, achievement: Achievement, player: Option<usize>, round: u32) {
+        let unlock = Unlock {
+            achievement,
+            player,
+            round,
+        };
+        let already_unlocked = self.unlocked.iter().any(|earlier| {
+            earlier.achievement == achievement
+                && (!achievement.is_per_round()
+                    || (earlier.player == player && earlier.round == round))
+        });
+        if !already_unlocked {
+            self.unlocked.push(unlock);
+            self.unannounced += 1;
+        }
+    }
+
+    /// Returns the unlocks since the last call, so they can be announced to the UI.
+    pub fn take_unannounced(&mut self) -> Vec<Unlock> {
+        let skip = self.unlocked.len() - self.unannounced;
+        self.unannounced = 0;
+        self.unlocked[skip..].to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::flanking::AttackArc;
+    use legion::{Entity, World};
+
+    fn spawn(world: &mut World, player: usize) -> Entity {
+        world.push((PlayerComponent(player), Unit::new(5, 1, 1, 1, 0, 1, 1, 1)))
+    }
+
+    fn attacked(world: &mut World, attacker: usize, defender: usize, damage: i32) -> GameEvent {
+        GameEvent::UnitAttacked {
+            attacker: spawn(world, attacker),
+            defender: spawn(world, defender),
+            attacker_player: Some(attacker),
+            defender_player: Some(defender),
+            attacker_position: Hexagon::new_axial(0, 0),
+            defender_position: Hexagon::new_axial(1, 0),
+            arc: AttackArc::Front,
+            damage,
+            remaining_integrity: 5 - damage,
+        }
+    }
+
+    fn destroyed(world: &mut World) -> GameEvent {
+        GameEvent::UnitDestroyed {
+            entity: spawn(world, 1),
+            position: Hexagon::new_axial(1, 0),
+        }
+    }
+
+    fn ids(unlocks: &[Unlock]) -> Vec<&'static str> {
+        unlocks
+            .iter()
+            .map(|unlock| unlock.achievement.id())
+            .collect()
+    }
+
+    #[test]
+    fn first_blood_fires_once_per_match() {
+        let mut world = World::default();
+        let mut tracker = AchievementTracker::new();
+        let events = vec![attacked(&mut world, 0, 1, 5), destroyed(&mut world)];
+
+        tracker.observe(&world, &events);
+        let events = vec![attacked(&mut world, 1, 0, 5), destroyed(&mut world)];
+        tracker.observe(&world, &events);
+
+        let unlocks = tracker.take_unannounced();
+        assert_eq!(ids(&unlocks), vec!["first_blood"]);
+        assert_eq!(unlocks[0].player, Some(0));
+        assert!(tracker.take_unannounced().is_empty());
+    }
+
+    #[test]
+    fn flawless_round_fires_for_players_without_damage_taken() {
+        let mut world = World::default();
+        let mut tracker = AchievementTracker::new();
+        tracker.observe(
+            &world,
+            &[GameEvent::TurnStarted {
+                round: 1,
+                player: 0,
+            }],
+        );
+        let events = vec![attacked(&mut world, 0, 1, 2), attacked(&mut world, 1, 2, 2)];
+        tracker.observe(&world, &events);
+        tracker.observe(
+            &world,
+            &[GameEvent::TurnStarted {
+                round: 1,
+                player: 1,
+            }],
+        );
+        assert!(tracker.take_unannounced().is_empty());
+
+        tracker.observe(
+            &world,
+            &[GameEvent::TurnStarted {
+                round: 2,
+                player: 0,
+            }],
+        );
+        tracker.observe(
+            &world,
+            &[GameEvent::TurnStarted {
+                round: 3,
+                player: 0,
+            }],
+        );
+
+        let unlocks = tracker.take_unannounced();
+        assert_eq!(
+            unlocks,
+            vec![Unlock {
+                achievement: Achievement::FlawlessRound,
+                player: Some(0),
+                round: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn comeback_requires_being_down_to_one_unit() {
+        let mut world = World::default();
+        let mut tracker = AchievementTracker::new();
+        spawn(&mut world, 0);
+        spawn(&mut world, 0);
+        tracker.observe(&world, &[GameEvent::GameOver { winner: Some(0) }]);
+        assert!(tracker.take_unannounced().is_empty());
+
+        let mut world = World::default();
+        let mut tracker = AchievementTracker::new();
+        let last = spawn(&mut world, 0);
+        tracker.observe(&world, &[]);
+        spawn(&mut world, 0);
+        world.remove(last);
+        tracker.observe(&world, &[GameEvent::GameOver { winner: Some(0) }]);
+        tracker.observe(&world, &[GameEvent::GameOver { winner: Some(0) }]);
+
+        assert_eq!(ids(&tracker.take_unannounced()), vec!["comeback"]);
+    }
+
+    #[test]
+    fn reloaded_tracker_does_not_fire_again() {
+        let mut world = World::default();
+        let mut tracker = AchievementTracker::new();
+        let events = vec![attacked(&mut world, 0, 1, 5), destroyed(&mut world)];
+        tracker.observe(&world, &events);
+        tracker.take_unannounced();
+
+        let json = serde_json::to_string(&tracker).unwrap();
+        let mut reloaded: AchievementTracker = serde_json::from_str(&json).unwrap();
+        let events = vec![attacked(&mut world, 1, 0, 5), destroyed(&mut world)];
+        reloaded.observe(&world, &events);
+
+        assert!(reloaded.take_unannounced().is_empty());
+        assert_eq!(reloaded.unlocked().len(), 1);
+    }
+}
@@ -0,0 +1,452 @@
+//! The timeline of a match. Every command that changed the game is logged with a description
+//! and, once the state machine carried it out, the checksum of the state it left. Together with
+//! the snapshot taken before the first command, the log can be replayed up to any action into a
+//! scratch world, which is how the replay scrubber previews earlier states without touching the
+//! running game.
+
+use crate::commands::{apply_command, Command};
+use crate::components::field::Field;
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::PersistentId;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::game_state::{GameState, State};
+use crate::messages::{self, Message};
+use crate::savegame::SaveData;
+use crate::simulation::simulate_frame;
+use crate::unit_names::{unit_names, UNNAMED_UNIT};
+use gdnative::prelude::*;
+use legion::{EntityStore, IntoQuery, World};
+
+/// Seconds every frame of a replay advances the state machine by.
+pub const REPLAY_FRAME_DELTA: f64 = 0.05;
+
+/// Frames a replayed action may take before the replay is given up.
+pub const MAX_REPLAY_FRAMES: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedAction {
+    pub round: u32,
+    pub player: usize,
+    pub command: Command,
+    pub message: Message,
+    /// Where the action happened, `None` for actions without a place like ending the turn.
+    pub position: Option<Hexagon>,
+    /// Checksum of the state once the action was carried out, `None` while it is resolving.
+    pub checksum: Option<u64>,
+}
+
+impl LoggedAction {
+    /// Logs `command` as the state is before it is executed. Pings and selections do not change
+    /// the game and are not logged.
+    pub fn new<S: EntityStore>(world: &S, state: &GameState, command: &Command) -> Option<Self> {
+        if let Command::Ping { .. } | Command::Select { .. } = command {
+            return None;
+        }
+        let names = unit_names(world);
+        let entity = |id| state.persistent_ids.find(world, id);
+        let name = |id| {
+            entity(id)
+                .and_then(|entity| names.get(&entity))
+                .map_or(UNNAMED_UNIT, String::as_str)
+        };
+        let position_of = |id| {
+            entity(id)
+                .and_then(|entity| world.entry_ref(entity).ok())
+                .and_then(|entry| entry.get_component::<Hexagon>().ok().copied())
+        };
+        let player = command.player();
+        let player_name = state
+            .players
+            .get(player)
+            .map_or_else(String::new, |player| player.get_name());
+        let (message, position) = match command {
+            Command::Move { unit, path, .. } => {
+                let position = path.last().copied().or_else(|| position_of(*unit));
+                let message = match position {
+                    Some(target) => messages::action_moved(&player_name, name(*unit), &target),
+                    None => messages::action_ordered(&player_name, name(*unit), command.name()),
+                };
+                (message, position)
+            }
+            Command::Attack {
+                attacker, defender, ..
+            } => (
+                messages::action_attacked(&player_name, name(*attacker), name(*defender)),
+                position_of(*defender),
+            ),
+            Command::EndTurn { .. } => (messages::action_turn_ended(&player_name), None),
+            Command::ResolveRound { .. } => (messages::action_round_resolved(&player_name), None),
+            _ => {
+                let unit = command.acting_unit()?;
+                let position = match *command {
+                    Command::UseAbility { target, .. }
+                    | Command::Ungarrison { target, .. }
+                    | Command::AttackBuilding { target, .. } => Some(target),
+                    Command::AttackEdge { to, .. } => Some(to),
+                    _ => position_of(unit),
+                };
+                (
+                    messages::action_ordered(&player_name, name(unit), command.name()),
+                    position,
+                )
+            }
+        };
+        Some(LoggedAction {
+            round: state.round,
+            player,
+            command: command.clone(),
+            message,
+            position,
+            checksum: None,
+        })
+    }
+
+    pub fn to_dictionary(&self, owner: &Object, index: usize) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("index", index as i64);
+        dict.insert("kind", self.command.name());
+        dict.insert("text", self.message.translate(owner));
+        dict.insert("has_position", self.position.is_some());
+        let position = self.position.unwrap_or_else(Hexagon::zero);
+        dict.insert("q", position.get_q());
+        dict.insert("r", position.get_r());
+        dict
+    }
+}
+
+/// The actions of one player in one round, by their index in the log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TurnSummary {
+    pub round: u32,
+    pub player: usize,
+    pub actions: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ActionLog {
+    /// The game as it was before the first logged action, the replays start from it.
+    pub base: Option<SaveData>,
+    pub actions: Vec<LoggedAction>,
+}
+
+impl ActionLog {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn push(&mut self, base: Option<SaveData>, action: LoggedAction) {
+        if self.base.is_none() {
+            self.base = base;
+        }
+        self.actions.push(action);
+    }
+
+    /// Whether an action is still waiting for the checksum of its outcome.
+    pub fn is_unsettled(&self) -> bool {
+        self.actions
+            .last()
+            .map_or(false, |action| action.checksum.is_none())
+    }
+
+    /// Gives the actions still waiting for their outcome the checksum of the current state.
+    pub fn settle(&mut self, checksum: u64) {
+        for action in self
+            .actions
+            .iter_mut()
+            .rev()
+            .take_while(|action| action.checksum.is_none())
+        {
+            action.checksum = Some(checksum);
+        }
+    }
+
+    /// The carried out actions grouped by round and player, in the order they happened.
+    pub fn turn_summaries(&self) -> Vec<TurnSummary> {
+        let mut summaries: Vec<TurnSummary> = Vec::new();
+        for (index, action) in self.actions.iter().enumerate() {
+            if action.checksum.is_none() {
+                continue;
+            }
+            match summaries.last_mut() {
+                Some(summary)
+                    if summary.round == action.round && summary.player == action.player =>
+                {
+                    summary.actions.push(index)
+                }
+                _ => summaries.push(TurnSummary {
+                    round: action.round,
+                    player: action.player,
+                    actions: vec![index],
+                }),
+            }
+        }
+        summaries
+    }
+}
+
+/// Whether the state machine is done with the last command and waits for input.
+pub fn is_settled(state: &State) -> bool {
+    state.accepts_orders()
+        || matches!(
+            state,
+            State::ChoosingPromotion(_, _) | State::Planning | State::GameOver(_)
+        )
+}
+
+/// Logs the checksum of the state for the actions that were carried out since the last frame.
+pub fn settle_actions<S: EntityStore>(world: &S, state: &mut GameState) {
+    if is_settled(&state.state) && state.action_log.is_unsettled() {
+        let checksum = state.compute_checksum(world);
+        state.action_log.settle(checksum);
+    }
+}
+
+/// Why a replay did not get to the action it should have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// There is no such action or no state the log starts from.
+    NoSuchAction,
+    /// The action with the index was rejected or not carried out.
+    NotCarriedOut(usize),
+    /// The action with the index left a state with another checksum than the logged one.
+    Desync {
+        index: usize,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+/// Replays the log of `state` up to and including the action with the index into a new world. The
+/// map is copied from `live`, which is only read. Every action that has a checksum logged has to
+/// lead to the same state again.
+pub fn replay_to<S: EntityStore>(
+    live: &S,
+    state: &GameState,
+    index: usize,
+) -> Result<(World, GameState), ReplayError> {
+    let base = state
+        .action_log
+        .base
+        .as_ref()
+        .ok_or(ReplayError::NoSuchAction)?;
+    let actions = state
+        .action_log
+        .actions
+        .get(..=index)
+        .ok_or(ReplayError::NoSuchAction)?;
+    let (mut world, mut scratch) = scratch_game(live, state, base);
+    for (index, action) in actions.iter().enumerate() {
+        if apply_command(&world, &mut scratch, &action.command).is_err()
+            || !run_until_settled(&mut world, &mut scratch)
+        {
+            return Err(ReplayError::NotCarriedOut(index));
+        }
+        if let Some(expected) = action.checksum {
+            let actual = scratch.compute_checksum(&world);
+            if actual != expected {
+                return Err(ReplayError::Desync {
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    Ok((world, scratch))
+}
+
+/// A new world with the map of `live` and the game of `save`, to try things out on without
+/// touching the running game.
+pub fn scratch_game<S: EntityStore>(
+    live: &S,
+    state: &GameState,
+    save: &SaveData,
+) -> (World, GameState) {
+    let mut world = World::default();
+    let fields: Vec<Field> = <&Field>::query().iter(live).copied().collect();
+    for field in fields {
+        world.push((field,));
+    }
+    let mut scratch = GameState::new();
+    scratch.rules = state.rules.clone();
+    scratch.unit_catalog = state.unit_catalog.clone();
+    scratch.map_bounds = state.map_bounds.clone();
+    save.spawn(&mut world, &mut scratch.persistent_ids, None);
+    save.restore_state(&mut scratch);
+    save.restore_promotion(&world, &mut scratch);
+    (world, scratch)
+}
+
+/// Advances the state machine frame by frame until it is done with the last command. False if it
+/// is not done after `MAX_REPLAY_FRAMES`.
+pub fn run_until_settled(world: &mut World, state: &mut GameState) -> bool {
+    for _ in 0..MAX_REPLAY_FRAMES {
+        simulate_frame(world, state, REPLAY_FRAME_DELTA);
+        if is_settled(&state.state) {
+            return true;
+        }
+    }
+    false
+}
+
+/// The units of a replayed world for the preview.
+pub fn unit_dictionaries<S: EntityStore>(world: &S) -> VariantArray<Unique> {
+    let units = VariantArray::new();
+    for (id, unit, position, player) in
+        <(&PersistentId, &Unit, &Hexagon, &PlayerComponent)>::query().iter(world)
+    {
+        let dict = Dictionary::new();
+        dict.insert("id", id.0 as i64);
+        dict.insert("player", player.0 as i64);
+        dict.insert("q", position.get_q());
+        dict.insert("r", position.get_r());
+        dict.insert("integrity", unit.integrity);
+        units.push(dict.owned_to_variant());
+    }
+    units
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::player::Player;
+    use crate::spawn::spawn_unit;
+    use crate::systems::hexgrid::create_grid;
+
+    pub(crate) fn game() -> (World, GameState, Vec<PersistentId>) {
+        let mut world = World::default();
+        for hexagon in create_grid(4) {
+            world.push((Field::new(hexagon),));
+        }
+        let mut state = GameState::new();
+        state.players = vec![
+            Player::new("Red".to_owned(), Color::rgb(1.0, 0.0, 0.0)),
+            Player::new("Blue".to_owned(), Color::rgb(0.0, 0.0, 1.0)),
+        ];
+        state.current_player = Some(0);
+        state.state = State::Waiting;
+        let ids = [(0, 0), (1, 2)]
+            .iter()
+            .map(|(player, q)| {
+                let entity = spawn_unit(
+                    &mut world,
+                    &mut state.persistent_ids,
+                    *player,
+                    Hexagon::new_axial(*q, 0),
+                    Unit::new(10, 4, 1, 1, 0, 3, 3, 1),
+                    None,
+                );
+                PersistentId::of_entity(&world, entity).unwrap()
+            })
+            .collect();
+        (world, state, ids)
+    }
+
+    /// Runs the command like the game loop does, frame by frame until it is carried out.
+    fn run(world: &mut World, state: &mut GameState, command: Command) {
+        apply_command(world, state, &command).unwrap();
+        loop {
+            simulate_frame(world, state, REPLAY_FRAME_DELTA);
+            if is_settled(&state.state) {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn replays_match_the_recorded_checksums() {
+        let (mut world, mut state, ids) = game();
+        let (mover, enemy) = (ids[0], ids[1]);
+
+        run(
+            &mut world,
+            &mut state,
+            Command::Move {
+                player: 0,
+                unit: mover,
+                path: vec![Hexagon::new_axial(1, 0)],
+            },
+        );
+        run(
+            &mut world,
+            &mut state,
+            Command::Attack {
+                player: 0,
+                attacker: mover,
+                defender: enemy,
+            },
+        );
+        let ping = Command::Ping {
+            player: 1,
+            hexagon: Hexagon::zero(),
+            kind: "look".to_owned(),
+        };
+        apply_command(&world, &mut state, &ping).unwrap();
+        run(&mut world, &mut state, Command::EndTurn { player: 0 });
+
+        let log = &state.action_log;
+        assert_eq!(log.actions.len(), 3);
+        assert!(!log.is_unsettled());
+        assert_eq!(log.actions[0].position, Some(Hexagon::new_axial(1, 0)));
+        assert_eq!(
+            log.actions[1].message.to_fallback_string(),
+            "Red attacked Unit with Unit"
+        );
+        assert_eq!(
+            log.turn_summaries(),
+            vec![TurnSummary {
+                round: 1,
+                player: 0,
+                actions: vec![0, 1, 2],
+            }]
+        );
+
+        let live_checksum = state.compute_checksum(&world);
+        for (index, action) in log.actions.iter().enumerate() {
+            let (replayed_world, replayed) = replay_to(&world, &state, index).unwrap();
+            assert_eq!(
+                Some(replayed.compute_checksum(&replayed_world)),
+                action.checksum
+            );
+        }
+        assert_eq!(
+            unit_dictionaries(&replay_to(&world, &state, 0).unwrap().0).len(),
+            2
+        );
+        assert_eq!(
+            replay_to(&world, &state, 3).err(),
+            Some(ReplayError::NoSuchAction)
+        );
+        // Scrubbing leaves the running game alone.
+        assert_eq!(state.compute_checksum(&world), live_checksum);
+        assert_eq!(state.action_log.actions.len(), 3);
+    }
+
+    #[test]
+    fn replays_report_the_action_that_went_apart() {
+        let (mut world, mut state, ids) = game();
+        run(
+            &mut world,
+            &mut state,
+            Command::Move {
+                player: 0,
+                unit: ids[0],
+                path: vec![Hexagon::new_axial(1, 0)],
+            },
+        );
+        run(&mut world, &mut state, Command::EndTurn { player: 0 });
+        let recorded = state.action_log.actions[1].checksum.unwrap();
+        state.action_log.actions[1].checksum = Some(recorded ^ 1);
+
+        assert!(replay_to(&world, &state, 0).is_ok());
+        assert_eq!(
+            replay_to(&world, &state, 1).err(),
+            Some(ReplayError::Desync {
+                index: 1,
+                expected: recorded ^ 1,
+                actual: recorded,
+            })
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Which units of a player can still do something this turn.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::PersistentId;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::{ActionPool, Unit};
+use crate::game_state::{GameState, State};
+use crate::map_bounds::MapBounds;
+use crate::rules::Ruleset;
+use crate::systems::hexgrid::{
+    get_hexagons_in_range, get_reachable_hexagons, is_line_of_sight_clear,
+};
+use gdnative::prelude::*;
+use legion::{Entity, EntityStore, IntoQuery};
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ActionableUnit {
+    pub entity: Entity,
+    pub id: Option<PersistentId>,
+    pub position: Hexagon,
+    pub can_move: bool,
+    pub can_attack: bool,
+    pub pool: ActionPool,
+}
+
+impl ActionableUnit {
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("id", self.id.map_or(-1, |id| id.0 as i64));
+        dict.insert("q", self.position.get_q());
+        dict.insert("r", self.position.get_r());
+        dict.insert("can_move", self.can_move);
+        dict.insert("can_attack", self.can_attack);
+        match self.pool {
+            ActionPool::Separate {
+                remaining_range,
+                remaining_attacks,
+            } => {
+                dict.insert("remaining_range", remaining_range);
+                dict.insert("remaining_attacks", remaining_attacks);
+            }
+            ActionPool::ActionPoints(action_points) => {
+                dict.insert("action_points", action_points);
+            }
+        }
+        dict
+    }
+}
+
+/// Returns the units of `player` that can still move somewhere or have an enemy in attack
+/// range, ordered by position.
+pub fn get_actionable_units<S: EntityStore>(
+    world: &S,
+    player: usize,
+    rules: &Ruleset,
+    bounds: &MapBounds,
+) -> Vec<ActionableUnit> {
+    let mut enemies = HashSet::new();
+    let mut own_units = Vec::new();
+    for (entity, id, owner, hexagon, unit) in <(
+        Entity,
+        Option<&PersistentId>,
+        &PlayerComponent,
+        &Hexagon,
+        &Unit,
+    )>::query()
+    .iter(world)
+    {
+        if owner.0 == player {
+            own_units.push((*entity, id.copied(), *hexagon, *unit));
+        } else {
+            enemies.insert(*hexagon);
+        }
+    }
+
+    let mut actionable: Vec<ActionableUnit> = own_units
+        .into_iter()
+        .map(|(entity, id, position, unit)| ActionableUnit {
+            entity,
+            id,
+            position,
+            can_move: unit.movement_cost_left(rules) > 0
+                && !get_reachable_hexagons(&position, 1, bounds, world).is_empty(),
+            can_attack: unit.can_attack(rules)
+                && get_hexagons_in_range(&position, unit.min_attack_range, unit.max_attack_range)
+                    .iter()
+                    .any(|hexagon| enemies.contains(hexagon)),
+            pool: unit.pool(rules),
+        })
+        .filter(|unit| unit.can_move || unit.can_attack)
+        .collect();
+    actionable.sort_by_key(|unit| (unit.position.get_q(), unit.position.get_r()));
+    actionable
+}
+
+/// The actionable units of the current player. With the initiative rules only the unit whose turn
+/// it is can act, and none can once the player used up their command points: units that could
+/// only act with another command point are not waiting for orders.
+pub fn current_actionable_units<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+) -> Vec<ActionableUnit> {
+    let player = match state.current_player {
+        Some(player) if state.has_command_points(player) => player,
+        _ => return Vec::new(),
+    };
+    let active = state.initiative.active();
+    get_actionable_units(world, player, &state.rules, &state.map_bounds)
+        .into_iter()
+        .filter(|unit| !state.rules.initiative || active == Some(unit.entity))
+        .collect()
+}
+
+/// Returns the enemy units `attacker` can attack right now: it needs an attack left, the enemy
+/// has to be in its attack range and, with the line of sight rule, visible.
+pub fn get_attackable_entities<S: EntityStore>(
+    world: &S,
+    attacker: Entity,
+    rules: &Ruleset,
+    bounds: &MapBounds,
+) -> HashSet<Entity> {
+    let (unit, position, owner) = match world.entry_ref(attacker) {
+        Err(_) => return HashSet::new(),
+        Ok(entry) => match (
+            entry.get_component::<Unit>(),
+            entry.get_component::<Hexagon>(),
+            entry.get_component::<PlayerComponent>(),
+        ) {
+            (Ok(unit), Ok(hexagon), Ok(player)) => (*unit, *hexagon, player.0),
+            _ => return HashSet::new(),
+        },
+    };
+    if !unit.can_attack(rules) {
+        return HashSet::new();
+    }
+    <(Entity, &PlayerComponent, &Hexagon, &Unit)>::query()
+        .iter(world)
+        .filter(|(_, player, hexagon, _)| {
+            player.0 != owner
+                && unit.is_in_attack_range(position.distance_to(hexagon))
+                && (!rules.line_of_sight
+                    || is_line_of_sight_clear(&position, hexagon, bounds, world))
+        })
+        .map(|(entity, _, _, _)| *entity)
+        .collect()
+}
+
+/// Computes `GameState::attackable_entities` after a unit got selected, which includes the
+/// selection at the end of a move.
+pub fn refresh_attackable_entities<S: EntityStore>(world: &S, state: &mut GameState) {
+    if !state.refresh_attackable_entities {
+        return;
+    }
+    state.refresh_attackable_entities = false;
+    state.attackable_entities = match state.state {
+        State::Selected(selected) | State::Targeting(selected) => {
+            get_attackable_entities(world, selected, &state.rules, &state.map_bounds)
+        }
+        _ => HashSet::new(),
+    };
+}
+
+pub const DEFAULT_AUTO_END_TURN_DELAY: f64 = 1.0;
+
+/// Ends the turn a short while after the last action, once no unit can act any more. The delay
+/// lets the player see the result of that action; any input during it cancels the timer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutoEndTurn {
+    pub enabled: bool,
+    pub delay: f64,
+    remaining: Option<f64>,
+}
+
+impl AutoEndTurn {
+    pub fn new() -> Self {
+        AutoEndTurn {
+            enabled: false,
+            delay: DEFAULT_AUTO_END_TURN_DELAY,
+            remaining: None,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.remaining.is_some()
+    }
+
+    /// Starts the timer if auto end turn is enabled and the timer is not already running.
+    pub fn schedule(&mut self) {
+        if self.enabled && self.remaining.is_none() {
+            self.remaining = Some(self.delay.max(0.0));
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        self.remaining = None;
+    }
+
+    /// Advances the timer and returns true once the turn should be ended. The timer only runs in
+    /// Waiting or Selected; it is cancelled as soon as an action starts resolving or the turn
+    /// ends in some other way.
+    pub fn update(&mut self, delta: f64, state: &State) -> bool {
+        let remaining = match self.remaining {
+            None => return false,
+            Some(remaining) => remaining - delta,
+        };
+        match state {
+            _ if state.accepts_orders() && self.enabled => {
+                if remaining > 0.0 {
+                    self.remaining = Some(remaining);
+                    false
+                } else {
+                    self.cancel();
+                    true
+                }
+            }
+            _ => {
+                self.cancel();
+                false
+            }
+        }
+    }
+}
+
+impl Default for AutoEndTurn {
+    fn default() -> Self {
+        AutoEndTurn::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion::World;
+
+    fn spawn(world: &mut World, player: usize, q: i32, r: i32, unit: Unit) -> Entity {
+        world.push((PlayerComponent(player), Hexagon::new_axial(q, r), unit))
+    }
+
+    #[test]
+    fn unit_with_attacks_but_no_target_in_range_cannot_attack() {
+        let mut world = World::default();
+        let unit = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 2, 1, 0, 2, 0, 1));
+        spawn(&mut world, 1, 5, 0, Unit::new(5, 1, 2, 1, 0, 2, 2, 1));
+
+        assert!(
+            get_actionable_units(&world, 0, &Ruleset::default(), &MapBounds::default()).is_empty()
+        );
+
+        let enemy = spawn(&mut world, 1, 2, 0, Unit::new(5, 1, 2, 1, 0, 2, 2, 1));
+        assert_eq!(
+            get_actionable_units(&world, 0, &Ruleset::default(), &MapBounds::default()),
+            vec![ActionableUnit {
+                entity: unit,
+                id: None,
+                position: Hexagon::zero(),
+                can_move: false,
+                can_attack: true,
+                pool: ActionPool::Separate {
+                    remaining_range: 0,
+                    remaining_attacks: 1,
+                },
+            }]
+        );
+
+        world.remove(enemy);
+        spawn(&mut world, 0, 1, 0, Unit::new(5, 1, 2, 1, 0, 2, 0, 0));
+        assert!(
+            get_actionable_units(&world, 0, &Ruleset::default(), &MapBounds::default()).is_empty()
+        );
+    }
+
+    #[test]
+    fn enemy_inside_minimum_range_cannot_be_attacked() {
+        let mut world = World::default();
+        spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 4, 2, 0, 2, 0, 1));
+        spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 2, 1, 0, 2, 2, 1));
+
+        assert!(
+            get_actionable_units(&world, 0, &Ruleset::default(), &MapBounds::default()).is_empty()
+        );
+    }
+
+    #[test]
+    fn unit_with_range_and_free_neighbour_can_move() {
+        let mut world = World::default();
+        spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 2, 1, 0, 2, 2, 0));
+
+        let actionable =
+            get_actionable_units(&world, 0, &Ruleset::default(), &MapBounds::default());
+
+        assert_eq!(actionable.len(), 1);
+        assert!(actionable[0].can_move);
+        assert!(!actionable[0].can_attack);
+        assert!(
+            get_actionable_units(&world, 1, &Ruleset::default(), &MapBounds::default()).is_empty()
+        );
+    }
+
+    #[test]
+    fn units_that_only_lack_command_points_are_not_waiting_for_orders() {
+        let mut world = World::default();
+        let unit = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 2, 1, 0, 2, 2, 1));
+        spawn(&mut world, 1, 2, 0, Unit::new(5, 1, 2, 1, 0, 2, 2, 1));
+        let mut state = GameState::new();
+        state.current_player = Some(0);
+        state.rules.command_points = 3;
+        state.commands_used.insert(0, 2);
+
+        let actionable = current_actionable_units(&world, &state);
+        assert_eq!(actionable.len(), 1);
+        assert_eq!(actionable[0].entity, unit);
+
+        // The unit could still move and attack, only the command points are used up, so there
+        // is nothing left to warn about before ending the turn.
+        state.commands_used.insert(0, 3);
+        assert_eq!(
+            get_actionable_units(&world, 0, &state.rules, &state.map_bounds).len(),
+            1
+        );
+        assert!(current_actionable_units(&world, &state).is_empty());
+        // The points of the other players are their own.
+        state.current_player = Some(1);
+        assert_eq!(current_actionable_units(&world, &state).len(), 1);
+    }
+
+    fn enabled_timer(delay: f64) -> AutoEndTurn {
+        let mut timer = AutoEndTurn::new();
+        timer.enabled = true;
+        timer.delay = delay;
+        timer
+    }
+
+    #[test]
+    fn auto_end_turn_fires_after_the_grace_delay() {
+        let mut timer = enabled_timer(0.5);
+        timer.schedule();
+
+        assert!(!timer.update(0.2, &State::Waiting));
+        assert!(!timer.update(0.2, &State::Waiting));
+        assert!(timer.update(0.2, &State::Waiting));
+        assert!(!timer.is_pending());
+        assert!(!timer.update(1.0, &State::Waiting));
+    }
+
+    #[test]
+    fn auto_end_turn_is_cancelled_by_input_and_resolving_actions() {
+        let mut world = World::default();
+        let unit = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 2, 1, 0, 2, 2, 1));
+        let mut timer = enabled_timer(0.5);
+
+        timer.schedule();
+        assert!(!timer.update(0.3, &State::Waiting));
+        timer.cancel();
+        assert!(!timer.update(1.0, &State::Waiting));
+
+        timer.schedule();
+        assert!(!timer.update(1.0, &State::Attacking(unit, unit)));
+        assert!(!timer.is_pending());
+        assert!(!timer.update(1.0, &State::Selected(unit)));
+    }
+
+    #[test]
+    fn auto_end_turn_requires_enabled_and_stops_when_the_turn_ends() {
+        let mut timer = AutoEndTurn::new();
+        timer.schedule();
+        assert!(!timer.is_pending());
+
+        let mut timer = enabled_timer(0.1);
+        timer.schedule();
+        assert!(!timer.update(0.5, &State::NewRound));
+        assert!(!timer.is_pending());
+        assert!(!timer.update(0.5, &State::Waiting));
+    }
+
+    #[test]
+    fn attackable_entities_are_recomputed_after_a_move() {
+        use crate::game_state::{force_move, force_state};
+        use crate::player::Player;
+        use crate::simulation::simulate_frame;
+        use gdnative::core_types::Color;
+        use std::collections::vec_deque::VecDeque;
+
+        let mut world = World::default();
+        let mut state = GameState::new();
+        for name in ["Player 1", "Player 2"].iter() {
+            state.add_player(Player::new((*name).to_owned(), Color::rgb(1.0, 1.0, 1.0)));
+        }
+        state.current_player = Some(0);
+        state.state = State::Waiting;
+        let unit = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 2, 2, 1));
+        let enemy = spawn(&mut world, 1, 3, 0, Unit::new(5, 1, 1, 1, 0, 2, 2, 1));
+
+        force_state(&mut state, State::Selected(unit));
+        refresh_attackable_entities(&world, &mut state);
+        assert!(state.attackable_entities.is_empty());
+
+        let path: VecDeque<Hexagon> = vec![Hexagon::new_axial(1, 0), Hexagon::new_axial(2, 0)]
+            .into_iter()
+            .collect();
+        force_move(&mut state, unit, path);
+        for _ in 0..10 {
+            simulate_frame(&mut world, &mut state, 0.1);
+        }
+
+        assert_eq!(state.state, State::Selected(unit));
+        assert_eq!(
+            state.attackable_entities,
+            std::iter::once(enemy).collect::<HashSet<_>>()
+        );
+    }
+}
@@ -0,0 +1,764 @@
+//! Scores candidate commands the way a computer player weighs them: damage dealt against the
+//! damage to expect in return, progress towards the enemy and pickups on the way. The same
+//! scores give hints to human players and explain why a unit got its last order. The profile of
+//! a computer player shifts the weights of these terms, see `AiProfile`.
+
+use crate::actionable::get_attackable_entities;
+use crate::commands::Command;
+use crate::components::hexagon::Hexagon;
+use crate::components::perks::PerkId;
+use crate::components::persistent_id::PersistentId;
+use crate::components::pickup::Pickup;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::game_state::{GameState, State};
+use crate::pickups::pickup_at;
+use crate::promotions::ai_perk;
+use crate::rules::Ruleset;
+use crate::state_machine::max_integrity;
+use crate::systems::hexgrid::{find_path_within, get_reachable_hexagons};
+use gdnative::prelude::*;
+use legion::{Entity, EntityStore, IntoQuery};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Bonus for destroying a unit, on top of the damage dealt.
+const KILL_BONUS: f64 = 10.0;
+/// How much worse losing a unit is than the integrity it had.
+const LOSS_WEIGHT: f64 = 1.5;
+/// Share of the damage to expect that counts against a unit that survives it.
+const THREAT_WEIGHT: f64 = 0.5;
+/// Score for every hexagon a move gets closer to the nearest enemy.
+const PROGRESS_WEIGHT: f64 = 0.5;
+/// Resources that are worth as much as one point of damage.
+const RESOURCES_PER_POINT: f64 = 10.0;
+/// What ending all cooldowns is worth.
+const ABILITY_CHARGE_VALUE: f64 = 2.0;
+
+/// How a computer player weighs the terms of a score, every weight from 0 to 1. A weight of 0.5
+/// keeps its terms as they are, 0 ignores them and 1 doubles them. Players without a profile are
+/// scored as balanced.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AiProfile {
+    /// Damage dealt and units destroyed.
+    pub aggression: f64,
+    /// Damage to expect in return.
+    pub caution: f64,
+    /// Progress towards the enemy.
+    pub objective_focus: f64,
+    /// Pickups collected on the way.
+    pub expansion: f64,
+}
+
+impl Default for AiProfile {
+    fn default() -> Self {
+        AiProfile {
+            aggression: 0.5,
+            caution: 0.5,
+            objective_focus: 0.5,
+            expansion: 0.5,
+        }
+    }
+}
+
+impl AiProfile {
+    /// The profile with the given name: "berserker", "turtle" or "balanced".
+    pub fn preset(name: &str) -> Option<AiProfile> {
+        match name {
+            "berserker" => Some(AiProfile {
+                aggression: 1.0,
+                caution: 0.1,
+                objective_focus: 0.9,
+                expansion: 0.3,
+            }),
+            "turtle" => Some(AiProfile {
+                aggression: 0.3,
+                caution: 1.0,
+                objective_focus: 0.1,
+                expansion: 0.4,
+            }),
+            "balanced" => Some(AiProfile::default()),
+            _ => None,
+        }
+    }
+
+    fn factor(weight: f64) -> f64 {
+        2.0 * weight.max(0.0).min(1.0)
+    }
+}
+
+/// The score of a command, higher is better, with the reasons behind it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Evaluation {
+    pub score: f64,
+    pub rationale: String,
+}
+
+/// A scored command in a form scripts can show: what the unit at `from` does to `to`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suggestion {
+    pub command: Command,
+    pub unit: PersistentId,
+    pub from: Hexagon,
+    pub to: Hexagon,
+    pub evaluation: Evaluation,
+}
+
+impl Suggestion {
+    pub fn kind(&self) -> &'static str {
+        match self.command {
+            Command::Move { .. } => "move",
+            Command::Attack { .. } => "attack",
+            _ => "other",
+        }
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("kind", self.kind());
+        dict.insert("id", self.unit.0 as i64);
+        dict.insert("from_q", self.from.get_q());
+        dict.insert("from_r", self.from.get_r());
+        dict.insert("to_q", self.to.get_q());
+        dict.insert("to_r", self.to.get_r());
+        dict.insert("score", self.evaluation.score);
+        dict.insert("rationale", self.evaluation.rationale.clone());
+        dict
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Combatant {
+    entity: Entity,
+    player: usize,
+    position: Hexagon,
+    unit: Unit,
+}
+
+/// The units on the map, ordered by persistent id.
+fn combatants<S: EntityStore>(world: &S) -> Vec<Combatant> {
+    let mut combatants: Vec<(Option<PersistentId>, Combatant)> = <(
+        Entity,
+        &PlayerComponent,
+        &Hexagon,
+        &Unit,
+        Option<&PersistentId>,
+    )>::query()
+    .iter(world)
+    .map(|(entity, player, position, unit, id)| {
+        (
+            id.copied(),
+            Combatant {
+                entity: *entity,
+                player: player.0,
+                position: *position,
+                unit: *unit,
+            },
+        )
+    })
+    .collect();
+    combatants.sort_by_key(|(id, _)| *id);
+    combatants
+        .into_iter()
+        .map(|(_, combatant)| combatant)
+        .collect()
+}
+
+fn find<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    combatants: &[Combatant],
+    id: PersistentId,
+) -> Option<Combatant> {
+    let entity = state.persistent_ids.find(world, id)?;
+    combatants
+        .iter()
+        .find(|combatant| combatant.entity == entity)
+        .copied()
+}
+
+/// The damage the enemies of `player` could deal next turn to `target` standing on `position`,
+/// if they all move towards it. `ignored` is left out, like a unit about to be destroyed.
+fn threat(
+    combatants: &[Combatant],
+    player: usize,
+    position: &Hexagon,
+    target: &Unit,
+    rules: &Ruleset,
+    ignored: Option<Entity>,
+) -> i32 {
+    combatants
+        .iter()
+        .filter(|enemy| enemy.player != player && Some(enemy.entity) != ignored)
+        .filter(|enemy| {
+            enemy.position.distance_to(position)
+                <= enemy.unit.mobility + enemy.unit.max_attack_range
+        })
+        .map(|enemy| rules.damage(enemy.unit.damage, target.armor).max(0))
+        .sum()
+}
+
+/// What expecting `damage` costs a unit with `integrity` left.
+fn damage_penalty(damage: i32, integrity: i32) -> f64 {
+    if damage >= integrity {
+        LOSS_WEIGHT * integrity.max(0) as f64
+    } else {
+        THREAT_WEIGHT * damage as f64
+    }
+}
+
+fn distance_to_nearest_enemy(combatants: &[Combatant], player: usize, position: &Hexagon) -> i32 {
+    combatants
+        .iter()
+        .filter(|enemy| enemy.player != player)
+        .map(|enemy| enemy.position.distance_to(position))
+        .min()
+        .unwrap_or(0)
+}
+
+fn pickup_value(pickup: Pickup) -> f64 {
+    match pickup {
+        Pickup::Resources(amount) => amount as f64 / RESOURCES_PER_POINT,
+        Pickup::Repair(amount) => amount as f64,
+        Pickup::AbilityCharge => ABILITY_CHARGE_VALUE,
+    }
+}
+
+fn evaluate_attack(
+    state: &GameState,
+    profile: &AiProfile,
+    combatants: &[Combatant],
+    attacker: Combatant,
+    defender: Combatant,
+) -> Evaluation {
+    let rules = &state.rules;
+    let aggression = AiProfile::factor(profile.aggression);
+    let caution = AiProfile::factor(profile.caution);
+    let damage = rules
+        .damage(attacker.unit.damage, defender.unit.armor)
+        .max(0)
+        .min(defender.unit.integrity);
+    let destroys = damage >= defender.unit.integrity;
+    let mut score = aggression * damage as f64;
+    let mut reasons = vec![format!("deals {} damage", damage)];
+    if destroys {
+        score += aggression * KILL_BONUS;
+        reasons.push("destroys the target".to_owned());
+    }
+
+    let mut integrity = attacker.unit.integrity;
+    if !destroys
+        && rules.counterattack
+        && defender
+            .unit
+            .is_in_attack_range(defender.position.distance_to(&attacker.position))
+    {
+        let counter = rules
+            .damage(defender.unit.damage, attacker.unit.armor)
+            .max(0);
+        score -= caution * damage_penalty(counter, integrity);
+        integrity -= counter;
+        reasons.push(format!("takes {} damage in return", counter));
+    }
+    if integrity > 0 {
+        let exposure = threat(
+            combatants,
+            attacker.player,
+            &attacker.position,
+            &attacker.unit,
+            rules,
+            if destroys {
+                Some(defender.entity)
+            } else {
+                None
+            },
+        );
+        if exposure > 0 {
+            score -= caution * damage_penalty(exposure, integrity);
+            reasons.push(format!("stays exposed to {} damage", exposure));
+        }
+    }
+    Evaluation {
+        score,
+        rationale: reasons.join(", "),
+    }
+}
+
+fn evaluate_move<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    profile: &AiProfile,
+    combatants: &[Combatant],
+    mover: Combatant,
+    destination: Hexagon,
+) -> Evaluation {
+    let rules = &state.rules;
+    let caution = AiProfile::factor(profile.caution);
+    let before = distance_to_nearest_enemy(combatants, mover.player, &mover.position);
+    let after = distance_to_nearest_enemy(combatants, mover.player, &destination);
+    let mut score =
+        AiProfile::factor(profile.objective_focus) * PROGRESS_WEIGHT * (before - after) as f64;
+    let mut reasons = Vec::new();
+    match after.cmp(&before) {
+        Ordering::Less => reasons.push(format!(
+            "gets {} hexagons closer to the enemy",
+            before - after
+        )),
+        Ordering::Greater => reasons.push(format!(
+            "falls back {} hexagons from the enemy",
+            after - before
+        )),
+        Ordering::Equal => {}
+    }
+    if let Some((_, pickup)) = pickup_at(world, &destination) {
+        score += AiProfile::factor(profile.expansion) * pickup_value(pickup);
+        reasons.push(format!("collects {}", pickup.name()));
+    }
+
+    let threat_before = threat(
+        combatants,
+        mover.player,
+        &mover.position,
+        &mover.unit,
+        rules,
+        None,
+    );
+    let threat_after = threat(
+        combatants,
+        mover.player,
+        &destination,
+        &mover.unit,
+        rules,
+        None,
+    );
+    score += caution
+        * (damage_penalty(threat_before, mover.unit.integrity)
+            - damage_penalty(threat_after, mover.unit.integrity));
+    if threat_after > 0 {
+        reasons.push(format!("is exposed to {} damage there", threat_after));
+    }
+    if reasons.is_empty() {
+        reasons.push("repositions".to_owned());
+    }
+    Evaluation {
+        score,
+        rationale: reasons.join(", "),
+    }
+}
+
+/// Scores a command with the profile of the player issuing it and tells where it happens. `None`
+/// for commands that are no unit orders or whose units are gone.
+pub fn evaluate_action<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    command: &Command,
+) -> Option<Suggestion> {
+    if !matches!(command, Command::Attack { .. } | Command::Move { .. }) {
+        return None;
+    }
+    let profile = state
+        .players
+        .get(command.player())
+        .and_then(|player| player.get_ai_profile())
+        .unwrap_or_default();
+    let combatants = combatants(world);
+    match command {
+        Command::Attack {
+            attacker, defender, ..
+        } => {
+            let attacking = find(world, state, &combatants, *attacker)?;
+            let defending = find(world, state, &combatants, *defender)?;
+            Some(Suggestion {
+                command: command.clone(),
+                unit: *attacker,
+                from: attacking.position,
+                to: defending.position,
+                evaluation: evaluate_attack(state, &profile, &combatants, attacking, defending),
+            })
+        }
+        Command::Move { unit, path, .. } => {
+            let mover = find(world, state, &combatants, *unit)?;
+            let destination = *path.last()?;
+            Some(Suggestion {
+                command: command.clone(),
+                unit: *unit,
+                from: mover.position,
+                to: destination,
+                evaluation: evaluate_move(world, state, &profile, &combatants, mover, destination),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The `count` best moves and attacks of the units of `player`, best first. Commands with the
+/// same score keep the order of the unit ids.
+pub fn best_actions<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    player: usize,
+    count: usize,
+) -> Vec<Suggestion> {
+    let mut units: Vec<(PersistentId, Entity, Hexagon, Unit)> =
+        <(Entity, &PersistentId, &PlayerComponent, &Hexagon, &Unit)>::query()
+            .iter(world)
+            .filter(|(_, _, owner, _, _)| owner.0 == player)
+            .map(|(entity, id, _, position, unit)| (*id, *entity, *position, *unit))
+            .collect();
+    units.sort_by_key(|(id, _, _, _)| *id);
+
+    let mut candidates = Vec::new();
+    for (id, entity, position, unit) in units {
+        let mut defenders: Vec<PersistentId> =
+            get_attackable_entities(world, entity, &state.rules, &state.map_bounds)
+                .into_iter()
+                .filter_map(|defender| PersistentId::of_entity(world, defender))
+                .collect();
+        defenders.sort();
+        for defender in defenders {
+            candidates.push(Command::Attack {
+                player,
+                attacker: id,
+                defender,
+            });
+        }
+        if unit.movement_cost_left(&state.rules) <= 0 {
+            continue;
+        }
+        let range = unit.movement_left(&state.rules).max(1);
+        let mut destinations = get_reachable_hexagons(&position, range, &state.map_bounds, world);
+        destinations.sort_by_key(|hexagon| (hexagon.get_q(), hexagon.get_r()));
+        for destination in destinations {
+            let path = find_path_within(
+                &position,
+                &destination,
+                range,
+                &state.edges,
+                &state.map_bounds,
+                world,
+            );
+            if !path.is_empty() {
+                candidates.push(Command::Move {
+                    player,
+                    unit: id,
+                    path,
+                });
+            }
+        }
+    }
+
+    let mut suggestions: Vec<Suggestion> = candidates
+        .iter()
+        .filter_map(|command| evaluate_action(world, state, command))
+        .collect();
+    suggestions.sort_by(|a, b| {
+        b.evaluation
+            .score
+            .partial_cmp(&a.evaluation.score)
+            .unwrap_or(Ordering::Equal)
+    });
+    suggestions.truncate(count);
+    suggestions
+}
+
+/// The perk a computer player picks for its unit waiting for a promotion, see `ai_perk`.
+fn promotion_command<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    player: usize,
+    entity: Entity,
+    options: &[PerkId],
+) -> Option<Command> {
+    let unit = *world.entry_ref(entity).ok()?.get_component::<Unit>().ok()?;
+    let max_integrity = max_integrity(world, state, entity, unit.integrity);
+    Some(Command::ChoosePromotion {
+        player,
+        unit: PersistentId::of_entity(world, entity)?,
+        perk: ai_perk(&unit, max_integrity, options),
+    })
+}
+
+/// The next order of the computer player whose turn it is: the pick of a pending promotion, the
+/// best move or attack while one is worth anything, then the end of the turn. With the initiative
+/// rules only the active unit is considered. `None` if the current player is no computer player
+/// or the game does not take orders right now.
+pub fn next_command<S: EntityStore>(world: &S, state: &GameState) -> Option<Command> {
+    let player = state.current_player?;
+    if !state.players.get(player)?.is_ai() {
+        return None;
+    }
+    if let State::ChoosingPromotion(entity, options) = &state.state {
+        return promotion_command(world, state, player, *entity, options);
+    }
+    if !state.state.accepts_orders() {
+        return None;
+    }
+    let active = if state.rules.initiative {
+        state
+            .initiative
+            .active()
+            .and_then(|entity| PersistentId::of_entity(world, entity))
+    } else {
+        None
+    };
+    Some(
+        best_actions(world, state, player, usize::MAX)
+            .into_iter()
+            .filter(|suggestion| !state.rules.initiative || active == Some(suggestion.unit))
+            .filter(|suggestion| {
+                state.has_command_points(player) || !suggestion.command.spends_command_point()
+            })
+            .find(|suggestion| suggestion.evaluation.score > 0.0)
+            .map_or(Command::EndTurn { player }, |suggestion| suggestion.command),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::unit_type::UnitType;
+    use crate::pickups::spawn_pickup;
+    use crate::player::Player;
+    use crate::spawn::spawn_unit;
+    use crate::systems::hexgrid::get_entities_at_hexagon;
+    use legion::World;
+
+    fn new_state() -> GameState {
+        let mut state = GameState::new();
+        for name in ["Player 1", "Player 2"].iter() {
+            state.add_player(Player::new((*name).to_owned(), Color::rgb(1.0, 1.0, 1.0)));
+        }
+        state.current_player = Some(0);
+        state
+    }
+
+    fn spawn(
+        world: &mut World,
+        state: &mut GameState,
+        player: usize,
+        q: i32,
+        r: i32,
+        damage: i32,
+    ) -> PersistentId {
+        let entity = spawn_unit(
+            world,
+            &mut state.persistent_ids,
+            player,
+            Hexagon::new_axial(q, r),
+            Unit::new(10, damage, 1, 1, 0, 1, 1, 1),
+            None,
+        );
+        PersistentId::of_entity(world, entity).unwrap()
+    }
+
+    #[test]
+    fn safe_kill_beats_a_suicidal_one() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let attacker = spawn(&mut world, &mut state, 0, 0, 0, 10);
+        let harmless = spawn(&mut world, &mut state, 1, 1, 0, 1);
+        let deadly = spawn(&mut world, &mut state, 1, -1, 0, 10);
+        let attack = |defender| Command::Attack {
+            player: 0,
+            attacker,
+            defender,
+        };
+
+        let suicidal = evaluate_action(&world, &state, &attack(harmless)).unwrap();
+        let safe = evaluate_action(&world, &state, &attack(deadly)).unwrap();
+
+        assert!(
+            safe.evaluation.score > suicidal.evaluation.score,
+            "{:?} should beat {:?}",
+            safe.evaluation,
+            suicidal.evaluation
+        );
+        assert!(safe.evaluation.rationale.contains("destroys the target"));
+        let best = best_actions(&world, &state, 0, 1);
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].command, attack(deadly));
+        assert_eq!(best[0].to, Hexagon::new_axial(-1, 0));
+    }
+
+    #[test]
+    fn moves_towards_pickups_and_away_from_danger() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let unit = spawn(&mut world, &mut state, 0, 0, 0, 1);
+        spawn(&mut world, &mut state, 1, 5, 0, 20);
+        spawn_pickup(
+            &mut world,
+            Hexagon::new_axial(-1, 0),
+            Pickup::Repair(5),
+            false,
+        );
+
+        let best = best_actions(&world, &state, 0, 3);
+
+        assert_eq!(best.len(), 3);
+        assert_eq!(best[0].kind(), "move");
+        assert_eq!(best[0].unit, unit);
+        assert_eq!(best[0].to, Hexagon::new_axial(-1, 0));
+        assert!(best
+            .windows(2)
+            .all(|pair| pair[0].evaluation.score >= pair[1].evaluation.score));
+    }
+
+    #[test]
+    fn profiles_weigh_the_same_attack_differently() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let attacker = spawn(&mut world, &mut state, 0, 0, 0, 2);
+        let defender = spawn(&mut world, &mut state, 1, 1, 0, 5);
+        let attack = Command::Attack {
+            player: 0,
+            attacker,
+            defender,
+        };
+        let score = |state: &GameState| {
+            evaluate_action(&world, state, &attack)
+                .unwrap()
+                .evaluation
+                .score
+        };
+
+        let balanced = score(&state);
+        state.players[0].set_ai_profile(AiProfile::preset("balanced"));
+        assert!((score(&state) - balanced).abs() < 1e-9);
+        state.players[0].set_ai_profile(AiProfile::preset("berserker"));
+        let berserker = score(&state);
+        state.players[0].set_ai_profile(AiProfile::preset("turtle"));
+        let turtle = score(&state);
+
+        assert!(berserker > balanced && balanced > turtle);
+        assert!(berserker > 0.0 && turtle < 0.0);
+        assert_eq!(AiProfile::preset("coward"), None);
+    }
+
+    #[test]
+    fn computer_players_end_their_turn_when_nothing_is_worth_doing() {
+        let mut world = World::default();
+        let mut state = new_state();
+        state.state = State::Waiting;
+        let unit = spawn(&mut world, &mut state, 0, 0, 0, 1);
+        spawn(&mut world, &mut state, 1, 2, 0, 20);
+        assert_eq!(next_command(&world, &state), None);
+
+        state.players[0].set_ai_profile(AiProfile::preset("turtle"));
+        let retreat = next_command(&world, &state);
+        assert!(matches!(retreat, Some(Command::Move { player: 0, .. })));
+
+        world
+            .entry(state.persistent_ids.find(&world, unit).unwrap())
+            .unwrap()
+            .get_component_mut::<Unit>()
+            .unwrap()
+            .remaining_range = 0;
+        assert_eq!(
+            next_command(&world, &state),
+            Some(Command::EndTurn { player: 0 })
+        );
+    }
+
+    #[test]
+    fn computer_players_pick_their_promotion() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let unit = spawn(&mut world, &mut state, 0, 0, 0, 4);
+        let entity = state.persistent_ids.find(&world, unit).unwrap();
+        // A new artillery has 10 integrity.
+        world
+            .entry(entity)
+            .unwrap()
+            .add_component(UnitType("Artillery".to_owned()));
+        let options = vec![PerkId::Integrity, PerkId::Damage];
+        state.state = State::ChoosingPromotion(entity, options.clone());
+        assert_eq!(next_command(&world, &state), None);
+
+        state.players[0].set_ai_profile(AiProfile::preset("turtle"));
+        assert_eq!(
+            next_command(&world, &state),
+            Some(Command::ChoosePromotion {
+                player: 0,
+                unit,
+                perk: 1,
+            })
+        );
+
+        world
+            .entry(entity)
+            .unwrap()
+            .get_component_mut::<Unit>()
+            .unwrap()
+            .integrity = 3;
+        assert_eq!(
+            next_command(&world, &state),
+            Some(Command::ChoosePromotion {
+                player: 0,
+                unit,
+                perk: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn outcomes_do_not_depend_on_the_order_entities_were_inserted_in() {
+        let units = [
+            (PersistentId(0), 0, Hexagon::new_axial(0, 0), 4),
+            (PersistentId(1), 0, Hexagon::new_axial(0, 1), 4),
+            (PersistentId(2), 1, Hexagon::new_axial(1, 0), 3),
+            (PersistentId(3), 1, Hexagon::new_axial(1, -1), 3),
+        ];
+        let build = |reversed: bool| {
+            let mut world = World::default();
+            let mut state = new_state();
+            state.state = State::Waiting;
+            let mut order: Vec<_> = units.iter().collect();
+            if reversed {
+                order.reverse();
+            }
+            spawn_pickup(
+                &mut world,
+                Hexagon::new_axial(1, 0),
+                Pickup::Repair(1),
+                false,
+            );
+            for (id, player, position, damage) in order {
+                let entity = world.push((
+                    *id,
+                    PlayerComponent(*player),
+                    *position,
+                    Unit::new(10, *damage, 1, 1, 0, 1, 1, 1),
+                ));
+                state.persistent_ids.register(*id, entity);
+            }
+            (world, state)
+        };
+        let outcome = |world: &World, state: &GameState| {
+            let at_hexagon: Vec<Option<PersistentId>> =
+                get_entities_at_hexagon(&Hexagon::new_axial(1, 0), world)
+                    .into_iter()
+                    .map(|entity| PersistentId::of_entity(world, entity))
+                    .collect();
+            let commands: Vec<Command> = best_actions(world, state, 0, 10)
+                .into_iter()
+                .map(|suggestion| suggestion.command)
+                .collect();
+            (
+                at_hexagon,
+                commands,
+                next_command(world, state),
+                state.compute_checksum(world),
+            )
+        };
+
+        let (world, state) = build(false);
+        let (reversed_world, reversed_state) = build(true);
+        let first = outcome(&world, &state);
+
+        assert_eq!(first, outcome(&reversed_world, &reversed_state));
+        assert_eq!(first.0, vec![None, Some(PersistentId(2))]);
+        assert!(first.2.is_some());
+    }
+}
@@ -0,0 +1,262 @@
+//! Matches between computer players without Godot, for balance testing. A match is set up from a
+//! scenario and played through the same commands and state machine as the game, so its outcome
+//! only depends on the scenario, the rules, the unit catalog and the seed.
+
+use crate::ai::next_command;
+use crate::commands::{apply_command, Command};
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::edges::EdgeData;
+use crate::game_state::{GameState, State};
+use crate::player::Player;
+use crate::random::Rng;
+use crate::rules::Ruleset;
+use crate::scenario::Scenario;
+use crate::simulation::simulate_frame;
+use crate::state_machine::GameEvent;
+use crate::triggers::Triggers;
+use crate::unit_catalog::UnitCatalog;
+use gdnative::core_types::Color;
+use legion::{IntoQuery, World};
+
+/// The time every simulated frame takes.
+const FRAME_DELTA: f64 = 0.05;
+/// Frames a round may take before the match is called off, so rules the computer players can not
+/// play, like simultaneous rounds, can not hang a batch.
+const FRAMES_PER_ROUND: u32 = 10_000;
+
+/// What a player did during a match.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlayerStats {
+    pub attacks: u32,
+    pub damage_dealt: i32,
+    pub units_lost: u32,
+    pub units_remaining: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulationResult {
+    /// `None` for a draw and for matches that reached the round cap.
+    pub winner: Option<usize>,
+    pub rounds: u32,
+    pub per_player_stats: Vec<PlayerStats>,
+    pub final_checksum: u64,
+}
+
+/// Plays the scenario with the default unit catalog, see `run_simulation_with_catalog`.
+pub fn run_simulation(
+    scenario: &Scenario,
+    ruleset: &Ruleset,
+    seed: u64,
+    max_rounds: u32,
+) -> SimulationResult {
+    run_simulation_with_catalog(
+        scenario,
+        ruleset,
+        &GameState::new().unit_catalog,
+        seed,
+        max_rounds,
+    )
+}
+
+/// Lets computer players play the scenario until one of them wins or `max_rounds` are over.
+/// Every player with units gets the profile the scenario gives them, balanced if it gives none.
+pub fn run_simulation_with_catalog(
+    scenario: &Scenario,
+    ruleset: &Ruleset,
+    catalog: &UnitCatalog,
+    seed: u64,
+    max_rounds: u32,
+) -> SimulationResult {
+    let mut world = World::default();
+    let mut state = GameState::new();
+    state.rules = ruleset.clone();
+    state.unit_catalog = catalog.clone();
+    state.rng = Rng::new(seed);
+    let player_count = scenario
+        .units
+        .iter()
+        .map(|unit| unit.player + 1)
+        .chain(scenario.ai_players.iter().map(|ai| ai.player + 1))
+        .max()
+        .unwrap_or(0);
+    for index in 0..player_count {
+        let mut player = Player::new(format!("Player {}", index + 1), Color::rgb(1.0, 1.0, 1.0));
+        let profile = scenario
+            .ai_players
+            .iter()
+            .find(|ai| ai.player == index)
+            .and_then(|ai| ai.profile.resolve())
+            .unwrap_or_default();
+        player.set_ai_profile(Some(profile));
+        state.add_player(player);
+    }
+    let handicaps = state.handicaps();
+    for unit_type in scenario.load(
+        &mut world,
+        &mut state.persistent_ids,
+        &state.unit_catalog,
+        &handicaps,
+        false,
+    ) {
+        log_warn!("Unit type {} is not in the catalog", unit_type);
+    }
+    state.triggers = Triggers::new(scenario.triggers.clone());
+    state.edges = EdgeData::from_list(&scenario.edges);
+    state.current_player = Some(0);
+
+    let initial_units = count_units(&world, player_count);
+    let mut stats = vec![PlayerStats::default(); player_count];
+    let mut events = simulate_frame(&mut world, &mut state, 0.0);
+    let mut frames = 0;
+    loop {
+        record_attacks(&mut stats, &events);
+        if matches!(state.state, State::GameOver(_))
+            || state.round > max_rounds
+            || frames >= max_rounds.saturating_mul(FRAMES_PER_ROUND)
+        {
+            break;
+        }
+        if let Some(command) = next_command(&world, &state) {
+            if let Err(error) = apply_command(&world, &mut state, &command) {
+                log_warn!("Computer player order {:?} rejected: {:?}", command, error);
+                if let Some(player) = state.current_player {
+                    let _ = apply_command(&world, &mut state, &Command::EndTurn { player });
+                }
+            }
+        }
+        events = simulate_frame(&mut world, &mut state, FRAME_DELTA);
+        frames += 1;
+    }
+
+    let remaining_units = count_units(&world, player_count);
+    for (player, stats) in stats.iter_mut().enumerate() {
+        stats.units_remaining = remaining_units[player];
+        stats.units_lost = initial_units[player].saturating_sub(remaining_units[player]);
+    }
+    SimulationResult {
+        winner: match state.state {
+            State::GameOver(winner) => winner,
+            _ => None,
+        },
+        rounds: state.round.min(max_rounds),
+        per_player_stats: stats,
+        final_checksum: state.compute_checksum(&world),
+    }
+}
+
+fn record_attacks(stats: &mut [PlayerStats], events: &[GameEvent]) {
+    for event in events {
+        if let GameEvent::UnitAttacked {
+            attacker_player: Some(player),
+            damage,
+            ..
+        } = event
+        {
+            if let Some(stats) = stats.get_mut(*player) {
+                stats.attacks += 1;
+                stats.damage_dealt += damage;
+            }
+        }
+    }
+}
+
+fn count_units(world: &World, player_count: usize) -> Vec<u32> {
+    let mut counts = vec![0; player_count];
+    for (player, _) in <(&PlayerComponent, &Unit)>::query().iter(world) {
+        if let Some(count) = counts.get_mut(player.0) {
+            *count += 1;
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::components::terrain::Terrain;
+    use crate::scenario::{ScenarioField, ScenarioUnit};
+    use crate::systems::hexgrid::create_grid;
+
+    const MAX_ROUNDS: u32 = 20;
+    const SEEDS: u64 = 8;
+
+    fn scenario() -> Scenario {
+        let unit = |player, q, r, unit_type: &str| ScenarioUnit {
+            player,
+            position: Hexagon::new_axial(q, r),
+            unit_type: unit_type.to_owned(),
+            name: None,
+            from_roster: false,
+        };
+        Scenario {
+            fields: create_grid(3)
+                .into_iter()
+                .map(|position| ScenarioField {
+                    position,
+                    terrain: Terrain::default(),
+                })
+                .collect(),
+            units: vec![
+                unit(0, -2, 0, "Tank"),
+                unit(0, -2, 1, "Tank"),
+                unit(1, 2, 0, "Artillery"),
+                unit(1, 2, -1, "Artillery"),
+            ],
+            ..Scenario::default()
+        }
+    }
+
+    fn rules() -> Ruleset {
+        Ruleset {
+            events: true,
+            event_chance: 0.5,
+            ..Ruleset::default()
+        }
+    }
+
+    fn catalog_with_tank_damage(damage: i32) -> UnitCatalog {
+        let mut definitions = GameState::new().unit_catalog.definitions().to_vec();
+        for definition in definitions.iter_mut() {
+            if definition.name == "Tank" {
+                definition.damage = damage;
+            }
+        }
+        let json = serde_json::to_string(&definitions).unwrap();
+        UnitCatalog::from_json(&json).unwrap().0
+    }
+
+    #[test]
+    fn same_seed_plays_the_same_match() {
+        let first = run_simulation(&scenario(), &rules(), 7, MAX_ROUNDS);
+        let second = run_simulation(&scenario(), &rules(), 7, MAX_ROUNDS);
+
+        assert_eq!(first, second);
+        assert!(first.rounds >= 1 && first.rounds <= MAX_ROUNDS);
+        assert_eq!(first.per_player_stats.len(), 2);
+        for stats in &first.per_player_stats {
+            assert_eq!(stats.units_lost + stats.units_remaining, 2);
+        }
+        if let Some(winner) = first.winner {
+            assert_eq!(first.per_player_stats[1 - winner].units_remaining, 0);
+        }
+    }
+
+    #[test]
+    fn sweep_tank_damage() {
+        let mut win_rates = Vec::new();
+        for damage in [2, 6, 12].iter() {
+            let catalog = catalog_with_tank_damage(*damage);
+            let wins = (0..SEEDS)
+                .map(|seed| {
+                    run_simulation_with_catalog(&scenario(), &rules(), &catalog, seed, MAX_ROUNDS)
+                })
+                .filter(|result| result.winner == Some(0))
+                .count();
+            win_rates.push(wins as f64 / SEEDS as f64);
+        }
+
+        assert!(win_rates[2] >= win_rates[0], "{:?}", win_rates);
+    }
+}
@@ -0,0 +1,147 @@
+//! Buildings on the map. Scenarios and the editor place them, a unit that ends its move on one can
+//! garrison inside. Buildings have no unit, so like pickups they never block paths, but units
+//! next to them are in cover.
+
+use crate::components::building::Building;
+use crate::components::cover::ProvidesCover;
+use crate::components::field::Field;
+use crate::components::garrison::Garrisoned;
+use crate::components::hexagon::Hexagon;
+use crate::components::node_template::NodeTemplate;
+use crate::components::unit::Unit;
+use crate::systems::hexgrid::{get_neighbours, is_occupied_by_unit};
+use legion::{Entity, EntityStore, IntoQuery, World};
+
+const BUILDING_SCENE: &str = "res://Building.tscn";
+
+/// Armor a unit gets on top of its own while it is inside a building.
+pub const GARRISON_ARMOR_BONUS: i32 = 2;
+
+/// Integrity of buildings placed without one.
+pub const DEFAULT_BUILDING_INTEGRITY: i32 = 20;
+
+/// Places a building on `hexagon`, with a Godot node if `with_node` is set.
+pub fn spawn_building(
+    world: &mut World,
+    hexagon: Hexagon,
+    building: Building,
+    with_node: bool,
+) -> Entity {
+    if with_node {
+        world.push((
+            hexagon,
+            building,
+            ProvidesCover,
+            NodeTemplate::new(BUILDING_SCENE)
+                .visible(false)
+                .group("buildings")
+                .parent("Buildings")
+                .y_sort(),
+        ))
+    } else {
+        world.push((hexagon, building, ProvidesCover))
+    }
+}
+
+pub fn building_at<S: EntityStore>(world: &S, hexagon: &Hexagon) -> Option<(Entity, Building)> {
+    <(Entity, &Hexagon, &Building)>::query()
+        .iter(world)
+        .find(|(_, position, _)| *position == hexagon)
+        .map(|(entity, _, building)| (*entity, *building))
+}
+
+/// The unit inside the building on `hexagon`, if there is one.
+pub fn garrison_at<S: EntityStore>(world: &S, hexagon: &Hexagon) -> Option<Entity> {
+    <(Entity, &Hexagon, &Unit, &Garrisoned)>::query()
+        .iter(world)
+        .find(|(_, position, _, _)| *position == hexagon)
+        .map(|(entity, _, _, _)| *entity)
+}
+
+/// The first neighbour of `hexagon` on the map without a unit on it, in the order of the
+/// directions starting east.
+pub fn free_neighbour<S: EntityStore>(world: &S, hexagon: &Hexagon) -> Option<Hexagon> {
+    get_neighbours(hexagon)
+        .into_iter()
+        .find(|neighbour| is_free(world, neighbour))
+}
+
+/// Whether a unit can be put on `hexagon`: it is on the map and no unit stands there.
+pub fn is_free<S: EntityStore>(world: &S, hexagon: &Hexagon) -> bool {
+    <&Field>::query()
+        .iter(world)
+        .any(|field| field.location == *hexagon)
+        && !is_occupied_by_unit(hexagon, world)
+}
+
+/// Moves the unit into the building on its hexagon and adds the armor bonus. Returns false if
+/// there is no such unit or it is inside already.
+pub fn enter_garrison(world: &mut World, entity: Entity) -> bool {
+    let mut entry = match world.entry(entity) {
+        None => return false,
+        Some(entry) => entry,
+    };
+    if entry.get_component::<Garrisoned>().is_ok() {
+        return false;
+    }
+    match entry.get_component_mut::<Unit>() {
+        Err(_) => return false,
+        Ok(unit) => unit.armor += GARRISON_ARMOR_BONUS,
+    }
+    entry.add_component(Garrisoned);
+    true
+}
+
+/// Lets the unit leave its building for `target` and takes back the armor bonus. Returns false
+/// if the unit is not inside a building.
+pub fn leave_garrison(world: &mut World, entity: Entity, target: Hexagon) -> bool {
+    let mut entry = match world.entry(entity) {
+        None => return false,
+        Some(entry) => entry,
+    };
+    if entry.get_component::<Garrisoned>().is_err() {
+        return false;
+    }
+    if let Ok(unit) = entry.get_component_mut::<Unit>() {
+        unit.armor -= GARRISON_ARMOR_BONUS;
+    }
+    entry.remove_component::<Garrisoned>();
+    entry.add_component(target);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garrisoned_units_get_armor_until_they_leave() {
+        let mut world = World::default();
+        let building = Hexagon::zero();
+        let outside = Hexagon::new_axial(1, 0);
+        world.push((Field::new(outside),));
+        spawn_building(&mut world, building, Building::new(10), false);
+        let entity = world.push((building, Unit::new(10, 4, 1, 1, 1, 3, 3, 1)));
+        let armor = |world: &World| {
+            world
+                .entry_ref(entity)
+                .unwrap()
+                .get_component::<Unit>()
+                .unwrap()
+                .armor
+        };
+
+        assert!(enter_garrison(&mut world, entity));
+        assert!(!enter_garrison(&mut world, entity));
+        assert_eq!(armor(&world), 1 + GARRISON_ARMOR_BONUS);
+        assert_eq!(garrison_at(&world, &building), Some(entity));
+        assert_eq!(free_neighbour(&world, &building), Some(outside));
+
+        assert!(leave_garrison(&mut world, entity, outside));
+        assert!(!leave_garrison(&mut world, entity, outside));
+        assert_eq!(armor(&world), 1);
+        assert_eq!(garrison_at(&world, &building), None);
+        assert_eq!(free_neighbour(&world, &building), None);
+        assert!(building_at(&world, &building).is_some());
+    }
+}
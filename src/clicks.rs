@@ -0,0 +1,131 @@
+//! Tells single and double clicks on hexagons apart. A second click on the same hexagon within
+//! the double click time is a double click. Clicks on units wait for that time before they count
+//! as single clicks, clicks on empty hexagons count right away so moving stays responsive.
+
+use crate::components::hexagon::Hexagon;
+
+pub const DEFAULT_DOUBLE_CLICK_TIME: f64 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Click {
+    Single(Hexagon),
+    Double(Hexagon),
+}
+
+#[derive(Clone, Debug)]
+pub struct ClickTracker {
+    pub double_click_time: f64,
+    /// The last click that may still become a double click.
+    last: Option<(Hexagon, f64)>,
+    /// A click on a unit that waits to find out whether it is a double click.
+    pending: Option<(Hexagon, f64)>,
+}
+
+impl Default for ClickTracker {
+    fn default() -> Self {
+        ClickTracker {
+            double_click_time: DEFAULT_DOUBLE_CLICK_TIME,
+            last: None,
+            pending: None,
+        }
+    }
+}
+
+impl ClickTracker {
+    /// Takes a click on `hexagon` and returns the clicks to act on now. `on_unit` delays the
+    /// single click until `poll` finds the double click time over.
+    pub fn click(&mut self, hexagon: Hexagon, time: f64, on_unit: bool) -> Vec<Click> {
+        let mut clicks = Vec::new();
+        if let Some((last, clicked_at)) = self.last.take() {
+            if last == hexagon && time - clicked_at <= self.double_click_time {
+                self.pending = None;
+                clicks.push(Click::Double(hexagon));
+                return clicks;
+            }
+        }
+        // A click somewhere else settles the one that was waiting.
+        if let Some((pending, _)) = self.pending.take() {
+            clicks.push(Click::Single(pending));
+        }
+        self.last = Some((hexagon, time));
+        if on_unit {
+            self.pending = Some((hexagon, time));
+        } else {
+            clicks.push(Click::Single(hexagon));
+        }
+        clicks
+    }
+
+    /// The waiting click, once it can no longer become a double click.
+    pub fn poll(&mut self, time: f64) -> Option<Click> {
+        match self.pending {
+            Some((hexagon, clicked_at)) if time - clicked_at > self.double_click_time => {
+                self.pending = None;
+                Some(Click::Single(hexagon))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hexagon(q: i32) -> Hexagon {
+        Hexagon::new_axial(q, 0)
+    }
+
+    #[test]
+    fn clicks_on_empty_hexagons_count_right_away() {
+        let mut tracker = ClickTracker::default();
+
+        assert_eq!(
+            tracker.click(hexagon(0), 1.0, false),
+            vec![Click::Single(hexagon(0))]
+        );
+        assert_eq!(tracker.poll(5.0), None);
+    }
+
+    #[test]
+    fn clicks_on_units_wait_for_the_double_click_time() {
+        let mut tracker = ClickTracker::default();
+
+        assert!(tracker.click(hexagon(0), 1.0, true).is_empty());
+        assert_eq!(tracker.poll(1.2), None);
+        assert_eq!(tracker.poll(1.31), Some(Click::Single(hexagon(0))));
+        assert_eq!(tracker.poll(2.0), None);
+    }
+
+    #[test]
+    fn second_click_within_the_window_is_a_double_click() {
+        let mut tracker = ClickTracker::default();
+
+        assert!(tracker.click(hexagon(0), 1.0, true).is_empty());
+        assert_eq!(
+            tracker.click(hexagon(0), 1.2, true),
+            vec![Click::Double(hexagon(0))]
+        );
+        // The single click is swallowed by the double click.
+        assert_eq!(tracker.poll(2.0), None);
+        // A third click starts over.
+        assert!(tracker.click(hexagon(0), 1.3, true).is_empty());
+    }
+
+    #[test]
+    fn late_or_distant_second_clicks_are_single_clicks() {
+        let mut tracker = ClickTracker::default();
+
+        tracker.click(hexagon(0), 1.0, true);
+        assert_eq!(
+            tracker.click(hexagon(1), 1.1, false),
+            vec![Click::Single(hexagon(0)), Click::Single(hexagon(1))]
+        );
+
+        tracker.click(hexagon(2), 2.0, false);
+        assert_eq!(
+            tracker.click(hexagon(2), 2.5, false),
+            vec![Click::Single(hexagon(2))]
+        );
+    }
+}
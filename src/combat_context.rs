@@ -0,0 +1,72 @@
+//! What changes the damage of an attack besides the two units: the side of the defender it hits
+//! and the cover in the way.
+
+use crate::components::hexagon::Hexagon;
+use crate::cover::{apply_cover, cover_count};
+use crate::flanking::{attack_arc, AttackArc};
+use legion::{Entity, EntityStore};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CombatContext {
+    pub arc: AttackArc,
+    /// Number of hexagons next to the defender that cover it against the attack.
+    pub cover: usize,
+}
+
+impl CombatContext {
+    /// `damage` with the bonus of the arc and the reduction of the cover.
+    pub fn damage(&self, damage: i32) -> i32 {
+        apply_cover(self.arc.apply(damage), self.cover)
+    }
+}
+
+/// The context of an attack from `attacker_position` on `defender`.
+pub fn combat_context<S: EntityStore>(
+    world: &S,
+    attacker_position: &Hexagon,
+    defender: Entity,
+) -> CombatContext {
+    let cover = world
+        .entry_ref(defender)
+        .ok()
+        .and_then(|entry| entry.get_component::<Hexagon>().ok().copied())
+        .map_or(0, |position| {
+            cover_count(world, attacker_position, &position)
+        });
+    CombatContext {
+        arc: attack_arc(world, attacker_position, defender),
+        cover,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::cover::ProvidesCover;
+    use crate::components::facing::Facing;
+    use crate::components::hexagon::Direction;
+    use crate::cover::COVER_DAMAGE_REDUCTION;
+    use legion::World;
+
+    #[test]
+    fn flanks_and_cover_add_up() {
+        let mut world = World::default();
+        world.push((Hexagon::new_axial(1, 0), ProvidesCover));
+        let defender = world.push((Hexagon::zero(), Facing(Direction::West)));
+
+        let context = combat_context(&world, &Hexagon::new_axial(3, 0), defender);
+
+        assert_eq!(
+            context,
+            CombatContext {
+                arc: AttackArc::Rear,
+                cover: 1,
+            }
+        );
+        assert_eq!(
+            context.damage(8),
+            AttackArc::Rear.apply(8) - COVER_DAMAGE_REDUCTION
+        );
+        assert_eq!(CombatContext::default().damage(8), 8);
+    }
+}
@@ -0,0 +1,78 @@
+//! How strongly a hit should be felt. The screen shake is left to the scripts, the hit pause
+//! freezes moving units for a moment.
+
+pub const MAX_SHAKE: f32 = 1.0;
+/// Seconds of hit pause for a lethal hit.
+pub const MAX_HITSTOP: f64 = 0.15;
+/// Upper bound of the hit pause of several hits in a row.
+pub const DEFAULT_HITSTOP_CAP: f64 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CombatFeedback {
+    pub shake: f32,
+    pub hitstop: f64,
+}
+
+/// Feedback for a hit of `damage` on a unit with `max_integrity`. The shake grows with the share
+/// of the integrity lost, the pause only for heavy hits. Lethal hits get the maximum of both.
+pub fn combat_feedback(damage: i32, max_integrity: i32, lethal: bool) -> CombatFeedback {
+    let ratio = if lethal {
+        1.0
+    } else {
+        (damage.max(0) as f64 / max_integrity.max(1) as f64).min(1.0)
+    };
+    CombatFeedback {
+        shake: MAX_SHAKE * ratio as f32,
+        hitstop: MAX_HITSTOP * ratio * ratio,
+    }
+}
+
+/// Adds the pause of a new hit to the running one, at most up to `cap`.
+pub fn add_hitstop(timer: f64, hitstop: f64, cap: f64) -> f64 {
+    (timer + hitstop).min(cap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feedback_grows_with_the_share_of_integrity_lost() {
+        assert_eq!(
+            combat_feedback(0, 10, false),
+            CombatFeedback {
+                shake: 0.0,
+                hitstop: 0.0
+            }
+        );
+        let half = combat_feedback(5, 10, false);
+        assert_eq!(half.shake, MAX_SHAKE / 2.0);
+        assert_eq!(half.hitstop, MAX_HITSTOP / 4.0);
+        // Overkill does not go beyond a full hit.
+        assert_eq!(
+            combat_feedback(25, 10, false),
+            combat_feedback(10, 10, false)
+        );
+    }
+
+    #[test]
+    fn lethal_hits_get_the_maximum() {
+        assert_eq!(
+            combat_feedback(1, 10, true),
+            CombatFeedback {
+                shake: MAX_SHAKE,
+                hitstop: MAX_HITSTOP
+            }
+        );
+    }
+
+    #[test]
+    fn chained_hits_stay_below_the_cap() {
+        let mut timer = 0.0;
+        for _ in 0..10 {
+            timer = add_hitstop(timer, MAX_HITSTOP, DEFAULT_HITSTOP_CAP);
+        }
+        assert_eq!(timer, DEFAULT_HITSTOP_CAP);
+        assert_eq!(add_hitstop(0.0, MAX_HITSTOP, 0.05), 0.05);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::components::hexagon::Hexagon;
+use crate::flanking::AttackArc;
+use crate::messages::{self, Message};
+use gdnative::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::vec_deque::VecDeque;
+
+pub const DEFAULT_LOG_CAPACITY: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogEntryKind {
+    Move,
+    Attack,
+    Destroy,
+    Capture,
+    Event,
+}
+
+impl LogEntryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogEntryKind::Move => "move",
+            LogEntryKind::Attack => "attack",
+            LogEntryKind::Destroy => "destroy",
+            LogEntryKind::Capture => "capture",
+            LogEntryKind::Event => "event",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub round: u32,
+    pub player: Option<usize>,
+    pub kind: LogEntryKind,
+    pub message: Message,
+    pub positions: Vec<Hexagon>,
+}
+
+impl LogEntry {
+    pub fn moved(
+        round: u32,
+        player: Option<usize>,
+        unit: &str,
+        from: Hexagon,
+        to: Hexagon,
+    ) -> Self {
+        LogEntry {
+            round,
+            player,
+            kind: LogEntryKind::Move,
+            message: messages::unit_moved(unit, &from, &to),
+            positions: vec![from, to],
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn attacked(
+        round: u32,
+        player: Option<usize>,
+        attacker_name: &str,
+        attacker: Hexagon,
+        defender_name: &str,
+        defender: Hexagon,
+        arc: AttackArc,
+        damage: i32,
+        remaining_integrity: i32,
+    ) -> Self {
+        LogEntry {
+            round,
+            player,
+            kind: LogEntryKind::Attack,
+            message: messages::damage_dealt(
+                attacker_name,
+                &attacker,
+                defender_name,
+                &defender,
+                arc,
+                damage,
+                remaining_integrity,
+            ),
+            positions: vec![attacker, defender],
+        }
+    }
+
+    pub fn destroyed(round: u32, player: Option<usize>, unit: &str, position: Hexagon) -> Self {
+        LogEntry {
+            round,
+            player,
+            kind: LogEntryKind::Destroy,
+            message: messages::unit_destroyed(unit, &position),
+            positions: vec![position],
+        }
+    }
+
+    /// A random event, `message` describes what happened.
+    pub fn random_event(
+        round: u32,
+        player: Option<usize>,
+        message: Message,
+        position: Option<Hexagon>,
+    ) -> Self {
+        LogEntry {
+            round,
+            player,
+            kind: LogEntryKind::Event,
+            message,
+            positions: position.into_iter().collect(),
+        }
+    }
+
+    pub fn to_dictionary(&self, owner: &Object) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("round", self.round);
+        dict.insert(
+            "player",
+            match self.player {
+                None => -1,
+                Some(player) => player as i64,
+            },
+        );
+        dict.insert("kind", self.kind.name());
+        dict.insert("text", self.message.translate(owner));
+        let positions = VariantArray::new();
+        for position in &self.positions {
+            let position_dict = Dictionary::new();
+            position_dict.insert("q", position.get_q());
+            position_dict.insert("r", position.get_r());
+            positions.push(position_dict.owned_to_variant());
+        }
+        dict.insert("positions", positions.owned_to_variant());
+        dict
+    }
+}
+
+/// Bounded log of the last actions, oldest entries are evicted once the capacity is reached.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CombatLog {
+    capacity: usize,
+    entries: VecDeque<LogEntry>,
+    #[serde(skip)]
+    unannounced: usize,
+}
+
+impl CombatLog {
+    pub fn new() -> Self {
+        CombatLog::with_capacity(DEFAULT_LOG_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        CombatLog {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            unannounced: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, entry: LogEntry) {
+        self.entries.push_back(entry);
+        self.unannounced += 1;
+        self.evict();
+    }
+
+    /// Returns up to `count` of the newest entries, oldest first.
+    pub fn latest(&self, count: usize) -> impl Iterator<Item = &LogEntry> {
+        let skip = self.entries.len().saturating_sub(count);
+        self.entries.iter().skip(skip)
+    }
+
+    /// Returns the entries pushed since the last call, so they can be announced to the UI.
+    pub fn take_unannounced(&mut self) -> Vec<LogEntry> {
+        let entries = self.latest(self.unannounced).cloned().collect();
+        self.unannounced = 0;
+        entries
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+        self.unannounced = self.unannounced.min(self.entries.len());
+    }
+}
+
+impl Default for CombatLog {
+    fn default() -> Self {
+        CombatLog::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_evicts_oldest_entries_when_capacity_is_reached() {
+        let mut log = CombatLog::with_capacity(2);
+        log.push(LogEntry::destroyed(
+            1,
+            Some(0),
+            "Unit",
+            Hexagon::new_axial(0, 0),
+        ));
+        log.push(LogEntry::destroyed(
+            2,
+            Some(0),
+            "Unit",
+            Hexagon::new_axial(1, 0),
+        ));
+        log.push(LogEntry::destroyed(
+            3,
+            Some(0),
+            "Unit",
+            Hexagon::new_axial(2, 0),
+        ));
+
+        assert_eq!(log.len(), 2);
+        let rounds: Vec<u32> = log.latest(10).map(|entry| entry.round).collect();
+        assert_eq!(rounds, vec![2, 3]);
+    }
+
+    #[test]
+    fn set_capacity_evicts_surplus_entries() {
+        let mut log = CombatLog::new();
+        for round in 0..10 {
+            log.push(LogEntry::destroyed(round, None, "Unit", Hexagon::zero()));
+        }
+
+        log.set_capacity(3);
+
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.latest(1).next().unwrap().round, 9);
+    }
+
+    #[test]
+    fn latest_returns_newest_entries_oldest_first() {
+        let mut log = CombatLog::new();
+        for round in 0..5 {
+            log.push(LogEntry::destroyed(round, None, "Unit", Hexagon::zero()));
+        }
+
+        let rounds: Vec<u32> = log.latest(2).map(|entry| entry.round).collect();
+        assert_eq!(rounds, vec![3, 4]);
+    }
+
+    #[test]
+    fn take_unannounced_only_returns_new_entries() {
+        let mut log = CombatLog::with_capacity(3);
+        log.push(LogEntry::destroyed(1, None, "Unit", Hexagon::zero()));
+        assert_eq!(log.take_unannounced().len(), 1);
+
+        for round in 2..7 {
+            log.push(LogEntry::destroyed(round, None, "Unit", Hexagon::zero()));
+        }
+        let rounds: Vec<u32> = log
+            .take_unannounced()
+            .iter()
+            .map(|entry| entry.round)
+            .collect();
+        assert_eq!(rounds, vec![4, 5, 6]);
+        assert!(log.take_unannounced().is_empty());
+    }
+
+    #[test]
+    fn moved_formats_text_and_positions() {
+        let entry = LogEntry::moved(
+            2,
+            Some(1),
+            "Tank 2",
+            Hexagon::new_axial(0, 1),
+            Hexagon::new_axial(1, 1),
+        );
+        assert_eq!(entry.kind, LogEntryKind::Move);
+        assert_eq!(
+            entry.message.to_fallback_string(),
+            "Tank 2 moved from (0, 1) to (1, 1)"
+        );
+        assert_eq!(
+            entry.positions,
+            vec![Hexagon::new_axial(0, 1), Hexagon::new_axial(1, 1)]
+        );
+    }
+
+    #[test]
+    fn attacked_formats_text_and_clamps_integrity() {
+        let entry = LogEntry::attacked(
+            1,
+            Some(0),
+            "Tank 1",
+            Hexagon::new_axial(2, 0),
+            "Old Faithful",
+            Hexagon::new_axial(-2, 0),
+            AttackArc::Rear,
+            7,
+            -3,
+        );
+        assert_eq!(entry.kind, LogEntryKind::Attack);
+        assert_eq!(
+            entry.message.to_fallback_string(),
+            "Tank 1 at (2, 0) dealt 7 damage to Old Faithful at (-2, 0) from the rear (0 integrity left)"
+        );
+    }
+
+    #[test]
+    fn destroyed_formats_text() {
+        let entry = LogEntry::destroyed(4, Some(1), "Artillery 2", Hexagon::new_axial(-1, 3));
+        assert_eq!(entry.kind, LogEntryKind::Destroy);
+        assert_eq!(
+            entry.message.to_fallback_string(),
+            "Artillery 2 at (-1, 3) was destroyed"
+        );
+    }
+}
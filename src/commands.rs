@@ -0,0 +1,1287 @@
+//! Player actions as data. Local input and remote peers both go through `apply_command`, so every
+//! client running the simulation ends up in the same state.
+
+use crate::abilities::{validate_ability, AbilityError};
+use crate::action_log::LoggedAction;
+use crate::ai::evaluate_action;
+use crate::buildings::{building_at, garrison_at, is_free};
+use crate::components::demolition::Demolition;
+use crate::components::garrison::Garrisoned;
+use crate::components::hexagon::{Direction, Hexagon};
+use crate::components::overwatch::Overwatching;
+use crate::components::persistent_id::{PersistentId, PersistentIds};
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::{AttackError, Unit};
+use crate::game_state::{set_state, validate, GameState, MovementProgress, State};
+use crate::legion::entity_has_component;
+use crate::ping::Ping;
+use crate::planning::{start_resolution, PlannedOrder};
+use crate::savegame::SaveData;
+use crate::systems::hexgrid::{is_line_of_sight_clear, is_occupied_by_unit};
+use crate::turn_file::note_turn_start;
+use legion::{Entity, EntityStore, World};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    Move {
+        player: usize,
+        unit: PersistentId,
+        path: Vec<Hexagon>,
+    },
+    Attack {
+        player: usize,
+        attacker: PersistentId,
+        defender: PersistentId,
+    },
+    /// Uses the ability with the index `ability` of the unit on `target`.
+    UseAbility {
+        player: usize,
+        unit: PersistentId,
+        ability: usize,
+        target: Hexagon,
+    },
+    /// Gives up the remaining movement of the unit to fire at the first enemy moving into its
+    /// range.
+    Overwatch {
+        player: usize,
+        unit: PersistentId,
+    },
+    /// Attacks the bridge between the neighbours `from` and `to` with a demolition unit.
+    AttackEdge {
+        player: usize,
+        unit: PersistentId,
+        from: Hexagon,
+        to: Hexagon,
+    },
+    /// Moves the unit into the building it stands on.
+    Garrison {
+        player: usize,
+        unit: PersistentId,
+    },
+    /// Lets the unit leave its building for the free neighbour `target`.
+    Ungarrison {
+        player: usize,
+        unit: PersistentId,
+        target: Hexagon,
+    },
+    /// Attacks the building on `target` and whoever is inside.
+    AttackBuilding {
+        player: usize,
+        unit: PersistentId,
+        target: Hexagon,
+    },
+    /// Turns the unit in place to face `facing`.
+    Rotate {
+        player: usize,
+        unit: PersistentId,
+        facing: Direction,
+    },
+    /// Selects a unit, or clears the selection, so other clients can show what the player is
+    /// looking at.
+    Select {
+        player: usize,
+        unit: Option<PersistentId>,
+    },
+    /// Picks the perk with the index `perk` among the options of the unit waiting for its
+    /// promotion.
+    ChoosePromotion {
+        player: usize,
+        unit: PersistentId,
+        perk: usize,
+    },
+    EndTurn {
+        player: usize,
+    },
+    /// Ends the planning phase of the simultaneous rules. The planned orders of all players are
+    /// carried out together.
+    ResolveRound {
+        player: usize,
+    },
+    /// Places a marker on the map. Pings do not change the game, so any player can send them at
+    /// any time.
+    Ping {
+        player: usize,
+        hexagon: Hexagon,
+        kind: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// The previous command is still being executed or the game is over.
+    NotReady,
+    NotCurrentPlayer,
+    UnknownPlayer(usize),
+    UnknownUnit(PersistentId),
+    NotOwnUnit(PersistentId),
+    FriendlyTarget(PersistentId),
+    InvalidPath,
+    OutOfRange,
+    /// With the line of sight rule, a unit, smoke or a hole blocks the view of the target.
+    NoLineOfSight,
+    NoAttacksLeft,
+    /// The unit moved this round and cannot fire after moving.
+    MovedBeforeFiring,
+    Ability(AbilityError),
+    AlreadyOverwatching(PersistentId),
+    /// Only demolition units can attack bridges.
+    NoDemolition(PersistentId),
+    /// There is no bridge between the hexagons.
+    NoBridge,
+    /// There is no building on the hexagon.
+    NoBuilding,
+    AlreadyGarrisoned(PersistentId),
+    NotGarrisoned(PersistentId),
+    /// The unit is inside a building and has to leave it before it can move.
+    Garrisoned(PersistentId),
+    /// The local session is an observer and cannot issue commands.
+    Observing,
+    /// The current player is not controlled on this machine.
+    NotYourTurn,
+    /// With the initiative rules only the unit whose turn it is can act.
+    NotActiveUnit(PersistentId),
+    /// Only moves and attacks can be planned.
+    NotPlannable,
+    /// A tutorial step waits for a different action.
+    TutorialBlocked,
+    /// The unit is not the one waiting for its promotion.
+    NotPromoted(PersistentId),
+    /// The promotion offers no perk with the index.
+    UnknownPerk(usize),
+    /// The player ordered as many moves and attacks this round as the command points allow.
+    NoCommandPointsLeft,
+}
+
+impl From<AttackError> for CommandError {
+    fn from(error: AttackError) -> Self {
+        match error {
+            AttackError::NoAttacksLeft => CommandError::NoAttacksLeft,
+            AttackError::MovedBeforeFiring => CommandError::MovedBeforeFiring,
+        }
+    }
+}
+
+impl Command {
+    pub fn player(&self) -> usize {
+        match *self {
+            Command::Move { player, .. } => player,
+            Command::Attack { player, .. } => player,
+            Command::UseAbility { player, .. } => player,
+            Command::Overwatch { player, .. } => player,
+            Command::AttackEdge { player, .. } => player,
+            Command::Garrison { player, .. } => player,
+            Command::Ungarrison { player, .. } => player,
+            Command::AttackBuilding { player, .. } => player,
+            Command::Rotate { player, .. } => player,
+            Command::Select { player, .. } => player,
+            Command::ChoosePromotion { player, .. } => player,
+            Command::EndTurn { player } => player,
+            Command::ResolveRound { player } => player,
+            Command::Ping { player, .. } => player,
+        }
+    }
+
+    /// The unit that carries out the command, if it is an order for a unit.
+    pub fn acting_unit(&self) -> Option<PersistentId> {
+        match *self {
+            Command::Move { unit, .. } => Some(unit),
+            Command::Attack { attacker, .. } => Some(attacker),
+            Command::UseAbility { unit, .. } => Some(unit),
+            Command::Overwatch { unit, .. } => Some(unit),
+            Command::AttackEdge { unit, .. } => Some(unit),
+            Command::Garrison { unit, .. } => Some(unit),
+            Command::Ungarrison { unit, .. } => Some(unit),
+            Command::AttackBuilding { unit, .. } => Some(unit),
+            Command::Rotate { unit, .. } => Some(unit),
+            Command::ChoosePromotion { unit, .. } => Some(unit),
+            Command::Select { .. }
+            | Command::EndTurn { .. }
+            | Command::ResolveRound { .. }
+            | Command::Ping { .. } => None,
+        }
+    }
+
+    /// Whether the command costs a command point, which only moves and attacks do.
+    pub fn spends_command_point(&self) -> bool {
+        matches!(self, Command::Move { .. } | Command::Attack { .. })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Move { .. } => "move",
+            Command::Attack { .. } => "attack",
+            Command::UseAbility { .. } => "use_ability",
+            Command::Overwatch { .. } => "overwatch",
+            Command::AttackEdge { .. } => "attack_edge",
+            Command::Garrison { .. } => "garrison",
+            Command::Ungarrison { .. } => "ungarrison",
+            Command::AttackBuilding { .. } => "attack_building",
+            Command::Rotate { .. } => "rotate",
+            Command::Select { .. } => "select",
+            Command::ChoosePromotion { .. } => "choose_promotion",
+            Command::EndTurn { .. } => "end_turn",
+            Command::ResolveRound { .. } => "resolve_round",
+            Command::Ping { .. } => "ping",
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Checks that the command is legal for the acting player and starts executing it. Illegal
+/// commands leave the state and the world untouched, legal ones are added to the action log.
+pub fn apply_command(
+    world: &World,
+    state: &mut GameState,
+    command: &Command,
+) -> Result<(), CommandError> {
+    let action = LoggedAction::new(world, state, command);
+    let base = match action {
+        Some(_) if state.action_log.is_empty() => Some(SaveData::from_game(world, state)),
+        _ => None,
+    };
+    if action.is_some() {
+        note_turn_start(world, state);
+    }
+    // Planned orders are limited by the units, not by command points.
+    let spends_point = command.spends_command_point() && state.state != State::Planning;
+    execute_command(world, state, command)?;
+    if spends_point {
+        *state.commands_used.entry(command.player()).or_insert(0) += 1;
+    }
+    if let Some(action) = action {
+        state.action_log.push(base, action);
+    }
+    Ok(())
+}
+
+fn execute_command(
+    world: &World,
+    state: &mut GameState,
+    command: &Command,
+) -> Result<(), CommandError> {
+    if let Command::Ping {
+        player,
+        hexagon,
+        kind,
+    } = command
+    {
+        if *player >= state.players.len() {
+            return Err(CommandError::UnknownPlayer(*player));
+        }
+        state.pings.add(Ping::new(*hexagon, kind.clone(), *player));
+        return Ok(());
+    }
+    if state.state == State::Planning {
+        return plan_command(world, state, command);
+    }
+    if let Command::ResolveRound { .. } = command {
+        // Only the planning phase has a round to resolve.
+        return Err(CommandError::NotReady);
+    }
+    if let State::ChoosingPromotion(promoted, options) = &state.state {
+        // The game waits for the pick, nothing else is accepted until then.
+        let (player, unit, perk) = match command {
+            Command::ChoosePromotion { player, unit, perk } => (*player, *unit, *perk),
+            _ => return Err(CommandError::NotReady),
+        };
+        let (entity, _, _) = find_own_unit(world, &state.persistent_ids, player, unit)?;
+        if entity != *promoted {
+            return Err(CommandError::NotPromoted(unit));
+        }
+        let perk = *options.get(perk).ok_or(CommandError::UnknownPerk(perk))?;
+        set_state(state, State::Promoting(entity, perk));
+        return Ok(());
+    }
+    if !state.state.accepts_orders() {
+        return Err(CommandError::NotReady);
+    }
+    if state.current_player != Some(command.player()) {
+        return Err(CommandError::NotCurrentPlayer);
+    }
+    if state.rules.initiative {
+        if let Some(unit) = command.acting_unit() {
+            let active = state
+                .initiative
+                .active()
+                .and_then(|entity| PersistentId::of_entity(world, entity));
+            if active != Some(unit) {
+                return Err(CommandError::NotActiveUnit(unit));
+            }
+        }
+    }
+    if command.spends_command_point() && !state.has_command_points(command.player()) {
+        return Err(CommandError::NoCommandPointsLeft);
+    }
+    let next_state = match command {
+        Command::Move { player, unit, path } => {
+            let (entity, moving_unit, hexagon) =
+                find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            if entity_has_component::<Garrisoned, _>(world, &entity) {
+                return Err(CommandError::Garrisoned(*unit));
+            }
+            validate_path(world, hexagon, path)?;
+            // Longer paths are fine, the unit stops once its range is used up.
+            if moving_unit.movement_cost_left(&state.rules) <= 0 {
+                return Err(CommandError::OutOfRange);
+            }
+            state.movement = MovementProgress::new(path.iter().copied().collect());
+            State::Moving(entity)
+        }
+        Command::Attack {
+            player,
+            attacker,
+            defender,
+        } => {
+            let (attacker_entity, attacker_unit, attacker_hexagon) =
+                find_own_unit(world, &state.persistent_ids, *player, *attacker)?;
+            let (defender_entity, defender_player, _, defender_hexagon) =
+                find_unit(world, &state.persistent_ids, *defender)?;
+            if defender_player == Some(*player) {
+                return Err(CommandError::FriendlyTarget(*defender));
+            }
+            if let Some(error) = attacker_unit.attack_error(&state.rules) {
+                return Err(error.into());
+            }
+            if !attacker_unit.is_in_attack_range(attacker_hexagon.distance_to(&defender_hexagon)) {
+                return Err(CommandError::OutOfRange);
+            }
+            if state.rules.line_of_sight
+                && !is_line_of_sight_clear(
+                    &attacker_hexagon,
+                    &defender_hexagon,
+                    &state.map_bounds,
+                    world,
+                )
+            {
+                return Err(CommandError::NoLineOfSight);
+            }
+            State::Attacking(attacker_entity, defender_entity)
+        }
+        Command::UseAbility {
+            player,
+            unit,
+            ability,
+            target,
+        } => {
+            let (entity, _, _) = find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            validate_ability(world, &state.rules, entity, *ability, *target)
+                .map_err(CommandError::Ability)?;
+            State::UsingAbility(entity, *ability, *target)
+        }
+        Command::Overwatch { player, unit } => {
+            let (entity, overwatching_unit, _) =
+                find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            if let Some(error) = overwatching_unit.attack_error(&state.rules) {
+                return Err(error.into());
+            }
+            if entity_has_component::<Overwatching, _>(world, &entity) {
+                return Err(CommandError::AlreadyOverwatching(*unit));
+            }
+            State::EnteringOverwatch(entity)
+        }
+        Command::AttackEdge {
+            player,
+            unit,
+            from,
+            to,
+        } => {
+            let (entity, attacking_unit, hexagon) =
+                find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            if !entity_has_component::<Demolition, _>(world, &entity) {
+                return Err(CommandError::NoDemolition(*unit));
+            }
+            if state.edges.bridge_integrity(from, to).is_none() {
+                return Err(CommandError::NoBridge);
+            }
+            if let Some(error) = attacking_unit.attack_error(&state.rules) {
+                return Err(error.into());
+            }
+            // The bridge is in range if one of its ends is.
+            if !attacking_unit.is_in_attack_range(hexagon.distance_to(from))
+                && !attacking_unit.is_in_attack_range(hexagon.distance_to(to))
+            {
+                return Err(CommandError::OutOfRange);
+            }
+            State::AttackingEdge(entity, *from, *to)
+        }
+        Command::Garrison { player, unit } => {
+            let (entity, _, hexagon) = find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            if building_at(world, &hexagon).is_none() {
+                return Err(CommandError::NoBuilding);
+            }
+            if entity_has_component::<Garrisoned, _>(world, &entity) {
+                return Err(CommandError::AlreadyGarrisoned(*unit));
+            }
+            State::Garrisoning(entity)
+        }
+        Command::Ungarrison {
+            player,
+            unit,
+            target,
+        } => {
+            let (entity, leaving_unit, hexagon) =
+                find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            if !entity_has_component::<Garrisoned, _>(world, &entity) {
+                return Err(CommandError::NotGarrisoned(*unit));
+            }
+            // Leaving is a step onto the neighbour.
+            if !hexagon.is_neighbour(target) || !is_free(world, target) {
+                return Err(CommandError::InvalidPath);
+            }
+            if leaving_unit.movement_cost_left(&state.rules) <= 0 {
+                return Err(CommandError::OutOfRange);
+            }
+            State::Ungarrisoning(entity, *target)
+        }
+        Command::AttackBuilding {
+            player,
+            unit,
+            target,
+        } => {
+            let (entity, attacking_unit, hexagon) =
+                find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            if building_at(world, target).is_none() {
+                return Err(CommandError::NoBuilding);
+            }
+            let garrison = garrison_at(world, target)
+                .and_then(|garrison| PersistentId::of_entity(world, garrison));
+            if let Some(garrison) = garrison {
+                if find_unit(world, &state.persistent_ids, garrison)?.1 == Some(*player) {
+                    return Err(CommandError::FriendlyTarget(garrison));
+                }
+            }
+            if let Some(error) = attacking_unit.attack_error(&state.rules) {
+                return Err(error.into());
+            }
+            if !attacking_unit.is_in_attack_range(hexagon.distance_to(target)) {
+                return Err(CommandError::OutOfRange);
+            }
+            State::AttackingBuilding(entity, *target)
+        }
+        Command::Rotate {
+            player,
+            unit,
+            facing,
+        } => {
+            let (entity, turning_unit, _) =
+                find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            if turning_unit.movement_cost_left(&state.rules) <= 0 {
+                return Err(CommandError::OutOfRange);
+            }
+            State::Turning(entity, *facing)
+        }
+        Command::Select { unit: None, .. } => State::Waiting,
+        Command::Select {
+            unit: Some(unit), ..
+        } => State::Selected(find_unit(world, &state.persistent_ids, *unit)?.0),
+        Command::ChoosePromotion { unit, .. } => return Err(CommandError::NotPromoted(*unit)),
+        // With the initiative rules this passes the turn of the active unit.
+        Command::EndTurn { .. } => State::NewRound,
+        Command::ResolveRound { .. } => {
+            unreachable!("Resolving the round is handled before the state checks")
+        }
+        Command::Ping { .. } => unreachable!("Pings are handled before the state checks"),
+    };
+    if let Some(suggestion) = evaluate_action(world, state, command) {
+        state.explanations.insert(suggestion.unit, suggestion);
+    }
+    set_state(state, next_state);
+    if cfg!(debug_assertions) {
+        let violations = validate(world, state);
+        debug_assert!(
+            violations.is_empty(),
+            "{:?} broke invariants: {:?}",
+            command,
+            violations
+        );
+    }
+    Ok(())
+}
+
+/// While planning, every player that is not an observer can plan moves and attacks for their
+/// units. They are only checked and recorded here, the state machine carries them out once a
+/// player resolves the round.
+fn plan_command(
+    world: &World,
+    state: &mut GameState,
+    command: &Command,
+) -> Result<(), CommandError> {
+    let player = command.player();
+    match state.players.get(player) {
+        None => return Err(CommandError::UnknownPlayer(player)),
+        Some(player) if player.is_observer() => return Err(CommandError::Observing),
+        Some(_) => {}
+    }
+    let order = match command {
+        Command::Move { player, unit, path } => {
+            let (entity, moving_unit, hexagon) =
+                find_own_unit(world, &state.persistent_ids, *player, *unit)?;
+            if entity_has_component::<Garrisoned, _>(world, &entity) {
+                return Err(CommandError::Garrisoned(*unit));
+            }
+            validate_path(world, hexagon, path)?;
+            if moving_unit.movement_cost_left(&state.rules) <= 0 {
+                return Err(CommandError::OutOfRange);
+            }
+            PlannedOrder::Move {
+                entity,
+                path: path.clone(),
+            }
+        }
+        Command::Attack {
+            player,
+            attacker,
+            defender,
+        } => {
+            let (attacker_entity, attacker_unit, _) =
+                find_own_unit(world, &state.persistent_ids, *player, *attacker)?;
+            let (defender_entity, defender_player, _, _) =
+                find_unit(world, &state.persistent_ids, *defender)?;
+            if defender_player == Some(*player) {
+                return Err(CommandError::FriendlyTarget(*defender));
+            }
+            if let Some(error) = attacker_unit.attack_error(&state.rules) {
+                return Err(error.into());
+            }
+            // The range is checked when the attack is carried out, after all units moved.
+            PlannedOrder::Attack {
+                attacker: attacker_entity,
+                target: defender_entity,
+            }
+        }
+        Command::ResolveRound { .. } => {
+            start_resolution(state);
+            return Ok(());
+        }
+        _ => return Err(CommandError::NotPlannable),
+    };
+    state.planned_orders.push(order);
+    Ok(())
+}
+
+/// Like `apply_command`, but for commands coming from local input. These are only accepted for
+/// players controlled on this machine and never from observers. During a tutorial step only the
+/// expected action is accepted.
+pub fn apply_local_command(
+    world: &World,
+    state: &mut GameState,
+    command: &Command,
+) -> Result<(), CommandError> {
+    if state.observing {
+        return Err(CommandError::Observing);
+    }
+    let local = match command {
+        Command::Ping { player, .. } => state.local_players.contains(player),
+        _ if state.state == State::Planning => state.local_players.contains(&command.player()),
+        _ => state.is_local_turn(),
+    };
+    if !local {
+        return Err(CommandError::NotYourTurn);
+    }
+    let expected = match state.tutorial.constraint() {
+        None => false,
+        Some(constraint) => {
+            if !constraint.allows(world, &state.persistent_ids, command) {
+                return Err(CommandError::TutorialBlocked);
+            }
+            constraint.is_expected(world, &state.persistent_ids, command)
+        }
+    };
+    apply_command(world, state, command)?;
+    if expected {
+        state.tutorial.accept();
+    }
+    Ok(())
+}
+
+fn find_unit<S: EntityStore>(
+    world: &S,
+    ids: &PersistentIds,
+    id: PersistentId,
+) -> Result<(Entity, Option<usize>, Unit, Hexagon), CommandError> {
+    let entity = ids.find(world, id).ok_or(CommandError::UnknownUnit(id))?;
+    let entry = world
+        .entry_ref(entity)
+        .map_err(|_| CommandError::UnknownUnit(id))?;
+    let unit = *entry
+        .get_component::<Unit>()
+        .map_err(|_| CommandError::UnknownUnit(id))?;
+    let hexagon = *entry
+        .get_component::<Hexagon>()
+        .map_err(|_| CommandError::UnknownUnit(id))?;
+    let player = entry
+        .get_component::<PlayerComponent>()
+        .ok()
+        .map(|player| player.0);
+    Ok((entity, player, unit, hexagon))
+}
+
+fn find_own_unit<S: EntityStore>(
+    world: &S,
+    ids: &PersistentIds,
+    player: usize,
+    id: PersistentId,
+) -> Result<(Entity, Unit, Hexagon), CommandError> {
+    let (entity, owner, unit, hexagon) = find_unit(world, ids, id)?;
+    if owner != Some(player) {
+        return Err(CommandError::NotOwnUnit(id));
+    }
+    Ok((entity, unit, hexagon))
+}
+
+/// A path has to start next to the unit, consist of adjacent hexagons and must not pass through
+/// other units.
+fn validate_path<S: EntityStore>(
+    world: &S,
+    start: Hexagon,
+    path: &[Hexagon],
+) -> Result<(), CommandError> {
+    if path.is_empty() {
+        return Err(CommandError::InvalidPath);
+    }
+    let mut current = start;
+    for next in path {
+        if !current.is_neighbour(next) || is_occupied_by_unit(next, world) {
+            return Err(CommandError::InvalidPath);
+        }
+        current = *next;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildings::spawn_building;
+    use crate::components::building::Building;
+    use crate::components::field::Field;
+    use crate::edges::EdgeKind;
+    use crate::game_state::force_move;
+    use crate::player::Player;
+    use crate::rules::Ruleset;
+    use crate::spawn::spawn_unit;
+    use crate::state_machine::{advance_state, SECONDS_PER_MOVEMENT};
+    use gdnative::core_types::Color;
+    use legion::IntoQuery;
+    use std::collections::vec_deque::VecDeque;
+
+    fn new_game() -> (World, GameState, PersistentId, PersistentId) {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.add_player(Player::new(
+            "Player 2".to_owned(),
+            Color::rgb(1.0, 0.0, 0.0),
+        ));
+        state.current_player = Some(0);
+        state.state = State::Waiting;
+        let first = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+            None,
+        );
+        let second = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            1,
+            Hexagon::new_axial(2, 0),
+            Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+            None,
+        );
+        let first = PersistentId::of_entity(&world, first).unwrap();
+        let second = PersistentId::of_entity(&world, second).unwrap();
+        (world, state, first, second)
+    }
+
+    #[test]
+    fn every_command_round_trips_through_json() {
+        let commands = vec![
+            Command::Move {
+                player: 0,
+                unit: PersistentId(3),
+                path: vec![Hexagon::new_axial(1, 0), Hexagon::new_axial(1, 1)],
+            },
+            Command::Attack {
+                player: 1,
+                attacker: PersistentId(2),
+                defender: PersistentId(7),
+            },
+            Command::UseAbility {
+                player: 0,
+                unit: PersistentId(3),
+                ability: 1,
+                target: Hexagon::new_axial(0, 1),
+            },
+            Command::Overwatch {
+                player: 1,
+                unit: PersistentId(2),
+            },
+            Command::AttackEdge {
+                player: 0,
+                unit: PersistentId(4),
+                from: Hexagon::new_axial(2, 0),
+                to: Hexagon::new_axial(2, 1),
+            },
+            Command::Garrison {
+                player: 1,
+                unit: PersistentId(2),
+            },
+            Command::Ungarrison {
+                player: 1,
+                unit: PersistentId(2),
+                target: Hexagon::new_axial(0, -1),
+            },
+            Command::AttackBuilding {
+                player: 0,
+                unit: PersistentId(3),
+                target: Hexagon::new_axial(1, 1),
+            },
+            Command::Rotate {
+                player: 1,
+                unit: PersistentId(2),
+                facing: Direction::SouthWest,
+            },
+            Command::Select {
+                player: 0,
+                unit: Some(PersistentId(3)),
+            },
+            Command::Select {
+                player: 0,
+                unit: None,
+            },
+            Command::ChoosePromotion {
+                player: 0,
+                unit: PersistentId(4),
+                perk: 1,
+            },
+            Command::EndTurn { player: 1 },
+            Command::ResolveRound { player: 0 },
+            Command::Ping {
+                player: 1,
+                hexagon: Hexagon::new_axial(-2, 3),
+                kind: "danger".to_owned(),
+            },
+        ];
+        for command in commands {
+            let json = command.to_json().unwrap();
+            assert_eq!(Command::from_json(&json).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn move_of_current_player_starts_moving() {
+        let (world, mut state, unit, _) = new_game();
+        let command = Command::Move {
+            player: 0,
+            unit,
+            path: vec![Hexagon::new_axial(0, 1)],
+        };
+
+        assert_eq!(apply_command(&world, &mut state, &command), Ok(()));
+        assert!(matches!(state.state, State::Moving(_)));
+        assert_eq!(state.movement.path.len(), 1);
+    }
+
+    #[test]
+    fn garrisoned_units_have_to_leave_before_moving() {
+        let (mut world, mut state, unit, _) = new_game();
+        spawn_building(&mut world, Hexagon::zero(), Building::new(10), false);
+        world.push((Field::new(Hexagon::new_axial(0, 1)),));
+        let garrison = Command::Garrison { player: 0, unit };
+        let leave = |q, r| Command::Ungarrison {
+            player: 0,
+            unit,
+            target: Hexagon::new_axial(q, r),
+        };
+
+        assert_eq!(apply_command(&world, &mut state, &garrison), Ok(()));
+        advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            apply_command(&world, &mut state, &garrison),
+            Err(CommandError::AlreadyGarrisoned(unit))
+        );
+        assert_eq!(
+            apply_command(
+                &world,
+                &mut state,
+                &Command::Move {
+                    player: 0,
+                    unit,
+                    path: vec![Hexagon::new_axial(0, 1)],
+                }
+            ),
+            Err(CommandError::Garrisoned(unit))
+        );
+        // Off the map.
+        assert_eq!(
+            apply_command(&world, &mut state, &leave(1, 0)),
+            Err(CommandError::InvalidPath)
+        );
+        assert_eq!(apply_command(&world, &mut state, &leave(0, 1)), Ok(()));
+        advance_state(&mut world, &mut state, 0.0);
+
+        let entity = state.persistent_ids.find(&world, unit).unwrap();
+        let entry = world.entry_ref(entity).unwrap();
+        assert_eq!(
+            entry.get_component::<Hexagon>().ok(),
+            Some(&Hexagon::new_axial(0, 1))
+        );
+        assert!(entry.get_component::<Garrisoned>().is_err());
+        assert_eq!(
+            apply_command(&world, &mut state, &leave(0, 0)),
+            Err(CommandError::NotGarrisoned(unit))
+        );
+    }
+
+    #[test]
+    fn move_for_non_current_player_is_rejected() {
+        let (world, mut state, _, unit) = new_game();
+        let command = Command::Move {
+            player: 1,
+            unit,
+            path: vec![Hexagon::new_axial(2, 1)],
+        };
+
+        assert_eq!(
+            apply_command(&world, &mut state, &command),
+            Err(CommandError::NotCurrentPlayer)
+        );
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn move_of_enemy_unit_is_rejected() {
+        let (world, mut state, _, unit) = new_game();
+        let command = Command::Move {
+            player: 0,
+            unit,
+            path: vec![Hexagon::new_axial(2, 1)],
+        };
+
+        assert_eq!(
+            apply_command(&world, &mut state, &command),
+            Err(CommandError::NotOwnUnit(unit))
+        );
+    }
+
+    #[test]
+    fn move_along_broken_path_is_rejected() {
+        let (world, mut state, unit, _) = new_game();
+        let command = Command::Move {
+            player: 0,
+            unit,
+            path: vec![Hexagon::new_axial(0, 2)],
+        };
+
+        assert_eq!(
+            apply_command(&world, &mut state, &command),
+            Err(CommandError::InvalidPath)
+        );
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn command_during_movement_is_rejected() {
+        let (world, mut state, unit, _) = new_game();
+        let entity = unit.find_entity(&world).unwrap();
+        force_move(&mut state, entity, VecDeque::new());
+
+        assert_eq!(
+            apply_command(&world, &mut state, &Command::EndTurn { player: 0 }),
+            Err(CommandError::NotReady)
+        );
+        assert_eq!(state.state, State::Moving(entity));
+    }
+
+    #[test]
+    fn attack_in_range_starts_attacking() {
+        let (world, mut state, attacker, defender) = new_game();
+        let command = Command::Attack {
+            player: 0,
+            attacker,
+            defender,
+        };
+
+        assert_eq!(apply_command(&world, &mut state, &command), Ok(()));
+        assert_eq!(
+            state.state,
+            State::Attacking(
+                attacker.find_entity(&world).unwrap(),
+                defender.find_entity(&world).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn attack_through_a_unit_needs_the_line_of_sight_rule_off() {
+        let (mut world, mut state, attacker, defender) = new_game();
+        spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(1, 0),
+            Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+            None,
+        );
+        let command = Command::Attack {
+            player: 0,
+            attacker,
+            defender,
+        };
+
+        assert_eq!(
+            apply_command(&world, &mut state, &command),
+            Err(CommandError::NoLineOfSight)
+        );
+        assert_eq!(state.state, State::Waiting);
+
+        state.rules.line_of_sight = false;
+        assert_eq!(apply_command(&world, &mut state, &command), Ok(()));
+    }
+
+    #[test]
+    fn attack_on_own_unit_is_rejected() {
+        let (world, mut state, unit, _) = new_game();
+        let command = Command::Attack {
+            player: 0,
+            attacker: unit,
+            defender: unit,
+        };
+
+        assert_eq!(
+            apply_command(&world, &mut state, &command),
+            Err(CommandError::FriendlyTarget(unit))
+        );
+    }
+
+    #[test]
+    fn only_demolition_units_attack_bridges_in_range() {
+        let (mut world, mut state, unit, _) = new_game();
+        let (from, to) = (Hexagon::new_axial(1, 0), Hexagon::new_axial(1, 1));
+        let command = Command::AttackEdge {
+            player: 0,
+            unit,
+            from,
+            to,
+        };
+        let entity = unit.find_entity(&world).unwrap();
+
+        assert_eq!(
+            apply_command(&world, &mut state, &command),
+            Err(CommandError::NoDemolition(unit))
+        );
+        world.entry(entity).unwrap().add_component(Demolition);
+        assert_eq!(
+            apply_command(&world, &mut state, &command),
+            Err(CommandError::NoBridge)
+        );
+        state.edges.set(from, to, Some(EdgeKind::Bridge));
+        assert_eq!(apply_command(&world, &mut state, &command), Ok(()));
+        assert_eq!(state.state, State::AttackingEdge(entity, from, to));
+
+        state.state = State::Waiting;
+        let (far_from, far_to) = (Hexagon::new_axial(4, 0), Hexagon::new_axial(5, 0));
+        state.edges.set(far_from, far_to, Some(EdgeKind::Bridge));
+        let far = Command::AttackEdge {
+            player: 0,
+            unit,
+            from: far_from,
+            to: far_to,
+        };
+        assert_eq!(
+            apply_command(&world, &mut state, &far),
+            Err(CommandError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn end_turn_starts_new_round() {
+        let (world, mut state, _, _) = new_game();
+
+        assert_eq!(
+            apply_command(&world, &mut state, &Command::EndTurn { player: 0 }),
+            Ok(())
+        );
+        assert_eq!(state.state, State::NewRound);
+    }
+
+    #[test]
+    fn only_the_active_unit_takes_orders_with_initiative() {
+        let (mut world, mut state, unit, _) = new_game();
+        let waiting = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(0, 2),
+            Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+            None,
+        );
+        let waiting = PersistentId::of_entity(&world, waiting).unwrap();
+        state.rules.initiative = true;
+        advance_state(&mut world, &mut state, 0.0);
+        assert!(matches!(state.state, State::UnitTurn(_)));
+
+        let command = |unit| Command::Move {
+            player: 0,
+            unit,
+            path: vec![Hexagon::new_axial(0, 1)],
+        };
+        assert_eq!(
+            apply_command(&world, &mut state, &command(waiting)),
+            Err(CommandError::NotActiveUnit(waiting))
+        );
+        assert_eq!(apply_command(&world, &mut state, &command(unit)), Ok(()));
+    }
+
+    /// Moves next to the enemy, attacks it and tries to move away again, resolving every
+    /// command before the next one.
+    fn move_attack_move(rules: Ruleset) -> Vec<Result<(), CommandError>> {
+        let (mut world, mut state, unit, enemy) = new_game();
+        state.rules = rules;
+        for unit in <&mut Unit>::query().iter_mut(&mut world) {
+            unit.refresh(&state.rules);
+        }
+        let script = vec![
+            Command::Move {
+                player: 0,
+                unit,
+                path: vec![Hexagon::new_axial(0, 1)],
+            },
+            Command::Attack {
+                player: 0,
+                attacker: unit,
+                defender: enemy,
+            },
+            Command::Move {
+                player: 0,
+                unit,
+                path: vec![Hexagon::new_axial(0, 0)],
+            },
+            Command::Attack {
+                player: 0,
+                attacker: unit,
+                defender: enemy,
+            },
+        ];
+        script
+            .iter()
+            .map(|command| {
+                let result = apply_command(&world, &mut state, command);
+                while !state.state.accepts_orders() {
+                    advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT);
+                }
+                result
+            })
+            .collect()
+    }
+
+    #[test]
+    fn command_points_limit_the_moves_and_attacks_of_a_round() {
+        let (mut world, mut state, unit, enemy) = new_game();
+        let reserve = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(0, -2),
+            Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+            None,
+        );
+        let reserve = PersistentId::of_entity(&world, reserve).unwrap();
+        state.rules.command_points = 2;
+        let apply = |world: &mut World, state: &mut GameState, command: Command| {
+            let result = apply_command(world, state, &command);
+            while !state.state.accepts_orders() {
+                advance_state(world, state, SECONDS_PER_MOVEMENT);
+            }
+            result
+        };
+        let reserve_move = Command::Move {
+            player: 0,
+            unit: reserve,
+            path: vec![Hexagon::new_axial(0, -1)],
+        };
+
+        let script = vec![
+            Command::Move {
+                player: 0,
+                unit,
+                path: vec![Hexagon::new_axial(0, 1)],
+            },
+            // Turning is free.
+            Command::Rotate {
+                player: 0,
+                unit: reserve,
+                facing: Direction::SouthWest,
+            },
+            Command::Attack {
+                player: 0,
+                attacker: unit,
+                defender: enemy,
+            },
+            reserve_move.clone(),
+            Command::Overwatch {
+                player: 0,
+                unit: reserve,
+            },
+        ];
+        let results: Vec<Result<(), CommandError>> = script
+            .into_iter()
+            .map(|command| apply(&mut world, &mut state, command))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(CommandError::NoCommandPointsLeft),
+                Ok(())
+            ]
+        );
+        assert_eq!(state.command_points_left(0), Some(0));
+        assert_eq!(state.command_points_left(1), Some(2));
+
+        // The points come back once the next round starts.
+        let end_turn = |player| Command::EndTurn { player };
+        assert_eq!(apply(&mut world, &mut state, end_turn(0)), Ok(()));
+        assert_eq!(state.current_player, Some(1));
+        assert_eq!(state.command_points_left(0), Some(0));
+        assert_eq!(apply(&mut world, &mut state, end_turn(1)), Ok(()));
+        assert_eq!(state.current_player, Some(0));
+        assert_eq!(state.round, 2);
+        assert_eq!(state.command_points_left(0), Some(2));
+        assert_eq!(apply(&mut world, &mut state, reserve_move), Ok(()));
+
+        // Without the rule there is no limit.
+        state.rules.command_points = 0;
+        assert_eq!(state.command_points_left(0), None);
+        assert!(state.has_command_points(0));
+    }
+
+    #[test]
+    fn only_action_points_allow_moving_after_an_attack() {
+        assert_eq!(
+            move_attack_move(Ruleset::default()),
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(CommandError::OutOfRange),
+                Err(CommandError::NoAttacksLeft)
+            ]
+        );
+        let action_points = Ruleset {
+            action_points: true,
+            action_points_per_round: 4,
+            attack_cost: 2,
+            ..Ruleset::default()
+        };
+        assert_eq!(
+            move_attack_move(action_points),
+            vec![Ok(()), Ok(()), Ok(()), Err(CommandError::NoAttacksLeft)]
+        );
+    }
+
+    #[test]
+    fn observers_cannot_issue_local_commands() {
+        let (world, mut state, first, _) = new_game();
+        state.observing = true;
+
+        let command = Command::Move {
+            player: 0,
+            unit: first,
+            path: vec![Hexagon::new_axial(1, 0)],
+        };
+        assert_eq!(
+            apply_local_command(&world, &mut state, &command),
+            Err(CommandError::Observing)
+        );
+        assert_eq!(
+            apply_local_command(&world, &mut state, &Command::EndTurn { player: 0 }),
+            Err(CommandError::Observing)
+        );
+        assert_eq!(state.state, State::Waiting);
+
+        assert_eq!(apply_command(&world, &mut state, &command), Ok(()));
+    }
+
+    #[test]
+    fn hotseat_players_are_all_local() {
+        let (world, mut state, _, _) = new_game();
+
+        assert_eq!(state.local_players, [0, 1].iter().copied().collect());
+        assert_eq!(
+            apply_local_command(&world, &mut state, &Command::EndTurn { player: 0 }),
+            Ok(())
+        );
+        state.state = State::Waiting;
+        state.current_player = Some(1);
+        assert_eq!(
+            apply_local_command(&world, &mut state, &Command::EndTurn { player: 1 }),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn local_commands_for_remote_player_are_rejected() {
+        let (world, mut state, first, second) = new_game();
+        state.local_players = [1].iter().copied().collect();
+
+        let select = Command::Select {
+            player: 0,
+            unit: Some(first),
+        };
+        assert_eq!(
+            apply_local_command(&world, &mut state, &select),
+            Err(CommandError::NotYourTurn)
+        );
+        assert_eq!(state.state, State::Waiting);
+
+        assert_eq!(apply_command(&world, &mut state, &select), Ok(()));
+        assert_eq!(
+            state.state,
+            State::Selected(first.find_entity(&world).unwrap())
+        );
+
+        let select_enemy = Command::Select {
+            player: 0,
+            unit: Some(second),
+        };
+        assert_eq!(apply_command(&world, &mut state, &select_enemy), Ok(()));
+    }
+
+    #[test]
+    fn ping_is_accepted_outside_of_the_own_turn() {
+        let (world, mut state, first, _) = new_game();
+        let entity = first.find_entity(&world).unwrap();
+        force_move(&mut state, entity, VecDeque::new());
+        let ping = Command::Ping {
+            player: 1,
+            hexagon: Hexagon::new_axial(1, 1),
+            kind: "attack".to_owned(),
+        };
+
+        assert_eq!(apply_local_command(&world, &mut state, &ping), Ok(()));
+        assert_eq!(state.pings.len(), 1);
+
+        state.local_players = [0].iter().copied().collect();
+        assert_eq!(
+            apply_local_command(&world, &mut state, &ping),
+            Err(CommandError::NotYourTurn)
+        );
+    }
+}
@@ -1,6 +1,30 @@
+pub mod abilities;
+pub mod building;
+pub mod cover;
+pub mod demolition;
+pub mod facing;
 pub mod field;
+pub mod garrison;
+pub mod ground_effect;
 pub mod hexagon;
+pub mod history;
+pub mod initiative;
+pub mod movement_type;
 pub mod node_component;
+pub mod node_creation_failed;
 pub mod node_template;
+pub mod orders;
+pub mod overwatch;
+pub mod pending_spawn;
+pub mod perks;
+pub mod persistent_id;
+pub mod pickup;
 pub mod player;
+pub mod selection_indicator;
+pub mod supply;
+pub mod terrain;
 pub mod unit;
+pub mod unit_name;
+pub mod unit_nodes;
+pub mod unit_type;
+pub mod y_sort_by_hex;
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AbilityKind {
+    /// More range for this turn.
+    Sprint,
+    /// A cloud on a neighbouring hexagon that blocks the line of sight.
+    Smoke,
+    /// More damage until the next turn, at the cost of some integrity.
+    Overdrive,
+}
+
+impl AbilityKind {
+    pub fn from_name(name: &str) -> Option<AbilityKind> {
+        match name {
+            "sprint" => Some(AbilityKind::Sprint),
+            "smoke" => Some(AbilityKind::Smoke),
+            "overdrive" => Some(AbilityKind::Overdrive),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AbilityKind::Sprint => "sprint",
+            AbilityKind::Smoke => "smoke",
+            AbilityKind::Overdrive => "overdrive",
+        }
+    }
+
+    /// Turns of the owner until the ability can be used again.
+    pub fn cooldown(&self) -> u8 {
+        match self {
+            AbilityKind::Sprint => 3,
+            AbilityKind::Smoke => 3,
+            AbilityKind::Overdrive => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbilityInstance {
+    pub kind: AbilityKind,
+    pub cooldown_remaining: u8,
+}
+
+impl AbilityInstance {
+    pub fn new(kind: AbilityKind) -> Self {
+        AbilityInstance {
+            kind,
+            cooldown_remaining: 0,
+        }
+    }
+}
+
+/// The active abilities of a unit, used by their index.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Abilities(pub Vec<AbilityInstance>);
+
+/// Damage a unit gained from Overdrive, taken back when its owner's next turn starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overdrive(pub i32);
+
+/// Blocks the line of sight through its hexagon until it expires at the start of a turn of the
+/// player that placed it. Smoke of ground effects has no player and runs out with the rounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlocksVision {
+    pub player: Option<usize>,
+    pub rounds_remaining: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for kind in [
+            AbilityKind::Sprint,
+            AbilityKind::Smoke,
+            AbilityKind::Overdrive,
+        ]
+        .iter()
+        {
+            assert_eq!(AbilityKind::from_name(kind.name()), Some(*kind));
+        }
+        assert_eq!(AbilityKind::from_name("teleport"), None);
+    }
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// A building on a hexagon. A unit that ends its move on it can garrison inside, the building
+/// then shields the unit until enough damage brings it down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Building {
+    pub integrity: i32,
+    pub max_integrity: i32,
+}
+
+impl Building {
+    pub fn new(integrity: i32) -> Self {
+        Building {
+            integrity,
+            max_integrity: integrity,
+        }
+    }
+}
@@ -0,0 +1,4 @@
+/// Marks something on a hexagon that units next to it can take cover behind, like a building.
+/// It shields them from ranged attacks coming from its side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProvidesCover;
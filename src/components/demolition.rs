@@ -0,0 +1,3 @@
+/// Marks a unit that can attack bridges. Destroyed bridges leave the river they crossed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Demolition;
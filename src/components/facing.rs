@@ -0,0 +1,11 @@
+use crate::components::hexagon::Direction;
+use serde::{Deserialize, Serialize};
+
+/// The direction a unit looks in, the direction of its last step or the one it was turned to.
+/// Attacks from its sides and its back deal more damage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Facing(pub Direction);
+
+/// Marks a unit whose "Model" node is turned to its facing. Unit types opt in with `rotatable`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rotatable;
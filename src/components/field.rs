@@ -1,10 +1,12 @@
 use crate::components::hexagon::Hexagon;
+use crate::components::terrain::Terrain;
 
 #[derive(Copy, Clone)]
 pub struct Field {
     pub location: Hexagon,
     pub moveable: bool,
     pub attackable: bool,
+    pub terrain: Terrain,
 }
 
 impl Field {
@@ -13,6 +15,7 @@ impl Field {
             location,
             moveable: false,
             attackable: false,
+            terrain: Terrain::default(),
         }
     }
 }
@@ -0,0 +1,4 @@
+/// Marks a unit inside the building on its hexagon. The unit gets extra armor and can not move
+/// until it leaves the building again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Garrisoned;
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// Something on the ground of its hexagon that lasts a few rounds, like a fire or a smoke cloud.
+/// Smoke also carries `BlocksVision` while it lasts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GroundEffect {
+    /// Burns the unit on the hexagon at the start of every round and units that move into it.
+    Fire {
+        damage_per_round: i32,
+        rounds_left: u8,
+    },
+    Smoke {
+        rounds_left: u8,
+    },
+}
+
+impl GroundEffect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroundEffect::Fire { .. } => "fire",
+            GroundEffect::Smoke { .. } => "smoke",
+        }
+    }
+
+    pub fn rounds_left(&self) -> u8 {
+        match self {
+            GroundEffect::Fire { rounds_left, .. } | GroundEffect::Smoke { rounds_left } => {
+                *rounds_left
+            }
+        }
+    }
+
+    /// The damage the effect deals to a unit on its hexagon, 0 for smoke.
+    pub fn damage(&self) -> i32 {
+        match self {
+            GroundEffect::Fire {
+                damage_per_round, ..
+            } => *damage_per_round,
+            GroundEffect::Smoke { .. } => 0,
+        }
+    }
+
+    /// Counts down a round. Returns whether the effect ran out.
+    pub fn tick(&mut self) -> bool {
+        match self {
+            GroundEffect::Fire { rounds_left, .. } | GroundEffect::Smoke { rounds_left } => {
+                *rounds_left = rounds_left.saturating_sub(1);
+                *rounds_left == 0
+            }
+        }
+    }
+}
@@ -1,14 +1,79 @@
 use gdnative::core_types::Vector2;
-use std::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
 /// Hexagonal map cube position as describe here: https://www.redblobgames.com/grids/hexagons/#coordinates-cube
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+///
+/// Hexagons are compared, hashed and ordered by their axial coordinates `q` and `r` only, `s`
+/// follows from them. They are written as the compact `[q, r]`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(from = "SerializedHexagon", into = "(i32, i32)")]
 pub struct Hexagon {
     q: i32,
     r: i32,
     s: i32,
 }
 
+/// The forms a hexagon is read from: the compact `[q, r]` and the `{q, r, s}` of older saves and
+/// scenarios.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedHexagon {
+    Axial(i32, i32),
+    Cube { q: i32, r: i32 },
+}
+
+impl From<SerializedHexagon> for Hexagon {
+    fn from(hexagon: SerializedHexagon) -> Self {
+        match hexagon {
+            SerializedHexagon::Axial(q, r) | SerializedHexagon::Cube { q, r } => {
+                Hexagon::new_axial(q, r)
+            }
+        }
+    }
+}
+
+impl From<Hexagon> for (i32, i32) {
+    fn from(hexagon: Hexagon) -> Self {
+        (hexagon.q, hexagon.r)
+    }
+}
+
+impl PartialEq for Hexagon {
+    fn eq(&self, other: &Self) -> bool {
+        self.q == other.q && self.r == other.r
+    }
+}
+
+impl Eq for Hexagon {}
+
+impl Hash for Hexagon {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.q.hash(state);
+        self.r.hash(state);
+    }
+}
+
+impl Ord for Hexagon {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.q, self.r).cmp(&(other.q, other.r))
+    }
+}
+
+impl PartialOrd for Hexagon {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Hexagon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {})", self.q, self.r)
+    }
+}
+
 impl Hexagon {
     pub fn zero() -> Self {
         Hexagon { q: 0, r: 0, s: 0 }
@@ -86,6 +151,44 @@ impl Hexagon {
             self.s + direction.s,
         )
     }
+
+    /// The direction of the neighbour that lies closest to the line towards `other`, `None` for
+    /// the hexagon itself. Ties go to the direction that comes first counterclockwise from east.
+    pub fn direction_to(&self, other: &Hexagon) -> Option<Direction> {
+        if self == other {
+            return None;
+        }
+        let (q, r, s) = (other.q - self.q, other.r - self.r, other.s - self.s);
+        Direction::ALL
+            .iter()
+            .rev()
+            .copied()
+            .max_by_key(|direction| {
+                let step = Hexagon::zero().get_neighbour(*direction);
+                step.q * q + step.r * r + step.s * s
+            })
+    }
+
+    /// Returns the hexagons on the straight line to `other`, including both ends.
+    pub fn line_to(&self, other: &Hexagon) -> Vec<Hexagon> {
+        // https://www.redblobgames.com/grids/hexagons/#line-drawing
+        let distance = self.distance_to(other);
+        if distance == 0 {
+            return vec![*self];
+        }
+        // The nudge keeps points that lie exactly on an edge from rounding differently.
+        let lerp = |a: i32, b: i32, nudge: f32, t: f32| a as f32 + nudge + (b - a) as f32 * t;
+        (0..=distance)
+            .map(|step| {
+                let t = step as f32 / distance as f32;
+                cube_round(
+                    lerp(self.q, other.q, 1e-6, t),
+                    lerp(self.r, other.r, 1e-6, t),
+                    lerp(self.s, other.s, -2e-6, t),
+                )
+            })
+            .collect()
+    }
 }
 
 fn calculate_axis(axis_1: i32, axis_2: i32) -> i32 {
@@ -111,6 +214,7 @@ fn cube_round(x: f32, y: f32, z: f32) -> Hexagon {
     Hexagon::new_cube(rx as i32, ry as i32, rz as i32)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Direction {
     East = 0,
     NorthEast = 1,
@@ -120,6 +224,28 @@ pub enum Direction {
     SouthEast = 5,
 }
 
+impl Direction {
+    /// All directions, counterclockwise from east.
+    pub const ALL: [Direction; 6] = [
+        Direction::East,
+        Direction::NorthEast,
+        Direction::NorthWest,
+        Direction::West,
+        Direction::SouthWest,
+        Direction::SouthEast,
+    ];
+
+    /// The direction with the given index in `ALL`, counted around the circle.
+    pub fn from_index(index: i64) -> Direction {
+        Direction::ALL[index.rem_euclid(6) as usize]
+    }
+
+    /// Number of sixth turns counterclockwise from `self` to `other`, from 0 to 5.
+    pub fn turns_to(&self, other: Direction) -> usize {
+        (other as usize + 6 - *self as usize) % 6
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -328,4 +454,104 @@ mod tests {
         neighbour_sw_2: (Hexagon::new_axial(-20, 13), SouthWest, Hexagon::new_cube(-21, 14, 7)),
         neighbour_se_2: (Hexagon::new_axial(-3, -8), SouthEast, Hexagon::new_cube(-3, -7, 10)),
     }
+
+    #[test]
+    fn line_to_contains_both_ends_and_adjacent_steps() {
+        let start = Hexagon::new_axial(-1, 2);
+        let end = Hexagon::new_axial(3, -1);
+
+        let line = start.line_to(&end);
+
+        assert_eq!(line.len(), 5);
+        assert_eq!(line.first(), Some(&start));
+        assert_eq!(line.last(), Some(&end));
+        assert!(line.windows(2).all(|pair| pair[0].is_neighbour(&pair[1])));
+        assert_eq!(start.line_to(&start), vec![start]);
+    }
+
+    #[test]
+    fn hexagons_are_ordered_by_q_then_r() {
+        let mut hexagons = vec![
+            Hexagon::new_axial(1, -1),
+            Hexagon::new_axial(-2, 5),
+            Hexagon::new_axial(0, 0),
+            Hexagon::new_axial(1, -3),
+            Hexagon::new_axial(-2, 4),
+        ];
+        hexagons.sort();
+
+        assert_eq!(
+            hexagons,
+            vec![
+                Hexagon::new_axial(-2, 4),
+                Hexagon::new_axial(-2, 5),
+                Hexagon::new_axial(0, 0),
+                Hexagon::new_axial(1, -3),
+                Hexagon::new_axial(1, -1),
+            ]
+        );
+        // The distance to the origin plays no part.
+        assert!(Hexagon::new_axial(-5, 0) < Hexagon::new_axial(0, 1));
+        assert_eq!(
+            Hexagon::new_axial(2, 3).cmp(&Hexagon::new_axial(2, 3)),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn equal_hexagons_hash_equally() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |hexagon: &Hexagon| {
+            let mut hasher = DefaultHasher::new();
+            hexagon.hash(&mut hasher);
+            hasher.finish()
+        };
+        let axial = Hexagon::new_axial(3, -7);
+        let cube = Hexagon::new_cube(3, -7, 0);
+
+        assert_eq!(axial, cube);
+        assert_eq!(hash(&axial), hash(&cube));
+        assert_ne!(axial, Hexagon::new_axial(-7, 3));
+    }
+
+    #[test]
+    fn hexagons_are_written_compact_and_read_in_both_forms() {
+        let hexagon = Hexagon::new_axial(-2, 5);
+
+        let json = serde_json::to_string(&hexagon).unwrap();
+        assert_eq!(json, "[-2,5]");
+        let read: Hexagon = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, hexagon);
+        assert_eq!(read.get_s(), -3);
+
+        let old: Hexagon = serde_json::from_str(r#"{"q": -2, "r": 5, "s": -3}"#).unwrap();
+        assert_eq!(old, hexagon);
+        assert!(serde_json::from_str::<Hexagon>("[1]").is_err());
+        assert_eq!(hexagon.to_string(), "(-2, 5)");
+    }
+
+    #[test]
+    fn direction_to_points_along_the_closest_neighbour() {
+        let centre = Hexagon::new_axial(2, -1);
+
+        for direction in Direction::ALL.iter() {
+            let neighbour = centre.get_neighbour(*direction);
+            assert_eq!(centre.direction_to(&neighbour), Some(*direction));
+            assert_eq!(
+                centre.direction_to(&neighbour.get_neighbour(*direction)),
+                Some(*direction)
+            );
+        }
+        assert_eq!(centre.direction_to(&centre), None);
+        // Exactly between east and north east.
+        assert_eq!(
+            Hexagon::zero().direction_to(&Hexagon::new_axial(2, -1)),
+            Some(East)
+        );
+        assert_eq!(East.turns_to(SouthEast), 5);
+        assert_eq!(West.turns_to(NorthEast), 4);
+        assert_eq!(Direction::from_index(-1), SouthEast);
+        assert_eq!(Direction::from_index(8), NorthWest);
+    }
 }
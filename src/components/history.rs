@@ -0,0 +1,249 @@
+use crate::components::hexagon::Hexagon;
+use crate::components::unit::AttackResult;
+use crate::messages::{self, Message};
+use gdnative::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::vec_deque::VecDeque;
+
+pub const HISTORY_CAPACITY: usize = 10;
+
+/// Something notable that happened to a unit. Other units are referenced by position and player,
+/// so entries stay valid after they are destroyed and can be saved.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HistoryEvent {
+    DamageTaken {
+        amount: i32,
+        source: Hexagon,
+        source_player: Option<usize>,
+    },
+    Kill {
+        position: Hexagon,
+        player: Option<usize>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub round: u32,
+    pub event: HistoryEvent,
+}
+
+impl HistoryEntry {
+    pub fn message(&self) -> Message {
+        match self.event {
+            HistoryEvent::DamageTaken { amount, source, .. } => {
+                messages::history_damage_taken(amount, &source, self.round)
+            }
+            HistoryEvent::Kill { position, .. } => messages::history_kill(&position, self.round),
+        }
+    }
+
+    pub fn to_dictionary(&self, owner: &Object) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("round", self.round);
+        let (kind, position, player, amount) = match self.event {
+            HistoryEvent::DamageTaken {
+                amount,
+                source,
+                source_player,
+            } => ("damage_taken", source, source_player, amount),
+            HistoryEvent::Kill { position, player } => ("kill", position, player, 0),
+        };
+        dict.insert("kind", kind);
+        dict.insert("q", position.get_q());
+        dict.insert("r", position.get_r());
+        dict.insert("player", player.map_or(-1, |player| player as i64));
+        dict.insert("amount", amount);
+        dict.insert("text", self.message().translate(owner));
+        dict
+    }
+}
+
+/// The last `HISTORY_CAPACITY` notable events of a unit, oldest first.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    pub rounds_survived: u32,
+}
+
+impl History {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.entries.len() >= HISTORY_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+/// A unit taking part in an attack, as it was before the attack was resolved.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Combatant {
+    pub position: Hexagon,
+    pub player: Option<usize>,
+    pub integrity: i32,
+}
+
+/// Returns the history entries of the attacker and of the defender for an attack result.
+pub fn attack_entries(
+    round: u32,
+    attacker: &Combatant,
+    defender: &Combatant,
+    result: &AttackResult,
+) -> (Vec<HistoryEntry>, Vec<HistoryEntry>) {
+    let mut attacker_entries = Vec::new();
+    let mut defender_entries = Vec::new();
+    record_hit(
+        round,
+        attacker,
+        defender,
+        result.defender.integrity,
+        &mut attacker_entries,
+        &mut defender_entries,
+    );
+    // The attacker only loses integrity to a counterattack.
+    record_hit(
+        round,
+        defender,
+        attacker,
+        result.attacker.integrity,
+        &mut defender_entries,
+        &mut attacker_entries,
+    );
+    (attacker_entries, defender_entries)
+}
+
+fn record_hit(
+    round: u32,
+    source: &Combatant,
+    target: &Combatant,
+    remaining_integrity: i32,
+    source_entries: &mut Vec<HistoryEntry>,
+    target_entries: &mut Vec<HistoryEntry>,
+) {
+    let amount = target.integrity - remaining_integrity;
+    if amount <= 0 {
+        return;
+    }
+    target_entries.push(HistoryEntry {
+        round,
+        event: HistoryEvent::DamageTaken {
+            amount,
+            source: source.position,
+            source_player: source.player,
+        },
+    });
+    if remaining_integrity <= 0 {
+        source_entries.push(HistoryEntry {
+            round,
+            event: HistoryEvent::Kill {
+                position: target.position,
+                player: target.player,
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::unit::Unit;
+
+    fn combatant(q: i32, player: usize, integrity: i32) -> Combatant {
+        Combatant {
+            position: Hexagon::new_axial(q, 0),
+            player: Some(player),
+            integrity,
+        }
+    }
+
+    fn damage_taken(round: u32, amount: i32) -> HistoryEntry {
+        HistoryEntry {
+            round,
+            event: HistoryEvent::DamageTaken {
+                amount,
+                source: Hexagon::new_axial(0, 0),
+                source_player: Some(0),
+            },
+        }
+    }
+
+    #[test]
+    fn oldest_entries_are_evicted() {
+        let mut history = History::default();
+        for round in 0..HISTORY_CAPACITY as u32 + 3 {
+            history.push(damage_taken(round, 1));
+        }
+
+        assert_eq!(history.len(), HISTORY_CAPACITY);
+        assert_eq!(history.iter().next().unwrap().round, 3);
+        assert_eq!(
+            history.iter().last().unwrap().round,
+            HISTORY_CAPACITY as u32 + 2
+        );
+    }
+
+    #[test]
+    fn attack_creates_damage_entry_for_defender() {
+        let result = AttackResult {
+            actual_damage: 7,
+            attacker: Unit::new(5, 7, 1, 1, 0, 3, 0, 0),
+            defender: Unit::new(3, 2, 1, 1, 0, 3, 3, 1),
+        };
+
+        let (attacker_entries, defender_entries) =
+            attack_entries(3, &combatant(2, 0, 5), &combatant(3, 1, 10), &result);
+
+        assert!(attacker_entries.is_empty());
+        assert_eq!(
+            defender_entries,
+            vec![HistoryEntry {
+                round: 3,
+                event: HistoryEvent::DamageTaken {
+                    amount: 7,
+                    source: Hexagon::new_axial(2, 0),
+                    source_player: Some(0),
+                },
+            }]
+        );
+        assert_eq!(
+            defender_entries[0].message().to_fallback_string(),
+            "Took 7 damage from (2, 0) on round 3"
+        );
+    }
+
+    #[test]
+    fn destroying_a_unit_is_recorded_as_kill() {
+        let result = AttackResult {
+            actual_damage: 4,
+            attacker: Unit::new(5, 4, 1, 1, 0, 3, 0, 0),
+            defender: Unit::new(-1, 2, 1, 1, 0, 3, 3, 1),
+        };
+
+        let (attacker_entries, defender_entries) =
+            attack_entries(1, &combatant(0, 0, 5), &combatant(1, 1, 3), &result);
+
+        assert_eq!(
+            attacker_entries,
+            vec![HistoryEntry {
+                round: 1,
+                event: HistoryEvent::Kill {
+                    position: Hexagon::new_axial(1, 0),
+                    player: Some(1),
+                },
+            }]
+        );
+        assert_eq!(defender_entries.len(), 1);
+    }
+}
@@ -0,0 +1,3 @@
+/// How early a unit acts in a round with the initiative rules, higher values act first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Initiative(pub i32);
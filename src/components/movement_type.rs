@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+/// How a unit moves, which decides what terrain costs it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MovementType {
+    Ground,
+    Tracked,
+    Wheeled,
+}
+
+impl MovementType {
+    /// The movement type of a catalog entry, units without one move on the ground.
+    pub fn from_name(name: &str) -> Option<MovementType> {
+        match name {
+            "" | "ground" => Some(MovementType::Ground),
+            "tracked" => Some(MovementType::Tracked),
+            "wheeled" => Some(MovementType::Wheeled),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MovementType::Ground => "ground",
+            MovementType::Tracked => "tracked",
+            MovementType::Wheeled => "wheeled",
+        }
+    }
+}
+
+impl Default for MovementType {
+    fn default() -> Self {
+        MovementType::Ground
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for movement in [
+            MovementType::Ground,
+            MovementType::Tracked,
+            MovementType::Wheeled,
+        ]
+        .iter()
+        {
+            assert_eq!(MovementType::from_name(movement.name()), Some(*movement));
+        }
+        assert_eq!(MovementType::from_name(""), Some(MovementType::Ground));
+        assert_eq!(MovementType::from_name("hover"), None);
+    }
+}
@@ -0,0 +1,7 @@
+/// Marks an entity whose scene could not be instanced, so `create_node` stops retrying it.
+/// `placeholder` is set if the entity shows a placeholder node instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeCreationFailed {
+    pub scene_file: String,
+    pub placeholder: bool,
+}
@@ -1,7 +1,93 @@
+use gdnative::core_types::Color;
+
+/// The group of every unit node.
+pub const UNIT_GROUP: &str = "units";
+/// The container every unit node is a child of.
+pub const UNIT_CONTAINER: &str = "Units";
+
+/// The scene of an entity and how `create_node` sets up its node.
 #[derive(Clone, Debug, PartialEq)]
 pub struct NodeTemplate {
     pub scene_file: String,
     pub scale_x: f32,
     pub scale_y: f32,
     pub z_index: i64,
+    pub rotation_degrees: f32,
+    pub modulate: Color,
+    pub visible: bool,
+    /// Groups the node is added to, so scripts can find it with `get_nodes_in_group`.
+    pub groups: Vec<String>,
+    /// Path of the container below the world node that becomes the parent of the node, like
+    /// "Units". Missing containers are created, without a path the world node is the parent.
+    pub parent_path: Option<String>,
+    /// Whether the z index follows the row of the hexagon, with `z_index` ordering the nodes
+    /// within a row.
+    pub y_sort: bool,
+}
+
+impl Default for NodeTemplate {
+    fn default() -> Self {
+        NodeTemplate {
+            scene_file: String::new(),
+            scale_x: 1.0,
+            scale_y: 1.0,
+            z_index: 0,
+            rotation_degrees: 0.0,
+            modulate: Color::rgb(1.0, 1.0, 1.0),
+            visible: true,
+            groups: Vec::new(),
+            parent_path: None,
+            y_sort: false,
+        }
+    }
+}
+
+impl NodeTemplate {
+    pub fn new(scene_file: &str) -> Self {
+        NodeTemplate {
+            scene_file: scene_file.to_owned(),
+            ..NodeTemplate::default()
+        }
+    }
+
+    pub fn scale(mut self, x: f32, y: f32) -> Self {
+        self.scale_x = x;
+        self.scale_y = y;
+        self
+    }
+
+    pub fn z_index(mut self, z_index: i64) -> Self {
+        self.z_index = z_index;
+        self
+    }
+
+    pub fn rotation_degrees(mut self, degrees: f32) -> Self {
+        self.rotation_degrees = degrees;
+        self
+    }
+
+    pub fn modulate(mut self, colour: Color) -> Self {
+        self.modulate = colour;
+        self
+    }
+
+    pub fn visible(mut self, visible: bool) -> Self {
+        self.visible = visible;
+        self
+    }
+
+    pub fn group(mut self, group: &str) -> Self {
+        self.groups.push(group.to_owned());
+        self
+    }
+
+    pub fn parent(mut self, path: &str) -> Self {
+        self.parent_path = Some(path.to_owned());
+        self
+    }
+
+    pub fn y_sort(mut self) -> Self {
+        self.y_sort = true;
+        self
+    }
 }
@@ -0,0 +1,14 @@
+use crate::components::hexagon::Hexagon;
+use legion::Entity;
+
+/// A move planned during the planning phase of the simultaneous rules.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MoveOrder {
+    pub path: Vec<Hexagon>,
+}
+
+/// An attack planned during the planning phase, carried out after all moves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttackOrder {
+    pub target: Entity,
+}
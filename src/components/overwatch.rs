@@ -0,0 +1,4 @@
+/// Marks a unit that keeps its attack to fire at the first enemy that moves into its range. The
+/// marker is used up by the shot or dropped when the owner's next turn starts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overwatching;
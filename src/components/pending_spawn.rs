@@ -0,0 +1,3 @@
+/// Marks a unit fresh from the catalog whose stats the spawn modifiers have not seen yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PendingSpawn;
@@ -0,0 +1,74 @@
+use crate::components::unit::Unit;
+use crate::rules::Ruleset;
+use serde::{Deserialize, Serialize};
+
+/// Damage a `Damage` perk adds to the attacks of a unit.
+pub const DAMAGE_BONUS: i32 = 1;
+/// Integrity a `Integrity` perk adds to the unit and to what it can be repaired up to.
+pub const INTEGRITY_BONUS: i32 = 2;
+/// Range, or action points with the action point rule, a `Mobility` perk adds to every refresh.
+pub const MOBILITY_BONUS: i32 = 1;
+
+/// What a unit can pick when it is promoted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PerkId {
+    Damage,
+    Integrity,
+    Mobility,
+}
+
+impl PerkId {
+    pub const ALL: [PerkId; 3] = [PerkId::Damage, PerkId::Integrity, PerkId::Mobility];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PerkId::Damage => "damage",
+            PerkId::Integrity => "integrity",
+            PerkId::Mobility => "mobility",
+        }
+    }
+}
+
+/// The perks a unit picked on its promotions, in the order they were picked. A perk can be picked
+/// more than once, its bonus adds up.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Perks(pub Vec<PerkId>);
+
+impl Perks {
+    pub fn count(&self, perk: PerkId) -> i32 {
+        self.0.iter().filter(|picked| **picked == perk).count() as i32
+    }
+
+    pub fn damage_bonus(&self) -> i32 {
+        self.count(PerkId::Damage) * DAMAGE_BONUS
+    }
+
+    pub fn integrity_bonus(&self) -> i32 {
+        self.count(PerkId::Integrity) * INTEGRITY_BONUS
+    }
+
+    /// The unit as it fights, with the bonus damage of the perks.
+    pub fn strengthen(&self, unit: Unit) -> Unit {
+        Unit {
+            damage: unit.damage + self.damage_bonus(),
+            ..unit
+        }
+    }
+
+    /// Adds the bonus movement of the perks after `Unit::refresh`.
+    pub fn refresh(&self, unit: &mut Unit, rules: &Ruleset) {
+        let bonus = self.count(PerkId::Mobility) * MOBILITY_BONUS;
+        if rules.action_points {
+            unit.action_points += bonus;
+        } else {
+            unit.remaining_range += bonus;
+        }
+    }
+}
+
+/// The enemy units a unit destroyed, they earn it its promotions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Experience {
+    pub kills: u32,
+}
@@ -0,0 +1,187 @@
+use legion::{Entity, EntityStore, IntoQuery};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Id of an entity that is the same for every simulation of a game, unlike legion's `Entity`
+/// which depends on the order entities were allocated in.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PersistentId(pub u64);
+
+impl PersistentId {
+    pub fn of_entity<S: EntityStore>(world: &S, entity: Entity) -> Option<Self> {
+        match world.entry_ref(entity) {
+            Err(_) => None,
+            Ok(entry) => entry.get_component::<PersistentId>().ok().copied(),
+        }
+    }
+
+    pub fn find_entity<S: EntityStore>(&self, world: &S) -> Option<Entity> {
+        <(Entity, &PersistentId)>::query()
+            .iter(world)
+            .find(|(_, id)| *id == self)
+            .map(|(entity, _)| *entity)
+    }
+}
+
+/// Hands out persistent ids and maps them to entities in both directions. Spawning registers the
+/// new entity right away, removed entities are dropped from the map by `sync_entity` once their
+/// world events arrive.
+#[derive(Clone, Debug, Default)]
+pub struct PersistentIds {
+    next: u64,
+    entities: HashMap<PersistentId, Entity>,
+    ids: HashMap<Entity, PersistentId>,
+}
+
+impl PersistentIds {
+    /// Returns a new id. Ids are never handed out twice, even after their entity was removed.
+    pub fn allocate(&mut self) -> PersistentId {
+        let id = PersistentId(self.next);
+        self.next += 1;
+        id
+    }
+
+    /// The id the next call to `allocate` returns.
+    pub fn next_id(&self) -> u64 {
+        self.next
+    }
+
+    /// Makes sure ids up to `id` are not allocated again, for ids restored from a save.
+    pub fn reserve(&mut self, id: PersistentId) {
+        self.next = self.next.max(id.0 + 1);
+    }
+
+    pub fn register(&mut self, id: PersistentId, entity: Entity) {
+        if let Some(old_id) = self.ids.insert(entity, id) {
+            self.entities.remove(&old_id);
+        }
+        if let Some(old_entity) = self.entities.insert(id, entity) {
+            if old_entity != entity {
+                self.ids.remove(&old_entity);
+            }
+        }
+        self.reserve(id);
+    }
+
+    fn unregister(&mut self, entity: Entity) {
+        if let Some(id) = self.ids.remove(&entity) {
+            self.entities.remove(&id);
+        }
+    }
+
+    /// Updates the map for an entity that was inserted, changed or removed.
+    pub fn sync_entity<S: EntityStore>(&mut self, world: &S, entity: Entity) {
+        match PersistentId::of_entity(world, entity) {
+            None => self.unregister(entity),
+            Some(id) => self.register(id, entity),
+        }
+    }
+
+    /// Rebuilds the map from the world, for worlds that changed without their events being seen.
+    pub fn rebuild<S: EntityStore>(&mut self, world: &S) {
+        self.entities.clear();
+        self.ids.clear();
+        for (entity, id) in <(Entity, &PersistentId)>::query().iter(world) {
+            self.register(*id, *entity);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn id(&self, entity: Entity) -> Option<PersistentId> {
+        self.ids.get(&entity).copied()
+    }
+
+    /// Looks up the entity of `id`. Falls back to searching the world if the map is out of date,
+    /// because events of a removal were not handled yet.
+    pub fn find<S: EntityStore>(&self, world: &S, id: PersistentId) -> Option<Entity> {
+        match self.entities.get(&id) {
+            Some(entity) if PersistentId::of_entity(world, *entity) == Some(id) => Some(*entity),
+            _ => id.find_entity(world),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion::World;
+
+    #[test]
+    fn find_entity_returns_entity_with_id() {
+        let mut world = World::default();
+        world.push((PersistentId(0),));
+        let entity = world.push((PersistentId(1),));
+
+        assert_eq!(PersistentId(1).find_entity(&world), Some(entity));
+        assert_eq!(PersistentId(2).find_entity(&world), None);
+        assert_eq!(
+            PersistentId::of_entity(&world, entity),
+            Some(PersistentId(1))
+        );
+    }
+
+    #[test]
+    fn allocated_ids_are_not_reused() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let first = ids.allocate();
+        let entity = world.push((first,));
+        ids.register(first, entity);
+
+        world.remove(entity);
+        ids.sync_entity(&world, entity);
+
+        assert_ne!(ids.allocate(), first);
+        assert!(ids.is_empty());
+        assert_eq!(ids.find(&world, first), None);
+    }
+
+    #[test]
+    fn map_stays_consistent_after_deletes_and_respawns() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        fn spawn(world: &mut World, ids: &mut PersistentIds) -> (PersistentId, Entity) {
+            let id = ids.allocate();
+            let entity = world.push((id,));
+            ids.register(id, entity);
+            (id, entity)
+        }
+        let (first_id, first) = spawn(&mut world, &mut ids);
+        let (second_id, second) = spawn(&mut world, &mut ids);
+
+        world.remove(first);
+        ids.sync_entity(&world, first);
+        let (third_id, third) = spawn(&mut world, &mut ids);
+
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids.find(&world, first_id), None);
+        assert_eq!(ids.find(&world, second_id), Some(second));
+        assert_eq!(ids.find(&world, third_id), Some(third));
+        assert_eq!(ids.id(third), Some(third_id));
+        assert_eq!(ids.id(first), None);
+        assert_eq!(third_id, PersistentId(2));
+    }
+
+    #[test]
+    fn find_falls_back_to_the_world_for_stale_entries() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let entity = world.push((PersistentId(7),));
+        ids.register(PersistentId(7), entity);
+        world.remove(entity);
+        let respawned = world.push((PersistentId(7),));
+
+        assert_eq!(ids.find(&world, PersistentId(7)), Some(respawned));
+
+        ids.rebuild(&world);
+        assert_eq!(ids.id(respawned), Some(PersistentId(7)));
+        assert_eq!(ids.next_id(), 8);
+    }
+}
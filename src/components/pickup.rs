@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+/// An item lying on a hexagon, collected by the first unit that steps onto it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pickup {
+    /// Resources for the owner of the unit.
+    Resources(i32),
+    /// Integrity the unit gets back, up to the integrity of a new unit of its type.
+    Repair(i32),
+    /// Ends the cooldowns of all abilities of the unit.
+    AbilityCharge,
+}
+
+impl Pickup {
+    /// The pickup with the given name. `amount` is ignored by pickups without one.
+    pub fn from_name(name: &str, amount: i32) -> Option<Pickup> {
+        match name {
+            "resources" => Some(Pickup::Resources(amount)),
+            "repair" => Some(Pickup::Repair(amount)),
+            "ability_charge" => Some(Pickup::AbilityCharge),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pickup::Resources(_) => "resources",
+            Pickup::Repair(_) => "repair",
+            Pickup::AbilityCharge => "ability_charge",
+        }
+    }
+
+    pub fn amount(&self) -> i32 {
+        match *self {
+            Pickup::Resources(amount) | Pickup::Repair(amount) => amount,
+            Pickup::AbilityCharge => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for pickup in [
+            Pickup::Resources(10),
+            Pickup::Repair(5),
+            Pickup::AbilityCharge,
+        ]
+        .iter()
+        {
+            assert_eq!(
+                Pickup::from_name(pickup.name(), pickup.amount()),
+                Some(*pickup)
+            );
+        }
+        assert_eq!(Pickup::from_name("treasure", 1), None);
+    }
+}
@@ -0,0 +1,59 @@
+/// Tracks which selection the outline of a unit node currently shows, so the outline animation
+/// is only started or stopped when the selection changes instead of every frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SelectionIndicator {
+    /// Selection generation the outline was last shown for, `None` while it is hidden.
+    shown_for: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndicatorChange {
+    Show,
+    Hide,
+}
+
+impl SelectionIndicator {
+    pub fn is_shown(&self) -> bool {
+        self.shown_for.is_some()
+    }
+
+    /// Takes the generation of the selection that includes this unit, or `None` if it is not
+    /// selected, and returns what has to change on the node.
+    pub fn update(&mut self, selection: Option<u64>) -> Option<IndicatorChange> {
+        if self.shown_for == selection {
+            return None;
+        }
+        self.shown_for = selection;
+        match selection {
+            None => Some(IndicatorChange::Hide),
+            Some(_) => Some(IndicatorChange::Show),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_is_reported_once_per_selection() {
+        let mut indicator = SelectionIndicator::default();
+
+        assert_eq!(indicator.update(None), None);
+        assert_eq!(indicator.update(Some(1)), Some(IndicatorChange::Show));
+        assert_eq!(indicator.update(Some(1)), None);
+        assert_eq!(indicator.update(Some(1)), None);
+        assert!(indicator.is_shown());
+        assert_eq!(indicator.update(None), Some(IndicatorChange::Hide));
+        assert_eq!(indicator.update(None), None);
+    }
+
+    #[test]
+    fn selecting_the_same_unit_again_restarts_the_indicator() {
+        let mut indicator = SelectionIndicator::default();
+
+        assert_eq!(indicator.update(Some(1)), Some(IndicatorChange::Show));
+        assert_eq!(indicator.update(Some(2)), Some(IndicatorChange::Show));
+        assert_eq!(indicator.update(Some(2)), None);
+    }
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the units of `player` draw their supply from, like a factory or a headquarters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SupplySource {
+    pub player: usize,
+}
+
+/// Whether a unit was in supply at the start of the round, with the supply rule. Units without
+/// it count as supplied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Supply {
+    pub in_supply: bool,
+    /// Rounds in a row the unit started out of supply.
+    pub unsupplied_rounds: u32,
+}
+
+impl Default for Supply {
+    fn default() -> Self {
+        Supply {
+            in_supply: true,
+            unsupplied_rounds: 0,
+        }
+    }
+}
+
+impl Supply {
+    /// Takes the supply of a new round.
+    pub fn record(&mut self, in_supply: bool) {
+        self.in_supply = in_supply;
+        if in_supply {
+            self.unsupplied_rounds = 0;
+        } else {
+            self.unsupplied_rounds += 1;
+        }
+    }
+}
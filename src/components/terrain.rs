@@ -0,0 +1,73 @@
+use gdnative::core_types::Color;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Terrain {
+    Plains,
+    Forest,
+    Hills,
+    Water,
+    /// Halves the movement cost for units on the ground or on wheels.
+    Road,
+}
+
+impl Terrain {
+    pub fn from_name(name: &str) -> Option<Terrain> {
+        match name {
+            "plains" => Some(Terrain::Plains),
+            "forest" => Some(Terrain::Forest),
+            "hills" => Some(Terrain::Hills),
+            "water" => Some(Terrain::Water),
+            "road" => Some(Terrain::Road),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Terrain::Plains => "plains",
+            Terrain::Forest => "forest",
+            Terrain::Hills => "hills",
+            Terrain::Water => "water",
+            Terrain::Road => "road",
+        }
+    }
+
+    pub fn colour(&self) -> Color {
+        match self {
+            Terrain::Plains => Color::rgba(0.5, 0.5, 0.5, 1.0),
+            Terrain::Forest => Color::rgba(0.2, 0.45, 0.2, 1.0),
+            Terrain::Hills => Color::rgba(0.55, 0.45, 0.3, 1.0),
+            Terrain::Water => Color::rgba(0.2, 0.3, 0.6, 1.0),
+            Terrain::Road => Color::rgba(0.65, 0.6, 0.5, 1.0),
+        }
+    }
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Terrain::Plains
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for terrain in [
+            Terrain::Plains,
+            Terrain::Forest,
+            Terrain::Hills,
+            Terrain::Water,
+            Terrain::Road,
+        ]
+        .iter()
+        {
+            assert_eq!(Terrain::from_name(terrain.name()), Some(*terrain));
+        }
+        assert_eq!(Terrain::from_name("lava"), None);
+    }
+}
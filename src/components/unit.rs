@@ -1,4 +1,9 @@
-#[derive(Copy, Clone)]
+use crate::combat_context::CombatContext;
+use crate::movement::HEXAGON_COST;
+use crate::rules::Ruleset;
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Unit {
     pub integrity: i32,
     pub damage: i32,
@@ -8,6 +13,26 @@ pub struct Unit {
     pub mobility: i32,
     pub remaining_range: i32,
     pub remaining_attacks: i32,
+    /// The single budget for moves and attacks when the rules use action points.
+    #[serde(default)]
+    pub action_points: i32,
+    /// Movement left below one hexagon, in movement cost. Roads cost half a hexagon, so a unit
+    /// can end its move with half a hexagon to spare.
+    #[serde(default)]
+    pub partial_movement: i32,
+    /// Whether the unit may attack in a round it moved in. Artillery that has to set up before
+    /// it fires may not.
+    #[serde(default = "fires_after_moving")]
+    pub fire_after_move: bool,
+    /// Whether the unit moved this round, across every step of a move that takes several turns.
+    /// Turning in place or entering overwatch does not count.
+    #[serde(default)]
+    pub moved: bool,
+}
+
+/// Units fire after moving unless their data says otherwise.
+pub fn fires_after_moving() -> bool {
+    true
 }
 
 impl Unit {
@@ -32,20 +57,98 @@ impl Unit {
             mobility,
             remaining_range,
             remaining_attacks,
+            action_points: 0,
+            partial_movement: 0,
+            fire_after_move: true,
+            moved: false,
+        }
+    }
+
+    /// Refills the budgets of the unit for a new round.
+    pub fn refresh(&mut self, rules: &Ruleset) {
+        self.moved = false;
+        if rules.action_points {
+            self.action_points = rules.action_points_per_round;
+            self.partial_movement = 0;
+        } else {
+            self.remaining_attacks = rules.attacks_per_round;
+            if rules.carry_over_range {
+                self.remaining_range += self.mobility;
+            } else {
+                self.remaining_range = self.mobility;
+                self.partial_movement = 0;
+            }
+        }
+    }
+
+    /// Hexagons the unit can still move this round. Every hexagon costs one action point.
+    pub fn movement_left(&self, rules: &Ruleset) -> i32 {
+        if rules.action_points {
+            self.action_points
+        } else {
+            self.remaining_range
+        }
+    }
+
+    /// The movement left in movement cost, including what is left below one hexagon.
+    pub fn movement_cost_left(&self, rules: &Ruleset) -> i32 {
+        self.movement_left(rules) * HEXAGON_COST + self.partial_movement
+    }
+
+    pub fn can_attack(&self, rules: &Ruleset) -> bool {
+        self.attack_error(rules).is_none()
+    }
+
+    /// Why the unit cannot attack now, `None` if it can.
+    pub fn attack_error(&self, rules: &Ruleset) -> Option<AttackError> {
+        let attacks_left = if rules.action_points {
+            self.action_points > 0 && self.action_points >= rules.attack_cost
+        } else {
+            self.remaining_attacks > 0
+        };
+        if !attacks_left {
+            Some(AttackError::NoAttacksLeft)
+        } else if self.must_hold_fire() {
+            Some(AttackError::MovedBeforeFiring)
+        } else {
+            None
         }
     }
 
-    pub fn attack(&self, defender: &Unit) -> Result<AttackResult, AttackError> {
-        if self.remaining_attacks <= 0 {
-            Err(AttackError::NoAttacksLeft)
+    /// Whether the unit moved this round and may not fire after moving.
+    pub fn must_hold_fire(&self) -> bool {
+        self.moved && !self.fire_after_move
+    }
+
+    /// The budgets the rules use, for the scripts.
+    pub fn pool(&self, rules: &Ruleset) -> ActionPool {
+        if rules.action_points {
+            ActionPool::ActionPoints(self.action_points)
         } else {
-            let actual_damage = self.damage - defender.armor;
+            ActionPool::Separate {
+                remaining_range: self.remaining_range,
+                remaining_attacks: self.remaining_attacks,
+            }
+        }
+    }
+
+    /// An attack ends the movement of the unit, unless the rules use action points. Then it only
+    /// costs the attack cost. The `context` adds flanking bonuses and cover.
+    pub fn attack(
+        &self,
+        defender: &Unit,
+        context: CombatContext,
+        rules: &Ruleset,
+    ) -> Result<AttackResult, AttackError> {
+        if let Some(error) = self.attack_error(rules) {
+            Err(error)
+        } else {
+            let actual_damage = context.damage(rules.damage(self.damage, defender.armor));
 
             let mut attacker = *self;
             let mut defender = *defender;
             defender.integrity -= actual_damage;
-            attacker.remaining_range = 0;
-            attacker.remaining_attacks -= 1;
+            attacker.spend_attack(rules);
             Ok(AttackResult {
                 actual_damage,
                 attacker,
@@ -54,14 +157,45 @@ impl Unit {
         }
     }
 
-    pub fn is_in_movement_range(&self, distance: i32) -> CanMove {
-        if distance > 0 && self.remaining_range >= distance {
-            CanMove::Yes(self.remaining_range - distance)
+    /// Uses up one attack, like `attack` does.
+    pub fn spend_attack(&mut self, rules: &Ruleset) {
+        if rules.action_points {
+            self.action_points -= rules.attack_cost;
+        } else {
+            self.remaining_range = 0;
+            self.partial_movement = 0;
+            self.remaining_attacks -= 1;
+        }
+    }
+
+    /// Whether the movement left pays for `cost`, with the movement cost left afterwards.
+    pub fn is_in_movement_range(&self, cost: i32, rules: &Ruleset) -> CanMove {
+        let movement_left = self.movement_cost_left(rules);
+        if cost > 0 && movement_left >= cost {
+            CanMove::Yes(movement_left - cost)
         } else {
             CanMove::No
         }
     }
 
+    /// Uses up the budget for moving `distance` hexagons, a negative distance adds to it.
+    pub fn spend_movement(&mut self, distance: i32, rules: &Ruleset) {
+        self.spend_movement_cost(distance * HEXAGON_COST, rules);
+    }
+
+    /// Uses up `cost` of the movement budget. What is left is split back into whole hexagons
+    /// and the rest below one hexagon.
+    pub fn spend_movement_cost(&mut self, cost: i32, rules: &Ruleset) {
+        let left = self.movement_cost_left(rules) - cost;
+        let hexagons = left.div_euclid(HEXAGON_COST);
+        self.partial_movement = left.rem_euclid(HEXAGON_COST);
+        if rules.action_points {
+            self.action_points = hexagons;
+        } else {
+            self.remaining_range = hexagons;
+        }
+    }
+
     pub fn is_in_attack_range(&self, distance: i32) -> bool {
         distance <= self.max_attack_range && distance >= self.min_attack_range
     }
@@ -74,8 +208,20 @@ pub struct AttackResult {
     pub defender: Unit,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AttackError {
     NoAttacksLeft,
+    /// The unit moved this round and cannot fire after moving.
+    MovedBeforeFiring,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionPool {
+    Separate {
+        remaining_range: i32,
+        remaining_attacks: i32,
+    },
+    ActionPoints(i32),
 }
 
 pub enum CanMove {
@@ -92,7 +238,7 @@ mod tests {
         let defender = Unit::new(5, 0, 0, 0, 0, 0, 0, 0);
         let attacker = Unit::new(0, 4, 0, 0, 0, 0, 0, 1);
 
-        let result = attacker.attack(&defender);
+        let result = attacker.attack(&defender, CombatContext::default(), &Ruleset::default());
         let result = match result {
             Ok(x) => x,
             Err(_) => panic!("Expected a result with Ok value"),
@@ -105,7 +251,7 @@ mod tests {
         let defender = Unit::new(5, 0, 0, 0, 0, 0, 0, 0);
         let attacker = Unit::new(0, 4, 0, 0, 0, 0, 5, 1);
 
-        let result = attacker.attack(&defender);
+        let result = attacker.attack(&defender, CombatContext::default(), &Ruleset::default());
         let result = match result {
             Ok(x) => x,
             Err(_) => panic!("Expected a result with Ok value"),
@@ -118,14 +264,14 @@ mod tests {
         let defender = Unit::new(5, 0, 0, 0, 0, 0, 0, 0);
         let attacker = Unit::new(0, 4, 0, 0, 0, 5, 0, 2);
 
-        let result = attacker.attack(&defender);
+        let result = attacker.attack(&defender, CombatContext::default(), &Ruleset::default());
         let result = match result {
             Ok(x) => x,
             Err(_) => panic!("Expected a result with Ok value"),
         };
         assert_eq!(result.attacker.remaining_attacks, 1);
         let attacker = result.attacker;
-        let result = attacker.attack(&defender);
+        let result = attacker.attack(&defender, CombatContext::default(), &Ruleset::default());
         let result = match result {
             Ok(x) => x,
             Err(_) => panic!("Expected a result with Ok value"),
@@ -133,12 +279,35 @@ mod tests {
         assert_eq!(result.attacker.remaining_attacks, 0);
     }
 
+    #[test]
+    pub fn attack_with_action_points_only_costs_the_attack_cost() {
+        let defender = Unit::new(5, 0, 0, 0, 0, 0, 0, 0);
+        let mut attacker = Unit::new(0, 4, 0, 0, 0, 0, 0, 0);
+        attacker.action_points = 3;
+        let rules = Ruleset {
+            action_points: true,
+            attack_cost: 2,
+            ..Ruleset::default()
+        };
+
+        let result = match attacker.attack(&defender, CombatContext::default(), &rules) {
+            Ok(x) => x,
+            Err(_) => panic!("Expected a result with Ok value"),
+        };
+        assert_eq!(result.attacker.action_points, 1);
+        assert!(!result.attacker.can_attack(&rules));
+        assert!(matches!(
+            result.attacker.is_in_movement_range(HEXAGON_COST, &rules),
+            CanMove::Yes(0)
+        ));
+    }
+
     #[test]
     pub fn attack_takes_armor_into_account() {
         let defender = Unit::new(5, 0, 0, 0, 1, 0, 0, 0);
         let attacker = Unit::new(0, 4, 0, 0, 0, 0, 0, 1);
 
-        let result = attacker.attack(&defender);
+        let result = attacker.attack(&defender, CombatContext::default(), &Ruleset::default());
         let result = match result {
             Ok(x) => x,
             Err(_) => panic!("Expected a result with Ok value"),
@@ -151,7 +320,7 @@ mod tests {
         let defender = Unit::new(5, 0, 0, 0, 1, 0, 0, 0);
         let attacker = Unit::new(0, 4, 0, 0, 0, 0, 0, 1);
 
-        let result = attacker.attack(&defender);
+        let result = attacker.attack(&defender, CombatContext::default(), &Ruleset::default());
         let result = match result {
             Ok(x) => x,
             Err(_) => panic!("Expected a result with Ok value"),
@@ -164,23 +333,65 @@ mod tests {
         let defender = Unit::new(5, 0, 0, 0, 1, 0, 0, 0);
         let attacker = Unit::new(0, 4, 0, 0, 0, 0, 0, 0);
 
-        let result = attacker.attack(&defender);
+        let result = attacker.attack(&defender, CombatContext::default(), &Ruleset::default());
         if result.is_ok() {
             panic!("Expected a result with Error value")
         };
     }
 
+    #[test]
+    pub fn units_that_moved_cannot_fire_without_fire_after_move() {
+        let rules = Ruleset::default();
+        let defender = Unit::new(5, 0, 0, 0, 0, 0, 0, 0);
+        let mut attacker = Unit::new(0, 4, 0, 0, 0, 2, 2, 1);
+        attacker.fire_after_move = false;
+        assert!(attacker.can_attack(&rules));
+
+        attacker.spend_movement(1, &rules);
+        attacker.moved = true;
+        assert_eq!(
+            attacker.attack_error(&rules),
+            Some(AttackError::MovedBeforeFiring)
+        );
+        assert!(matches!(
+            attacker.attack(&defender, CombatContext::default(), &rules),
+            Err(AttackError::MovedBeforeFiring)
+        ));
+
+        // The next round it may fire again.
+        attacker.refresh(&rules);
+        assert!(!attacker.moved);
+        assert!(attacker.can_attack(&rules));
+    }
+
+    #[test]
+    pub fn units_fire_after_moving_unless_told_otherwise() {
+        let mut unit = Unit::new(0, 4, 0, 0, 0, 2, 2, 1);
+        assert!(unit.fire_after_move);
+        unit.moved = true;
+        assert!(unit.can_attack(&Ruleset::default()));
+
+        // Units saved before the flag existed.
+        let mut saved = serde_json::to_value(Unit::new(0, 4, 0, 0, 0, 2, 2, 1)).unwrap();
+        let fields = saved.as_object_mut().unwrap();
+        fields.remove("fire_after_move");
+        fields.remove("moved");
+        let loaded: Unit = serde_json::from_value(saved).unwrap();
+        assert!(loaded.fire_after_move);
+        assert!(!loaded.moved);
+    }
+
     #[test]
     pub fn is_in_movement_range_returns_ok_with_remaining_distance_if_distance_is_below_or_equal_to_remaining_range(
     ) {
         let unit = Unit::new(0, 0, 0, 0, 0, 0, 5, 0);
-        let result = unit.is_in_movement_range(4);
+        let result = unit.is_in_movement_range(4 * HEXAGON_COST, &Ruleset::default());
         match result {
-            CanMove::Yes(remaining_range) => assert_eq!(remaining_range, 1),
+            CanMove::Yes(remaining_range) => assert_eq!(remaining_range, HEXAGON_COST),
             _ => panic!("Expected result of Yes"),
         }
 
-        let result = unit.is_in_movement_range(5);
+        let result = unit.is_in_movement_range(5 * HEXAGON_COST, &Ruleset::default());
         match result {
             CanMove::Yes(remaining_range) => assert_eq!(remaining_range, 0),
             _ => panic!("Expected result of Yes"),
@@ -190,7 +401,7 @@ mod tests {
     #[test]
     pub fn is_in_movement_range_returns_no_if_distance_is_higher_than_remaining_range() {
         let unit = Unit::new(0, 0, 0, 0, 0, 0, 4, 0);
-        let result = unit.is_in_movement_range(5);
+        let result = unit.is_in_movement_range(5 * HEXAGON_COST, &Ruleset::default());
         match result {
             CanMove::No => {}
             _ => panic!("Expected result of No"),
@@ -200,13 +411,32 @@ mod tests {
     #[test]
     pub fn is_in_movement_range_returns_no_if_distance_is_0() {
         let unit = Unit::new(0, 0, 0, 0, 0, 0, 4, 0);
-        let result = unit.is_in_movement_range(0);
+        let result = unit.is_in_movement_range(0, &Ruleset::default());
         match result {
             CanMove::No => {}
             _ => panic!("Expected result of No"),
         };
     }
 
+    #[test]
+    pub fn road_steps_add_up_without_rounding() {
+        let rules = Ruleset::default();
+        let mut unit = Unit::new(0, 0, 0, 0, 0, 3, 3, 0);
+
+        unit.spend_movement_cost(1, &rules);
+        assert_eq!((unit.remaining_range, unit.partial_movement), (2, 1));
+        assert_eq!(unit.movement_cost_left(&rules), 5);
+
+        for _ in 0..5 {
+            unit.spend_movement_cost(1, &rules);
+        }
+        assert_eq!((unit.remaining_range, unit.partial_movement), (0, 0));
+        assert_eq!(unit.movement_cost_left(&rules), 0);
+
+        unit.spend_movement(-1, &rules);
+        assert_eq!(unit.movement_cost_left(&rules), HEXAGON_COST);
+    }
+
     #[test]
     pub fn is_in_attack_range_returns_true_if_distance_is_inside_range() {
         let unit = Unit::new(0, 0, 2, 1, 0, 0, 0, 0);
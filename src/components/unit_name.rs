@@ -0,0 +1,3 @@
+/// Name of a unit shown to the players, like "Tank 3".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnitName(pub String);
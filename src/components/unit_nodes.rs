@@ -0,0 +1,212 @@
+//! The child nodes of a unit scene the units system fills in. Scenes with another layout name
+//! their nodes with `UnitNodePaths`, and the nodes are looked up once per unit node instead of
+//! every frame.
+
+use gdnative::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A child node of a unit scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnitNode {
+    /// Label with the integrity.
+    Integrity,
+    /// Shown while the unit is selected.
+    Outline,
+    /// Tinted in the colour of the player and turned with the facing.
+    Model,
+    /// Range filled with the integrity.
+    HealthBar,
+    NameLabel,
+    Movement,
+    Attacks,
+    SupplyWarning,
+    AttackableOutline,
+    /// Pulses the outline.
+    AnimationPlayer,
+}
+
+const UNIT_NODE_COUNT: usize = 10;
+
+impl UnitNode {
+    /// The path of the node in the default unit scene.
+    pub fn default_path(self) -> &'static str {
+        match self {
+            UnitNode::Integrity => "Integrity",
+            UnitNode::Outline => "Outline",
+            UnitNode::Model => "Model",
+            UnitNode::HealthBar => "HealthBar",
+            UnitNode::NameLabel => "NameLabel",
+            UnitNode::Movement => "Movement",
+            UnitNode::Attacks => "Attacks",
+            UnitNode::SupplyWarning => "SupplyWarning",
+            UnitNode::AttackableOutline => "AttackableOutline",
+            UnitNode::AnimationPlayer => "AnimationPlayer",
+        }
+    }
+
+    /// Whether every unit scene is expected to have the node. Only those are reported missing.
+    pub fn is_expected(self) -> bool {
+        matches!(
+            self,
+            UnitNode::Integrity | UnitNode::Outline | UnitNode::Model
+        )
+    }
+}
+
+/// Where the nodes of a unit scene lie, relative to its root. Units without the component use
+/// the default paths.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UnitNodePaths {
+    pub integrity: String,
+    pub outline: String,
+    pub model: String,
+    pub health_bar: String,
+    pub name_label: String,
+}
+
+impl Default for UnitNodePaths {
+    fn default() -> Self {
+        UnitNodePaths {
+            integrity: UnitNode::Integrity.default_path().to_owned(),
+            outline: UnitNode::Outline.default_path().to_owned(),
+            model: UnitNode::Model.default_path().to_owned(),
+            health_bar: UnitNode::HealthBar.default_path().to_owned(),
+            name_label: UnitNode::NameLabel.default_path().to_owned(),
+        }
+    }
+}
+
+impl UnitNodePaths {
+    pub fn path(&self, node: UnitNode) -> &str {
+        match node {
+            UnitNode::Integrity => &self.integrity,
+            UnitNode::Outline => &self.outline,
+            UnitNode::Model => &self.model,
+            UnitNode::HealthBar => &self.health_bar,
+            UnitNode::NameLabel => &self.name_label,
+            _ => node.default_path(),
+        }
+    }
+}
+
+/// The child nodes of one unit node, looked up the first time they are needed. A node that is
+/// missing stays missing until the unit gets another node, told apart by its instance id.
+#[derive(Debug)]
+pub struct UnitNodeCache<N> {
+    root: Option<i64>,
+    nodes: [Option<Option<N>>; UNIT_NODE_COUNT],
+    reported: [bool; UNIT_NODE_COUNT],
+}
+
+impl<N> Default for UnitNodeCache<N> {
+    fn default() -> Self {
+        UnitNodeCache {
+            root: None,
+            nodes: Default::default(),
+            reported: [false; UNIT_NODE_COUNT],
+        }
+    }
+}
+
+impl<N: Clone> UnitNodeCache<N> {
+    /// The child `node` of the unit node with the instance id `root`. `lookup` is only called
+    /// the first time for every unit node.
+    pub fn get<F>(&mut self, root: i64, node: UnitNode, lookup: F) -> Option<N>
+    where
+        F: FnOnce() -> Option<N>,
+    {
+        if self.root != Some(root) {
+            self.root = Some(root);
+            self.nodes = Default::default();
+        }
+        self.nodes[node as usize].get_or_insert_with(lookup).clone()
+    }
+
+    /// Whether the missing node is to be reported, which is the first time for nodes every scene
+    /// is expected to have and never for the others.
+    pub fn report_missing(&mut self, node: UnitNode) -> bool {
+        node.is_expected() && !std::mem::replace(&mut self.reported[node as usize], true)
+    }
+}
+
+/// A child node of the Godot node of a unit.
+#[derive(Clone)]
+pub struct ChildNode(pub Ref<Node>);
+
+unsafe impl Send for ChildNode {}
+unsafe impl Sync for ChildNode {}
+
+/// The child nodes of the Godot node of a unit.
+pub type UnitNodes = UnitNodeCache<ChildNode>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn nodes_are_looked_up_once_per_unit_node() {
+        let mut cache = UnitNodeCache::default();
+        let lookups = Cell::new(0);
+        let lookup = |found: Option<&'static str>| {
+            lookups.set(lookups.get() + 1);
+            found
+        };
+
+        for _ in 0..3 {
+            assert_eq!(
+                cache.get(1, UnitNode::Model, || lookup(Some("model"))),
+                Some("model")
+            );
+            assert_eq!(cache.get(1, UnitNode::HealthBar, || lookup(None)), None);
+        }
+        assert_eq!(lookups.get(), 2);
+
+        // Another node for the unit resolves everything again.
+        assert_eq!(
+            cache.get(2, UnitNode::Model, || lookup(Some("other model"))),
+            Some("other model")
+        );
+        assert_eq!(
+            cache.get(2, UnitNode::HealthBar, || lookup(Some("bar"))),
+            Some("bar")
+        );
+        assert_eq!(
+            cache.get(2, UnitNode::HealthBar, || lookup(None)),
+            Some("bar")
+        );
+        assert_eq!(lookups.get(), 4);
+    }
+
+    #[test]
+    fn missing_nodes_are_reported_once() {
+        let mut cache = UnitNodeCache::<()>::default();
+
+        assert!(cache.report_missing(UnitNode::Integrity));
+        assert!(!cache.report_missing(UnitNode::Integrity));
+        assert!(cache.report_missing(UnitNode::Model));
+        cache.get(7, UnitNode::Model, || None);
+        assert!(!cache.report_missing(UnitNode::Model));
+        // Optional nodes are skipped silently.
+        assert!(!cache.report_missing(UnitNode::HealthBar));
+        assert!(!cache.report_missing(UnitNode::NameLabel));
+    }
+
+    #[test]
+    fn paths_default_to_the_node_names() {
+        let paths: UnitNodePaths =
+            serde_json::from_str(r#"{"model": "Body/Sprite", "health_bar": "Bars/Health"}"#)
+                .unwrap();
+
+        assert_eq!(paths.path(UnitNode::Model), "Body/Sprite");
+        assert_eq!(paths.path(UnitNode::HealthBar), "Bars/Health");
+        assert_eq!(paths.path(UnitNode::Integrity), "Integrity");
+        assert_eq!(paths.path(UnitNode::Outline), "Outline");
+        assert_eq!(paths.path(UnitNode::AnimationPlayer), "AnimationPlayer");
+        assert_eq!(
+            UnitNodePaths::default().path(UnitNode::NameLabel),
+            "NameLabel"
+        );
+    }
+}
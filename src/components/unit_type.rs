@@ -0,0 +1,3 @@
+/// Name of the catalog entry a unit was created from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnitType(pub String);
@@ -0,0 +1,27 @@
+/// Marks a node whose z index follows the row of its hexagon, so nodes further south draw over
+/// the ones north of them. Remembers the z index it last set, so moving units are the only ones
+/// whose node is touched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct YSortByHex {
+    /// The z index of the template, it orders nodes within the same row.
+    pub base: i64,
+    applied: Option<i64>,
+}
+
+impl YSortByHex {
+    pub fn new(base: i64) -> Self {
+        YSortByHex {
+            base,
+            applied: None,
+        }
+    }
+
+    /// Takes the z index the node should have and returns it if the node has to be updated.
+    pub fn update(&mut self, z_index: i64) -> Option<i64> {
+        if self.applied == Some(z_index) {
+            return None;
+        }
+        self.applied = Some(z_index);
+        Some(z_index)
+    }
+}
@@ -0,0 +1,115 @@
+//! Cover against ranged attacks. A unit next to a hexagon with `ProvidesCover` takes less damage
+//! from attacks that come from that side: the line from the attacker passes through or right
+//! next to the cover before it reaches the unit. Attacks on neighbours are melee and ignore
+//! cover.
+
+use crate::components::cover::ProvidesCover;
+use crate::components::hexagon::Hexagon;
+use crate::systems::hexgrid::get_neighbours;
+use legion::{EntityStore, IntoQuery};
+use std::collections::HashSet;
+
+/// Damage ranged attacks lose against a unit in cover.
+pub const COVER_DAMAGE_REDUCTION: i32 = 2;
+
+/// Attacks from at least this far away are ranged.
+pub const MIN_COVER_DISTANCE: i32 = 2;
+
+/// The neighbours of `defender` that can cover it against an attack from `attacker`, empty for
+/// melee attacks.
+pub fn covering_hexagons(attacker: &Hexagon, defender: &Hexagon) -> Vec<Hexagon> {
+    let distance = attacker.distance_to(defender);
+    if distance < MIN_COVER_DISTANCE {
+        return Vec::new();
+    }
+    let line = attacker.line_to(defender);
+    // The last hexagon the attack crosses before it arrives.
+    let before = line[line.len() - 2];
+    get_neighbours(defender)
+        .into_iter()
+        .filter(|neighbour| {
+            neighbour.distance_to(attacker) < distance
+                && (*neighbour == before || neighbour.is_neighbour(&before))
+        })
+        .collect()
+}
+
+/// Number of hexagons with cover between `defender` and an attack from `attacker`.
+pub fn cover_count<S: EntityStore>(world: &S, attacker: &Hexagon, defender: &Hexagon) -> usize {
+    let covering = covering_hexagons(attacker, defender);
+    if covering.is_empty() {
+        return 0;
+    }
+    <(&Hexagon, &ProvidesCover)>::query()
+        .iter(world)
+        .map(|(hexagon, _)| *hexagon)
+        .filter(|hexagon| covering.contains(hexagon))
+        .collect::<HashSet<Hexagon>>()
+        .len()
+}
+
+/// `damage` of an attack with `cover` hexagons of cover in the way. Cover reduces the damage
+/// once, however much of it there is, and never below zero.
+pub fn apply_cover(damage: i32, cover: usize) -> i32 {
+    if cover == 0 || damage <= 0 {
+        damage
+    } else {
+        (damage - COVER_DAMAGE_REDUCTION).max(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion::World;
+
+    fn world_with_cover(hexagons: &[Hexagon]) -> World {
+        let mut world = World::default();
+        for hexagon in hexagons {
+            world.push((*hexagon, ProvidesCover));
+        }
+        world
+    }
+
+    #[test]
+    fn only_cover_on_the_side_of_the_attacker_counts() {
+        let defender = Hexagon::zero();
+        let west = Hexagon::new_axial(-3, 0);
+        let east = Hexagon::new_axial(3, 0);
+        let world = world_with_cover(&[Hexagon::new_axial(-1, 0)]);
+
+        assert_eq!(cover_count(&world, &west, &defender), 1);
+        assert_eq!(cover_count(&world, &east, &defender), 0);
+        // This line passes right next to the cover.
+        assert_eq!(
+            cover_count(&world, &Hexagon::new_axial(-2, -2), &defender),
+            1
+        );
+        assert_eq!(cover_count(&world, &Hexagon::new_axial(0, 3), &defender), 0);
+    }
+
+    #[test]
+    fn melee_attacks_ignore_cover() {
+        let defender = Hexagon::zero();
+        let world = world_with_cover(&[Hexagon::new_axial(-1, 0), Hexagon::new_axial(0, -1)]);
+
+        assert!(covering_hexagons(&Hexagon::new_axial(-1, 1), &defender).is_empty());
+        assert_eq!(
+            cover_count(&world, &Hexagon::new_axial(-1, 1), &defender),
+            0
+        );
+        assert_eq!(
+            cover_count(&world, &Hexagon::new_axial(-2, -2), &defender),
+            2
+        );
+    }
+
+    #[test]
+    fn cover_reduces_damage_once() {
+        assert_eq!(apply_cover(5, 0), 5);
+        assert_eq!(apply_cover(5, 1), 5 - COVER_DAMAGE_REDUCTION);
+        assert_eq!(apply_cover(5, 2), 5 - COVER_DAMAGE_REDUCTION);
+        assert_eq!(apply_cover(1, 1), 0);
+        assert_eq!(apply_cover(-1, 1), -1);
+    }
+}
@@ -0,0 +1,211 @@
+//! Counts and consistency checks of the world, for debugging and smoke tests.
+
+use crate::components::field::Field;
+use crate::components::node_component::NodeComponent;
+use crate::components::node_template::NodeTemplate;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::game_state::{validate, GameState};
+use gdnative::prelude::*;
+use legion::{component, Entity, IntoQuery, World};
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorldDiagnostics {
+    pub total_entities: usize,
+    pub units_per_player: BTreeMap<usize, usize>,
+    pub grid_hexes: usize,
+    pub with_node: usize,
+    /// Entities with a template whose node was not created yet.
+    pub pending_nodes: usize,
+}
+
+impl WorldDiagnostics {
+    pub fn collect(world: &World) -> Self {
+        let mut units_per_player = BTreeMap::new();
+        for (player, _) in <(&PlayerComponent, &Unit)>::query().iter(world) {
+            *units_per_player.entry(player.0).or_insert(0) += 1;
+        }
+        WorldDiagnostics {
+            total_entities: world.len(),
+            units_per_player,
+            grid_hexes: <&Field>::query().iter(world).count(),
+            with_node: <&NodeComponent>::query().iter(world).count(),
+            pending_nodes: <&NodeTemplate>::query()
+                .filter(!component::<NodeComponent>())
+                .iter(world)
+                .count(),
+        }
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("total_entities", self.total_entities as i64);
+        let units = Dictionary::new();
+        for (player, count) in &self.units_per_player {
+            units.insert(*player as i64, *count as i64);
+        }
+        dict.insert("units_per_player", units.owned_to_variant());
+        dict.insert("grid_hexes", self.grid_hexes as i64);
+        dict.insert("with_node", self.with_node as i64);
+        dict.insert("pending_nodes", self.pending_nodes as i64);
+        dict
+    }
+}
+
+/// Checks the invariants of `game_state::validate` and that the nodes known to the game world
+/// match the entities with a NodeComponent. Returns a description of every violation.
+pub fn validate_world(
+    world: &World,
+    state: &GameState,
+    node_entities: &HashSet<Entity>,
+) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    let with_node: HashSet<Entity> = <(Entity, &NodeComponent)>::query()
+        .iter(world)
+        .map(|(entity, _)| *entity)
+        .collect();
+    if with_node.len() != node_entities.len() {
+        violations.push(format!(
+            "{} nodes are tracked, but {} entities have a NodeComponent",
+            node_entities.len(),
+            with_node.len()
+        ));
+    }
+    for entity in node_entities.difference(&with_node) {
+        violations.push(format!(
+            "Node of entity {:?} is tracked without a NodeComponent",
+            entity
+        ));
+    }
+
+    violations.extend(
+        validate(world, state)
+            .iter()
+            .map(|violation| violation.to_string()),
+    );
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::components::persistent_id::PersistentIds;
+    use crate::game_state::State;
+    use crate::player::Player;
+    use crate::spawn::spawn_unit;
+
+    fn unit() -> Unit {
+        Unit::new(1, 1, 1, 1, 0, 1, 1, 1)
+    }
+
+    fn new_state() -> GameState {
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.current_player = Some(0);
+        state
+    }
+
+    #[test]
+    fn diagnostics_count_units_and_fields() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        spawn_unit(
+            &mut world,
+            &mut ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            unit(),
+            None,
+        );
+        spawn_unit(
+            &mut world,
+            &mut ids,
+            1,
+            Hexagon::new_axial(1, 0),
+            unit(),
+            None,
+        );
+        spawn_unit(
+            &mut world,
+            &mut ids,
+            1,
+            Hexagon::new_axial(2, 0),
+            unit(),
+            None,
+        );
+        world.push((Field::new(Hexagon::new_axial(0, 0)),));
+
+        let diagnostics = WorldDiagnostics::collect(&world);
+
+        assert_eq!(diagnostics.total_entities, 4);
+        assert_eq!(
+            diagnostics.units_per_player.into_iter().collect::<Vec<_>>(),
+            vec![(0, 1), (1, 2)]
+        );
+        assert_eq!(diagnostics.grid_hexes, 1);
+        assert_eq!(diagnostics.with_node, 0);
+        assert_eq!(diagnostics.pending_nodes, 0);
+    }
+
+    #[test]
+    fn consistent_world_has_no_violations() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let entity = spawn_unit(
+            &mut world,
+            &mut ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            unit(),
+            None,
+        );
+        let mut state = new_state();
+        state.state = State::Selected(entity);
+
+        assert!(validate_world(&world, &state, &HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn violations_are_reported() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let missing = world.push((Hexagon::zero(),));
+        world.remove(missing);
+        spawn_unit(
+            &mut world,
+            &mut ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            unit(),
+            None,
+        );
+        spawn_unit(
+            &mut world,
+            &mut ids,
+            3,
+            Hexagon::new_axial(0, 0),
+            unit(),
+            None,
+        );
+        let mut state = new_state();
+        state.state = State::Selected(missing);
+        let node_entities: HashSet<Entity> = vec![missing].into_iter().collect();
+
+        let violations = validate_world(&world, &state, &node_entities);
+
+        assert_eq!(violations.len(), 5);
+        assert_eq!(
+            violations[0],
+            "1 nodes are tracked, but 0 entities have a NodeComponent"
+        );
+        assert!(violations[2].ends_with("belongs to unknown player 3"));
+        assert_eq!(violations[3], "Several units at (0, 0)");
+        assert!(violations[4].starts_with("The state references"));
+    }
+}
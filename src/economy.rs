@@ -0,0 +1,216 @@
+//! The resources players earn and pay at the start of every round. Income comes from the supply
+//! sources a player holds, upkeep from their units. The report for the economy panel projects
+//! the next round with the same functions the round start credits with.
+
+use crate::components::player::Player as PlayerComponent;
+use crate::components::supply::SupplySource;
+use crate::components::unit::Unit;
+use crate::game_state::GameState;
+use gdnative::prelude::*;
+use legion::{EntityStore, IntoQuery, World};
+
+/// Resources `player` earns at the start of the next round, scaled by their handicap.
+pub fn compute_income<S: EntityStore>(world: &S, state: &GameState, player: usize) -> i32 {
+    let sources = <&SupplySource>::query()
+        .iter(world)
+        .filter(|source| source.player == player)
+        .count() as i32;
+    let multiplier = state
+        .players
+        .get(player)
+        .map_or(1.0, |player| player.get_handicap().resource_multiplier);
+    ((sources * state.rules.income_per_source) as f32 * multiplier).round() as i32
+}
+
+/// Resources `player` pays for their units at the start of the next round.
+pub fn compute_upkeep<S: EntityStore>(world: &S, state: &GameState, player: usize) -> i32 {
+    let units = <(&Unit, &PlayerComponent)>::query()
+        .iter(world)
+        .filter(|(unit, owner)| owner.0 == player && unit.integrity > 0)
+        .count() as i32;
+    units * state.rules.upkeep_per_unit
+}
+
+/// Credits the income and charges the upkeep of every player that is not an observer. Called at
+/// the start of a round after the events and the supply, so a unit lost there costs nothing.
+pub fn collect_income(world: &World, state: &mut GameState) {
+    for player in 0..state.players.len() {
+        if state.players[player].is_observer() {
+            continue;
+        }
+        let net = compute_income(world, state, player) - compute_upkeep(world, state, player);
+        let data = &mut state.players[player];
+        data.set_resources((data.get_resources() + net).max(0));
+    }
+}
+
+/// What a player can buy of one unit type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Affordability {
+    pub unit_type: String,
+    pub cost: i32,
+    /// Units of the type the current resources pay for, -1 for units that cost nothing.
+    pub affordable: i32,
+    /// Rounds of net income until the player can afford one unit, 0 if they can now and -1 if
+    /// they never will at the current net income.
+    pub rounds_until_affordable: i32,
+}
+
+/// The economy of a player for the economy panel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EconomyReport {
+    pub resources: i32,
+    pub income: i32,
+    pub upkeep: i32,
+    pub net: i32,
+    pub units: Vec<Affordability>,
+}
+
+impl EconomyReport {
+    pub fn new<S: EntityStore>(world: &S, state: &GameState, player: usize) -> Self {
+        let resources = state
+            .players
+            .get(player)
+            .map_or(0, |player| player.get_resources());
+        let income = compute_income(world, state, player);
+        let upkeep = compute_upkeep(world, state, player);
+        let net = income - upkeep;
+        let units = state
+            .unit_catalog
+            .definitions()
+            .iter()
+            .map(|definition| {
+                let cost = definition.cost;
+                let missing = cost - resources;
+                Affordability {
+                    unit_type: definition.name.clone(),
+                    cost,
+                    affordable: if cost > 0 { resources / cost } else { -1 },
+                    rounds_until_affordable: if missing <= 0 {
+                        0
+                    } else if net <= 0 {
+                        -1
+                    } else {
+                        (missing + net - 1) / net
+                    },
+                }
+            })
+            .collect();
+        EconomyReport {
+            resources,
+            income,
+            upkeep,
+            net,
+            units,
+        }
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("resources", self.resources);
+        dict.insert("income", self.income);
+        dict.insert("upkeep", self.upkeep);
+        dict.insert("net", self.net);
+        let units = VariantArray::new();
+        for unit in &self.units {
+            let entry = Dictionary::new();
+            entry.insert("unit_type", unit.unit_type.as_str());
+            entry.insert("cost", unit.cost);
+            entry.insert("affordable", unit.affordable);
+            entry.insert("rounds_until_affordable", unit.rounds_until_affordable);
+            units.push(entry.into_shared());
+        }
+        dict.insert("units", units.into_shared());
+        dict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::game_state::{force_state, State};
+    use crate::player::Player;
+    use crate::rules::Ruleset;
+    use crate::simulation::simulate_frame;
+    use crate::spawn::{spawn_grid, spawn_unit};
+    use crate::supply::spawn_supply_source;
+    use gdnative::core_types::Color;
+
+    fn end_turn(world: &mut World, state: &mut GameState) {
+        force_state(state, State::NewRound);
+        for _ in 0..10 {
+            simulate_frame(world, state, 0.05);
+            if state.state == State::Waiting {
+                return;
+            }
+        }
+        panic!("The turn did not end");
+    }
+
+    #[test]
+    fn projections_match_the_next_round() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.rules = Ruleset {
+            income_per_source: 30,
+            upkeep_per_unit: 10,
+            ..Ruleset::default()
+        };
+        for name in &["Player 1", "Player 2"] {
+            state
+                .players
+                .push(Player::new((*name).to_owned(), Color::rgb(0.0, 0.0, 1.0)));
+        }
+        state.players[0].set_resources(50);
+        state.players[1].set_resources(5);
+        state.current_player = Some(0);
+        spawn_grid(&mut world, 3);
+        spawn_supply_source(&mut world, Hexagon::new_axial(-3, 0), 0);
+        spawn_supply_source(&mut world, Hexagon::new_axial(-3, 1), 0);
+        for (player, q) in &[(0, -2), (0, -1), (1, 2), (1, 3)] {
+            spawn_unit(
+                &mut world,
+                &mut state.persistent_ids,
+                *player,
+                Hexagon::new_axial(*q, 0),
+                Unit::new(10, 1, 1, 1, 0, 2, 2, 1),
+                None,
+            );
+        }
+        simulate_frame(&mut world, &mut state, 0.0);
+
+        let first = EconomyReport::new(&world, &state, 0);
+        assert_eq!((first.income, first.upkeep, first.net), (60, 20, 40));
+        let tank = &first.units[0];
+        assert_eq!((tank.affordable, tank.rounds_until_affordable), (0, 2));
+        let second = EconomyReport::new(&world, &state, 1);
+        assert_eq!(second.net, -20);
+        assert_eq!(second.units[0].rounds_until_affordable, -1);
+
+        end_turn(&mut world, &mut state);
+        assert_eq!(state.round, 1);
+        assert_eq!(state.players[0].get_resources(), 50);
+        end_turn(&mut world, &mut state);
+        assert_eq!(state.round, 2);
+        assert_eq!(
+            state.players[0].get_resources(),
+            first.resources + first.net
+        );
+        // Upkeep never takes more than the player has.
+        assert_eq!(state.players[1].get_resources(), 0);
+
+        let report = EconomyReport::new(&world, &state, 0);
+        assert_eq!(report.resources, 90);
+        assert_eq!(report.units[0].rounds_until_affordable, 1);
+        end_turn(&mut world, &mut state);
+        end_turn(&mut world, &mut state);
+        assert_eq!(state.players[0].get_resources(), 130);
+        let report = EconomyReport::new(&world, &state, 0);
+        assert_eq!(
+            (report.units[0].affordable, report.units[1].affordable),
+            (1, 1)
+        );
+        assert_eq!(report.units[0].rounds_until_affordable, 0);
+    }
+}
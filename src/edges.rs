@@ -0,0 +1,267 @@
+//! Edges between neighbouring hexagons. Rivers run along edges instead of filling hexagons, so
+//! crossing one costs extra. Cliffs can not be crossed at all, bridges cross a river like open
+//! ground. Demolition units can destroy bridges, which leaves the river behind.
+
+use crate::components::hexagon::Hexagon;
+use crate::movement::HEXAGON_COST;
+use gdnative::core_types::Color;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// What crossing a river costs on top of entering the hexagon behind it.
+pub const RIVER_CROSSING_COST: i32 = 2 * HEXAGON_COST;
+/// The integrity of an undamaged bridge.
+pub const BRIDGE_INTEGRITY: i32 = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    River,
+    Bridge,
+    Cliff,
+}
+
+impl EdgeKind {
+    pub fn from_name(name: &str) -> Option<EdgeKind> {
+        match name {
+            "river" => Some(EdgeKind::River),
+            "bridge" => Some(EdgeKind::Bridge),
+            "cliff" => Some(EdgeKind::Cliff),
+            _ => None,
+        }
+    }
+
+    pub fn colour(&self) -> Color {
+        match self {
+            EdgeKind::River => Color::rgba(0.2, 0.4, 0.9, 1.0),
+            EdgeKind::Bridge => Color::rgba(0.5, 0.35, 0.2, 1.0),
+            EdgeKind::Cliff => Color::rgba(0.15, 0.1, 0.05, 1.0),
+        }
+    }
+
+    /// What crossing the edge costs on top of entering the hexagon, `None` if it can not be
+    /// crossed.
+    pub fn crossing_cost(&self) -> Option<i32> {
+        match self {
+            EdgeKind::River => Some(RIVER_CROSSING_COST),
+            EdgeKind::Bridge => Some(0),
+            EdgeKind::Cliff => None,
+        }
+    }
+}
+
+/// The two hexagons of an edge in a fixed order, so both directions find the same edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EdgeKey(Hexagon, Hexagon);
+
+impl EdgeKey {
+    pub fn new(first: Hexagon, second: Hexagon) -> Self {
+        if (first.get_q(), first.get_r()) <= (second.get_q(), second.get_r()) {
+            EdgeKey(first, second)
+        } else {
+            EdgeKey(second, first)
+        }
+    }
+
+    pub fn hexagons(&self) -> (Hexagon, Hexagon) {
+        (self.0, self.1)
+    }
+}
+
+/// An edge as scenarios and saves store it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioEdge {
+    pub from: Hexagon,
+    pub to: Hexagon,
+    pub kind: EdgeKind,
+    /// The damage a bridge took, zero for other edges.
+    #[serde(default)]
+    pub damage: i32,
+}
+
+/// The edges of the map. Edges without an entry are open ground.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EdgeData {
+    edges: HashMap<EdgeKey, EdgeKind>,
+    /// The damage of bridges that were attacked, undamaged bridges have no entry.
+    bridge_damage: HashMap<EdgeKey, i32>,
+}
+
+impl EdgeData {
+    /// The edges of a scenario or save. Entries between hexagons that are no neighbours are
+    /// skipped.
+    pub fn from_list(edges: &[ScenarioEdge]) -> Self {
+        let mut data = EdgeData::default();
+        for edge in edges {
+            data.set(edge.from, edge.to, Some(edge.kind));
+            if edge.kind == EdgeKind::Bridge && edge.damage > 0 {
+                data.damage_bridge(&edge.from, &edge.to, edge.damage);
+            }
+        }
+        data
+    }
+
+    /// The edges ordered by their hexagons, for scenarios and saves.
+    pub fn to_list(&self) -> Vec<ScenarioEdge> {
+        let mut edges: Vec<ScenarioEdge> = self
+            .edges
+            .iter()
+            .map(|(key, kind)| {
+                let (from, to) = key.hexagons();
+                ScenarioEdge {
+                    from,
+                    to,
+                    kind: *kind,
+                    damage: self.bridge_damage.get(key).copied().unwrap_or(0),
+                }
+            })
+            .collect();
+        edges.sort_by_key(|edge| {
+            (
+                edge.from.get_q(),
+                edge.from.get_r(),
+                edge.to.get_q(),
+                edge.to.get_r(),
+            )
+        });
+        edges
+    }
+
+    pub fn get(&self, first: &Hexagon, second: &Hexagon) -> Option<EdgeKind> {
+        self.edges.get(&EdgeKey::new(*first, *second)).copied()
+    }
+
+    /// Sets the edge between two neighbours, `None` makes it open ground again. Returns whether
+    /// the edge changed.
+    pub fn set(&mut self, first: Hexagon, second: Hexagon, kind: Option<EdgeKind>) -> bool {
+        if !first.is_neighbour(&second) {
+            return false;
+        }
+        let key = EdgeKey::new(first, second);
+        self.bridge_damage.remove(&key);
+        let previous = match kind {
+            None => self.edges.remove(&key),
+            Some(kind) => self.edges.insert(key, kind),
+        };
+        previous != kind
+    }
+
+    /// Drops every edge of `hexagon`, for hexagons removed from the map.
+    pub fn remove_around(&mut self, hexagon: &Hexagon) {
+        self.edges
+            .retain(|key, _| key.0 != *hexagon && key.1 != *hexagon);
+        self.bridge_damage
+            .retain(|key, _| key.0 != *hexagon && key.1 != *hexagon);
+    }
+
+    /// The integrity left of the bridge between the hexagons, `None` if there is no bridge.
+    pub fn bridge_integrity(&self, first: &Hexagon, second: &Hexagon) -> Option<i32> {
+        if self.get(first, second) != Some(EdgeKind::Bridge) {
+            return None;
+        }
+        let damage = self
+            .bridge_damage
+            .get(&EdgeKey::new(*first, *second))
+            .copied()
+            .unwrap_or(0);
+        Some(BRIDGE_INTEGRITY - damage)
+    }
+
+    /// Damages the bridge between the hexagons and returns the integrity it has left. A bridge
+    /// without integrity collapses into the river it crossed. `None` if there is no bridge.
+    pub fn damage_bridge(&mut self, first: &Hexagon, second: &Hexagon, damage: i32) -> Option<i32> {
+        let remaining = self.bridge_integrity(first, second)? - damage.max(0);
+        if remaining <= 0 {
+            self.set(*first, *second, Some(EdgeKind::River));
+        } else {
+            self.bridge_damage
+                .insert(EdgeKey::new(*first, *second), BRIDGE_INTEGRITY - remaining);
+        }
+        Some(remaining)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&EdgeKey, &EdgeKind)> {
+        self.edges.iter()
+    }
+
+    /// What crossing from `from` to `to` costs on top of entering `to`, `None` if a cliff is in
+    /// the way.
+    pub fn crossing_cost(&self, from: &Hexagon, to: &Hexagon) -> Option<i32> {
+        self.get(from, to)
+            .map_or(Some(0), |kind| kind.crossing_cost())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_directions_find_the_same_edge() {
+        let first = Hexagon::new_axial(1, 0);
+        let second = Hexagon::new_axial(0, 1);
+        assert_eq!(EdgeKey::new(first, second), EdgeKey::new(second, first));
+
+        let mut edges = EdgeData::default();
+        assert!(edges.set(first, second, Some(EdgeKind::River)));
+        assert!(!edges.set(second, first, Some(EdgeKind::River)));
+        assert_eq!(edges.get(&second, &first), Some(EdgeKind::River));
+        assert_eq!(
+            edges.crossing_cost(&second, &first),
+            Some(RIVER_CROSSING_COST)
+        );
+        assert_eq!(edges.to_list().len(), 1);
+
+        assert!(!edges.set(first, Hexagon::new_axial(3, 0), Some(EdgeKind::Cliff)));
+        assert!(edges.set(second, first, None));
+        assert_eq!(edges.crossing_cost(&first, &second), Some(0));
+    }
+
+    #[test]
+    fn list_round_trip() {
+        let mut edges = EdgeData::default();
+        edges.set(
+            Hexagon::zero(),
+            Hexagon::new_axial(1, 0),
+            Some(EdgeKind::Cliff),
+        );
+        edges.set(
+            Hexagon::zero(),
+            Hexagon::new_axial(0, 1),
+            Some(EdgeKind::Bridge),
+        );
+
+        assert_eq!(EdgeData::from_list(&edges.to_list()), edges);
+        edges.remove_around(&Hexagon::zero());
+        assert!(edges.to_list().is_empty());
+    }
+
+    #[test]
+    fn destroyed_bridge_becomes_a_river() {
+        let first = Hexagon::zero();
+        let second = Hexagon::new_axial(1, 0);
+        let mut edges = EdgeData::default();
+        edges.set(first, second, Some(EdgeKind::Bridge));
+
+        assert_eq!(
+            edges.damage_bridge(&second, &first, 5),
+            Some(BRIDGE_INTEGRITY - 5)
+        );
+        assert_eq!(edges.crossing_cost(&first, &second), Some(0));
+        assert_eq!(EdgeData::from_list(&edges.to_list()), edges);
+
+        assert!(
+            edges
+                .damage_bridge(&first, &second, BRIDGE_INTEGRITY)
+                .unwrap()
+                <= 0
+        );
+        assert_eq!(edges.get(&first, &second), Some(EdgeKind::River));
+        assert_eq!(
+            edges.crossing_cost(&first, &second),
+            Some(RIVER_CROSSING_COST)
+        );
+        assert_eq!(edges.damage_bridge(&first, &second, 5), None);
+        assert_eq!(edges.to_list()[0].damage, 0);
+    }
+}
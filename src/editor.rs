@@ -0,0 +1,327 @@
+//! Map mutations for the scenario editor. Every function returns whether anything changed.
+
+use crate::buildings::{building_at, spawn_building};
+use crate::components::building::Building;
+use crate::components::field::Field;
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::PersistentIds;
+use crate::components::pickup::Pickup;
+use crate::components::terrain::Terrain;
+use crate::components::unit::Unit;
+use crate::edges::{EdgeData, EdgeKind};
+use crate::pickups::{pickup_at, spawn_pickup};
+use crate::spawn::spawn_unit_of_type;
+use crate::unit_catalog::UnitCatalog;
+use legion::{Entity, EntityStore, IntoQuery, World};
+
+fn find_field<S: EntityStore>(world: &S, hexagon: &Hexagon) -> Option<Entity> {
+    <(Entity, &Field)>::query()
+        .iter(world)
+        .find(|(_, field)| field.location == *hexagon)
+        .map(|(entity, _)| *entity)
+}
+
+fn find_unit<S: EntityStore>(world: &S, hexagon: &Hexagon) -> Option<Entity> {
+    <(Entity, &Hexagon, &Unit)>::query()
+        .iter(world)
+        .find(|(_, position, _)| *position == hexagon)
+        .map(|(entity, _, _)| *entity)
+}
+
+pub fn set_terrain(world: &mut World, hexagon: &Hexagon, terrain: Terrain) -> bool {
+    let entity = match find_field(world, hexagon) {
+        None => return false,
+        Some(entity) => entity,
+    };
+    match world.entry(entity) {
+        None => false,
+        Some(mut entry) => match entry.get_component_mut::<Field>() {
+            Err(_) => false,
+            Ok(field) => {
+                field.terrain = terrain;
+                true
+            }
+        },
+    }
+}
+
+pub fn add_hex(world: &mut World, hexagon: &Hexagon, terrain: Terrain) -> bool {
+    if find_field(world, hexagon).is_some() {
+        return false;
+    }
+    let mut field = Field::new(*hexagon);
+    field.terrain = terrain;
+    world.push((field,));
+    true
+}
+
+/// Removes the hex together with a unit, a pickup and a building on it.
+pub fn remove_hex(world: &mut World, hexagon: &Hexagon) -> bool {
+    let entity = match find_field(world, hexagon) {
+        None => return false,
+        Some(entity) => entity,
+    };
+    remove_unit(world, hexagon);
+    remove_pickup(world, hexagon);
+    remove_building(world, hexagon);
+    world.remove(entity);
+    true
+}
+
+/// Sets the edge between two neighbouring hexes of the map, `None` removes it.
+pub fn set_edge(
+    world: &World,
+    edges: &mut EdgeData,
+    from: Hexagon,
+    to: Hexagon,
+    kind: Option<EdgeKind>,
+) -> bool {
+    find_field(world, &from).is_some()
+        && find_field(world, &to).is_some()
+        && edges.set(from, to, kind)
+}
+
+/// Places a unit on an existing, free hex.
+pub fn place_unit(
+    world: &mut World,
+    ids: &mut PersistentIds,
+    catalog: &UnitCatalog,
+    hexagon: &Hexagon,
+    player: usize,
+    unit_type: &str,
+    with_node: bool,
+) -> bool {
+    if find_field(world, hexagon).is_none() || find_unit(world, hexagon).is_some() {
+        return false;
+    }
+    spawn_unit_of_type(world, ids, catalog, player, *hexagon, unit_type, with_node).is_some()
+}
+
+pub fn remove_unit(world: &mut World, hexagon: &Hexagon) -> bool {
+    match find_unit(world, hexagon) {
+        None => false,
+        Some(entity) => world.remove(entity),
+    }
+}
+
+/// Places a pickup on an existing hex without one. Units may stand on it.
+pub fn place_pickup(world: &mut World, hexagon: &Hexagon, pickup: Pickup, with_node: bool) -> bool {
+    if find_field(world, hexagon).is_none() || pickup_at(world, hexagon).is_some() {
+        return false;
+    }
+    spawn_pickup(world, *hexagon, pickup, with_node);
+    true
+}
+
+pub fn remove_pickup(world: &mut World, hexagon: &Hexagon) -> bool {
+    match pickup_at(world, hexagon) {
+        None => false,
+        Some((entity, _)) => world.remove(entity),
+    }
+}
+
+/// Places a building on an existing hex without one. Units may stand on it.
+pub fn place_building(
+    world: &mut World,
+    hexagon: &Hexagon,
+    building: Building,
+    with_node: bool,
+) -> bool {
+    if find_field(world, hexagon).is_none() || building_at(world, hexagon).is_some() {
+        return false;
+    }
+    spawn_building(world, *hexagon, building, with_node);
+    true
+}
+
+pub fn remove_building(world: &mut World, hexagon: &Hexagon) -> bool {
+    match building_at(world, hexagon) {
+        None => false,
+        Some((entity, _)) => world.remove(entity),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+    use crate::spawn::spawn_grid;
+    use crate::unit_catalog::DEFAULT_UNIT_CATALOG;
+
+    fn catalog() -> UnitCatalog {
+        UnitCatalog::from_json(DEFAULT_UNIT_CATALOG).unwrap().0
+    }
+
+    #[test]
+    fn units_can_only_be_placed_on_free_hexes() {
+        let catalog = catalog();
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        spawn_grid(&mut world, 1);
+
+        assert!(place_unit(
+            &mut world,
+            &mut ids,
+            &catalog,
+            &Hexagon::zero(),
+            0,
+            "Tank",
+            false
+        ));
+        assert!(!place_unit(
+            &mut world,
+            &mut ids,
+            &catalog,
+            &Hexagon::zero(),
+            1,
+            "Tank",
+            false
+        ));
+        assert!(!place_unit(
+            &mut world,
+            &mut ids,
+            &catalog,
+            &Hexagon::new_axial(5, 0),
+            0,
+            "Tank",
+            false
+        ));
+        assert!(!place_unit(
+            &mut world,
+            &mut ids,
+            &catalog,
+            &Hexagon::new_axial(1, 0),
+            0,
+            "Dragon",
+            false
+        ));
+    }
+
+    #[test]
+    fn removing_a_hex_removes_its_unit() {
+        let catalog = catalog();
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        spawn_grid(&mut world, 1);
+        place_unit(
+            &mut world,
+            &mut ids,
+            &catalog,
+            &Hexagon::zero(),
+            0,
+            "Tank",
+            false,
+        );
+
+        assert!(remove_hex(&mut world, &Hexagon::zero()));
+
+        assert!(find_unit(&world, &Hexagon::zero()).is_none());
+        assert!(!remove_hex(&mut world, &Hexagon::zero()));
+    }
+
+    #[test]
+    fn editor_changes_survive_export_and_load() {
+        let catalog = catalog();
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        spawn_grid(&mut world, 1);
+        assert!(set_terrain(&mut world, &Hexagon::zero(), Terrain::Forest));
+        assert!(remove_hex(&mut world, &Hexagon::new_axial(1, 0)));
+        assert!(add_hex(
+            &mut world,
+            &Hexagon::new_axial(3, 0),
+            Terrain::Water
+        ));
+        assert!(place_unit(
+            &mut world,
+            &mut ids,
+            &catalog,
+            &Hexagon::new_axial(0, 1),
+            1,
+            "Artillery",
+            false
+        ));
+        assert!(place_unit(
+            &mut world,
+            &mut ids,
+            &catalog,
+            &Hexagon::zero(),
+            0,
+            "Tank",
+            false
+        ));
+        assert!(remove_unit(&mut world, &Hexagon::new_axial(0, 1)));
+        assert!(place_pickup(
+            &mut world,
+            &Hexagon::zero(),
+            Pickup::Resources(25),
+            false
+        ));
+        assert!(!place_pickup(
+            &mut world,
+            &Hexagon::zero(),
+            Pickup::AbilityCharge,
+            false
+        ));
+        assert!(place_building(
+            &mut world,
+            &Hexagon::zero(),
+            Building::new(12),
+            false
+        ));
+        assert!(!place_building(
+            &mut world,
+            &Hexagon::new_axial(1, 0),
+            Building::new(12),
+            false
+        ));
+        let mut edges = EdgeData::default();
+        assert!(set_edge(
+            &world,
+            &mut edges,
+            Hexagon::zero(),
+            Hexagon::new_axial(0, 1),
+            Some(EdgeKind::River)
+        ));
+        assert!(!set_edge(
+            &world,
+            &mut edges,
+            Hexagon::zero(),
+            Hexagon::new_axial(1, 0),
+            Some(EdgeKind::Cliff)
+        ));
+
+        let mut exported = Scenario::from_world(&world);
+        exported.edges = edges.to_list();
+        let json = exported.to_json().unwrap();
+        let mut loaded_world = World::default();
+        spawn_grid(&mut loaded_world, 2);
+        let scenario = Scenario::from_json(&json).unwrap();
+        let missing = scenario.load(&mut loaded_world, &mut ids, &catalog, &[], false);
+
+        assert!(missing.is_empty());
+        let mut reexported = Scenario::from_world(&loaded_world);
+        reexported.edges = EdgeData::from_list(&scenario.edges).to_list();
+        assert_eq!(reexported, scenario);
+        assert_eq!(
+            EdgeData::from_list(&scenario.edges).get(&Hexagon::new_axial(0, 1), &Hexagon::zero()),
+            Some(EdgeKind::River)
+        );
+        assert_eq!(scenario.units.len(), 1);
+        assert_eq!(scenario.units[0].unit_type, "Tank");
+        assert_eq!(scenario.pickups.len(), 1);
+        assert_eq!(scenario.pickups[0].pickup, Pickup::Resources(25));
+        assert_eq!(scenario.buildings.len(), 1);
+        assert_eq!(scenario.buildings[0].building, Building::new(12));
+        let terrain_at = |hexagon: Hexagon| {
+            scenario
+                .fields
+                .iter()
+                .find(|field| field.position == hexagon)
+                .map(|field| field.terrain)
+        };
+        assert_eq!(terrain_at(Hexagon::zero()), Some(Terrain::Forest));
+        assert_eq!(terrain_at(Hexagon::new_axial(1, 0)), None);
+        assert_eq!(terrain_at(Hexagon::new_axial(3, 0)), Some(Terrain::Water));
+    }
+}
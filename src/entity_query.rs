@@ -0,0 +1,359 @@
+//! Read only queries over the world for the scripts. A filter is a dictionary whose keys each
+//! narrow down the result, every key is checked on its own and an entity has to pass all of them:
+//!
+//! - `player`: index of the owner
+//! - `has_unit`: whether the entity is a unit
+//! - `min_integrity`: units with at least this integrity
+//! - `within_range_of`: `{q, r, range}`, entities at most `range` hexagons away
+//! - `terrain`: name of the terrain the entity stands on
+//!
+//! Filters arrive as JSON, so malformed ones are reported instead of trusted.
+
+use crate::components::field::Field;
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::PersistentId;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::terrain::Terrain;
+use crate::components::unit::Unit;
+use crate::components::unit_name::UnitName;
+use crate::godot_convert::Fields;
+use gdnative::prelude::*;
+use legion::{EntityStore, IntoQuery};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    /// The filter is no dictionary.
+    NotADictionary,
+    UnknownKey(String),
+    /// The value of the key has the wrong type or is out of range.
+    InvalidValue(String),
+}
+
+impl FilterError {
+    pub fn description(&self) -> String {
+        match self {
+            FilterError::NotADictionary => "The filter has to be a dictionary".to_owned(),
+            FilterError::UnknownKey(key) => format!("Unknown filter key '{}'", key),
+            FilterError::InvalidValue(key) => format!("Invalid value for filter key '{}'", key),
+        }
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("error", self.description());
+        dict
+    }
+}
+
+/// One condition of a filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Player(usize),
+    HasUnit(bool),
+    MinIntegrity(i32),
+    WithinRangeOf(Hexagon, i32),
+    Terrain(Terrain),
+}
+
+/// An entity with a position, as a filter sees it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityRow {
+    pub id: Option<PersistentId>,
+    pub player: Option<usize>,
+    pub position: Hexagon,
+    pub unit: Option<Unit>,
+    pub name: Option<String>,
+}
+
+impl EntityRow {
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("id", self.id.map_or(-1, |id| id.0 as i64));
+        dict.insert("player", self.player.map_or(-1, |player| player as i64));
+        dict.insert("q", self.position.get_q());
+        dict.insert("r", self.position.get_r());
+        if let Some(unit) = self.unit {
+            dict.insert("unit", Fields::from(&unit).to_dictionary().into_shared());
+        }
+        if let Some(name) = &self.name {
+            dict.insert("name", name.as_str());
+        }
+        dict
+    }
+}
+
+impl Condition {
+    fn matches(&self, row: &EntityRow, terrain: &HashMap<Hexagon, Terrain>) -> bool {
+        match self {
+            Condition::Player(player) => row.player == Some(*player),
+            Condition::HasUnit(has_unit) => row.unit.is_some() == *has_unit,
+            Condition::MinIntegrity(integrity) => {
+                row.unit.map_or(false, |unit| unit.integrity >= *integrity)
+            }
+            Condition::WithinRangeOf(centre, range) => centre.distance_to(&row.position) <= *range,
+            Condition::Terrain(expected) => terrain.get(&row.position) == Some(expected),
+        }
+    }
+}
+
+/// The conditions of a filter, an empty filter lets every entity pass.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EntityFilter {
+    conditions: Vec<Condition>,
+}
+
+fn integer(key: &str, value: &Value) -> Result<i64, FilterError> {
+    value
+        .as_i64()
+        .or_else(|| {
+            // Scripts send every number as a float.
+            value
+                .as_f64()
+                .filter(|number| number.fract() == 0.0 && number.abs() < i32::MAX as f64)
+                .map(|number| number as i64)
+        })
+        .ok_or_else(|| FilterError::InvalidValue(key.to_owned()))
+}
+
+fn bounded(key: &str, value: &Value, min: i64) -> Result<i32, FilterError> {
+    let number = integer(key, value)?;
+    if number < min || number > i64::from(i32::MAX) {
+        return Err(FilterError::InvalidValue(key.to_owned()));
+    }
+    Ok(number as i32)
+}
+
+impl EntityFilter {
+    pub fn from_json(filter: &Value) -> Result<Self, FilterError> {
+        let entries = filter.as_object().ok_or(FilterError::NotADictionary)?;
+        let mut keys: Vec<&String> = entries.keys().collect();
+        keys.sort();
+        let mut conditions = Vec::new();
+        for key in keys {
+            conditions.push(Self::condition(key, &entries[key])?);
+        }
+        Ok(EntityFilter { conditions })
+    }
+
+    fn condition(key: &str, value: &Value) -> Result<Condition, FilterError> {
+        let invalid = || FilterError::InvalidValue(key.to_owned());
+        match key {
+            "player" => Ok(Condition::Player(bounded(key, value, 0)? as usize)),
+            "has_unit" => Ok(Condition::HasUnit(value.as_bool().ok_or_else(invalid)?)),
+            "min_integrity" => Ok(Condition::MinIntegrity(bounded(
+                key,
+                value,
+                i64::from(i32::MIN),
+            )?)),
+            "within_range_of" => {
+                let area: &Map<String, Value> = value.as_object().ok_or_else(invalid)?;
+                let field = |name: &str, min: i64| match area.get(name) {
+                    None => Err(invalid()),
+                    Some(value) => bounded(key, value, min),
+                };
+                if area
+                    .keys()
+                    .any(|name| !["q", "r", "range"].contains(&name.as_str()))
+                {
+                    return Err(invalid());
+                }
+                let centre = Hexagon::new_axial(
+                    field("q", i64::from(i32::MIN))?,
+                    field("r", i64::from(i32::MIN))?,
+                );
+                Ok(Condition::WithinRangeOf(centre, field("range", 0)?))
+            }
+            "terrain" => Ok(Condition::Terrain(
+                value
+                    .as_str()
+                    .and_then(Terrain::from_name)
+                    .ok_or_else(invalid)?,
+            )),
+            _ => Err(FilterError::UnknownKey(key.to_owned())),
+        }
+    }
+
+    pub fn matches(&self, row: &EntityRow, terrain: &HashMap<Hexagon, Terrain>) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(row, terrain))
+    }
+}
+
+/// Every entity with a position that passes the filter, ordered by persistent id and entities
+/// without one last, by position.
+pub fn query_entities<S: EntityStore>(world: &S, filter: &EntityFilter) -> Vec<EntityRow> {
+    let terrain: HashMap<Hexagon, Terrain> = <&Field>::query()
+        .iter(world)
+        .map(|field| (field.location, field.terrain))
+        .collect();
+    let mut rows: Vec<EntityRow> = <(
+        &Hexagon,
+        Option<&PersistentId>,
+        Option<&PlayerComponent>,
+        Option<&Unit>,
+        Option<&UnitName>,
+    )>::query()
+    .iter(world)
+    .map(|(position, id, player, unit, name)| EntityRow {
+        id: id.copied(),
+        player: player.map(|player| player.0),
+        position: *position,
+        unit: unit.copied(),
+        name: name.map(|name| name.0.clone()),
+    })
+    .filter(|row| filter.matches(row, &terrain))
+    .collect();
+    rows.sort_by_key(|row| {
+        (
+            row.id.is_none(),
+            row.id,
+            row.position.get_q(),
+            row.position.get_r(),
+        )
+    });
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::persistent_id::PersistentIds;
+    use crate::components::pickup::Pickup;
+    use crate::pickups::spawn_pickup;
+    use crate::spawn::spawn_unit;
+    use legion::World;
+    use serde_json::json;
+
+    fn setup() -> World {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        for q in -3..=3 {
+            let mut field = Field::new(Hexagon::new_axial(q, 0));
+            if q > 0 {
+                field.terrain = Terrain::Forest;
+            }
+            world.push((field,));
+        }
+        for (player, q, integrity) in [(0, -2, 10), (0, 0, 4), (1, 2, 8)].iter() {
+            spawn_unit(
+                &mut world,
+                &mut ids,
+                *player,
+                Hexagon::new_axial(*q, 0),
+                Unit::new(*integrity, 1, 1, 1, 0, 1, 1, 1),
+                None,
+            );
+        }
+        spawn_pickup(
+            &mut world,
+            Hexagon::new_axial(3, 0),
+            Pickup::Repair(1),
+            false,
+        );
+        world
+    }
+
+    fn positions(world: &World, filter: Value) -> Vec<i32> {
+        let filter = EntityFilter::from_json(&filter).unwrap();
+        query_entities(world, &filter)
+            .iter()
+            .map(|row| row.position.get_q())
+            .collect()
+    }
+
+    #[test]
+    fn every_key_narrows_the_result() {
+        let world = setup();
+
+        assert_eq!(positions(&world, json!({})), vec![-2, 0, 2, 3]);
+        assert_eq!(positions(&world, json!({"player": 0})), vec![-2, 0]);
+        assert_eq!(positions(&world, json!({"player": 1.0})), vec![2]);
+        assert_eq!(positions(&world, json!({"has_unit": false})), vec![3]);
+        assert_eq!(positions(&world, json!({"min_integrity": 8})), vec![-2, 2]);
+        assert_eq!(
+            positions(
+                &world,
+                json!({"within_range_of": {"q": 1, "r": 0, "range": 1}})
+            ),
+            vec![0, 2]
+        );
+        assert_eq!(positions(&world, json!({"terrain": "forest"})), vec![2, 3]);
+    }
+
+    #[test]
+    fn keys_combine() {
+        let world = setup();
+
+        assert_eq!(
+            positions(&world, json!({"player": 0, "min_integrity": 5})),
+            vec![-2]
+        );
+        assert_eq!(
+            positions(
+                &world,
+                json!({"terrain": "forest", "has_unit": true, "within_range_of": {"q": 3, "r": 0, "range": 1}})
+            ),
+            vec![2]
+        );
+        assert!(positions(&world, json!({"player": 1, "terrain": "plains"})).is_empty());
+    }
+
+    #[test]
+    fn rows_carry_ids_and_stats() {
+        let world = setup();
+        let filter = EntityFilter::from_json(&json!({"player": 1})).unwrap();
+        let rows = query_entities(&world, &filter);
+
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].id.is_some());
+        assert_eq!(rows[0].unit.map(|unit| unit.integrity), Some(8));
+    }
+
+    #[test]
+    fn malformed_filters_are_rejected() {
+        let invalid = |key: &str| Err(FilterError::InvalidValue(key.to_owned()));
+
+        assert_eq!(
+            EntityFilter::from_json(&json!([1, 2])),
+            Err(FilterError::NotADictionary)
+        );
+        assert_eq!(
+            EntityFilter::from_json(&json!({"owner": 0})),
+            Err(FilterError::UnknownKey("owner".to_owned()))
+        );
+        assert_eq!(
+            EntityFilter::from_json(&json!({"player": -1})),
+            invalid("player")
+        );
+        assert_eq!(
+            EntityFilter::from_json(&json!({"player": 0.5})),
+            invalid("player")
+        );
+        assert_eq!(
+            EntityFilter::from_json(&json!({"has_unit": "yes"})),
+            invalid("has_unit")
+        );
+        assert_eq!(
+            EntityFilter::from_json(&json!({"min_integrity": "high"})),
+            invalid("min_integrity")
+        );
+        assert_eq!(
+            EntityFilter::from_json(&json!({"within_range_of": {"q": 0, "r": 0}})),
+            invalid("within_range_of")
+        );
+        assert_eq!(
+            EntityFilter::from_json(&json!({"within_range_of": {"q": 0, "r": 0, "range": -1}})),
+            invalid("within_range_of")
+        );
+        assert_eq!(
+            EntityFilter::from_json(&json!({"terrain": "lava"})),
+            invalid("terrain")
+        );
+        assert!(FilterError::UnknownKey("owner".to_owned())
+            .description()
+            .contains("owner"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Attacks on the side or the back of a unit deal more damage. Which side is hit follows from the
+//! facing of the defender and the direction the attack comes from, so keeping units in a line
+//! with their fronts to the enemy pays off. Units that never moved or turned have no facing yet
+//! and are always hit in the front.
+
+use crate::combat_context::{combat_context, CombatContext};
+use crate::components::facing::Facing;
+use crate::components::hexagon::{Direction, Hexagon};
+use crate::components::unit::Unit;
+use crate::godot_convert::Fields;
+use crate::promotions::perks_of;
+use crate::rules::Ruleset;
+use legion::{Entity, EntityStore};
+use serde::{Deserialize, Serialize};
+
+/// Extra damage, in percent, of attacks on the two directions next to the front.
+pub const FLANK_BONUS_PERCENT: i32 = 10;
+
+/// Extra damage, in percent, of attacks on the back and the two directions next to it.
+pub const REAR_BONUS_PERCENT: i32 = 25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttackArc {
+    Front,
+    Flank,
+    Rear,
+}
+
+impl Default for AttackArc {
+    fn default() -> Self {
+        AttackArc::Front
+    }
+}
+
+impl AttackArc {
+    /// The arc a defender facing `facing` is hit in by an attack from `towards_attacker`.
+    pub fn classify(facing: Direction, towards_attacker: Direction) -> Self {
+        match facing.turns_to(towards_attacker) {
+            0 => AttackArc::Front,
+            1 | 5 => AttackArc::Flank,
+            _ => AttackArc::Rear,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AttackArc::Front => "front",
+            AttackArc::Flank => "flank",
+            AttackArc::Rear => "rear",
+        }
+    }
+
+    pub fn bonus_percent(&self) -> i32 {
+        match self {
+            AttackArc::Front => 0,
+            AttackArc::Flank => FLANK_BONUS_PERCENT,
+            AttackArc::Rear => REAR_BONUS_PERCENT,
+        }
+    }
+
+    /// `damage` with the bonus of the arc, rounded to the nearest point. Attacks that deal no
+    /// damage stay harmless.
+    pub fn apply(&self, damage: i32) -> i32 {
+        if damage <= 0 {
+            return damage;
+        }
+        (damage * (100 + self.bonus_percent()) + 50) / 100
+    }
+}
+
+/// The arc of the defender an attack from `attacker_position` hits. Defenders without a facing
+/// or a position are hit in the front.
+pub fn attack_arc<S: EntityStore>(
+    world: &S,
+    attacker_position: &Hexagon,
+    defender: Entity,
+) -> AttackArc {
+    let entry = match world.entry_ref(defender) {
+        Err(_) => return AttackArc::Front,
+        Ok(entry) => entry,
+    };
+    match (
+        entry.get_component::<Facing>(),
+        entry.get_component::<Hexagon>(),
+    ) {
+        (Ok(facing), Ok(position)) => position
+            .direction_to(attacker_position)
+            .map_or(AttackArc::Front, |towards_attacker| {
+                AttackArc::classify(facing.0, towards_attacker)
+            }),
+        _ => AttackArc::Front,
+    }
+}
+
+/// What an attack would do right now, without checking range or sight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttackPreview {
+    pub damage: i32,
+    pub context: CombatContext,
+    pub remaining_integrity: i32,
+}
+
+impl AttackPreview {
+    pub fn to_fields(&self) -> Fields {
+        Fields::new()
+            .with("damage", self.damage)
+            .with("arc", self.context.arc.name())
+            .with("bonus_percent", self.context.arc.bonus_percent())
+            .with("cover", self.context.cover)
+            .with("remaining_integrity", self.remaining_integrity)
+    }
+}
+
+/// The preview of `attacker` attacking `defender`, `None` if either is no unit on the map or the
+/// attacker cannot attack now.
+pub fn attack_preview<S: EntityStore>(
+    world: &S,
+    rules: &Ruleset,
+    attacker: Entity,
+    defender: Entity,
+) -> Option<AttackPreview> {
+    let unit_and_position = |entity: Entity| {
+        let entry = world.entry_ref(entity).ok()?;
+        Some((
+            *entry.get_component::<Unit>().ok()?,
+            *entry.get_component::<Hexagon>().ok()?,
+        ))
+    };
+    let (attacking_unit, attacker_position) = unit_and_position(attacker)?;
+    let (defending_unit, _) = unit_and_position(defender)?;
+    let context = combat_context(world, &attacker_position, defender);
+    let result = perks_of(world, attacker)
+        .strengthen(attacking_unit)
+        .attack(&defending_unit, context, rules)
+        .ok()?;
+    Some(AttackPreview {
+        damage: result.actual_damage,
+        context,
+        remaining_integrity: result.defender.integrity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion::World;
+
+    #[test]
+    fn every_pair_of_directions_has_an_arc() {
+        for (facing_index, facing) in Direction::ALL.iter().enumerate() {
+            for (attack_index, towards_attacker) in Direction::ALL.iter().enumerate() {
+                let expected = match (attack_index + 6 - facing_index) % 6 {
+                    0 => AttackArc::Front,
+                    1 | 5 => AttackArc::Flank,
+                    2 | 3 | 4 => AttackArc::Rear,
+                    _ => unreachable!(),
+                };
+                assert_eq!(
+                    AttackArc::classify(*facing, *towards_attacker),
+                    expected,
+                    "{:?} attacked from {:?}",
+                    facing,
+                    towards_attacker
+                );
+            }
+        }
+        assert_eq!(
+            AttackArc::classify(Direction::East, Direction::West),
+            AttackArc::Rear
+        );
+        assert_eq!(
+            AttackArc::classify(Direction::NorthEast, Direction::East),
+            AttackArc::Flank
+        );
+    }
+
+    #[test]
+    fn bonus_damage_is_rounded() {
+        assert_eq!(AttackArc::Front.apply(7), 7);
+        assert_eq!(AttackArc::Flank.apply(7), 8);
+        assert_eq!(AttackArc::Rear.apply(7), 9);
+        assert_eq!(AttackArc::Flank.apply(4), 4);
+        assert_eq!(AttackArc::Rear.apply(4), 5);
+        assert_eq!(AttackArc::Rear.apply(20), 25);
+        assert_eq!(AttackArc::Rear.apply(0), 0);
+    }
+
+    #[test]
+    fn previews_use_the_facing_of_the_defender() {
+        let mut world = World::default();
+        let rules = Ruleset::default();
+        let attacker = world.push((Hexagon::zero(), Unit::new(10, 4, 1, 1, 0, 1, 1, 1)));
+        let defender = world.push((Hexagon::new_axial(1, 0), Unit::new(10, 1, 1, 1, 0, 1, 1, 1)));
+        let unaligned = attack_preview(&world, &rules, attacker, defender).unwrap();
+        let turn = |world: &mut World, direction| {
+            world
+                .entry(defender)
+                .unwrap()
+                .add_component(Facing(direction));
+            attack_preview(&*world, &rules, attacker, defender).unwrap()
+        };
+        let front = turn(&mut world, Direction::West);
+        let flank = turn(&mut world, Direction::SouthWest);
+        let rear = turn(&mut world, Direction::East);
+
+        assert_eq!(unaligned, front);
+        assert_eq!(front.context.arc, AttackArc::Front);
+        assert_eq!(flank.context.arc, AttackArc::Flank);
+        assert_eq!(rear.context.arc, AttackArc::Rear);
+        assert_eq!(rear.damage, AttackArc::Rear.apply(front.damage));
+        assert_eq!(rear.remaining_integrity, 10 - rear.damage);
+    }
+}
@@ -0,0 +1,150 @@
+//! Where the camera should look: the unit that is acting, the selection or the last fight. A
+//! script moves the camera there whenever the focus jumps, small steps of a moving unit are
+//! collected until they add up to a hexagon.
+
+use crate::components::hexagon::Hexagon;
+use crate::game_state::{GameState, State};
+use crate::state_machine::SECONDS_PER_MOVEMENT;
+use crate::systems::hexgrid::get_2d_position_from_hex;
+use gdnative::core_types::Vector2;
+use legion::{Entity, EntityStore};
+
+fn position_of<S: EntityStore>(world: &S, entity: Entity) -> Option<Hexagon> {
+    world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<Hexagon>().ok().copied())
+}
+
+/// The position the camera should show. A moving unit comes first, it is followed between
+/// hexagons. Then the fight being resolved, the selected unit and finally the last fight.
+pub fn focus_position<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    hexfield_size: f32,
+) -> Option<Vector2> {
+    let to_position = |hexagon: Hexagon| get_2d_position_from_hex(&hexagon, hexfield_size);
+    let focus = match &state.state {
+        State::Moving(entity) => position_of(world, *entity).map(|hexagon| {
+            let from = to_position(hexagon);
+            match state.movement.path.front() {
+                None => from,
+                Some(next) => {
+                    let progress = (state.movement.elapsed / SECONDS_PER_MOVEMENT).min(1.0) as f32;
+                    from + (to_position(*next) - from) * progress
+                }
+            }
+        }),
+        State::Attacking(attacker, defender) => {
+            match (position_of(world, *attacker), position_of(world, *defender)) {
+                (Some(attacker), Some(defender)) => {
+                    Some((to_position(attacker) + to_position(defender)) / 2.0)
+                }
+                (attacker, defender) => attacker.or(defender).map(to_position),
+            }
+        }
+        State::Selected(entity) => position_of(world, *entity).map(to_position),
+        State::GroupSelected(members) => members
+            .first()
+            .and_then(|entity| position_of(world, *entity))
+            .map(to_position),
+        _ => None,
+    };
+    focus.or_else(|| state.last_combat.map(to_position))
+}
+
+/// Remembers where the camera was last sent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FocusTracker {
+    last: Option<Vector2>,
+}
+
+impl FocusTracker {
+    pub fn last(&self) -> Option<Vector2> {
+        self.last
+    }
+
+    /// Remembers that the camera was sent to `position` outside of `update`.
+    pub fn jump_to(&mut self, position: Vector2) {
+        self.last = Some(position);
+    }
+
+    /// Takes the current focus and returns whether it moved further than one hexagon from where
+    /// the camera was last sent, which is then the new position.
+    pub fn update(&mut self, focus: Option<Vector2>, hexfield_size: f32) -> bool {
+        let focus = match focus {
+            None => return false,
+            Some(focus) => focus,
+        };
+        let hexagon_width = 3.0_f32.sqrt() * hexfield_size;
+        match self.last {
+            Some(last) if (focus - last).length() <= hexagon_width => false,
+            _ => {
+                self.last = Some(focus);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::unit::Unit;
+    use crate::game_state::force_move;
+    use legion::World;
+    use std::collections::vec_deque::VecDeque;
+
+    const SIZE: f32 = 40.0;
+
+    fn spawn(world: &mut World, q: i32, r: i32) -> Entity {
+        world.push((Hexagon::new_axial(q, r), Unit::new(1, 1, 1, 1, 0, 1, 1, 1)))
+    }
+
+    fn centre(q: i32, r: i32) -> Vector2 {
+        get_2d_position_from_hex(&Hexagon::new_axial(q, r), SIZE)
+    }
+
+    fn assert_near(focus: Option<Vector2>, expected: Vector2) {
+        let focus = focus.unwrap();
+        assert!((focus - expected).length() < 0.01, "{:?}", focus);
+    }
+
+    #[test]
+    fn acting_units_come_before_the_selection_and_the_last_fight() {
+        let mut world = World::default();
+        let mover = spawn(&mut world, 0, 0);
+        let attacker = spawn(&mut world, 3, 0);
+        let defender = spawn(&mut world, 5, 0);
+        let mut state = GameState::new();
+        state.last_combat = Some(Hexagon::new_axial(-4, 2));
+
+        state.state = State::Waiting;
+        assert_near(focus_position(&world, &state, SIZE), centre(-4, 2));
+
+        state.state = State::Selected(attacker);
+        assert_near(focus_position(&world, &state, SIZE), centre(3, 0));
+
+        state.state = State::Attacking(attacker, defender);
+        assert_near(focus_position(&world, &state, SIZE), centre(4, 0));
+
+        let path: VecDeque<Hexagon> = vec![Hexagon::new_axial(1, 0)].into();
+        force_move(&mut state, mover, path);
+        state.movement.elapsed = SECONDS_PER_MOVEMENT / 2.0;
+        let halfway = (centre(0, 0) + centre(1, 0)) / 2.0;
+        assert_near(focus_position(&world, &state, SIZE), halfway);
+    }
+
+    #[test]
+    fn focus_changes_only_after_a_jump() {
+        let mut tracker = FocusTracker::default();
+
+        assert!(!tracker.update(None, SIZE));
+        assert!(tracker.update(Some(centre(0, 0)), SIZE));
+        // A unit halfway to the next hexagon does not move the camera yet.
+        let halfway = (centre(0, 0) + centre(1, 0)) / 2.0;
+        assert!(!tracker.update(Some(halfway), SIZE));
+        assert!(tracker.update(Some(centre(2, 0)), SIZE));
+        assert_eq!(tracker.last(), Some(centre(2, 0)));
+    }
+}
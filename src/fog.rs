@@ -0,0 +1,288 @@
+//! Fog of war. Players see the hexagons around their units. Enemies that disappear from that area
+//! are remembered as ghosts where they were last seen, until that hexagon is looked at again or
+//! the unit shows up somewhere else.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::PersistentId;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::map_bounds::MapBounds;
+use crate::player::Player;
+use crate::rules::Ruleset;
+use crate::systems::hexgrid::{get_hexagons_in_range, is_line_of_sight_clear};
+use legion::{Entity, EntityStore, IntoQuery};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// How many hexagons a unit sees in clear weather.
+pub const VISION_RANGE: i32 = 4;
+
+/// An enemy unit as it was when it was last seen. Ghosts are only drawn, they cannot be attacked
+/// and do not block paths.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ghost {
+    pub hexagon: Hexagon,
+    pub id: Option<PersistentId>,
+    pub player: usize,
+    pub unit: Unit,
+    pub round_seen: u32,
+}
+
+/// What one player has seen.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerMemory {
+    pub ghosts: Vec<Ghost>,
+    /// The hexagons seen in the last update.
+    #[serde(skip)]
+    visible: HashSet<Hexagon>,
+    /// The enemies seen in the last update.
+    #[serde(skip)]
+    seen: HashMap<Entity, Ghost>,
+}
+
+/// The memories of all players, by player index.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LastSeen {
+    players: Vec<PlayerMemory>,
+}
+
+impl LastSeen {
+    /// Looks at the world with the eyes of every player that is not an observer. Enemies that left
+    /// the view of a player become ghosts, ghosts on hexagons that came into view or of units that
+    /// were seen again are dropped.
+    pub fn update<S: EntityStore>(
+        &mut self,
+        world: &S,
+        players: &[Player],
+        rules: &Ruleset,
+        bounds: &MapBounds,
+        range: i32,
+        round: u32,
+    ) {
+        self.players.resize_with(players.len(), Default::default);
+        for (player, memory) in self.players.iter_mut().enumerate() {
+            if !players[player].is_observer() {
+                memory.update(world, player, rules, bounds, range, round);
+            }
+        }
+    }
+
+    pub fn ghosts(&self, player: usize) -> &[Ghost] {
+        self.players
+            .get(player)
+            .map(|memory| memory.ghosts.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Whether units of `owner` on `hexagon` are hidden from `viewer`. Own units are always shown.
+    pub fn hides(&self, viewer: usize, owner: usize, hexagon: &Hexagon) -> bool {
+        viewer != owner && !self.sees(viewer, hexagon)
+    }
+
+    /// Whether `hexagon` was in view of `viewer` in the last update. Players that never looked
+    /// see everything.
+    pub fn sees(&self, viewer: usize, hexagon: &Hexagon) -> bool {
+        self.players
+            .get(viewer)
+            .map_or(true, |memory| memory.visible.contains(hexagon))
+    }
+}
+
+impl PlayerMemory {
+    fn update<S: EntityStore>(
+        &mut self,
+        world: &S,
+        player: usize,
+        rules: &Ruleset,
+        bounds: &MapBounds,
+        range: i32,
+        round: u32,
+    ) {
+        let visible = visible_hexagons(world, player, rules, bounds, range);
+        let seen: HashMap<Entity, Ghost> = <(
+            Entity,
+            &PlayerComponent,
+            &Hexagon,
+            &Unit,
+            Option<&PersistentId>,
+        )>::query()
+        .iter(world)
+        .filter(|(_, owner, hexagon, _, _)| owner.0 != player && visible.contains(*hexagon))
+        .map(|(entity, owner, hexagon, unit, id)| {
+            let ghost = Ghost {
+                hexagon: *hexagon,
+                id: id.copied(),
+                player: owner.0,
+                unit: *unit,
+                round_seen: round,
+            };
+            (*entity, ghost)
+        })
+        .collect();
+
+        let previously_visible = &self.visible;
+        self.ghosts.retain(|ghost| {
+            let observed_again =
+                visible.contains(&ghost.hexagon) && !previously_visible.contains(&ghost.hexagon);
+            let seen_again = ghost.id.is_some() && seen.values().any(|unit| unit.id == ghost.id);
+            !observed_again && !seen_again
+        });
+        let mut lost: Vec<Ghost> = self
+            .seen
+            .drain()
+            .filter(|(entity, last)| {
+                // A unit that was destroyed in plain view is not remembered.
+                let destroyed_in_view =
+                    world.entry_ref(*entity).is_err() && visible.contains(&last.hexagon);
+                !seen.contains_key(entity) && !destroyed_in_view
+            })
+            .map(|(_, last)| last)
+            .collect();
+        // The map hands them out in any order, saves should not depend on it.
+        lost.sort_by_key(|ghost| (ghost.id, ghost.hexagon));
+        self.ghosts.extend(lost);
+        self.visible = visible;
+        self.seen = seen;
+    }
+}
+
+/// The hexagons the units of `player` can see up to `range` hexagons away. With the line of sight
+/// rule, other units and smoke block the view.
+pub fn visible_hexagons<S: EntityStore>(
+    world: &S,
+    player: usize,
+    rules: &Ruleset,
+    bounds: &MapBounds,
+    range: i32,
+) -> HashSet<Hexagon> {
+    let mut visible = HashSet::new();
+    for (owner, position, _) in <(&PlayerComponent, &Hexagon, &Unit)>::query().iter(world) {
+        if owner.0 != player {
+            continue;
+        }
+        for hexagon in get_hexagons_in_range(position, 0, range) {
+            if !rules.line_of_sight || is_line_of_sight_clear(position, &hexagon, bounds, world) {
+                visible.insert(hexagon);
+            }
+        }
+    }
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gdnative::core_types::Color;
+    use legion::World;
+
+    fn players() -> Vec<Player> {
+        vec![
+            Player::new("Player 1".to_owned(), Color::rgb(0.0, 0.0, 1.0)),
+            Player::new("Player 2".to_owned(), Color::rgb(1.0, 0.0, 0.0)),
+        ]
+    }
+
+    fn spawn(world: &mut World, player: usize, q: i32, id: u64) -> Entity {
+        world.push((
+            PlayerComponent(player),
+            Hexagon::new_axial(q, 0),
+            Unit::new(5, 1, 1, 1, 0, 3, 3, 1),
+            PersistentId(id),
+        ))
+    }
+
+    fn move_to(world: &mut World, entity: Entity, q: i32) {
+        world
+            .entry(entity)
+            .unwrap()
+            .add_component(Hexagon::new_axial(q, 0));
+    }
+
+    #[test]
+    fn enemies_leaving_the_view_leave_a_ghost() {
+        let mut world = World::default();
+        let (players, rules) = (players(), Ruleset::default());
+        let bounds = MapBounds::default();
+        spawn(&mut world, 0, 0, 1);
+        let enemy = spawn(&mut world, 1, 3, 2);
+        let mut last_seen = LastSeen::default();
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 1);
+        assert!(last_seen.ghosts(0).is_empty());
+        assert!(!last_seen.hides(0, 1, &Hexagon::new_axial(3, 0)));
+
+        move_to(&mut world, enemy, 8);
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 2);
+
+        let ghosts = last_seen.ghosts(0);
+        assert_eq!(ghosts.len(), 1);
+        assert_eq!(ghosts[0].hexagon, Hexagon::new_axial(3, 0));
+        assert_eq!(ghosts[0].id, Some(PersistentId(2)));
+        assert_eq!(ghosts[0].round_seen, 1);
+        assert!(last_seen.hides(0, 1, &Hexagon::new_axial(8, 0)));
+        assert!(!last_seen.sees(0, &Hexagon::new_axial(8, 0)));
+        assert!(last_seen.sees(0, &Hexagon::new_axial(3, 0)));
+    }
+
+    #[test]
+    fn ghosts_are_cleared_when_their_hexagon_is_seen_again() {
+        let mut world = World::default();
+        let (players, rules) = (players(), Ruleset::default());
+        let bounds = MapBounds::default();
+        let scout = spawn(&mut world, 0, 0, 1);
+        let enemy = spawn(&mut world, 1, 3, 2);
+        let mut last_seen = LastSeen::default();
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 1);
+
+        // The scout walks away, the enemy stays where it was.
+        move_to(&mut world, scout, -5);
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 1);
+        assert_eq!(last_seen.ghosts(0).len(), 1);
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 1);
+        assert_eq!(last_seen.ghosts(0).len(), 1);
+
+        // Meanwhile the enemy left unseen, the scout only finds the hexagon empty.
+        move_to(&mut world, enemy, 12);
+        move_to(&mut world, scout, 0);
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 2);
+        assert!(last_seen.ghosts(0).is_empty());
+    }
+
+    #[test]
+    fn ghosts_follow_units_seen_elsewhere_and_ignore_destroyed_ones() {
+        let mut world = World::default();
+        let (players, rules) = (players(), Ruleset::default());
+        let bounds = MapBounds::default();
+        spawn(&mut world, 0, 0, 1);
+        let enemy = spawn(&mut world, 1, 3, 2);
+        let doomed = spawn(&mut world, 1, 2, 3);
+        let mut last_seen = LastSeen::default();
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 1);
+
+        world.remove(doomed);
+        move_to(&mut world, enemy, 9);
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 1);
+        assert_eq!(last_seen.ghosts(0).len(), 1);
+
+        move_to(&mut world, enemy, -4);
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 2);
+        assert!(last_seen.ghosts(0).is_empty());
+    }
+
+    #[test]
+    fn players_never_see_ghosts_of_their_own_units() {
+        let mut world = World::default();
+        let (players, rules) = (players(), Ruleset::default());
+        let bounds = MapBounds::default();
+        let own = spawn(&mut world, 0, 0, 1);
+        spawn(&mut world, 0, 1, 2);
+        spawn(&mut world, 1, 20, 3);
+        let mut last_seen = LastSeen::default();
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 1);
+
+        move_to(&mut world, own, 10);
+        last_seen.update(&world, &players, &rules, &bounds, VISION_RANGE, 1);
+
+        assert!(last_seen.ghosts(0).is_empty());
+        assert!(!last_seen.hides(0, 0, &Hexagon::new_axial(10, 0)));
+    }
+}
@@ -1,19 +1,169 @@
-use crate::components::hexagon::Hexagon;
+use crate::achievements::AchievementTracker;
+use crate::action_log::ActionLog;
+use crate::ai::Suggestion;
+use crate::checksum::StableHasher;
+use crate::combat_feedback::DEFAULT_HITSTOP_CAP;
+use crate::combat_log::CombatLog;
+use crate::components::abilities::{Abilities, BlocksVision, Overdrive};
+use crate::components::facing::Facing;
+use crate::components::garrison::Garrisoned;
+use crate::components::hexagon::{Direction, Hexagon};
+use crate::components::overwatch::Overwatching;
+use crate::components::perks::{Experience, PerkId, Perks};
+use crate::components::persistent_id::{PersistentId, PersistentIds};
+use crate::components::player::Player as PlayerComponent;
+use crate::components::supply::Supply;
+use crate::components::unit::Unit;
+use crate::edges::EdgeData;
+use crate::fog::LastSeen;
+use crate::ground_effects::ground_effects;
+use crate::handicap::{Handicap, HandicapError};
+use crate::hex_cursor::HexCursor;
+use crate::influence::InfluenceMap;
+use crate::initiative::InitiativeQueue;
+use crate::input_buffer::InputBuffer;
+use crate::map_bounds::MapBounds;
+use crate::measurement::Measurement;
+use crate::messages::Message;
+use crate::node_sync::MatchGeneration;
+use crate::palette::{Palette, DEFAULT_PALETTE};
+use crate::ping::Pings;
+use crate::planning::PlannedOrder;
 use crate::player::Player;
-use legion::Entity;
+use crate::random::Rng;
+use crate::roster::Roster;
+use crate::rules::Ruleset;
+use crate::scenario::{scenario_buildings, scenario_pickups, Scenario};
+use crate::spawn::{carve_lake, spawn_grid, spawn_unit_of_type, MAP_RADIUS};
+use crate::statistics::{StatisticsSeries, DEFAULT_STATISTICS_LIMIT};
+use crate::transitions::is_allowed;
+use crate::triggers::Triggers;
+use crate::turn_file::TurnHandoff;
+use crate::tutorial::Tutorial;
+use crate::unit_catalog::{UnitCatalog, DEFAULT_UNIT_CATALOG};
+use crate::unit_names::UnitNames;
+use crate::weather::Weather;
+use gdnative::core_types::{Color, Vector2};
+use legion::{Entity, EntityStore, IntoQuery, World};
 use std::collections::vec_deque::VecDeque;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 
 pub struct GameState {
     pub state: State,
     pub players: Vec<Player>,
     pub current_player: Option<usize>,
+    pub round: u32,
+    /// Moves and attacks every player ordered this round, for the command points rule. Ordered by
+    /// player, as it decides which commands are accepted and goes into the checksum.
+    pub commands_used: BTreeMap<usize, u32>,
     pub current_path: Vec<Hexagon>,
     pub redraw_grid: bool,
     pub red_layer: bool,
     pub green_layer: bool,
     pub blue_layer: bool,
+    /// Draws rivers, bridges and cliffs along the edges of the hexagons.
+    pub edge_layer: bool,
+    /// Tints every hexagon in the colour of the player dominating it.
+    pub influence_layer: bool,
     pub update_fields: bool,
     pub hovered_hexagon: Option<Hexagon>,
+    /// The unit selected last, to pick it up again after it acted.
+    pub last_selected: Option<Entity>,
+    /// Counts the selections made, so a repeated selection of the same unit can be told apart.
+    pub selection_generation: u64,
+    /// Enemy units the selected unit can attack right now.
+    pub attackable_entities: HashSet<Entity>,
+    /// Set when a unit got selected, so `attackable_entities` is computed once the world is
+    /// available.
+    pub refresh_attackable_entities: bool,
+    /// Shown while the measure modifier is held and a unit is selected.
+    pub measurement: Option<Measurement>,
+    pub combat_log: CombatLog,
+    pub achievements: AchievementTracker,
+    pub notifications: Vec<Message>,
+    pub pings: Pings,
+    pub rules: Ruleset,
+    /// Seconds input is paused at the start of a turn, so the next player does not act by
+    /// accident during the handover.
+    pub banner_duration: f64,
+    /// Hides the map between turns until the next player confirms they are at the screen, for
+    /// hotseat games.
+    pub privacy_screen: bool,
+    /// Whether the list of controls is shown, toggled with the help action.
+    pub show_help: bool,
+    /// Set while a turn transition waits for `confirm_player_ready` instead of a timer.
+    pub awaiting_player: bool,
+    /// Name of the colour palette applied to the players.
+    pub palette: String,
+    pub unit_catalog: UnitCatalog,
+    pub persistent_ids: PersistentIds,
+    /// The last click made while an action resolved, replayed afterwards.
+    pub input_buffer: InputBuffer,
+    /// Units of a group move that still have to move, with their destination, in order.
+    pub group_moves: VecDeque<(Entity, Hexagon)>,
+    /// Units of a retreat that still have to move, with their slot at the rally hexagon.
+    pub retreats: VecDeque<(Entity, Hexagon)>,
+    /// Corners of the box being dragged to select units, in view coordinates.
+    pub selection_box: Option<(Vector2, Vector2)>,
+    pub hex_cursor: HexCursor,
+    /// Radius of the map of a new game, the game world can change it for the running one.
+    pub grid_radius: u32,
+    /// Size of the lake carved into the centre of the map of a new game, see `carve_lake`.
+    pub lake_size: u32,
+    /// The hexagons of the running game's map. Refreshed whenever fields are added or removed.
+    pub map_bounds: MapBounds,
+    /// The rosters imported for the next scenario by player, its `from_roster` unit slots are
+    /// filled from them. They are used up when a scenario is loaded.
+    pub rosters: HashMap<usize, Roster>,
+    /// A snapshot of every player at the start of each round, for the graphs after a match.
+    pub statistics: StatisticsSeries,
+    /// Snapshots kept in `statistics` before it is thinned out.
+    pub statistics_limit: usize,
+    /// Bumped whenever the world is emptied for a new match, so the node map drops the world
+    /// events of the old one.
+    pub match_generation: MatchGeneration,
+    /// Where the last attack of the game hit.
+    pub last_combat: Option<Hexagon>,
+    /// Where every player last saw the enemies hidden by the fog of war.
+    pub last_seen: LastSeen,
+    /// Order in which units act with the initiative rules.
+    pub initiative: InitiativeQueue,
+    /// Orders accepted during planning that the state machine has not put on their units yet.
+    pub planned_orders: Vec<PlannedOrder>,
+    /// The rest of the path and the time since the last step while the state is `Moving`.
+    pub movement: MovementProgress,
+    /// Seconds moving units stay frozen after a heavy hit.
+    pub hitstop_timer: f64,
+    /// Upper bound of `hitstop_timer`, so a chain of hits does not stall the game.
+    pub hitstop_cap: f64,
+    /// Set when this session only watches the game, local input then cannot change it.
+    pub observing: bool,
+    /// Players whose commands may be issued from this machine.
+    pub local_players: HashSet<usize>,
+    /// Triggers of the loaded scenario.
+    pub triggers: Triggers,
+    /// Units created during the match get Godot nodes. Headless simulations leave this off.
+    pub spawn_nodes: bool,
+    pub tutorial: Tutorial,
+    /// Draws the random events, seeded so a match can be played again the same way.
+    pub rng: Rng,
+    /// Weather of the current round.
+    pub weather: Weather,
+    /// Rivers, bridges and cliffs between hexagons.
+    pub edges: EdgeData,
+    /// Which player dominates each hexagon, for the influence overlay.
+    pub influence: InfluenceMap,
+    /// How the last order of each unit was scored, for explain_ai_move.
+    pub explanations: HashMap<PersistentId, Suggestion>,
+    /// Hands out the names of new units.
+    pub unit_names: UnitNames,
+    /// The commands of the match so far, for the replay scrubber.
+    pub action_log: ActionLog,
+    /// The turn files of a play-by-email game.
+    pub turn_handoff: TurnHandoff,
+    /// The names of the states left and entered since the game world last announced them.
+    pub state_changes: Vec<(&'static str, &'static str)>,
 }
 
 impl GameState {
@@ -22,23 +172,1726 @@ impl GameState {
             state: State::Startup,
             players: Vec::new(),
             current_player: None,
+            round: 1,
+            commands_used: BTreeMap::new(),
             current_path: Vec::new(),
             redraw_grid: false,
             red_layer: true,
             green_layer: true,
             blue_layer: true,
+            edge_layer: true,
+            influence_layer: false,
             update_fields: false,
             hovered_hexagon: None,
+            last_selected: None,
+            selection_generation: 0,
+            attackable_entities: HashSet::new(),
+            refresh_attackable_entities: false,
+            measurement: None,
+            combat_log: CombatLog::new(),
+            achievements: AchievementTracker::new(),
+            notifications: Vec::new(),
+            pings: Pings::new(),
+            rules: Ruleset::default(),
+            banner_duration: 0.0,
+            privacy_screen: false,
+            show_help: false,
+            awaiting_player: false,
+            palette: DEFAULT_PALETTE.to_owned(),
+            unit_catalog: UnitCatalog::from_json(DEFAULT_UNIT_CATALOG)
+                .map(|(catalog, _)| catalog)
+                .unwrap_or_default(),
+            persistent_ids: PersistentIds::default(),
+            input_buffer: InputBuffer::default(),
+            group_moves: VecDeque::new(),
+            retreats: VecDeque::new(),
+            selection_box: None,
+            hex_cursor: HexCursor::new(MAP_RADIUS),
+            grid_radius: MAP_RADIUS,
+            lake_size: 0,
+            map_bounds: MapBounds::default(),
+            rosters: HashMap::new(),
+            statistics: StatisticsSeries::default(),
+            statistics_limit: DEFAULT_STATISTICS_LIMIT,
+            match_generation: MatchGeneration::default(),
+            last_combat: None,
+            last_seen: LastSeen::default(),
+            initiative: InitiativeQueue::default(),
+            planned_orders: Vec::new(),
+            movement: MovementProgress::default(),
+            hitstop_timer: 0.0,
+            hitstop_cap: DEFAULT_HITSTOP_CAP,
+            observing: false,
+            local_players: HashSet::new(),
+            triggers: Triggers::default(),
+            spawn_nodes: false,
+            tutorial: Tutorial::default(),
+            rng: Rng::default(),
+            weather: Weather::default(),
+            edges: EdgeData::default(),
+            influence: InfluenceMap::default(),
+            explanations: HashMap::new(),
+            unit_names: UnitNames::default(),
+            action_log: ActionLog::default(),
+            turn_handoff: TurnHandoff::default(),
+            state_changes: Vec::new(),
         }
     }
+
+    /// Ends the match: empties the world and puts everything back as `new` has it. The rules, the
+    /// unit catalog, the palette and the settings the game world pushes stay, as does the random
+    /// number generator so the next match does not draw the same events. The match generation
+    /// goes up.
+    pub fn reset(&mut self, world: &mut World) {
+        world.clear();
+        let previous = std::mem::replace(self, GameState::new());
+        self.rules = previous.rules;
+        self.unit_catalog = previous.unit_catalog;
+        self.palette = previous.palette;
+        self.banner_duration = previous.banner_duration;
+        self.privacy_screen = previous.privacy_screen;
+        self.show_help = previous.show_help;
+        self.hitstop_cap = previous.hitstop_cap;
+        self.spawn_nodes = previous.spawn_nodes;
+        self.grid_radius = previous.grid_radius;
+        self.lake_size = previous.lake_size;
+        self.rosters = previous.rosters;
+        self.statistics_limit = previous.statistics_limit;
+        self.match_generation = previous.match_generation;
+        self.match_generation.bump();
+        self.hex_cursor = HexCursor::new(previous.grid_radius);
+        self.rng = previous.rng;
+    }
+
+    /// Takes the bounds of the map from the fields in the world. Has to be called whenever fields
+    /// are added or removed.
+    pub fn refresh_map_bounds<S: EntityStore>(&mut self, world: &S) {
+        self.map_bounds = MapBounds::from_world(world);
+        self.map_bounds
+            .set_holes_block_sight(self.rules.holes_block_sight);
+    }
+
+    /// Adds a player that is controlled on this machine, as in a hotseat game, until
+    /// `local_players` is changed.
+    pub fn add_player(&mut self, player: Player) {
+        self.local_players.insert(self.players.len());
+        self.players.push(player);
+    }
+
+    /// Recolours every player with the named palette. Returns false for unknown palettes.
+    pub fn set_palette(&mut self, name: &str) -> bool {
+        match Palette::find(name) {
+            None => false,
+            Some(palette) => {
+                palette.apply(&mut self.players);
+                self.palette = name.to_owned();
+                self.redraw_grid = true;
+                true
+            }
+        }
+    }
+
+    /// The handicaps of all players, by player index.
+    pub fn handicaps(&self) -> Vec<Handicap> {
+        self.players
+            .iter()
+            .map(|player| player.get_handicap())
+            .collect()
+    }
+
+    /// Changes the handicap of `player`. The units the player already has get the new integrity
+    /// bonus instead of the old one. Handicaps are locked once the first round is over.
+    pub fn set_handicap(
+        &mut self,
+        world: &mut World,
+        player: usize,
+        handicap: Handicap,
+    ) -> Result<(), HandicapError> {
+        if self.round > 1 {
+            return Err(HandicapError::MatchStarted);
+        }
+        let previous = match self.players.get(player) {
+            None => return Err(HandicapError::UnknownPlayer(player)),
+            Some(data) => data.get_handicap(),
+        };
+        for (owner, unit) in <(&PlayerComponent, &mut Unit)>::query().iter_mut(world) {
+            if owner.0 == player {
+                unit.integrity = handicap.rescale_integrity(unit.integrity, &previous);
+            }
+        }
+        self.players[player].set_handicap(handicap);
+        Ok(())
+    }
+
+    /// Whether the player whose turn it is may act from this machine.
+    /// Drops the moves of a group move or a retreat that did not start yet.
+    pub fn cancel_group_orders(&mut self) {
+        self.group_moves.clear();
+        self.retreats.clear();
+    }
+
+    /// Whether units and overlays are hidden, because the next hotseat player has not taken
+    /// over yet.
+    pub fn is_view_hidden(&self) -> bool {
+        self.awaiting_player && matches!(self.state, State::TurnTransition(_))
+    }
+
+    /// Sets the hexagon under the mouse, `None` once the mouse left the viewport. Returns what
+    /// changed, nothing while the mouse stays on the same hexagon.
+    pub fn set_hovered_hexagon(&mut self, hexagon: Option<Hexagon>) -> Option<HoverChange> {
+        if self.hovered_hexagon == hexagon {
+            return None;
+        }
+        let change = HoverChange {
+            exited: self.hovered_hexagon,
+            entered: hexagon,
+        };
+        self.hovered_hexagon = hexagon;
+        self.redraw_grid = true;
+        Some(change)
+    }
+
+    /// The unit selected last if it can be selected again: it still exists, belongs to the
+    /// current player and is not selected already. A unit that can not is forgotten.
+    pub fn take_reselect_target<S: EntityStore>(&mut self, world: &S) -> Option<Entity> {
+        let entity = self.last_selected?;
+        let valid = world.entry_ref(entity).map_or(false, |entry| {
+            entry.get_component::<Unit>().is_ok()
+                && entry
+                    .get_component::<PlayerComponent>()
+                    .ok()
+                    .map(|player| player.0)
+                    == self.current_player
+        });
+        if !valid {
+            self.last_selected = None;
+            return None;
+        }
+        if self.state == State::Selected(entity) {
+            return None;
+        }
+        Some(entity)
+    }
+
+    /// The moves and attacks `player` may still order this round, `None` without a limit.
+    pub fn command_points_left(&self, player: usize) -> Option<u32> {
+        if self.rules.command_points <= 0 {
+            return None;
+        }
+        let used = self.commands_used.get(&player).copied().unwrap_or(0);
+        Some((self.rules.command_points as u32).saturating_sub(used))
+    }
+
+    pub fn has_command_points(&self, player: usize) -> bool {
+        self.command_points_left(player) != Some(0)
+    }
+
+    pub fn is_local_turn(&self) -> bool {
+        match self.current_player {
+            None => false,
+            Some(player) => self.local_players.contains(&player),
+        }
+    }
+
+    /// Hashes every unit and what else on the map decides the outcome of commands, together with
+    /// the current player, round and the state of the random number generator. Everything is
+    /// sorted by its data first, so the result does not depend on entity ids or storage order.
+    pub fn compute_checksum<S: EntityStore>(&self, world: &S) -> u64 {
+        let mut units: Vec<_> = <(
+            &Unit,
+            Option<&Hexagon>,
+            Option<&PlayerComponent>,
+            Option<&Facing>,
+            Option<&Experience>,
+            Option<&Perks>,
+            Option<&Abilities>,
+            Option<&Overdrive>,
+            Option<&Overwatching>,
+            Option<&Garrisoned>,
+            Option<&Supply>,
+        )>::query()
+        .iter(world)
+        .map(
+            |(
+                unit,
+                hexagon,
+                player,
+                facing,
+                experience,
+                perks,
+                abilities,
+                overdrive,
+                overwatching,
+                garrisoned,
+                supply,
+            )| {
+                (
+                    hexagon.map(|hexagon| (hexagon.get_q(), hexagon.get_r())),
+                    player.map(|player| player.0),
+                    // The facing decides the flanking bonus of attacks.
+                    facing.map(|facing| facing.0 as u8),
+                    // Kills lead to promotions, perks change damage, integrity and movement.
+                    experience.map(|experience| experience.kills),
+                    perks.map(|perks| perks.0.iter().map(|perk| *perk as u8).collect::<Vec<u8>>()),
+                    // A spent cooldown keeps the ability from being used.
+                    abilities.map(|abilities| {
+                        abilities
+                            .0
+                            .iter()
+                            .map(|ability| (ability.kind as u8, ability.cooldown_remaining))
+                            .collect::<Vec<(u8, u8)>>()
+                    }),
+                    // The damage that is taken back at the start of the owner's next turn.
+                    overdrive.map(|overdrive| overdrive.0),
+                    // Units that stay out of supply suffer attrition.
+                    supply.map(|supply| (supply.in_supply, supply.unsupplied_rounds)),
+                    [
+                        unit.integrity,
+                        unit.damage,
+                        unit.max_attack_range,
+                        unit.min_attack_range,
+                        unit.armor,
+                        unit.mobility,
+                        unit.remaining_range,
+                        unit.remaining_attacks,
+                        unit.action_points,
+                        unit.partial_movement,
+                    ],
+                    // Whether the unit may still fire after moving, whether it fires at enemies
+                    // moving into its range and whether a building shields it.
+                    (
+                        unit.moved,
+                        unit.fire_after_move,
+                        overwatching.is_some(),
+                        garrisoned.is_some(),
+                    ),
+                )
+            },
+        )
+        .collect();
+        units.sort_unstable();
+
+        let mut hasher = StableHasher::new();
+        hasher.write_u64(units.len() as u64);
+        for (
+            position,
+            player,
+            facing,
+            kills,
+            perks,
+            abilities,
+            overdrive,
+            supply,
+            fields,
+            (moved, fire_after_move, overwatching, garrisoned),
+        ) in units
+        {
+            match position {
+                None => hasher.write_u8(0),
+                Some((q, r)) => {
+                    hasher.write_u8(1);
+                    hasher.write_i32(q);
+                    hasher.write_i32(r);
+                }
+            }
+            hasher.write_option_usize(player);
+            match facing {
+                None => hasher.write_u8(0),
+                Some(direction) => {
+                    hasher.write_u8(1);
+                    hasher.write_u8(direction);
+                }
+            }
+            match kills {
+                None => hasher.write_u8(0),
+                Some(kills) => {
+                    hasher.write_u8(1);
+                    hasher.write_u32(kills);
+                }
+            }
+            match perks {
+                None => hasher.write_u8(0),
+                Some(perks) => {
+                    hasher.write_u8(1);
+                    hasher.write_u64(perks.len() as u64);
+                    hasher.write_bytes(&perks);
+                }
+            }
+            match abilities {
+                None => hasher.write_u8(0),
+                Some(abilities) => {
+                    hasher.write_u8(1);
+                    hasher.write_u64(abilities.len() as u64);
+                    for (kind, cooldown_remaining) in abilities {
+                        hasher.write_u8(kind);
+                        hasher.write_u8(cooldown_remaining);
+                    }
+                }
+            }
+            match overdrive {
+                None => hasher.write_u8(0),
+                Some(damage) => {
+                    hasher.write_u8(1);
+                    hasher.write_i32(damage);
+                }
+            }
+            match supply {
+                None => hasher.write_u8(0),
+                Some((in_supply, unsupplied_rounds)) => {
+                    hasher.write_u8(1);
+                    hasher.write_u8(in_supply as u8);
+                    hasher.write_u32(unsupplied_rounds);
+                }
+            }
+            for field in fields.iter() {
+                hasher.write_i32(*field);
+            }
+            hasher.write_u8(moved as u8);
+            hasher.write_u8(fire_after_move as u8);
+            hasher.write_u8(overwatching as u8);
+            hasher.write_u8(garrisoned as u8);
+        }
+        // Destroyed bridges change the paths of every client.
+        let edges = self.edges.to_list();
+        hasher.write_u64(edges.len() as u64);
+        for edge in edges {
+            hasher.write_i32(edge.from.get_q());
+            hasher.write_i32(edge.from.get_r());
+            hasher.write_i32(edge.to.get_q());
+            hasher.write_i32(edge.to.get_r());
+            hasher.write_u8(edge.kind as u8);
+            hasher.write_i32(edge.damage);
+        }
+        // Buildings shield the units inside until they are brought down.
+        let buildings = scenario_buildings(world);
+        hasher.write_u64(buildings.len() as u64);
+        for saved in buildings {
+            hasher.write_i32(saved.position.get_q());
+            hasher.write_i32(saved.position.get_r());
+            hasher.write_i32(saved.building.integrity);
+            hasher.write_i32(saved.building.max_integrity);
+        }
+        // Fires burn the units on their hexagon every round.
+        let mut effects: Vec<_> = ground_effects(world)
+            .into_iter()
+            .map(|saved| {
+                (
+                    saved.position.get_q(),
+                    saved.position.get_r(),
+                    saved.effect.name(),
+                    saved.effect.damage(),
+                    saved.effect.rounds_left(),
+                )
+            })
+            .collect();
+        effects.sort_unstable();
+        hasher.write_u64(effects.len() as u64);
+        for (q, r, kind, damage, rounds_left) in effects {
+            hasher.write_i32(q);
+            hasher.write_i32(r);
+            hasher.write_u64(kind.len() as u64);
+            hasher.write_bytes(kind.as_bytes());
+            hasher.write_i32(damage);
+            hasher.write_u8(rounds_left);
+        }
+        // Pickups go to the first unit that steps onto them.
+        let mut pickups: Vec<_> = scenario_pickups(world)
+            .into_iter()
+            .map(|saved| {
+                (
+                    saved.position.get_q(),
+                    saved.position.get_r(),
+                    saved.pickup.name(),
+                    saved.pickup.amount(),
+                )
+            })
+            .collect();
+        pickups.sort_unstable();
+        hasher.write_u64(pickups.len() as u64);
+        for (q, r, kind, amount) in pickups {
+            hasher.write_i32(q);
+            hasher.write_i32(r);
+            hasher.write_u64(kind.len() as u64);
+            hasher.write_bytes(kind.as_bytes());
+            hasher.write_i32(amount);
+        }
+        // Smoke blocks the line of sight until it runs out.
+        let mut smoke: Vec<_> = <(&Hexagon, &BlocksVision)>::query()
+            .iter(world)
+            .map(|(position, smoke)| {
+                (
+                    position.get_q(),
+                    position.get_r(),
+                    smoke.player,
+                    smoke.rounds_remaining,
+                )
+            })
+            .collect();
+        smoke.sort_unstable();
+        hasher.write_u64(smoke.len() as u64);
+        for (q, r, player, rounds_remaining) in smoke {
+            hasher.write_i32(q);
+            hasher.write_i32(r);
+            hasher.write_option_usize(player);
+            hasher.write_u8(rounds_remaining);
+        }
+        // Resources come from pickups and income and set off triggers.
+        hasher.write_u64(self.players.len() as u64);
+        for player in &self.players {
+            hasher.write_i32(player.get_resources());
+        }
+        // The command points left decide which commands are accepted.
+        hasher.write_u64(self.commands_used.len() as u64);
+        for (player, used) in &self.commands_used {
+            hasher.write_u64(*player as u64);
+            hasher.write_u32(*used);
+        }
+        hasher.write_option_usize(self.current_player);
+        hasher.write_u32(self.round);
+        hasher.write_u64(self.rng.state());
+        hasher.finish()
+    }
 }
 
-#[derive(Clone)]
+/// How far a unit got along its path. Kept out of `State::Moving` and changed in place, so the
+/// frames between two steps only add to the time and leave the state alone.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MovementProgress {
+    pub path: VecDeque<Hexagon>,
+    /// Seconds since the last step.
+    pub elapsed: f64,
+}
+
+impl MovementProgress {
+    pub fn new(path: VecDeque<Hexagon>) -> Self {
+        MovementProgress { path, elapsed: 0.0 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum State {
+    /// The nodes of the new world are still being created, input is ignored until it is ready.
+    Loading,
     Startup,
     NewRound,
     Waiting,
     Selected(Entity),
+    /// The selected unit picks the target of an attack, clicks attack instead of moving it.
+    Targeting(Entity),
+    /// Several units of the current player, ordered by when they joined the selection.
+    GroupSelected(Vec<Entity>),
     Attacking(Entity, Entity),
-    Moving(Entity, VecDeque<Hexagon>, f64),
+    /// The unit walks along the path in `GameState::movement`.
+    Moving(Entity),
+    /// A unit uses the ability with the index on the target hexagon.
+    UsingAbility(Entity, usize, Hexagon),
+    /// A unit gives up its remaining movement to fire at enemies moving into its range.
+    EnteringOverwatch(Entity),
+    /// A demolition unit attacks the bridge between the two hexagons.
+    AttackingEdge(Entity, Hexagon, Hexagon),
+    /// A unit moves into the building it stands on.
+    Garrisoning(Entity),
+    /// A unit leaves its building for the hexagon.
+    Ungarrisoning(Entity, Hexagon),
+    /// A unit attacks the building on the hexagon.
+    AttackingBuilding(Entity, Hexagon),
+    /// A unit turns to face the direction.
+    Turning(Entity, Direction),
+    /// The unit was promoted and waits for its owner to pick one of the perks.
+    ChoosingPromotion(Entity, Vec<PerkId>),
+    /// A promoted unit takes the perk its owner picked.
+    Promoting(Entity, PerkId),
+    /// With the initiative rules, the unit whose turn it is waits for orders.
+    UnitTurn(Entity),
+    /// With the simultaneous rules, all players plan the orders of their units.
+    Planning,
+    /// The planned orders of all players are carried out.
+    Resolving,
+    /// Handover to the next player, input is ignored for the remaining seconds.
+    TurnTransition(f64),
+    GameOver(Option<usize>),
+}
+
+impl State {
+    /// The name of the variant, as the transition table knows it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Loading => "Loading",
+            State::Startup => "Startup",
+            State::NewRound => "NewRound",
+            State::Waiting => "Waiting",
+            State::Selected(_) => "Selected",
+            State::Targeting(_) => "Targeting",
+            State::GroupSelected(_) => "GroupSelected",
+            State::Attacking(_, _) => "Attacking",
+            State::Moving(_) => "Moving",
+            State::UsingAbility(_, _, _) => "UsingAbility",
+            State::EnteringOverwatch(_) => "EnteringOverwatch",
+            State::AttackingEdge(_, _, _) => "AttackingEdge",
+            State::Garrisoning(_) => "Garrisoning",
+            State::Ungarrisoning(_, _) => "Ungarrisoning",
+            State::AttackingBuilding(_, _) => "AttackingBuilding",
+            State::Turning(_, _) => "Turning",
+            State::ChoosingPromotion(_, _) => "ChoosingPromotion",
+            State::Promoting(_, _) => "Promoting",
+            State::UnitTurn(_) => "UnitTurn",
+            State::Planning => "Planning",
+            State::Resolving => "Resolving",
+            State::TurnTransition(_) => "TurnTransition",
+            State::GameOver(_) => "GameOver",
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::Loading => write!(f, "Loading"),
+            State::Startup => write!(f, "Startup"),
+            State::NewRound => write!(f, "NewRound"),
+            State::Waiting => write!(f, "Waiting"),
+            State::Selected(entity) => write!(f, "Selected({:?})", entity),
+            State::Targeting(entity) => write!(f, "Targeting({:?})", entity),
+            State::GroupSelected(members) => write!(f, "GroupSelected({} units)", members.len()),
+            State::Attacking(attacker, defender) => {
+                write!(f, "Attacking({:?}, {:?})", attacker, defender)
+            }
+            State::Moving(entity) => write!(f, "Moving({:?})", entity),
+            State::UsingAbility(entity, index, target) => {
+                write!(f, "UsingAbility({:?}, {} on {})", entity, index, target)
+            }
+            State::EnteringOverwatch(entity) => write!(f, "EnteringOverwatch({:?})", entity),
+            State::AttackingEdge(entity, from, to) => {
+                write!(f, "AttackingEdge({:?}, {} to {})", entity, from, to)
+            }
+            State::Garrisoning(entity) => write!(f, "Garrisoning({:?})", entity),
+            State::Ungarrisoning(entity, target) => {
+                write!(f, "Ungarrisoning({:?} to {})", entity, target)
+            }
+            State::AttackingBuilding(entity, target) => {
+                write!(f, "AttackingBuilding({:?}, {})", entity, target)
+            }
+            State::Turning(entity, direction) => {
+                write!(f, "Turning({:?} to {:?})", entity, direction)
+            }
+            State::ChoosingPromotion(entity, options) => {
+                write!(f, "ChoosingPromotion({:?}, {:?})", entity, options)
+            }
+            State::Promoting(entity, perk) => write!(f, "Promoting({:?}, {:?})", entity, perk),
+            State::UnitTurn(entity) => write!(f, "UnitTurn({:?})", entity),
+            State::Planning => write!(f, "Planning"),
+            State::Resolving => write!(f, "Resolving"),
+            State::TurnTransition(remaining) => write!(f, "TurnTransition({:.1}s)", remaining),
+            State::GameOver(None) => write!(f, "GameOver(draw)"),
+            State::GameOver(Some(winner)) => write!(f, "GameOver(winner {})", winner),
+        }
+    }
+}
+
+impl State {
+    /// Whether a move, attack, ability, overwatch, garrison, turn or promotion order is currently
+    /// being carried out.
+    pub fn is_resolving(&self) -> bool {
+        matches!(
+            self,
+            State::Moving(_)
+                | State::Attacking(_, _)
+                | State::UsingAbility(_, _, _)
+                | State::EnteringOverwatch(_)
+                | State::AttackingEdge(_, _, _)
+                | State::Garrisoning(_)
+                | State::Ungarrisoning(_, _)
+                | State::AttackingBuilding(_, _)
+                | State::Turning(_, _)
+                | State::Promoting(_, _)
+                | State::Resolving
+        )
+    }
+
+    /// Whether the current player can give new orders.
+    pub fn accepts_orders(&self) -> bool {
+        matches!(
+            self,
+            State::Waiting
+                | State::Selected(_)
+                | State::Targeting(_)
+                | State::GroupSelected(_)
+                | State::UnitTurn(_)
+        )
+    }
+
+    /// The selected units, empty outside of a selection.
+    pub fn selection(&self) -> Vec<Entity> {
+        match self {
+            State::Selected(selected) => vec![*selected],
+            State::GroupSelected(members) => members.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the selection after a unit was added to or removed from it.
+    pub fn toggle_selection(&self, entity: Entity) -> State {
+        let mut members = self.selection();
+        match members.iter().position(|member| *member == entity) {
+            None => members.push(entity),
+            Some(index) => {
+                members.remove(index);
+            }
+        }
+        State::from_selection(members)
+    }
+
+    /// The state for a selection of units: waiting, a single selected unit or a group.
+    pub fn from_selection(mut members: Vec<Entity>) -> State {
+        match members.len() {
+            0 => State::Waiting,
+            1 => State::Selected(members.remove(0)),
+            _ => State::GroupSelected(members),
+        }
+    }
+}
+
+/// Changes the state if the transition table allows it, see `transitions`. A forbidden transition
+/// is logged and the old state kept. Returns whether the state changed.
+pub fn set_state(state: &mut GameState, game_state: State) -> bool {
+    if !is_allowed(&state.state, &game_state) {
+        log_warn!(
+            "Refused state transition from {} to {}",
+            state.state,
+            game_state
+        );
+        return false;
+    }
+    force_state(state, game_state);
+    true
+}
+
+/// Changes the state without asking the transition table, for the paths that recover from a
+/// broken state or replace the game.
+pub fn force_state(state: &mut GameState, game_state: State) {
+    let old = state.state.name();
+    if old != game_state.name() {
+        state.state_changes.push((old, game_state.name()));
+    }
+    match game_state {
+        State::NewRound => {}
+        State::Loading => {}
+        State::Startup => {}
+        State::Waiting => {}
+        State::Selected(entity) => {
+            state.last_selected = Some(entity);
+            state.update_fields = true;
+            state.selection_generation += 1;
+            state.refresh_attackable_entities = true;
+        }
+        State::Targeting(_) => {
+            state.update_fields = true;
+            state.refresh_attackable_entities = true;
+        }
+        State::GroupSelected(_) => {
+            state.selection_generation += 1;
+        }
+        State::Attacking(_, _) => {}
+        State::Moving(_) => {}
+        State::UsingAbility(_, _, _) => {}
+        State::EnteringOverwatch(_) => {}
+        State::AttackingEdge(_, _, _) => {}
+        State::Garrisoning(_) => {}
+        State::Ungarrisoning(_, _) => {}
+        State::AttackingBuilding(_, _) => {}
+        State::Turning(_, _) => {}
+        State::ChoosingPromotion(_, _) => {}
+        State::Promoting(_, _) => {}
+        State::UnitTurn(_) => {}
+        State::Planning => {}
+        State::Resolving => {}
+        State::TurnTransition(_) => {}
+        State::GameOver(_) => {}
+    }
+    if !matches!(game_state, State::Moving(_)) {
+        state.movement = MovementProgress::default();
+    }
+    if !matches!(game_state, State::TurnTransition(_)) {
+        state.awaiting_player = false;
+    }
+    state.state = game_state;
+    state.attackable_entities.clear();
+    state.current_path = Vec::new();
+    state.redraw_grid = true;
+}
+
+/// Starts moving the unit along the path, if the transition table lets the state machine move.
+pub fn start_move(state: &mut GameState, entity: Entity, path: VecDeque<Hexagon>) -> bool {
+    if !set_state(state, State::Moving(entity)) {
+        return false;
+    }
+    state.movement = MovementProgress::new(path);
+    true
+}
+
+/// Starts moving the unit along the path without asking the transition table.
+pub fn force_move(state: &mut GameState, entity: Entity, path: VecDeque<Hexagon>) {
+    force_state(state, State::Moving(entity));
+    state.movement = MovementProgress::new(path);
+}
+
+/// A broken assumption of the state machine about the state and the world.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvariantViolation {
+    StackedUnits(Hexagon),
+    UnitWithoutPlayer(Entity),
+    UnknownPlayer {
+        entity: Entity,
+        player: usize,
+    },
+    NoCurrentPlayer,
+    CurrentPlayerOutOfRange(usize),
+    /// The state references an entity that is missing or lacks its Unit or Hexagon.
+    InvalidStateEntity(Entity),
+    PathNotAdjacent(Hexagon, Hexagon),
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::StackedUnits(hexagon) => {
+                write!(f, "Several units at {}", hexagon)
+            }
+            InvariantViolation::UnitWithoutPlayer(entity) => {
+                write!(f, "Unit {:?} has no player", entity)
+            }
+            InvariantViolation::UnknownPlayer { entity, player } => {
+                write!(f, "Unit {:?} belongs to unknown player {}", entity, player)
+            }
+            InvariantViolation::NoCurrentPlayer => write!(f, "There is no current player"),
+            InvariantViolation::CurrentPlayerOutOfRange(player) => {
+                write!(f, "Current player {} does not exist", player)
+            }
+            InvariantViolation::InvalidStateEntity(entity) => write!(
+                f,
+                "The state references {:?}, which is not a unit on the map",
+                entity
+            ),
+            InvariantViolation::PathNotAdjacent(from, to) => write!(
+                f,
+                "Path steps from {} to {}, which are not neighbours",
+                from, to
+            ),
+        }
+    }
+}
+
+fn state_entities(state: &State) -> Vec<Entity> {
+    match state {
+        State::Selected(entity)
+        | State::Targeting(entity)
+        | State::Moving(entity)
+        | State::UsingAbility(entity, _, _)
+        | State::EnteringOverwatch(entity)
+        | State::AttackingEdge(entity, _, _)
+        | State::Garrisoning(entity)
+        | State::Ungarrisoning(entity, _)
+        | State::AttackingBuilding(entity, _)
+        | State::Turning(entity, _)
+        | State::ChoosingPromotion(entity, _)
+        | State::Promoting(entity, _)
+        | State::UnitTurn(entity) => vec![*entity],
+        State::GroupSelected(members) => members.clone(),
+        State::Attacking(attacker, defender) => vec![*attacker, *defender],
+        _ => Vec::new(),
+    }
+}
+
+/// Checks the assumptions the state machine makes about the state and the world. Returns every
+/// violation, an empty list means the game is consistent.
+pub fn validate<S: EntityStore>(world: &S, state: &GameState) -> Vec<InvariantViolation> {
+    let mut violations = Vec::new();
+
+    let mut units_at: HashMap<Hexagon, usize> = HashMap::new();
+    for (entity, _, hexagon, player) in
+        <(Entity, &Unit, &Hexagon, Option<&PlayerComponent>)>::query().iter(world)
+    {
+        *units_at.entry(*hexagon).or_insert(0) += 1;
+        match player {
+            None => violations.push(InvariantViolation::UnitWithoutPlayer(*entity)),
+            Some(player) if player.0 >= state.players.len() => {
+                violations.push(InvariantViolation::UnknownPlayer {
+                    entity: *entity,
+                    player: player.0,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    // There are no stacking rules, so a hexagon holds at most one unit.
+    let mut stacked: Vec<Hexagon> = units_at
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(hexagon, _)| hexagon)
+        .collect();
+    stacked.sort_by_key(|hexagon| (hexagon.get_q(), hexagon.get_r()));
+    violations.extend(stacked.into_iter().map(InvariantViolation::StackedUnits));
+
+    match (&state.state, state.current_player) {
+        (State::Loading, _) | (State::Startup, _) | (State::GameOver(_), _) => {}
+        (_, None) => violations.push(InvariantViolation::NoCurrentPlayer),
+        (_, Some(player)) if player >= state.players.len() => {
+            violations.push(InvariantViolation::CurrentPlayerOutOfRange(player))
+        }
+        _ => {}
+    }
+
+    for entity in state_entities(&state.state) {
+        let is_placed_unit = world.entry_ref(entity).map_or(false, |entry| {
+            entry.get_component::<Unit>().is_ok() && entry.get_component::<Hexagon>().is_ok()
+        });
+        if !is_placed_unit {
+            violations.push(InvariantViolation::InvalidStateEntity(entity));
+        }
+    }
+
+    for step in state.current_path.windows(2) {
+        if !step[0].is_neighbour(&step[1]) {
+            violations.push(InvariantViolation::PathNotAdjacent(step[0], step[1]));
+        }
+    }
+
+    violations
+}
+
+/// Sets up a match in an empty world: two players and either the default units on the default
+/// grid or the scenario. Returns the unit types that are not in the catalog.
+pub fn setup_match(
+    world: &mut World,
+    state: &mut GameState,
+    scenario: Option<&Scenario>,
+) -> Vec<String> {
+    state.add_player(Player::new(
+        "Player 1".to_owned(),
+        Color::rgb(0.0, 0.0, 1.0),
+    ));
+    state.add_player(Player::new(
+        "Player 2".to_owned(),
+        Color::rgb(1.0, 0.0, 0.0),
+    ));
+    let palette = state.palette.clone();
+    state.set_palette(&palette);
+
+    let missing = match scenario {
+        Some(scenario) => load_scenario(world, state, scenario),
+        None => {
+            let units = [
+                (0, Hexagon::new_axial(2, 0), "Tank"),
+                (0, Hexagon::new_axial(2, 1), "Artillery"),
+                (1, Hexagon::new_axial(-2, 0), "Tank"),
+                (1, Hexagon::new_axial(-2, -1), "Artillery"),
+            ];
+            let mut missing = Vec::new();
+            for (player, hexagon, unit_type) in units.iter() {
+                if spawn_unit_of_type(
+                    world,
+                    &mut state.persistent_ids,
+                    &state.unit_catalog,
+                    *player,
+                    *hexagon,
+                    unit_type,
+                    state.spawn_nodes,
+                )
+                .is_none()
+                {
+                    missing.push((*unit_type).to_owned());
+                }
+            }
+            spawn_grid(world, state.grid_radius);
+            carve_lake(world, &Hexagon::zero(), state.lake_size);
+            state.refresh_map_bounds(world);
+            missing
+        }
+    };
+    state.current_player = Some(0);
+    missing
+}
+
+/// Replaces the map with the scenario and takes over its triggers, edges and computer players.
+/// Returns the unit types that are not in the catalog.
+pub fn load_scenario(world: &mut World, state: &mut GameState, scenario: &Scenario) -> Vec<String> {
+    let handicaps = state.handicaps();
+    let rosters = std::mem::take(&mut state.rosters);
+    let missing = scenario.load_with_rosters(
+        world,
+        &mut state.persistent_ids,
+        &state.unit_catalog,
+        &handicaps,
+        &rosters,
+        state.spawn_nodes,
+    );
+    state.triggers = Triggers::new(scenario.triggers.clone());
+    state.edges = EdgeData::from_list(&scenario.edges);
+    state.refresh_map_bounds(world);
+    for ai in &scenario.ai_players {
+        match (state.players.get_mut(ai.player), ai.profile.resolve()) {
+            (Some(player), Some(profile)) => player.set_ai_profile(Some(profile)),
+            (None, _) => log_warn!("Scenario has no player {}", ai.player),
+            (_, None) => log_warn!("Unknown AI profile {:?}", ai.profile),
+        }
+    }
+    missing
+}
+
+/// The hexagons the mouse left and entered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HoverChange {
+    pub exited: Option<Hexagon>,
+    pub entered: Option<Hexagon>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildings::spawn_building;
+    use crate::components::abilities::{AbilityInstance, AbilityKind};
+    use crate::components::building::Building;
+    use crate::components::field::Field;
+    use crate::components::ground_effect::GroundEffect;
+    use crate::components::pickup::Pickup;
+    use crate::components::terrain::Terrain;
+    use crate::components::unit_name::UnitName;
+    use crate::ground_effects::spawn_ground_effect;
+    use crate::pickups::spawn_pickup;
+    use crate::scenario::{ScenarioField, ScenarioUnit};
+    use crate::spawn::spawn_unit;
+    use crate::systems::hexgrid::create_grid;
+
+    fn spawn_units(world: &mut World, first_integrity: i32) {
+        let mut ids = PersistentIds::default();
+        spawn_unit(
+            world,
+            &mut ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            Unit::new(first_integrity, 5, 2, 1, 3, 5, 5, 1),
+            None,
+        );
+        spawn_unit(
+            world,
+            &mut ids,
+            1,
+            Hexagon::new_axial(2, -1),
+            Unit::new(10, 10, 4, 2, 1, 2, 2, 1),
+            None,
+        );
+    }
+
+    #[test]
+    fn identical_states_have_equal_checksums() {
+        let state = GameState::new();
+        let mut first = World::default();
+        spawn_units(&mut first, 20);
+        let mut second = World::default();
+        spawn_units(&mut second, 20);
+
+        assert_eq!(
+            state.compute_checksum(&first),
+            state.compute_checksum(&second)
+        );
+    }
+
+    #[test]
+    fn checksum_does_not_depend_on_spawn_order() {
+        let state = GameState::new();
+        let mut first = World::default();
+        spawn_units(&mut first, 20);
+        let mut second = World::default();
+        let unit = spawn_unit(
+            &mut second,
+            &mut PersistentIds::default(),
+            1,
+            Hexagon::new_axial(2, -1),
+            Unit::new(10, 10, 4, 2, 1, 2, 2, 1),
+            None,
+        );
+        spawn_units(&mut second, 20);
+        second.remove(unit);
+
+        assert_eq!(
+            state.compute_checksum(&first),
+            state.compute_checksum(&second)
+        );
+    }
+
+    #[test]
+    fn one_integrity_point_changes_the_checksum() {
+        let state = GameState::new();
+        let mut first = World::default();
+        spawn_units(&mut first, 20);
+        let mut second = World::default();
+        spawn_units(&mut second, 19);
+
+        assert_ne!(
+            state.compute_checksum(&first),
+            state.compute_checksum(&second)
+        );
+    }
+
+    #[test]
+    fn turning_a_unit_changes_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let unit = <(Entity, &PlayerComponent)>::query()
+            .iter(&world)
+            .find(|(_, player)| player.0 == 0)
+            .map(|(entity, _)| *entity)
+            .unwrap();
+        world
+            .entry(unit)
+            .unwrap()
+            .add_component(Facing(Direction::East));
+        let facing_east = state.compute_checksum(&world);
+
+        world
+            .entry(unit)
+            .unwrap()
+            .add_component(Facing(Direction::West));
+        assert_ne!(state.compute_checksum(&world), facing_east);
+        world
+            .entry(unit)
+            .unwrap()
+            .add_component(Facing(Direction::East));
+        assert_eq!(state.compute_checksum(&world), facing_east);
+    }
+
+    #[test]
+    fn experience_and_perks_change_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let unit = <(Entity, &PlayerComponent)>::query()
+            .iter(&world)
+            .find(|(_, player)| player.0 == 0)
+            .map(|(entity, _)| *entity)
+            .unwrap();
+        let plain = state.compute_checksum(&world);
+
+        world
+            .entry(unit)
+            .unwrap()
+            .add_component(Experience { kills: 2 });
+        let veteran = state.compute_checksum(&world);
+        assert_ne!(veteran, plain);
+
+        let mut checksums = Vec::new();
+        for perk in PerkId::ALL.iter() {
+            world.entry(unit).unwrap().add_component(Perks(vec![*perk]));
+            checksums.push(state.compute_checksum(&world));
+        }
+        assert!(!checksums.contains(&veteran));
+        assert_ne!(checksums[0], checksums[1]);
+        assert_ne!(checksums[1], checksums[2]);
+        assert_ne!(checksums[0], checksums[2]);
+    }
+
+    #[test]
+    fn cooldowns_overdrive_and_smoke_change_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let unit = <(Entity, &PlayerComponent)>::query()
+            .iter(&world)
+            .find(|(_, player)| player.0 == 0)
+            .map(|(entity, _)| *entity)
+            .unwrap();
+        let mut abilities = Abilities(vec![AbilityInstance::new(AbilityKind::Sprint)]);
+        world.entry(unit).unwrap().add_component(abilities.clone());
+        let ready = state.compute_checksum(&world);
+
+        abilities.0[0].cooldown_remaining = 2;
+        world.entry(unit).unwrap().add_component(abilities);
+        let cooling_down = state.compute_checksum(&world);
+        assert_ne!(cooling_down, ready);
+
+        world.entry(unit).unwrap().add_component(Overdrive(3));
+        let overdriven = state.compute_checksum(&world);
+        assert_ne!(overdriven, cooling_down);
+
+        let smoke = world.push((
+            Hexagon::new_axial(1, 0),
+            BlocksVision {
+                player: Some(0),
+                rounds_remaining: 2,
+            },
+        ));
+        let smoked = state.compute_checksum(&world);
+        assert_ne!(smoked, overdriven);
+        world
+            .entry(smoke)
+            .unwrap()
+            .get_component_mut::<BlocksVision>()
+            .unwrap()
+            .rounds_remaining = 1;
+        assert_ne!(state.compute_checksum(&world), smoked);
+    }
+
+    #[test]
+    fn moving_before_firing_changes_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let checksum = state.compute_checksum(&world);
+
+        for unit in <&mut Unit>::query().iter_mut(&mut world) {
+            unit.moved = true;
+        }
+        let moved = state.compute_checksum(&world);
+        assert_ne!(moved, checksum);
+        for unit in <&mut Unit>::query().iter_mut(&mut world) {
+            unit.fire_after_move = false;
+        }
+        assert_ne!(state.compute_checksum(&world), moved);
+    }
+
+    #[test]
+    fn overwatch_changes_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let checksum = state.compute_checksum(&world);
+
+        let unit = <(Entity, &Unit)>::query()
+            .iter(&world)
+            .map(|(entity, _)| *entity)
+            .next()
+            .unwrap();
+        world.entry(unit).unwrap().add_component(Overwatching);
+        assert_ne!(state.compute_checksum(&world), checksum);
+        world
+            .entry(unit)
+            .unwrap()
+            .remove_component::<Overwatching>();
+        assert_eq!(state.compute_checksum(&world), checksum);
+    }
+
+    #[test]
+    fn garrisons_and_buildings_change_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let building = spawn_building(
+            &mut world,
+            Hexagon::new_axial(0, 0),
+            Building::new(15),
+            false,
+        );
+        let empty = state.compute_checksum(&world);
+
+        let unit = <(Entity, &PlayerComponent)>::query()
+            .iter(&world)
+            .find(|(_, player)| player.0 == 0)
+            .map(|(entity, _)| *entity)
+            .unwrap();
+        world.entry(unit).unwrap().add_component(Garrisoned);
+        let garrisoned = state.compute_checksum(&world);
+        assert_ne!(garrisoned, empty);
+
+        world
+            .entry(building)
+            .unwrap()
+            .get_component_mut::<Building>()
+            .unwrap()
+            .integrity = 10;
+        assert_ne!(state.compute_checksum(&world), garrisoned);
+    }
+
+    #[test]
+    fn rounds_out_of_supply_change_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let unit = <(Entity, &Unit)>::query()
+            .iter(&world)
+            .map(|(entity, _)| *entity)
+            .next()
+            .unwrap();
+        let mut supply = Supply::default();
+        supply.record(false);
+        world.entry(unit).unwrap().add_component(supply);
+        let one_round = state.compute_checksum(&world);
+
+        supply.record(false);
+        world.entry(unit).unwrap().add_component(supply);
+        assert_ne!(state.compute_checksum(&world), one_round);
+    }
+
+    #[test]
+    fn ground_effects_change_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let checksum = state.compute_checksum(&world);
+
+        let fire = GroundEffect::Fire {
+            damage_per_round: 2,
+            rounds_left: 3,
+        };
+        let effect = spawn_ground_effect(&mut world, Hexagon::new_axial(1, 0), fire, false);
+        let burning = state.compute_checksum(&world);
+        assert_ne!(burning, checksum);
+
+        world
+            .entry(effect)
+            .unwrap()
+            .get_component_mut::<GroundEffect>()
+            .unwrap()
+            .tick();
+        assert_ne!(state.compute_checksum(&world), burning);
+    }
+
+    #[test]
+    fn collected_pickups_change_the_checksum() {
+        let state = GameState::new();
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let pickup = spawn_pickup(
+            &mut world,
+            Hexagon::new_axial(1, 0),
+            Pickup::Repair(4),
+            false,
+        );
+        let checksum = state.compute_checksum(&world);
+
+        world.remove(pickup);
+        assert_ne!(state.compute_checksum(&world), checksum);
+        spawn_pickup(
+            &mut world,
+            Hexagon::new_axial(1, 0),
+            Pickup::Repair(5),
+            false,
+        );
+        assert_ne!(state.compute_checksum(&world), checksum);
+    }
+
+    #[test]
+    fn round_and_current_player_change_the_checksum() {
+        let world = World::default();
+        let state = GameState::new();
+        let mut next_round = GameState::new();
+        next_round.round = 2;
+        let mut other_player = GameState::new();
+        other_player.current_player = Some(1);
+
+        let checksum = state.compute_checksum(&world);
+        assert_ne!(checksum, next_round.compute_checksum(&world));
+        assert_ne!(checksum, other_player.compute_checksum(&world));
+    }
+
+    #[test]
+    fn resources_change_the_checksum() {
+        let world = World::default();
+        let mut state = GameState::new();
+        for name in ["Red", "Blue"].iter() {
+            state
+                .players
+                .push(Player::new((*name).to_owned(), Color::rgb(1.0, 0.0, 0.0)));
+        }
+        let checksum = state.compute_checksum(&world);
+
+        state.players[0].set_resources(5);
+        let first_player = state.compute_checksum(&world);
+        assert_ne!(first_player, checksum);
+        state.players[0].set_resources(0);
+        state.players[1].set_resources(5);
+        assert_ne!(state.compute_checksum(&world), first_player);
+    }
+
+    #[test]
+    fn used_command_points_change_the_checksum() {
+        let world = World::default();
+        let mut state = GameState::new();
+        let checksum = state.compute_checksum(&world);
+
+        state.commands_used.insert(0, 1);
+        let first_player = state.compute_checksum(&world);
+        assert_ne!(first_player, checksum);
+        state.commands_used.clear();
+        state.commands_used.insert(1, 1);
+        assert_ne!(state.compute_checksum(&world), first_player);
+
+        // The order the players gave their commands in does not matter.
+        let mut other_order = GameState::new();
+        other_order.commands_used.insert(1, 1);
+        other_order.commands_used.insert(0, 2);
+        state.commands_used.insert(0, 2);
+        assert_eq!(
+            other_order.compute_checksum(&world),
+            state.compute_checksum(&world)
+        );
+    }
+
+    #[test]
+    fn states_are_displayed_for_diagnostics() {
+        assert_eq!(State::Waiting.to_string(), "Waiting");
+        assert_eq!(State::Loading.to_string(), "Loading");
+        assert_eq!(
+            State::TurnTransition(1.25).to_string(),
+            "TurnTransition(1.2s)"
+        );
+        assert_eq!(State::GameOver(Some(1)).to_string(), "GameOver(winner 1)");
+        assert_eq!(State::GameOver(None).to_string(), "GameOver(draw)");
+    }
+
+    fn valid_game() -> (World, GameState, Entity) {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.current_player = Some(0);
+        let entity = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            Unit::new(1, 1, 1, 1, 0, 1, 1, 1),
+            None,
+        );
+        state.state = State::Selected(entity);
+        (world, state, entity)
+    }
+
+    #[test]
+    fn valid_game_has_no_violations() {
+        let (world, state, _) = valid_game();
+        assert_eq!(validate(&world, &state), vec![]);
+    }
+
+    #[test]
+    fn stacked_units_are_reported() {
+        let (mut world, mut state, _) = valid_game();
+        spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            Unit::new(1, 1, 1, 1, 0, 1, 1, 1),
+            None,
+        );
+
+        assert_eq!(
+            validate(&world, &state),
+            vec![InvariantViolation::StackedUnits(Hexagon::new_axial(0, 0))]
+        );
+    }
+
+    #[test]
+    fn units_of_unknown_players_are_reported() {
+        let (mut world, mut state, _) = valid_game();
+        let stray = world.push((Unit::new(1, 1, 1, 1, 0, 1, 1, 1), Hexagon::new_axial(1, 0)));
+        let foreign = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            2,
+            Hexagon::new_axial(2, 0),
+            Unit::new(1, 1, 1, 1, 0, 1, 1, 1),
+            None,
+        );
+
+        let violations = validate(&world, &state);
+
+        assert!(violations.contains(&InvariantViolation::UnitWithoutPlayer(stray)));
+        assert!(violations.contains(&InvariantViolation::UnknownPlayer {
+            entity: foreign,
+            player: 2
+        }));
+        assert_eq!(violations.len(), 2);
+    }
+
+    #[test]
+    fn missing_or_unknown_current_player_is_reported() {
+        let (world, mut state, _) = valid_game();
+        state.current_player = None;
+        assert_eq!(
+            validate(&world, &state),
+            vec![InvariantViolation::NoCurrentPlayer]
+        );
+
+        state.current_player = Some(1);
+        assert_eq!(
+            validate(&world, &state),
+            vec![InvariantViolation::CurrentPlayerOutOfRange(1)]
+        );
+
+        state.current_player = None;
+        state.state = State::GameOver(Some(0));
+        assert_eq!(validate(&world, &state), vec![]);
+    }
+
+    #[test]
+    fn state_entities_without_unit_components_are_reported() {
+        let (mut world, mut state, entity) = valid_game();
+        let field = world.push((Hexagon::new_axial(3, 0),));
+        state.state = State::Attacking(entity, field);
+
+        assert_eq!(
+            validate(&world, &state),
+            vec![InvariantViolation::InvalidStateEntity(field)]
+        );
+
+        world.remove(entity);
+        state.state = State::Moving(entity);
+        assert_eq!(
+            validate(&world, &state),
+            vec![InvariantViolation::InvalidStateEntity(entity)]
+        );
+    }
+
+    #[test]
+    fn path_with_gaps_is_reported() {
+        let (world, mut state, _) = valid_game();
+        state.current_path = vec![
+            Hexagon::new_axial(1, 0),
+            Hexagon::new_axial(2, 0),
+            Hexagon::new_axial(4, 0),
+        ];
+
+        assert_eq!(
+            validate(&world, &state),
+            vec![InvariantViolation::PathNotAdjacent(
+                Hexagon::new_axial(2, 0),
+                Hexagon::new_axial(4, 0)
+            )]
+        );
+    }
+
+    #[test]
+    fn handicaps_adjust_existing_units_and_lock_after_the_first_round() {
+        let mut world = World::default();
+        spawn_units(&mut world, 20);
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.add_player(Player::new(
+            "Player 2".to_owned(),
+            Color::rgb(1.0, 0.0, 0.0),
+        ));
+        let stronger = Handicap {
+            integrity_bonus: 50,
+            ..Handicap::default()
+        };
+        let integrities = |world: &World| -> Vec<(usize, i32)> {
+            let mut integrities: Vec<(usize, i32)> = <(&PlayerComponent, &Unit)>::query()
+                .iter(world)
+                .map(|(player, unit)| (player.0, unit.integrity))
+                .collect();
+            integrities.sort_unstable();
+            integrities
+        };
+
+        assert_eq!(state.set_handicap(&mut world, 0, stronger), Ok(()));
+        assert_eq!(integrities(&world), vec![(0, 30), (1, 10)]);
+        assert_eq!(state.handicaps()[0], stronger);
+        assert_eq!(
+            state.set_handicap(&mut world, 0, Handicap::default()),
+            Ok(())
+        );
+        assert_eq!(integrities(&world), vec![(0, 20), (1, 10)]);
+        assert_eq!(
+            state.set_handicap(&mut world, 2, stronger),
+            Err(HandicapError::UnknownPlayer(2))
+        );
+
+        state.round = 2;
+        assert_eq!(
+            state.set_handicap(&mut world, 1, stronger),
+            Err(HandicapError::MatchStarted)
+        );
+        assert_eq!(integrities(&world), vec![(0, 20), (1, 10)]);
+        assert_eq!(state.handicaps()[1], Handicap::default());
+    }
+
+    #[test]
+    fn hover_changes_only_when_the_hexagon_does() {
+        let mut state = GameState::new();
+        let first = Hexagon::new_axial(0, 0);
+        let second = Hexagon::new_axial(1, 0);
+
+        assert_eq!(
+            state.set_hovered_hexagon(Some(first)),
+            Some(HoverChange {
+                exited: None,
+                entered: Some(first)
+            })
+        );
+        state.redraw_grid = false;
+        assert_eq!(state.set_hovered_hexagon(Some(first)), None);
+        assert!(!state.redraw_grid);
+        assert_eq!(
+            state.set_hovered_hexagon(Some(second)),
+            Some(HoverChange {
+                exited: Some(first),
+                entered: Some(second)
+            })
+        );
+        assert!(state.redraw_grid);
+    }
+
+    #[test]
+    fn last_selected_unit_can_be_selected_again() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.current_player = Some(0);
+        let mut ids = PersistentIds::default();
+        let own = spawn_unit(
+            &mut world,
+            &mut ids,
+            0,
+            Hexagon::zero(),
+            Unit::new(5, 1, 1, 1, 0, 1, 1, 1),
+            None,
+        );
+
+        assert_eq!(state.take_reselect_target(&world), None);
+        force_state(&mut state, State::Selected(own));
+        assert_eq!(state.take_reselect_target(&world), None);
+        force_state(&mut state, State::Waiting);
+        assert_eq!(state.take_reselect_target(&world), Some(own));
+
+        // Not during the turn of another player.
+        state.current_player = Some(1);
+        assert_eq!(state.take_reselect_target(&world), None);
+        assert_eq!(state.last_selected, None);
+    }
+
+    #[test]
+    fn removed_last_selected_unit_is_forgotten() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.current_player = Some(0);
+        let mut ids = PersistentIds::default();
+        let unit = spawn_unit(
+            &mut world,
+            &mut ids,
+            0,
+            Hexagon::zero(),
+            Unit::new(5, 1, 1, 1, 0, 1, 1, 1),
+            None,
+        );
+        force_state(&mut state, State::Selected(unit));
+        force_state(&mut state, State::Waiting);
+        world.remove(unit);
+
+        assert_eq!(state.take_reselect_target(&world), None);
+        assert_eq!(state.last_selected, None);
+    }
+
+    #[test]
+    fn leaving_the_viewport_clears_the_hover() {
+        let mut state = GameState::new();
+        let hexagon = Hexagon::new_axial(2, -1);
+        state.set_hovered_hexagon(Some(hexagon));
+
+        assert_eq!(
+            state.set_hovered_hexagon(None),
+            Some(HoverChange {
+                exited: Some(hexagon),
+                entered: None
+            })
+        );
+        assert_eq!(state.hovered_hexagon, None);
+        assert_eq!(state.set_hovered_hexagon(None), None);
+    }
+
+    fn assert_clean_match(world: &World, state: &GameState, units: usize, fields: usize) {
+        let ids: Vec<(Entity, PersistentId)> = <(Entity, &PersistentId)>::query()
+            .iter(world)
+            .map(|(entity, id)| (*entity, *id))
+            .collect();
+        assert_eq!(<&Unit>::query().iter(world).count(), units);
+        assert_eq!(<&Field>::query().iter(world).count(), fields);
+        assert_eq!(Entity::query().iter(world).count(), units + fields);
+        assert_eq!(ids.len(), units);
+        assert_eq!(state.persistent_ids.len(), units);
+        for (entity, id) in ids {
+            assert_eq!(state.persistent_ids.find(world, id), Some(entity));
+        }
+        assert_eq!(state.players.len(), 2);
+        assert_eq!(state.state, State::Startup);
+        assert_eq!(state.current_player, Some(0));
+    }
+
+    #[test]
+    fn consecutive_matches_leave_nothing_behind() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.rules.events = true;
+        let scenario = Scenario {
+            fields: vec![ScenarioField {
+                position: Hexagon::zero(),
+                terrain: Terrain::default(),
+            }],
+            units: vec![ScenarioUnit {
+                player: 1,
+                position: Hexagon::zero(),
+                unit_type: "Tank".to_owned(),
+                name: Some("Old Faithful".to_owned()),
+                from_roster: false,
+            }],
+            ..Scenario::default()
+        };
+        let grid = 3 * MAP_RADIUS as usize * (MAP_RADIUS as usize + 1) + 1;
+
+        assert!(setup_match(&mut world, &mut state, None).is_empty());
+        assert_clean_match(&world, &state, 4, grid);
+        state.round = 7;
+        state.last_selected = state.persistent_ids.find(&world, PersistentId(0));
+
+        state.reset(&mut world);
+        assert_eq!(Entity::query().iter(&world).count(), 0);
+        assert!(state.players.is_empty() && state.persistent_ids.is_empty());
+        assert_eq!((state.round, state.last_selected), (1, None));
+        assert!(state.rules.events);
+
+        assert!(setup_match(&mut world, &mut state, Some(&scenario)).is_empty());
+        assert_clean_match(&world, &state, 1, 1);
+        let names: Vec<&UnitName> = <&UnitName>::query().iter(&world).collect();
+        assert_eq!(names, vec![&UnitName("Old Faithful".to_owned())]);
+
+        state.reset(&mut world);
+        setup_match(&mut world, &mut state, None);
+        assert_clean_match(&world, &state, 4, grid);
+    }
+
+    #[test]
+    fn removed_hexagons_are_holes_in_the_map() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        let scenario = Scenario {
+            fields: create_grid(2)
+                .into_iter()
+                .map(|position| ScenarioField {
+                    position,
+                    terrain: Terrain::default(),
+                })
+                .collect(),
+            removed: vec![Hexagon::zero()],
+            units: vec![ScenarioUnit {
+                player: 0,
+                position: Hexagon::zero(),
+                unit_type: "Tank".to_owned(),
+                name: None,
+                from_roster: false,
+            }],
+            ..Scenario::default()
+        };
+
+        setup_match(&mut world, &mut state, Some(&scenario));
+
+        assert_eq!(<&Field>::query().iter(&world).count(), 18);
+        assert_eq!(<&Unit>::query().iter(&world).count(), 0);
+        assert!(!state.map_bounds.contains(&Hexagon::zero()));
+        assert!(state.map_bounds.contains(&Hexagon::new_axial(1, 0)));
+        assert!(state.map_bounds.blocks_sight(&Hexagon::zero()));
+    }
 }
@@ -0,0 +1,658 @@
+//! Conversions between the core types and the dictionaries that signals carry and scripts pass
+//! in. The values go through `Fields`, which holds the keys of a dictionary without Godot, so the
+//! conversions work the same in tests and headless simulations.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::PersistentIds;
+use crate::components::unit::{AttackResult, Unit};
+use crate::player::Player;
+use crate::spawn::{UnitSource, UnitSpawn};
+use crate::state_machine::GameEvent;
+use gdnative::prelude::*;
+use legion::Entity;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Why a dictionary could not be read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConvertError {
+    MissingKey(String),
+    WrongType {
+        key: String,
+        expected: &'static str,
+    },
+    /// A number that does not fit the value it is read into.
+    OutOfRange(String),
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::MissingKey(key) => write!(f, "The key '{}' is missing", key),
+            ConvertError::WrongType { key, expected } => {
+                write!(f, "The key '{}' has to be {}", key, expected)
+            }
+            ConvertError::OutOfRange(key) => write!(f, "The value of '{}' is out of range", key),
+        }
+    }
+}
+
+/// A value of a dictionary. Values of other types are kept as `Unsupported`, so reading them
+/// reports a wrong type instead of a missing key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+    Colour(Color),
+    Vector(Vector2),
+    Fields(Fields),
+    Unsupported,
+}
+
+impl From<i64> for FieldValue {
+    fn from(value: i64) -> Self {
+        FieldValue::Int(value)
+    }
+}
+
+impl From<i32> for FieldValue {
+    fn from(value: i32) -> Self {
+        FieldValue::Int(i64::from(value))
+    }
+}
+
+impl From<u32> for FieldValue {
+    fn from(value: u32) -> Self {
+        FieldValue::Int(i64::from(value))
+    }
+}
+
+impl From<usize> for FieldValue {
+    fn from(value: usize) -> Self {
+        FieldValue::Int(value as i64)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        FieldValue::Float(value)
+    }
+}
+
+impl From<f32> for FieldValue {
+    fn from(value: f32) -> Self {
+        FieldValue::Float(f64::from(value))
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        FieldValue::Bool(value)
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        FieldValue::Text(value)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        FieldValue::Text(value.to_owned())
+    }
+}
+
+impl From<Color> for FieldValue {
+    fn from(value: Color) -> Self {
+        FieldValue::Colour(value)
+    }
+}
+
+impl From<Vector2> for FieldValue {
+    fn from(value: Vector2) -> Self {
+        FieldValue::Vector(value)
+    }
+}
+
+impl From<Fields> for FieldValue {
+    fn from(value: Fields) -> Self {
+        FieldValue::Fields(value)
+    }
+}
+
+/// The keys and values of a dictionary.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fields {
+    values: BTreeMap<String, FieldValue>,
+}
+
+impl Fields {
+    pub fn new() -> Self {
+        Fields::default()
+    }
+
+    pub fn with<V: Into<FieldValue>>(mut self, key: &str, value: V) -> Self {
+        self.values.insert(key.to_owned(), value.into());
+        self
+    }
+
+    /// Adds the keys of `other`, replacing those that are set already.
+    pub fn merge(mut self, other: Fields) -> Self {
+        self.values.extend(other.values);
+        self
+    }
+
+    fn value(&self, key: &str) -> Result<&FieldValue, ConvertError> {
+        self.values
+            .get(key)
+            .ok_or_else(|| ConvertError::MissingKey(key.to_owned()))
+    }
+
+    pub fn get_i64(&self, key: &str) -> Result<i64, ConvertError> {
+        match self.value(key)? {
+            FieldValue::Int(value) => Ok(*value),
+            // Numbers that went through JSON arrive as floats.
+            FieldValue::Float(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => {
+                Ok(*value as i64)
+            }
+            _ => Err(ConvertError::WrongType {
+                key: key.to_owned(),
+                expected: "an integer",
+            }),
+        }
+    }
+
+    pub fn get_i32(&self, key: &str) -> Result<i32, ConvertError> {
+        i32::try_from(self.get_i64(key)?).map_err(|_| ConvertError::OutOfRange(key.to_owned()))
+    }
+
+    pub fn get_usize(&self, key: &str) -> Result<usize, ConvertError> {
+        usize::try_from(self.get_i64(key)?).map_err(|_| ConvertError::OutOfRange(key.to_owned()))
+    }
+
+    pub fn get_str(&self, key: &str) -> Result<&str, ConvertError> {
+        match self.value(key)? {
+            FieldValue::Text(value) => Ok(value),
+            _ => Err(ConvertError::WrongType {
+                key: key.to_owned(),
+                expected: "a string",
+            }),
+        }
+    }
+
+    pub fn get_fields(&self, key: &str) -> Result<&Fields, ConvertError> {
+        match self.value(key)? {
+            FieldValue::Fields(value) => Ok(value),
+            _ => Err(ConvertError::WrongType {
+                key: key.to_owned(),
+                expected: "a dictionary",
+            }),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    pub fn from_dictionary<Access: ThreadAccess>(dictionary: &Dictionary<Access>) -> Self {
+        let mut fields = Fields::new();
+        for (key, value) in dictionary.iter() {
+            let key = match key.try_to_string() {
+                None => continue,
+                Some(key) => key,
+            };
+            let value = match value.get_type() {
+                VariantType::I64 => FieldValue::Int(value.to_i64()),
+                VariantType::F64 => FieldValue::Float(value.to_f64()),
+                VariantType::Bool => FieldValue::Bool(value.to_bool()),
+                VariantType::GodotString => {
+                    FieldValue::Text(value.try_to_string().unwrap_or_default())
+                }
+                VariantType::Color => FieldValue::Colour(value.to_color()),
+                VariantType::Vector2 => FieldValue::Vector(value.to_vector2()),
+                VariantType::Dictionary => {
+                    FieldValue::Fields(Fields::from_dictionary(&value.to_dictionary()))
+                }
+                _ => FieldValue::Unsupported,
+            };
+            fields.values.insert(key, value);
+        }
+        fields
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dictionary = Dictionary::new();
+        for (key, value) in &self.values {
+            match value {
+                FieldValue::Int(value) => dictionary.insert(key.as_str(), *value),
+                FieldValue::Float(value) => dictionary.insert(key.as_str(), *value),
+                FieldValue::Bool(value) => dictionary.insert(key.as_str(), *value),
+                FieldValue::Text(value) => dictionary.insert(key.as_str(), value.as_str()),
+                FieldValue::Colour(value) => dictionary.insert(key.as_str(), *value),
+                FieldValue::Vector(value) => dictionary.insert(key.as_str(), *value),
+                FieldValue::Fields(value) => {
+                    dictionary.insert(key.as_str(), value.to_dictionary().into_shared())
+                }
+                FieldValue::Unsupported => {}
+            }
+        }
+        dictionary
+    }
+}
+
+impl From<&Hexagon> for Fields {
+    fn from(hexagon: &Hexagon) -> Self {
+        Fields::new()
+            .with("q", hexagon.get_q())
+            .with("r", hexagon.get_r())
+    }
+}
+
+impl TryFrom<&Fields> for Hexagon {
+    type Error = ConvertError;
+
+    fn try_from(fields: &Fields) -> Result<Self, Self::Error> {
+        Ok(Hexagon::new_axial(
+            fields.get_i32("q")?,
+            fields.get_i32("r")?,
+        ))
+    }
+}
+
+impl<Access: ThreadAccess> TryFrom<&Dictionary<Access>> for Hexagon {
+    type Error = ConvertError;
+
+    fn try_from(dictionary: &Dictionary<Access>) -> Result<Self, Self::Error> {
+        Hexagon::try_from(&Fields::from_dictionary(dictionary))
+    }
+}
+
+impl From<&Unit> for Fields {
+    fn from(unit: &Unit) -> Self {
+        Fields::new()
+            .with("integrity", unit.integrity)
+            .with("damage", unit.damage)
+            .with("max_attack_range", unit.max_attack_range)
+            .with("min_attack_range", unit.min_attack_range)
+            .with("armor", unit.armor)
+            .with("mobility", unit.mobility)
+            .with("remaining_range", unit.remaining_range)
+            .with("remaining_attacks", unit.remaining_attacks)
+            .with("action_points", unit.action_points)
+    }
+}
+
+/// Reads the stats of a unit. The movement below one hexagon is not part of the dictionary and
+/// starts at 0.
+impl TryFrom<&Fields> for Unit {
+    type Error = ConvertError;
+
+    fn try_from(fields: &Fields) -> Result<Self, Self::Error> {
+        let mut unit = Unit::new(
+            fields.get_i32("integrity")?,
+            fields.get_i32("damage")?,
+            fields.get_i32("max_attack_range")?,
+            fields.get_i32("min_attack_range")?,
+            fields.get_i32("armor")?,
+            fields.get_i32("mobility")?,
+            fields.get_i32("remaining_range")?,
+            fields.get_i32("remaining_attacks")?,
+        );
+        unit.action_points = fields.get_i32("action_points")?;
+        Ok(unit)
+    }
+}
+
+impl<Access: ThreadAccess> TryFrom<&Dictionary<Access>> for Unit {
+    type Error = ConvertError;
+
+    fn try_from(dictionary: &Dictionary<Access>) -> Result<Self, Self::Error> {
+        Unit::try_from(&Fields::from_dictionary(dictionary))
+    }
+}
+
+/// Reads a unit to spawn: `player`, `q`, `r` and either `unit_type` or the unit stats in
+/// `stats`, with an optional `scene`.
+impl TryFrom<&Fields> for UnitSpawn {
+    type Error = ConvertError;
+
+    fn try_from(fields: &Fields) -> Result<Self, Self::Error> {
+        let source = if fields.contains("stats") {
+            UnitSource::Stats(Unit::try_from(fields.get_fields("stats")?)?)
+        } else {
+            UnitSource::Type(fields.get_str("unit_type")?.to_owned())
+        };
+        let scene = if fields.contains("scene") {
+            Some(fields.get_str("scene")?.to_owned())
+        } else {
+            None
+        };
+        Ok(UnitSpawn {
+            player: fields.get_usize("player")?,
+            position: Hexagon::try_from(fields)?,
+            source,
+            scene,
+        })
+    }
+}
+
+impl From<&Player> for Fields {
+    fn from(player: &Player) -> Self {
+        Fields::new()
+            .with("name", player.get_name())
+            .with("colour", player.get_colour())
+            .with("observer", player.is_observer())
+            .with("resources", player.get_resources())
+    }
+}
+
+impl From<&AttackResult> for Fields {
+    fn from(result: &AttackResult) -> Self {
+        Fields::new()
+            .with("damage", result.actual_damage)
+            .with("attacker", Fields::from(&result.attacker))
+            .with("defender", Fields::from(&result.defender))
+    }
+}
+
+/// The signal and its payload for events that only need the ids of their units. Events whose
+/// payload depends on more, like the translated description of a random event, return `None`.
+pub fn event_signal(event: &GameEvent, ids: &PersistentIds) -> Option<(&'static str, Fields)> {
+    let id = |entity: &Entity| ids.id(*entity).map_or(-1, |id| id.0 as i64);
+    match event {
+        GameEvent::PlanningStarted { round } => {
+            Some(("planning_started", Fields::new().with("round", *round)))
+        }
+        GameEvent::OrderInterrupted { entity, position } => Some((
+            "order_interrupted",
+            Fields::from(position).with("id", id(entity)),
+        )),
+        GameEvent::AbilityUsed {
+            entity,
+            kind,
+            position,
+            target,
+        } => Some((
+            "ability_used",
+            Fields::from(position)
+                .with("id", id(entity))
+                .with("ability", kind.name())
+                .with("target_q", target.get_q())
+                .with("target_r", target.get_r()),
+        )),
+        GameEvent::UnitTurnStarted {
+            entity,
+            player,
+            position,
+        } => Some((
+            "unit_turn_started",
+            Fields::from(position)
+                .with("id", id(entity))
+                .with("player", *player),
+        )),
+        GameEvent::OverwatchChanged {
+            entity,
+            position,
+            active,
+        } => Some((
+            "overwatch_changed",
+            Fields::from(position)
+                .with("id", id(entity))
+                .with("active", *active),
+        )),
+        GameEvent::PickupCollected {
+            entity,
+            player,
+            pickup,
+            position,
+        } => Some((
+            "pickup_collected",
+            Fields::from(position)
+                .with("id", id(entity))
+                .with("player", player.map_or(-1, |player| player as i64))
+                .with("pickup", pickup.name())
+                .with("amount", pickup.amount()),
+        )),
+        GameEvent::GarrisonChanged {
+            entity,
+            position,
+            active,
+        } => Some((
+            "garrison_changed",
+            Fields::from(position)
+                .with("id", id(entity))
+                .with("active", *active),
+        )),
+        GameEvent::BuildingDestroyed { position } => {
+            Some(("building_destroyed", Fields::from(position)))
+        }
+        GameEvent::FacingChanged {
+            entity,
+            position,
+            facing,
+        } => Some((
+            "facing_changed",
+            Fields::from(position)
+                .with("id", id(entity))
+                .with("facing", *facing as i64),
+        )),
+        GameEvent::FireDamage {
+            entity,
+            position,
+            damage,
+            remaining_integrity,
+        } => Some((
+            "fire_damage",
+            Fields::from(position)
+                .with("id", id(entity))
+                .with("damage", *damage)
+                .with("remaining_integrity", *remaining_integrity),
+        )),
+        GameEvent::PromotionAvailable {
+            entity,
+            player,
+            position,
+            options,
+        } => Some((
+            "promotion_available",
+            Fields::from(position)
+                .with("id", id(entity))
+                .with("player", player.map_or(-1, |player| player as i64))
+                .with(
+                    "options",
+                    options
+                        .iter()
+                        .map(|perk| perk.name())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                ),
+        )),
+        GameEvent::BridgeDestroyed { from, to } => Some((
+            "bridge_destroyed",
+            Fields::new()
+                .with("from_q", from.get_q())
+                .with("from_r", from.get_r())
+                .with("to_q", to.get_q())
+                .with("to_r", to.get_r()),
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::persistent_id::PersistentId;
+    use crate::components::pickup::Pickup;
+    use legion::World;
+
+    #[test]
+    fn hexagons_round_trip() {
+        let hexagon = Hexagon::new_axial(-4, 7);
+
+        assert_eq!(Hexagon::try_from(&Fields::from(&hexagon)), Ok(hexagon));
+        assert_eq!(
+            Hexagon::try_from(&Fields::new().with("q", 2.0).with("r", -1)),
+            Ok(Hexagon::new_axial(2, -1))
+        );
+    }
+
+    #[test]
+    fn missing_and_mistyped_keys_are_reported() {
+        assert_eq!(
+            Hexagon::try_from(&Fields::new().with("q", 1)),
+            Err(ConvertError::MissingKey("r".to_owned()))
+        );
+        assert_eq!(
+            Hexagon::try_from(&Fields::new().with("q", "one").with("r", 1)),
+            Err(ConvertError::WrongType {
+                key: "q".to_owned(),
+                expected: "an integer"
+            })
+        );
+        assert!(Hexagon::try_from(&Fields::new().with("q", 0.5).with("r", 1)).is_err());
+        assert_eq!(
+            Hexagon::try_from(&Fields::new().with("q", 1).with("r", i64::MAX)),
+            Err(ConvertError::OutOfRange("r".to_owned()))
+        );
+        let unsupported = Fields {
+            values: vec![("q".to_owned(), FieldValue::Unsupported)]
+                .into_iter()
+                .collect(),
+        };
+        assert!(matches!(
+            unsupported.get_i64("q"),
+            Err(ConvertError::WrongType { .. })
+        ));
+        assert!(ConvertError::MissingKey("q".to_owned())
+            .to_string()
+            .contains("'q'"));
+    }
+
+    #[test]
+    fn units_round_trip() {
+        let mut unit = Unit::new(12, 5, 3, 1, 2, 4, 3, 1);
+        unit.action_points = 2;
+
+        assert_eq!(Unit::try_from(&Fields::from(&unit)), Ok(unit));
+        let without_armor = Fields::from(&unit).merge(Fields::new().with("armor", true));
+        assert!(Unit::try_from(&without_armor).is_err());
+    }
+
+    #[test]
+    fn unit_spawns_take_a_type_or_stats() {
+        let unit = Unit::new(12, 5, 3, 1, 2, 4, 3, 1);
+        let position = Fields::new().with("player", 1).with("q", 2).with("r", -1);
+
+        assert_eq!(
+            UnitSpawn::try_from(&position.clone().with("unit_type", "Tank")),
+            Ok(UnitSpawn {
+                player: 1,
+                position: Hexagon::new_axial(2, -1),
+                source: UnitSource::Type("Tank".to_owned()),
+                scene: None,
+            })
+        );
+        let spawn = UnitSpawn::try_from(
+            &position
+                .clone()
+                .with("stats", Fields::from(&unit))
+                .with("scene", "res://Bunker.tscn"),
+        )
+        .unwrap();
+        assert_eq!(spawn.source, UnitSource::Stats(unit));
+        assert_eq!(spawn.scene.as_deref(), Some("res://Bunker.tscn"));
+        assert_eq!(
+            UnitSpawn::try_from(&position.clone().with("stats", 3)),
+            Err(ConvertError::WrongType {
+                key: "stats".to_owned(),
+                expected: "a dictionary"
+            })
+        );
+        assert_eq!(
+            UnitSpawn::try_from(&position.with("unit_type", "Tank").with("player", -1)),
+            Err(ConvertError::OutOfRange("player".to_owned()))
+        );
+    }
+
+    #[test]
+    fn players_and_attack_results_carry_their_values() {
+        let mut player = Player::new("Ada".to_owned(), Color::rgb(1.0, 0.0, 0.0));
+        player.set_resources(30);
+        let fields = Fields::from(&player);
+
+        assert_eq!(
+            fields.value("name"),
+            Ok(&FieldValue::Text("Ada".to_owned()))
+        );
+        assert_eq!(
+            fields.value("colour"),
+            Ok(&FieldValue::Colour(Color::rgb(1.0, 0.0, 0.0)))
+        );
+        assert_eq!(fields.get_i64("resources"), Ok(30));
+        assert_eq!(fields.value("observer"), Ok(&FieldValue::Bool(false)));
+
+        let result = AttackResult {
+            actual_damage: 4,
+            attacker: Unit::new(10, 5, 1, 1, 0, 1, 1, 0),
+            defender: Unit::new(6, 2, 1, 1, 1, 1, 1, 1),
+        };
+        let fields = Fields::from(&result);
+        let unit = |key: &str| match fields.value(key) {
+            Ok(FieldValue::Fields(unit)) => Unit::try_from(unit),
+            _ => panic!("{} is no dictionary", key),
+        };
+        assert_eq!(fields.get_i64("damage"), Ok(4));
+        assert_eq!(unit("attacker"), Ok(result.attacker));
+        assert_eq!(unit("defender"), Ok(result.defender));
+    }
+
+    #[test]
+    fn events_carry_the_ids_and_positions() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let entity = world.push((PersistentId(7),));
+        ids.register(PersistentId(7), entity);
+        let position = Hexagon::new_axial(1, -2);
+
+        let (name, fields) = event_signal(
+            &GameEvent::PickupCollected {
+                entity,
+                player: None,
+                pickup: Pickup::Repair(3),
+                position,
+            },
+            &ids,
+        )
+        .unwrap();
+        assert_eq!(name, "pickup_collected");
+        assert_eq!(Hexagon::try_from(&fields), Ok(position));
+        assert_eq!(fields.get_i64("id"), Ok(7));
+        assert_eq!(fields.get_i64("player"), Ok(-1));
+        assert_eq!(fields.get_i64("amount"), Ok(3));
+
+        let (_, fields) = event_signal(
+            &GameEvent::OrderInterrupted {
+                entity: world.push((position,)),
+                position,
+            },
+            &ids,
+        )
+        .unwrap();
+        assert_eq!(fields.get_i64("id"), Ok(-1));
+        assert_eq!(
+            event_signal(
+                &GameEvent::TurnStarted {
+                    round: 1,
+                    player: 0
+                },
+                &ids
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Ground effects, fires and smoke clouds that stay on their hexagon for a few rounds. Random
+//! events start them. At the start of every round `tick_ground_effects` burns the units standing
+//! in fires and counts the effects down, units that move into a fire get burnt on the way. Burns
+//! wear a unit down like attrition, they never destroy it.
+
+use crate::components::abilities::BlocksVision;
+use crate::components::ground_effect::GroundEffect;
+use crate::components::hexagon::Hexagon;
+use crate::components::node_template::NodeTemplate;
+use crate::components::unit::Unit;
+use crate::state_machine::GameEvent;
+use gdnative::core_types::Color;
+use legion::{Entity, EntityStore, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const FIRE_SCENE: &str = "res://Fire.tscn";
+const SMOKE_SCENE: &str = "res://Smoke.tscn";
+
+/// Integrity a wildfire burns every round.
+pub const WILDFIRE_DAMAGE: i32 = 2;
+/// Rounds a wildfire and its smoke last.
+pub const WILDFIRE_ROUNDS: u8 = 3;
+
+/// A ground effect in a savegame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedGroundEffect {
+    pub position: Hexagon,
+    pub effect: GroundEffect,
+}
+
+/// Places the effect on `hexagon`, with a Godot node if `with_node` is set. Smoke blocks the line
+/// of sight until it runs out.
+pub fn spawn_ground_effect(
+    world: &mut World,
+    hexagon: Hexagon,
+    effect: GroundEffect,
+    with_node: bool,
+) -> Entity {
+    let entity = world.push((hexagon, effect));
+    let mut entry = world.entry(entity).unwrap();
+    if let GroundEffect::Smoke { rounds_left } = effect {
+        entry.add_component(BlocksVision {
+            player: None,
+            rounds_remaining: rounds_left,
+        });
+    }
+    if with_node {
+        let scene = match effect {
+            GroundEffect::Fire { .. } => FIRE_SCENE,
+            GroundEffect::Smoke { .. } => SMOKE_SCENE,
+        };
+        entry.add_component(
+            NodeTemplate::new(scene)
+                .z_index(2)
+                .modulate(Color::rgba(1.0, 1.0, 1.0, 0.8))
+                .group("effects")
+                .parent("Effects")
+                .y_sort(),
+        );
+    }
+    entity
+}
+
+/// The damage the fires on `hexagon` deal to a unit there.
+pub fn fire_damage_at<S: EntityStore>(world: &S, hexagon: &Hexagon) -> i32 {
+    <(&Hexagon, &GroundEffect)>::query()
+        .iter(world)
+        .filter(|(position, _)| *position == hexagon)
+        .map(|(_, effect)| effect.damage())
+        .sum()
+}
+
+pub fn ground_effects<S: EntityStore>(world: &S) -> Vec<SavedGroundEffect> {
+    <(&Hexagon, &GroundEffect)>::query()
+        .iter(world)
+        .map(|(position, effect)| SavedGroundEffect {
+            position: *position,
+            effect: *effect,
+        })
+        .collect()
+}
+
+/// Burns the unit by the fires on its hexagon, down to 1 integrity at worst. `None` if it is not
+/// in a fire or has nothing left to burn.
+fn burn(world: &mut World, entity: Entity) -> Option<GameEvent> {
+    let position = *world
+        .entry_ref(entity)
+        .ok()?
+        .get_component::<Hexagon>()
+        .ok()?;
+    let damage = fire_damage_at(world, &position);
+    if damage <= 0 {
+        return None;
+    }
+    let mut entry = world.entry(entity)?;
+    let unit = entry.get_component_mut::<Unit>().ok()?;
+    let remaining_integrity = (unit.integrity - damage).max(1);
+    let damage = unit.integrity - remaining_integrity;
+    if damage <= 0 {
+        return None;
+    }
+    unit.integrity = remaining_integrity;
+    Some(GameEvent::FireDamage {
+        entity,
+        position,
+        damage,
+        remaining_integrity,
+    })
+}
+
+/// Burns the unit if it moved into a fire.
+pub fn burn_on_entry(world: &mut World, entity: Entity, events: &mut Vec<GameEvent>) {
+    events.extend(burn(world, entity));
+}
+
+/// Burns the units standing in fires and counts every effect down for a new round. Effects that
+/// run out are removed together with their nodes, which clears the sight through smoke.
+pub fn tick_ground_effects(world: &mut World, events: &mut Vec<GameEvent>) {
+    let fires: HashSet<Hexagon> = <(&Hexagon, &GroundEffect)>::query()
+        .iter(world)
+        .filter(|(_, effect)| effect.damage() > 0)
+        .map(|(position, _)| *position)
+        .collect();
+    let burning: Vec<Entity> = <(Entity, &Hexagon, &Unit)>::query()
+        .iter(world)
+        .filter(|(_, position, _)| fires.contains(position))
+        .map(|(entity, _, _)| *entity)
+        .collect();
+    for entity in burning {
+        events.extend(burn(world, entity));
+    }
+
+    let mut expired = Vec::new();
+    for (entity, effect, smoke) in
+        <(Entity, &mut GroundEffect, Option<&mut BlocksVision>)>::query().iter_mut(world)
+    {
+        if effect.tick() {
+            expired.push(*entity);
+        } else if let Some(smoke) = smoke {
+            smoke.rounds_remaining = effect.rounds_left();
+        }
+    }
+    for entity in expired {
+        world.remove(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_bounds::MapBounds;
+    use crate::systems::hexgrid::is_line_of_sight_clear;
+
+    fn integrity(world: &World, entity: Entity) -> i32 {
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap()
+            .integrity
+    }
+
+    #[test]
+    fn fires_burn_every_round_until_they_run_out() {
+        let mut world = World::default();
+        let position = Hexagon::zero();
+        let fire = GroundEffect::Fire {
+            damage_per_round: 3,
+            rounds_left: 2,
+        };
+        let effect = spawn_ground_effect(&mut world, position, fire, false);
+        let unit = world.push((position, Unit::new(7, 1, 1, 1, 0, 3, 3, 1)));
+        let bystander = world.push((Hexagon::new_axial(1, 0), Unit::new(7, 1, 1, 1, 0, 3, 3, 1)));
+        let mut events = Vec::new();
+
+        tick_ground_effects(&mut world, &mut events);
+        assert_eq!(
+            events,
+            vec![GameEvent::FireDamage {
+                entity: unit,
+                position,
+                damage: 3,
+                remaining_integrity: 4,
+            }]
+        );
+        assert_eq!(ground_effects(&world)[0].effect.rounds_left(), 1);
+
+        tick_ground_effects(&mut world, &mut events);
+        assert_eq!(integrity(&world, unit), 1);
+        assert_eq!(integrity(&world, bystander), 7);
+        assert!(world.entry_ref(effect).is_err());
+        assert!(ground_effects(&world).is_empty());
+
+        events.clear();
+        tick_ground_effects(&mut world, &mut events);
+        assert!(events.is_empty());
+        assert_eq!(integrity(&world, unit), 1);
+    }
+
+    #[test]
+    fn the_sight_clears_once_the_smoke_runs_out() {
+        let mut world = World::default();
+        let start = Hexagon::zero();
+        let end = Hexagon::new_axial(2, 0);
+        spawn_ground_effect(
+            &mut world,
+            Hexagon::new_axial(1, 0),
+            GroundEffect::Smoke { rounds_left: 2 },
+            false,
+        );
+        let mut events = Vec::new();
+
+        assert!(!is_line_of_sight_clear(
+            &start,
+            &end,
+            &MapBounds::default(),
+            &world
+        ));
+        tick_ground_effects(&mut world, &mut events);
+        assert!(!is_line_of_sight_clear(
+            &start,
+            &end,
+            &MapBounds::default(),
+            &world
+        ));
+        tick_ground_effects(&mut world, &mut events);
+        assert!(is_line_of_sight_clear(
+            &start,
+            &end,
+            &MapBounds::default(),
+            &world
+        ));
+        assert!(events.is_empty());
+    }
+}
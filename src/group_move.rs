@@ -0,0 +1,208 @@
+//! Orders for several selected units at once. A group move gives every unit its own destination
+//! around the clicked hexagon, and the units then walk there one after another, so each path is
+//! computed with the units that already arrived in place.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::edges::EdgeData;
+use crate::map_bounds::MapBounds;
+use crate::systems::hexgrid::{
+    find_path_within, get_2d_position_from_hex, get_hexagons_in_range, is_occupied_by_unit,
+};
+use gdnative::core_types::Vector2;
+use legion::{Entity, EntityStore, IntoQuery};
+use std::collections::vec_deque::VecDeque;
+use std::collections::HashSet;
+
+fn position_of<S: EntityStore>(world: &S, entity: Entity) -> Option<Hexagon> {
+    world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<Hexagon>().ok().copied())
+}
+
+/// Assigns every unit of the group a distinct free hexagon: the target first, then the rings
+/// around it. The unit closest to the target gets the closest slot, the result does not depend
+/// on the order of `members`. Units that find no slot nearby are left out.
+pub fn assign_slots<S: EntityStore>(
+    world: &S,
+    members: &[Entity],
+    target: Hexagon,
+) -> Vec<(Entity, Hexagon)> {
+    let mut units: Vec<(Entity, Hexagon)> = members
+        .iter()
+        .filter_map(|entity| position_of(world, *entity).map(|hexagon| (*entity, hexagon)))
+        .collect();
+    units.sort_by_key(|(_, hexagon)| {
+        (
+            hexagon.distance_to(&target),
+            hexagon.get_q(),
+            hexagon.get_r(),
+        )
+    });
+    let own_positions: HashSet<Hexagon> = units.iter().map(|(_, hexagon)| *hexagon).collect();
+
+    // Rings of radius r hold 6r hexagons, this leaves room for other units in the way.
+    let max_radius = units.len() as i32 + 1;
+    let mut slots = Vec::new();
+    let mut radius = 0;
+    while slots.len() < units.len() && radius <= max_radius {
+        let mut ring = get_hexagons_in_range(&target, radius, radius);
+        ring.sort_by_key(|hexagon| (hexagon.get_q(), hexagon.get_r()));
+        slots.extend(ring.into_iter().filter(|hexagon| {
+            own_positions.contains(hexagon) || !is_occupied_by_unit(hexagon, world)
+        }));
+        radius += 1;
+    }
+
+    units
+        .into_iter()
+        .zip(slots)
+        .map(|((entity, _), slot)| (entity, slot))
+        .collect()
+}
+
+/// Takes the next unit of a group move that still has a way to its slot and returns it with
+/// its path. Units that are gone, already there or blocked in are skipped.
+pub fn next_group_move<S: EntityStore>(
+    world: &S,
+    edges: &EdgeData,
+    bounds: &MapBounds,
+    queue: &mut VecDeque<(Entity, Hexagon)>,
+) -> Option<(Entity, Vec<Hexagon>)> {
+    while let Some((entity, destination)) = queue.pop_front() {
+        let start = match position_of(world, entity) {
+            None => continue,
+            Some(start) => start,
+        };
+        if start == destination {
+            continue;
+        }
+        let max_length = 2 * start.distance_to(&destination) + 2;
+        let path = find_path_within(&start, &destination, max_length, edges, bounds, world);
+        if !path.is_empty() {
+            return Some((entity, path));
+        }
+    }
+    None
+}
+
+/// Returns the units of `player` whose hexagon centre lies in the box spanned by the two corners,
+/// in view coordinates.
+pub fn units_in_box<S: EntityStore>(
+    world: &S,
+    player: usize,
+    corners: (Vector2, Vector2),
+    hexfield_size: f32,
+) -> Vec<Entity> {
+    let (first, second) = corners;
+    let (left, right) = (first.x.min(second.x), first.x.max(second.x));
+    let (top, bottom) = (first.y.min(second.y), first.y.max(second.y));
+    let mut units: Vec<(Entity, Hexagon)> = <(Entity, &PlayerComponent, &Hexagon, &Unit)>::query()
+        .iter(world)
+        .filter(|(_, owner, _, _)| owner.0 == player)
+        .filter(|(_, _, hexagon, _)| {
+            let centre = get_2d_position_from_hex(hexagon, hexfield_size);
+            (left..=right).contains(&centre.x) && (top..=bottom).contains(&centre.y)
+        })
+        .map(|(entity, _, hexagon, _)| (*entity, *hexagon))
+        .collect();
+    units.sort_by_key(|(_, hexagon)| (hexagon.get_q(), hexagon.get_r()));
+    units.into_iter().map(|(entity, _)| entity).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_state::{force_move, GameState, State};
+    use crate::player::Player;
+    use crate::state_machine::advance_state;
+    use gdnative::core_types::Color;
+    use legion::World;
+
+    fn spawn(world: &mut World, player: usize, q: i32, r: i32) -> Entity {
+        world.push((
+            PlayerComponent(player),
+            Hexagon::new_axial(q, r),
+            Unit::new(5, 1, 1, 1, 0, 8, 8, 1),
+        ))
+    }
+
+    #[test]
+    fn slots_are_assigned_nearest_unit_first() {
+        let mut world = World::default();
+        let far = spawn(&mut world, 0, -4, 0);
+        let near = spawn(&mut world, 0, 0, 3);
+        let middle = spawn(&mut world, 0, 3, -3);
+        let target = Hexagon::new_axial(0, 0);
+        // Occupied slots are skipped.
+        spawn(&mut world, 1, 0, -1);
+
+        let slots = assign_slots(&world, &[far, near, middle], target);
+
+        assert_eq!(
+            slots,
+            vec![
+                (near, target),
+                (middle, Hexagon::new_axial(-1, 0)),
+                (far, Hexagon::new_axial(-1, 1)),
+            ]
+        );
+        assert_eq!(assign_slots(&world, &[middle, far, near], target), slots);
+    }
+
+    #[test]
+    fn group_moves_one_unit_after_another() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.players.push(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.current_player = Some(0);
+        state.state = State::Waiting;
+        let members = vec![
+            spawn(&mut world, 0, -3, 0),
+            spawn(&mut world, 0, -3, 1),
+            spawn(&mut world, 0, -2, -1),
+        ];
+        let slots = assign_slots(&world, &members, Hexagon::new_axial(2, 0));
+        state.group_moves = slots.iter().copied().collect();
+
+        let mut moves_started = 0;
+        for _ in 0..200 {
+            if state.state.accepts_orders() {
+                match next_group_move(
+                    &world,
+                    &state.edges,
+                    &state.map_bounds,
+                    &mut state.group_moves,
+                ) {
+                    None => break,
+                    Some((entity, path)) => {
+                        moves_started += 1;
+                        force_move(&mut state, entity, path.into());
+                    }
+                }
+            }
+            advance_state(&mut world, &mut state, 0.05);
+        }
+
+        assert_eq!(moves_started, 3);
+        for (entity, slot) in slots {
+            assert_eq!(position_of(&world, entity), Some(slot));
+        }
+    }
+
+    #[test]
+    fn box_selects_own_units_inside() {
+        let mut world = World::default();
+        let inside = spawn(&mut world, 0, 0, 0);
+        spawn(&mut world, 1, 1, 0);
+        spawn(&mut world, 0, 5, 0);
+        let corners = (Vector2::new(-20.0, -20.0), Vector2::new(20.0, 20.0));
+
+        assert_eq!(units_in_box(&world, 0, corners, 40.0), vec![inside]);
+    }
+}
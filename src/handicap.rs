@@ -0,0 +1,143 @@
+//! Handicaps that balance players of different strength. They are set per player before the match
+//! and can no longer be changed once the first round is over.
+
+use crate::components::unit::Unit;
+use legion::{Entity, World};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Handicap {
+    /// Factor for the resources the player earns.
+    pub resource_multiplier: f32,
+    /// Extra integrity of the player's units, in percent of the integrity of their type.
+    pub integrity_bonus: i32,
+    /// How much a computer player prefers attacks over retreats, from 0 to 100.
+    pub aggression: u8,
+}
+
+impl Default for Handicap {
+    fn default() -> Self {
+        Handicap {
+            resource_multiplier: 1.0,
+            integrity_bonus: 0,
+            aggression: 50,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandicapError {
+    UnknownPlayer(usize),
+    /// The first round is over, handicaps are locked.
+    MatchStarted,
+    /// The named setting is out of its range.
+    InvalidValue(&'static str),
+}
+
+/// New values for some of the settings of a handicap, the others keep their value.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HandicapChanges {
+    pub resource_multiplier: Option<f64>,
+    pub integrity_bonus: Option<i64>,
+    pub aggression: Option<i64>,
+}
+
+impl HandicapChanges {
+    /// Returns `handicap` with the changes applied. The multiplier must not be negative, the
+    /// bonus must leave units with some integrity and the aggression is a percentage.
+    pub fn apply(&self, handicap: &Handicap) -> Result<Handicap, HandicapError> {
+        let mut changed = *handicap;
+        if let Some(multiplier) = self.resource_multiplier {
+            if !multiplier.is_finite() || multiplier < 0.0 {
+                return Err(HandicapError::InvalidValue("resource_multiplier"));
+            }
+            changed.resource_multiplier = multiplier as f32;
+        }
+        if let Some(bonus) = self.integrity_bonus {
+            if bonus <= -100 || bonus > 1000 {
+                return Err(HandicapError::InvalidValue("integrity_bonus"));
+            }
+            changed.integrity_bonus = bonus as i32;
+        }
+        if let Some(aggression) = self.aggression {
+            if !(0..=100).contains(&aggression) {
+                return Err(HandicapError::InvalidValue("aggression"));
+            }
+            changed.aggression = aggression as u8;
+        }
+        Ok(changed)
+    }
+}
+
+impl Handicap {
+    /// The integrity of a new unit whose type has `integrity`.
+    pub fn bonus_integrity(&self, integrity: i32) -> i32 {
+        self.rescale_integrity(integrity, &Handicap::default())
+    }
+
+    /// Moves the integrity of a unit that got the bonus of `previous` over to this bonus. Damage
+    /// taken so far keeps its share, like it does when the unit catalog is reloaded.
+    pub fn rescale_integrity(&self, integrity: i32, previous: &Handicap) -> i32 {
+        let (from, to) = (100 + previous.integrity_bonus, 100 + self.integrity_bonus);
+        ((integrity * to + from / 2) / from).max(1)
+    }
+}
+
+/// Gives a freshly spawned unit the integrity bonus of its owner.
+pub fn apply_integrity_bonus(world: &mut World, entity: Entity, handicap: &Handicap) {
+    if let Some(mut entry) = world.entry(entity) {
+        if let Ok(unit) = entry.get_component_mut::<Unit>() {
+            unit.integrity = handicap.bonus_integrity(unit.integrity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_keep_unset_values_and_reject_invalid_ones() {
+        let changes = HandicapChanges {
+            integrity_bonus: Some(25),
+            ..HandicapChanges::default()
+        };
+        let handicap = changes.apply(&Handicap::default()).unwrap();
+        assert_eq!(handicap.integrity_bonus, 25);
+        assert_eq!(handicap.aggression, 50);
+        assert_eq!(handicap.resource_multiplier, 1.0);
+
+        for invalid in &[
+            HandicapChanges {
+                aggression: Some(101),
+                ..HandicapChanges::default()
+            },
+            HandicapChanges {
+                resource_multiplier: Some(-0.5),
+                ..HandicapChanges::default()
+            },
+            HandicapChanges {
+                integrity_bonus: Some(-100),
+                ..HandicapChanges::default()
+            },
+        ] {
+            assert!(matches!(
+                invalid.apply(&handicap),
+                Err(HandicapError::InvalidValue(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn integrity_bonus_scales_fresh_and_damaged_units() {
+        let stronger = Handicap {
+            integrity_bonus: 50,
+            ..Handicap::default()
+        };
+        assert_eq!(stronger.bonus_integrity(20), 30);
+        assert_eq!(Handicap::default().bonus_integrity(20), 20);
+        // A unit at half integrity stays at half.
+        assert_eq!(Handicap::default().rescale_integrity(15, &stronger), 10);
+    }
+}
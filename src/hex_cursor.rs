@@ -0,0 +1,248 @@
+//! A hexagon cursor for keyboards and gamepads. Six actions move it to the neighbouring
+//! hexagons, accepting it acts like a left click on its hexagon and cancelling like a right click.
+
+use crate::components::hexagon::{Direction, Hexagon};
+use crate::input_actions::{
+    InputAction, CURSOR_E, CURSOR_N, CURSOR_NE, CURSOR_NW, CURSOR_S, CURSOR_SE, CURSOR_SW, CURSOR_W,
+};
+use crate::map_bounds::MapBounds;
+use gdnative::core_types::Vector2;
+
+/// Stick deflections below this length do not point anywhere.
+const STICK_DEAD_ZONE: f32 = 0.5;
+
+/// How the hexagons of the grid are laid out on screen. The names of the move actions follow
+/// the screen, so they map to different neighbours for both layouts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    PointyTop,
+    FlatTop,
+}
+
+impl Orientation {
+    /// The move actions and the neighbour each one moves to.
+    pub fn move_actions(self) -> [(InputAction, Direction); 6] {
+        match self {
+            Orientation::PointyTop => [
+                (CURSOR_E, Direction::East),
+                (CURSOR_NE, Direction::NorthEast),
+                (CURSOR_NW, Direction::NorthWest),
+                (CURSOR_W, Direction::West),
+                (CURSOR_SW, Direction::SouthWest),
+                (CURSOR_SE, Direction::SouthEast),
+            ],
+            Orientation::FlatTop => [
+                (CURSOR_SE, Direction::East),
+                (CURSOR_NE, Direction::NorthEast),
+                (CURSOR_N, Direction::NorthWest),
+                (CURSOR_NW, Direction::West),
+                (CURSOR_SW, Direction::SouthWest),
+                (CURSOR_S, Direction::SouthEast),
+            ],
+        }
+    }
+
+    /// Returns the neighbour closest to where the stick points, `stick` is in screen
+    /// coordinates with y pointing down.
+    pub fn direction_for_stick(self, stick: Vector2) -> Option<Direction> {
+        if stick.length() < STICK_DEAD_ZONE {
+            return None;
+        }
+        let angle = (-stick.y).atan2(stick.x).to_degrees();
+        // Pointy-top neighbours lie at multiples of 60 degrees from east, flat-top ones are
+        // turned by 30 degrees and start with the east direction of the cube coordinates.
+        let sector = match self {
+            Orientation::PointyTop => (angle / 60.0).round() as i32,
+            Orientation::FlatTop => ((angle - 30.0) / 60.0).round() as i32 + 1,
+        };
+        Some(Direction::ALL[sector.rem_euclid(6) as usize])
+    }
+}
+
+/// Returns `hexagon`, or the hexagon on the edge of the map towards it if it lies outside.
+pub fn clamp_to_map(hexagon: Hexagon, radius: i32) -> Hexagon {
+    let radius = radius.max(0);
+    if hexagon.distance_to(&Hexagon::zero()) <= radius {
+        hexagon
+    } else {
+        Hexagon::zero().line_to(&hexagon)[radius as usize]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HexCursor {
+    pub hexagon: Hexagon,
+    /// Shown once the cursor is used, the mouse hides it again.
+    pub visible: bool,
+    pub orientation: Orientation,
+    map_radius: i32,
+    /// Where the stick pointed last, the cursor moves once per tilt.
+    stick_direction: Option<Direction>,
+    /// The selection the cursor last snapped to.
+    selection_generation: u64,
+}
+
+impl HexCursor {
+    pub fn new(map_radius: u32) -> Self {
+        HexCursor {
+            hexagon: Hexagon::zero(),
+            visible: false,
+            orientation: Orientation::PointyTop,
+            map_radius: map_radius as i32,
+            stick_direction: None,
+            selection_generation: 0,
+        }
+    }
+
+    /// Keeps the cursor on a map of the new radius.
+    pub fn set_map_radius(&mut self, map_radius: u32) {
+        self.map_radius = map_radius as i32;
+        self.hexagon = clamp_to_map(self.hexagon, self.map_radius);
+    }
+
+    /// Moves the cursor to the neighbour, unless that lies off the map.
+    pub fn move_towards(&mut self, direction: Direction, bounds: &MapBounds) {
+        self.visible = true;
+        let next = clamp_to_map(self.hexagon.get_neighbour(direction), self.map_radius);
+        if bounds.contains(&next) {
+            self.hexagon = next;
+        }
+    }
+
+    /// Moves the cursor when the stick is tilted into a new direction. Returns whether it moved.
+    pub fn tilt_stick(&mut self, direction: Option<Direction>, bounds: &MapBounds) -> bool {
+        let tilted = direction.is_some() && direction != self.stick_direction;
+        self.stick_direction = direction;
+        match direction {
+            Some(direction) if tilted => {
+                self.move_towards(direction, bounds);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Puts the cursor on the selected unit whenever the selection changes.
+    pub fn follow_selection(
+        &mut self,
+        generation: u64,
+        selected: Option<Hexagon>,
+        bounds: &MapBounds,
+    ) {
+        if generation == self.selection_generation {
+            return;
+        }
+        self.selection_generation = generation;
+        if let Some(hexagon) = selected {
+            self.hexagon = bounds.clamp(clamp_to_map(hexagon, self.map_radius));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direction_for_action(orientation: Orientation, action: &str) -> Option<Direction> {
+        orientation
+            .move_actions()
+            .iter()
+            .find(|(move_action, _)| move_action.name() == action)
+            .map(|(_, direction)| *direction)
+    }
+
+    #[test]
+    fn actions_map_to_the_neighbours_on_screen() {
+        let pointy = Orientation::PointyTop;
+        assert_eq!(
+            direction_for_action(pointy, "cursor_e"),
+            Some(Direction::East)
+        );
+        assert_eq!(
+            direction_for_action(pointy, "cursor_sw"),
+            Some(Direction::SouthWest)
+        );
+        assert_eq!(direction_for_action(pointy, "cursor_n"), None);
+
+        let flat = Orientation::FlatTop;
+        assert_eq!(
+            direction_for_action(flat, "cursor_n"),
+            Some(Direction::NorthWest)
+        );
+        assert_eq!(
+            direction_for_action(flat, "cursor_se"),
+            Some(Direction::East)
+        );
+        assert_eq!(direction_for_action(flat, "cursor_e"), None);
+    }
+
+    #[test]
+    fn stick_maps_to_the_dominant_direction() {
+        let right = Vector2::new(1.0, 0.1);
+        let up = Vector2::new(0.1, -1.0);
+        let down_left = Vector2::new(-0.5, 0.8);
+
+        let pointy = Orientation::PointyTop;
+        assert_eq!(pointy.direction_for_stick(right), Some(Direction::East));
+        assert_eq!(pointy.direction_for_stick(up), Some(Direction::NorthEast));
+        assert_eq!(
+            pointy.direction_for_stick(down_left),
+            Some(Direction::SouthWest)
+        );
+
+        let flat = Orientation::FlatTop;
+        assert_eq!(flat.direction_for_stick(right), Some(Direction::East));
+        assert_eq!(flat.direction_for_stick(up), Some(Direction::NorthWest));
+        assert_eq!(
+            flat.direction_for_stick(down_left),
+            Some(Direction::SouthWest)
+        );
+
+        assert_eq!(pointy.direction_for_stick(Vector2::new(0.2, 0.2)), None);
+    }
+
+    #[test]
+    fn cursor_stays_on_the_map() {
+        let mut cursor = HexCursor::new(2);
+        cursor.move_towards(Direction::East, &MapBounds::default());
+        cursor.move_towards(Direction::East, &MapBounds::default());
+        cursor.move_towards(Direction::East, &MapBounds::default());
+
+        assert_eq!(cursor.hexagon, Hexagon::new_axial(2, 0));
+        assert!(cursor.visible);
+        assert_eq!(
+            clamp_to_map(Hexagon::new_axial(-6, 3), 2),
+            Hexagon::new_axial(-2, 1)
+        );
+
+        // Hexagons the map does not have are skipped as well.
+        let bounds = MapBounds::new(vec![Hexagon::zero(), Hexagon::new_axial(0, 1)]);
+        let mut cursor = HexCursor::new(2);
+        cursor.move_towards(Direction::East, &bounds);
+        assert_eq!(cursor.hexagon, Hexagon::zero());
+        cursor.move_towards(Direction::SouthEast, &bounds);
+        assert_eq!(cursor.hexagon, Hexagon::new_axial(0, 1));
+    }
+
+    #[test]
+    fn stick_moves_once_per_tilt() {
+        let mut cursor = HexCursor::new(10);
+
+        assert!(cursor.tilt_stick(Some(Direction::West), &MapBounds::default()));
+        assert!(!cursor.tilt_stick(Some(Direction::West), &MapBounds::default()));
+        assert!(!cursor.tilt_stick(None, &MapBounds::default()));
+        assert!(cursor.tilt_stick(Some(Direction::West), &MapBounds::default()));
+
+        assert_eq!(cursor.hexagon, Hexagon::new_axial(-2, 0));
+    }
+
+    #[test]
+    fn cursor_snaps_to_a_new_selection() {
+        let mut cursor = HexCursor::new(10);
+        cursor.follow_selection(1, Some(Hexagon::new_axial(3, -1)), &MapBounds::default());
+        cursor.move_towards(Direction::East, &MapBounds::default());
+        cursor.follow_selection(1, Some(Hexagon::new_axial(3, -1)), &MapBounds::default());
+
+        assert_eq!(cursor.hexagon, Hexagon::new_axial(4, -1));
+    }
+}
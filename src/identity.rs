@@ -0,0 +1,151 @@
+//! What makes a unit the one its player knows: its name, experience, perks and history. Flows
+//! that build the components of a unit anew, like loading a game, or change one of them, like a
+//! promotion, carry these components as one `UnitIdentity` so none of them is dropped on the way.
+
+use crate::components::history::History;
+use crate::components::perks::{Experience, Perks};
+use crate::components::unit_name::UnitName;
+use legion::{Entity, EntityStore, World};
+
+/// The identity components of a unit, `None` for those it does not have.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnitIdentity {
+    pub name: Option<UnitName>,
+    pub experience: Option<Experience>,
+    pub perks: Option<Perks>,
+    pub history: Option<History>,
+}
+
+impl UnitIdentity {
+    /// The identity of `entity`, `None` if it is not in the world.
+    pub fn of<S: EntityStore>(world: &S, entity: Entity) -> Option<Self> {
+        let entry = world.entry_ref(entity).ok()?;
+        Some(UnitIdentity {
+            name: entry.get_component::<UnitName>().ok().cloned(),
+            experience: entry.get_component::<Experience>().ok().copied(),
+            perks: entry.get_component::<Perks>().ok().cloned(),
+            history: entry.get_component::<History>().ok().cloned(),
+        })
+    }
+
+    /// Gives `entity` this identity as a whole. Identity components it has that this identity
+    /// lacks are removed, so two identities never mix. Returns false if the entity is gone.
+    pub fn apply(&self, world: &mut World, entity: Entity) -> bool {
+        let mut entry = match world.entry(entity) {
+            None => return false,
+            Some(entry) => entry,
+        };
+        match &self.name {
+            Some(name) => entry.add_component(name.clone()),
+            None => entry.remove_component::<UnitName>(),
+        }
+        match self.experience {
+            Some(experience) => entry.add_component(experience),
+            None => entry.remove_component::<Experience>(),
+        }
+        match &self.perks {
+            Some(perks) => entry.add_component(perks.clone()),
+            None => entry.remove_component::<Perks>(),
+        }
+        match &self.history {
+            Some(history) => entry.add_component(history.clone()),
+            None => entry.remove_component::<History>(),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildings::{enter_garrison, leave_garrison};
+    use crate::components::hexagon::Hexagon;
+    use crate::components::history::{HistoryEntry, HistoryEvent};
+    use crate::components::perks::PerkId;
+    use crate::components::persistent_id::PersistentIds;
+    use crate::components::unit::Unit;
+    use crate::promotions::promote;
+    use crate::spawn::spawn_unit;
+
+    fn spawn(world: &mut World, ids: &mut PersistentIds, q: i32) -> Entity {
+        spawn_unit(
+            world,
+            ids,
+            0,
+            Hexagon::new_axial(q, 0),
+            Unit::new(10, 2, 1, 1, 0, 3, 3, 1),
+            None,
+        )
+    }
+
+    fn decorate(world: &mut World, entity: Entity, name: &str, kills: u32, perks: Vec<PerkId>) {
+        let mut history = History::default();
+        history.push(HistoryEntry {
+            round: kills,
+            event: HistoryEvent::Kill {
+                position: Hexagon::new_axial(kills as i32, 1),
+                player: Some(1),
+            },
+        });
+        history.rounds_survived = kills + 2;
+        UnitIdentity {
+            name: Some(UnitName(name.to_owned())),
+            experience: Some(Experience { kills }),
+            perks: Some(Perks(perks)),
+            history: Some(history),
+        }
+        .apply(world, entity);
+    }
+
+    fn identity(world: &World, entity: Entity) -> UnitIdentity {
+        UnitIdentity::of(world, entity).unwrap()
+    }
+
+    #[test]
+    fn garrisoned_units_keep_their_identity_untouched() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let passenger = spawn(&mut world, &mut ids, 0);
+        decorate(
+            &mut world,
+            passenger,
+            "Long Tom",
+            3,
+            vec![PerkId::Mobility, PerkId::Integrity],
+        );
+        let original = identity(&world, passenger);
+
+        // Boarding a building and leaving it again keeps the entity and everything on it.
+        assert!(enter_garrison(&mut world, passenger));
+        assert!(leave_garrison(
+            &mut world,
+            passenger,
+            Hexagon::new_axial(1, -1)
+        ));
+        assert_eq!(identity(&world, passenger), original);
+    }
+
+    #[test]
+    fn promotions_keep_the_rest_of_the_identity() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let entity = spawn(&mut world, &mut ids, 0);
+        decorate(&mut world, entity, "Long Tom", 2, vec![PerkId::Damage]);
+        let before = identity(&world, entity);
+
+        promote(&mut world, entity, PerkId::Mobility);
+
+        let after = identity(&world, entity);
+        assert_eq!(
+            after.perks,
+            Some(Perks(vec![PerkId::Damage, PerkId::Mobility]))
+        );
+        assert_eq!(
+            UnitIdentity {
+                perks: before.perks.clone(),
+                ..after
+            },
+            before
+        );
+    }
+}
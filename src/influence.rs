@@ -0,0 +1,76 @@
+//! The influence map behind the territory overlay. Which player dominates a hexagon only changes
+//! when units move, die or appear, so the map is kept until it is marked dirty.
+
+use crate::components::hexagon::Hexagon;
+use crate::systems::hexgrid::compute_influence;
+use legion::EntityStore;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug)]
+pub struct InfluenceMap {
+    claims: BTreeMap<Hexagon, (usize, f32)>,
+    dirty: bool,
+}
+
+impl Default for InfluenceMap {
+    fn default() -> Self {
+        InfluenceMap {
+            claims: BTreeMap::new(),
+            dirty: true,
+        }
+    }
+}
+
+impl InfluenceMap {
+    /// Recomputes the map the next time it is read.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// The player claiming each hexagon with their influence there, see `compute_influence`.
+    pub fn claims<S: EntityStore>(&mut self, world: &S) -> &BTreeMap<Hexagon, (usize, f32)> {
+        if self.dirty {
+            self.claims = compute_influence(world);
+            self.dirty = false;
+        }
+        &self.claims
+    }
+
+    pub fn claim<S: EntityStore>(&mut self, world: &S, hexagon: &Hexagon) -> Option<(usize, f32)> {
+        self.claims(world).get(hexagon).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::player::Player;
+    use crate::components::unit::Unit;
+    use legion::World;
+
+    #[test]
+    fn map_is_only_recomputed_when_dirty() {
+        let mut world = World::default();
+        let unit = world.push((
+            Hexagon::zero(),
+            Unit::new(1, 1, 1, 1, 0, 1, 1, 1),
+            Player(0),
+        ));
+        let mut influence = InfluenceMap::default();
+        let far = Hexagon::new_axial(6, 0);
+
+        assert_eq!(influence.claim(&world, &Hexagon::zero()), Some((0, 1.0)));
+        assert_eq!(influence.claim(&world, &far), None);
+
+        *world
+            .entry(unit)
+            .unwrap()
+            .get_component_mut::<Hexagon>()
+            .unwrap() = far;
+        assert_eq!(influence.claim(&world, &far), None);
+
+        influence.mark_dirty();
+        assert_eq!(influence.claim(&world, &far), Some((0, 1.0)));
+        assert_eq!(influence.claim(&world, &Hexagon::zero()), None);
+    }
+}
@@ -0,0 +1,137 @@
+//! Turn order of the initiative rules. Every round all units act one after another, ordered by
+//! their initiative across all players. Ties go to the unit with the lower persistent id.
+
+use crate::components::initiative::Initiative;
+use crate::components::persistent_id::PersistentId;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use legion::{Entity, EntityStore, IntoQuery};
+use std::cmp::Reverse;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InitiativeQueue {
+    /// The units of the round, in the order they act.
+    order: Vec<Entity>,
+    /// Index of the unit whose turn it is.
+    active: usize,
+}
+
+impl InitiativeQueue {
+    /// Orders every living unit of a player for a new round.
+    pub fn build<S: EntityStore>(world: &S) -> Self {
+        let mut units: Vec<(Entity, i32, Option<PersistentId>)> = <(
+            Entity,
+            &PlayerComponent,
+            &Unit,
+            Option<&Initiative>,
+            Option<&PersistentId>,
+        )>::query()
+        .iter(world)
+        .map(|(entity, _, _, initiative, id)| {
+            (
+                *entity,
+                initiative.map_or(0, |initiative| initiative.0),
+                id.copied(),
+            )
+        })
+        .collect();
+        units.sort_by_key(|(_, initiative, id)| (Reverse(*initiative), *id));
+        InitiativeQueue {
+            order: units.into_iter().map(|(entity, _, _)| entity).collect(),
+            active: 0,
+        }
+    }
+
+    /// The unit whose turn it is, `None` once every unit of the round had its turn.
+    pub fn active(&self) -> Option<Entity> {
+        self.order.get(self.active).copied()
+    }
+
+    /// The units that still act this round, starting with the active one.
+    pub fn remaining(&self) -> &[Entity] {
+        &self.order[self.active.min(self.order.len())..]
+    }
+
+    /// Ends the turn of the active unit and returns the next one. Units destroyed in the meantime
+    /// are skipped, units added during the round act after everyone else.
+    pub fn advance<S: EntityStore>(&mut self, world: &S) -> Option<Entity> {
+        if self.active >= self.order.len() {
+            return None;
+        }
+        self.active += 1;
+        self.sync(world);
+        self.active()
+    }
+
+    fn sync<S: EntityStore>(&mut self, world: &S) {
+        let (active, mut index) = (self.active, 0);
+        let mut removed_before_active = 0;
+        self.order.retain(|entity| {
+            let alive = world.entry_ref(*entity).is_ok();
+            if !alive && index < active {
+                removed_before_active += 1;
+            }
+            index += 1;
+            alive
+        });
+        self.active -= removed_before_active;
+        let known = self.order.clone();
+        let mut added: Vec<(Entity, Option<PersistentId>)> =
+            <(Entity, &PlayerComponent, &Unit, Option<&PersistentId>)>::query()
+                .iter(world)
+                .filter(|(entity, _, _, _)| !known.contains(*entity))
+                .map(|(entity, _, _, id)| (*entity, id.copied()))
+                .collect();
+        added.sort_by_key(|(_, id)| *id);
+        self.order
+            .extend(added.into_iter().map(|(entity, _)| entity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion::World;
+
+    fn spawn(world: &mut World, initiative: i32, id: u64) -> Entity {
+        world.push((
+            PlayerComponent(0),
+            Unit::new(5, 1, 1, 1, 0, 3, 3, 1),
+            Initiative(initiative),
+            PersistentId(id),
+        ))
+    }
+
+    #[test]
+    fn units_are_ordered_by_initiative_then_id() {
+        let mut world = World::default();
+        let slow = spawn(&mut world, 1, 0);
+        let fast_late = spawn(&mut world, 5, 4);
+        let fast_early = spawn(&mut world, 5, 2);
+        let average = spawn(&mut world, 3, 1);
+
+        let queue = InitiativeQueue::build(&world);
+
+        assert_eq!(queue.remaining(), &[fast_early, fast_late, average, slow]);
+        assert_eq!(queue.active(), Some(fast_early));
+    }
+
+    #[test]
+    fn destroyed_units_drop_out_and_new_units_join_at_the_end() {
+        let mut world = World::default();
+        let first = spawn(&mut world, 3, 0);
+        let second = spawn(&mut world, 2, 1);
+        let third = spawn(&mut world, 1, 2);
+        let mut queue = InitiativeQueue::build(&world);
+
+        // The active unit destroys the next one in line and a fast unit is produced.
+        world.remove(second);
+        let produced = spawn(&mut world, 9, 3);
+
+        assert_eq!(queue.advance(&world), Some(third));
+        world.remove(first);
+        assert_eq!(queue.advance(&world), Some(produced));
+        assert_eq!(queue.advance(&world), None);
+        assert!(queue.remaining().is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+//! The named input actions of the project settings the game responds to. Every action comes with
+//! a description for the help overlay, which lists the actions with the keys and buttons bound
+//! to them.
+
+use gdnative::api::{InputEventJoypadButton, InputMap};
+use gdnative::prelude::*;
+
+/// An input action with what it does. Only made with `InputAction::new`, so there is no action
+/// without a description.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputAction {
+    name: &'static str,
+    description: &'static str,
+}
+
+impl InputAction {
+    pub const fn new(name: &'static str, description: &'static str) -> Self {
+        InputAction { name, description }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.name
+    }
+
+    pub fn description(self) -> &'static str {
+        self.description
+    }
+}
+
+pub const RESELECT_LAST: InputAction =
+    InputAction::new("reselect_last", "Select the unit selected last again");
+pub const ATTACK_MODE: InputAction = InputAction::new(
+    "attack_mode",
+    "Pick the target of an attack of the selected unit",
+);
+pub const TOGGLE_HELP: InputAction =
+    InputAction::new("toggle_help", "Show or hide this list of controls");
+pub const CURSOR_ACCEPT: InputAction = InputAction::new(
+    "cursor_accept",
+    "Act on the hexagon of the cursor, like a left click",
+);
+pub const CURSOR_CANCEL: InputAction = InputAction::new(
+    "cursor_cancel",
+    "Cancel on the hexagon of the cursor, like a right click",
+);
+pub const CURSOR_N: InputAction = InputAction::new("cursor_n", "Move the cursor up");
+pub const CURSOR_NE: InputAction = InputAction::new("cursor_ne", "Move the cursor up and right");
+pub const CURSOR_E: InputAction = InputAction::new("cursor_e", "Move the cursor right");
+pub const CURSOR_SE: InputAction = InputAction::new("cursor_se", "Move the cursor down and right");
+pub const CURSOR_S: InputAction = InputAction::new("cursor_s", "Move the cursor down");
+pub const CURSOR_SW: InputAction = InputAction::new("cursor_sw", "Move the cursor down and left");
+pub const CURSOR_W: InputAction = InputAction::new("cursor_w", "Move the cursor left");
+pub const CURSOR_NW: InputAction = InputAction::new("cursor_nw", "Move the cursor up and left");
+
+/// Every action the game responds to, in the order of the help.
+pub const INPUT_ACTIONS: &[InputAction] = &[
+    RESELECT_LAST,
+    ATTACK_MODE,
+    TOGGLE_HELP,
+    CURSOR_ACCEPT,
+    CURSOR_CANCEL,
+    CURSOR_N,
+    CURSOR_NE,
+    CURSOR_E,
+    CURSOR_SE,
+    CURSOR_S,
+    CURSOR_SW,
+    CURSOR_W,
+    CURSOR_NW,
+];
+
+/// An action with the names of the keys and buttons bound to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActionBindings {
+    pub action: InputAction,
+    pub bindings: Vec<String>,
+}
+
+impl ActionBindings {
+    /// The line of the action in the help overlay.
+    pub fn line(&self) -> String {
+        let keys = if self.bindings.is_empty() {
+            "-".to_owned()
+        } else {
+            self.bindings.join(", ")
+        };
+        format!("{}: {}", keys, self.action.description())
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("name", self.action.name());
+        dict.insert("description", self.action.description());
+        let bindings = VariantArray::new();
+        for binding in &self.bindings {
+            bindings.push(binding.as_str());
+        }
+        dict.insert("bindings", bindings.into_shared());
+        dict
+    }
+}
+
+/// Every action with its bindings, as `bindings_of` names them.
+pub fn list_actions<F>(mut bindings_of: F) -> Vec<ActionBindings>
+where
+    F: FnMut(&str) -> Vec<String>,
+{
+    INPUT_ACTIONS
+        .iter()
+        .map(|action| ActionBindings {
+            action: *action,
+            bindings: bindings_of(action.name()),
+        })
+        .collect()
+}
+
+/// The names of the events the input map binds to `action`, none for an action missing from the
+/// project settings.
+pub fn current_bindings(action: &str) -> Vec<String> {
+    let input_map = InputMap::godot_singleton();
+    if !input_map.has_action(action) {
+        return Vec::new();
+    }
+    input_map
+        .get_action_list(action)
+        .iter()
+        .filter_map(|event| event.try_to_object::<InputEvent>())
+        .map(|event| {
+            let event = unsafe { event.assume_safe() };
+            match event.cast::<InputEventJoypadButton>() {
+                Some(button) => format!("Joypad button {}", button.button_index()),
+                None => event.as_text().to_string(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const PROJECT: &str = include_str!("../project.godot");
+
+    #[test]
+    fn the_listing_has_every_action_with_a_description() {
+        let listing = list_actions(|action| vec![format!("key of {}", action)]);
+
+        assert_eq!(listing.len(), INPUT_ACTIONS.len());
+        let names: HashSet<&str> = listing.iter().map(|entry| entry.action.name()).collect();
+        assert_eq!(names.len(), INPUT_ACTIONS.len());
+        for (entry, action) in listing.iter().zip(INPUT_ACTIONS) {
+            assert_eq!(entry.action, *action);
+            assert!(!action.description().is_empty(), "{}", action.name());
+            assert_eq!(entry.bindings, vec![format!("key of {}", action.name())]);
+        }
+        assert_eq!(
+            listing[2].line(),
+            "key of toggle_help: Show or hide this list of controls"
+        );
+        assert_eq!(
+            list_actions(|_| Vec::new())[0].line(),
+            "-: Select the unit selected last again"
+        );
+    }
+
+    #[test]
+    fn every_action_is_in_the_project_settings() {
+        let input = PROJECT
+            .split("[input]")
+            .nth(1)
+            .and_then(|section| section.split("\n[").next())
+            .unwrap();
+        let defined: HashSet<&str> = input
+            .lines()
+            .filter_map(|line| line.strip_suffix("={"))
+            .collect();
+
+        for action in INPUT_ACTIONS {
+            assert!(defined.contains(action.name()), "{}", action.name());
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Clicks made while an action resolves. The last one is kept and replayed once the game accepts
+//! input again, so clicking ahead during a move does not act on a world that is still changing.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::unit::Unit;
+use crate::game_state::State;
+use crate::systems::hexgrid::get_entities_at_hexagon;
+use legion::{Entity, EntityStore};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PendingClick {
+    hexagon: Hexagon,
+    /// The unit that was on the hexagon when it was clicked.
+    target: Option<Entity>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InputBuffer {
+    pending: Option<PendingClick>,
+}
+
+impl InputBuffer {
+    /// Remembers a click, replacing any older one.
+    pub fn buffer<S: EntityStore>(&mut self, world: &S, hexagon: Hexagon) {
+        let target = get_entities_at_hexagon(&hexagon, world)
+            .into_iter()
+            .find(|entity| {
+                world
+                    .entry_ref(*entity)
+                    .map_or(false, |entry| entry.get_component::<Unit>().is_ok())
+            });
+        self.pending = Some(PendingClick { hexagon, target });
+    }
+
+    pub fn clear(&mut self) {
+        self.pending = None;
+    }
+
+    /// Returns the buffered click once `state` accepts clicks again. It is dropped if the
+    /// clicked unit was removed meanwhile or the game moved on to a state the click was not made
+    /// for, like the next turn.
+    pub fn take_ready<S: EntityStore>(&mut self, world: &S, state: &State) -> Option<Hexagon> {
+        match state {
+            _ if state.is_resolving() => None,
+            _ if state.accepts_orders() => {
+                let click = self.pending.take()?;
+                match click.target {
+                    Some(target) if world.entry_ref(target).is_err() => None,
+                    _ => Some(click.hexagon),
+                }
+            }
+            _ => {
+                self.pending = None;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion::World;
+
+    fn moving(entity: Entity) -> State {
+        State::Moving(entity)
+    }
+
+    #[test]
+    fn click_is_replayed_once_the_action_resolved() {
+        let mut world = World::default();
+        let unit = world.push((Hexagon::zero(), Unit::new(1, 1, 1, 1, 0, 1, 1, 1)));
+        let mut buffer = InputBuffer::default();
+
+        buffer.buffer(&world, Hexagon::new_axial(2, 0));
+
+        assert_eq!(buffer.take_ready(&world, &moving(unit)), None);
+        assert_eq!(
+            buffer.take_ready(&world, &State::Selected(unit)),
+            Some(Hexagon::new_axial(2, 0))
+        );
+        assert_eq!(buffer.take_ready(&world, &State::Selected(unit)), None);
+    }
+
+    #[test]
+    fn newer_click_replaces_the_buffered_one() {
+        let world = World::default();
+        let mut buffer = InputBuffer::default();
+
+        buffer.buffer(&world, Hexagon::new_axial(1, 0));
+        buffer.buffer(&world, Hexagon::new_axial(0, 1));
+
+        assert_eq!(
+            buffer.take_ready(&world, &State::Waiting),
+            Some(Hexagon::new_axial(0, 1))
+        );
+    }
+
+    #[test]
+    fn click_on_a_destroyed_unit_is_dropped() {
+        let mut world = World::default();
+        let attacker = world.push((Hexagon::zero(), Unit::new(1, 1, 1, 1, 0, 1, 1, 1)));
+        let defender = world.push((Hexagon::new_axial(1, 0), Unit::new(1, 1, 1, 1, 0, 1, 1, 1)));
+        let mut buffer = InputBuffer::default();
+
+        buffer.buffer(&world, Hexagon::new_axial(1, 0));
+        world.remove(defender);
+
+        assert_eq!(buffer.take_ready(&world, &State::Selected(attacker)), None);
+        assert_eq!(buffer.take_ready(&world, &State::Waiting), None);
+    }
+
+    #[test]
+    fn click_is_dropped_when_the_turn_ends() {
+        let world = World::default();
+        let mut buffer = InputBuffer::default();
+
+        buffer.buffer(&world, Hexagon::new_axial(1, 0));
+
+        assert_eq!(buffer.take_ready(&world, &State::NewRound), None);
+        assert_eq!(buffer.take_ready(&world, &State::Waiting), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Where the clicks on hexagons come from. A scene may report them with the hex signals of the
+//! parent of the game world, each carrying a dictionary with the `q` and `r` of the hexagon, or
+//! leave it to the game world to pick the hexagon under the mouse itself. The automatic mode uses
+//! the signals only when the parent has all of them, so a scene without them stays playable.
+
+/// The signals of the parent that report clicks on and hovering over hexagons.
+pub const HEX_SIGNALS: [&str; 4] = [
+    "hex_left_clicked",
+    "hex_right_clicked",
+    "hex_mouse_entered",
+    "hex_mouse_exited",
+];
+
+/// The `input_mode` property of the game world.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    /// The signals of the parent if it has all of them, the mouse otherwise.
+    Auto,
+    Signals,
+    Direct,
+}
+
+impl InputMode {
+    /// The mode with the value of the property: 0 automatic, 1 signals, 2 direct.
+    pub fn from_index(index: i64) -> Option<Self> {
+        match index {
+            0 => Some(InputMode::Auto),
+            1 => Some(InputMode::Signals),
+            2 => Some(InputMode::Direct),
+            _ => None,
+        }
+    }
+}
+
+/// Where the clicks on hexagons are taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputSource {
+    /// The hex signals of the parent.
+    Signals,
+    /// The hexagon under the mouse, picked by the game world.
+    Direct,
+}
+
+impl Default for InputSource {
+    fn default() -> Self {
+        InputSource::Direct
+    }
+}
+
+impl InputSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            InputSource::Signals => "signals",
+            InputSource::Direct => "direct",
+        }
+    }
+}
+
+/// The source of the clicks with the hex signals the parent lacks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputSelection {
+    pub source: InputSource,
+    /// Empty if the parent has every signal or the direct mode did not look for them.
+    pub missing: Vec<&'static str>,
+}
+
+/// Picks the source of the clicks for `mode`, `has_signal` tells the signals of the parent.
+/// Forcing the signals keeps them even if some are missing, those are still reported.
+pub fn select_input_source<F>(mode: InputMode, has_signal: F) -> InputSelection
+where
+    F: Fn(&str) -> bool,
+{
+    if mode == InputMode::Direct {
+        return InputSelection {
+            source: InputSource::Direct,
+            missing: Vec::new(),
+        };
+    }
+    let missing: Vec<&'static str> = HEX_SIGNALS
+        .iter()
+        .copied()
+        .filter(|signal| !has_signal(signal))
+        .collect();
+    let source = if mode == InputMode::Signals || missing.is_empty() {
+        InputSource::Signals
+    } else {
+        InputSource::Direct
+    };
+    InputSelection { source, missing }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_automatic_mode_falls_back_to_the_mouse() {
+        let complete = select_input_source(InputMode::Auto, |_| true);
+        assert_eq!(complete.source, InputSource::Signals);
+        assert!(complete.missing.is_empty());
+
+        let partial = select_input_source(InputMode::Auto, |signal| signal.contains("clicked"));
+        assert_eq!(partial.source, InputSource::Direct);
+        assert_eq!(
+            partial.missing,
+            vec!["hex_mouse_entered", "hex_mouse_exited"]
+        );
+
+        // Without a parent there are no signals at all.
+        let orphan = select_input_source(InputMode::Auto, |_| false);
+        assert_eq!(orphan.source, InputSource::Direct);
+        assert_eq!(orphan.missing, HEX_SIGNALS.to_vec());
+    }
+
+    #[test]
+    fn forced_modes_keep_their_source() {
+        let forced = select_input_source(InputMode::Signals, |signal| signal != "hex_left_clicked");
+        assert_eq!(forced.source, InputSource::Signals);
+        assert_eq!(forced.missing, vec!["hex_left_clicked"]);
+
+        // The direct mode does not look for the signals, so there is nothing to report.
+        for has_signals in &[true, false] {
+            let direct = select_input_source(InputMode::Direct, |_| *has_signals);
+            assert_eq!(direct.source, InputSource::Direct);
+            assert!(direct.missing.is_empty());
+        }
+    }
+
+    #[test]
+    fn the_property_values_name_the_modes() {
+        assert_eq!(InputMode::from_index(0), Some(InputMode::Auto));
+        assert_eq!(InputMode::from_index(1), Some(InputMode::Signals));
+        assert_eq!(InputMode::from_index(2), Some(InputMode::Direct));
+        assert_eq!(InputMode::from_index(3), None);
+        assert_eq!(InputMode::from_index(-1), None);
+        assert_eq!(InputSource::default(), InputSource::Direct);
+    }
+}
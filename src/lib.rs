@@ -20,12 +20,86 @@ use gdnative::prelude::*;
 use nodes::gameworld;
 use nodes::units::dummy_unit;
 
+#[macro_use]
+mod logging;
+#[macro_use]
+mod panic_guard;
+
+mod abilities;
+mod achievements;
+mod action_log;
+mod actionable;
+mod ai;
+#[cfg(feature = "headless")]
+pub mod balance;
+mod buildings;
+mod checksum;
+mod clicks;
+mod combat_context;
+mod combat_feedback;
+mod combat_log;
+mod commands;
 mod components;
+mod cover;
+mod diagnostics;
+mod economy;
+mod edges;
+mod editor;
+mod entity_query;
+mod flanking;
+mod focus;
+mod fog;
 mod game_state;
+mod godot_convert;
+mod ground_effects;
+mod group_move;
+mod handicap;
+mod hex_cursor;
+mod identity;
+mod influence;
+mod initiative;
+mod input_actions;
+mod input_buffer;
+mod input_source;
 mod legion;
+mod map_bounds;
+mod measurement;
+mod messages;
+mod movement;
+mod node_sync;
 mod nodes;
+mod palette;
+mod path_preview;
+mod pickups;
+mod ping;
+mod planning;
 mod player;
+mod profiler;
+mod promotions;
+mod random;
+mod random_events;
+mod retreat;
+mod roster;
+mod rules;
+mod savegame;
+mod scenario;
+mod simulation;
+mod sounds;
+mod spawn;
+mod spawn_modifiers;
+mod state_machine;
+mod statistics;
+mod supply;
 mod systems;
+mod targeting;
+mod touch;
+mod transitions;
+mod triggers;
+mod turn_file;
+mod tutorial;
+mod unit_catalog;
+mod unit_names;
+mod weather;
 
 // Function that registers all exposed classes to Godot
 fn init(handle: InitHandle) {
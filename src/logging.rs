@@ -0,0 +1,44 @@
+//! Logging facade. Messages go to the Godot console, unless the crate is built with the
+//! `headless` feature, where they go to stdout/stderr instead.
+
+#[cfg(not(feature = "headless"))]
+macro_rules! log_print {
+    ($($args:tt)*) => {
+        gdnative::godot_print!($($args)*)
+    };
+}
+
+#[cfg(not(feature = "headless"))]
+macro_rules! log_warn {
+    ($($args:tt)*) => {
+        gdnative::godot_warn!($($args)*)
+    };
+}
+
+#[cfg(not(feature = "headless"))]
+macro_rules! log_error {
+    ($($args:tt)*) => {
+        gdnative::godot_error!($($args)*)
+    };
+}
+
+#[cfg(feature = "headless")]
+macro_rules! log_print {
+    ($($args:tt)*) => {
+        println!($($args)*)
+    };
+}
+
+#[cfg(feature = "headless")]
+macro_rules! log_warn {
+    ($($args:tt)*) => {
+        eprintln!("WARNING: {}", format!($($args)*))
+    };
+}
+
+#[cfg(feature = "headless")]
+macro_rules! log_error {
+    ($($args:tt)*) => {
+        eprintln!("ERROR: {}", format!($($args)*))
+    };
+}
@@ -0,0 +1,96 @@
+//! The hexagons that make up the map. They are the locations of the field entities, which
+//! `create_grid`, the scenarios, saved games and the editor put down, so the bounds follow a map
+//! of any shape, holes like lakes included. Paths, the hexagon cursor and clicks stay within
+//! them, and depending on the rules the holes block the line of sight.
+
+use crate::components::field::Field;
+use crate::components::hexagon::Hexagon;
+use legion::{EntityStore, IntoQuery};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The hexagons of the map, shared as the set is copied into every path search. Without any
+/// hexagons every hexagon is in bounds, which is what tests without a map rely on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MapBounds {
+    hexagons: Option<Arc<HashSet<Hexagon>>>,
+    /// Whether a line of sight over a hexagon off the map is blocked, see
+    /// `Ruleset::holes_block_sight`.
+    holes_block_sight: bool,
+}
+
+impl MapBounds {
+    pub fn new<I: IntoIterator<Item = Hexagon>>(hexagons: I) -> Self {
+        let hexagons: HashSet<Hexagon> = hexagons.into_iter().collect();
+        MapBounds {
+            hexagons: if hexagons.is_empty() {
+                None
+            } else {
+                Some(Arc::new(hexagons))
+            },
+            holes_block_sight: false,
+        }
+    }
+
+    /// The bounds of the fields in the world.
+    pub fn from_world<S: EntityStore>(world: &S) -> Self {
+        MapBounds::new(<&Field>::query().iter(world).map(|field| field.location))
+    }
+
+    pub fn contains(&self, hexagon: &Hexagon) -> bool {
+        self.hexagons
+            .as_ref()
+            .map_or(true, |hexagons| hexagons.contains(hexagon))
+    }
+
+    pub fn set_holes_block_sight(&mut self, holes_block_sight: bool) {
+        self.holes_block_sight = holes_block_sight;
+    }
+
+    /// Whether `hexagon` blocks a line of sight over it.
+    pub fn blocks_sight(&self, hexagon: &Hexagon) -> bool {
+        self.holes_block_sight && !self.contains(hexagon)
+    }
+
+    /// Returns `hexagon`, or the hexagon of the map closest to it if it lies outside. Of equally
+    /// close hexagons the one with the lowest coordinates is taken.
+    pub fn clamp(&self, hexagon: Hexagon) -> Hexagon {
+        match &self.hexagons {
+            Some(hexagons) if !hexagons.contains(&hexagon) => hexagons
+                .iter()
+                .min_by_key(|inside| (inside.distance_to(&hexagon), inside.get_q(), inside.get_r()))
+                .copied()
+                .unwrap_or(hexagon),
+            _ => hexagon,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::hexgrid::create_grid;
+    use legion::World;
+
+    #[test]
+    fn bounds_follow_the_fields() {
+        assert!(MapBounds::from_world(&World::default()).contains(&Hexagon::new_axial(50, 50)));
+
+        let mut world = World::default();
+        for hexagon in create_grid(2) {
+            world.push((Field::new(hexagon),));
+        }
+        let bounds = MapBounds::from_world(&world);
+
+        assert!(bounds.contains(&Hexagon::new_axial(2, -2)));
+        assert!(!bounds.contains(&Hexagon::new_axial(3, 0)));
+        assert_eq!(
+            bounds.clamp(Hexagon::new_axial(1, 0)),
+            Hexagon::new_axial(1, 0)
+        );
+        assert_eq!(
+            bounds.clamp(Hexagon::new_axial(4, 0)),
+            Hexagon::new_axial(2, 0)
+        );
+    }
+}
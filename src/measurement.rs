@@ -0,0 +1,149 @@
+//! Distances between two hexagons, for analysing the map.
+
+use crate::components::hexagon::Hexagon;
+use crate::edges::EdgeData;
+use crate::map_bounds::MapBounds;
+use crate::systems::hexgrid::{find_path, get_2d_position_from_hex, is_line_of_sight_clear};
+use gdnative::prelude::*;
+use legion::EntityStore;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    pub from: Hexagon,
+    pub to: Hexagon,
+    pub distance: i32,
+    pub pixel_distance: f32,
+    /// Number of steps a unit needs to get from `from` to `to`. None if no unit is selected or
+    /// the target cannot be reached.
+    pub path_cost: Option<i32>,
+    pub line_of_sight: bool,
+}
+
+impl Measurement {
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("distance", self.distance);
+        dict.insert("pixel_distance", self.pixel_distance);
+        dict.insert("reachable", self.path_cost.is_some());
+        dict.insert("path_cost", self.path_cost.unwrap_or(-1));
+        dict.insert("line_of_sight", self.line_of_sight);
+        dict
+    }
+}
+
+/// Measures the distance between two hexagons. The path cost is only calculated with
+/// `unit_selected`, as it describes how far the selected unit would have to move.
+pub fn measure<S: EntityStore>(
+    world: &S,
+    edges: &EdgeData,
+    bounds: &MapBounds,
+    from: Hexagon,
+    to: Hexagon,
+    unit_selected: bool,
+    hexfield_size: f32,
+) -> Measurement {
+    let path_cost = if !unit_selected {
+        None
+    } else if from == to {
+        Some(0)
+    } else {
+        match find_path(&from, &to, edges, bounds, world).len() {
+            0 => None,
+            length => Some(length as i32),
+        }
+    };
+    let from_position = get_2d_position_from_hex(&from, hexfield_size);
+    let to_position = get_2d_position_from_hex(&to, hexfield_size);
+    Measurement {
+        from,
+        to,
+        distance: from.distance_to(&to),
+        pixel_distance: (to_position - from_position).length(),
+        path_cost,
+        line_of_sight: is_line_of_sight_clear(&from, &to, bounds, world),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::unit::Unit;
+    use legion::World;
+
+    fn unit() -> Unit {
+        Unit::new(1, 1, 1, 1, 0, 1, 1, 1)
+    }
+
+    #[test]
+    fn measure_reachable_pair() {
+        let mut world = World::default();
+        world.push((Hexagon::new_axial(0, 0), unit()));
+
+        let measurement = measure(
+            &world,
+            &EdgeData::default(),
+            &MapBounds::default(),
+            Hexagon::new_axial(0, 0),
+            Hexagon::new_axial(2, 0),
+            true,
+            10.0,
+        );
+
+        assert_eq!(measurement.distance, 2);
+        assert!((measurement.pixel_distance - 20.0 * 3.0_f32.sqrt()).abs() < 0.001);
+        assert_eq!(measurement.path_cost, Some(2));
+        assert!(measurement.line_of_sight);
+    }
+
+    #[test]
+    fn measure_unreachable_pair() {
+        let mut world = World::default();
+        world.push((Hexagon::new_axial(0, 0), unit()));
+        world.push((Hexagon::new_axial(3, 0), unit()));
+
+        let measurement = measure(
+            &world,
+            &EdgeData::default(),
+            &MapBounds::default(),
+            Hexagon::new_axial(0, 0),
+            Hexagon::new_axial(3, 0),
+            true,
+            10.0,
+        );
+
+        assert_eq!(measurement.distance, 3);
+        assert_eq!(measurement.path_cost, None);
+        assert!(measurement.line_of_sight);
+
+        let without_selection = measure(
+            &world,
+            &EdgeData::default(),
+            &MapBounds::default(),
+            Hexagon::new_axial(0, 0),
+            Hexagon::new_axial(1, 0),
+            false,
+            10.0,
+        );
+        assert_eq!(without_selection.path_cost, None);
+    }
+
+    #[test]
+    fn measure_pair_with_blocked_line_of_sight() {
+        let mut world = World::default();
+        world.push((Hexagon::new_axial(0, 0), unit()));
+        world.push((Hexagon::new_axial(1, 0), unit()));
+
+        let measurement = measure(
+            &world,
+            &EdgeData::default(),
+            &MapBounds::default(),
+            Hexagon::new_axial(0, 0),
+            Hexagon::new_axial(2, 0),
+            true,
+            10.0,
+        );
+
+        assert!(!measurement.line_of_sight);
+        assert_eq!(measurement.path_cost, Some(3));
+    }
+}
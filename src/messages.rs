@@ -0,0 +1,324 @@
+use crate::components::hexagon::Hexagon;
+use crate::flanking::AttackArc;
+use gdnative::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const UNIT_MOVED: &str = "MSG_UNIT_MOVED";
+pub const DAMAGE_DEALT: &str = "MSG_DAMAGE_DEALT";
+pub const UNIT_DESTROYED: &str = "MSG_UNIT_DESTROYED";
+pub const NO_ATTACKS_LEFT: &str = "MSG_NO_ATTACKS_LEFT";
+pub const MOVED_BEFORE_FIRING: &str = "MSG_MOVED_BEFORE_FIRING";
+pub const TURN_STARTED: &str = "MSG_TURN_STARTED";
+pub const CURRENT_PLAYER: &str = "MSG_CURRENT_PLAYER";
+pub const HISTORY_DAMAGE_TAKEN: &str = "MSG_HISTORY_DAMAGE_TAKEN";
+pub const HISTORY_KILL: &str = "MSG_HISTORY_KILL";
+pub const ORDER_INTERRUPTED: &str = "MSG_ORDER_INTERRUPTED";
+pub const SUPPLY_DROP: &str = "MSG_SUPPLY_DROP";
+pub const SANDSTORM: &str = "MSG_SANDSTORM";
+pub const DESERTION: &str = "MSG_DESERTION";
+pub const WILDFIRE: &str = "MSG_WILDFIRE";
+pub const TUTORIAL_SELECT: &str = "MSG_TUTORIAL_SELECT";
+pub const TUTORIAL_MOVE: &str = "MSG_TUTORIAL_MOVE";
+pub const TUTORIAL_ATTACK: &str = "MSG_TUTORIAL_ATTACK";
+pub const ACTION_MOVED: &str = "MSG_ACTION_MOVED";
+pub const ACTION_ATTACKED: &str = "MSG_ACTION_ATTACKED";
+pub const ACTION_ORDERED: &str = "MSG_ACTION_ORDERED";
+pub const ACTION_TURN_ENDED: &str = "MSG_ACTION_TURN_ENDED";
+pub const ACTION_ROUND_RESOLVED: &str = "MSG_ACTION_ROUND_RESOLVED";
+
+/// Player facing message. It is stored as a translation key plus named parameters, so it can be
+/// translated with Godot's `tr` when it is shown and still be saved or logged without an engine.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub key: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl Message {
+    pub fn new(key: &str) -> Self {
+        Message {
+            key: key.to_owned(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn with<T: ToString>(mut self, name: &str, value: T) -> Self {
+        self.params.push((name.to_owned(), value.to_string()));
+        self
+    }
+
+    /// Replaces every `{name}` placeholder in the template with the parameter value.
+    pub fn format(&self, template: &str) -> String {
+        let mut text = template.to_owned();
+        for (name, value) in &self.params {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        text
+    }
+
+    /// The English text used when no translation is loaded.
+    pub fn to_fallback_string(&self) -> String {
+        match fallback_template(&self.key) {
+            None => self.key.clone(),
+            Some(template) => self.format(template),
+        }
+    }
+
+    /// Translates the message through `owner.tr()`. Godot returns the key itself when no
+    /// translation exists, in which case the fallback text is used.
+    pub fn translate(&self, owner: &Object) -> String {
+        let template = owner.tr(self.key.as_str()).to_string();
+        if template == self.key {
+            self.to_fallback_string()
+        } else {
+            self.format(&template)
+        }
+    }
+}
+
+fn fallback_template(key: &str) -> Option<&'static str> {
+    match key {
+        UNIT_MOVED => Some("{unit} moved from {from} to {to}"),
+        DAMAGE_DEALT => {
+            Some("{attacker_name} at {attacker} dealt {amount} damage to {defender_name} at {defender} from the {arc} ({integrity} integrity left)")
+        }
+        UNIT_DESTROYED => Some("{unit} at {position} was destroyed"),
+        NO_ATTACKS_LEFT => Some("Unit at {position} has no attacks left"),
+        MOVED_BEFORE_FIRING => Some("Unit at {position} cannot fire after moving"),
+        TURN_STARTED => Some("Round {round}: {player}'s turn"),
+        CURRENT_PLAYER => Some("Current player: {player}"),
+        HISTORY_DAMAGE_TAKEN => Some("Took {amount} damage from {source} on round {round}"),
+        HISTORY_KILL => Some("Destroyed the unit at {position} on round {round}"),
+        ORDER_INTERRUPTED => Some("Unit at {position} found its path blocked and stopped"),
+        SUPPLY_DROP => Some("Supplies worth {amount} resources were dropped at {position}"),
+        SANDSTORM => Some("A sandstorm limits the view for this round"),
+        DESERTION => Some("Soldiers of {player} deserted from the unit at {position}"),
+        WILDFIRE => Some("A fire broke out at {position}"),
+        TUTORIAL_SELECT => Some("Select the unit at {position}"),
+        TUTORIAL_MOVE => Some("Move to {position}"),
+        TUTORIAL_ATTACK => Some("Attack the unit at {position}"),
+        ACTION_MOVED => Some("{player} moved {unit} to {position}"),
+        ACTION_ATTACKED => Some("{player} attacked {defender} with {attacker}"),
+        ACTION_ORDERED => Some("{player} ordered {unit}: {order}"),
+        ACTION_TURN_ENDED => Some("{player} ended the turn"),
+        ACTION_ROUND_RESOLVED => Some("{player} resolved the round"),
+        _ => None,
+    }
+}
+
+fn format_hexagon(hexagon: &Hexagon) -> String {
+    hexagon.to_string()
+}
+
+pub fn unit_moved(unit: &str, from: &Hexagon, to: &Hexagon) -> Message {
+    Message::new(UNIT_MOVED)
+        .with("unit", unit)
+        .with("from", format_hexagon(from))
+        .with("to", format_hexagon(to))
+}
+
+pub fn damage_dealt(
+    attacker_name: &str,
+    attacker: &Hexagon,
+    defender_name: &str,
+    defender: &Hexagon,
+    arc: AttackArc,
+    amount: i32,
+    remaining_integrity: i32,
+) -> Message {
+    Message::new(DAMAGE_DEALT)
+        .with("attacker_name", attacker_name)
+        .with("attacker", format_hexagon(attacker))
+        .with("defender_name", defender_name)
+        .with("defender", format_hexagon(defender))
+        .with("arc", arc.name())
+        .with("amount", amount)
+        .with("integrity", remaining_integrity.max(0))
+}
+
+pub fn unit_destroyed(unit: &str, position: &Hexagon) -> Message {
+    Message::new(UNIT_DESTROYED)
+        .with("unit", unit)
+        .with("position", format_hexagon(position))
+}
+
+pub fn no_attacks_left(position: &Hexagon) -> Message {
+    Message::new(NO_ATTACKS_LEFT).with("position", format_hexagon(position))
+}
+
+pub fn moved_before_firing(position: &Hexagon) -> Message {
+    Message::new(MOVED_BEFORE_FIRING).with("position", format_hexagon(position))
+}
+
+pub fn order_interrupted(position: &Hexagon) -> Message {
+    Message::new(ORDER_INTERRUPTED).with("position", format_hexagon(position))
+}
+
+pub fn supply_drop(amount: i32, position: &Hexagon) -> Message {
+    Message::new(SUPPLY_DROP)
+        .with("amount", amount)
+        .with("position", format_hexagon(position))
+}
+
+pub fn sandstorm() -> Message {
+    Message::new(SANDSTORM)
+}
+
+pub fn desertion(player_name: &str, position: &Hexagon) -> Message {
+    Message::new(DESERTION)
+        .with("player", player_name)
+        .with("position", format_hexagon(position))
+}
+
+pub fn wildfire(position: &Hexagon) -> Message {
+    Message::new(WILDFIRE).with("position", format_hexagon(position))
+}
+
+pub fn tutorial_select(position: &Hexagon) -> Message {
+    Message::new(TUTORIAL_SELECT).with("position", format_hexagon(position))
+}
+
+pub fn tutorial_move(position: &Hexagon) -> Message {
+    Message::new(TUTORIAL_MOVE).with("position", format_hexagon(position))
+}
+
+pub fn tutorial_attack(position: &Hexagon) -> Message {
+    Message::new(TUTORIAL_ATTACK).with("position", format_hexagon(position))
+}
+
+pub fn turn_started(round: u32, player_name: &str) -> Message {
+    Message::new(TURN_STARTED)
+        .with("round", round)
+        .with("player", player_name)
+}
+
+pub fn current_player(player_name: &str) -> Message {
+    Message::new(CURRENT_PLAYER).with("player", player_name)
+}
+
+pub fn history_damage_taken(amount: i32, source: &Hexagon, round: u32) -> Message {
+    Message::new(HISTORY_DAMAGE_TAKEN)
+        .with("amount", amount)
+        .with("source", format_hexagon(source))
+        .with("round", round)
+}
+
+pub fn history_kill(position: &Hexagon, round: u32) -> Message {
+    Message::new(HISTORY_KILL)
+        .with("position", format_hexagon(position))
+        .with("round", round)
+}
+
+pub fn action_moved(player_name: &str, unit: &str, position: &Hexagon) -> Message {
+    Message::new(ACTION_MOVED)
+        .with("player", player_name)
+        .with("unit", unit)
+        .with("position", format_hexagon(position))
+}
+
+pub fn action_attacked(player_name: &str, attacker: &str, defender: &str) -> Message {
+    Message::new(ACTION_ATTACKED)
+        .with("player", player_name)
+        .with("attacker", attacker)
+        .with("defender", defender)
+}
+
+pub fn action_ordered(player_name: &str, unit: &str, order: &str) -> Message {
+    Message::new(ACTION_ORDERED)
+        .with("player", player_name)
+        .with("unit", unit)
+        .with("order", order)
+}
+
+pub fn action_turn_ended(player_name: &str) -> Message {
+    Message::new(ACTION_TURN_ENDED).with("player", player_name)
+}
+
+pub fn action_round_resolved(player_name: &str) -> Message {
+    Message::new(ACTION_ROUND_RESOLVED).with("player", player_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_replaces_all_placeholders() {
+        let message = Message::new("TEST").with("a", 1).with("b", "two");
+        assert_eq!(message.format("{a} and {b} and {a}"), "1 and two and 1");
+    }
+
+    #[test]
+    fn format_keeps_unknown_placeholders() {
+        let message = Message::new("TEST").with("a", 1);
+        assert_eq!(message.format("{a} {missing}"), "1 {missing}");
+    }
+
+    #[test]
+    fn fallback_string_of_unknown_key_is_the_key() {
+        assert_eq!(
+            Message::new("MSG_UNKNOWN").to_fallback_string(),
+            "MSG_UNKNOWN"
+        );
+    }
+
+    #[test]
+    fn unit_moved_fallback() {
+        let message = unit_moved(
+            "Tank 2",
+            &Hexagon::new_axial(0, 1),
+            &Hexagon::new_axial(1, 1),
+        );
+        assert_eq!(
+            message.to_fallback_string(),
+            "Tank 2 moved from (0, 1) to (1, 1)"
+        );
+    }
+
+    #[test]
+    fn damage_dealt_fallback_clamps_integrity() {
+        let message = damage_dealt(
+            "Tank 1",
+            &Hexagon::new_axial(2, 0),
+            "Artillery 3",
+            &Hexagon::new_axial(-2, 0),
+            AttackArc::Flank,
+            7,
+            -3,
+        );
+        assert_eq!(
+            message.to_fallback_string(),
+            "Tank 1 at (2, 0) dealt 7 damage to Artillery 3 at (-2, 0) from the flank (0 integrity left)"
+        );
+    }
+
+    #[test]
+    fn unit_destroyed_fallback() {
+        let message = unit_destroyed("Unit", &Hexagon::new_axial(-1, 3));
+        assert_eq!(
+            message.to_fallback_string(),
+            "Unit at (-1, 3) was destroyed"
+        );
+    }
+
+    #[test]
+    fn no_attacks_left_fallback() {
+        let message = no_attacks_left(&Hexagon::new_axial(4, -2));
+        assert_eq!(
+            message.to_fallback_string(),
+            "Unit at (4, -2) has no attacks left"
+        );
+    }
+
+    #[test]
+    fn turn_started_fallback() {
+        let message = turn_started(3, "Player 2");
+        assert_eq!(message.to_fallback_string(), "Round 3: Player 2's turn");
+    }
+
+    #[test]
+    fn current_player_fallback() {
+        let message = current_player("Player 1");
+        assert_eq!(message.to_fallback_string(), "Current player: Player 1");
+    }
+}
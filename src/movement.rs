@@ -0,0 +1,158 @@
+//! Movement costs. Costs are counted in fixed point, a hexagon of open terrain costs
+//! `HEXAGON_COST` and a road half of that for units on the ground or on wheels. Budgets of units
+//! stay whole hexagons, the rest below one hexagon is kept in `Unit::partial_movement`, so
+//! moving along a road never loses or gains movement to rounding. Crossing a river edge costs
+//! extra, see `edges`.
+
+use crate::components::field::Field;
+use crate::components::hexagon::Hexagon;
+use crate::components::movement_type::MovementType;
+use crate::components::terrain::Terrain;
+use crate::components::unit::Unit;
+use crate::edges::EdgeData;
+use crate::map_bounds::MapBounds;
+use legion::{Entity, EntityStore, IntoQuery};
+use std::collections::{HashMap, HashSet};
+
+/// The cost of one hexagon of open terrain.
+pub const HEXAGON_COST: i32 = 2;
+/// The cost of one road hexagon for units that use roads.
+pub const ROAD_COST: i32 = 1;
+
+/// Whether units of this movement type move faster on roads. Tracked units are as slow on a
+/// road as anywhere else.
+pub fn uses_roads(movement: MovementType) -> bool {
+    match movement {
+        MovementType::Ground | MovementType::Wheeled => true,
+        MovementType::Tracked => false,
+    }
+}
+
+/// The movement type of the entity, units without one move on the ground.
+pub fn movement_type_of<S: EntityStore>(world: &S, entity: Entity) -> MovementType {
+    world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<MovementType>().ok().copied())
+        .unwrap_or_default()
+}
+
+/// What a step costs a unit of one movement type. Built once per search, as it collects the
+/// roads of the map.
+#[derive(Clone, Debug, Default)]
+pub struct MovementCosts {
+    roads: HashSet<Hexagon>,
+    edges: EdgeData,
+    /// Extra cost of entering a hexagon, which steers the search around it without making it
+    /// impassable.
+    penalties: HashMap<Hexagon, i32>,
+    /// Steps off the map are never taken.
+    bounds: MapBounds,
+}
+
+impl MovementCosts {
+    pub fn new<S: EntityStore>(world: &S, movement: MovementType, edges: &EdgeData) -> Self {
+        let roads = if uses_roads(movement) {
+            <&Field>::query()
+                .iter(world)
+                .filter(|field| field.terrain == Terrain::Road)
+                .map(|field| field.location)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        MovementCosts {
+            roads,
+            edges: edges.clone(),
+            penalties: HashMap::new(),
+            bounds: MapBounds::default(),
+        }
+    }
+
+    /// The costs for moving within `bounds`.
+    pub fn with_bounds(mut self, bounds: &MapBounds) -> Self {
+        self.bounds = bounds.clone();
+        self
+    }
+
+    /// The costs with `penalties` added to entering the hexagons. Penalties must not be negative.
+    pub fn with_penalties(mut self, penalties: HashMap<Hexagon, i32>) -> Self {
+        self.penalties = penalties;
+        self
+    }
+
+    /// The costs for the unit standing on `hexagon`, those of a ground unit if there is none.
+    pub fn for_unit_at<S: EntityStore>(world: &S, hexagon: &Hexagon, edges: &EdgeData) -> Self {
+        let movement = <(&Hexagon, &Unit, Option<&MovementType>)>::query()
+            .iter(world)
+            .find(|(position, _, _)| *position == hexagon)
+            .and_then(|(_, _, movement)| movement.copied())
+            .unwrap_or_default();
+        MovementCosts::new(world, movement, edges)
+    }
+
+    /// The cost of stepping from `from` onto its neighbour `to`, `None` if a cliff is in the way
+    /// or `to` is off the map.
+    pub fn step_cost(&self, from: &Hexagon, to: &Hexagon) -> Option<i32> {
+        if !self.bounds.contains(to) {
+            return None;
+        }
+        let entering = if self.roads.contains(to) {
+            ROAD_COST
+        } else {
+            HEXAGON_COST
+        };
+        let penalty = self.penalties.get(to).copied().unwrap_or(0);
+        Some(entering + penalty + self.edges.crossing_cost(from, to)?)
+    }
+
+    /// The lowest cost any step can have, which keeps the estimate of the path search below the
+    /// real cost.
+    pub fn cheapest_step(&self) -> i32 {
+        if self.roads.is_empty() {
+            HEXAGON_COST
+        } else {
+            ROAD_COST
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion::World;
+
+    fn road(world: &mut World, q: i32, r: i32) {
+        let mut field = Field::new(Hexagon::new_axial(q, r));
+        field.terrain = Terrain::Road;
+        world.push((field,));
+    }
+
+    fn path_cost(costs: &MovementCosts, path: &[Hexagon]) -> Option<i32> {
+        let mut from = Hexagon::zero();
+        let mut cost = 0;
+        for hexagon in path {
+            cost += costs.step_cost(&from, hexagon)?;
+            from = *hexagon;
+        }
+        Some(cost)
+    }
+
+    #[test]
+    fn roads_only_help_units_that_use_them() {
+        let mut world = World::default();
+        road(&mut world, 1, 0);
+        world.push((Field::new(Hexagon::new_axial(2, 0)),));
+        let path = [Hexagon::new_axial(1, 0), Hexagon::new_axial(2, 0)];
+        let edges = EdgeData::default();
+
+        let ground = MovementCosts::new(&world, MovementType::Ground, &edges);
+        let wheeled = MovementCosts::new(&world, MovementType::Wheeled, &edges);
+        let tracked = MovementCosts::new(&world, MovementType::Tracked, &edges);
+
+        assert_eq!(path_cost(&ground, &path), Some(ROAD_COST + HEXAGON_COST));
+        assert_eq!(path_cost(&wheeled, &path), Some(ROAD_COST + HEXAGON_COST));
+        assert_eq!(path_cost(&tracked, &path), Some(2 * HEXAGON_COST));
+        assert_eq!(tracked.cheapest_step(), HEXAGON_COST);
+    }
+}
@@ -0,0 +1,255 @@
+//! Keeps the map from entities to their Godot nodes in step with the events of the world. Events
+//! arrive a frame late: an entity may be gone again by the time its insertion is seen, and the
+//! events of a match that already ended may still be waiting in the channel. Every event is
+//! tagged with the generation of the match it happened in, which `GameState::reset` bumps, so
+//! those of an earlier match are dropped.
+
+use crossbeam::channel::Sender;
+use legion::world::{Event, EventSender};
+use legion::{Entity, EntityStore};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Counts the matches played. Shared between the game state and the event sender of the world.
+#[derive(Clone, Debug, Default)]
+pub struct MatchGeneration(Arc<AtomicU64>);
+
+impl MatchGeneration {
+    pub fn current(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Starts a new match, events sent from now on belong to it.
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// An event of the world with the match it happened in.
+#[derive(Clone, Debug)]
+pub struct TaggedEvent {
+    pub generation: u64,
+    pub event: Event,
+}
+
+/// Sends the events of the world tagged with the generation of the match.
+#[derive(Clone, Debug)]
+pub struct GenerationSender {
+    pub sender: Sender<TaggedEvent>,
+    pub generation: MatchGeneration,
+}
+
+impl EventSender for GenerationSender {
+    fn send(&self, event: Event) -> bool {
+        let generation = self.generation.current();
+        self.sender.send(TaggedEvent { generation, event }).is_ok()
+    }
+}
+
+/// The nodes of the entities of one match.
+#[derive(Debug)]
+pub struct NodeMap<N> {
+    pub nodes: HashMap<Entity, N>,
+    generation: u64,
+}
+
+impl<N> Default for NodeMap<N> {
+    fn default() -> Self {
+        NodeMap {
+            nodes: HashMap::new(),
+            generation: 0,
+        }
+    }
+}
+
+/// What `sync_node_map` did with the events of a frame.
+#[derive(Debug)]
+pub struct NodeSync<N> {
+    /// Nodes of removed entities and of an earlier match, the caller frees them.
+    pub freed: Vec<N>,
+    pub drained: usize,
+    /// Events of an earlier match that were dropped.
+    pub stale: usize,
+}
+
+/// Applies the events to the node map. Only the last event of an entity counts, as moving an
+/// entity to another archetype removes it from the old one before it is inserted into the new
+/// one. Inserted entities that are gone or have no node by now are skipped. `node_of` reads the
+/// node of an entity from the world.
+pub fn sync_node_map<S, N, I, F>(
+    world: &S,
+    generation: u64,
+    events: I,
+    map: &mut NodeMap<N>,
+    node_of: F,
+) -> NodeSync<N>
+where
+    S: EntityStore,
+    I: IntoIterator<Item = TaggedEvent>,
+    F: Fn(&S, Entity) -> Option<N>,
+{
+    let mut freed = Vec::new();
+    if map.generation != generation {
+        freed.extend(map.nodes.drain().map(|(_, node)| node));
+        map.generation = generation;
+    }
+    let mut drained = 0;
+    let mut stale = 0;
+    let mut order = Vec::new();
+    let mut present: HashMap<Entity, bool> = HashMap::new();
+    for tagged in events {
+        drained += 1;
+        if tagged.generation != generation {
+            stale += 1;
+            continue;
+        }
+        let (entity, inserted) = match tagged.event {
+            Event::EntityInserted(entity, _) => (entity, true),
+            Event::EntityRemoved(entity, _) => (entity, false),
+            Event::ArchetypeCreated(_) => continue,
+        };
+        if present.insert(entity, inserted).is_none() {
+            order.push(entity);
+        }
+    }
+    for entity in order {
+        if present[&entity] {
+            if let Some(node) = node_of(world, entity) {
+                map.nodes.insert(entity, node);
+            }
+        } else if let Some(node) = map.nodes.remove(&entity) {
+            freed.push(node);
+        }
+    }
+    NodeSync {
+        freed,
+        drained,
+        stale,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::channel::{unbounded, Receiver};
+    use legion::storage::ArchetypeIndex;
+    use legion::World;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct TestNode(u32);
+
+    fn node_of(world: &World, entity: Entity) -> Option<TestNode> {
+        world
+            .entry_ref(entity)
+            .ok()
+            .and_then(|entry| entry.get_component::<TestNode>().ok().copied())
+    }
+
+    fn subscribed() -> (World, MatchGeneration, Receiver<TaggedEvent>) {
+        let mut world = World::default();
+        let generation = MatchGeneration::default();
+        let (sender, receiver) = unbounded();
+        world.subscribe(
+            GenerationSender {
+                sender,
+                generation: generation.clone(),
+            },
+            legion::component::<TestNode>(),
+        );
+        (world, generation, receiver)
+    }
+
+    fn sync_events(
+        world: &World,
+        generation: &MatchGeneration,
+        receiver: &Receiver<TaggedEvent>,
+        map: &mut NodeMap<TestNode>,
+    ) -> NodeSync<TestNode> {
+        sync_node_map(
+            world,
+            generation.current(),
+            receiver.try_iter(),
+            map,
+            node_of,
+        )
+    }
+
+    #[test]
+    fn entities_removed_in_the_frame_they_appear_are_skipped() {
+        let (mut world, generation, receiver) = subscribed();
+        let mut map = NodeMap::default();
+        let kept = world.push((TestNode(1),));
+        let killed = world.push((TestNode(2),));
+        world.remove(killed);
+
+        let sync = sync_events(&world, &generation, &receiver, &mut map);
+        assert_eq!(sync.drained, 4);
+        assert!(sync.freed.is_empty());
+        assert_eq!(map.nodes.len(), 1);
+        assert_eq!(map.nodes[&kept], TestNode(1));
+
+        // The node of an entity that is gone by the time its insertion is seen is not read.
+        let vanished = world.push((TestNode(3),));
+        let events: Vec<TaggedEvent> = receiver.try_iter().collect();
+        world.remove(vanished);
+        let sync = sync_node_map(&world, 0, events, &mut map, node_of);
+        assert!(sync.freed.is_empty());
+        assert!(!map.nodes.contains_key(&vanished));
+        sync_node_map(&world, 0, receiver.try_iter(), &mut map, node_of);
+
+        world.remove(kept);
+        let sync = sync_node_map(&world, 0, receiver.try_iter(), &mut map, node_of);
+        assert_eq!(sync.freed, vec![TestNode(1)]);
+        assert!(map.nodes.is_empty());
+    }
+
+    #[test]
+    fn duplicate_inserts_keep_the_node() {
+        let (mut world, generation, receiver) = subscribed();
+        let mut map = NodeMap::default();
+        let entity = world.push((TestNode(1),));
+        sync_events(&world, &generation, &receiver, &mut map);
+
+        // Adding a component moves the entity to another archetype: it is removed from the old
+        // one and inserted into the new one.
+        world.entry(entity).unwrap().add_component(5u8);
+        world.entry(entity).unwrap().add_component(true);
+        let sync = sync_events(&world, &generation, &receiver, &mut map);
+        assert_eq!(sync.drained, 6);
+        assert!(sync.freed.is_empty());
+        assert_eq!(map.nodes[&entity], TestNode(1));
+
+        // Repeated insertions of the same entity are harmless.
+        let events = vec![
+            TaggedEvent {
+                generation: 0,
+                event: Event::EntityInserted(entity, ArchetypeIndex(0)),
+            };
+            2
+        ];
+        let sync = sync_node_map(&world, 0, events, &mut map, node_of);
+        assert!(sync.freed.is_empty());
+        assert_eq!(map.nodes.len(), 1);
+    }
+
+    #[test]
+    fn events_of_an_earlier_match_are_dropped() {
+        let (mut world, generation, receiver) = subscribed();
+        let mut map = NodeMap::default();
+        let old = world.push((TestNode(1),));
+        sync_events(&world, &generation, &receiver, &mut map);
+        // Created in the last frame of the old match, seen after the new one started.
+        world.push((TestNode(2),));
+
+        world.clear();
+        generation.bump();
+        let new = world.push((TestNode(3),));
+        let sync = sync_events(&world, &generation, &receiver, &mut map);
+        assert_eq!(sync.stale, 3);
+        assert_eq!(sync.freed, vec![TestNode(1)]);
+        assert!(!map.nodes.contains_key(&old));
+        assert_eq!(map.nodes.len(), 1);
+        assert_eq!(map.nodes[&new], TestNode(3));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod buildings;
 pub mod gameworld;
 pub mod hexgrid;
+pub mod pickups;
 pub mod units;
+pub mod y_sort;
@@ -0,0 +1,41 @@
+use crate::components::building::Building;
+use crate::components::garrison::Garrisoned;
+use crate::components::hexagon::Hexagon;
+use crate::components::node_component::NodeComponent;
+use crate::game_state::GameState;
+use gdnative::prelude::*;
+use legion::world::SubWorld;
+use legion::{system, IntoQuery};
+use std::collections::HashSet;
+
+/// Shows buildings only where the current player can see. The optional "OccupancyBadge" child
+/// shows while a unit is inside, the unit itself is hidden.
+#[system]
+#[read_component(NodeComponent)]
+#[read_component(Hexagon)]
+#[read_component(Building)]
+#[read_component(Garrisoned)]
+pub fn update_buildings(world: &SubWorld<'_>, #[resource] state: &GameState) {
+    let occupied: HashSet<Hexagon> = <(&Hexagon, &Garrisoned)>::query()
+        .iter(world)
+        .map(|(hexagon, _)| *hexagon)
+        .collect();
+    for (node, hexagon, _) in <(&NodeComponent, &Hexagon, &Building)>::query().iter(world) {
+        let node = match node.get_node() {
+            Some(node) => node,
+            None => continue,
+        };
+        let hidden_by_fog = state.rules.fog_of_war
+            && state
+                .current_player
+                .map_or(false, |viewer| !state.last_seen.sees(viewer, hexagon));
+        node.set_visible(!state.is_view_hidden() && !hidden_by_fog);
+        if let Some(badge) = node
+            .get_node("OccupancyBadge")
+            .and_then(|badge| unsafe { badge.assume_safe_if_sane() })
+            .and_then(|badge| badge.cast::<CanvasItem>())
+        {
+            badge.set_visible(occupied.contains(hexagon));
+        }
+    }
+}
@@ -1,39 +1,164 @@
+use crate::action_log::ReplayError;
+use crate::actionable::DEFAULT_AUTO_END_TURN_DELAY;
+use crate::ai::AiProfile;
+use crate::buildings::DEFAULT_BUILDING_INTEGRITY;
+use crate::clicks::DEFAULT_DOUBLE_CLICK_TIME;
+use crate::combat_feedback::DEFAULT_HITSTOP_CAP;
+use crate::components::building::Building;
+use crate::components::hexagon::{Direction, Hexagon};
 use crate::components::node_component::NodeComponent;
+use crate::components::node_template::NodeTemplate;
+use crate::components::persistent_id::PersistentId;
+use crate::components::pickup::Pickup;
+use crate::components::terrain::Terrain;
+use crate::edges::EdgeKind;
+use crate::editor;
+use crate::entity_query::{EntityFilter, FilterError};
+use crate::game_state::load_scenario;
+use crate::godot_convert::Fields;
+use crate::handicap::HandicapChanges;
+use crate::hex_cursor::Orientation;
+use crate::input_source::{select_input_source, InputMode, InputSource};
+use crate::node_sync::{sync_node_map, GenerationSender, MatchGeneration, NodeMap, TaggedEvent};
+use crate::panic_guard::PanicReport;
+use crate::profiler;
+use crate::rules::{Ruleset, DEFAULT_RULES};
+use crate::scenario::Scenario;
+use crate::spawn::{GridResizeError, UnitSpawn, MAP_RADIUS};
+use crate::spawn_modifiers::ScriptModifier;
+use crate::statistics::DEFAULT_STATISTICS_LIMIT;
+use crate::systems::dynamic_nodes::{
+    NodeBudget, DEFAULT_NODES_PER_FRAME, DEFAULT_NODE_MS_PER_FRAME,
+};
+use crate::systems::hexgrid::{self, create_rect_grid, DEFAULT_HEXFIELD_SIZE};
 use crate::systems::{with_world, UpdateNodes};
+use crate::touch::{DEFAULT_DRAG_DISTANCE, DEFAULT_LONG_PRESS_DURATION};
+use crate::tutorial::TutorialConstraint;
+use crate::unit_catalog::{UnitCatalog, DEFAULT_UNIT_CATALOG};
 use crossbeam::channel::Receiver;
 use crossbeam::crossbeam_channel;
-use gdnative::api::Camera2D;
+use gdnative::api::{Camera2D, Curve2D, File, MainLoop};
 use gdnative::prelude::*;
-use legion::world::Event;
 use legion::{component, Entity};
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryFrom;
 
 #[derive(NativeClass)]
 #[inherit(Node2D)]
 #[register_with(Self::register_signals)]
 pub struct GameWorld {
     process: UpdateNodes,
-    event_receiver: Receiver<Event>,
-    node_entity: HashMap<Entity, Ref<Node2D>>,
+    event_receiver: Receiver<TaggedEvent>,
+    node_entity: NodeMap<Ref<Node2D>>,
+    /// Shared with the game state, tags the world events with the match they belong to.
+    match_generation: MatchGeneration,
+    /// Number of world events handled in the last frame.
+    events_drained: usize,
     #[property]
     ui_node: Option<NodePath>,
     #[property]
     camera_node: Option<NodePath>,
+    #[property]
+    rules_path: String,
+    #[property]
+    units_path: String,
+    #[property]
+    editor_mode: bool,
+    #[property]
+    auto_end_turn: bool,
+    #[property(default = 1.0)]
+    auto_end_turn_delay: f64,
+    #[property(default = 1.5)]
+    banner_duration: f64,
+    #[property(default = false)]
+    privacy_screen_enabled: bool,
+    /// Collects frame timings and lists them on screen. Off, the timers cost next to nothing.
+    #[property(default = false)]
+    profiling_enabled: bool,
+    /// Maps the cursor actions to the neighbours of a flat-top grid instead of a pointy-top one.
+    #[property(default = false)]
+    flat_top_cursor: bool,
+    /// Seconds a finger has to rest on the screen for a long press.
+    #[property(default = 0.5)]
+    long_press_duration: f64,
+    /// Pixels a finger has to move before a touch pans the view instead of tapping.
+    #[property(default = 20.0)]
+    touch_drag_distance: f32,
+    /// Seconds between two clicks on a unit that make a double click.
+    #[property(default = 0.3)]
+    double_click_time: f64,
+    /// Longest pause in seconds that moving units get from hits in a row.
+    #[property(default = 0.3)]
+    max_hitstop: f64,
+    /// Most nodes created per frame, the rest of a large world follows in the next frames.
+    #[property(default = 50)]
+    nodes_per_frame: u32,
+    /// Milliseconds per frame after which no more nodes are created.
+    #[property(default = 4.0)]
+    node_ms_per_frame: f64,
+    /// Distance from the centre of a hexagon to its corners in pixels. Changing it resizes the
+    /// map and scales the nodes with it.
+    #[property(default = 40.0)]
+    hexfield_size: f32,
+    /// Hexagons from the centre to the edge of the map. Shrinking it is refused while units stand
+    /// outside of the new radius.
+    #[property(default = 128)]
+    grid_radius: u32,
+    /// Rounds the statistics series keeps before only every second, fourth, ... round is kept.
+    #[property(default = 200)]
+    statistics_limit: u32,
+    /// Size of the lake in the centre of the map of new games, 0 for none. Units standing in it
+    /// keep their hexagon.
+    #[property(default = 0)]
+    lake_size: u32,
+    /// Where clicks on hexagons come from, read once the node is ready: 0 the hex signals of the
+    /// parent if it has all of them and the mouse otherwise, 1 always the signals, 2 always the
+    /// mouse.
+    #[property(default = 0)]
+    input_mode: i64,
 }
 
 #[methods]
 impl GameWorld {
     pub fn new(owner: TRef<'_, Node2D>) -> Self {
+        let process = UpdateNodes::new(owner.claim(), DEFAULT_HEXFIELD_SIZE);
+        let match_generation = process.match_generation();
         let (sender, receiver) = crossbeam_channel::unbounded();
         with_world(|world| {
-            world.subscribe(sender.clone(), component::<NodeComponent>());
+            let sender = GenerationSender {
+                sender: sender.clone(),
+                generation: match_generation.clone(),
+            };
+            world.subscribe(sender, component::<NodeComponent>());
         });
         Self {
-            process: UpdateNodes::new(owner.claim(), 40f32),
+            process,
             event_receiver: receiver,
-            node_entity: HashMap::new(),
+            node_entity: NodeMap::default(),
+            match_generation,
+            events_drained: 0,
             ui_node: None,
             camera_node: None,
+            rules_path: String::new(),
+            units_path: String::new(),
+            editor_mode: false,
+            auto_end_turn: false,
+            auto_end_turn_delay: DEFAULT_AUTO_END_TURN_DELAY,
+            banner_duration: 1.5,
+            privacy_screen_enabled: false,
+            profiling_enabled: false,
+            flat_top_cursor: false,
+            long_press_duration: DEFAULT_LONG_PRESS_DURATION,
+            touch_drag_distance: DEFAULT_DRAG_DISTANCE,
+            double_click_time: DEFAULT_DOUBLE_CLICK_TIME,
+            max_hitstop: DEFAULT_HITSTOP_CAP,
+            nodes_per_frame: DEFAULT_NODES_PER_FRAME as u32,
+            node_ms_per_frame: DEFAULT_NODE_MS_PER_FRAME,
+            hexfield_size: DEFAULT_HEXFIELD_SIZE,
+            grid_radius: MAP_RADIUS,
+            statistics_limit: DEFAULT_STATISTICS_LIMIT as u32,
+            lake_size: 0,
+            input_mode: 0,
         }
     }
 
@@ -54,90 +179,1809 @@ impl GameWorld {
             name: "hex_mouse_exited",
             args: &[],
         });
+        builder.add_signal(Signal {
+            name: "hex_hover_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "log_entry_added",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "turn_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "local_command_issued",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "load_failed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "actionable_units_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "input_error",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "ping_placed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "round_started",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "awaiting_player",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "achievement_unlocked",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "scenario_event",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "tutorial_blocked",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "turn_rejected",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "desync_detected",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "state_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "tutorial_step_done",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "random_event",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "pickup_collected",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "order_interrupted",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "bridge_destroyed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "building_destroyed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "garrison_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "facing_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "retreat_ordered",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "fire_damage",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "promotion_available",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "target_validity_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "zoom_requested",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "focus_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "play_sound",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "combat_feedback",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "ability_used",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "overwatch_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "unit_turn_started",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "planning_started",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "node_creation_failed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "path_changed",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "world_loading_progress",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "world_ready",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "rust_panic",
+            args: &[],
+        });
+        builder.add_signal(Signal {
+            name: "input_source_missing",
+            args: &[],
+        });
+    }
+
+    /// Called by `guarded!` once an exported method panicked. The game goes back to waiting for
+    /// orders and the "rust_panic" signal carries the message, so the game can show it or save.
+    fn recover_from_panic(&self, owner: &Node2D, report: &PanicReport) {
+        self.process.recover_from_panic();
+        unsafe {
+            owner.call_deferred(
+                "emit_signal",
+                &[
+                    GodotString::from_str("rust_panic").to_variant(),
+                    report.to_string().to_variant(),
+                ],
+            );
+        }
     }
 
     #[export]
-    pub fn _process(&mut self, owner: TRef<'_, Node2D>, delta: f64) {
-        let mut added_entities = Vec::new();
-        let mut removed_entities = Vec::new();
-        for event in self.event_receiver.try_iter() {
-            match event {
-                Event::EntityInserted(entity, _) => added_entities.push(entity),
-                Event::EntityRemoved(entity, _) => removed_entities.push(entity),
-                _ => {}
+    pub fn _ready(&mut self, owner: TRef<'_, Node2D>) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            let rules = if self.rules_path.is_empty() {
+                Ruleset::from_toml(DEFAULT_RULES)
+            } else {
+                match read_text_file(&self.rules_path) {
+                    None => return,
+                    Some(text) => Ruleset::from_toml(&text),
+                }
+            };
+            match rules {
+                Err(error) => godot_error!("{}", error.message()),
+                Ok(rules) => self.process.set_rules(rules),
+            }
+            self.reload_unit_catalog(owner);
+            self.connect_input_source(owner);
+        })
+    }
+
+    /// Connects the hex signals of the parent or leaves the clicks to the mouse, as `input_mode`
+    /// asks. Missing signals are reported with a warning and the "input_source_missing" signal,
+    /// which carries their names and the source the clicks are taken from.
+    fn connect_input_source(&mut self, owner: TRef<'_, Node2D>) {
+        let mode = InputMode::from_index(self.input_mode).unwrap_or_else(|| {
+            godot_warn!("Unknown input_mode {}, using 0 (auto)", self.input_mode);
+            InputMode::Auto
+        });
+        let parent = owner
+            .get_parent()
+            .map(|parent| unsafe { parent.assume_safe() });
+        let selection = select_input_source(mode, |signal| {
+            parent.map_or(false, |parent| parent.has_signal(signal))
+        });
+        if !selection.missing.is_empty() {
+            let consequence = match selection.source {
+                InputSource::Signals => "input_mode forces them, so those inputs are not handled",
+                InputSource::Direct => "hexagons are picked with the mouse instead",
+            };
+            godot_warn!(
+                "The parent of the game world lacks the signals {}, {}",
+                selection.missing.join(", "),
+                consequence
+            );
+            let missing = VariantArray::new();
+            for signal in &selection.missing {
+                missing.push(*signal);
+            }
+            unsafe {
+                owner.call_deferred(
+                    "emit_signal",
+                    &[
+                        GodotString::from_str("input_source_missing").to_variant(),
+                        missing.into_shared().to_variant(),
+                        GodotString::from_str(selection.source.name()).to_variant(),
+                    ],
+                );
             }
         }
-        for entity in added_entities {
-            with_world(|world| {
-                let entry = world.entry(entity).unwrap();
-                let node = entry.get_component::<NodeComponent>().unwrap();
-                self.node_entity.insert(entity, node.node);
-            });
+        if let (InputSource::Signals, Some(parent)) = (selection.source, parent) {
+            let handlers = [
+                ("hex_left_clicked", "on_hex_left_clicked"),
+                ("hex_right_clicked", "on_hex_right_clicked"),
+                ("hex_mouse_entered", "on_hex_mouse_entered"),
+                ("hex_mouse_exited", "on_hex_mouse_exited"),
+            ];
+            for (signal, method) in handlers.iter() {
+                if selection.missing.contains(signal) {
+                    continue;
+                }
+                if let Err(error) =
+                    parent.connect(*signal, owner, *method, VariantArray::new_shared(), 0)
+                {
+                    godot_error!("Could not connect {}: {:?}", signal, error);
+                }
+            }
         }
+        self.process.set_input_source(selection.source);
+    }
 
-        for entity in removed_entities {
-            unsafe { self.node_entity[&entity].assume_safe() }.queue_free();
+    /// The hexagon in the dictionary of a hex signal of the parent.
+    fn signalled_hexagon(signal: &str, hex: &Dictionary) -> Option<Hexagon> {
+        match Hexagon::try_from(hex) {
+            Ok(hexagon) => Some(hexagon),
+            Err(error) => {
+                godot_warn!("{}: {}", signal, error);
+                None
+            }
         }
+    }
+
+    #[export]
+    pub fn on_hex_left_clicked(&mut self, owner: TRef<'_, Node2D>, hex: Dictionary) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            if let Some(hexagon) = Self::signalled_hexagon("hex_left_clicked", &hex) {
+                self.process.signal_left_click(&owner, hexagon);
+            }
+        })
+    }
+
+    #[export]
+    pub fn on_hex_right_clicked(&mut self, owner: TRef<'_, Node2D>, hex: Dictionary) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            if let Some(hexagon) = Self::signalled_hexagon("hex_right_clicked", &hex) {
+                self.process.signal_right_click(&owner, hexagon);
+            }
+        })
+    }
+
+    #[export]
+    pub fn on_hex_mouse_entered(&mut self, owner: TRef<'_, Node2D>, hex: Dictionary) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            if let Some(hexagon) = Self::signalled_hexagon("hex_mouse_entered", &hex) {
+                self.process.signal_hover(&owner, hexagon);
+            }
+        })
+    }
 
-        let ui_node = match &self.ui_node {
-            None => {
-                godot_error!("ui_node is not set");
-                return;
+    #[export]
+    pub fn on_hex_mouse_exited(&mut self, owner: TRef<'_, Node2D>, hex: Dictionary) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            if let Some(hexagon) = Self::signalled_hexagon("hex_mouse_exited", &hex) {
+                self.process.signal_hover_end(&owner, hexagon);
+            }
+        })
+    }
+
+    /// Reads the unit catalog from units_path, or the embedded one if it is empty, and applies
+    /// changed stats to the existing units.
+    #[export]
+    pub fn reload_unit_catalog(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let text = if self.units_path.is_empty() {
+                DEFAULT_UNIT_CATALOG.to_owned()
+            } else {
+                match read_text_file(&self.units_path) {
+                    None => return false,
+                    Some(text) => text,
+                }
+            };
+            match UnitCatalog::from_json(&text) {
+                Err(error) => {
+                    godot_error!("Could not read unit catalog: {}", error);
+                    false
+                }
+                Ok((catalog, errors)) => {
+                    for error in errors {
+                        godot_warn!(
+                            "Skipped unit catalog entry {}: {}",
+                            error.index,
+                            error.message
+                        );
+                    }
+                    self.process.set_unit_catalog(catalog);
+                    true
+                }
+            }
+        })
+    }
+
+    /// Replaces the map with fields on `hexagons` and returns the positions of the units outside
+    /// of it, see `create_grid`.
+    fn replace_grid(
+        &mut self,
+        hexagons: &[Hexagon],
+        scene_file: &str,
+        node_scale: f64,
+    ) -> VariantArray {
+        let template = if scene_file.is_empty() {
+            None
+        } else {
+            let scale = node_scale as f32;
+            Some(
+                NodeTemplate::new(scene_file)
+                    .scale(scale, scale)
+                    .group("hex_fields")
+                    .parent("HexFields"),
+            )
+        };
+        let orphaned = VariantArray::new();
+        match self.process.replace_grid(hexagons, template) {
+            Ok(()) => self.grid_radius = self.process.grid_radius(),
+            Err(GridResizeError::OrphanedUnits(positions)) => {
+                godot_warn!("Could not replace the map, units stand outside of it");
+                for position in positions {
+                    let dict = Dictionary::new();
+                    dict.insert("q", position.get_q());
+                    dict.insert("r", position.get_r());
+                    orphaned.push(dict.into_shared());
+                }
             }
-            Some(node) => match owner.get_node(node.to_godot_string()) {
+        }
+        orphaned.into_shared()
+    }
+
+    /// Tracks the nodes of entities that got one and frees those of removed entities.
+    fn handle_world_events(&mut self) {
+        let generation = self.match_generation.current();
+        let events = &self.event_receiver;
+        let node_entity = &mut self.node_entity;
+        let mut freed = Vec::new();
+        let mut drained = 0;
+        with_world(|world| {
+            let sync = sync_node_map(
+                &*world,
+                generation,
+                events.try_iter(),
+                node_entity,
+                |world, entity| {
+                    world
+                        .entry_ref(entity)
+                        .ok()
+                        .and_then(|entry| entry.get_component::<NodeComponent>().ok().copied())
+                        .map(|component| component.node)
+                },
+            );
+            freed = sync.freed;
+            drained = sync.drained;
+        });
+        self.events_drained = drained;
+        for node in freed {
+            unsafe { node.assume_safe() }.queue_free();
+        }
+    }
+
+    #[export]
+    pub fn _process(&mut self, owner: TRef<'_, Node2D>, delta: f64) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            self.handle_world_events();
+
+            let ui_node = match &self.ui_node {
                 None => {
-                    godot_error!("No node found at ui_node path");
+                    godot_error!("ui_node is not set");
                     return;
                 }
-                Some(node) => match unsafe { node.assume_safe().cast::<Control>() } {
+                Some(node) => match owner.get_node(node.to_godot_string()) {
                     None => {
-                        godot_error!("Node at ui_node path is not a Control");
+                        godot_error!("No node found at ui_node path");
                         return;
                     }
-                    Some(node) => node,
+                    Some(node) => match unsafe { node.assume_safe().cast::<Control>() } {
+                        None => {
+                            godot_error!("Node at ui_node path is not a Control");
+                            return;
+                        }
+                        Some(node) => node,
+                    },
                 },
-            },
-        };
+            };
 
-        let camera_node = match &self.camera_node {
-            None => {
-                godot_error!("camera_node is not set");
-                return;
-            }
-            Some(node) => match owner.get_node(node.to_godot_string()) {
+            let camera_node = match &self.camera_node {
                 None => {
-                    godot_error!("No node found at camera_node path");
+                    godot_error!("camera_node is not set");
                     return;
                 }
-                Some(node) => match unsafe { node.assume_safe().cast::<Camera2D>() } {
+                Some(node) => match owner.get_node(node.to_godot_string()) {
                     None => {
-                        godot_error!("Node at camera_node path is not a Control");
+                        godot_error!("No node found at camera_node path");
                         return;
                     }
-                    Some(node) => node,
+                    Some(node) => match unsafe { node.assume_safe().cast::<Camera2D>() } {
+                        None => {
+                            godot_error!("Node at camera_node path is not a Control");
+                            return;
+                        }
+                        Some(node) => node,
+                    },
                 },
-            },
-        };
+            };
 
-        self.process.execute(&owner, ui_node, camera_node, delta);
-        owner.update();
+            self.process
+                .set_auto_end_turn(self.auto_end_turn, self.auto_end_turn_delay);
+            self.process.set_banner_duration(self.banner_duration);
+            self.process.set_privacy_screen(self.privacy_screen_enabled);
+            self.process
+                .set_cursor_orientation(if self.flat_top_cursor {
+                    Orientation::FlatTop
+                } else {
+                    Orientation::PointyTop
+                });
+            self.process
+                .set_touch_thresholds(self.long_press_duration, self.touch_drag_distance);
+            self.process.set_double_click_time(self.double_click_time);
+            self.process.set_hitstop_cap(self.max_hitstop);
+            self.process
+                .set_statistics_limit(self.statistics_limit as usize);
+            self.process.set_node_budget(NodeBudget {
+                max_nodes: self.nodes_per_frame as usize,
+                max_ms: self.node_ms_per_frame,
+            });
+            profiler::set_enabled(self.profiling_enabled);
+            self.process.set_hexfield_size(self.hexfield_size);
+            if let Err(error) = self.process.set_grid_radius(self.grid_radius) {
+                godot_warn!("Could not resize the map: {:?}", error);
+                self.grid_radius = self.process.grid_radius();
+            }
+            self.process.set_lake_size(self.lake_size);
+            self.process.execute(&owner, ui_node, camera_node, delta);
+            owner.update();
+        })
     }
 
     #[export]
-    pub fn _unhandled_input(&mut self, _owner: &Node2D, event: Variant) {
-        if let Some(event) = event.try_to_object::<InputEvent>() {
-            self.process.queue_input(event);
-        }
+    pub fn _unhandled_input(&mut self, owner: &Node2D, event: Variant) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            if let Some(event) = event.try_to_object::<InputEvent>() {
+                self.process.queue_input(event);
+            }
+        })
+    }
+
+    #[export]
+    pub fn _notification(&mut self, owner: TRef<'_, Node2D>, what: i64) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            if what == MainLoop::NOTIFICATION_WM_MOUSE_EXIT {
+                self.process.clear_hover(&owner);
+            }
+        })
+    }
+
+    #[export]
+    pub fn on_new_round(&mut self, owner: TRef<'_, Node2D>) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            self.process.new_round(&owner);
+        })
+    }
+
+    /// With the initiative rules, ends the turn of the active unit before it used up its actions.
+    /// Returns false in the classic turn order.
+    #[export]
+    pub fn pass_turn(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.pass_turn(&owner)
+        })
+    }
+
+    /// With the simultaneous rules, plans a move of the unit with the persistent id to the
+    /// hexagon. Returns false outside of the planning phase or if the move is not legal.
+    #[export]
+    pub fn plan_move(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        unit: i64,
+        target_q: i64,
+        target_r: i64,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            unit >= 0
+                && self.process.plan_move(
+                    &owner,
+                    PersistentId(unit as u64),
+                    Hexagon::new_axial(target_q as i32, target_r as i32),
+                )
+        })
+    }
+
+    /// With the simultaneous rules, plans an attack of one unit on another, both given by their
+    /// persistent ids. Returns false outside of the planning phase or if the attack is not legal.
+    #[export]
+    pub fn plan_attack(&mut self, owner: TRef<'_, Node2D>, unit: i64, target: i64) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            unit >= 0
+                && target >= 0
+                && self.process.plan_attack(
+                    &owner,
+                    PersistentId(unit as u64),
+                    PersistentId(target as u64),
+                )
+        })
+    }
+
+    /// Ends the planning phase and carries out the orders of all players. Returns false if
+    /// nobody is planning.
+    #[export]
+    pub fn resolve_round(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.resolve_round(&owner)
+        })
+    }
+
+    /// Orders planned by the players controlled on this machine, as dictionaries with the id of
+    /// the unit and its planned path or target. The orders of other players stay hidden.
+    #[export]
+    pub fn get_planned_orders(&self, owner: TRef<'_, Node2D>) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            { self.process.get_planned_orders() }
+        )
+    }
+
+    /// Orders the selected unit to use its ability with the index on the hexagon. Returns false
+    /// if no unit is selected or the ability cannot be used there.
+    #[export]
+    pub fn use_ability(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        index: i64,
+        target_q: i64,
+        target_r: i64,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            index >= 0
+                && self.process.use_ability(
+                    &owner,
+                    index as usize,
+                    Hexagon::new_axial(target_q as i32, target_r as i32),
+                )
+        })
+    }
+
+    /// Orders the selected unit to give up its remaining movement and fire at the first enemy
+    /// that moves into its range. Returns false if no unit is selected or it cannot attack.
+    #[export]
+    pub fn overwatch_selected(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.overwatch_selected(&owner)
+        })
+    }
+
+    /// Orders the selected demolition unit to attack the bridge between two neighbouring
+    /// hexagons. Returns false if there is no bridge in its range.
+    #[export]
+    pub fn attack_edge(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        from_q: i32,
+        from_r: i32,
+        to_q: i32,
+        to_r: i32,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.attack_edge(
+                &owner,
+                Hexagon::new_axial(from_q, from_r),
+                Hexagon::new_axial(to_q, to_r),
+            )
+        })
     }
 
+    /// Orders the selected unit into the building it stands on, for extra armor until it leaves.
+    /// Returns false if there is no building.
     #[export]
-    pub fn on_new_round(&mut self, _owner: TRef<'_, Node2D>) {
-        self.process.new_round();
+    pub fn garrison_selected(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.garrison_selected(&owner)
+        })
     }
 
+    /// Orders the selected unit out of its building onto a free neighbouring hexagon.
     #[export]
-    pub fn _draw(&mut self, _owner: TRef<'_, Node2D>) {
-        self.process.execute_draw();
+    pub fn ungarrison(&mut self, owner: TRef<'_, Node2D>, target_q: i32, target_r: i32) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process
+                .ungarrison(&owner, Hexagon::new_axial(target_q, target_r))
+        })
+    }
+
+    /// Orders the selected unit to attack the building on a hexagon in its range.
+    #[export]
+    pub fn attack_building(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        target_q: i32,
+        target_r: i32,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process
+                .attack_building(&owner, Hexagon::new_axial(target_q, target_r))
+        })
+    }
+
+    /// Turns the selected unit in place. `direction` counts sixth turns counterclockwise from
+    /// east, turning costs the movement of one hexagon.
+    #[export]
+    pub fn rotate_selected(&mut self, owner: TRef<'_, Node2D>, direction: i64) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process
+                .rotate_selected(&owner, Direction::from_index(direction))
+        })
+    }
+
+    /// Orders every selected unit back to the rally hexagon, around enemy threats where it can.
+    /// Units that can not get there safely step away from the closest enemy. Returns false
+    /// without a selection.
+    #[export]
+    pub fn retreat_all(&mut self, owner: TRef<'_, Node2D>, q: i32, r: i32) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.retreat_all(&owner, Hexagon::new_axial(q, r))
+        })
+    }
+
+    /// Picks the perk with the index among the options of the last "promotion_available" signal,
+    /// after which the game goes on. Returns false if no unit of the local player waits for its
+    /// promotion or the index is not offered.
+    #[export]
+    pub fn choose_promotion(&mut self, owner: TRef<'_, Node2D>, perk_index: i64) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            perk_index >= 0 && self.process.choose_promotion(&owner, perk_index as usize)
+        })
+    }
+
+    /// Every input action the game responds to as a dictionary with its "name", a short
+    /// "description" and the names of the keys and buttons bound to it in "bindings".
+    #[export]
+    pub fn get_input_bindings(&self, owner: TRef<'_, Node2D>) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            { self.process.get_input_bindings() }
+        )
+    }
+
+    /// Shows or hides the list of controls, like the "toggle_help" action. Returns whether it
+    /// is shown now.
+    #[export]
+    pub fn toggle_help(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.toggle_help()
+        })
+    }
+
+    /// Lets the selected unit pick the target of an attack, like the "attack_mode" action. Clicks
+    /// attack instead of moving until a right click or escape. Returns false without a selected
+    /// unit with an attack left.
+    #[export]
+    pub fn begin_targeting(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.begin_targeting(&owner)
+        })
+    }
+
+    /// The damage an attack of the selected unit on the unit on a hexagon would deal, with
+    /// `arc` telling whether it hits the front, the flank or the rear and `cover` how many
+    /// hexagons shield the target. A unit that moved and cannot fire after moving only gets the
+    /// "reason".
+    #[export]
+    pub fn preview_attack(
+        &self,
+        owner: TRef<'_, Node2D>,
+        target_q: i32,
+        target_r: i32,
+    ) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            {
+                self.process
+                    .preview_attack(Hexagon::new_axial(target_q, target_r))
+            }
+        )
+    }
+
+    /// Ends the pause at the start of a turn before banner_duration has passed.
+    #[export]
+    pub fn dismiss_banner(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.dismiss_banner()
+        })
+    }
+
+    /// Reveals the map after the privacy screen, once the next player is at the screen.
+    #[export]
+    pub fn confirm_player_ready(&mut self, owner: TRef<'_, Node2D>) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.confirm_player_ready()
+        })
+    }
+
+    #[export]
+    pub fn apply_remote_command(&mut self, owner: TRef<'_, Node2D>, json: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.apply_remote_command(&json)
+        })
+    }
+
+    /// Notable events of the unit at the hexagon, for its tooltip.
+    #[export]
+    pub fn get_unit_history(&self, owner: TRef<'_, Node2D>, q: i32, r: i32) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            {
+                self.process
+                    .get_unit_history(&owner, Hexagon::new_axial(q, r))
+            }
+        )
+    }
+
+    /// The timeline of the match for the replay scrubber: one entry per round and player with its
+    /// "round", "player" and "actions". Every action has its "index", "kind", "text",
+    /// "has_position" and "q"/"r".
+    #[export]
+    pub fn get_turn_summaries(&self, owner: TRef<'_, Node2D>) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            { self.process.get_turn_summaries(&owner) }
+        )
+    }
+
+    /// The units right after the action with the index, each with its "id", "player", "q"/"r"
+    /// and "integrity". The running game stays as it is. If an action of the replay does not
+    /// lead to the state it was logged with, "desync_detected" carries its index.
+    #[export]
+    pub fn preview_state_at(&self, owner: TRef<'_, Node2D>, action_index: i64) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            {
+                if action_index < 0 {
+                    return VariantArray::new_shared();
+                }
+                match self.process.preview_state_at(action_index as usize) {
+                    Ok(units) => units,
+                    Err(ReplayError::Desync {
+                        index,
+                        expected,
+                        actual,
+                    }) => {
+                        godot_warn!(
+                            "Replayed action {} led to checksum {}, the log has {}",
+                            index,
+                            actual,
+                            expected
+                        );
+                        unsafe {
+                            owner.call_deferred(
+                                "emit_signal",
+                                &[
+                                    GodotString::from_str("desync_detected").to_variant(),
+                                    (index as i64).to_variant(),
+                                ],
+                            );
+                        }
+                        VariantArray::new_shared()
+                    }
+                    Err(error) => {
+                        godot_warn!(
+                            "Could not replay up to action {}: {:?}",
+                            action_index,
+                            error
+                        );
+                        VariantArray::new_shared()
+                    }
+                }
+            }
+        )
+    }
+
+    /// The best move or attack for the local player whose turn it is, with its kind, the unit
+    /// "id", "from_q"/"from_r", "to_q"/"to_r", "score" and "rationale". Empty outside of a local
+    /// turn.
+    #[export]
+    pub fn get_hint(&self, owner: TRef<'_, Node2D>) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            { self.process.get_hint().into_shared() }
+        )
+    }
+
+    /// Lets the computer play for a player with the profile "berserker", "turtle" or "balanced".
+    /// An empty name hands the player back to a human. Returns false for unknown players and
+    /// profiles.
+    #[export]
+    pub fn set_ai_profile(&mut self, owner: TRef<'_, Node2D>, player: i64, name: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            if player < 0 {
+                return false;
+            }
+            let profile = if name.is_empty() {
+                None
+            } else {
+                match AiProfile::preset(&name) {
+                    None => {
+                        godot_warn!("Unknown AI profile {}", name);
+                        return false;
+                    }
+                    profile => profile,
+                }
+            };
+            self.process.set_ai_profile(player as usize, profile)
+        })
+    }
+
+    /// Why the unit got its last order, in the form of get_hint. Empty if it got none yet.
+    #[export]
+    pub fn explain_ai_move(&self, owner: TRef<'_, Node2D>, id: i64) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            {
+                if id < 0 {
+                    return Dictionary::new().into_shared();
+                }
+                self.process
+                    .explain_move(PersistentId(id as u64))
+                    .into_shared()
+            }
+        )
+    }
+
+    /// The player dominating the hexagon as "player" and their "influence" there, empty if no
+    /// player claims it.
+    #[export]
+    pub fn get_influence(&mut self, owner: TRef<'_, Node2D>, q: i32, r: i32) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            {
+                self.process
+                    .get_influence(Hexagon::new_axial(q, r))
+                    .into_shared()
+            }
+        )
+    }
+
+    /// The hexagons the player can supply, as dictionaries with q and r, for the supply overlay.
+    /// Empty without the supply rule.
+    #[export]
+    pub fn get_supply_overlay(&self, owner: TRef<'_, Node2D>, player: i64) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            {
+                if player < 0 {
+                    return VariantArray::new().into_shared();
+                }
+                self.process.get_supply_overlay(player as usize)
+            }
+        )
+    }
+
+    /// Frame timings by bucket, each with last_ms, avg_ms and max_ms.
+    #[export]
+    pub fn get_frame_timings(&self, owner: TRef<'_, Node2D>) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            { self.process.get_frame_timings() }
+        )
+    }
+
+    /// Entity counts and the current state, for a debug panel.
+    #[export]
+    pub fn get_world_diagnostics(&self, owner: TRef<'_, Node2D>) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            {
+                let diagnostics = self.process.get_world_diagnostics();
+                diagnostics.insert("node_entity", self.node_entity.nodes.len() as i64);
+                diagnostics.insert("events_drained", self.events_drained as i64);
+                diagnostics.into_shared()
+            }
+        )
+    }
+
+    /// Descriptions of every broken invariant of the world, empty if it is consistent.
+    #[export]
+    pub fn validate_world(&self, owner: TRef<'_, Node2D>) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            {
+                let node_entities: HashSet<Entity> =
+                    self.node_entity.nodes.keys().copied().collect();
+                let violations = VariantArray::new();
+                for violation in self.process.validate_world(&node_entities) {
+                    violations.push(violation);
+                }
+                violations.into_shared()
+            }
+        )
+    }
+
+    #[export]
+    pub fn get_log_entries(&self, owner: TRef<'_, Node2D>, count: i64) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            { self.process.get_log_entries(&owner, count.max(0) as usize) }
+        )
+    }
+
+    /// The name of the state the state machine is in, like "Waiting" or "Moving". Every change
+    /// is announced with "state_changed".
+    #[export]
+    pub fn get_state_name(&self, owner: TRef<'_, Node2D>) -> String {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            String::new(),
+            { self.process.get_state_name() }
+        )
+    }
+
+    /// Checksum of the current game state, see `GameState::compute_checksum`. Godot integers are
+    /// signed, so the bits of the unsigned checksum are passed through unchanged.
+    #[export]
+    pub fn get_state_checksum(&self, owner: TRef<'_, Node2D>) -> i64 {
+        guarded!(|report| self.recover_from_panic(&owner, &report), 0, {
+            self.process.get_state_checksum() as i64
+        })
+    }
+
+    /// Returns the current game as JSON, or an empty string if it could not be saved.
+    #[export]
+    pub fn save_game(&self, owner: TRef<'_, Node2D>) -> String {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            String::new(),
+            { self.process.save_game().unwrap_or_default() }
+        )
+    }
+
+    #[export]
+    pub fn load_game(&mut self, owner: TRef<'_, Node2D>, json: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.load_game(&owner, &json)
+        })
+    }
+
+    /// The position a camera should move to, follows the acting unit.
+    #[export]
+    pub fn get_focus_position(&self, owner: TRef<'_, Node2D>) -> Vector2 {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Vector2::zero(),
+            { self.process.get_focus_position() }
+        )
+    }
+
+    /// Position of the centre of a hexagon.
+    #[export]
+    pub fn center_on_hex(&self, owner: TRef<'_, Node2D>, q: i64, r: i64) -> Vector2 {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Vector2::zero(),
+            {
+                self.process
+                    .get_hexagon_position(Hexagon::new_axial(q as i32, r as i32))
+            }
+        )
+    }
+
+    /// Calls `method` on `target` with the stats of every new unit, from scenarios, the editor and
+    /// triggers. The method gets a dictionary with "unit_type", "player", "q", "r" and the stats
+    /// and can return a dictionary with the stats to change. Modifiers run in the order they
+    /// were registered. Returns false if the modifier was registered already or `target` is gone.
+    #[export]
+    pub fn register_spawn_modifier(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        target: Ref<Object>,
+        method: String,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            match ScriptModifier::new(target, method) {
+                None => false,
+                Some(modifier) => self.process.register_spawn_modifier(modifier),
+            }
+        })
+    }
+
+    /// Returns false if the modifier was not registered. Modifiers of freed objects are dropped
+    /// on their own.
+    #[export]
+    pub fn unregister_spawn_modifier(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        target: Ref<Object>,
+        method: String,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            match ScriptModifier::new(target, method) {
+                None => false,
+                Some(modifier) => self.process.unregister_spawn_modifier(&modifier),
+            }
+        })
+    }
+
+    /// Entities matching the filter, see `entity_query` for its keys. Every row holds "id",
+    /// "player", "q", "r" and for units their stats in "unit". A malformed filter returns a
+    /// dictionary with the reason in "error" instead.
+    #[export]
+    pub fn query_entities(&self, owner: TRef<'_, Node2D>, filter: Dictionary) -> Variant {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Variant::new(),
+            {
+                let parsed =
+                    serde_json::from_str::<serde_json::Value>(&filter.to_json().to_string())
+                        .map_err(|_| FilterError::NotADictionary)
+                        .and_then(|value| EntityFilter::from_json(&value));
+                match parsed {
+                    Err(error) => {
+                        godot_warn!("query_entities: {}", error.description());
+                        error.to_dictionary().owned_to_variant()
+                    }
+                    Ok(filter) => self.process.query_entities(&filter).owned_to_variant(),
+                }
+            }
+        )
+    }
+
+    /// Writes the commands since the last export, with the checksum of the state they started
+    /// from, as a turn file for import_turn. Returns false if there are none.
+    #[export]
+    pub fn export_turn(&mut self, owner: TRef<'_, Node2D>, path: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            match self.process.export_turn() {
+                None => false,
+                Some(json) => write_text_file(&path, &json),
+            }
+        })
+    }
+
+    /// Carries out the turn of the opponent from a file written by export_turn. The file is
+    /// rejected with "turn_rejected" if it does not start from the local state or its commands
+    /// do not lead where they did for the opponent.
+    #[export]
+    pub fn import_turn(&mut self, owner: TRef<'_, Node2D>, path: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let json = match read_text_file(&path) {
+                None => return false,
+                Some(json) => json,
+            };
+            match self.process.import_turn(&json) {
+                Ok(()) => true,
+                Err(rejection) => {
+                    godot_warn!("Turn rejected: {:?}", rejection);
+                    unsafe {
+                        owner.call_deferred(
+                            "emit_signal",
+                            &[
+                                GodotString::from_str("turn_rejected").to_variant(),
+                                rejection.to_dictionary().owned_to_variant(),
+                            ],
+                        );
+                    }
+                    false
+                }
+            }
+        })
+    }
+
+    /// The "resources", "income", "upkeep" and "net" income per round of the player, with the
+    /// "units" of the catalog as dictionaries of "unit_type", "cost", how many are "affordable"
+    /// and the "rounds_until_affordable", -1 if never. Empty for unknown players.
+    #[export]
+    pub fn get_economy_report(&self, owner: TRef<'_, Node2D>, player: i64) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            {
+                if player < 0 {
+                    return Dictionary::new().into_shared();
+                }
+                self.process
+                    .get_economy_report(player as usize)
+                    .into_shared()
+            }
+        )
+    }
+
+    /// A snapshot of every player at the start of each round for the graphs after a match: the
+    /// "rounds" and per metric an array with an array of values per player, "units",
+    /// "integrity", "resources" and "territory". Long matches keep only every k-th round.
+    #[export]
+    pub fn get_statistics_series(&self, owner: TRef<'_, Node2D>) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            { self.process.get_statistics_series().into_shared() }
+        )
+    }
+
+    /// Replaces the map of the running game with a hexagonal one of `radius` hexagons around the
+    /// centre. Every hexagon instances `scene_file` scaled by `node_scale`, the map is only drawn
+    /// if it is empty. Hexagons that stay keep their terrain, units stay where they are. Returns
+    /// the positions of the units outside of the new map as dictionaries with q and r, the map is
+    /// left as it is then. Empty on success.
+    #[export]
+    pub fn create_grid(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        radius: i64,
+        scene_file: String,
+        node_scale: f64,
+    ) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            {
+                let hexagons = hexgrid::create_grid(radius.max(0) as u32);
+                self.replace_grid(&hexagons, &scene_file, node_scale)
+            }
+        )
+    }
+
+    /// Replaces the map of the running game with a rectangular one of `width` hexagons and
+    /// `height` rows around the centre, like create_grid.
+    #[export]
+    pub fn create_rect_grid(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        width: i64,
+        height: i64,
+        scene_file: String,
+        node_scale: f64,
+    ) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            {
+                let hexagons = create_rect_grid(width.max(0) as u32, height.max(0) as u32);
+                self.replace_grid(&hexagons, &scene_file, node_scale)
+            }
+        )
+    }
+
+    /// The surviving units of the player as JSON, for import_roster before the next scenario of
+    /// a campaign. Empty if there is no game.
+    #[export]
+    pub fn export_roster(&self, owner: TRef<'_, Node2D>, player: i64) -> String {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            String::new(),
+            {
+                if player < 0 {
+                    return String::new();
+                }
+                self.process
+                    .export_roster(player as usize)
+                    .unwrap_or_default()
+            }
+        )
+    }
+
+    /// Fills the unit slots marked "from_roster" of the next scenario with the units of an
+    /// exported roster, in order. Returns false if the roster could not be read.
+    #[export]
+    pub fn import_roster(&mut self, owner: TRef<'_, Node2D>, player: i64, json: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            player >= 0 && self.process.import_roster(player as usize, &json)
+        })
+    }
+
+    /// Whether the map has the hexagon. Paths, the cursor and clicks stay on these hexagons.
+    #[export]
+    pub fn is_on_map(&self, owner: TRef<'_, Node2D>, q: i32, r: i32) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            self.process.is_on_map(&Hexagon::new_axial(q, r))
+        })
+    }
+
+    /// Units of the current player that can still move or have an enemy in attack range. Empty
+    /// once the player used up their command points.
+    #[export]
+    pub fn get_actionable_units(&self, owner: TRef<'_, Node2D>) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            { self.process.get_actionable_units() }
+        )
+    }
+
+    /// Pass a player index to control the game, or -1 to only watch it.
+    #[export]
+    pub fn set_local_controller(&mut self, owner: TRef<'_, Node2D>, player: i64) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            self.process.set_local_controller(player);
+        })
+    }
+
+    /// Sets the handicap of a player before the match. The dictionary can hold
+    /// "resource_multiplier", "integrity_bonus" in percent and "aggression" from 0 to 100,
+    /// missing keys keep their value. Returns false for invalid values and once the first round
+    /// is over.
+    #[export]
+    pub fn set_player_handicap(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        player: i64,
+        settings: Dictionary,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            if player < 0 {
+                return false;
+            }
+            let number = |key: &str| {
+                let value = settings.get(key);
+                value
+                    .try_to_f64()
+                    .or_else(|| value.try_to_i64().map(|value| value as f64))
+            };
+            let changes = HandicapChanges {
+                resource_multiplier: number("resource_multiplier"),
+                integrity_bonus: number("integrity_bonus").map(|value| value.round() as i64),
+                aggression: number("aggression").map(|value| value.round() as i64),
+            };
+            match self.process.set_player_handicap(player as usize, &changes) {
+                Ok(()) => true,
+                Err(error) => {
+                    godot_warn!("Handicap of player {} rejected: {:?}", player, error);
+                    false
+                }
+            }
+        })
+    }
+
+    /// Tutorial step: only selecting the unit on the hexagon is accepted until it is done.
+    #[export]
+    pub fn allow_only_select(&mut self, owner: TRef<'_, Node2D>, q: i64, r: i64) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.process
+                .set_tutorial_constraint(Some(TutorialConstraint::Select(hexagon)));
+        })
+    }
+
+    /// Tutorial step: only moving to the hexagon is accepted until it is done.
+    #[export]
+    pub fn allow_only_move_to(&mut self, owner: TRef<'_, Node2D>, q: i64, r: i64) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.process
+                .set_tutorial_constraint(Some(TutorialConstraint::MoveTo(hexagon)));
+        })
+    }
+
+    /// Tutorial step: only attacking the unit on the hexagon is accepted until it is done.
+    #[export]
+    pub fn allow_only_attack(&mut self, owner: TRef<'_, Node2D>, q: i64, r: i64) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.process
+                .set_tutorial_constraint(Some(TutorialConstraint::Attack(hexagon)));
+        })
+    }
+
+    #[export]
+    pub fn clear_constraints(&mut self, owner: TRef<'_, Node2D>) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            self.process.set_tutorial_constraint(None);
+        })
+    }
+
+    /// Renames the unit with the persistent id. Returns false for unknown units and empty names.
+    #[export]
+    pub fn rename_unit(&mut self, owner: TRef<'_, Node2D>, id: i64, name: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            if id < 0 {
+                return false;
+            }
+            match self.process.rename_unit(PersistentId(id as u64), &name) {
+                Ok(()) => true,
+                Err(error) => {
+                    godot_warn!("Unit {} not renamed: {:?}", id, error);
+                    false
+                }
+            }
+        })
+    }
+
+    /// Seeds the random events of the match.
+    #[export]
+    pub fn set_random_seed(&mut self, owner: TRef<'_, Node2D>, seed: i64) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            self.process.set_random_seed(seed as u64);
+        })
+    }
+
+    /// Only the given player indices can be controlled from this machine.
+    #[export]
+    pub fn set_local_players(&mut self, owner: TRef<'_, Node2D>, players: VariantArray) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            let players = players
+                .iter()
+                .filter_map(|player| player.try_to_i64())
+                .filter(|player| *player >= 0)
+                .map(|player| player as usize)
+                .collect();
+            self.process.set_local_players(players);
+        })
+    }
+
+    /// Spawns many units at once, much faster than one at a time. Every entry is a dictionary
+    /// with `player`, `q`, `r` and either `unit_type` or the unit stats in `stats`, `scene`
+    /// replaces the scene of the unit type. Nothing is spawned if an entry is invalid. Returns
+    /// the error of every entry, null for valid ones.
+    #[export]
+    pub fn spawn_units_bulk(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        units: VariantArray,
+    ) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            {
+                let entries: Vec<Result<UnitSpawn, String>> = units
+                    .iter()
+                    .map(|unit| match unit.try_to_dictionary() {
+                        None => Err("The entry has to be a dictionary".to_owned()),
+                        Some(dictionary) => {
+                            UnitSpawn::try_from(&Fields::from_dictionary(&dictionary))
+                                .map_err(|error| error.to_string())
+                        }
+                    })
+                    .collect();
+                let errors = VariantArray::new();
+                for error in self.process.spawn_units_bulk(&entries) {
+                    match error {
+                        None => errors.push(Variant::new()),
+                        Some(error) => errors.push(error),
+                    }
+                }
+                errors.into_shared()
+            }
+        )
+    }
+
+    #[export]
+    pub fn place_ping(&mut self, owner: TRef<'_, Node2D>, q: i64, r: i64, kind: String) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            self.process
+                .place_ping(&owner, Hexagon::new_axial(q as i32, r as i32), kind);
+        })
+    }
+
+    /// Recolours the players with one of the predefined palettes, some of which are safe for
+    /// colour blindness.
+    #[export]
+    pub fn set_color_palette(&mut self, owner: TRef<'_, Node2D>, name: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            if self.process.set_color_palette(&name) {
+                true
+            } else {
+                godot_warn!("Unknown colour palette {}", name);
+                false
+            }
+        })
+    }
+
+    /// The planned path of the selected unit for drawing it with a Line2D or a shader. A
+    /// `smoothing` of 0 keeps the corners, 1 rounds them.
+    #[export]
+    pub fn get_current_path_curve(
+        &self,
+        owner: TRef<'_, Node2D>,
+        smoothing: f32,
+    ) -> Ref<Curve2D, Unique> {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Curve2D::new(),
+            { self.process.get_current_path_curve(smoothing) }
+        )
+    }
+
+    #[export]
+    pub fn measure(
+        &self,
+        owner: TRef<'_, Node2D>,
+        q1: i64,
+        r1: i64,
+        q2: i64,
+        r2: i64,
+    ) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            {
+                self.process.measure(
+                    Hexagon::new_axial(q1 as i32, r1 as i32),
+                    Hexagon::new_axial(q2 as i32, r2 as i32),
+                )
+            }
+        )
+    }
+
+    /// Positions of the enemy units the selected unit can attack, with the number of hexagons
+    /// that cover each of them as `cover`.
+    #[export]
+    pub fn get_attackable_targets(&self, owner: TRef<'_, Node2D>) -> VariantArray {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            VariantArray::new_shared(),
+            { self.process.get_attackable_targets() }
+        )
+    }
+
+    #[export]
+    pub fn get_active_rules(&self, owner: TRef<'_, Node2D>) -> Dictionary {
+        guarded!(
+            |report| self.recover_from_panic(&owner, &report),
+            Dictionary::new_shared(),
+            { self.process.get_active_rules() }
+        )
+    }
+
+    #[export]
+    pub fn set_log_capacity(&mut self, owner: TRef<'_, Node2D>, capacity: i64) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            self.process.set_log_capacity(capacity.max(0) as usize);
+        })
+    }
+
+    #[export]
+    pub fn editor_set_terrain(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        q: i64,
+        r: i64,
+        terrain: String,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let terrain = match Terrain::from_name(&terrain) {
+                None => {
+                    godot_warn!("Unknown terrain {}", terrain);
+                    return false;
+                }
+                Some(terrain) => terrain,
+            };
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self
+                    .process
+                    .edit_map(|world, _| editor::set_terrain(world, &hexagon, terrain))
+        })
+    }
+
+    #[export]
+    pub fn editor_add_hex(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        q: i64,
+        r: i64,
+        terrain: String,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let terrain = match Terrain::from_name(&terrain) {
+                None => {
+                    godot_warn!("Unknown terrain {}", terrain);
+                    return false;
+                }
+                Some(terrain) => terrain,
+            };
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self
+                    .process
+                    .edit_map(|world, _| editor::add_hex(world, &hexagon, terrain))
+        })
+    }
+
+    #[export]
+    pub fn editor_remove_hex(&mut self, owner: TRef<'_, Node2D>, q: i64, r: i64) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self.process.edit_map(|world, state| {
+                    let removed = editor::remove_hex(world, &hexagon);
+                    if removed {
+                        state.edges.remove_around(&hexagon);
+                    }
+                    removed
+                })
+        })
+    }
+
+    /// Sets the edge between two neighbouring hexes to "river", "bridge" or "cliff". An empty
+    /// `kind` removes the edge.
+    #[export]
+    pub fn editor_set_edge(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        from_q: i64,
+        from_r: i64,
+        to_q: i64,
+        to_r: i64,
+        kind: String,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let kind = if kind.is_empty() {
+                None
+            } else {
+                match EdgeKind::from_name(&kind) {
+                    None => {
+                        godot_warn!("Unknown edge {}", kind);
+                        return false;
+                    }
+                    Some(kind) => Some(kind),
+                }
+            };
+            let from = Hexagon::new_axial(from_q as i32, from_r as i32);
+            let to = Hexagon::new_axial(to_q as i32, to_r as i32);
+            self.editor_mode
+                && self.process.edit_map(|world, state| {
+                    editor::set_edge(world, &mut state.edges, from, to, kind)
+                })
+        })
+    }
+
+    #[export]
+    pub fn editor_place_unit(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        q: i64,
+        r: i64,
+        player: i64,
+        unit_type: String,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            if player < 0 {
+                return false;
+            }
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self.process.edit_map(|world, state| {
+                    editor::place_unit(
+                        world,
+                        &mut state.persistent_ids,
+                        &state.unit_catalog,
+                        &hexagon,
+                        player as usize,
+                        &unit_type,
+                        true,
+                    )
+                })
+        })
+    }
+
+    #[export]
+    pub fn editor_remove_unit(&mut self, owner: TRef<'_, Node2D>, q: i64, r: i64) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self
+                    .process
+                    .edit_map(|world, _| editor::remove_unit(world, &hexagon))
+        })
+    }
+
+    /// Places a pickup, `kind` is "resources", "repair" or "ability_charge". `amount` is the
+    /// resources or integrity it gives.
+    #[export]
+    pub fn editor_place_pickup(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        q: i64,
+        r: i64,
+        kind: String,
+        amount: i64,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let pickup = match Pickup::from_name(&kind, amount as i32) {
+                None => {
+                    godot_warn!("Unknown pickup {}", kind);
+                    return false;
+                }
+                Some(pickup) => pickup,
+            };
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self
+                    .process
+                    .edit_map(|world, _| editor::place_pickup(world, &hexagon, pickup, true))
+        })
+    }
+
+    #[export]
+    pub fn editor_remove_pickup(&mut self, owner: TRef<'_, Node2D>, q: i64, r: i64) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self
+                    .process
+                    .edit_map(|world, _| editor::remove_pickup(world, &hexagon))
+        })
+    }
+
+    /// Places a building units can garrison, `integrity` of 0 or less uses the default.
+    #[export]
+    pub fn editor_place_building(
+        &mut self,
+        owner: TRef<'_, Node2D>,
+        q: i64,
+        r: i64,
+        integrity: i64,
+    ) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let integrity = if integrity > 0 {
+                integrity as i32
+            } else {
+                DEFAULT_BUILDING_INTEGRITY
+            };
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self.process.edit_map(|world, _| {
+                    editor::place_building(world, &hexagon, Building::new(integrity), true)
+                })
+        })
+    }
+
+    #[export]
+    pub fn editor_remove_building(&mut self, owner: TRef<'_, Node2D>, q: i64, r: i64) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let hexagon = Hexagon::new_axial(q as i32, r as i32);
+            self.editor_mode
+                && self
+                    .process
+                    .edit_map(|world, _| editor::remove_building(world, &hexagon))
+        })
+    }
+
+    /// Writes the current map and units as a scenario file that load_scenario can read.
+    #[export]
+    pub fn export_scenario(&mut self, owner: TRef<'_, Node2D>, path: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            if !self.editor_mode {
+                return false;
+            }
+            match self.process.scenario().map(|scenario| scenario.to_json()) {
+                None => false,
+                Some(Err(error)) => {
+                    godot_error!("Could not serialize scenario: {}", error);
+                    false
+                }
+                Some(Ok(json)) => write_text_file(&path, &json),
+            }
+        })
+    }
+
+    #[export]
+    pub fn load_scenario(&mut self, owner: TRef<'_, Node2D>, path: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let scenario = match read_scenario(&path) {
+                None => return false,
+                Some(scenario) => scenario,
+            };
+            self.process.edit_map(|world, state| {
+                for unit_type in load_scenario(world, state, &scenario) {
+                    godot_warn!("Unit type {} is not in the catalog", unit_type);
+                }
+                true
+            })
+        })
+    }
+
+    /// Ends the current match and starts a new one, on the default map if `scenario_path` is
+    /// empty. Returns false if the scenario could not be read, the current match then goes on.
+    #[export]
+    pub fn new_game(&mut self, owner: TRef<'_, Node2D>, scenario_path: String) -> bool {
+        guarded!(|report| self.recover_from_panic(&owner, &report), false, {
+            let scenario = if scenario_path.is_empty() {
+                None
+            } else {
+                match read_scenario(&scenario_path) {
+                    None => return false,
+                    Some(scenario) => Some(scenario),
+                }
+            };
+            // Nodes created since the last frame are only known from their events.
+            self.handle_world_events();
+            self.process.new_game(scenario.as_ref());
+            // Frees the nodes of the old match and drops its removals.
+            self.handle_world_events();
+            true
+        })
+    }
+
+    #[export]
+    pub fn _draw(&mut self, owner: TRef<'_, Node2D>) {
+        guarded!(|report| self.recover_from_panic(&owner, &report), (), {
+            self.process.execute_draw();
+        })
+    }
+}
+
+fn read_scenario(path: &str) -> Option<Scenario> {
+    match Scenario::from_json(&read_text_file(path)?) {
+        Err(error) => {
+            godot_error!("Could not read scenario {}: {}", path, error);
+            None
+        }
+        Ok(scenario) => Some(scenario),
+    }
+}
+
+fn read_text_file(path: &str) -> Option<String> {
+    let file = File::new();
+    match file.open(path, File::READ) {
+        Err(_) => {
+            godot_error!("Could not open file {}", path);
+            None
+        }
+        Ok(_) => {
+            let text = file.get_as_text().to_string();
+            file.close();
+            Some(text)
+        }
+    }
+}
+
+fn write_text_file(path: &str, text: &str) -> bool {
+    let file = File::new();
+    match file.open(path, File::WRITE) {
+        Err(_) => {
+            godot_error!("Could not open file {} for writing", path);
+            false
+        }
+        Ok(_) => {
+            file.store_string(text);
+            file.close();
+            true
+        }
     }
 }
 
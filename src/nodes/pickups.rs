@@ -0,0 +1,24 @@
+use crate::components::hexagon::Hexagon;
+use crate::components::node_component::NodeComponent;
+use crate::components::pickup::Pickup;
+use crate::game_state::GameState;
+use legion::system;
+
+/// Shows pickups only where the current player can see. Unlike enemy units they leave no ghost.
+#[system(for_each)]
+pub fn update_pickups(
+    node: &NodeComponent,
+    hexagon: &Hexagon,
+    _pickup: &Pickup,
+    #[resource] state: &GameState,
+) {
+    let node = match node.get_node() {
+        Some(node) => node,
+        None => return,
+    };
+    let hidden_by_fog = state.rules.fog_of_war
+        && state
+            .current_player
+            .map_or(false, |viewer| !state.last_seen.sees(viewer, hexagon));
+    node.set_visible(!state.is_view_hidden() && !hidden_by_fog);
+}
@@ -1,8 +1,18 @@
+use crate::components::facing::{Facing, Rotatable};
+use crate::components::garrison::Garrisoned;
+use crate::components::hexagon::Hexagon;
 use crate::components::node_component::NodeComponent;
 use crate::components::player::Player;
-use crate::components::unit::Unit;
+use crate::components::selection_indicator::{IndicatorChange, SelectionIndicator};
+use crate::components::supply::Supply;
+use crate::components::unit::{ActionPool, Unit};
+use crate::components::unit_name::UnitName;
+use crate::components::unit_nodes::{ChildNode, UnitNode, UnitNodePaths, UnitNodes};
 use crate::game_state::GameState;
-use crate::game_state::State::Selected;
+use crate::game_state::State::{GroupSelected, Selected, Targeting};
+use crate::profiler;
+use crate::systems::hexgrid::get_2d_position_from_hex;
+use gdnative::api::AnimationPlayer;
 use gdnative::prelude::*;
 use legion::{system, Entity};
 
@@ -14,58 +24,221 @@ pub fn update_units(
     node: &NodeComponent,
     unit: &Unit,
     player: &Player,
+    hexagon: &Hexagon,
+    indicator: &mut SelectionIndicator,
+    name: Option<&UnitName>,
+    garrisoned: Option<&Garrisoned>,
+    facing: Option<&Facing>,
+    rotatable: Option<&Rotatable>,
+    supply: Option<&Supply>,
+    paths: Option<&UnitNodePaths>,
+    children: &mut UnitNodes,
     #[resource] state: &GameState,
 ) {
+    let _timer = profiler::scope("update_units");
     let node = match node.get_node() {
         Some(node) => node,
         None => return,
     };
-    let integrity_label = node
-        .get_node("Integrity")
-        .and_then(|node| unsafe { node.assume_safe_if_sane() })
-        .and_then(|node| node.cast::<Label>());
-    let integrity_label = match integrity_label {
-        None => {
-            godot_error!("Node has no Integrity label");
-            return;
+    // Nothing of the outgoing player's view may show while the next hotseat player takes over.
+    // Enemies in the fog of war are only shown as ghosts.
+    let hidden_by_fog = state.rules.fog_of_war
+        && state.current_player.map_or(false, |viewer| {
+            state.last_seen.hides(viewer, player.0, hexagon)
+        });
+    // Units inside a building show as the badge of the building.
+    let hidden = state.is_view_hidden() || hidden_by_fog || garrisoned.is_some();
+    node.set_visible(!hidden);
+    if hidden {
+        return;
+    }
+    if let Some(label) = child::<Label>(node, children, paths, UnitNode::Integrity) {
+        label.set_text(format!("{}", unit.integrity));
+    }
+    // The optional health bar is full for the integrity the unit started with.
+    if let Some(bar) = child::<Range>(node, children, paths, UnitNode::HealthBar) {
+        let integrity = f64::from(unit.integrity);
+        if bar.max() < integrity {
+            bar.set_max(integrity);
         }
-        Some(label) => label,
-    };
-
-    integrity_label.set_text(format!("{}", unit.integrity));
+        bar.set_value(integrity);
+    }
 
-    let visible = if let Selected(selected) = state.state {
-        *entity == selected
-    } else {
-        false
-    };
+    let (movement, attacks) = action_labels(unit, player.0, state).unwrap_or_default();
+    for (slot, text) in [(UnitNode::Movement, movement), (UnitNode::Attacks, attacks)].iter() {
+        if let Some(label) = child::<Label>(node, children, paths, *slot) {
+            label.set_text(text.as_str());
+        }
+    }
+    // The name label is optional, scenes without one show no names.
+    if let Some(label) = child::<Label>(node, children, paths, UnitNode::NameLabel) {
+        label.set_text(name.map_or("", |name| name.0.as_str()));
+    }
+    // The optional warning shows units that started the round out of supply.
+    if let Some(warning) = child::<CanvasItem>(node, children, paths, UnitNode::SupplyWarning) {
+        warning.set_visible(supply.map_or(false, |supply| !supply.in_supply));
+    }
 
-    let outline = node.get_node("Outline");
+    if let Some(attackable_outline) =
+        child::<CanvasItem>(node, children, paths, UnitNode::AttackableOutline)
+    {
+        attackable_outline.set_visible(state.attackable_entities.contains(entity));
+    }
 
-    let outline = outline
-        .and_then(|outline| unsafe { outline.assume_safe_if_sane() })
-        .and_then(|outline| outline.cast::<CanvasItem>());
-    let outline = match outline {
-        None => {
-            return;
+    let selection = match state.state {
+        Selected(selected) | Targeting(selected) if *entity == selected => {
+            Some(state.selection_generation)
         }
-        Some(outline) => outline,
+        GroupSelected(ref members) if members.contains(entity) => Some(state.selection_generation),
+        _ => None,
     };
+    let change = indicator.update(selection);
 
-    outline.set_visible(visible);
-    let model = node
-        .get_node("Model")
-        .and_then(|node| unsafe { node.assume_safe_if_sane() })
-        .and_then(|node| node.cast::<CanvasItem>());
-
-    let model = match model {
-        None => {
-            godot_error!("Node has no Model CanvasItem node");
-            return;
+    if let Some(outline) = child::<CanvasItem>(node, children, paths, UnitNode::Outline) {
+        // The optional AnimationPlayer pulses the outline, without it the outline is static.
+        let animation = child::<AnimationPlayer>(node, children, paths, UnitNode::AnimationPlayer);
+        match change {
+            None => {}
+            Some(IndicatorChange::Show) => {
+                outline.set_visible(true);
+                if let Some(animation) = animation {
+                    animation.stop(true);
+                    animation.play("pulse", -1.0, 1.0, false);
+                }
+            }
+            Some(IndicatorChange::Hide) => {
+                outline.set_visible(false);
+                if let Some(animation) = animation {
+                    animation.stop(true);
+                }
+            }
         }
-        Some(model) => model,
-    };
+        // Selections of players on other machines are only shown for watching, so they are
+        // dimmed.
+        let outline_alpha = if state.is_local_turn() { 1.0 } else { 0.4 };
+        outline.set_modulate(Color::rgba(1.0, 1.0, 1.0, outline_alpha));
+    }
+
     let player = &state.players[player.0];
-    let colour = player.get_colour();
-    model.set_modulate(colour);
+    if let Some(model) = child::<CanvasItem>(node, children, paths, UnitNode::Model) {
+        model.set_modulate(player.get_colour());
+        // Models of rotatable units point east and turn towards the neighbour they face.
+        if let (Some(facing), Some(_)) = (facing, rotatable) {
+            if let Some(model) = model.cast::<Node2D>() {
+                let towards =
+                    get_2d_position_from_hex(&Hexagon::zero().get_neighbour(facing.0), 1.0);
+                model.set_rotation(f64::from(towards.y.atan2(towards.x)));
+            }
+        }
+    }
+
+    for (name, visible) in player.get_pattern().node_visibility().iter() {
+        if let Some(pattern) = node
+            .get_node(*name)
+            .and_then(|node| unsafe { node.assume_safe_if_sane() })
+            .and_then(|node| node.cast::<CanvasItem>())
+        {
+            pattern.set_visible(*visible);
+        }
+    }
+}
+
+/// The child of the unit node at the path of `slot`, if it is a `T`. Looked up once per unit
+/// node, a missing node every scene should have is reported the first time only.
+#[allow(unused_qualifications)] // See `instance_scene`, the bound needs the qualified trait.
+fn child<'a, T>(
+    node: TRef<'a, Node2D>,
+    children: &mut UnitNodes,
+    paths: Option<&UnitNodePaths>,
+    slot: UnitNode,
+) -> Option<TRef<'a, T>>
+where
+    T: gdnative::GodotObject<RefKind = ManuallyManaged> + SubClass<Node>,
+{
+    let found = children.get(node.get_instance_id(), slot, || {
+        let path = paths.map_or(slot.default_path(), |paths| paths.path(slot));
+        node.get_node(path).map(ChildNode)
+    });
+    let found = found
+        .and_then(|child| unsafe { child.0.assume_safe_if_sane() })
+        .and_then(|child| child.cast::<T>());
+    if found.is_none() && children.report_missing(slot) {
+        godot_warn!("Unit node has no {:?} node", slot);
+    }
+    found
+}
+
+/// Formats what is left of a per turn budget, like "3/5".
+pub fn format_remaining(remaining: i32, total: i32) -> String {
+    format!("{}/{}", remaining.max(0), total)
+}
+
+/// Texts of the optional Movement and Attacks labels. Only units of the current player show
+/// them, so nothing leaks about enemy units. With action points the Movement label shows the
+/// points and the Attacks label stays empty.
+pub fn action_labels(unit: &Unit, owner: usize, state: &GameState) -> Option<(String, String)> {
+    if state.current_player != Some(owner) {
+        return None;
+    }
+    match unit.pool(&state.rules) {
+        ActionPool::Separate {
+            remaining_range,
+            remaining_attacks,
+        } => Some((
+            format_remaining(remaining_range, unit.mobility),
+            format_remaining(remaining_attacks, state.rules.attacks_per_round),
+        )),
+        ActionPool::ActionPoints(action_points) => Some((
+            format_remaining(action_points, state.rules.action_points_per_round),
+            String::new(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_remaining_shows_remaining_and_total() {
+        assert_eq!(format_remaining(3, 5), "3/5");
+        assert_eq!(format_remaining(0, 1), "0/1");
+        assert_eq!(format_remaining(-2, 4), "0/4");
+    }
+
+    #[test]
+    fn action_labels_are_only_shown_for_the_current_player() {
+        let mut state = GameState::new();
+        state.current_player = Some(1);
+        let unit = Unit::new(5, 1, 1, 1, 0, 5, 3, 0);
+
+        assert_eq!(action_labels(&unit, 0, &state), None);
+        assert_eq!(
+            action_labels(&unit, 1, &state),
+            Some((
+                "3/5".to_owned(),
+                format!("0/{}", state.rules.attacks_per_round)
+            ))
+        );
+
+        state.current_player = None;
+        assert_eq!(action_labels(&unit, 1, &state), None);
+    }
+
+    #[test]
+    fn action_labels_show_action_points_when_the_rules_use_them() {
+        let mut state = GameState::new();
+        state.current_player = Some(0);
+        state.rules.action_points = true;
+        let mut unit = Unit::new(5, 1, 1, 1, 0, 5, 3, 1);
+        unit.action_points = 1;
+
+        assert_eq!(
+            action_labels(&unit, 0, &state),
+            Some((
+                format!("1/{}", state.rules.action_points_per_round),
+                String::new()
+            ))
+        );
+    }
 }
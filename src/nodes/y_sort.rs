@@ -0,0 +1,170 @@
+//! Draws nodes further south over the ones north of them, for sprites taller than their hexagon.
+//! Rows are stacked above the fixed layers, like the terrain, and every row has `ROW_LAYERS` z
+//! indices for the base z index of the templates.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::node_component::NodeComponent;
+use crate::components::y_sort_by_hex::YSortByHex;
+use crate::game_state::GameState;
+use crate::hex_cursor::Orientation;
+use crate::profiler;
+use crate::systems::dynamic_nodes::TemplateTarget;
+use legion::system;
+
+/// Z indices of every row, the base z index of a template picks one of them.
+pub const ROW_LAYERS: i64 = 4;
+/// Rows north of the centre that still get their own z index.
+const ROW_OFFSET: i64 = 256;
+/// The highest z index Godot allows.
+const MAX_Z_INDEX: i64 = 4096;
+
+/// The row of the hexagon on screen, growing to the south.
+pub fn hex_row(hexagon: &Hexagon, orientation: Orientation) -> i64 {
+    let q = i64::from(hexagon.get_q());
+    let r = i64::from(hexagon.get_r());
+    match orientation {
+        Orientation::PointyTop => r,
+        // Flat-top columns are shifted by half a hexagon, so rows are counted in half hexagons.
+        Orientation::FlatTop => 2 * r + q,
+    }
+}
+
+pub fn y_sorted_z_index(base: i64, hexagon: &Hexagon, orientation: Orientation) -> i64 {
+    let rows = MAX_Z_INDEX / ROW_LAYERS - 1;
+    let row = (hex_row(hexagon, orientation) + ROW_OFFSET)
+        .max(0)
+        .min(rows);
+    ROW_LAYERS * (row + 1) + base.max(0).min(ROW_LAYERS - 1)
+}
+
+/// Sets the z index of the node if its row changed, returns whether it did.
+pub fn refresh_y_sort<T: TemplateTarget + ?Sized>(
+    node: &T,
+    sort: &mut YSortByHex,
+    hexagon: &Hexagon,
+    orientation: Orientation,
+) -> bool {
+    match sort.update(y_sorted_z_index(sort.base, hexagon, orientation)) {
+        None => false,
+        Some(z_index) => {
+            node.set_z_index(z_index);
+            true
+        }
+    }
+}
+
+#[system(for_each)]
+pub fn update_y_sort(
+    node: &NodeComponent,
+    hexagon: &Hexagon,
+    sort: &mut YSortByHex,
+    #[resource] state: &GameState,
+) {
+    let _timer = profiler::scope("update_y_sort");
+    if let Some(node) = node.get_node() {
+        refresh_y_sort(&*node, sort, hexagon, state.hex_cursor.orientation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gdnative::core_types::Color;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct FakeNode {
+        z_indices: RefCell<Vec<i64>>,
+    }
+
+    impl TemplateTarget for FakeNode {
+        fn set_z_index(&self, z_index: i64) {
+            self.z_indices.borrow_mut().push(z_index);
+        }
+
+        fn set_scale(&self, _x: f32, _y: f32) {}
+
+        fn set_rotation_degrees(&self, _degrees: f32) {}
+
+        fn set_modulate(&self, _colour: Color) {}
+
+        fn set_visible(&self, _visible: bool) {}
+
+        fn add_to_group(&self, _group: &str) {}
+    }
+
+    #[test]
+    fn southern_rows_draw_over_northern_ones() {
+        let z = |q, r, orientation| y_sorted_z_index(1, &Hexagon::new_axial(q, r), orientation);
+
+        assert!(z(0, 1, Orientation::PointyTop) > z(0, 0, Orientation::PointyTop));
+        assert!(z(-1, 1, Orientation::PointyTop) > z(3, 0, Orientation::PointyTop));
+        assert_eq!(
+            z(-2, 0, Orientation::PointyTop),
+            z(2, 0, Orientation::PointyTop)
+        );
+
+        // The east neighbour is south east on a flat-top grid, the north east one is north east.
+        assert!(z(1, 0, Orientation::FlatTop) > z(0, 0, Orientation::FlatTop));
+        assert!(z(1, -1, Orientation::FlatTop) < z(0, 0, Orientation::FlatTop));
+        assert!(z(0, 1, Orientation::FlatTop) > z(1, 0, Orientation::FlatTop));
+    }
+
+    #[test]
+    fn base_orders_nodes_within_a_row_and_rows_stay_above_the_terrain() {
+        let hexagon = Hexagon::new_axial(0, 0);
+        let unit = y_sorted_z_index(1, &hexagon, Orientation::PointyTop);
+        let smoke = y_sorted_z_index(2, &hexagon, Orientation::PointyTop);
+        let unit_south = y_sorted_z_index(1, &Hexagon::new_axial(0, 1), Orientation::PointyTop);
+
+        assert!(unit < smoke && smoke < unit_south);
+        let far_north =
+            y_sorted_z_index(0, &Hexagon::new_axial(0, -10_000), Orientation::PointyTop);
+        let far_south = y_sorted_z_index(9, &Hexagon::new_axial(0, 10_000), Orientation::PointyTop);
+        assert!(far_north > 0);
+        assert!(far_south <= MAX_Z_INDEX);
+    }
+
+    #[test]
+    fn only_moved_nodes_are_updated() {
+        let node = FakeNode::default();
+        let mut sort = YSortByHex::new(1);
+        let start = Hexagon::new_axial(0, 0);
+
+        assert!(refresh_y_sort(
+            &node,
+            &mut sort,
+            &start,
+            Orientation::PointyTop
+        ));
+        assert!(!refresh_y_sort(
+            &node,
+            &mut sort,
+            &start,
+            Orientation::PointyTop
+        ));
+        // Moving within the row keeps the z index.
+        let east = Hexagon::new_axial(1, 0);
+        assert!(!refresh_y_sort(
+            &node,
+            &mut sort,
+            &east,
+            Orientation::PointyTop
+        ));
+        let south = Hexagon::new_axial(1, 1);
+        assert!(refresh_y_sort(
+            &node,
+            &mut sort,
+            &south,
+            Orientation::PointyTop
+        ));
+
+        assert_eq!(
+            *node.z_indices.borrow(),
+            vec![
+                y_sorted_z_index(1, &start, Orientation::PointyTop),
+                y_sorted_z_index(1, &south, Orientation::PointyTop)
+            ]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Player colour palettes, including sets that stay distinguishable with colour blindness.
+
+use crate::player::Player;
+use gdnative::core_types::Color;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PALETTE: &str = "default";
+
+/// Pattern drawn on top of the unit tint, so ownership does not rely on colour alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OwnershipPattern {
+    Solid,
+    Stripes,
+    Dots,
+}
+
+impl OwnershipPattern {
+    const ALL: [OwnershipPattern; 3] = [
+        OwnershipPattern::Solid,
+        OwnershipPattern::Stripes,
+        OwnershipPattern::Dots,
+    ];
+
+    /// Visibility of the optional pattern nodes of a unit scene.
+    pub fn node_visibility(&self) -> [(&'static str, bool); 2] {
+        [
+            ("PatternStripes", *self == OwnershipPattern::Stripes),
+            ("PatternDots", *self == OwnershipPattern::Dots),
+        ]
+    }
+}
+
+impl Default for OwnershipPattern {
+    fn default() -> Self {
+        OwnershipPattern::Solid
+    }
+}
+
+#[derive(Debug)]
+pub struct Palette {
+    pub name: &'static str,
+    colours: &'static [(f32, f32, f32)],
+    /// Gives every player a different pattern, not only those that share a colour.
+    patterns_for_all: bool,
+}
+
+static PALETTES: [Palette; 3] = [
+    Palette {
+        name: DEFAULT_PALETTE,
+        colours: &[(0.0, 0.0, 1.0), (1.0, 0.0, 0.0)],
+        patterns_for_all: false,
+    },
+    // Okabe & Ito, "Color Universal Design".
+    Palette {
+        name: "okabe_ito",
+        colours: &[
+            (0.0, 0.447, 0.698),
+            (0.902, 0.624, 0.0),
+            (0.0, 0.620, 0.451),
+            (0.800, 0.475, 0.655),
+            (0.337, 0.706, 0.914),
+            (0.835, 0.369, 0.0),
+        ],
+        patterns_for_all: false,
+    },
+    // Blue and orange only, with patterns for everyone.
+    Palette {
+        name: "high_contrast",
+        colours: &[(0.0, 0.447, 0.698), (0.902, 0.624, 0.0)],
+        patterns_for_all: true,
+    },
+];
+
+impl Palette {
+    pub fn find(name: &str) -> Option<&'static Palette> {
+        PALETTES.iter().find(|palette| palette.name == name)
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        PALETTES.iter().map(|palette| palette.name)
+    }
+
+    /// Colour and pattern of the player with the given index. Colours repeat once the palette is
+    /// exhausted, the pattern then tells the players apart.
+    pub fn style(&self, index: usize) -> (Color, OwnershipPattern) {
+        let (r, g, b) = self.colours[index % self.colours.len()];
+        let pattern_index = if self.patterns_for_all {
+            index
+        } else {
+            index / self.colours.len()
+        };
+        (
+            Color::rgb(r, g, b),
+            OwnershipPattern::ALL[pattern_index % OwnershipPattern::ALL.len()],
+        )
+    }
+
+    pub fn apply(&self, players: &mut [Player]) {
+        for (index, player) in players.iter_mut().enumerate() {
+            let (colour, pattern) = self.style(index);
+            player.set_colour(colour);
+            player.set_pattern(pattern);
+        }
+    }
+}
+
+/// Moves the colour towards the grey of the same brightness by `amount` between 0 and 1, for
+/// things that are only remembered like units hidden by the fog of war. Alpha is kept.
+pub fn desaturate(colour: Color, amount: f32) -> Color {
+    let grey = 0.299 * colour.r + 0.587 * colour.g + 0.114 * colour.b;
+    let mix = |channel: f32| channel + (grey - channel) * amount;
+    Color::rgba(mix(colour.r), mix(colour.g), mix(colour.b), colour.a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players(count: usize) -> Vec<Player> {
+        (0..count)
+            .map(|index| Player::new(format!("Player {}", index + 1), Color::rgb(0.0, 0.0, 0.0)))
+            .collect()
+    }
+
+    #[test]
+    fn every_palette_keeps_players_distinguishable() {
+        for name in Palette::names() {
+            let palette = Palette::find(name).unwrap();
+            let mut players = players(palette.colours.len() * OwnershipPattern::ALL.len());
+
+            palette.apply(&mut players);
+
+            for (index, player) in players.iter().enumerate() {
+                for other in &players[index + 1..] {
+                    assert!(
+                        player.get_colour() != other.get_colour()
+                            || player.get_pattern() != other.get_pattern(),
+                        "{} repeats a style",
+                        name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn default_palette_only_adds_patterns_when_colours_repeat() {
+        let mut players = players(5);
+
+        Palette::find(DEFAULT_PALETTE).unwrap().apply(&mut players);
+
+        let patterns: Vec<OwnershipPattern> =
+            players.iter().map(|player| player.get_pattern()).collect();
+        assert_eq!(
+            patterns,
+            vec![
+                OwnershipPattern::Solid,
+                OwnershipPattern::Solid,
+                OwnershipPattern::Stripes,
+                OwnershipPattern::Stripes,
+                OwnershipPattern::Dots,
+            ]
+        );
+        assert_eq!(players[0].get_colour(), players[2].get_colour());
+        assert_ne!(players[0].get_colour(), players[1].get_colour());
+    }
+
+    #[test]
+    fn high_contrast_palette_gives_everyone_a_pattern() {
+        let mut players = players(3);
+
+        Palette::find("high_contrast").unwrap().apply(&mut players);
+
+        assert_eq!(players[1].get_pattern(), OwnershipPattern::Stripes);
+        assert_eq!(players[2].get_pattern(), OwnershipPattern::Dots);
+        assert_eq!(players[0].get_colour(), players[2].get_colour());
+    }
+
+    #[test]
+    fn desaturated_colours_turn_grey() {
+        let red = Color::rgba(1.0, 0.0, 0.0, 0.5);
+
+        assert_eq!(desaturate(red, 0.0), red);
+        let grey = desaturate(red, 1.0);
+        assert!((grey.r - grey.g).abs() < 1e-6);
+        assert!((grey.g - grey.b).abs() < 1e-6);
+        assert_eq!(grey.a, 0.5);
+    }
+
+    #[test]
+    fn unknown_palette_is_not_found() {
+        assert!(Palette::find("sepia").is_none());
+    }
+
+    #[test]
+    fn pattern_nodes_match_the_pattern() {
+        assert_eq!(
+            OwnershipPattern::Solid.node_visibility(),
+            [("PatternStripes", false), ("PatternDots", false)]
+        );
+        assert_eq!(
+            OwnershipPattern::Stripes.node_visibility(),
+            [("PatternStripes", true), ("PatternDots", false)]
+        );
+        assert_eq!(
+            OwnershipPattern::Dots.node_visibility(),
+            [("PatternStripes", false), ("PatternDots", true)]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! The boundary between Godot and panics. Every exported method runs its body through `guarded!`,
+//! so a panic is logged with its location and reported instead of unwinding into the engine. The
+//! game state is put back into a state that takes orders, the world mutex recovers from the
+//! poisoning the panic left.
+//! Tests run the same boundary, but the panic is raised again after the recovery so they still
+//! fail.
+
+use crate::game_state::{force_state, GameState, State};
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, Once, TryLockError};
+
+static INSTALL_HOOK: Once = Once::new();
+
+thread_local! {
+    /// Where the last panic on this thread happened, filled in by the panic hook.
+    static LAST_LOCATION: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// A panic caught at the boundary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PanicReport {
+    pub message: String,
+    /// "file:line:column" of the panic, if the hook saw it.
+    pub location: Option<String>,
+}
+
+impl fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            None => write!(f, "{}", self.message),
+            Some(location) => write!(f, "{} at {}", self.message, location),
+        }
+    }
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+/// Remembers the location of every panic before the previous hook prints it.
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let location = info.location().map(|location| {
+                format!(
+                    "{}:{}:{}",
+                    location.file(),
+                    location.line(),
+                    location.column()
+                )
+            });
+            LAST_LOCATION.with(|last| *last.borrow_mut() = location);
+            previous(info);
+        }));
+    });
+}
+
+/// Runs `f`, turning a panic into a report.
+pub fn catch<T, F: FnOnce() -> T>(f: F) -> Result<T, PanicReport> {
+    install_hook();
+    LAST_LOCATION.with(|last| *last.borrow_mut() = None);
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| PanicReport {
+        message: payload_message(payload.as_ref()),
+        location: LAST_LOCATION.with(|last| last.borrow_mut().take()),
+    })
+}
+
+pub fn log_panic(report: &PanicReport) {
+    log_error!("Rust panic: {}", report);
+}
+
+/// Raises the panic again in tests, so they do not pass over it. Does nothing in the game.
+pub fn resume_in_tests(report: PanicReport) {
+    if cfg!(test) {
+        panic::resume_unwind(Box::new(report.to_string()));
+    }
+}
+
+/// Locks the mutex unless it is locked already. A mutex poisoned by a panic is taken over, its
+/// data is whatever the panic left.
+pub fn try_lock_recovering<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// Drops whatever order was being carried out when the panic hit and waits for new orders. A game
+/// that is over stays over.
+pub fn recover_state(state: &mut GameState) {
+    if let State::GameOver(_) = state.state {
+        return;
+    }
+    state.current_path.clear();
+    state.group_moves.clear();
+    state.retreats.clear();
+    state.redraw_grid = true;
+    force_state(state, State::Waiting);
+}
+
+/// Runs `$body` behind the panic boundary. On a panic it is logged, `$recover` is run with the
+/// report bound to `$report` and `$fallback` is returned in place of the result.
+macro_rules! guarded {
+    (|$report:ident| $recover:expr, $fallback:expr, $body:expr) => {
+        match $crate::panic_guard::catch(|| $body) {
+            Ok(value) => value,
+            Err($report) => {
+                $crate::panic_guard::log_panic(&$report);
+                $recover;
+                $crate::panic_guard::resume_in_tests($report);
+                $fallback
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    struct Session {
+        state: GameState,
+        reports: Vec<PanicReport>,
+    }
+
+    impl Session {
+        fn recover_from_panic(&mut self, report: &PanicReport) {
+            recover_state(&mut self.state);
+            self.reports.push(report.clone());
+        }
+
+        fn export(&mut self, fail: bool) -> bool {
+            guarded!(|report| self.recover_from_panic(&report), false, {
+                self.state.state = State::Resolving;
+                if fail {
+                    panic!("deliberate panic");
+                }
+                true
+            })
+        }
+    }
+
+    #[test]
+    fn panics_are_reported_and_the_state_recovers() {
+        let mut session = Session {
+            state: GameState::new(),
+            reports: Vec::new(),
+        };
+
+        assert!(session.export(false));
+        assert!(session.reports.is_empty());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| session.export(true)));
+        // The panic is raised again in tests, after the recovery.
+        assert!(result.is_err());
+        assert_eq!(session.state.state, State::Waiting);
+        assert!(session.state.redraw_grid);
+        assert_eq!(session.reports.len(), 1);
+        assert_eq!(session.reports[0].message, "deliberate panic");
+        assert!(session.reports[0]
+            .location
+            .as_ref()
+            .map_or(false, |location| location.starts_with(file!())));
+    }
+
+    #[test]
+    fn poisoned_mutexes_are_taken_over() {
+        let mutex = Arc::new(Mutex::new(1));
+        let poisoner = Arc::clone(&mutex);
+        let _ = thread::spawn(move || {
+            let mut value = poisoner.lock().unwrap();
+            *value = 2;
+            panic!("poisoning the mutex");
+        })
+        .join();
+
+        assert!(mutex.is_poisoned());
+        assert_eq!(try_lock_recovering(&mutex).map(|value| *value), Some(2));
+        let _held = try_lock_recovering(&mutex);
+        assert!(try_lock_recovering(&mutex).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! The planned path of the selected unit for scripts that draw it themselves. The path is handed
+//! out as a `Curve2D` that starts at the unit, optionally smoothed into a Catmull-Rom spline,
+//! and "path_changed" tells them when to fetch it again.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::unit::Unit;
+use crate::game_state::{GameState, State};
+use crate::godot_convert::Fields;
+use crate::movement::MovementCosts;
+use crate::systems::hexgrid::get_2d_position_from_hex;
+use gdnative::api::Curve2D;
+use gdnative::prelude::*;
+use legion::EntityStore;
+
+/// The points of the planned path, the position of the unit first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathPreview {
+    pub points: Vec<Vector2>,
+    /// Index of the first point the unit can not reach this round, the number of points if it
+    /// reaches all of them.
+    pub split_index: usize,
+}
+
+impl PathPreview {
+    pub fn to_fields(&self) -> Fields {
+        Fields::new()
+            .with("point_count", self.points.len())
+            .with("split_index", self.split_index)
+    }
+}
+
+/// Number of steps of `path` from `start` that `budget` pays for, in movement cost.
+pub fn reachable_steps(
+    start: &Hexagon,
+    path: &[Hexagon],
+    costs: &MovementCosts,
+    budget: i32,
+) -> usize {
+    let mut from = *start;
+    let mut left = budget;
+    for (steps, hexagon) in path.iter().enumerate() {
+        match costs.step_cost(&from, hexagon) {
+            Some(cost) if cost <= left => left -= cost,
+            _ => return steps,
+        }
+        from = *hexagon;
+    }
+    path.len()
+}
+
+/// The current path of the selected unit, empty if there is none.
+pub fn path_preview<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    hexfield_size: f32,
+) -> PathPreview {
+    let entity = match state.state {
+        State::Selected(entity) if !state.current_path.is_empty() => entity,
+        _ => return PathPreview::default(),
+    };
+    let (start, unit) = match world.entry_ref(entity) {
+        Err(_) => return PathPreview::default(),
+        Ok(entry) => match (
+            entry.get_component::<Hexagon>(),
+            entry.get_component::<Unit>(),
+        ) {
+            (Ok(hexagon), Ok(unit)) => (*hexagon, *unit),
+            _ => return PathPreview::default(),
+        },
+    };
+    let costs =
+        MovementCosts::for_unit_at(world, &start, &state.edges).with_bounds(&state.map_bounds);
+    let steps = reachable_steps(
+        &start,
+        &state.current_path,
+        &costs,
+        unit.movement_cost_left(&state.rules),
+    );
+    PathPreview {
+        points: std::iter::once(&start)
+            .chain(&state.current_path)
+            .map(|hexagon| get_2d_position_from_hex(hexagon, hexfield_size))
+            .collect(),
+        split_index: steps + 1,
+    }
+}
+
+/// A point of a curve with the control points of the segments before and after it, relative to
+/// the point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CurvePoint {
+    pub position: Vector2,
+    pub control_in: Vector2,
+    pub control_out: Vector2,
+}
+
+/// Turns the points into a curve through all of them. With a `smoothing` of 0 the segments are
+/// straight lines, with 1 they follow a Catmull-Rom spline. The ends use their neighbour in place
+/// of the missing point behind them.
+pub fn curve_points(points: &[Vector2], smoothing: f32) -> Vec<CurvePoint> {
+    let smoothing = smoothing.max(0.0).min(1.0);
+    (0..points.len())
+        .map(|index| {
+            let before = points[index.saturating_sub(1)];
+            let after = points[(index + 1).min(points.len() - 1)];
+            // A Catmull-Rom tangent is half the distance between the neighbours, a third of it
+            // makes the control point of the cubic Bezier segment.
+            let control = (after - before) * (smoothing / 6.0);
+            CurvePoint {
+                position: points[index],
+                control_in: -control,
+                control_out: control,
+            }
+        })
+        .collect()
+}
+
+pub fn build_curve(points: &[CurvePoint]) -> Ref<Curve2D, Unique> {
+    let curve = Curve2D::new();
+    for point in points {
+        curve.add_point(point.position, point.control_in, point.control_out, -1);
+    }
+    curve
+}
+
+/// Remembers the path last announced, so "path_changed" is only emitted when it changed.
+#[derive(Clone, Debug, Default)]
+pub struct PathTracker {
+    announced: PathPreview,
+}
+
+impl PathTracker {
+    /// Takes the current path and returns whether it differs from the one announced last.
+    pub fn update(&mut self, preview: PathPreview) -> bool {
+        if preview == self.announced {
+            false
+        } else {
+            self.announced = preview;
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::field::Field;
+    use crate::components::movement_type::MovementType;
+    use crate::components::terrain::Terrain;
+    use crate::edges::EdgeData;
+    use legion::World;
+
+    const SIZE: f32 = 40.0;
+
+    fn assert_near(actual: Vector2, expected: Vector2) {
+        assert!((actual - expected).length() < 0.001, "{:?}", actual);
+    }
+
+    fn straight_path(length: i32) -> Vec<Hexagon> {
+        (1..=length).map(|q| Hexagon::new_axial(q, 0)).collect()
+    }
+
+    #[test]
+    fn curves_pass_through_every_point() {
+        let points = vec![
+            Vector2::new(0.0, 0.0),
+            Vector2::new(6.0, 0.0),
+            Vector2::new(6.0, 12.0),
+        ];
+
+        let straight = curve_points(&points, 0.0);
+        let smooth = curve_points(&points, 1.0);
+
+        assert_eq!(straight.len(), 3);
+        for (point, expected) in smooth.iter().zip(&points) {
+            assert_eq!(point.position, *expected);
+        }
+        assert!(straight.iter().all(
+            |point| point.control_in == Vector2::zero() && point.control_out == Vector2::zero()
+        ));
+        assert_near(smooth[1].control_out, Vector2::new(1.0, 2.0));
+        assert_near(smooth[1].control_in, Vector2::new(-1.0, -2.0));
+        assert_near(smooth[0].control_out, Vector2::new(1.0, 0.0));
+        assert_near(smooth[2].control_in, Vector2::new(0.0, -2.0));
+        assert!(curve_points(&[], 1.0).is_empty());
+    }
+
+    #[test]
+    fn smoothing_scales_the_control_points() {
+        let points = vec![
+            Vector2::new(0.0, 0.0),
+            Vector2::new(6.0, 0.0),
+            Vector2::new(12.0, 0.0),
+        ];
+
+        let half = curve_points(&points, 0.5);
+        let beyond = curve_points(&points, 3.0);
+
+        assert_near(half[1].control_out, Vector2::new(1.0, 0.0));
+        assert_near(beyond[1].control_out, Vector2::new(2.0, 0.0));
+    }
+
+    #[test]
+    fn the_budget_splits_the_path() {
+        let mut world = World::default();
+        let mut road = Field::new(Hexagon::new_axial(1, 0));
+        road.terrain = Terrain::Road;
+        world.push((road,));
+        let start = Hexagon::zero();
+        let path = straight_path(4);
+        let open = MovementCosts::default();
+        let roads = MovementCosts::new(&world, MovementType::Ground, &EdgeData::default());
+
+        assert_eq!(reachable_steps(&start, &path, &open, 0), 0);
+        assert_eq!(reachable_steps(&start, &path, &open, 5), 2);
+        assert_eq!(reachable_steps(&start, &path, &open, 100), 4);
+        assert_eq!(reachable_steps(&start, &path, &roads, 5), 3);
+    }
+
+    #[test]
+    fn previews_start_at_the_selected_unit() {
+        let mut world = World::default();
+        let unit = world.push((Hexagon::zero(), Unit::new(1, 1, 1, 1, 0, 2, 2, 1)));
+        let mut state = GameState::new();
+        state.current_path = straight_path(3);
+
+        assert_eq!(path_preview(&world, &state, SIZE), PathPreview::default());
+
+        state.state = State::Selected(unit);
+        let preview = path_preview(&world, &state, SIZE);
+        assert_eq!(preview.points.len(), 4);
+        assert_near(preview.points[0], Vector2::zero());
+        assert_near(
+            preview.points[3],
+            get_2d_position_from_hex(&Hexagon::new_axial(3, 0), SIZE),
+        );
+        assert_eq!(preview.split_index, 3);
+
+        let mut tracker = PathTracker::default();
+        assert!(tracker.update(preview.clone()));
+        assert!(!tracker.update(preview));
+        state.current_path.clear();
+        assert!(tracker.update(path_preview(&world, &state, SIZE)));
+    }
+}
@@ -0,0 +1,265 @@
+//! Pickups on the map. Scenarios, the editor and random events place them, the state machine lets
+//! a unit collect the pickup on every hexagon it steps onto. Pickups have no unit, so they never
+//! block paths.
+
+use crate::components::abilities::Abilities;
+use crate::components::hexagon::Hexagon;
+use crate::components::node_template::NodeTemplate;
+use crate::components::pickup::Pickup;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::components::unit_type::UnitType;
+use crate::game_state::GameState;
+use crate::state_machine::GameEvent;
+use crate::supply::can_be_repaired;
+use legion::{Entity, EntityStore, IntoQuery, World};
+
+const PICKUP_SCENE: &str = "res://Pickup.tscn";
+
+/// Places a pickup on `hexagon`, with a Godot node if `with_node` is set.
+pub fn spawn_pickup(
+    world: &mut World,
+    hexagon: Hexagon,
+    pickup: Pickup,
+    with_node: bool,
+) -> Entity {
+    if with_node {
+        world.push((
+            hexagon,
+            pickup,
+            NodeTemplate::new(PICKUP_SCENE)
+                .visible(false)
+                .group("pickups")
+                .parent("Pickups")
+                .y_sort(),
+        ))
+    } else {
+        world.push((hexagon, pickup))
+    }
+}
+
+pub fn pickup_at<S: EntityStore>(world: &S, hexagon: &Hexagon) -> Option<(Entity, Pickup)> {
+    <(Entity, &Hexagon, &Pickup)>::query()
+        .iter(world)
+        .find(|(_, position, _)| *position == hexagon)
+        .map(|(entity, _, pickup)| (*entity, *pickup))
+}
+
+/// Lets the unit collect the pickup on its hexagon and removes the pickup together with its
+/// node. Resources are scaled by the handicap of the owner, units out of supply leave repairs
+/// behind. Returns what was collected, `None` if there was nothing to collect.
+pub fn collect_pickup(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+) -> Option<GameEvent> {
+    let (position, player, unit_type) = {
+        let entry = world.entry_ref(entity).ok()?;
+        entry.get_component::<Unit>().ok()?;
+        (
+            *entry.get_component::<Hexagon>().ok()?,
+            entry
+                .get_component::<PlayerComponent>()
+                .ok()
+                .map(|player| player.0),
+            entry.get_component::<UnitType>().ok().cloned(),
+        )
+    };
+    let (pickup_entity, pickup) = pickup_at(world, &position)?;
+    // Repairs wait on the map for a unit in supply.
+    if let Pickup::Repair(_) = pickup {
+        if !can_be_repaired(world, entity) {
+            return None;
+        }
+    }
+    let handicap = player
+        .and_then(|player| state.players.get(player))
+        .map(|player| player.get_handicap())
+        .unwrap_or_default();
+    match pickup {
+        Pickup::Resources(amount) => {
+            if let Some(data) = player.and_then(|player| state.players.get_mut(player)) {
+                let earned = (amount as f32 * handicap.resource_multiplier).round() as i32;
+                data.set_resources(data.get_resources() + earned);
+            }
+        }
+        Pickup::Repair(amount) => {
+            // Units that were not created from the catalog have no known integrity to go back
+            // to, so nothing limits their repair.
+            let limit = unit_type
+                .and_then(|unit_type| state.unit_catalog.get(&unit_type.0))
+                .map(|definition| handicap.bonus_integrity(definition.integrity));
+            let mut entry = world.entry(entity)?;
+            if let Ok(unit) = entry.get_component_mut::<Unit>() {
+                let repaired = unit.integrity + amount.max(0);
+                unit.integrity =
+                    limit.map_or(repaired, |limit| repaired.min(limit.max(unit.integrity)));
+            }
+        }
+        Pickup::AbilityCharge => {
+            let mut entry = world.entry(entity)?;
+            if let Ok(abilities) = entry.get_component_mut::<Abilities>() {
+                for ability in abilities.0.iter_mut() {
+                    ability.cooldown_remaining = 0;
+                }
+            }
+        }
+    }
+    world.remove(pickup_entity);
+    Some(GameEvent::PickupCollected {
+        entity,
+        player,
+        pickup,
+        position,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::abilities::{AbilityInstance, AbilityKind};
+    use crate::components::supply::Supply;
+    use crate::edges::EdgeData;
+    use crate::map_bounds::MapBounds;
+    use crate::player::Player;
+    use crate::spawn::{spawn_unit, spawn_unit_of_type};
+    use crate::systems::hexgrid::find_path;
+    use gdnative::core_types::Color;
+
+    fn new_state() -> GameState {
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state
+    }
+
+    fn spawn(world: &mut World, state: &mut GameState, integrity: i32) -> Entity {
+        spawn_unit(
+            world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::zero(),
+            Unit::new(integrity, 1, 1, 1, 0, 3, 3, 1),
+            None,
+        )
+    }
+
+    fn unit(world: &World, entity: Entity) -> Unit {
+        *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap()
+    }
+
+    #[test]
+    fn resources_go_to_the_owner() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let mut handicap = state.players[0].get_handicap();
+        handicap.resource_multiplier = 1.5;
+        state.players[0].set_handicap(handicap);
+        let entity = spawn(&mut world, &mut state, 10);
+        spawn_pickup(&mut world, Hexagon::zero(), Pickup::Resources(20), false);
+
+        let event = collect_pickup(&mut world, &mut state, entity);
+
+        assert_eq!(
+            event,
+            Some(GameEvent::PickupCollected {
+                entity,
+                player: Some(0),
+                pickup: Pickup::Resources(20),
+                position: Hexagon::zero(),
+            })
+        );
+        assert_eq!(state.players[0].get_resources(), 30);
+        assert_eq!(pickup_at(&world, &Hexagon::zero()), None);
+        assert_eq!(collect_pickup(&mut world, &mut state, entity), None);
+    }
+
+    #[test]
+    fn repair_stops_at_the_integrity_of_a_new_unit() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let tank = spawn_unit_of_type(
+            &mut world,
+            &mut state.persistent_ids,
+            &state.unit_catalog,
+            0,
+            Hexagon::zero(),
+            "Tank",
+            false,
+        )
+        .unwrap();
+        let full = unit(&world, tank).integrity;
+        world
+            .entry(tank)
+            .unwrap()
+            .get_component_mut::<Unit>()
+            .unwrap()
+            .integrity = full - 3;
+        spawn_pickup(&mut world, Hexagon::zero(), Pickup::Repair(10), false);
+
+        assert!(collect_pickup(&mut world, &mut state, tank).is_some());
+        assert_eq!(unit(&world, tank).integrity, full);
+    }
+
+    #[test]
+    fn units_out_of_supply_leave_repairs_behind() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let entity = spawn(&mut world, &mut state, 5);
+        let mut supply = Supply::default();
+        supply.record(false);
+        world.entry(entity).unwrap().add_component(supply);
+        spawn_pickup(&mut world, Hexagon::zero(), Pickup::Repair(2), false);
+
+        assert_eq!(collect_pickup(&mut world, &mut state, entity), None);
+        assert_eq!(unit(&world, entity).integrity, 5);
+        assert!(pickup_at(&world, &Hexagon::zero()).is_some());
+    }
+
+    #[test]
+    fn ability_charge_ends_all_cooldowns() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let entity = spawn(&mut world, &mut state, 10);
+        let mut abilities = Abilities(vec![
+            AbilityInstance::new(AbilityKind::Sprint),
+            AbilityInstance::new(AbilityKind::Smoke),
+        ]);
+        abilities.0[0].cooldown_remaining = 2;
+        abilities.0[1].cooldown_remaining = 1;
+        world.entry(entity).unwrap().add_component(abilities);
+        spawn_pickup(&mut world, Hexagon::zero(), Pickup::AbilityCharge, false);
+
+        assert!(collect_pickup(&mut world, &mut state, entity).is_some());
+        let entry = world.entry_ref(entity).unwrap();
+        let abilities = entry.get_component::<Abilities>().unwrap();
+        assert!(abilities
+            .0
+            .iter()
+            .all(|ability| ability.cooldown_remaining == 0));
+    }
+
+    #[test]
+    fn pickups_do_not_block_paths() {
+        let mut world = World::default();
+        let target = Hexagon::new_axial(1, 0);
+        spawn_pickup(&mut world, target, Pickup::Repair(1), false);
+
+        assert_eq!(
+            find_path(
+                &Hexagon::zero(),
+                &target,
+                &EdgeData::default(),
+                &MapBounds::default(),
+                &world
+            ),
+            vec![target]
+        );
+    }
+}
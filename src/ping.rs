@@ -0,0 +1,157 @@
+use crate::components::hexagon::Hexagon;
+use gdnative::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_PINGS_PER_PLAYER: usize = 5;
+pub const PING_TTL_SECONDS: f64 = 5.0;
+
+/// Marker a player places on the map to point the others at something.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ping {
+    pub hexagon: Hexagon,
+    pub kind: String,
+    pub player: usize,
+    /// Seconds until the ping disappears.
+    pub ttl: f64,
+}
+
+impl Ping {
+    pub fn new(hexagon: Hexagon, kind: String, player: usize) -> Self {
+        Ping {
+            hexagon,
+            kind,
+            player,
+            ttl: PING_TTL_SECONDS,
+        }
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("q", self.hexagon.get_q());
+        dict.insert("r", self.hexagon.get_r());
+        dict.insert("kind", self.kind.as_str());
+        dict.insert("player", self.player as i64);
+        dict
+    }
+}
+
+/// The active pings, at most `MAX_PINGS_PER_PLAYER` per player. The oldest ping of a player is
+/// evicted when the limit is reached.
+#[derive(Clone, Debug, Default)]
+pub struct Pings {
+    pings: Vec<Ping>,
+    unannounced: usize,
+}
+
+impl Pings {
+    pub fn new() -> Self {
+        Pings::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Ping> {
+        self.pings.iter()
+    }
+
+    pub fn add(&mut self, ping: Ping) {
+        let player = ping.player;
+        if self
+            .pings
+            .iter()
+            .filter(|ping| ping.player == player)
+            .count()
+            >= MAX_PINGS_PER_PLAYER
+        {
+            if let Some(oldest) = self.pings.iter().position(|ping| ping.player == player) {
+                if oldest >= self.pings.len() - self.unannounced {
+                    self.unannounced -= 1;
+                }
+                self.pings.remove(oldest);
+            }
+        }
+        self.pings.push(ping);
+        self.unannounced += 1;
+    }
+
+    /// Counts down the time to live of every ping and removes the expired ones.
+    pub fn update(&mut self, delta: f64) {
+        for ping in &mut self.pings {
+            ping.ttl -= delta;
+        }
+        self.pings.retain(|ping| ping.ttl > 0.0);
+        self.unannounced = self.unannounced.min(self.pings.len());
+    }
+
+    /// Returns the pings added since the last call, so they can be announced to the UI.
+    pub fn take_unannounced(&mut self) -> Vec<Ping> {
+        let skip = self.pings.len() - self.unannounced;
+        self.unannounced = 0;
+        self.pings.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping(player: usize, q: i32) -> Ping {
+        Ping::new(Hexagon::new_axial(q, 0), "attack".to_owned(), player)
+    }
+
+    #[test]
+    fn pings_expire_after_their_ttl() {
+        let mut pings = Pings::new();
+        pings.add(ping(0, 0));
+        pings.update(PING_TTL_SECONDS / 2.0);
+        pings.add(ping(1, 1));
+
+        pings.update(PING_TTL_SECONDS / 2.0);
+
+        let remaining: Vec<usize> = pings.iter().map(|ping| ping.player).collect();
+        assert_eq!(remaining, vec![1]);
+        pings.update(PING_TTL_SECONDS);
+        assert!(pings.is_empty());
+    }
+
+    #[test]
+    fn oldest_ping_of_a_player_is_evicted_at_the_cap() {
+        let mut pings = Pings::new();
+        pings.add(ping(1, 100));
+        for q in 0..MAX_PINGS_PER_PLAYER as i32 + 2 {
+            pings.add(ping(0, q));
+        }
+
+        assert_eq!(pings.len(), MAX_PINGS_PER_PLAYER + 1);
+        let own: Vec<i32> = pings
+            .iter()
+            .filter(|ping| ping.player == 0)
+            .map(|ping| ping.hexagon.get_q())
+            .collect();
+        assert_eq!(own, vec![2, 3, 4, 5, 6]);
+        assert!(pings.iter().any(|ping| ping.player == 1));
+    }
+
+    #[test]
+    fn take_unannounced_only_returns_new_pings() {
+        let mut pings = Pings::new();
+        pings.add(ping(0, 0));
+        assert_eq!(pings.take_unannounced().len(), 1);
+
+        pings.add(ping(0, 1));
+        pings.add(ping(1, 2));
+        let announced: Vec<i32> = pings
+            .take_unannounced()
+            .iter()
+            .map(|ping| ping.hexagon.get_q())
+            .collect();
+        assert_eq!(announced, vec![1, 2]);
+        assert!(pings.take_unannounced().is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+//! Planning phase of the simultaneous rules. Moves and attacks given while planning are checked
+//! like any other command, but only kept as `MoveOrder` and `AttackOrder` components on the
+//! units. The state machine carries them out for all players at once when the round is resolved.
+
+use crate::commands::Command;
+use crate::components::hexagon::Hexagon;
+use crate::components::orders::{AttackOrder, MoveOrder};
+use crate::components::persistent_id::{PersistentId, PersistentIds};
+use crate::components::player::Player as PlayerComponent;
+use crate::game_state::{set_state, GameState, State};
+use gdnative::prelude::*;
+use legion::{Entity, EntityStore, IntoQuery, World};
+
+/// An order accepted during planning that still has to be put on its unit.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlannedOrder {
+    Move { entity: Entity, path: Vec<Hexagon> },
+    Attack { attacker: Entity, target: Entity },
+}
+
+/// Puts accepted orders on their units. A new order replaces the unit's earlier one of the same
+/// kind, so a unit can plan one move and one attack.
+pub fn store_orders(world: &mut World, orders: Vec<PlannedOrder>) {
+    for order in orders {
+        match order {
+            PlannedOrder::Move { entity, path } => {
+                if let Some(mut entry) = world.entry(entity) {
+                    entry.add_component(MoveOrder { path });
+                }
+            }
+            PlannedOrder::Attack { attacker, target } => {
+                if let Some(mut entry) = world.entry(attacker) {
+                    entry.add_component(AttackOrder { target });
+                }
+            }
+        }
+    }
+}
+
+/// The planned orders of all players as the commands that gave them, for savegames. Orders on
+/// the units come first, ordered by their units, then the ones still waiting to be put on them.
+pub fn planned_commands<S: EntityStore>(world: &S, state: &GameState) -> Vec<Command> {
+    let unit = |entity: Entity| {
+        let entry = world.entry_ref(entity).ok()?;
+        let id = *entry.get_component::<PersistentId>().ok()?;
+        let player = entry.get_component::<PlayerComponent>().ok()?.0;
+        Some((id, player))
+    };
+    let mut stored: Vec<(PersistentId, usize, Option<MoveOrder>, Option<AttackOrder>)> = <(
+        &PersistentId,
+        &PlayerComponent,
+        Option<&MoveOrder>,
+        Option<&AttackOrder>,
+    )>::query(
+    )
+    .iter(world)
+    .filter(|(_, _, move_order, attack_order)| move_order.is_some() || attack_order.is_some())
+    .map(|(id, owner, move_order, attack_order)| {
+        (*id, owner.0, move_order.cloned(), attack_order.copied())
+    })
+    .collect();
+    stored.sort_by_key(|(id, _, _, _)| *id);
+
+    let mut commands = Vec::new();
+    for (id, player, move_order, attack_order) in stored {
+        if let Some(order) = move_order {
+            commands.push(Command::Move {
+                player,
+                unit: id,
+                path: order.path,
+            });
+        }
+        if let Some((defender, _)) = attack_order.and_then(|order| unit(order.target)) {
+            commands.push(Command::Attack {
+                player,
+                attacker: id,
+                defender,
+            });
+        }
+    }
+    for order in &state.planned_orders {
+        match order {
+            PlannedOrder::Move { entity, path } => {
+                if let Some((id, player)) = unit(*entity) {
+                    commands.push(Command::Move {
+                        player,
+                        unit: id,
+                        path: path.clone(),
+                    });
+                }
+            }
+            PlannedOrder::Attack { attacker, target } => {
+                if let (Some((id, player)), Some((defender, _))) = (unit(*attacker), unit(*target))
+                {
+                    commands.push(Command::Attack {
+                        player,
+                        attacker: id,
+                        defender,
+                    });
+                }
+            }
+        }
+    }
+    commands
+}
+
+/// Puts the orders of `planned_commands` back on their units. Orders of units that are gone are
+/// dropped.
+pub fn restore_planned_commands(world: &mut World, ids: &PersistentIds, commands: &[Command]) {
+    let orders = commands
+        .iter()
+        .filter_map(|command| match command {
+            Command::Move { unit, path, .. } => Some(PlannedOrder::Move {
+                entity: ids.find(&*world, *unit)?,
+                path: path.clone(),
+            }),
+            Command::Attack {
+                attacker, defender, ..
+            } => Some(PlannedOrder::Attack {
+                attacker: ids.find(&*world, *attacker)?,
+                target: ids.find(&*world, *defender)?,
+            }),
+            _ => None,
+        })
+        .collect();
+    store_orders(world, orders);
+}
+
+/// Ends the planning phase. Returns false if nobody is planning.
+pub fn start_resolution(state: &mut GameState) -> bool {
+    if state.state != State::Planning {
+        return false;
+    }
+    set_state(state, State::Resolving);
+    true
+}
+
+/// The stored orders of the units of `players`, for drawing them. Orders of other players are
+/// left out, they stay hidden until the round is resolved.
+pub fn orders_to_array<S: EntityStore>(
+    world: &S,
+    ids: &PersistentIds,
+    players: &[usize],
+) -> VariantArray {
+    let orders = VariantArray::new();
+    for (entity, owner, move_order, attack_order) in <(
+        Entity,
+        &PlayerComponent,
+        Option<&MoveOrder>,
+        Option<&AttackOrder>,
+    )>::query()
+    .iter(world)
+    {
+        if !players.contains(&owner.0) || (move_order.is_none() && attack_order.is_none()) {
+            continue;
+        }
+        let dict = Dictionary::new();
+        dict.insert("id", ids.id(*entity).map_or(-1, |id| id.0 as i64));
+        if let Some(move_order) = move_order {
+            let path = VariantArray::new();
+            for hexagon in &move_order.path {
+                let step = Dictionary::new();
+                step.insert("q", hexagon.get_q());
+                step.insert("r", hexagon.get_r());
+                path.push(step.owned_to_variant());
+            }
+            dict.insert("path", path.owned_to_variant());
+        }
+        if let Some(attack_order) = attack_order {
+            dict.insert(
+                "target",
+                ids.id(attack_order.target).map_or(-1, |id| id.0 as i64),
+            );
+        }
+        orders.push(dict.owned_to_variant());
+    }
+    orders.into_shared()
+}
@@ -1,14 +1,44 @@
+use crate::ai::AiProfile;
+use crate::handicap::Handicap;
+use crate::palette::OwnershipPattern;
 use gdnative::prelude::*;
 
 #[derive(Clone)]
 pub struct Player {
     name: String,
     colour: Color,
+    observer: bool,
+    pattern: OwnershipPattern,
+    handicap: Handicap,
+    resources: i32,
+    /// Set for computer players.
+    ai_profile: Option<AiProfile>,
 }
 
 impl Player {
     pub fn new(name: String, colour: Color) -> Self {
-        Player { name, colour }
+        Player {
+            name,
+            colour,
+            observer: false,
+            pattern: OwnershipPattern::Solid,
+            handicap: Handicap::default(),
+            resources: 0,
+            ai_profile: None,
+        }
+    }
+
+    /// A player that sees the whole game but never gets a turn.
+    pub fn new_observer(name: String, colour: Color) -> Self {
+        Player {
+            name,
+            colour,
+            observer: true,
+            pattern: OwnershipPattern::Solid,
+            handicap: Handicap::default(),
+            resources: 0,
+            ai_profile: None,
+        }
     }
 
     pub fn get_name(&self) -> String {
@@ -18,4 +48,51 @@ impl Player {
     pub fn get_colour(&self) -> Color {
         self.colour
     }
+
+    pub fn set_colour(&mut self, colour: Color) {
+        self.colour = colour;
+    }
+
+    pub fn get_pattern(&self) -> OwnershipPattern {
+        self.pattern
+    }
+
+    pub fn set_pattern(&mut self, pattern: OwnershipPattern) {
+        self.pattern = pattern;
+    }
+
+    pub fn get_handicap(&self) -> Handicap {
+        self.handicap
+    }
+
+    /// Only changes the stored handicap, `GameState::set_handicap` also adjusts the units.
+    pub fn set_handicap(&mut self, handicap: Handicap) {
+        self.handicap = handicap;
+    }
+
+    pub fn get_resources(&self) -> i32 {
+        self.resources
+    }
+
+    pub fn set_resources(&mut self, resources: i32) {
+        self.resources = resources;
+    }
+
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    /// Whether the computer plays for this player.
+    pub fn is_ai(&self) -> bool {
+        self.ai_profile.is_some()
+    }
+
+    pub fn get_ai_profile(&self) -> Option<AiProfile> {
+        self.ai_profile
+    }
+
+    /// `None` hands the player back to a human.
+    pub fn set_ai_profile(&mut self, profile: Option<AiProfile>) {
+        self.ai_profile = profile;
+    }
 }
@@ -0,0 +1,160 @@
+//! Frame time measurements. Timed scopes add their duration to a named bucket, the buckets are
+//! closed once per frame. Without profiling enabled a scope does not even read the clock.
+
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref PROFILER: Mutex<FrameProfiler> = Mutex::new(FrameProfiler::default());
+}
+
+/// Time spent in one bucket, per frame in which it was used.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bucket {
+    pub last_ms: f64,
+    pub max_ms: f64,
+    total_ms: f64,
+    frames: u64,
+}
+
+impl Bucket {
+    pub fn avg_ms(&self) -> f64 {
+        if self.frames == 0 {
+            0.0
+        } else {
+            self.total_ms / self.frames as f64
+        }
+    }
+
+    fn record(&mut self, ms: f64) {
+        self.last_ms = ms;
+        self.max_ms = self.max_ms.max(ms);
+        self.total_ms += ms;
+        self.frames += 1;
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameProfiler {
+    buckets: BTreeMap<&'static str, Bucket>,
+    /// Time collected in the running frame. A bucket can be entered several times per frame.
+    current: BTreeMap<&'static str, f64>,
+}
+
+impl FrameProfiler {
+    pub fn add(&mut self, name: &'static str, ms: f64) {
+        *self.current.entry(name).or_insert(0.0) += ms;
+    }
+
+    /// Moves the time collected in the running frame into the buckets.
+    pub fn end_frame(&mut self) {
+        for (name, ms) in std::mem::take(&mut self.current) {
+            self.buckets.entry(name).or_default().record(ms);
+        }
+    }
+
+    pub fn buckets(&self) -> impl Iterator<Item = (&'static str, &Bucket)> {
+        self.buckets.iter().map(|(name, bucket)| (*name, bucket))
+    }
+}
+
+/// Adds the time until it is dropped to its bucket.
+#[derive(Debug)]
+pub struct ScopedTimer {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Drop for ScopedTimer {
+    fn drop(&mut self) {
+        let ms = self.start.elapsed().as_secs_f64() * 1000.0;
+        if let Ok(mut profiler) = PROFILER.lock() {
+            profiler.add(self.name, ms);
+        }
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Starts timing the bucket `name`, keep the result alive for the scope to measure.
+pub fn scope(name: &'static str) -> Option<ScopedTimer> {
+    if is_enabled() {
+        Some(ScopedTimer {
+            name,
+            start: Instant::now(),
+        })
+    } else {
+        None
+    }
+}
+
+pub fn end_frame() {
+    if !is_enabled() {
+        return;
+    }
+    if let Ok(mut profiler) = PROFILER.lock() {
+        profiler.end_frame();
+    }
+}
+
+/// Runs `f` with the collected timings.
+pub fn with_profiler<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&FrameProfiler) -> R,
+{
+    PROFILER.lock().ok().map(|profiler| f(&profiler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_of_a_frame_are_summed() {
+        let mut profiler = FrameProfiler::default();
+        profiler.add("find_path", 1.0);
+        profiler.add("find_path", 2.5);
+        profiler.add("draw_grid", 4.0);
+
+        profiler.end_frame();
+
+        let buckets: Vec<(&str, f64)> = profiler
+            .buckets()
+            .map(|(name, bucket)| (name, bucket.last_ms))
+            .collect();
+        assert_eq!(buckets, vec![("draw_grid", 4.0), ("find_path", 3.5)]);
+    }
+
+    #[test]
+    fn buckets_track_average_and_maximum() {
+        let mut profiler = FrameProfiler::default();
+        for ms in [2.0, 6.0, 1.0].iter() {
+            profiler.add("update_units", *ms);
+            profiler.end_frame();
+        }
+        // Frames without the bucket do not count towards its average.
+        profiler.end_frame();
+
+        let (_, bucket) = profiler.buckets().next().unwrap();
+        assert_eq!(bucket.last_ms, 1.0);
+        assert_eq!(bucket.max_ms, 6.0);
+        assert_eq!(bucket.avg_ms(), 3.0);
+    }
+
+    #[test]
+    fn disabled_profiling_creates_no_timers() {
+        assert!(!is_enabled());
+        assert!(scope("frame").is_none());
+    }
+}
@@ -0,0 +1,181 @@
+//! Promotions. Every `KILLS_PER_PROMOTION` enemy units a unit destroys earn it a promotion, right
+//! after the attack that earned it the game waits until its owner picked one of the offered perks.
+//! Computer players pick on their own. Kills on the turn of the enemy, by counterattacks or
+//! overwatch, are offered after the next attack of the unit.
+
+use crate::components::perks::{Experience, PerkId, Perks, INTEGRITY_BONUS};
+use crate::components::unit::{AttackResult, Unit};
+use crate::identity::UnitIdentity;
+use legion::{Entity, EntityStore, World};
+
+/// Kills a unit needs for every promotion.
+pub const KILLS_PER_PROMOTION: u32 = 2;
+
+/// Perks offered on every promotion.
+pub const PROMOTION_OPTIONS: usize = 2;
+
+pub fn perks_of<S: EntityStore>(world: &S, entity: Entity) -> Perks {
+    world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<Perks>().ok().cloned())
+        .unwrap_or_default()
+}
+
+fn add_kill(world: &mut World, entity: Entity) {
+    if let Some(mut entry) = world.entry(entity) {
+        let mut experience = entry
+            .get_component::<Experience>()
+            .ok()
+            .copied()
+            .unwrap_or_default();
+        experience.kills += 1;
+        entry.add_component(experience);
+    }
+}
+
+/// Credits the survivor of an attack that destroyed the other unit with a kill. Has to be called
+/// before the destroyed unit is removed.
+pub fn award_kills(world: &mut World, attacker: Entity, defender: Entity, result: &AttackResult) {
+    if result.defender.integrity <= 0 && result.attacker.integrity > 0 {
+        add_kill(world, attacker);
+    } else if result.attacker.integrity <= 0 && result.defender.integrity > 0 {
+        add_kill(world, defender);
+    }
+}
+
+/// The perks the unit can pick from, `None` if it has no promotion coming. The perks it has
+/// picked the least are offered, ties go to the earlier perk.
+pub fn promotion_options<S: EntityStore>(world: &S, entity: Entity) -> Option<Vec<PerkId>> {
+    let entry = world.entry_ref(entity).ok()?;
+    let kills = entry.get_component::<Experience>().ok()?.kills;
+    let perks = entry
+        .get_component::<Perks>()
+        .ok()
+        .cloned()
+        .unwrap_or_default();
+    if (kills / KILLS_PER_PROMOTION) as usize <= perks.0.len() {
+        return None;
+    }
+    let mut options = PerkId::ALL.to_vec();
+    options.sort_by_key(|perk| perks.count(*perk));
+    options.truncate(PROMOTION_OPTIONS);
+    Some(options)
+}
+
+/// Gives the unit the perk. A new `Integrity` perk repairs the unit by its bonus right away.
+pub fn promote(world: &mut World, entity: Entity, perk: PerkId) {
+    let mut identity = match UnitIdentity::of(world, entity) {
+        None => return,
+        Some(identity) => identity,
+    };
+    identity
+        .perks
+        .get_or_insert_with(Perks::default)
+        .0
+        .push(perk);
+    identity.apply(world, entity);
+    if perk == PerkId::Integrity {
+        if let Some(mut entry) = world.entry(entity) {
+            if let Ok(unit) = entry.get_component_mut::<Unit>() {
+                unit.integrity += INTEGRITY_BONUS;
+            }
+        }
+    }
+}
+
+/// The index of the perk a computer player picks from `options`: more integrity for a unit that
+/// lost at least half of `max_integrity`, more damage otherwise, the first option if neither is
+/// offered.
+pub fn ai_perk(unit: &Unit, max_integrity: i32, options: &[PerkId]) -> usize {
+    let wanted = if unit.integrity * 2 <= max_integrity {
+        PerkId::Integrity
+    } else {
+        PerkId::Damage
+    };
+    options.iter().position(|perk| *perk == wanted).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::perks::{DAMAGE_BONUS, MOBILITY_BONUS};
+    use crate::rules::Ruleset;
+
+    fn result(attacker: Unit, defender: Unit) -> AttackResult {
+        AttackResult {
+            attacker,
+            defender,
+            actual_damage: 0,
+        }
+    }
+
+    #[test]
+    fn every_second_kill_earns_a_promotion() {
+        let mut world = World::default();
+        let unit = Unit::new(10, 4, 1, 1, 0, 3, 3, 1);
+        let destroyed = Unit {
+            integrity: 0,
+            ..unit
+        };
+        let veteran = world.push((unit,));
+        let enemy = world.push((unit,));
+
+        assert_eq!(promotion_options(&world, veteran), None);
+        award_kills(&mut world, veteran, enemy, &result(unit, destroyed));
+        assert_eq!(promotion_options(&world, veteran), None);
+        // The veteran destroys its attacker with a counterattack.
+        award_kills(&mut world, enemy, veteran, &result(destroyed, unit));
+        assert_eq!(
+            promotion_options(&world, veteran),
+            Some(vec![PerkId::Damage, PerkId::Integrity])
+        );
+        assert_eq!(promotion_options(&world, enemy), None);
+
+        promote(&mut world, veteran, PerkId::Damage);
+        assert_eq!(promotion_options(&world, veteran), None);
+        award_kills(&mut world, veteran, enemy, &result(unit, destroyed));
+        award_kills(&mut world, veteran, enemy, &result(unit, destroyed));
+        assert_eq!(
+            promotion_options(&world, veteran),
+            Some(vec![PerkId::Integrity, PerkId::Mobility])
+        );
+    }
+
+    #[test]
+    fn perks_strengthen_the_unit() {
+        let mut world = World::default();
+        let unit = Unit::new(10, 4, 1, 1, 0, 3, 3, 1);
+        let entity = world.push((unit,));
+        for perk in PerkId::ALL.iter() {
+            promote(&mut world, entity, *perk);
+        }
+        let perks = perks_of(&world, entity);
+        let mut promoted = *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap();
+
+        assert_eq!(promoted.integrity, 10 + INTEGRITY_BONUS);
+        assert_eq!(perks.integrity_bonus(), INTEGRITY_BONUS);
+        assert_eq!(perks.strengthen(unit).damage, 4 + DAMAGE_BONUS);
+        promoted.refresh(&Ruleset::default());
+        perks.refresh(&mut promoted, &Ruleset::default());
+        assert_eq!(promoted.remaining_range, 3 + MOBILITY_BONUS);
+    }
+
+    #[test]
+    fn computer_players_pick_integrity_when_hurt() {
+        let healthy = Unit::new(10, 4, 1, 1, 0, 3, 3, 1);
+        let hurt = Unit {
+            integrity: 4,
+            ..healthy
+        };
+        let options = [PerkId::Integrity, PerkId::Damage];
+
+        assert_eq!(ai_perk(&healthy, 10, &options), 1);
+        assert_eq!(ai_perk(&hurt, 10, &options), 0);
+        assert_eq!(ai_perk(&hurt, 10, &[PerkId::Mobility, PerkId::Damage]), 0);
+    }
+}
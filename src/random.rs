@@ -0,0 +1,56 @@
+//! Seeded random numbers. The generator is part of the game state and saved with it, so every
+//! simulation started from the same seed draws the same numbers.
+
+use serde::{Deserialize, Serialize};
+
+/// SplitMix64, small and good enough for game events. It is not meant for anything secret.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 up to, but not including, `bound`. `bound` must not be 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Whether something with the given probability happens.
+    pub fn chance(&mut self, probability: f64) -> bool {
+        // The upper 53 bits fill the mantissa of a double in [0, 1).
+        let sample = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_draws_the_same_numbers() {
+        let (mut first, mut second) = (Rng::new(7), Rng::new(7));
+        let drawn: Vec<u64> = (0..5).map(|_| first.next_u64()).collect();
+        assert_eq!(drawn, (0..5).map(|_| second.next_u64()).collect::<Vec<_>>());
+        assert_ne!(Rng::new(8).next_u64(), drawn[0]);
+        assert!((0..100).all(|_| first.below(6) < 6));
+        assert!(!first.chance(0.0));
+        assert!(first.chance(1.0));
+    }
+}
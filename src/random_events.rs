@@ -0,0 +1,443 @@
+//! Random events at the start of a round. With the events rule, every new round draws an event
+//! with `event_chance` from the weighted `event_table` of the rules. Adding an event takes a
+//! variant here, its id, its description and an arm in `RandomEventKind::apply`.
+
+use crate::components::field::Field;
+use crate::components::ground_effect::GroundEffect;
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::PersistentId;
+use crate::components::pickup::Pickup;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::game_state::GameState;
+use crate::ground_effects::{spawn_ground_effect, WILDFIRE_DAMAGE, WILDFIRE_ROUNDS};
+use crate::messages::{self, Message};
+use crate::pickups::spawn_pickup;
+use crate::random::Rng;
+use crate::rules::Ruleset;
+use crate::state_machine::GameEvent;
+use crate::weather::Weather;
+use legion::{Entity, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Resources in the pickup of a supply drop.
+pub const SUPPLY_DROP_RESOURCES: i32 = 50;
+/// Share of its integrity a deserting unit loses, in percent.
+pub const DESERTION_LOSS_PERCENT: i32 = 25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RandomEventKind {
+    /// A pickup with resources lands on a random free hexagon.
+    SupplyDrop,
+    /// Units see less far for one round.
+    Sandstorm,
+    /// A random unit of the player with the most units loses some of its integrity.
+    Desertion,
+    /// A fire breaks out on a random hexagon without a unit, its smoke blocks the view through it.
+    Wildfire,
+}
+
+/// An entry of the event table, events are drawn in proportion to their weight.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventWeight {
+    pub event: RandomEventKind,
+    pub weight: u32,
+}
+
+impl RandomEventKind {
+    pub fn id(&self) -> &'static str {
+        match self {
+            RandomEventKind::SupplyDrop => "supply_drop",
+            RandomEventKind::Sandstorm => "sandstorm",
+            RandomEventKind::Desertion => "desertion",
+            RandomEventKind::Wildfire => "wildfire",
+        }
+    }
+
+    /// Applies the event to the game and returns what happened. `None` if the event had
+    /// nothing to act on, like a desertion without units.
+    pub fn apply(&self, world: &mut World, state: &mut GameState) -> Option<GameEvent> {
+        match self {
+            RandomEventKind::SupplyDrop => {
+                let taken: HashSet<Hexagon> = <(&Hexagon, &Unit)>::query()
+                    .iter(world)
+                    .map(|(position, _)| *position)
+                    .chain(
+                        <(&Hexagon, &Pickup)>::query()
+                            .iter(world)
+                            .map(|(position, _)| *position),
+                    )
+                    .collect();
+                let mut free: Vec<Hexagon> = <&Field>::query()
+                    .iter(world)
+                    .map(|field| field.location)
+                    .filter(|position| !taken.contains(position))
+                    .collect();
+                if free.is_empty() {
+                    return None;
+                }
+                free.sort_by_key(|position| (position.get_q(), position.get_r()));
+                let position = free[state.rng.below(free.len() as u64) as usize];
+                spawn_pickup(
+                    world,
+                    position,
+                    Pickup::Resources(SUPPLY_DROP_RESOURCES),
+                    state.spawn_nodes,
+                );
+                Some(GameEvent::RandomEvent {
+                    kind: *self,
+                    player: None,
+                    position: Some(position),
+                })
+            }
+            RandomEventKind::Sandstorm => {
+                state.weather = Weather::Sandstorm;
+                Some(GameEvent::RandomEvent {
+                    kind: *self,
+                    player: None,
+                    position: None,
+                })
+            }
+            RandomEventKind::Desertion => {
+                let mut units: Vec<(usize, PersistentId, Entity)> =
+                    <(Entity, &PlayerComponent, &Unit, &PersistentId)>::query()
+                        .iter(world)
+                        .map(|(entity, owner, _, id)| (owner.0, *id, *entity))
+                        .collect();
+                units.sort_by_key(|(owner, id, _)| (*owner, *id));
+                let mut counts = vec![0; state.players.len()];
+                for (owner, _, _) in &units {
+                    if let Some(count) = counts.get_mut(*owner) {
+                        *count += 1;
+                    }
+                }
+                // Ties go to the player with the lower index.
+                let player = (0..counts.len())
+                    .rev()
+                    .max_by_key(|player| counts[*player])?;
+                let deserters: Vec<Entity> = units
+                    .iter()
+                    .filter(|(owner, _, _)| *owner == player)
+                    .map(|(_, _, entity)| *entity)
+                    .collect();
+                if deserters.is_empty() {
+                    return None;
+                }
+                let entity = deserters[state.rng.below(deserters.len() as u64) as usize];
+                let mut entry = world.entry(entity)?;
+                let position = entry.get_component::<Hexagon>().ok().copied();
+                let unit = entry.get_component_mut::<Unit>().ok()?;
+                // Deserters weaken a unit, they never destroy it.
+                let loss = (unit.integrity * DESERTION_LOSS_PERCENT / 100).max(1);
+                unit.integrity = (unit.integrity - loss).max(1);
+                Some(GameEvent::RandomEvent {
+                    kind: *self,
+                    player: Some(player),
+                    position,
+                })
+            }
+            RandomEventKind::Wildfire => {
+                let taken: HashSet<Hexagon> = <(&Hexagon, &Unit)>::query()
+                    .iter(world)
+                    .map(|(position, _)| *position)
+                    .chain(
+                        <(&Hexagon, &GroundEffect)>::query()
+                            .iter(world)
+                            .map(|(position, _)| *position),
+                    )
+                    .collect();
+                let mut free: Vec<Hexagon> = <&Field>::query()
+                    .iter(world)
+                    .map(|field| field.location)
+                    .filter(|position| !taken.contains(position))
+                    .collect();
+                if free.is_empty() {
+                    return None;
+                }
+                free.sort_by_key(|position| (position.get_q(), position.get_r()));
+                let position = free[state.rng.below(free.len() as u64) as usize];
+                let fire = GroundEffect::Fire {
+                    damage_per_round: WILDFIRE_DAMAGE,
+                    rounds_left: WILDFIRE_ROUNDS,
+                };
+                let smoke = GroundEffect::Smoke {
+                    rounds_left: WILDFIRE_ROUNDS,
+                };
+                spawn_ground_effect(world, position, fire, state.spawn_nodes);
+                spawn_ground_effect(world, position, smoke, state.spawn_nodes);
+                Some(GameEvent::RandomEvent {
+                    kind: *self,
+                    player: None,
+                    position: Some(position),
+                })
+            }
+        }
+    }
+}
+
+/// The event table of the default rules.
+pub fn default_event_table() -> Vec<EventWeight> {
+    vec![
+        EventWeight {
+            event: RandomEventKind::SupplyDrop,
+            weight: 2,
+        },
+        EventWeight {
+            event: RandomEventKind::Sandstorm,
+            weight: 2,
+        },
+        EventWeight {
+            event: RandomEventKind::Desertion,
+            weight: 1,
+        },
+        EventWeight {
+            event: RandomEventKind::Wildfire,
+            weight: 1,
+        },
+    ]
+}
+
+/// Draws the event of a new round, `None` if no event happens.
+pub fn draw_event(rng: &mut Rng, rules: &Ruleset) -> Option<RandomEventKind> {
+    if !rng.chance(rules.event_chance) {
+        return None;
+    }
+    let total: u64 = rules
+        .event_table
+        .iter()
+        .map(|entry| u64::from(entry.weight))
+        .sum();
+    if total == 0 {
+        return None;
+    }
+    let mut roll = rng.below(total);
+    for entry in &rules.event_table {
+        if roll < u64::from(entry.weight) {
+            return Some(entry.event);
+        }
+        roll -= u64::from(entry.weight);
+    }
+    None
+}
+
+/// Starts the round's weather and draws its event, for the events rule.
+pub fn start_round(world: &mut World, state: &mut GameState, events: &mut Vec<GameEvent>) {
+    state.weather = Weather::Clear;
+    if !state.rules.events {
+        return;
+    }
+    if let Some(kind) = draw_event(&mut state.rng, &state.rules) {
+        events.extend(kind.apply(world, state));
+    }
+}
+
+/// What the event did, for the combat log and the scripts.
+pub fn describe(
+    kind: RandomEventKind,
+    player_name: Option<&str>,
+    position: Option<&Hexagon>,
+) -> Message {
+    let player_name = player_name.unwrap_or_default();
+    let position = position.copied().unwrap_or_else(Hexagon::zero);
+    match kind {
+        RandomEventKind::SupplyDrop => messages::supply_drop(SUPPLY_DROP_RESOURCES, &position),
+        RandomEventKind::Sandstorm => messages::sandstorm(),
+        RandomEventKind::Desertion => messages::desertion(player_name, &position),
+        RandomEventKind::Wildfire => messages::wildfire(&position),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ground_effects::fire_damage_at;
+    use crate::pickups::pickup_at;
+    use crate::player::Player;
+    use crate::spawn::{spawn_grid, spawn_unit};
+    use crate::systems::hexgrid::is_vision_blocked;
+    use gdnative::core_types::Color;
+
+    fn new_state() -> GameState {
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.add_player(Player::new(
+            "Player 2".to_owned(),
+            Color::rgb(1.0, 0.0, 0.0),
+        ));
+        state.rules.events = true;
+        state.rules.event_chance = 1.0;
+        state.rng = Rng::new(42);
+        state
+    }
+
+    fn spawn(world: &mut World, state: &mut GameState, player: usize, q: i32) -> Entity {
+        spawn_unit(
+            world,
+            &mut state.persistent_ids,
+            player,
+            Hexagon::new_axial(q, 0),
+            Unit::new(20, 1, 1, 1, 0, 3, 3, 1),
+            None,
+        )
+    }
+
+    fn integrity(world: &World, entity: Entity) -> i32 {
+        let entry = world.entry_ref(entity).unwrap();
+        entry.get_component::<Unit>().unwrap().integrity
+    }
+
+    #[test]
+    fn events_are_drawn_by_weight() {
+        let mut rules = Ruleset::default();
+        rules.event_chance = 1.0;
+        rules.event_table = vec![
+            EventWeight {
+                event: RandomEventKind::SupplyDrop,
+                weight: 3,
+            },
+            EventWeight {
+                event: RandomEventKind::Sandstorm,
+                weight: 1,
+            },
+            EventWeight {
+                event: RandomEventKind::Desertion,
+                weight: 0,
+            },
+        ];
+        let mut rng = Rng::new(1);
+        let drawn: Vec<RandomEventKind> = (0..4000)
+            .filter_map(|_| draw_event(&mut rng, &rules))
+            .collect();
+
+        let supply_drops = drawn
+            .iter()
+            .filter(|kind| **kind == RandomEventKind::SupplyDrop)
+            .count();
+        assert_eq!(drawn.len(), 4000);
+        assert!(!drawn.contains(&RandomEventKind::Desertion));
+        assert!((2800..3200).contains(&supply_drops), "{}", supply_drops);
+
+        rules.event_chance = 0.0;
+        assert_eq!(draw_event(&mut rng, &rules), None);
+    }
+
+    #[test]
+    fn pinned_seed_draws_the_same_events() {
+        let rules = Ruleset {
+            event_chance: 0.5,
+            ..Ruleset::default()
+        };
+        let draw = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..20)
+                .map(|_| draw_event(&mut rng, &rules))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(draw(7), draw(7));
+        assert!(draw(7).iter().any(Option::is_some));
+        assert!(draw(7).iter().any(Option::is_none));
+    }
+
+    #[test]
+    fn supply_drop_lands_on_a_free_hexagon() {
+        let mut world = World::default();
+        let mut state = new_state();
+        spawn_grid(&mut world, 1);
+        for q in -1..=1 {
+            spawn(&mut world, &mut state, 0, q);
+        }
+
+        let event = RandomEventKind::SupplyDrop.apply(&mut world, &mut state);
+
+        let position = match event {
+            Some(GameEvent::RandomEvent {
+                position: Some(position),
+                ..
+            }) => position,
+            other => panic!("Unexpected event {:?}", other),
+        };
+        assert_ne!(position.get_r(), 0);
+        assert_eq!(
+            pickup_at(&world, &position).map(|(_, pickup)| pickup),
+            Some(Pickup::Resources(SUPPLY_DROP_RESOURCES))
+        );
+    }
+
+    #[test]
+    fn sandstorm_lasts_one_round() {
+        let mut world = World::default();
+        let mut state = new_state();
+        state.rules.event_table = vec![EventWeight {
+            event: RandomEventKind::Sandstorm,
+            weight: 1,
+        }];
+        let mut events = Vec::new();
+
+        start_round(&mut world, &mut state, &mut events);
+        assert_eq!(state.weather, Weather::Sandstorm);
+        assert_eq!(events.len(), 1);
+
+        state.rules.event_chance = 0.0;
+        start_round(&mut world, &mut state, &mut events);
+        assert_eq!(state.weather, Weather::Clear);
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn wildfire_starts_on_a_hexagon_without_a_unit() {
+        let mut world = World::default();
+        let mut state = new_state();
+        spawn_grid(&mut world, 1);
+        for q in -1..=1 {
+            spawn(&mut world, &mut state, 0, q);
+        }
+
+        let event = RandomEventKind::Wildfire.apply(&mut world, &mut state);
+
+        let position = match event {
+            Some(GameEvent::RandomEvent {
+                position: Some(position),
+                ..
+            }) => position,
+            other => panic!("Unexpected event {:?}", other),
+        };
+        assert_ne!(position.get_r(), 0);
+        assert_eq!(fire_damage_at(&world, &position), WILDFIRE_DAMAGE);
+        assert!(is_vision_blocked(&position, &world));
+    }
+
+    #[test]
+    fn desertion_weakens_a_unit_of_the_largest_army() {
+        let mut world = World::default();
+        let mut state = new_state();
+        let small = spawn(&mut world, &mut state, 0, 0);
+        let large = vec![
+            spawn(&mut world, &mut state, 1, 3),
+            spawn(&mut world, &mut state, 1, 4),
+        ];
+
+        let event = RandomEventKind::Desertion.apply(&mut world, &mut state);
+
+        assert!(matches!(
+            event,
+            Some(GameEvent::RandomEvent {
+                player: Some(1),
+                position: Some(_),
+                ..
+            })
+        ));
+        assert_eq!(integrity(&world, small), 20);
+        let mut integrities: Vec<i32> = large
+            .iter()
+            .map(|entity| integrity(&world, *entity))
+            .collect();
+        integrities.sort();
+        assert_eq!(integrities, vec![15, 20]);
+    }
+}
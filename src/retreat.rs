@@ -0,0 +1,215 @@
+//! Retreats of the selected units to a rally hexagon. Every unit gets its own slot around the
+//! rally hexagon like in a group move, then walks there on the path that stays out of reach of
+//! the enemy the most. Units that could only get there through the enemy step away from the
+//! closest enemy instead.
+
+use crate::buildings::is_free;
+use crate::components::hexagon::Hexagon;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::edges::EdgeData;
+use crate::map_bounds::MapBounds;
+use crate::movement::{MovementCosts, HEXAGON_COST};
+use crate::systems::hexgrid::{find_path_with_costs, get_hexagons_in_range, get_neighbours};
+use legion::{Entity, EntityStore, IntoQuery};
+use std::collections::vec_deque::VecDeque;
+use std::collections::HashMap;
+
+/// Movement cost added to a hexagon for every enemy that can reach it next turn.
+pub const THREAT_PENALTY: i32 = 2 * HEXAGON_COST;
+
+/// Highest threat a retreat path may pass through, summed over its hexagons.
+pub const MAX_RETREAT_THREAT: i32 = 2;
+
+fn enemies<S: EntityStore>(world: &S, player: usize) -> Vec<(Hexagon, Unit)> {
+    <(&Hexagon, &Unit, &PlayerComponent)>::query()
+        .iter(world)
+        .filter(|(_, _, owner)| owner.0 != player)
+        .map(|(position, unit, _)| (*position, *unit))
+        .collect()
+}
+
+/// How many enemies of `player` could move and attack each hexagon next turn. Hexagons no enemy
+/// reaches are left out.
+pub fn threat_map<S: EntityStore>(world: &S, player: usize) -> HashMap<Hexagon, i32> {
+    let mut threats = HashMap::new();
+    for (position, unit) in enemies(world, player) {
+        let reach = unit.mobility + unit.max_attack_range;
+        for hexagon in get_hexagons_in_range(&position, 0, reach) {
+            *threats.entry(hexagon).or_insert(0) += 1;
+        }
+    }
+    threats
+}
+
+/// The threat summed over the hexagons of `path`.
+pub fn path_threat(path: &[Hexagon], threats: &HashMap<Hexagon, i32>) -> i32 {
+    path.iter()
+        .map(|hexagon| threats.get(hexagon).copied().unwrap_or(0))
+        .sum()
+}
+
+/// The way of the unit of `player` on `start` to `rally`. Threatened hexagons cost more, so the
+/// path bends around the enemy where it can. If every path runs into more than
+/// `MAX_RETREAT_THREAT`, the unit only steps away from the closest enemy. Empty if it can do
+/// neither.
+pub fn retreat_path<S: EntityStore>(
+    world: &S,
+    edges: &EdgeData,
+    bounds: &MapBounds,
+    player: usize,
+    start: &Hexagon,
+    rally: &Hexagon,
+) -> Vec<Hexagon> {
+    let threats = threat_map(world, player);
+    let penalties = threats
+        .iter()
+        .map(|(hexagon, threat)| (*hexagon, threat * THREAT_PENALTY))
+        .collect();
+    let costs = MovementCosts::for_unit_at(world, start, edges)
+        .with_bounds(bounds)
+        .with_penalties(penalties);
+    let max_cost =
+        (2 * start.distance_to(rally) + 2) * HEXAGON_COST + MAX_RETREAT_THREAT * THREAT_PENALTY;
+    let path = find_path_with_costs(start, rally, max_cost, &costs, world);
+    if !path.is_empty() && path_threat(&path, &threats) <= MAX_RETREAT_THREAT {
+        return path;
+    }
+    step_away(world, edges, bounds, player, start, &threats)
+        .into_iter()
+        .collect()
+}
+
+/// The free neighbour of `start` farthest from the closest enemy, the least threatened one on
+/// ties. `None` if there is no enemy or no step leads away from it.
+fn step_away<S: EntityStore>(
+    world: &S,
+    edges: &EdgeData,
+    bounds: &MapBounds,
+    player: usize,
+    start: &Hexagon,
+    threats: &HashMap<Hexagon, i32>,
+) -> Option<Hexagon> {
+    let enemies = enemies(world, player);
+    let closest = |hexagon: &Hexagon| {
+        enemies
+            .iter()
+            .map(|(position, _)| position.distance_to(hexagon))
+            .min()
+    };
+    let current = closest(start)?;
+    let costs = MovementCosts::for_unit_at(world, start, edges).with_bounds(bounds);
+    get_neighbours(start)
+        .into_iter()
+        .filter(|neighbour| {
+            is_free(world, neighbour) && costs.step_cost(start, neighbour).is_some()
+        })
+        .filter(|neighbour| closest(neighbour).map_or(false, |distance| distance > current))
+        .min_by_key(|neighbour| {
+            (
+                threats.get(neighbour).copied().unwrap_or(0),
+                neighbour.get_q(),
+                neighbour.get_r(),
+            )
+        })
+}
+
+/// Takes the next unit of a retreat that still has somewhere to go and returns it with its
+/// path. Units that are gone, already there or stuck are skipped.
+pub fn next_retreat<S: EntityStore>(
+    world: &S,
+    edges: &EdgeData,
+    bounds: &MapBounds,
+    queue: &mut VecDeque<(Entity, Hexagon)>,
+) -> Option<(Entity, Vec<Hexagon>)> {
+    while let Some((entity, rally)) = queue.pop_front() {
+        let (start, player) = match world.entry_ref(entity) {
+            Err(_) => continue,
+            Ok(entry) => match (
+                entry.get_component::<Hexagon>(),
+                entry.get_component::<PlayerComponent>(),
+            ) {
+                (Ok(start), Ok(player)) => (*start, player.0),
+                _ => continue,
+            },
+        };
+        if start == rally {
+            continue;
+        }
+        let path = retreat_path(world, edges, bounds, player, &start, &rally);
+        if !path.is_empty() {
+            return Some((entity, path));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::field::Field;
+    use crate::systems::hexgrid::{create_grid, find_path};
+    use legion::World;
+
+    fn spawn(world: &mut World, player: usize, q: i32, r: i32, mobility: i32) -> Entity {
+        world.push((
+            PlayerComponent(player),
+            Hexagon::new_axial(q, r),
+            Unit::new(5, 1, 1, 1, 0, mobility, mobility, 1),
+        ))
+    }
+
+    fn map() -> World {
+        let mut world = World::default();
+        for hexagon in create_grid(6) {
+            world.push((Field::new(hexagon),));
+        }
+        world
+    }
+
+    #[test]
+    fn retreats_bend_around_the_enemy() {
+        let mut world = map();
+        spawn(&mut world, 0, 0, 0, 4);
+        // Only reaches its neighbours, among them the middle of the straight way.
+        spawn(&mut world, 1, 2, -1, 0);
+        let start = Hexagon::zero();
+        let rally = Hexagon::new_axial(4, 0);
+        let edges = EdgeData::default();
+        let bounds = MapBounds::from_world(&world);
+
+        let shortest = find_path(&start, &rally, &edges, &bounds, &world);
+        let retreat = retreat_path(&world, &edges, &bounds, 0, &start, &rally);
+        let threats = threat_map(&world, 0);
+
+        assert_eq!(shortest.len(), 4);
+        assert_eq!(path_threat(&shortest, &threats), 2);
+        assert_ne!(retreat, shortest);
+        assert_eq!(retreat.len(), 5);
+        assert_eq!(retreat.last(), Some(&rally));
+        assert_eq!(path_threat(&retreat, &threats), 0);
+    }
+
+    #[test]
+    fn units_step_away_when_every_way_is_threatened() {
+        let mut world = map();
+        let unit = spawn(&mut world, 0, 0, 0, 4);
+        // Reaches three hexagons far, the rally hexagon and everything around the unit.
+        spawn(&mut world, 1, 2, 0, 2);
+        let rally = Hexagon::new_axial(4, 0);
+        let edges = EdgeData::default();
+        let bounds = MapBounds::from_world(&world);
+
+        assert_eq!(
+            retreat_path(&world, &edges, &bounds, 0, &Hexagon::zero(), &rally),
+            vec![Hexagon::new_axial(-1, 0)]
+        );
+
+        let mut queue: VecDeque<(Entity, Hexagon)> = vec![(unit, rally)].into_iter().collect();
+        assert_eq!(
+            next_retreat(&world, &edges, &bounds, &mut queue),
+            Some((unit, vec![Hexagon::new_axial(-1, 0)]))
+        );
+        assert!(queue.is_empty());
+    }
+}
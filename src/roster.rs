@@ -0,0 +1,293 @@
+//! Campaigns carry the surviving units of a player from one scenario to the next. The roster of a
+//! player is exported at the end of a scenario and imported before the next one, whose unit slots
+//! marked `from_roster` are filled from it in order.
+
+use crate::components::perks::{Experience, Perks};
+use crate::components::persistent_id::PersistentId;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::components::unit_name::UnitName;
+use crate::components::unit_type::UnitType;
+use crate::game_state::GameState;
+use crate::state_machine::max_integrity;
+use legion::{Entity, EntityStore, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+
+/// A unit that survived a scenario.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RosterUnit {
+    pub unit_type: String,
+    /// Integrity left as a percentage of the maximum, so it carries over to a unit type or a
+    /// handicap with another maximum.
+    pub integrity_percent: u32,
+    #[serde(default)]
+    pub experience: Experience,
+    #[serde(default)]
+    pub perks: Perks,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl RosterUnit {
+    /// Gives the freshly spawned `entity` the experience, the perks, the name and the integrity
+    /// of the roster unit. The unit is at full integrity before, its maximum grows with the
+    /// integrity perks.
+    pub fn apply(&self, world: &mut World, entity: Entity) {
+        if let Some(mut entry) = world.entry(entity) {
+            if let Ok(unit) = entry.get_component_mut::<Unit>() {
+                let max = unit.integrity + self.perks.integrity_bonus();
+                unit.integrity = integrity_from_percent(self.integrity_percent, max);
+            }
+            entry.add_component(self.experience);
+            entry.add_component(self.perks.clone());
+            if let Some(name) = &self.name {
+                entry.add_component(UnitName(name.clone()));
+            }
+        }
+    }
+}
+
+/// The surviving units of one player.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Roster {
+    pub units: Vec<RosterUnit>,
+}
+
+impl Roster {
+    /// The units of `player` that were created from the catalog, in the order they were created.
+    pub fn from_world<S: EntityStore>(world: &S, state: &GameState, player: usize) -> Self {
+        let mut units: Vec<(PersistentId, RosterUnit)> = <(
+            Entity,
+            &PersistentId,
+            &PlayerComponent,
+            &Unit,
+            &UnitType,
+            Option<&Experience>,
+            Option<&Perks>,
+            Option<&UnitName>,
+        )>::query()
+        .iter(world)
+        .filter(|(_, _, owner, unit, _, _, _, _)| owner.0 == player && unit.integrity > 0)
+        .map(
+            |(entity, id, _, unit, unit_type, experience, perks, name)| {
+                let max = max_integrity(world, state, *entity, unit.integrity);
+                (
+                    *id,
+                    RosterUnit {
+                        unit_type: unit_type.0.clone(),
+                        integrity_percent: ((unit.integrity * 100 + max / 2) / max.max(1)) as u32,
+                        experience: experience.copied().unwrap_or_default(),
+                        perks: perks.cloned().unwrap_or_default(),
+                        name: name.map(|name| name.0.clone()),
+                    },
+                )
+            },
+        )
+        .collect();
+        units.sort_by_key(|(id, _)| *id);
+        Roster {
+            units: units.into_iter().map(|(_, unit)| unit).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// The integrity a unit with `max` integrity has at `percent`, rounded. A surviving unit keeps at
+/// least one point of integrity.
+pub fn integrity_from_percent(percent: u32, max: i32) -> i32 {
+    ((max * percent.min(100) as i32 + 50) / 100).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::field::Field;
+    use crate::components::hexagon::Hexagon;
+    use crate::components::perks::PerkId;
+    use crate::game_state::setup_match;
+    use crate::promotions::promote;
+    use crate::savegame::{load_game, save_game, SaveData};
+    use crate::scenario::{Scenario, ScenarioField, ScenarioUnit};
+    use crate::systems::hexgrid::create_grid;
+
+    fn scenario(units: Vec<ScenarioUnit>) -> Scenario {
+        Scenario {
+            fields: create_grid(3)
+                .into_iter()
+                .map(|position| ScenarioField {
+                    position,
+                    terrain: Default::default(),
+                })
+                .collect(),
+            units,
+            ..Scenario::default()
+        }
+    }
+
+    fn slot(player: usize, q: i32, unit_type: &str, from_roster: bool) -> ScenarioUnit {
+        ScenarioUnit {
+            player,
+            position: Hexagon::new_axial(q, 0),
+            unit_type: unit_type.to_owned(),
+            name: None,
+            from_roster,
+        }
+    }
+
+    fn unit_at(world: &World, q: i32) -> Entity {
+        <(Entity, &Hexagon, &Unit)>::query()
+            .iter(world)
+            .find(|(_, position, _)| **position == Hexagon::new_axial(q, 0))
+            .map(|(entity, _, _)| *entity)
+            .unwrap()
+    }
+
+    #[test]
+    fn rosters_carry_units_into_the_next_scenario() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        let first = scenario(vec![
+            slot(0, 0, "Tank", false),
+            slot(0, 1, "Artillery", false),
+            slot(0, 2, "Tank", false),
+            slot(1, -2, "Tank", false),
+        ]);
+        setup_match(&mut world, &mut state, Some(&first));
+        let (tank, artillery, wreck) = (unit_at(&world, 0), unit_at(&world, 1), unit_at(&world, 2));
+        world
+            .entry(tank)
+            .unwrap()
+            .get_component_mut::<Unit>()
+            .unwrap()
+            .integrity = 10;
+        promote(&mut world, tank, PerkId::Integrity);
+        let mut entry = world.entry(artillery).unwrap();
+        entry.add_component(Experience { kills: 2 });
+        entry.add_component(UnitName("Long Tom".to_owned()));
+        world
+            .entry(wreck)
+            .unwrap()
+            .get_component_mut::<Unit>()
+            .unwrap()
+            .integrity = 0;
+
+        let json = Roster::from_world(&world, &state, 0).to_json().unwrap();
+        let roster = Roster::from_json(&json).unwrap();
+        assert_eq!(roster.units.len(), 2);
+        assert_eq!(roster.units[0].unit_type, "Tank");
+        assert_eq!(roster.units[0].integrity_percent, 55);
+        assert_eq!(roster.units[0].perks, Perks(vec![PerkId::Integrity]));
+        assert_eq!(roster.units[1].integrity_percent, 100);
+        assert_eq!(roster.units[1].experience, Experience { kills: 2 });
+        assert_eq!(roster.units[1].name.as_deref(), Some("Long Tom"));
+
+        state.rosters.insert(0, roster);
+        state.reset(&mut world);
+        let second = scenario(vec![
+            slot(0, -1, "Artillery", true),
+            slot(1, 3, "Tank", true),
+            slot(0, 1, "Artillery", true),
+        ]);
+        assert!(setup_match(&mut world, &mut state, Some(&second)).is_empty());
+        assert!(state.rosters.is_empty());
+        assert_eq!(<&Field>::query().iter(&world).count(), 37);
+
+        let entry = world.entry_ref(unit_at(&world, -1)).unwrap();
+        assert_eq!(entry.get_component::<UnitType>().unwrap().0, "Tank");
+        assert_eq!(entry.get_component::<Unit>().unwrap().integrity, 12);
+        assert_eq!(
+            entry.get_component::<Perks>().unwrap(),
+            &Perks(vec![PerkId::Integrity])
+        );
+        let entry = world.entry_ref(unit_at(&world, 1)).unwrap();
+        assert_eq!(entry.get_component::<UnitType>().unwrap().0, "Artillery");
+        assert_eq!(
+            entry.get_component::<Experience>().unwrap(),
+            &Experience { kills: 2 }
+        );
+        assert_eq!(entry.get_component::<UnitName>().unwrap().0, "Long Tom");
+        // Player 1 has no roster, the slot gets a fresh unit.
+        let entry = world.entry_ref(unit_at(&world, 3)).unwrap();
+        assert_eq!(entry.get_component::<Unit>().unwrap().integrity, 20);
+        assert!(entry.get_component::<Experience>().is_err());
+    }
+
+    #[test]
+    fn rosters_survive_saving_in_the_middle_of_a_scenario() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        let units = vec![slot(0, 0, "Tank", false), slot(1, 2, "Tank", false)];
+        setup_match(&mut world, &mut state, Some(&scenario(units)));
+        world
+            .entry(unit_at(&world, 0))
+            .unwrap()
+            .get_component_mut::<Unit>()
+            .unwrap()
+            .integrity = 10;
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        let mut loaded_world = World::default();
+        let mut loaded_state = GameState::new();
+        loaded.restore_state(&mut loaded_state);
+        loaded.spawn(&mut loaded_world, &mut loaded_state.persistent_ids, None);
+
+        let roster = Roster::from_world(&loaded_world, &loaded_state, 0);
+        assert_eq!(roster, Roster::from_world(&world, &state, 0));
+        assert_eq!(roster.units.len(), 1);
+        assert_eq!(roster.units[0].unit_type, "Tank");
+        assert_eq!(roster.units[0].integrity_percent, 50);
+    }
+
+    #[test]
+    fn short_rosters_fall_back_to_fresh_units() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        let veteran = RosterUnit {
+            unit_type: "Tank".to_owned(),
+            integrity_percent: 50,
+            experience: Experience { kills: 1 },
+            perks: Perks::default(),
+            name: None,
+        };
+        state.rosters.insert(
+            0,
+            Roster {
+                units: vec![veteran],
+            },
+        );
+        let units = vec![
+            slot(0, 0, "Artillery", true),
+            slot(0, 1, "Artillery", true),
+            slot(0, 2, "Artillery", true),
+        ];
+
+        assert!(setup_match(&mut world, &mut state, Some(&scenario(units))).is_empty());
+
+        let units: Vec<(String, i32, bool)> = (0..3)
+            .map(|q| {
+                let entry = world.entry_ref(unit_at(&world, q)).unwrap();
+                (
+                    entry.get_component::<UnitType>().unwrap().0.clone(),
+                    entry.get_component::<Unit>().unwrap().integrity,
+                    entry.get_component::<Experience>().is_ok(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            units,
+            vec![
+                ("Tank".to_owned(), 10, true),
+                ("Artillery".to_owned(), 10, false),
+                ("Artillery".to_owned(), 10, false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Game rules that can be changed without recompiling, loaded from a TOML file.
+
+use crate::random_events::{default_event_table, EventWeight};
+use gdnative::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_RULES: &str = include_str!("../rules/default.toml");
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ruleset {
+    pub damage_multiplier: i32,
+    pub armor_multiplier: i32,
+    pub counterattack: bool,
+    pub attacks_per_round: i32,
+    pub carry_over_range: bool,
+    pub line_of_sight: bool,
+    /// Whether hexagons missing from the map, like lakes, block the line of sight. They can
+    /// never be entered.
+    pub holes_block_sight: bool,
+    pub fog_of_war: bool,
+    pub turn_timer_seconds: i32,
+    /// Replaces the separate range and attack budgets with one pool of action points.
+    pub action_points: bool,
+    pub action_points_per_round: i32,
+    /// Action points an attack costs, moving costs one per hexagon.
+    pub attack_cost: i32,
+    /// Action points an ability costs.
+    pub ability_cost: i32,
+    /// Units act one at a time in the order of their initiative instead of player by player.
+    pub initiative: bool,
+    /// Experimental: all players plan their orders at the same time and the orders are carried
+    /// out together by `resolve_round`.
+    pub simultaneous: bool,
+    /// Draws a random event from `event_table` at the start of every round.
+    pub events: bool,
+    /// Probability of an event at the start of a round, from 0 to 1.
+    pub event_chance: f64,
+    pub event_table: Vec<EventWeight>,
+    /// Units cut off from the supply sources of their player move less and wear down.
+    pub supply: bool,
+    /// Hexagons a supply line reaches from a supply source.
+    pub supply_range: i32,
+    /// Resources a player earns at the start of every round for each supply source they hold.
+    pub income_per_source: i32,
+    /// Resources a player pays at the start of every round for each of their units.
+    pub upkeep_per_unit: i32,
+    /// Moves and attacks a player may order per round, however large their army. 0 for no
+    /// limit.
+    pub command_points: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RulesError {
+    Parse(String),
+    Negative(&'static str),
+    NotAProbability(&'static str),
+}
+
+impl RulesError {
+    pub fn message(&self) -> String {
+        match self {
+            RulesError::Parse(message) => format!("Could not parse rules: {}", message),
+            RulesError::Negative(field) => format!("Rule '{}' must not be negative", field),
+            RulesError::NotAProbability(field) => {
+                format!("Rule '{}' must be between 0 and 1", field)
+            }
+        }
+    }
+}
+
+impl Ruleset {
+    pub fn from_toml(text: &str) -> Result<Self, RulesError> {
+        let rules: Ruleset =
+            toml::from_str(text).map_err(|error| RulesError::Parse(error.to_string()))?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    pub fn validate(&self) -> Result<(), RulesError> {
+        let non_negative = [
+            ("damage_multiplier", self.damage_multiplier),
+            ("armor_multiplier", self.armor_multiplier),
+            ("attacks_per_round", self.attacks_per_round),
+            ("turn_timer_seconds", self.turn_timer_seconds),
+            ("action_points_per_round", self.action_points_per_round),
+            ("attack_cost", self.attack_cost),
+            ("ability_cost", self.ability_cost),
+            ("supply_range", self.supply_range),
+            ("income_per_source", self.income_per_source),
+            ("upkeep_per_unit", self.upkeep_per_unit),
+            ("command_points", self.command_points),
+        ];
+        for (field, value) in non_negative.iter() {
+            if *value < 0 {
+                return Err(RulesError::Negative(field));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.event_chance) {
+            return Err(RulesError::NotAProbability("event_chance"));
+        }
+        Ok(())
+    }
+
+    pub fn damage(&self, damage: i32, armor: i32) -> i32 {
+        damage * self.damage_multiplier - armor * self.armor_multiplier
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("damage_multiplier", self.damage_multiplier);
+        dict.insert("armor_multiplier", self.armor_multiplier);
+        dict.insert("counterattack", self.counterattack);
+        dict.insert("attacks_per_round", self.attacks_per_round);
+        dict.insert("carry_over_range", self.carry_over_range);
+        dict.insert("line_of_sight", self.line_of_sight);
+        dict.insert("holes_block_sight", self.holes_block_sight);
+        dict.insert("fog_of_war", self.fog_of_war);
+        dict.insert("turn_timer_seconds", self.turn_timer_seconds);
+        dict.insert("action_points", self.action_points);
+        dict.insert("action_points_per_round", self.action_points_per_round);
+        dict.insert("attack_cost", self.attack_cost);
+        dict.insert("ability_cost", self.ability_cost);
+        dict.insert("initiative", self.initiative);
+        dict.insert("simultaneous", self.simultaneous);
+        dict.insert("events", self.events);
+        dict.insert("event_chance", self.event_chance);
+        dict.insert("supply", self.supply);
+        dict.insert("supply_range", self.supply_range);
+        dict.insert("income_per_source", self.income_per_source);
+        dict.insert("upkeep_per_unit", self.upkeep_per_unit);
+        dict.insert("command_points", self.command_points);
+        dict
+    }
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Ruleset {
+            damage_multiplier: 1,
+            armor_multiplier: 1,
+            counterattack: false,
+            attacks_per_round: 1,
+            carry_over_range: false,
+            line_of_sight: true,
+            holes_block_sight: true,
+            fog_of_war: false,
+            turn_timer_seconds: 0,
+            action_points: false,
+            action_points_per_round: 4,
+            attack_cost: 2,
+            ability_cost: 1,
+            initiative: false,
+            simultaneous: false,
+            events: false,
+            event_chance: 0.25,
+            event_table: default_event_table(),
+            supply: false,
+            supply_range: 6,
+            income_per_source: 0,
+            upkeep_per_unit: 0,
+            command_points: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::components::player::Player as PlayerComponent;
+    use crate::components::unit::Unit;
+    use crate::game_state::{GameState, State};
+    use crate::player::Player;
+    use crate::state_machine::advance_state;
+    use legion::World;
+
+    const VARIANT_RULES: &str = include_str!("../fixtures/rules_variant.toml");
+
+    fn new_state(rules: Ruleset) -> GameState {
+        let mut state = GameState::new();
+        state.players.push(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.players.push(Player::new(
+            "Player 2".to_owned(),
+            Color::rgb(1.0, 0.0, 0.0),
+        ));
+        state.current_player = Some(0);
+        state.rules = rules;
+        state
+    }
+
+    fn unit_after_attack(rules: Ruleset, entity_is_attacker: bool) -> Option<Unit> {
+        let mut world = World::default();
+        let mut state = new_state(rules);
+        let attacker = world.push((
+            PlayerComponent(0),
+            Hexagon::new_axial(0, 0),
+            Unit::new(10, 4, 1, 1, 1, 2, 2, 1),
+        ));
+        let defender = world.push((
+            PlayerComponent(1),
+            Hexagon::new_axial(1, 0),
+            Unit::new(20, 3, 1, 1, 1, 2, 2, 1),
+        ));
+        state.state = State::Attacking(attacker, defender);
+        advance_state(&mut world, &mut state, 0.0);
+        let entity = if entity_is_attacker {
+            attacker
+        } else {
+            defender
+        };
+        let entry = world.entry(entity)?;
+        entry.get_component::<Unit>().ok().copied()
+    }
+
+    #[test]
+    fn embedded_rules_are_the_defaults() {
+        assert_eq!(Ruleset::from_toml(DEFAULT_RULES), Ok(Ruleset::default()));
+    }
+
+    #[test]
+    fn fixture_is_parsed() {
+        let rules = Ruleset::from_toml(VARIANT_RULES).unwrap();
+        assert_eq!(rules.damage_multiplier, 2);
+        assert!(rules.counterattack);
+        assert_eq!(rules.attacks_per_round, 2);
+        assert!(!rules.line_of_sight);
+        assert!(!rules.fog_of_war);
+        assert_eq!(rules.turn_timer_seconds, 90);
+    }
+
+    #[test]
+    fn missing_rules_keep_defaults() {
+        let rules = Ruleset::from_toml("counterattack = true").unwrap();
+        assert_eq!(
+            rules,
+            Ruleset {
+                counterattack: true,
+                ..Ruleset::default()
+            }
+        );
+    }
+
+    #[test]
+    fn negative_values_are_rejected_with_field() {
+        assert_eq!(
+            Ruleset::from_toml("armor_multiplier = -1"),
+            Err(RulesError::Negative("armor_multiplier"))
+        );
+        assert_eq!(
+            Ruleset::from_toml("turn_timer_seconds = -5"),
+            Err(RulesError::Negative("turn_timer_seconds"))
+        );
+    }
+
+    #[test]
+    fn unknown_rules_are_rejected() {
+        assert!(matches!(
+            Ruleset::from_toml("no_such_rule = 1"),
+            Err(RulesError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn damage_multiplier_changes_damage() {
+        let default = unit_after_attack(Ruleset::default(), false).unwrap();
+        let variant = unit_after_attack(Ruleset::from_toml(VARIANT_RULES).unwrap(), false).unwrap();
+
+        assert_eq!(default.integrity, 17);
+        assert_eq!(variant.integrity, 13);
+    }
+
+    #[test]
+    fn counterattack_damages_attacker() {
+        let default = unit_after_attack(Ruleset::default(), true).unwrap();
+        let counterattack = Ruleset {
+            counterattack: true,
+            ..Ruleset::default()
+        };
+        let countered = unit_after_attack(counterattack, true).unwrap();
+
+        assert_eq!(default.integrity, 10);
+        assert_eq!(countered.integrity, 8);
+    }
+
+    #[test]
+    fn attacks_per_round_and_carry_over_change_new_round() {
+        let mut world = World::default();
+        let mut state = new_state(Ruleset::from_toml(VARIANT_RULES).unwrap());
+        let entity = world.push((
+            PlayerComponent(0),
+            Hexagon::zero(),
+            Unit::new(10, 4, 1, 1, 1, 3, 2, 0),
+        ));
+        state.state = State::NewRound;
+
+        advance_state(&mut world, &mut state, 0.0);
+
+        let entry = world.entry(entity).unwrap();
+        let unit = entry.get_component::<Unit>().unwrap();
+        assert_eq!(unit.remaining_attacks, 2);
+        assert_eq!(unit.remaining_range, 5);
+    }
+}
@@ -0,0 +1,964 @@
+//! Savegames. The game data is wrapped in an envelope carrying the format version, older saves are
+//! migrated step by step on the raw JSON before they are deserialized.
+
+use crate::abilities::{smoke_clouds, spawn_smoke, SavedSmoke};
+use crate::achievements::AchievementTracker;
+use crate::ai::AiProfile;
+use crate::buildings::spawn_building;
+use crate::combat_log::CombatLog;
+use crate::commands::Command;
+use crate::components::abilities::{Abilities, Overdrive};
+use crate::components::demolition::Demolition;
+use crate::components::facing::Facing;
+use crate::components::garrison::Garrisoned;
+use crate::components::hexagon::{Direction, Hexagon};
+use crate::components::history::History;
+use crate::components::movement_type::MovementType;
+use crate::components::node_template::NodeTemplate;
+use crate::components::overwatch::Overwatching;
+use crate::components::perks::{Experience, PerkId, Perks};
+use crate::components::persistent_id::{PersistentId, PersistentIds};
+use crate::components::player::Player as PlayerComponent;
+use crate::components::supply::Supply;
+use crate::components::unit::Unit;
+use crate::components::unit_name::UnitName;
+use crate::components::unit_type::UnitType;
+use crate::edges::{EdgeData, ScenarioEdge};
+use crate::fog::LastSeen;
+use crate::game_state::{force_state, GameState, State};
+use crate::ground_effects::{ground_effects, spawn_ground_effect, SavedGroundEffect};
+use crate::handicap::Handicap;
+use crate::identity::UnitIdentity;
+use crate::initiative::InitiativeQueue;
+use crate::pickups::spawn_pickup;
+use crate::planning::{planned_commands, restore_planned_commands};
+use crate::player::Player;
+use crate::random::Rng;
+use crate::scenario::{
+    scenario_buildings, scenario_pickups, scenario_supply_sources, ScenarioBuilding,
+    ScenarioPickup, ScenarioSupplySource,
+};
+use crate::spawn::spawn_unit;
+use crate::statistics::StatisticsSeries;
+use crate::supply::spawn_supply_source;
+use crate::triggers::Triggers;
+use crate::unit_names::UnitNames;
+use crate::weather::Weather;
+use gdnative::core_types::Color;
+use legion::{EntityStore, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+pub const CURRENT_SAVE_VERSION: u32 = 2;
+
+/// Migrations in order, the function at index `n` migrates version `n + 1` to version `n + 2`.
+const MIGRATIONS: &[fn(Value) -> Option<Value>] = &[migrate_v1_to_v2];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    data: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedPlayer {
+    pub name: String,
+    pub colour: [f32; 4],
+    #[serde(default)]
+    pub observer: bool,
+    #[serde(default)]
+    pub handicap: Handicap,
+    #[serde(default)]
+    pub resources: i32,
+    #[serde(default)]
+    pub ai_profile: Option<AiProfile>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedUnit {
+    pub id: PersistentId,
+    pub player: usize,
+    pub position: Hexagon,
+    pub unit: Unit,
+    #[serde(default)]
+    pub history: History,
+    #[serde(default)]
+    pub movement_type: MovementType,
+    #[serde(default)]
+    pub demolition: bool,
+    /// Units of older saves get a new name after loading.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The unit is inside the building on its position, its armor includes the bonus.
+    #[serde(default)]
+    pub garrisoned: bool,
+    /// Units that never moved or turned have no facing.
+    #[serde(default)]
+    pub facing: Option<Direction>,
+    /// Units without it count as supplied.
+    #[serde(default)]
+    pub supply: Option<Supply>,
+    #[serde(default)]
+    pub perks: Perks,
+    #[serde(default)]
+    pub experience: Experience,
+    /// The abilities with their running cooldowns.
+    #[serde(default)]
+    pub abilities: Abilities,
+    /// Damage gained from Overdrive, taken back when the owner's next turn starts.
+    #[serde(default)]
+    pub overdrive: i32,
+    /// The unit keeps its attack for the first enemy that moves into its range.
+    #[serde(default)]
+    pub overwatching: bool,
+    /// The catalog entry the unit was created from, `None` for units given by their stats.
+    #[serde(default)]
+    pub unit_type: Option<String>,
+}
+
+/// A unit that waited for its owner to pick a perk when the game was saved.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedPromotion {
+    pub unit: PersistentId,
+    pub options: Vec<PerkId>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SaveData {
+    pub round: u32,
+    pub current_player: Option<usize>,
+    pub players: Vec<SavedPlayer>,
+    pub units: Vec<SavedUnit>,
+    /// Pickups are spawned by the caller, like the units.
+    #[serde(default)]
+    pub pickups: Vec<ScenarioPickup>,
+    /// Buildings are spawned by the caller, like the pickups.
+    #[serde(default)]
+    pub buildings: Vec<ScenarioBuilding>,
+    #[serde(default)]
+    pub supply_sources: Vec<ScenarioSupplySource>,
+    /// Fires and smoke clouds with the rounds they have left.
+    #[serde(default)]
+    pub ground_effects: Vec<SavedGroundEffect>,
+    /// Smoke clouds placed by the abilities of units.
+    #[serde(default)]
+    pub smoke: Vec<SavedSmoke>,
+    #[serde(default)]
+    pub combat_log: CombatLog,
+    #[serde(default)]
+    pub palette: Option<String>,
+    /// Saved while the privacy screen waited for the next player.
+    #[serde(default)]
+    pub awaiting_player: bool,
+    #[serde(default)]
+    pub achievements: AchievementTracker,
+    /// The next persistent id to hand out, so ids of destroyed units are not reused after loading.
+    #[serde(default)]
+    pub next_persistent_id: u64,
+    /// The ghosts every player remembers under the fog of war.
+    #[serde(default)]
+    pub last_seen: LastSeen,
+    /// The triggers of the scenario and which of them fired.
+    #[serde(default)]
+    pub triggers: Triggers,
+    /// The random number generator, so a loaded game draws the same events.
+    #[serde(default)]
+    pub rng: Rng,
+    #[serde(default)]
+    pub weather: Weather,
+    #[serde(default)]
+    pub edges: Vec<ScenarioEdge>,
+    /// The ordinals handed out, so new units are not named like the saved ones.
+    #[serde(default)]
+    pub unit_names: UnitNames,
+    /// The per-round snapshots for the graphs after the match.
+    #[serde(default)]
+    pub statistics: StatisticsSeries,
+    #[serde(default)]
+    pub promotion: Option<SavedPromotion>,
+    /// The moves and attacks every player ordered in the saved round.
+    #[serde(default)]
+    pub commands_used: BTreeMap<usize, u32>,
+    /// The orders planned in the saved round with the simultaneous rules, as the commands that
+    /// gave them.
+    #[serde(default)]
+    pub planned_orders: Vec<Command>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaveLoadError {
+    /// The save was written by a newer version of the game.
+    TooNew(u32),
+    /// The save could not be read, the path points to the offending field.
+    CorruptAtField(String),
+    /// Migrating from the given version to the next one failed.
+    MigrationFailed(u32),
+}
+
+impl SaveLoadError {
+    pub fn message(&self) -> String {
+        match self {
+            SaveLoadError::TooNew(version) => format!(
+                "The save has version {}, but only versions up to {} are supported",
+                version, CURRENT_SAVE_VERSION
+            ),
+            SaveLoadError::CorruptAtField(path) => format!("The save is corrupt at '{}'", path),
+            SaveLoadError::MigrationFailed(version) => format!(
+                "The save could not be migrated from version {} to {}",
+                version,
+                version + 1
+            ),
+        }
+    }
+}
+
+impl SaveData {
+    pub fn from_game<S: EntityStore>(world: &S, state: &GameState) -> Self {
+        let mut units: Vec<SavedUnit> = <(
+            &PersistentId,
+            &PlayerComponent,
+            &Hexagon,
+            &Unit,
+            Option<&History>,
+            Option<&MovementType>,
+            Option<&Demolition>,
+            Option<&UnitName>,
+            Option<&Garrisoned>,
+            Option<&Facing>,
+            Option<&Supply>,
+            Option<&Perks>,
+            Option<&Experience>,
+            Option<&Abilities>,
+            Option<&Overdrive>,
+            Option<&Overwatching>,
+            Option<&UnitType>,
+        )>::query()
+        .iter(world)
+        .map(
+            |(
+                id,
+                player,
+                position,
+                unit,
+                history,
+                movement_type,
+                demolition,
+                name,
+                garrisoned,
+                facing,
+                supply,
+                perks,
+                experience,
+                abilities,
+                overdrive,
+                overwatching,
+                unit_type,
+            )| {
+                SavedUnit {
+                    id: *id,
+                    player: player.0,
+                    position: *position,
+                    unit: *unit,
+                    history: history.cloned().unwrap_or_default(),
+                    movement_type: movement_type.copied().unwrap_or_default(),
+                    demolition: demolition.is_some(),
+                    name: name.map(|name| name.0.clone()),
+                    garrisoned: garrisoned.is_some(),
+                    facing: facing.map(|facing| facing.0),
+                    supply: supply.copied(),
+                    perks: perks.cloned().unwrap_or_default(),
+                    experience: experience.copied().unwrap_or_default(),
+                    abilities: abilities.cloned().unwrap_or_default(),
+                    overdrive: overdrive.map_or(0, |overdrive| overdrive.0),
+                    overwatching: overwatching.is_some(),
+                    unit_type: unit_type.map(|unit_type| unit_type.0.clone()),
+                }
+            },
+        )
+        .collect();
+        units.sort_by_key(|unit| unit.id);
+        let promotion = match &state.state {
+            State::ChoosingPromotion(entity, options) => PersistentId::of_entity(world, *entity)
+                .map(|unit| SavedPromotion {
+                    unit,
+                    options: options.clone(),
+                }),
+            _ => None,
+        };
+        SaveData {
+            round: state.round,
+            current_player: state.current_player,
+            players: state
+                .players
+                .iter()
+                .map(|player| {
+                    let colour = player.get_colour();
+                    SavedPlayer {
+                        name: player.get_name(),
+                        colour: [colour.r, colour.g, colour.b, colour.a],
+                        observer: player.is_observer(),
+                        handicap: player.get_handicap(),
+                        resources: player.get_resources(),
+                        ai_profile: player.get_ai_profile(),
+                    }
+                })
+                .collect(),
+            units,
+            pickups: scenario_pickups(world),
+            buildings: scenario_buildings(world),
+            supply_sources: scenario_supply_sources(world),
+            ground_effects: ground_effects(world),
+            smoke: smoke_clouds(world),
+            combat_log: state.combat_log.clone(),
+            palette: Some(state.palette.clone()),
+            awaiting_player: state.is_view_hidden(),
+            achievements: state.achievements.clone(),
+            next_persistent_id: state.persistent_ids.next_id(),
+            last_seen: state.last_seen.clone(),
+            triggers: state.triggers.clone(),
+            rng: state.rng,
+            weather: state.weather,
+            edges: state.edges.to_list(),
+            unit_names: state.unit_names.clone(),
+            statistics: state.statistics.clone(),
+            promotion,
+            commands_used: state.commands_used.clone(),
+            planned_orders: planned_commands(world, state),
+        }
+    }
+
+    /// Spawns the saved units, pickups, buildings, supply sources, ground effects and smoke.
+    /// Units get nodes from `unit_template`, the rest only if it is set as well. The ids are
+    /// rebuilt with the saved ones and the planned orders put back on their units.
+    pub fn spawn(
+        &self,
+        world: &mut World,
+        ids: &mut PersistentIds,
+        unit_template: Option<NodeTemplate>,
+    ) {
+        let with_nodes = unit_template.is_some();
+        for saved in &self.pickups {
+            spawn_pickup(world, saved.position, saved.pickup, with_nodes);
+        }
+        for saved in &self.buildings {
+            spawn_building(world, saved.position, saved.building, with_nodes);
+        }
+        for saved in &self.supply_sources {
+            spawn_supply_source(world, saved.position, saved.player);
+        }
+        for saved in &self.ground_effects {
+            spawn_ground_effect(world, saved.position, saved.effect, with_nodes);
+        }
+        for saved in &self.smoke {
+            spawn_smoke(
+                world,
+                saved.position,
+                saved.player,
+                saved.rounds_remaining,
+                with_nodes,
+            );
+        }
+        for saved in &self.units {
+            let entity = spawn_unit(
+                world,
+                ids,
+                saved.player,
+                saved.position,
+                saved.unit,
+                unit_template.clone(),
+            );
+            UnitIdentity {
+                name: saved.name.clone().map(UnitName),
+                experience: Some(saved.experience),
+                perks: Some(saved.perks.clone()),
+                history: Some(saved.history.clone()),
+            }
+            .apply(world, entity);
+            if let Some(mut entry) = world.entry(entity) {
+                entry.add_component(saved.id);
+                entry.add_component(saved.movement_type);
+                if saved.demolition {
+                    entry.add_component(Demolition);
+                }
+                // The saved armor already includes the bonus of the building.
+                if saved.garrisoned {
+                    entry.add_component(Garrisoned);
+                }
+                if let Some(facing) = saved.facing {
+                    entry.add_component(Facing(facing));
+                }
+                if let Some(supply) = saved.supply {
+                    entry.add_component(supply);
+                }
+                if !saved.abilities.0.is_empty() {
+                    entry.add_component(saved.abilities.clone());
+                }
+                // The saved damage already includes the bonus.
+                if saved.overdrive > 0 {
+                    entry.add_component(Overdrive(saved.overdrive));
+                }
+                if saved.overwatching {
+                    entry.add_component(Overwatching);
+                }
+                if let Some(unit_type) = &saved.unit_type {
+                    entry.add_component(UnitType(unit_type.clone()));
+                }
+            }
+        }
+        // The saved ids replace the ones the units were spawned with.
+        ids.rebuild(world);
+        restore_planned_commands(world, ids, &self.planned_orders);
+    }
+
+    /// Copies the saved values into the state. Units have to be spawned by the caller, as they
+    /// might need Godot nodes.
+    pub fn restore_state(&self, state: &mut GameState) {
+        state.round = self.round;
+        state.current_player = self.current_player;
+        state.players = self
+            .players
+            .iter()
+            .map(|player| {
+                let [r, g, b, a] = player.colour;
+                let colour = Color::rgba(r, g, b, a);
+                let mut restored = if player.observer {
+                    Player::new_observer(player.name.clone(), colour)
+                } else {
+                    Player::new(player.name.clone(), colour)
+                };
+                restored.set_handicap(player.handicap);
+                restored.set_resources(player.resources);
+                restored.set_ai_profile(player.ai_profile);
+                restored
+            })
+            .collect();
+        state.combat_log = self.combat_log.clone();
+        state.achievements = self.achievements.clone();
+        state.last_seen = self.last_seen.clone();
+        state.triggers = self.triggers.clone();
+        state.rng = self.rng;
+        state.weather = self.weather;
+        state.edges = EdgeData::from_list(&self.edges);
+        state.unit_names = self.unit_names.clone();
+        state.statistics = self.statistics.clone();
+        state.commands_used = self.commands_used.clone();
+        // The initiative queue is built again from the restored units.
+        state.initiative = InitiativeQueue::default();
+        if self.next_persistent_id > 0 {
+            state
+                .persistent_ids
+                .reserve(PersistentId(self.next_persistent_id - 1));
+        }
+        if let Some(palette) = &self.palette {
+            if !state.set_palette(palette) {
+                log_warn!("Unknown palette {} in save", palette);
+            }
+        }
+        if self.awaiting_player {
+            force_state(state, State::TurnTransition(0.0));
+            state.awaiting_player = true;
+        } else if state.rules.simultaneous {
+            // The planned orders come back with the units.
+            force_state(state, State::Planning);
+        } else {
+            force_state(state, State::Waiting);
+        }
+    }
+
+    /// Waits for the promotion that was pending when the game was saved again. Has to be called
+    /// after `restore_state`, once the units are spawned.
+    pub fn restore_promotion<S: EntityStore>(&self, world: &S, state: &mut GameState) {
+        if self.awaiting_player {
+            return;
+        }
+        let promotion = match &self.promotion {
+            None => return,
+            Some(promotion) => promotion,
+        };
+        match state.persistent_ids.find(world, promotion.unit) {
+            None => log_warn!("The promoted unit {:?} is not in the save", promotion.unit),
+            Some(entity) => force_state(
+                state,
+                State::ChoosingPromotion(entity, promotion.options.clone()),
+            ),
+        }
+    }
+}
+
+pub fn save_game(data: &SaveData) -> Result<String, serde_json::Error> {
+    let envelope = Envelope {
+        version: CURRENT_SAVE_VERSION,
+        data: serde_json::to_value(data)?,
+    };
+    serde_json::to_string(&envelope)
+}
+
+pub fn load_game(json: &str) -> Result<SaveData, SaveLoadError> {
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let envelope: Envelope = serde_path_to_error::deserialize(&mut deserializer)
+        .map_err(|error| SaveLoadError::CorruptAtField(error.path().to_string()))?;
+    if envelope.version > CURRENT_SAVE_VERSION {
+        return Err(SaveLoadError::TooNew(envelope.version));
+    }
+    if envelope.version == 0 {
+        return Err(SaveLoadError::CorruptAtField("version".to_owned()));
+    }
+    let mut data = envelope.data;
+    for version in envelope.version..CURRENT_SAVE_VERSION {
+        let migrate = MIGRATIONS[(version - 1) as usize];
+        data = migrate(data).ok_or(SaveLoadError::MigrationFailed(version))?;
+    }
+    serde_path_to_error::deserialize(data)
+        .map_err(|error| SaveLoadError::CorruptAtField(format!("data.{}", error.path())))
+}
+
+/// Version 1 did not store persistent ids, units get them in the order they were saved.
+fn migrate_v1_to_v2(mut data: Value) -> Option<Value> {
+    let units = data.get_mut("units")?.as_array_mut()?;
+    for (index, unit) in units.iter_mut().enumerate() {
+        unit.as_object_mut()?
+            .insert("id".to_owned(), Value::from(index as u64));
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abilities::{start_turn, use_ability, validate_ability, AbilityError};
+    use crate::buildings::{enter_garrison, spawn_building, GARRISON_ARMOR_BONUS};
+    use crate::commands::apply_command;
+    use crate::components::abilities::{AbilityInstance, AbilityKind};
+    use crate::components::building::Building;
+    use crate::components::ground_effect::GroundEffect;
+    use crate::components::pickup::Pickup;
+    use crate::ground_effects::spawn_ground_effect;
+    use crate::palette::DEFAULT_PALETTE;
+    use crate::pickups::spawn_pickup;
+    use crate::rules::Ruleset;
+    use crate::simulation::simulate_frame;
+    use crate::spawn::spawn_unit;
+    use crate::statistics::record_round;
+    use crate::systems::hexgrid::is_vision_blocked;
+    use crate::triggers::{Trigger, TriggerAction, TriggerCondition};
+    use crate::unit_names::{name_new_units, rename_unit};
+    use legion::World;
+
+    const V1_SAVE: &str = include_str!("../fixtures/savegame_v1.json");
+
+    #[test]
+    fn v1_fixture_is_migrated() {
+        let data = load_game(V1_SAVE).unwrap();
+
+        assert_eq!(data.round, 3);
+        assert_eq!(data.current_player, Some(1));
+        assert_eq!(data.players[1].name, "Player 2");
+        assert_eq!(data.units.len(), 2);
+        assert_eq!(data.units[0].id, PersistentId(0));
+        assert_eq!(data.units[1].id, PersistentId(1));
+        assert_eq!(data.units[0].unit.integrity, 14);
+        assert_eq!(data.units[1].position, Hexagon::new_axial(-2, -1));
+        assert!(data.combat_log.is_empty());
+    }
+
+    #[test]
+    fn saved_game_loads_again() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.players.push(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.current_player = Some(0);
+        let unit = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(1, 2),
+            Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+            None,
+        );
+        spawn_building(
+            &mut world,
+            Hexagon::new_axial(1, 2),
+            Building::new(15),
+            false,
+        );
+        enter_garrison(&mut world, unit);
+        world
+            .entry(unit)
+            .unwrap()
+            .add_component(Facing(Direction::NorthWest));
+        world.entry(unit).unwrap().add_component(Overwatching);
+        world
+            .entry(unit)
+            .unwrap()
+            .add_component(UnitType("Tank".to_owned()));
+        name_new_units(&mut world, &mut state.unit_names);
+        rename_unit(
+            &mut world,
+            &state.persistent_ids,
+            PersistentId(0),
+            "Old Faithful",
+        )
+        .unwrap();
+        spawn_pickup(
+            &mut world,
+            Hexagon::new_axial(0, 1),
+            Pickup::Repair(4),
+            false,
+        );
+        let smoke = GroundEffect::Smoke { rounds_left: 2 };
+        spawn_ground_effect(&mut world, Hexagon::new_axial(0, 1), smoke, false);
+        record_round(&world, &mut state);
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+
+        assert_eq!(loaded.units, saved.units);
+        assert_eq!(loaded.statistics, state.statistics);
+        assert_eq!(loaded.statistics.snapshots()[0].players[0].units, 1);
+        assert_eq!(loaded.units[0].name, Some("Old Faithful".to_owned()));
+        assert_eq!(loaded.unit_names, state.unit_names);
+        assert_eq!(loaded.pickups.len(), 1);
+        assert_eq!(loaded.pickups[0].pickup, Pickup::Repair(4));
+        assert!(loaded.units[0].garrisoned);
+        assert_eq!(loaded.units[0].facing, Some(Direction::NorthWest));
+        assert!(loaded.units[0].overwatching);
+        let mut loaded_world = World::default();
+        loaded.spawn(&mut loaded_world, &mut PersistentIds::default(), None);
+        assert_eq!(<&Overwatching>::query().iter(&loaded_world).count(), 1);
+        assert_eq!(
+            <&UnitType>::query().iter(&loaded_world).collect::<Vec<_>>(),
+            vec![&UnitType("Tank".to_owned())]
+        );
+        assert_eq!(loaded.units[0].unit.armor, GARRISON_ARMOR_BONUS);
+        assert_eq!(loaded.buildings.len(), 1);
+        assert_eq!(loaded.buildings[0].building, Building::new(15));
+        assert_eq!(
+            loaded.ground_effects,
+            vec![SavedGroundEffect {
+                position: Hexagon::new_axial(0, 1),
+                effect: smoke,
+            }]
+        );
+        assert_eq!(loaded.players, saved.players);
+        assert_eq!(loaded.current_player, Some(0));
+        assert_eq!(loaded.palette, Some(DEFAULT_PALETTE.to_owned()));
+    }
+
+    #[test]
+    fn abilities_keep_their_cooldowns_after_loading() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        let unit = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::zero(),
+            Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+            None,
+        );
+        world.entry(unit).unwrap().add_component(Abilities(vec![
+            AbilityInstance::new(AbilityKind::Smoke),
+            AbilityInstance::new(AbilityKind::Overdrive),
+        ]));
+        let rules = Ruleset::default();
+        let cloud = Hexagon::new_axial(1, 0);
+        use_ability(&mut world, &rules, unit, 0, cloud).unwrap();
+        use_ability(&mut world, &rules, unit, 1, Hexagon::zero()).unwrap();
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        let mut loaded_world = World::default();
+        let mut ids = PersistentIds::default();
+        loaded.spawn(&mut loaded_world, &mut ids, None);
+        let unit = ids.find(&loaded_world, PersistentId(0)).unwrap();
+
+        assert_eq!(
+            validate_ability(&loaded_world, &rules, unit, 0, Hexagon::new_axial(-1, 0)),
+            Err(AbilityError::CoolingDown(AbilityKind::Smoke.cooldown()))
+        );
+        assert!(is_vision_blocked(&cloud, &loaded_world));
+        // Overdrive is still taken back at the start of the owner's next turn.
+        start_turn(&mut loaded_world, 0);
+        let damage = loaded_world
+            .entry_ref(unit)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap()
+            .damage;
+        assert_eq!(damage, 5);
+        assert!(is_vision_blocked(&cloud, &loaded_world));
+    }
+
+    #[test]
+    fn planned_orders_survive_loading() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        for name in ["Player 1", "Player 2"].iter() {
+            state.add_player(Player::new((*name).to_owned(), Color::rgb(0.0, 0.0, 1.0)));
+        }
+        state.rules.simultaneous = true;
+        let ids: Vec<PersistentId> = [(0, 0), (1, 3)]
+            .iter()
+            .map(|(player, q)| {
+                let entity = spawn_unit(
+                    &mut world,
+                    &mut state.persistent_ids,
+                    *player,
+                    Hexagon::new_axial(*q, 0),
+                    Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+                    None,
+                );
+                PersistentId::of_entity(&world, entity).unwrap()
+            })
+            .collect();
+        force_state(&mut state, State::Planning);
+        let orders = vec![
+            Command::Move {
+                player: 0,
+                unit: ids[0],
+                path: vec![Hexagon::new_axial(1, 0)],
+            },
+            Command::Attack {
+                player: 1,
+                attacker: ids[1],
+                defender: ids[0],
+            },
+        ];
+        // The move is on its unit already, the attack still waits for the next frame.
+        apply_command(&world, &mut state, &orders[0]).unwrap();
+        simulate_frame(&mut world, &mut state, 0.0);
+        apply_command(&world, &mut state, &orders[1]).unwrap();
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        assert_eq!(loaded.planned_orders, orders);
+        let mut restored_world = World::default();
+        let mut restored = GameState::new();
+        restored.rules.simultaneous = true;
+        loaded.restore_state(&mut restored);
+        loaded.spawn(&mut restored_world, &mut restored.persistent_ids, None);
+        assert_eq!(restored.state, State::Planning);
+        assert_eq!(planned_commands(&restored_world, &restored), orders);
+
+        let resolve = Command::ResolveRound { player: 0 };
+        apply_command(&restored_world, &mut restored, &resolve).unwrap();
+        simulate_frame(&mut restored_world, &mut restored, 0.0);
+        let moved = restored
+            .persistent_ids
+            .find(&restored_world, ids[0])
+            .unwrap();
+        assert_eq!(
+            restored_world
+                .entry_ref(moved)
+                .unwrap()
+                .get_component::<Hexagon>()
+                .unwrap(),
+            &Hexagon::new_axial(1, 0)
+        );
+    }
+
+    #[test]
+    fn ids_of_destroyed_units_are_not_reused_after_loading() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        for q in 0..2 {
+            spawn_unit(
+                &mut world,
+                &mut state.persistent_ids,
+                0,
+                Hexagon::new_axial(q, 0),
+                Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+                None,
+            );
+        }
+        let last = PersistentId(1).find_entity(&world).unwrap();
+        world.remove(last);
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        let mut restored = GameState::new();
+        loaded.restore_state(&mut restored);
+
+        assert_eq!(loaded.units.len(), 1);
+        assert_eq!(restored.persistent_ids.allocate(), PersistentId(2));
+    }
+
+    #[test]
+    fn ghosts_are_saved() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        for name in ["Player 1", "Player 2"].iter() {
+            state
+                .players
+                .push(Player::new((*name).to_owned(), Color::rgb(0.0, 0.0, 1.0)));
+        }
+        for (player, q) in [(0, 0), (1, 3)].iter() {
+            spawn_unit(
+                &mut world,
+                &mut state.persistent_ids,
+                *player,
+                Hexagon::new_axial(*q, 0),
+                Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+                None,
+            );
+        }
+        state
+            .last_seen
+            .update(&world, &state.players, &state.rules, 1);
+        let enemy = PersistentId(1).find_entity(&world).unwrap();
+        world
+            .entry(enemy)
+            .unwrap()
+            .add_component(Hexagon::new_axial(9, 0));
+        state
+            .last_seen
+            .update(&world, &state.players, &state.rules, 1);
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        let mut restored = GameState::new();
+        loaded.restore_state(&mut restored);
+
+        assert_eq!(restored.last_seen.ghosts(0).len(), 1);
+        assert_eq!(restored.last_seen.ghosts(0), state.last_seen.ghosts(0));
+    }
+
+    #[test]
+    fn handicaps_are_saved() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        let handicap = Handicap {
+            resource_multiplier: 1.5,
+            integrity_bonus: 20,
+            aggression: 80,
+        };
+        state.set_handicap(&mut world, 0, handicap).unwrap();
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        let mut restored = GameState::new();
+        loaded.restore_state(&mut restored);
+
+        assert_eq!(restored.handicaps(), vec![handicap]);
+    }
+
+    #[test]
+    fn fired_triggers_stay_fired_after_loading() {
+        let world = World::default();
+        let mut state = GameState::new();
+        state.triggers = Triggers::new(vec![Trigger {
+            condition: TriggerCondition::RoundReached { round: 1 },
+            actions: vec![TriggerAction::EndGame { winner: None }],
+            repeating: false,
+        }]);
+        assert_eq!(
+            state
+                .triggers
+                .evaluate(&world, &state.players, state.round, &[])
+                .len(),
+            1
+        );
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        let mut restored = GameState::new();
+        loaded.restore_state(&mut restored);
+        restored.round = 2;
+
+        assert_eq!(restored.triggers.definitions().len(), 1);
+        assert!(restored
+            .triggers
+            .evaluate(&world, &restored.players, restored.round, &[])
+            .is_empty());
+    }
+
+    #[test]
+    fn save_during_privacy_screen_restores_waiting_for_player() {
+        let world = World::default();
+        let mut state = GameState::new();
+        state.state = State::TurnTransition(0.0);
+        state.awaiting_player = true;
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        let mut restored = GameState::new();
+        loaded.restore_state(&mut restored);
+
+        assert!(restored.is_view_hidden());
+        assert_eq!(restored.state, State::TurnTransition(0.0));
+    }
+
+    #[test]
+    fn save_during_a_promotion_restores_the_choice() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        let unit = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::zero(),
+            Unit::new(10, 5, 2, 1, 0, 3, 3, 1),
+            None,
+        );
+        world
+            .entry(unit)
+            .unwrap()
+            .add_component(Perks(vec![PerkId::Mobility]));
+        world
+            .entry(unit)
+            .unwrap()
+            .add_component(Experience { kills: 4 });
+        let options = vec![PerkId::Damage, PerkId::Integrity];
+        state.state = State::ChoosingPromotion(unit, options.clone());
+
+        let saved = SaveData::from_game(&world, &state);
+        let loaded = load_game(&save_game(&saved).unwrap()).unwrap();
+        let mut restored = GameState::new();
+        loaded.restore_state(&mut restored);
+        assert_eq!(restored.state, State::Waiting);
+        restored.persistent_ids.rebuild(&world);
+        loaded.restore_promotion(&world, &mut restored);
+
+        assert_eq!(loaded.units[0].perks, Perks(vec![PerkId::Mobility]));
+        assert_eq!(loaded.units[0].experience.kills, 4);
+        assert_eq!(restored.state, State::ChoosingPromotion(unit, options));
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let json = format!(
+            r#"{{"version": {}, "data": {{}}}}"#,
+            CURRENT_SAVE_VERSION + 1
+        );
+        assert_eq!(
+            load_game(&json).unwrap_err(),
+            SaveLoadError::TooNew(CURRENT_SAVE_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn failed_migration_reports_step() {
+        let json = r#"{"version": 1, "data": {"units": 5}}"#;
+        assert_eq!(
+            load_game(json).unwrap_err(),
+            SaveLoadError::MigrationFailed(1)
+        );
+    }
+
+    #[test]
+    fn corrupt_field_is_reported_with_path() {
+        let json = V1_SAVE.replace(r#""integrity": 10"#, r#""integrity": "ten""#);
+        assert_eq!(
+            load_game(&json).unwrap_err(),
+            SaveLoadError::CorruptAtField("data.units[1].unit.integrity".to_owned())
+        );
+    }
+}
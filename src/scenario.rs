@@ -0,0 +1,325 @@
+//! Scenario files describe a map and the units on it, so it can be set up again later.
+
+use crate::ai::AiProfile;
+use crate::buildings::spawn_building;
+use crate::components::building::Building;
+use crate::components::field::Field;
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::{PersistentId, PersistentIds};
+use crate::components::pickup::Pickup;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::supply::SupplySource;
+use crate::components::terrain::Terrain;
+use crate::components::unit::Unit;
+use crate::components::unit_name::UnitName;
+use crate::components::unit_type::UnitType;
+use crate::edges::ScenarioEdge;
+use crate::handicap::Handicap;
+use crate::pickups::spawn_pickup;
+use crate::roster::{Roster, RosterUnit};
+use crate::spawn::{spawn_units, SpawnError, UnitSource, UnitSpawn};
+use crate::supply::spawn_supply_source;
+use crate::triggers::Trigger;
+use crate::unit_catalog::UnitCatalog;
+use legion::{Entity, EntityStore, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioField {
+    pub position: Hexagon,
+    pub terrain: Terrain,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioUnit {
+    pub player: usize,
+    pub position: Hexagon,
+    pub unit_type: String,
+    /// Replaces the name made from the type and an ordinal.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Takes the next unit of the player's imported roster, the unit of the slot is only created
+    /// when the roster is used up.
+    #[serde(default)]
+    pub from_roster: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioPickup {
+    pub position: Hexagon,
+    pub pickup: Pickup,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioBuilding {
+    pub position: Hexagon,
+    pub building: Building,
+}
+
+/// A factory or headquarters the units of `player` draw their supply from.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioSupplySource {
+    pub position: Hexagon,
+    pub player: usize,
+}
+
+/// The profile of a computer player, either the name of a preset or the weights themselves.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScenarioAiProfile {
+    Preset(String),
+    Weights(AiProfile),
+}
+
+impl ScenarioAiProfile {
+    /// The weights, `None` for an unknown preset.
+    pub fn resolve(&self) -> Option<AiProfile> {
+        match self {
+            ScenarioAiProfile::Preset(name) => AiProfile::preset(name),
+            ScenarioAiProfile::Weights(profile) => Some(*profile),
+        }
+    }
+}
+
+/// A player the computer plays.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioAi {
+    pub player: usize,
+    pub profile: ScenarioAiProfile,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    pub fields: Vec<ScenarioField>,
+    /// Hexagons cut out of `fields`, for lakes and voids in a map that lists a filled grid.
+    #[serde(default)]
+    pub removed: Vec<Hexagon>,
+    pub units: Vec<ScenarioUnit>,
+    #[serde(default)]
+    pub pickups: Vec<ScenarioPickup>,
+    #[serde(default)]
+    pub buildings: Vec<ScenarioBuilding>,
+    #[serde(default)]
+    pub supply_sources: Vec<ScenarioSupplySource>,
+    #[serde(default)]
+    pub triggers: Vec<Trigger>,
+    /// Rivers, bridges and cliffs between the hexagons.
+    #[serde(default)]
+    pub edges: Vec<ScenarioEdge>,
+    #[serde(default)]
+    pub ai_players: Vec<ScenarioAi>,
+}
+
+impl Scenario {
+    /// Describes the map, the units, the pickups, the buildings and the supply sources in the
+    /// world. Units that were not created from the catalog can not be described and are left out. The world holds no triggers, no edges and
+    /// no computer players, they have to be added by the caller.
+    pub fn from_world<S: EntityStore>(world: &S) -> Self {
+        let mut fields: Vec<ScenarioField> = <&Field>::query()
+            .iter(world)
+            .map(|field| ScenarioField {
+                position: field.location,
+                terrain: field.terrain,
+            })
+            .collect();
+        fields.sort_by_key(|field| (field.position.get_q(), field.position.get_r()));
+
+        let mut units: Vec<(PersistentId, ScenarioUnit)> =
+            <(&PersistentId, &PlayerComponent, &Hexagon, &UnitType)>::query()
+                .iter(world)
+                .map(|(id, player, position, unit_type)| {
+                    (
+                        *id,
+                        ScenarioUnit {
+                            player: player.0,
+                            position: *position,
+                            unit_type: unit_type.0.clone(),
+                            name: None,
+                            from_roster: false,
+                        },
+                    )
+                })
+                .collect();
+        units.sort_by_key(|(id, _)| *id);
+
+        Scenario {
+            fields,
+            removed: Vec::new(),
+            units: units.into_iter().map(|(_, unit)| unit).collect(),
+            pickups: scenario_pickups(world),
+            buildings: scenario_buildings(world),
+            supply_sources: scenario_supply_sources(world),
+            triggers: Vec::new(),
+            edges: Vec::new(),
+            ai_players: Vec::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Replaces the map, all units, pickups, buildings and supply sources in the world with the
+    /// scenario. Returns the unit types that were not found in the catalog. Units off the map or on top of another one are skipped.
+    pub fn load(
+        &self,
+        world: &mut World,
+        ids: &mut PersistentIds,
+        catalog: &UnitCatalog,
+        handicaps: &[Handicap],
+        with_nodes: bool,
+    ) -> Vec<String> {
+        self.load_with_rosters(world, ids, catalog, handicaps, &HashMap::new(), with_nodes)
+    }
+
+    /// Like `load`, the unit slots marked `from_roster` are filled from the rosters of their
+    /// players in order.
+    pub fn load_with_rosters(
+        &self,
+        world: &mut World,
+        ids: &mut PersistentIds,
+        catalog: &UnitCatalog,
+        handicaps: &[Handicap],
+        rosters: &HashMap<usize, Roster>,
+        with_nodes: bool,
+    ) -> Vec<String> {
+        let existing: Vec<Entity> = <(Entity, &Field)>::query()
+            .iter(world)
+            .map(|(entity, _)| *entity)
+            .chain(
+                <(Entity, &Unit)>::query()
+                    .iter(world)
+                    .map(|(entity, _)| *entity),
+            )
+            .chain(
+                <(Entity, &Pickup)>::query()
+                    .iter(world)
+                    .map(|(entity, _)| *entity),
+            )
+            .chain(
+                <(Entity, &Building)>::query()
+                    .iter(world)
+                    .map(|(entity, _)| *entity),
+            )
+            .chain(
+                <(Entity, &SupplySource)>::query()
+                    .iter(world)
+                    .map(|(entity, _)| *entity),
+            )
+            .collect();
+        for entity in existing {
+            world.remove(entity);
+        }
+
+        for field in self
+            .fields
+            .iter()
+            .filter(|field| !self.removed.contains(&field.position))
+        {
+            let mut new_field = Field::new(field.position);
+            new_field.terrain = field.terrain;
+            world.push((new_field,));
+        }
+        for pickup in &self.pickups {
+            spawn_pickup(world, pickup.position, pickup.pickup, with_nodes);
+        }
+        for building in &self.buildings {
+            spawn_building(world, building.position, building.building, with_nodes);
+        }
+        for source in &self.supply_sources {
+            spawn_supply_source(world, source.position, source.player);
+        }
+        let mut taken: HashMap<usize, usize> = HashMap::new();
+        let veterans: Vec<Option<&RosterUnit>> = self
+            .units
+            .iter()
+            .map(|unit| {
+                if !unit.from_roster {
+                    return None;
+                }
+                let next = taken.entry(unit.player).or_insert(0);
+                let veteran = rosters
+                    .get(&unit.player)
+                    .and_then(|roster| roster.units.get(*next));
+                *next += 1;
+                veteran
+            })
+            .collect();
+        let spawns: Vec<UnitSpawn> = self
+            .units
+            .iter()
+            .zip(&veterans)
+            .map(|(unit, veteran)| UnitSpawn {
+                player: unit.player,
+                position: unit.position,
+                source: UnitSource::Type(
+                    veteran
+                        .map_or(&unit.unit_type, |veteran| &veteran.unit_type)
+                        .clone(),
+                ),
+                scene: None,
+            })
+            .collect();
+        let mut missing = Vec::new();
+        let results = spawn_units(world, ids, catalog, handicaps, &spawns, with_nodes).results;
+        for ((unit, veteran), result) in self.units.iter().zip(veterans).zip(results) {
+            match result {
+                Ok(entity) => {
+                    if let (Some(name), Some(mut entry)) = (&unit.name, world.entry(entity)) {
+                        entry.add_component(UnitName(name.clone()));
+                    }
+                    if let Some(veteran) = veteran {
+                        veteran.apply(world, entity);
+                    }
+                }
+                Err(SpawnError::UnknownUnitType(unit_type)) => missing.push(unit_type),
+                Err(error) => log_warn!("Scenario unit skipped: {}", error),
+            }
+        }
+        missing
+    }
+}
+
+/// The pickups in the world, ordered by their position.
+pub fn scenario_pickups<S: EntityStore>(world: &S) -> Vec<ScenarioPickup> {
+    let mut pickups: Vec<ScenarioPickup> = <(&Hexagon, &Pickup)>::query()
+        .iter(world)
+        .map(|(position, pickup)| ScenarioPickup {
+            position: *position,
+            pickup: *pickup,
+        })
+        .collect();
+    pickups.sort_by_key(|pickup| (pickup.position.get_q(), pickup.position.get_r()));
+    pickups
+}
+
+/// The buildings in the world, ordered by their position.
+pub fn scenario_buildings<S: EntityStore>(world: &S) -> Vec<ScenarioBuilding> {
+    let mut buildings: Vec<ScenarioBuilding> = <(&Hexagon, &Building)>::query()
+        .iter(world)
+        .map(|(position, building)| ScenarioBuilding {
+            position: *position,
+            building: *building,
+        })
+        .collect();
+    buildings.sort_by_key(|building| (building.position.get_q(), building.position.get_r()));
+    buildings
+}
+
+/// The supply sources in the world, ordered by their position.
+pub fn scenario_supply_sources<S: EntityStore>(world: &S) -> Vec<ScenarioSupplySource> {
+    let mut sources: Vec<ScenarioSupplySource> = <(&Hexagon, &SupplySource)>::query()
+        .iter(world)
+        .map(|(position, source)| ScenarioSupplySource {
+            position: *position,
+            player: source.player,
+        })
+        .collect();
+    sources.sort_by_key(|source| (source.position.get_q(), source.position.get_r()));
+    sources
+}
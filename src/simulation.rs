@@ -0,0 +1,639 @@
+use crate::action_log::settle_actions;
+use crate::actionable::refresh_attackable_entities;
+use crate::combat_log::LogEntry;
+use crate::components::unit::AttackError;
+use crate::game_state::GameState;
+use crate::messages;
+use crate::random_events::describe;
+use crate::state_machine::{advance_state, GameEvent};
+use crate::triggers::apply_trigger_actions;
+use crate::turn_file::apply_incoming_command;
+use crate::unit_names::{name_new_units, unit_names, UNNAMED_UNIT};
+use legion::{Entity, World};
+use std::collections::HashMap;
+
+/// Advances the game by one frame, runs the scenario triggers and records what happened in the
+/// combat log and the notifications. Derived selection data is refreshed as well. This does not touch any Godot object, so it can be driven without the engine.
+/// The events are returned so the caller can react to them as well.
+pub fn simulate_frame(world: &mut World, state: &mut GameState, delta: f64) -> Vec<GameEvent> {
+    name_new_units(world, &mut state.unit_names);
+    // Taken before the frame, as destroyed units are gone from the world once it is over.
+    let names = unit_names(world);
+    let mut events = advance_state(world, state, delta);
+    let actions = state
+        .triggers
+        .evaluate(world, &state.players, state.round, &events);
+    apply_trigger_actions(world, state, actions, &mut events);
+    if events.iter().any(|event| {
+        matches!(
+            event,
+            GameEvent::UnitMoved { .. } | GameEvent::UnitDestroyed { .. }
+        )
+    }) {
+        state.influence.mark_dirty();
+        state.redraw_grid |= state.influence_layer;
+    }
+    if state.rules.fog_of_war {
+        state.last_seen.update(
+            world,
+            &state.players,
+            &state.rules,
+            &state.map_bounds,
+            state.weather.vision_range(),
+            state.round,
+        );
+    }
+    for event in &events {
+        record_event(state, &names, event);
+    }
+    state.achievements.observe(world, &events);
+    let resolving = state.state.is_resolving();
+    state.tutorial.update(delta, resolving);
+    refresh_attackable_entities(world, state);
+    settle_actions(world, state);
+    apply_incoming_command(world, state);
+    events
+}
+
+fn record_event(state: &mut GameState, names: &HashMap<Entity, String>, event: &GameEvent) {
+    let name = |entity: &Entity| names.get(entity).map_or(UNNAMED_UNIT, String::as_str);
+    match *event {
+        GameEvent::TurnStarted { round, player } => {
+            let player_name = state.players[player].get_name();
+            state
+                .notifications
+                .push(messages::turn_started(round, &player_name));
+        }
+        GameEvent::UnitMoved {
+            ref entity,
+            from,
+            to,
+        } => {
+            state.combat_log.push(LogEntry::moved(
+                state.round,
+                state.current_player,
+                name(entity),
+                from,
+                to,
+            ));
+        }
+        GameEvent::UnitAttacked {
+            ref attacker,
+            ref defender,
+            attacker_position,
+            defender_position,
+            arc,
+            damage,
+            remaining_integrity,
+            ..
+        } => {
+            state.last_combat = Some(defender_position);
+            state.combat_log.push(LogEntry::attacked(
+                state.round,
+                state.current_player,
+                name(attacker),
+                attacker_position,
+                name(defender),
+                defender_position,
+                arc,
+                damage,
+                remaining_integrity,
+            ));
+        }
+        GameEvent::UnitDestroyed {
+            ref entity,
+            position,
+        } => {
+            state.combat_log.push(LogEntry::destroyed(
+                state.round,
+                state.current_player,
+                name(entity),
+                position,
+            ));
+        }
+        GameEvent::AttackFailed {
+            position, error, ..
+        } => match error {
+            AttackError::NoAttacksLeft => {
+                state
+                    .notifications
+                    .push(messages::no_attacks_left(&position));
+            }
+            AttackError::MovedBeforeFiring => {
+                state
+                    .notifications
+                    .push(messages::moved_before_firing(&position));
+            }
+        },
+        GameEvent::RandomEvent {
+            kind,
+            player,
+            position,
+        } => {
+            let player_name = player
+                .and_then(|player| state.players.get(player))
+                .map(|player| player.get_name());
+            let message = describe(kind, player_name.as_deref(), position.as_ref());
+            state.combat_log.push(LogEntry::random_event(
+                state.round,
+                player,
+                message,
+                position,
+            ));
+        }
+        GameEvent::OrderInterrupted { position, .. } => {
+            state
+                .notifications
+                .push(messages::order_interrupted(&position));
+        }
+        GameEvent::CombatFeedback { .. }
+        | GameEvent::AbilityUsed { .. }
+        | GameEvent::OverwatchChanged { .. }
+        | GameEvent::UnitTurnStarted { .. }
+        | GameEvent::PlanningStarted { .. }
+        | GameEvent::PickupCollected { .. }
+        | GameEvent::BridgeAttacked { .. }
+        | GameEvent::BridgeDestroyed { .. }
+        | GameEvent::GarrisonChanged { .. }
+        | GameEvent::BuildingAttacked { .. }
+        | GameEvent::BuildingDestroyed { .. }
+        | GameEvent::FacingChanged { .. }
+        | GameEvent::FireDamage { .. }
+        | GameEvent::PromotionAvailable { .. }
+        | GameEvent::GameOver { .. } => {}
+        GameEvent::Error(error) => {
+            if error.is_warning() {
+                log_warn!("{}", error.description());
+            } else {
+                log_error!("{}", error.description());
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "headless"))]
+mod headless_tests {
+    use crate::ai::{next_command, AiProfile};
+    use crate::combat_log::LogEntryKind;
+    use crate::commands::{apply_command, apply_local_command, Command, CommandError};
+    use crate::components::hexagon::Hexagon;
+    use crate::components::initiative::Initiative;
+    use crate::components::persistent_id::PersistentId;
+    use crate::components::pickup::Pickup;
+    use crate::components::unit::Unit;
+    use crate::game_state::{force_move, force_state, GameState, State};
+    use crate::pickups::{pickup_at, spawn_pickup};
+    use crate::player::Player;
+    use crate::simulation::*;
+    use crate::spawn::{spawn_grid, spawn_unit};
+    use crate::systems::hexgrid::find_path;
+    use crate::tutorial::TutorialConstraint;
+    use gdnative::core_types::Color;
+    use legion::{Entity, EntityStore, World};
+    use std::collections::vec_deque::VecDeque;
+
+    fn get_hexagon_of_entity(world: &World, entity: Entity) -> Option<Hexagon> {
+        let entry = world.entry_ref(entity).ok()?;
+        entry.get_component::<Hexagon>().ok().copied()
+    }
+
+    fn run_until_idle(world: &mut World, state: &mut GameState) {
+        for _ in 0..100 {
+            simulate_frame(world, state, 0.05);
+            match state.state {
+                State::Moving(_) | State::Attacking(_, _) | State::NewRound => {}
+                _ => return,
+            }
+        }
+        panic!("Simulation did not settle");
+    }
+
+    fn move_unit(world: &mut World, state: &mut GameState, unit: Entity, target: Hexagon) {
+        let start = get_hexagon_of_entity(world, unit).unwrap();
+        let path = find_path(&start, &target, &state.edges, &state.map_bounds, world);
+        assert!(!path.is_empty());
+        force_move(state, unit, VecDeque::from(path));
+        run_until_idle(world, state);
+        assert_eq!(get_hexagon_of_entity(world, unit), Some(target));
+    }
+
+    fn attack(world: &mut World, state: &mut GameState, attacker: Entity, defender: Entity) {
+        force_state(state, State::Attacking(attacker, defender));
+        run_until_idle(world, state);
+    }
+
+    fn end_turn(world: &mut World, state: &mut GameState) {
+        state.state = State::NewRound;
+        run_until_idle(world, state);
+    }
+
+    #[test]
+    fn scripted_match_runs_to_game_over() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.players.push(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.players.push(Player::new(
+            "Player 2".to_owned(),
+            Color::rgb(1.0, 0.0, 0.0),
+        ));
+        state.current_player = Some(0);
+
+        let tank = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            Unit::new(20, 10, 1, 1, 1, 3, 3, 1),
+            None,
+        );
+        let scout = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            1,
+            Hexagon::new_axial(4, 0),
+            Unit::new(8, 2, 1, 1, 0, 2, 2, 1),
+            None,
+        );
+
+        simulate_frame(&mut world, &mut state, 0.0);
+        assert!(matches!(state.state, State::Waiting));
+
+        move_unit(&mut world, &mut state, tank, Hexagon::new_axial(2, 0));
+        end_turn(&mut world, &mut state);
+        assert_eq!(state.current_player, Some(1));
+
+        move_unit(&mut world, &mut state, scout, Hexagon::new_axial(3, 0));
+        attack(&mut world, &mut state, scout, tank);
+        assert!(matches!(state.state, State::Waiting));
+        end_turn(&mut world, &mut state);
+        assert_eq!(state.current_player, Some(0));
+        assert_eq!(state.round, 2);
+
+        attack(&mut world, &mut state, tank, scout);
+
+        assert!(matches!(state.state, State::GameOver(Some(0))));
+        assert!(!world.contains(scout));
+        let kinds: Vec<LogEntryKind> = state.combat_log.latest(10).map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                LogEntryKind::Move,
+                LogEntryKind::Move,
+                LogEntryKind::Move,
+                LogEntryKind::Attack,
+                LogEntryKind::Attack,
+                LogEntryKind::Destroy,
+            ]
+        );
+    }
+
+    #[test]
+    fn planned_orders_resolve_together_and_collisions_favour_initiative() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.add_player(Player::new(
+            "Player 2".to_owned(),
+            Color::rgb(1.0, 0.0, 0.0),
+        ));
+        state.current_player = Some(0);
+        state.rules.simultaneous = true;
+        let fast = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            Unit::new(20, 4, 1, 1, 0, 3, 3, 1),
+            None,
+        );
+        let slow = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            1,
+            Hexagon::new_axial(2, 0),
+            Unit::new(20, 5, 1, 1, 0, 3, 3, 1),
+            None,
+        );
+        world.entry(fast).unwrap().add_component(Initiative(3));
+        world.entry(slow).unwrap().add_component(Initiative(1));
+        let (fast_id, slow_id) = (
+            PersistentId::of_entity(&world, fast).unwrap(),
+            PersistentId::of_entity(&world, slow).unwrap(),
+        );
+
+        let events = simulate_frame(&mut world, &mut state, 0.0);
+        assert_eq!(events, vec![GameEvent::PlanningStarted { round: 1 }]);
+        assert_eq!(state.state, State::Planning);
+
+        // Both players want the hexagon between their units, the second one also plans to shoot.
+        let contested = Hexagon::new_axial(1, 0);
+        let orders = vec![
+            Command::Move {
+                player: 1,
+                unit: slow_id,
+                path: vec![contested],
+            },
+            Command::Attack {
+                player: 1,
+                attacker: slow_id,
+                defender: fast_id,
+            },
+            Command::Move {
+                player: 0,
+                unit: fast_id,
+                path: vec![contested],
+            },
+        ];
+        for order in &orders {
+            assert_eq!(apply_command(&world, &mut state, order), Ok(()));
+        }
+        assert_eq!(
+            apply_command(&world, &mut state, &Command::EndTurn { player: 0 }),
+            Err(CommandError::NotPlannable)
+        );
+        simulate_frame(&mut world, &mut state, 0.0);
+        // Nothing happens before the round is resolved.
+        assert_eq!(
+            get_hexagon_of_entity(&world, fast),
+            Some(Hexagon::new_axial(0, 0))
+        );
+
+        let resolve = Command::ResolveRound { player: 1 };
+        assert_eq!(apply_command(&world, &mut state, &resolve), Ok(()));
+        assert_eq!(state.state, State::Resolving);
+        let events = simulate_frame(&mut world, &mut state, 0.0);
+
+        assert_eq!(get_hexagon_of_entity(&world, fast), Some(contested));
+        assert_eq!(
+            get_hexagon_of_entity(&world, slow),
+            Some(Hexagon::new_axial(2, 0))
+        );
+        assert!(events.contains(&GameEvent::OrderInterrupted {
+            entity: slow,
+            position: Hexagon::new_axial(2, 0),
+        }));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            GameEvent::UnitAttacked { attacker, defender, .. }
+                if *attacker == slow && *defender == fast
+        )));
+        assert_eq!(
+            events.last(),
+            Some(&GameEvent::PlanningStarted { round: 2 })
+        );
+        assert_eq!(state.state, State::Planning);
+        assert_eq!(
+            apply_command(&world, &mut GameState::new(), &resolve),
+            Err(CommandError::NotReady)
+        );
+    }
+
+    #[test]
+    fn scripted_tutorial_only_accepts_the_expected_actions() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.add_player(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.add_player(Player::new(
+            "Player 2".to_owned(),
+            Color::rgb(1.0, 0.0, 0.0),
+        ));
+        state.current_player = Some(0);
+        let tank = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::new_axial(0, 0),
+            Unit::new(20, 10, 1, 1, 1, 3, 3, 1),
+            None,
+        );
+        let scout = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            1,
+            Hexagon::new_axial(3, 0),
+            Unit::new(30, 2, 1, 1, 0, 2, 2, 1),
+            None,
+        );
+        let (tank_id, scout_id) = (
+            PersistentId::of_entity(&world, tank).unwrap(),
+            PersistentId::of_entity(&world, scout).unwrap(),
+        );
+        simulate_frame(&mut world, &mut state, 0.0);
+        let select = |unit| Command::Select {
+            player: 0,
+            unit: Some(unit),
+        };
+        let move_along = |path: Vec<Hexagon>| Command::Move {
+            player: 0,
+            unit: tank_id,
+            path,
+        };
+
+        // Step 1: select the tank.
+        state
+            .tutorial
+            .set_constraint(Some(TutorialConstraint::Select(Hexagon::new_axial(0, 0))));
+        for blocked in &[
+            select(scout_id),
+            move_along(vec![Hexagon::new_axial(1, 0)]),
+            Command::EndTurn { player: 0 },
+        ] {
+            assert_eq!(
+                apply_local_command(&world, &mut state, blocked),
+                Err(CommandError::TutorialBlocked)
+            );
+        }
+        assert_eq!(
+            apply_local_command(&world, &mut state, &select(tank_id)),
+            Ok(())
+        );
+        simulate_frame(&mut world, &mut state, 0.0);
+        assert_eq!(
+            state.tutorial.take_completed(),
+            vec![TutorialConstraint::Select(Hexagon::new_axial(0, 0))]
+        );
+        assert_eq!(state.tutorial.constraint(), None);
+
+        // Step 2: move next to the scout. The step is only done once the move resolved.
+        let destination = Hexagon::new_axial(2, 0);
+        state
+            .tutorial
+            .set_constraint(Some(TutorialConstraint::MoveTo(destination)));
+        assert_eq!(
+            apply_local_command(
+                &world,
+                &mut state,
+                &move_along(vec![Hexagon::new_axial(1, 0)])
+            ),
+            Err(CommandError::TutorialBlocked)
+        );
+        assert_eq!(
+            apply_local_command(
+                &world,
+                &mut state,
+                &move_along(vec![Hexagon::new_axial(1, 0), destination])
+            ),
+            Ok(())
+        );
+        simulate_frame(&mut world, &mut state, 0.0);
+        assert!(state.tutorial.take_completed().is_empty());
+        run_until_idle(&mut world, &mut state);
+        assert_eq!(get_hexagon_of_entity(&world, tank), Some(destination));
+        assert_eq!(
+            state.tutorial.take_completed(),
+            vec![TutorialConstraint::MoveTo(destination)]
+        );
+
+        // Step 3: attack the scout. Selecting the attacker is fine on the way.
+        let target = Hexagon::new_axial(3, 0);
+        state
+            .tutorial
+            .set_constraint(Some(TutorialConstraint::Attack(target)));
+        assert_eq!(
+            apply_local_command(&world, &mut state, &Command::EndTurn { player: 0 }),
+            Err(CommandError::TutorialBlocked)
+        );
+        assert_eq!(
+            apply_local_command(&world, &mut state, &select(tank_id)),
+            Ok(())
+        );
+        let attack = Command::Attack {
+            player: 0,
+            attacker: tank_id,
+            defender: scout_id,
+        };
+        assert_eq!(apply_local_command(&world, &mut state, &attack), Ok(()));
+        run_until_idle(&mut world, &mut state);
+        assert_eq!(
+            state.tutorial.take_completed(),
+            vec![TutorialConstraint::Attack(target)]
+        );
+        assert_eq!(state.tutorial.constraint(), None);
+    }
+
+    #[test]
+    fn pickups_are_collected_along_a_move() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.players.push(Player::new(
+            "Player 1".to_owned(),
+            Color::rgb(0.0, 0.0, 1.0),
+        ));
+        state.current_player = Some(0);
+        let tank = spawn_unit(
+            &mut world,
+            &mut state.persistent_ids,
+            0,
+            Hexagon::zero(),
+            Unit::new(10, 1, 1, 1, 0, 3, 3, 1),
+            None,
+        );
+        spawn_pickup(
+            &mut world,
+            Hexagon::new_axial(1, 0),
+            Pickup::Resources(10),
+            false,
+        );
+        spawn_pickup(
+            &mut world,
+            Hexagon::new_axial(2, 0),
+            Pickup::Repair(5),
+            false,
+        );
+        let beyond = Hexagon::new_axial(4, 0);
+        spawn_pickup(&mut world, beyond, Pickup::AbilityCharge, false);
+
+        let path = find_path(
+            &Hexagon::zero(),
+            &Hexagon::new_axial(3, 0),
+            &state.edges,
+            &state.map_bounds,
+            &world,
+        );
+        assert_eq!(path.len(), 3);
+        force_move(&mut state, tank, VecDeque::from(path));
+        let mut collected = Vec::new();
+        for _ in 0..100 {
+            for event in simulate_frame(&mut world, &mut state, 0.05) {
+                if let GameEvent::PickupCollected { pickup, .. } = event {
+                    collected.push(pickup);
+                }
+            }
+            if let State::Selected(_) = state.state {
+                break;
+            }
+        }
+
+        assert_eq!(collected, vec![Pickup::Resources(10), Pickup::Repair(5)]);
+        assert_eq!(state.players[0].get_resources(), 10);
+        let entry = world.entry_ref(tank).unwrap();
+        assert_eq!(entry.get_component::<Unit>().unwrap().integrity, 15);
+        assert_eq!(pickup_at(&world, &Hexagon::new_axial(1, 0)), None);
+        assert!(pickup_at(&world, &beyond).is_some());
+    }
+
+    #[test]
+    fn berserker_attacks_more_than_turtle() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        spawn_grid(&mut world, 4);
+        for (name, profile) in [("Berserker", "berserker"), ("Turtle", "turtle")].iter() {
+            let mut player = Player::new((*name).to_owned(), Color::rgb(1.0, 1.0, 1.0));
+            player.set_ai_profile(AiProfile::preset(profile));
+            state.add_player(player);
+        }
+        state.current_player = Some(0);
+        state.combat_log.set_capacity(10_000);
+        // Mirrored through the centre, so neither side starts closer to the other.
+        for (player, q, r) in [(0, -3, 0), (0, -3, 1), (1, 3, 0), (1, 3, -1)].iter() {
+            spawn_unit(
+                &mut world,
+                &mut state.persistent_ids,
+                *player,
+                Hexagon::new_axial(*q, *r),
+                Unit::new(20, 5, 1, 1, 0, 2, 2, 1),
+                None,
+            );
+        }
+        simulate_frame(&mut world, &mut state, 0.0);
+
+        const ROUNDS: u32 = 6;
+        let mut commands = 0;
+        while state.round <= ROUNDS {
+            let command = match next_command(&world, &state) {
+                None => break,
+                Some(command) => command,
+            };
+            apply_command(&world, &mut state, &command).unwrap();
+            run_until_idle(&mut world, &mut state);
+            commands += 1;
+            assert!(
+                commands < 1000,
+                "The computer players never end their turns"
+            );
+        }
+
+        let attacks = |player| {
+            state
+                .combat_log
+                .latest(state.combat_log.len())
+                .filter(|entry| entry.kind == LogEntryKind::Attack && entry.player == Some(player))
+                .count()
+        };
+        assert!(
+            attacks(0) > attacks(1),
+            "berserker attacked {} times, turtle {} times",
+            attacks(0),
+            attacks(1)
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Sound cues for the scripts. Game events are translated into named cues with a position, so
+//! sounds can be chosen and placed in the scenes. A new cue only needs a mapping in
+//! `cues_for_event`.
+
+use crate::components::hexagon::Hexagon;
+use crate::state_machine::{GameEvent, SECONDS_PER_MOVEMENT};
+use legion::Entity;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoundCue {
+    pub event: &'static str,
+    /// Where the sound happens, `None` for sounds without a place like a turn change.
+    pub position: Option<Hexagon>,
+    pub player: Option<usize>,
+    /// How strong the sound is, like the damage of a hit.
+    pub magnitude: f32,
+}
+
+impl SoundCue {
+    fn new(
+        event: &'static str,
+        position: Option<Hexagon>,
+        player: Option<usize>,
+        magnitude: f32,
+    ) -> Self {
+        SoundCue {
+            event,
+            position,
+            player,
+            magnitude,
+        }
+    }
+}
+
+/// The cues for `event`. Moves are made by the current player, so steps are attributed to them.
+pub fn cues_for_event(event: &GameEvent, current_player: Option<usize>) -> Vec<SoundCue> {
+    match *event {
+        GameEvent::TurnStarted { player, .. } => {
+            vec![SoundCue::new("turn_changed", None, Some(player), 1.0)]
+        }
+        GameEvent::PlanningStarted { .. } => {
+            vec![SoundCue::new("turn_changed", None, None, 1.0)]
+        }
+        GameEvent::UnitMoved { to, .. } => {
+            vec![SoundCue::new("unit_step", Some(to), current_player, 1.0)]
+        }
+        GameEvent::UnitAttacked {
+            attacker_player,
+            defender_player,
+            attacker_position,
+            defender_position,
+            damage,
+            ..
+        } => vec![
+            SoundCue::new(
+                "attack_fired",
+                Some(attacker_position),
+                attacker_player,
+                damage as f32,
+            ),
+            SoundCue::new(
+                "attack_impact",
+                Some(defender_position),
+                defender_player,
+                damage as f32,
+            ),
+        ],
+        GameEvent::UnitDestroyed { position, .. } => {
+            vec![SoundCue::new("unit_destroyed", Some(position), None, 1.0)]
+        }
+        GameEvent::AbilityUsed { kind, target, .. } => {
+            vec![SoundCue::new(
+                kind.name(),
+                Some(target),
+                current_player,
+                1.0,
+            )]
+        }
+        GameEvent::OverwatchChanged {
+            position,
+            active: true,
+            ..
+        } => vec![SoundCue::new(
+            "overwatch",
+            Some(position),
+            current_player,
+            1.0,
+        )],
+        GameEvent::PickupCollected {
+            player, position, ..
+        } => vec![SoundCue::new(
+            "pickup_collected",
+            Some(position),
+            player,
+            1.0,
+        )],
+        GameEvent::BridgeAttacked {
+            attacker_position,
+            from,
+            damage,
+            ..
+        } => vec![
+            SoundCue::new(
+                "attack_fired",
+                Some(attacker_position),
+                current_player,
+                damage as f32,
+            ),
+            SoundCue::new("attack_impact", Some(from), None, damage as f32),
+        ],
+        GameEvent::BridgeDestroyed { from, .. } => {
+            vec![SoundCue::new("bridge_destroyed", Some(from), None, 1.0)]
+        }
+        GameEvent::BuildingAttacked {
+            attacker_position,
+            position,
+            damage,
+            ..
+        } => vec![
+            SoundCue::new(
+                "attack_fired",
+                Some(attacker_position),
+                current_player,
+                damage as f32,
+            ),
+            SoundCue::new("attack_impact", Some(position), None, damage as f32),
+        ],
+        GameEvent::FireDamage {
+            position, damage, ..
+        } => vec![SoundCue::new(
+            "fire_damage",
+            Some(position),
+            None,
+            damage as f32,
+        )],
+        GameEvent::PromotionAvailable {
+            player, position, ..
+        } => vec![SoundCue::new("promotion", Some(position), player, 1.0)],
+        GameEvent::BuildingDestroyed { position } => {
+            vec![SoundCue::new(
+                "building_destroyed",
+                Some(position),
+                None,
+                1.0,
+            )]
+        }
+        GameEvent::AttackFailed { .. }
+        | GameEvent::UnitTurnStarted { .. }
+        | GameEvent::OverwatchChanged { .. }
+        | GameEvent::GarrisonChanged { .. }
+        | GameEvent::FacingChanged { .. }
+        | GameEvent::OrderInterrupted { .. }
+        | GameEvent::CombatFeedback { .. }
+        | GameEvent::RandomEvent { .. }
+        | GameEvent::GameOver { .. }
+        | GameEvent::Error(_) => Vec::new(),
+    }
+}
+
+/// Translates game events into cues. A unit plays at most one step per `SECONDS_PER_MOVEMENT`,
+/// so a slow frame that moves it several hexagons does not stack the steps.
+#[derive(Clone, Debug, Default)]
+pub struct SoundCues {
+    last_step: HashMap<Entity, f64>,
+}
+
+impl SoundCues {
+    pub fn translate(
+        &mut self,
+        event: &GameEvent,
+        current_player: Option<usize>,
+        time: f64,
+    ) -> Vec<SoundCue> {
+        match *event {
+            GameEvent::UnitMoved { entity, .. } => match self.last_step.get(&entity) {
+                Some(last) if time - last < SECONDS_PER_MOVEMENT => return Vec::new(),
+                _ => {
+                    self.last_step.insert(entity, time);
+                }
+            },
+            GameEvent::UnitDestroyed { entity, .. } => {
+                self.last_step.remove(&entity);
+            }
+            _ => {}
+        }
+        cues_for_event(event, current_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flanking::AttackArc;
+    use legion::World;
+
+    fn moved(entity: Entity, q: i32) -> GameEvent {
+        GameEvent::UnitMoved {
+            entity,
+            from: Hexagon::new_axial(q - 1, 0),
+            to: Hexagon::new_axial(q, 0),
+        }
+    }
+
+    #[test]
+    fn events_map_to_cues() {
+        let mut world = World::default();
+        let unit = world.push((Hexagon::zero(),));
+        let attack = GameEvent::UnitAttacked {
+            attacker: unit,
+            defender: unit,
+            attacker_player: Some(0),
+            defender_player: Some(1),
+            attacker_position: Hexagon::new_axial(0, 0),
+            defender_position: Hexagon::new_axial(2, 0),
+            arc: AttackArc::Front,
+            damage: 4,
+            remaining_integrity: 3,
+        };
+
+        assert_eq!(
+            cues_for_event(&attack, Some(0)),
+            vec![
+                SoundCue::new("attack_fired", Some(Hexagon::new_axial(0, 0)), Some(0), 4.0),
+                SoundCue::new(
+                    "attack_impact",
+                    Some(Hexagon::new_axial(2, 0)),
+                    Some(1),
+                    4.0
+                ),
+            ]
+        );
+        assert_eq!(
+            cues_for_event(&moved(unit, 1), Some(1)),
+            vec![SoundCue::new(
+                "unit_step",
+                Some(Hexagon::new_axial(1, 0)),
+                Some(1),
+                1.0
+            )]
+        );
+        assert_eq!(
+            cues_for_event(
+                &GameEvent::TurnStarted {
+                    round: 2,
+                    player: 1
+                },
+                Some(0)
+            )[0]
+            .event,
+            "turn_changed"
+        );
+        assert!(cues_for_event(&GameEvent::GameOver { winner: None }, None).is_empty());
+    }
+
+    #[test]
+    fn steps_are_rate_limited_per_unit() {
+        let mut world = World::default();
+        let first = world.push((Hexagon::zero(),));
+        let second = world.push((Hexagon::zero(),));
+        let mut cues = SoundCues::default();
+
+        assert_eq!(cues.translate(&moved(first, 1), Some(0), 1.0).len(), 1);
+        // A slow frame moved the unit twice.
+        assert!(cues.translate(&moved(first, 2), Some(0), 1.0).is_empty());
+        assert_eq!(cues.translate(&moved(second, 1), Some(0), 1.0).len(), 1);
+        assert_eq!(
+            cues.translate(&moved(first, 3), Some(0), 1.0 + SECONDS_PER_MOVEMENT)
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,848 @@
+use crate::components::demolition::Demolition;
+use crate::components::facing::Rotatable;
+use crate::components::field::Field;
+use crate::components::hexagon::Hexagon;
+use crate::components::history::History;
+use crate::components::initiative::Initiative;
+use crate::components::movement_type::MovementType;
+use crate::components::node_template::{NodeTemplate, UNIT_CONTAINER, UNIT_GROUP};
+use crate::components::pending_spawn::PendingSpawn;
+use crate::components::persistent_id::{PersistentId, PersistentIds};
+use crate::components::player::Player as PlayerComponent;
+use crate::components::selection_indicator::SelectionIndicator;
+use crate::components::unit::Unit;
+use crate::components::unit_nodes::UnitNodes;
+use crate::components::unit_type::UnitType;
+use crate::handicap::Handicap;
+use crate::profiler;
+use crate::systems::hexgrid::create_grid;
+use crate::unit_catalog::UnitCatalog;
+use legion::storage::IntoComponentSource;
+use legion::{Entity, EntityStore, IntoQuery, World};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Spawns a unit for the given player. Units without a template never get a Godot node, which
+/// is what headless simulations use. Units with a node also track their selection outline. Every
+/// unit gets a new `PersistentId` from `ids`.
+pub fn spawn_unit(
+    world: &mut World,
+    ids: &mut PersistentIds,
+    player: usize,
+    hexagon: Hexagon,
+    unit: Unit,
+    template: Option<NodeTemplate>,
+) -> Entity {
+    let id = ids.allocate();
+    let entity = match template {
+        None => world.push((
+            id,
+            PlayerComponent(player),
+            hexagon,
+            unit,
+            History::default(),
+        )),
+        Some(template) => world.push((
+            id,
+            PlayerComponent(player),
+            hexagon,
+            template,
+            unit,
+            History::default(),
+            SelectionIndicator::default(),
+            UnitNodes::default(),
+        )),
+    };
+    ids.register(id, entity);
+    entity
+}
+
+/// Spawns a fresh unit of the catalog entry `unit_type`, with a Godot node if `with_node` is set.
+/// The unit waits for the spawn modifiers. Returns `None` if the catalog has no such entry.
+pub fn spawn_unit_of_type(
+    world: &mut World,
+    ids: &mut PersistentIds,
+    catalog: &UnitCatalog,
+    player: usize,
+    hexagon: Hexagon,
+    unit_type: &str,
+    with_node: bool,
+) -> Option<Entity> {
+    let definition = catalog.get(unit_type)?;
+    let template = if with_node {
+        Some(definition.template())
+    } else {
+        None
+    };
+    let entity = spawn_unit(
+        world,
+        ids,
+        player,
+        hexagon,
+        definition.create_unit(),
+        template,
+    );
+    let mut entry = world.entry(entity)?;
+    entry.add_component(UnitType(definition.name.clone()));
+    entry.add_component(Initiative(definition.initiative));
+    entry.add_component(definition.movement());
+    entry.add_component(PendingSpawn);
+    if definition.demolition {
+        entry.add_component(Demolition);
+    }
+    if definition.rotatable {
+        entry.add_component(Rotatable);
+    }
+    if !definition.abilities.is_empty() {
+        entry.add_component(definition.create_abilities());
+    }
+    if let Some(paths) = &definition.node_paths {
+        entry.add_component(paths.clone());
+    }
+    Some(entity)
+}
+
+/// Where the stats of a unit spawned with `spawn_units` come from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnitSource {
+    /// A fresh unit of the catalog entry.
+    Type(String),
+    /// The stats themselves. Such units have no type, so the spawn modifiers leave them alone.
+    Stats(Unit),
+}
+
+/// A unit to spawn with `spawn_units`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitSpawn {
+    pub player: usize,
+    pub position: Hexagon,
+    pub source: UnitSource,
+    /// Replaces the scene of the unit type. Units given by their stats only get a node with one.
+    pub scene: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpawnError {
+    UnknownUnitType(String),
+    /// There is no field at the position.
+    OffMap(Hexagon),
+    /// A unit stands at the position already, or an earlier entry goes there.
+    Occupied(Hexagon),
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::UnknownUnitType(name) => write!(f, "Unknown unit type '{}'", name),
+            SpawnError::OffMap(hexagon) => write!(f, "There is no field at {}", hexagon),
+            SpawnError::Occupied(hexagon) => write!(f, "There is a unit at {} already", hexagon),
+        }
+    }
+}
+
+/// What `spawn_units` did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BulkSpawn {
+    /// The entity or the error of every entry, in the order of the entries.
+    pub results: Vec<Result<Entity, SpawnError>>,
+    /// Number of `World::extend` calls, at most one per combination of components.
+    pub extend_calls: usize,
+}
+
+type TypedUnit = (
+    PersistentId,
+    PlayerComponent,
+    Hexagon,
+    Unit,
+    History,
+    UnitType,
+    Initiative,
+    MovementType,
+    PendingSpawn,
+);
+type TypedUnitWithNode = (
+    PersistentId,
+    PlayerComponent,
+    Hexagon,
+    Unit,
+    History,
+    UnitType,
+    Initiative,
+    MovementType,
+    PendingSpawn,
+    NodeTemplate,
+    SelectionIndicator,
+    UnitNodes,
+);
+type PlainUnit = (PersistentId, PlayerComponent, Hexagon, Unit, History);
+type PlainUnitWithNode = (
+    PersistentId,
+    PlayerComponent,
+    Hexagon,
+    Unit,
+    History,
+    NodeTemplate,
+    SelectionIndicator,
+    UnitNodes,
+);
+
+/// Units with the same components, with the entries they were made from.
+#[derive(Debug)]
+struct Batch<C> {
+    entries: Vec<usize>,
+    ids: Vec<PersistentId>,
+    components: Vec<C>,
+}
+
+impl<C> Default for Batch<C> {
+    fn default() -> Self {
+        Batch {
+            entries: Vec::new(),
+            ids: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+}
+
+impl<C> Batch<C>
+where
+    Vec<C>: IntoComponentSource,
+{
+    fn push(&mut self, entry: usize, id: PersistentId, components: C) {
+        self.entries.push(entry);
+        self.ids.push(id);
+        self.components.push(components);
+    }
+
+    fn extend(
+        self,
+        world: &mut World,
+        ids: &mut PersistentIds,
+        entities: &mut [Option<Entity>],
+        extend_calls: &mut usize,
+    ) {
+        if self.components.is_empty() {
+            return;
+        }
+        *extend_calls += 1;
+        let spawned = world.extend(self.components).to_vec();
+        for ((entry, id), entity) in self.entries.into_iter().zip(self.ids).zip(spawned) {
+            ids.register(id, entity);
+            entities[entry] = Some(entity);
+        }
+    }
+}
+
+/// The template for units given by their stats, like that of a unit type without its looks.
+fn plain_template(scene: &str) -> NodeTemplate {
+    NodeTemplate::new(scene)
+        .z_index(1)
+        .visible(false)
+        .group(UNIT_GROUP)
+        .parent(UNIT_CONTAINER)
+        .y_sort()
+}
+
+/// Checks the entries against the map, the units in the world and each other.
+pub fn validate_spawns<S: EntityStore>(
+    world: &S,
+    catalog: &UnitCatalog,
+    spawns: &[UnitSpawn],
+) -> Vec<Result<(), SpawnError>> {
+    let fields: HashSet<Hexagon> = <&Field>::query()
+        .iter(world)
+        .map(|field| field.location)
+        .collect();
+    let mut occupied: HashSet<Hexagon> = <(&Hexagon, &Unit)>::query()
+        .iter(world)
+        .map(|(hexagon, _)| *hexagon)
+        .collect();
+    spawns
+        .iter()
+        .map(|spawn| {
+            if let UnitSource::Type(name) = &spawn.source {
+                if catalog.get(name).is_none() {
+                    return Err(SpawnError::UnknownUnitType(name.clone()));
+                }
+            }
+            if !fields.contains(&spawn.position) {
+                return Err(SpawnError::OffMap(spawn.position));
+            }
+            if !occupied.insert(spawn.position) {
+                return Err(SpawnError::Occupied(spawn.position));
+            }
+            Ok(())
+        })
+        .collect()
+}
+
+/// Spawns the entries `validate_spawns` accepts and skips the others. Instead of moving every
+/// unit through the archetypes of its components one by one, the units are added with one
+/// `World::extend` per combination of components. Only demolition, rotation and abilities are
+/// added to the units that have them afterwards. The units get their `PersistentId`s in the order of the
+/// entries, and the integrity bonus of the handicap of their player.
+pub fn spawn_units(
+    world: &mut World,
+    ids: &mut PersistentIds,
+    catalog: &UnitCatalog,
+    handicaps: &[Handicap],
+    spawns: &[UnitSpawn],
+    with_nodes: bool,
+) -> BulkSpawn {
+    let _timer = profiler::scope("spawn_units");
+    let validation = validate_spawns(world, catalog, spawns);
+    let mut typed = Batch::<TypedUnit>::default();
+    let mut typed_with_node = Batch::<TypedUnitWithNode>::default();
+    let mut plain = Batch::<PlainUnit>::default();
+    let mut plain_with_node = Batch::<PlainUnitWithNode>::default();
+    let mut extras = Vec::new();
+    for (entry, spawn) in spawns.iter().enumerate() {
+        if validation[entry].is_err() {
+            continue;
+        }
+        let player = PlayerComponent(spawn.player);
+        let handicap = handicaps.get(spawn.player).copied().unwrap_or_default();
+        match &spawn.source {
+            UnitSource::Type(name) => {
+                let definition = match catalog.get(name) {
+                    None => continue,
+                    Some(definition) => definition,
+                };
+                let mut unit = definition.create_unit();
+                unit.integrity = handicap.bonus_integrity(unit.integrity);
+                let id = ids.allocate();
+                if with_nodes {
+                    let mut template = definition.template();
+                    if let Some(scene) = &spawn.scene {
+                        template.scene_file = scene.clone();
+                    }
+                    typed_with_node.push(
+                        entry,
+                        id,
+                        (
+                            id,
+                            player,
+                            spawn.position,
+                            unit,
+                            History::default(),
+                            UnitType(definition.name.clone()),
+                            Initiative(definition.initiative),
+                            definition.movement(),
+                            PendingSpawn,
+                            template,
+                            SelectionIndicator::default(),
+                            UnitNodes::default(),
+                        ),
+                    );
+                } else {
+                    typed.push(
+                        entry,
+                        id,
+                        (
+                            id,
+                            player,
+                            spawn.position,
+                            unit,
+                            History::default(),
+                            UnitType(definition.name.clone()),
+                            Initiative(definition.initiative),
+                            definition.movement(),
+                            PendingSpawn,
+                        ),
+                    );
+                }
+                if definition.demolition
+                    || definition.rotatable
+                    || !definition.abilities.is_empty()
+                    || definition.node_paths.is_some()
+                {
+                    extras.push((entry, definition));
+                }
+            }
+            UnitSource::Stats(unit) => {
+                let mut unit = *unit;
+                unit.integrity = handicap.bonus_integrity(unit.integrity);
+                let id = ids.allocate();
+                match spawn.scene.as_deref().filter(|_| with_nodes) {
+                    None => plain.push(
+                        entry,
+                        id,
+                        (id, player, spawn.position, unit, History::default()),
+                    ),
+                    Some(scene) => plain_with_node.push(
+                        entry,
+                        id,
+                        (
+                            id,
+                            player,
+                            spawn.position,
+                            unit,
+                            History::default(),
+                            plain_template(scene),
+                            SelectionIndicator::default(),
+                            UnitNodes::default(),
+                        ),
+                    ),
+                }
+            }
+        }
+    }
+
+    let mut entities = vec![None; spawns.len()];
+    let mut extend_calls = 0;
+    typed.extend(world, ids, &mut entities, &mut extend_calls);
+    typed_with_node.extend(world, ids, &mut entities, &mut extend_calls);
+    plain.extend(world, ids, &mut entities, &mut extend_calls);
+    plain_with_node.extend(world, ids, &mut entities, &mut extend_calls);
+    for (entry, definition) in extras {
+        if let Some(mut unit) = entities[entry].and_then(|entity| world.entry(entity)) {
+            if definition.demolition {
+                unit.add_component(Demolition);
+            }
+            if definition.rotatable {
+                unit.add_component(Rotatable);
+            }
+            if !definition.abilities.is_empty() {
+                unit.add_component(definition.create_abilities());
+            }
+            if let Some(paths) = &definition.node_paths {
+                unit.add_component(paths.clone());
+            }
+        }
+    }
+    BulkSpawn {
+        results: validation
+            .into_iter()
+            .zip(entities)
+            .map(|(valid, entity)| valid.map(|_| entity.expect("Valid entries are spawned")))
+            .collect(),
+        extend_calls,
+    }
+}
+
+/// Radius of the map generated for a new game.
+pub const MAP_RADIUS: u32 = 128;
+
+pub fn spawn_grid(world: &mut World, radius: u32) {
+    for field in create_grid(radius) {
+        world.extend(vec![(Field::new(field),)]);
+    }
+}
+
+/// Removes the fields closer than `size` to `centre` for a lake, so a size of 0 carves nothing and
+/// 1 only the centre. Hexagons with a unit stay as the shore.
+pub fn carve_lake(world: &mut World, centre: &Hexagon, size: u32) {
+    let occupied: HashSet<Hexagon> = <(&Hexagon, &Unit)>::query()
+        .iter(world)
+        .map(|(position, _)| *position)
+        .collect();
+    let lake: Vec<Entity> = <(Entity, &Field)>::query()
+        .iter(world)
+        .filter(|(_, field)| {
+            field.location.distance_to(centre) < size as i32 && !occupied.contains(&field.location)
+        })
+        .map(|(entity, _)| *entity)
+        .collect();
+    for entity in lake {
+        world.remove(entity);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GridResizeError {
+    /// Units stand on these hexagons, outside of the new radius.
+    OrphanedUnits(Vec<Hexagon>),
+}
+
+/// Grows or shrinks the map around the centre to `radius`. Fields are added to fill the ring
+/// beyond the old edge, so holes in the map stay. Fields and everything else standing outside of
+/// the radius are removed, together with their nodes. Units are never removed, the map is left as
+/// it is if one stands outside.
+pub fn resize_grid(world: &mut World, radius: u32) -> Result<(), GridResizeError> {
+    let outside = |hexagon: &Hexagon| hexagon.distance_to(&Hexagon::zero()) > radius as i32;
+    let mut orphaned: Vec<Hexagon> = <(&Hexagon, &Unit)>::query()
+        .iter(world)
+        .map(|(position, _)| *position)
+        .filter(|position| outside(position))
+        .collect();
+    if !orphaned.is_empty() {
+        orphaned.sort_by_key(|hexagon| (hexagon.get_q(), hexagon.get_r()));
+        return Err(GridResizeError::OrphanedUnits(orphaned));
+    }
+
+    let removed: Vec<Entity> = <(Entity, &Field)>::query()
+        .iter(world)
+        .filter(|(_, field)| outside(&field.location))
+        .map(|(entity, _)| *entity)
+        .chain(
+            <(Entity, &Hexagon)>::query()
+                .iter(world)
+                .filter(|(_, position)| outside(position))
+                .map(|(entity, _)| *entity),
+        )
+        .collect();
+    for entity in removed {
+        world.remove(entity);
+    }
+    let existing: HashSet<Hexagon> = <&Field>::query()
+        .iter(world)
+        .map(|field| field.location)
+        .collect();
+    let edge = existing
+        .iter()
+        .map(|hexagon| hexagon.distance_to(&Hexagon::zero()))
+        .max();
+    let added: Vec<(Field,)> = create_grid(radius)
+        .into_iter()
+        .filter(|hexagon| {
+            !existing.contains(hexagon)
+                && edge.map_or(true, |edge| hexagon.distance_to(&Hexagon::zero()) > edge)
+        })
+        .map(|hexagon| (Field::new(hexagon),))
+        .collect();
+    world.extend(added);
+    Ok(())
+}
+
+/// Replaces the fields of the map with fresh ones on `hexagons`, which get nodes from `template`
+/// if it is set. Fields that stay keep their terrain, the old fields are removed together with
+/// their nodes, as is everything else standing outside of the new map. Units are never removed,
+/// the map is left as it is if one stands outside.
+pub fn replace_grid(
+    world: &mut World,
+    hexagons: &[Hexagon],
+    template: Option<&NodeTemplate>,
+) -> Result<(), GridResizeError> {
+    let inside: HashSet<Hexagon> = hexagons.iter().copied().collect();
+    let mut orphaned: Vec<Hexagon> = <(&Hexagon, &Unit)>::query()
+        .iter(world)
+        .map(|(position, _)| *position)
+        .filter(|position| !inside.contains(position))
+        .collect();
+    if !orphaned.is_empty() {
+        orphaned.sort_by_key(|hexagon| (hexagon.get_q(), hexagon.get_r()));
+        orphaned.dedup();
+        return Err(GridResizeError::OrphanedUnits(orphaned));
+    }
+
+    let old: HashMap<Hexagon, Field> = <&Field>::query()
+        .iter(world)
+        .map(|field| (field.location, *field))
+        .collect();
+    let removed: Vec<Entity> = <(Entity, &Field)>::query()
+        .iter(world)
+        .map(|(entity, _)| *entity)
+        .chain(
+            <(Entity, &Hexagon)>::query()
+                .iter(world)
+                .filter(|(_, position)| !inside.contains(position))
+                .map(|(entity, _)| *entity),
+        )
+        .collect();
+    for entity in removed {
+        world.remove(entity);
+    }
+    let mut spawned = HashSet::new();
+    for hexagon in hexagons.iter().filter(|hexagon| spawned.insert(**hexagon)) {
+        let field = old
+            .get(hexagon)
+            .copied()
+            .unwrap_or_else(|| Field::new(*hexagon));
+        match template {
+            Some(template) => world.push((field, template.clone())),
+            None => world.push((field,)),
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::abilities::Abilities;
+    use crate::components::terrain::Terrain;
+    use crate::game_state::GameState;
+    use crate::systems::hexgrid::create_rect_grid;
+
+    fn spawn(player: usize, q: i32, r: i32, unit_type: &str) -> UnitSpawn {
+        UnitSpawn {
+            player,
+            position: Hexagon::new_axial(q, r),
+            source: UnitSource::Type(unit_type.to_owned()),
+            scene: None,
+        }
+    }
+
+    fn unit_count(world: &World) -> usize {
+        <&Unit>::query().iter(world).count()
+    }
+
+    fn field_count(world: &World) -> usize {
+        <&Field>::query().iter(world).count()
+    }
+
+    #[test]
+    fn grids_grow_and_shrink_around_the_units() {
+        let mut world = World::default();
+        spawn_grid(&mut world, 3);
+        let unit = world.push((Hexagon::new_axial(2, 0), Unit::new(5, 1, 1, 1, 0, 1, 1, 1)));
+        let pickup_far_out = world.push((Hexagon::new_axial(4, -1),));
+
+        assert_eq!(resize_grid(&mut world, 4), Ok(()));
+        assert_eq!(field_count(&world), create_grid(4).len());
+        assert!(world.entry_ref(pickup_far_out).is_ok());
+
+        assert_eq!(resize_grid(&mut world, 2), Ok(()));
+        assert_eq!(field_count(&world), create_grid(2).len());
+        assert!(world.entry_ref(pickup_far_out).is_err());
+        assert!(world.entry_ref(unit).is_ok());
+
+        assert_eq!(
+            resize_grid(&mut world, 1),
+            Err(GridResizeError::OrphanedUnits(vec![Hexagon::new_axial(
+                2, 0
+            )]))
+        );
+        // The refused resize leaves the map alone.
+        assert_eq!(field_count(&world), create_grid(2).len());
+    }
+
+    #[test]
+    fn lakes_spare_the_units_and_survive_resizes() {
+        let mut world = World::default();
+        spawn_grid(&mut world, 3);
+        world.push((Hexagon::new_axial(0, 1), Unit::new(5, 1, 1, 1, 0, 1, 1, 1)));
+
+        carve_lake(&mut world, &Hexagon::zero(), 0);
+        assert_eq!(field_count(&world), create_grid(3).len());
+        carve_lake(&mut world, &Hexagon::zero(), 2);
+        assert_eq!(field_count(&world), create_grid(3).len() - 6);
+
+        assert_eq!(resize_grid(&mut world, 4), Ok(()));
+        assert_eq!(field_count(&world), create_grid(4).len() - 6);
+        assert!(!<&Field>::query()
+            .iter(&world)
+            .any(|field| field.location == Hexagon::zero()));
+    }
+
+    #[test]
+    fn grids_are_replaced_in_place() {
+        let mut world = World::default();
+        spawn_grid(&mut world, 3);
+        let forest = Hexagon::new_axial(1, 0);
+        for field in <&mut Field>::query().iter_mut(&mut world) {
+            if field.location == forest {
+                field.terrain = Terrain::Forest;
+            }
+        }
+        let unit = world.push((forest, Unit::new(5, 1, 1, 1, 0, 1, 1, 1)));
+        let pickup_far_out = world.push((Hexagon::new_axial(0, 3),));
+        let template = NodeTemplate::new("res://HexField.tscn").scale(0.5, 0.5);
+
+        let rectangle = create_rect_grid(6, 4);
+        assert_eq!(rectangle.len(), 24);
+        assert_eq!(
+            replace_grid(&mut world, &rectangle, Some(&template)),
+            Ok(())
+        );
+        assert_eq!(field_count(&world), 24);
+        assert_eq!(<(&Field, &NodeTemplate)>::query().iter(&world).count(), 24);
+        let terrain = <&Field>::query()
+            .iter(&world)
+            .find(|field| field.location == forest)
+            .map(|field| field.terrain);
+        assert_eq!(terrain, Some(Terrain::Forest));
+        assert!(world.entry_ref(unit).is_ok());
+        assert!(world.entry_ref(pickup_far_out).is_err());
+
+        // A second call replaces the fields again, without nodes this time.
+        assert_eq!(replace_grid(&mut world, &create_grid(2), None), Ok(()));
+        assert_eq!(field_count(&world), create_grid(2).len());
+        assert_eq!(<(&Field, &NodeTemplate)>::query().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn units_outside_a_replaced_grid_are_listed() {
+        let mut world = World::default();
+        spawn_grid(&mut world, 3);
+        for (q, r) in &[(3, 0), (0, 0), (-3, 1), (3, 0)] {
+            world.push((
+                Hexagon::new_axial(*q, *r),
+                Unit::new(5, 1, 1, 1, 0, 1, 1, 1),
+            ));
+        }
+
+        assert_eq!(
+            replace_grid(&mut world, &create_grid(1), None),
+            Err(GridResizeError::OrphanedUnits(vec![
+                Hexagon::new_axial(-3, 1),
+                Hexagon::new_axial(3, 0)
+            ]))
+        );
+        // The refused replacement leaves the map alone.
+        assert_eq!(field_count(&world), create_grid(3).len());
+        assert_eq!(unit_count(&world), 4);
+    }
+
+    #[test]
+    fn bulk_spawns_match_single_spawns() {
+        let catalog = GameState::new().unit_catalog;
+        let spawns: Vec<UnitSpawn> = create_grid(9)
+            .into_iter()
+            .take(240)
+            .enumerate()
+            .map(|(index, hexagon)| UnitSpawn {
+                position: hexagon,
+                ..spawn(index % 2, 0, 0, ["Tank", "Artillery"][index % 3 % 2])
+            })
+            .chain((0..10).map(|q| UnitSpawn {
+                source: UnitSource::Stats(Unit::new(5, 1, 1, 1, 0, 1, 1, 1)),
+                ..spawn(1, q, -10, "")
+            }))
+            .collect();
+        let mut bulk_world = World::default();
+        let mut bulk_ids = PersistentIds::default();
+        spawn_grid(&mut bulk_world, 10);
+        let mut single_world = World::default();
+        let mut single_ids = PersistentIds::default();
+        spawn_grid(&mut single_world, 10);
+
+        let spawned = spawn_units(
+            &mut bulk_world,
+            &mut bulk_ids,
+            &catalog,
+            &[],
+            &spawns,
+            false,
+        );
+        for spawn in &spawns {
+            match &spawn.source {
+                UnitSource::Type(name) => {
+                    spawn_unit_of_type(
+                        &mut single_world,
+                        &mut single_ids,
+                        &catalog,
+                        spawn.player,
+                        spawn.position,
+                        name,
+                        false,
+                    );
+                }
+                UnitSource::Stats(unit) => {
+                    spawn_unit(
+                        &mut single_world,
+                        &mut single_ids,
+                        spawn.player,
+                        spawn.position,
+                        *unit,
+                        None,
+                    );
+                }
+            }
+        }
+
+        assert!(spawned.results.iter().all(Result::is_ok));
+        assert_eq!(spawned.extend_calls, 2);
+        assert_eq!(unit_count(&bulk_world), 250);
+        assert_eq!(bulk_ids.len(), 250);
+        assert_eq!(bulk_world.len(), single_world.len());
+        let describe = |world: &World| {
+            let mut units: Vec<_> = <(
+                &PersistentId,
+                &Hexagon,
+                &Unit,
+                Option<&UnitType>,
+                Option<&Abilities>,
+                Option<&Demolition>,
+            )>::query()
+            .iter(world)
+            .map(|(id, hexagon, unit, unit_type, abilities, demolition)| {
+                (
+                    *id,
+                    *hexagon,
+                    *unit,
+                    unit_type.cloned(),
+                    abilities.is_some(),
+                    demolition.is_some(),
+                )
+            })
+            .collect();
+            units.sort_by_key(|unit| unit.0);
+            units
+        };
+        assert_eq!(describe(&bulk_world), describe(&single_world));
+        for (result, spawn) in spawned.results.iter().zip(&spawns) {
+            let entity = *result.as_ref().unwrap();
+            assert_eq!(
+                bulk_world
+                    .entry_ref(entity)
+                    .unwrap()
+                    .get_component::<Hexagon>()
+                    .ok(),
+                Some(&spawn.position)
+            );
+            assert_eq!(
+                bulk_ids.id(entity),
+                PersistentId::of_entity(&bulk_world, entity)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_entries_are_reported_and_skipped() {
+        let catalog = GameState::new().unit_catalog;
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        spawn_grid(&mut world, 2);
+        spawn_unit_of_type(
+            &mut world,
+            &mut ids,
+            &catalog,
+            0,
+            Hexagon::zero(),
+            "Tank",
+            false,
+        );
+        let spawns = vec![
+            spawn(0, 0, 0, "Tank"),
+            spawn(0, 1, 0, "Ship"),
+            spawn(0, 5, 0, "Tank"),
+            spawn(1, 1, 0, "Tank"),
+            spawn(1, 1, 0, "Artillery"),
+        ];
+        let handicaps = [
+            Handicap::default(),
+            Handicap {
+                integrity_bonus: 50,
+                ..Handicap::default()
+            },
+        ];
+
+        let spawned = spawn_units(&mut world, &mut ids, &catalog, &handicaps, &spawns, false);
+
+        let errors: Vec<Option<SpawnError>> = spawned
+            .results
+            .iter()
+            .map(|result| result.clone().err())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                Some(SpawnError::Occupied(Hexagon::zero())),
+                Some(SpawnError::UnknownUnitType("Ship".to_owned())),
+                Some(SpawnError::OffMap(Hexagon::new_axial(5, 0))),
+                None,
+                Some(SpawnError::Occupied(Hexagon::new_axial(1, 0))),
+            ]
+        );
+        assert_eq!(spawned.extend_calls, 1);
+        assert_eq!(unit_count(&world), 2);
+        let tank = *spawned.results[3].as_ref().unwrap();
+        let entry = world.entry_ref(tank).unwrap();
+        assert_eq!(entry.get_component::<Unit>().unwrap().integrity, 30);
+        assert!(entry.get_component::<PendingSpawn>().is_ok());
+        assert!(SpawnError::OffMap(Hexagon::new_axial(5, 0))
+            .to_string()
+            .contains("(5, 0)"));
+    }
+}
@@ -0,0 +1,429 @@
+//! Spawn modifiers let mods change the stats of every new unit without touching the catalog.
+//! Units fresh from the catalog are marked with `PendingSpawn`, the modifiers see them before
+//! their first frame and may return new stats. Returned stats are checked first: a negative
+//! integrity rejects them, the other stats are clamped to values a unit can have.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::pending_spawn::PendingSpawn;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::components::unit_type::UnitType;
+use gdnative::prelude::*;
+use legion::{Entity, IntoQuery, World};
+
+/// The stats of a unit about to be spawned, as modifiers see them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingStats {
+    pub unit_type: Option<String>,
+    pub player: Option<usize>,
+    pub position: Hexagon,
+    pub unit: Unit,
+}
+
+impl PendingStats {
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("unit_type", self.unit_type.clone().unwrap_or_default());
+        dict.insert("player", self.player.map_or(-1, |player| player as i64));
+        dict.insert("q", self.position.get_q());
+        dict.insert("r", self.position.get_r());
+        dict.insert("integrity", self.unit.integrity);
+        dict.insert("damage", self.unit.damage);
+        dict.insert("max_attack_range", self.unit.max_attack_range);
+        dict.insert("min_attack_range", self.unit.min_attack_range);
+        dict.insert("armor", self.unit.armor);
+        dict.insert("mobility", self.unit.mobility);
+        dict
+    }
+}
+
+/// New values for some stats, the others keep their value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatOverrides {
+    pub integrity: Option<i64>,
+    pub damage: Option<i64>,
+    pub max_attack_range: Option<i64>,
+    pub min_attack_range: Option<i64>,
+    pub armor: Option<i64>,
+    pub mobility: Option<i64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModifierError {
+    NegativeIntegrity(i64),
+}
+
+fn clamp_stat(value: i64) -> i32 {
+    value.max(0).min(i64::from(i32::MAX)) as i32
+}
+
+impl StatOverrides {
+    /// Reads the stats a script returned. Keys that are missing or no numbers are left alone.
+    pub fn from_dictionary(dict: &Dictionary) -> Self {
+        let number = |key: &str| {
+            let value = dict.get(key);
+            value
+                .try_to_i64()
+                .or_else(|| value.try_to_f64().map(|value| value.round() as i64))
+        };
+        StatOverrides {
+            integrity: number("integrity"),
+            damage: number("damage"),
+            max_attack_range: number("max_attack_range"),
+            min_attack_range: number("min_attack_range"),
+            armor: number("armor"),
+            mobility: number("mobility"),
+        }
+    }
+
+    /// Returns `unit` with the overrides applied. Stats can not go below zero and the minimum
+    /// attack range stays below the maximum. A fresh unit keeps its full movement if its
+    /// mobility changes.
+    pub fn apply(&self, unit: &Unit) -> Result<Unit, ModifierError> {
+        let mut changed = *unit;
+        if let Some(integrity) = self.integrity {
+            if integrity < 0 {
+                return Err(ModifierError::NegativeIntegrity(integrity));
+            }
+            changed.integrity = clamp_stat(integrity);
+        }
+        if let Some(damage) = self.damage {
+            changed.damage = clamp_stat(damage);
+        }
+        if let Some(armor) = self.armor {
+            changed.armor = clamp_stat(armor);
+        }
+        if let Some(mobility) = self.mobility {
+            changed.mobility = clamp_stat(mobility);
+            if unit.remaining_range == unit.mobility {
+                changed.remaining_range = changed.mobility;
+            } else {
+                changed.remaining_range = changed.remaining_range.min(changed.mobility);
+            }
+        }
+        if let Some(range) = self.max_attack_range {
+            changed.max_attack_range = clamp_stat(range);
+        }
+        if let Some(range) = self.min_attack_range {
+            changed.min_attack_range = clamp_stat(range);
+        }
+        changed.min_attack_range = changed.min_attack_range.min(changed.max_attack_range);
+        Ok(changed)
+    }
+}
+
+/// Something that can change the stats of new units, a script method in the game.
+pub trait SpawnModifier {
+    /// New stats for the unit, `None` keeps them.
+    fn modify(&self, stats: &PendingStats) -> Option<StatOverrides>;
+
+    /// Whether the modifier can still be called. Dead modifiers are dropped.
+    fn is_alive(&self) -> bool;
+}
+
+/// A method of a script object that gets the stats as a dictionary and returns a dictionary with
+/// the stats to change, or nothing.
+pub struct ScriptModifier {
+    target: Ref<Object>,
+    /// The instance id of the target when it was registered, as the target may be freed since.
+    target_id: i64,
+    method: String,
+}
+
+impl ScriptModifier {
+    /// `None` if the target is already freed.
+    pub fn new(target: Ref<Object>, method: String) -> Option<Self> {
+        let target_id = unsafe { target.assume_safe_if_sane() }?.get_instance_id();
+        Some(ScriptModifier {
+            target,
+            target_id,
+            method,
+        })
+    }
+}
+
+impl PartialEq for ScriptModifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.target_id == other.target_id && self.method == other.method
+    }
+}
+
+impl SpawnModifier for ScriptModifier {
+    fn modify(&self, stats: &PendingStats) -> Option<StatOverrides> {
+        let target = unsafe { self.target.assume_safe_if_sane() }?;
+        if !target.has_method(&self.method) {
+            return None;
+        }
+        let result = unsafe {
+            target.call(
+                &self.method,
+                &[stats.to_dictionary().into_shared().to_variant()],
+            )
+        };
+        result
+            .try_to_dictionary()
+            .map(|dict| StatOverrides::from_dictionary(&dict))
+    }
+
+    fn is_alive(&self) -> bool {
+        unsafe { self.target.assume_safe_if_sane() }.is_some()
+    }
+}
+
+/// The registered modifiers, called in the order they were registered.
+#[derive(Debug)]
+pub struct SpawnModifiers<M> {
+    modifiers: Vec<M>,
+}
+
+impl<M> Default for SpawnModifiers<M> {
+    fn default() -> Self {
+        SpawnModifiers {
+            modifiers: Vec::new(),
+        }
+    }
+}
+
+impl<M: SpawnModifier + PartialEq> SpawnModifiers<M> {
+    /// Adds the modifier after the others. Registering the same modifier twice keeps the first.
+    pub fn register(&mut self, modifier: M) -> bool {
+        if self.modifiers.contains(&modifier) {
+            return false;
+        }
+        self.modifiers.push(modifier);
+        true
+    }
+
+    /// Returns whether the modifier was registered.
+    pub fn unregister(&mut self, modifier: &M) -> bool {
+        let count = self.modifiers.len();
+        self.modifiers.retain(|registered| registered != modifier);
+        self.modifiers.len() != count
+    }
+
+    /// Runs every modifier on the stats, each one seeing the result of those before it.
+    /// Modifiers whose target is gone are dropped, rejected stats are skipped.
+    pub fn apply(&mut self, stats: &PendingStats) -> Unit {
+        self.modifiers.retain(|modifier| modifier.is_alive());
+        let mut current = stats.clone();
+        for modifier in &self.modifiers {
+            let overrides = match modifier.modify(&current) {
+                None => continue,
+                Some(overrides) => overrides,
+            };
+            match overrides.apply(&current.unit) {
+                Err(error) => log_warn!("Spawn modifier rejected: {:?}", error),
+                Ok(unit) => current.unit = unit,
+            }
+        }
+        current.unit
+    }
+
+    /// Applies the modifiers to every unit waiting for them and clears their marker.
+    pub fn apply_pending(&mut self, world: &mut World) {
+        let pending: Vec<(Entity, PendingStats)> = <(
+            Entity,
+            &PendingSpawn,
+            &Hexagon,
+            &Unit,
+            Option<&PlayerComponent>,
+            Option<&UnitType>,
+        )>::query()
+        .iter(world)
+        .map(|(entity, _, position, unit, player, unit_type)| {
+            (
+                *entity,
+                PendingStats {
+                    unit_type: unit_type.map(|unit_type| unit_type.0.clone()),
+                    player: player.map(|player| player.0),
+                    position: *position,
+                    unit: *unit,
+                },
+            )
+        })
+        .collect();
+        for (entity, stats) in pending {
+            let unit = if self.modifiers.is_empty() {
+                stats.unit
+            } else {
+                self.apply(&stats)
+            };
+            if let Some(mut entry) = world.entry(entity) {
+                if let Ok(current) = entry.get_component_mut::<Unit>() {
+                    *current = unit;
+                }
+                entry.remove_component::<PendingSpawn>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::persistent_id::PersistentIds;
+    use crate::spawn::spawn_unit_of_type;
+    use crate::unit_catalog::{UnitCatalog, DEFAULT_UNIT_CATALOG};
+    use std::cell::Cell;
+
+    /// Answers with fixed overrides and counts its calls.
+    struct FakeModifier {
+        name: &'static str,
+        overrides: Option<StatOverrides>,
+        alive: Cell<bool>,
+        calls: Cell<u32>,
+    }
+
+    impl FakeModifier {
+        fn new(name: &'static str, overrides: Option<StatOverrides>) -> Self {
+            FakeModifier {
+                name,
+                overrides,
+                alive: Cell::new(true),
+                calls: Cell::new(0),
+            }
+        }
+    }
+
+    impl PartialEq for FakeModifier {
+        fn eq(&self, other: &Self) -> bool {
+            self.name == other.name
+        }
+    }
+
+    impl SpawnModifier for FakeModifier {
+        fn modify(&self, _stats: &PendingStats) -> Option<StatOverrides> {
+            self.calls.set(self.calls.get() + 1);
+            self.overrides
+        }
+
+        fn is_alive(&self) -> bool {
+            self.alive.get()
+        }
+    }
+
+    fn stats() -> PendingStats {
+        PendingStats {
+            unit_type: Some("Tank".to_owned()),
+            player: Some(0),
+            position: Hexagon::zero(),
+            unit: Unit::new(20, 5, 2, 1, 3, 5, 5, 1),
+        }
+    }
+
+    #[test]
+    fn overrides_are_validated_and_clamped() {
+        let unit = stats().unit;
+        let negative = StatOverrides {
+            integrity: Some(-1),
+            ..StatOverrides::default()
+        };
+        assert_eq!(
+            negative.apply(&unit),
+            Err(ModifierError::NegativeIntegrity(-1))
+        );
+
+        let changed = StatOverrides {
+            integrity: Some(22),
+            damage: Some(-4),
+            max_attack_range: Some(1),
+            min_attack_range: Some(3),
+            mobility: Some(7),
+            ..StatOverrides::default()
+        }
+        .apply(&unit)
+        .unwrap();
+        assert_eq!(changed.integrity, 22);
+        assert_eq!(changed.damage, 0);
+        assert_eq!(changed.max_attack_range, 1);
+        assert_eq!(changed.min_attack_range, 1);
+        assert_eq!(changed.armor, unit.armor);
+        assert_eq!((changed.mobility, changed.remaining_range), (7, 7));
+    }
+
+    #[test]
+    fn modifiers_apply_in_registration_order() {
+        let mut modifiers = SpawnModifiers::default();
+        modifiers.register(FakeModifier::new(
+            "hardcore",
+            Some(StatOverrides {
+                integrity: Some(22),
+                damage: Some(6),
+                ..StatOverrides::default()
+            }),
+        ));
+        modifiers.register(FakeModifier::new(
+            "broken",
+            Some(StatOverrides {
+                integrity: Some(-5),
+                armor: Some(0),
+                ..StatOverrides::default()
+            }),
+        ));
+        modifiers.register(FakeModifier::new(
+            "glass cannon",
+            Some(StatOverrides {
+                damage: Some(9),
+                ..StatOverrides::default()
+            }),
+        ));
+        assert!(!modifiers.register(FakeModifier::new("hardcore", None)));
+
+        let unit = modifiers.apply(&stats());
+
+        assert_eq!(unit.integrity, 22);
+        assert_eq!(unit.damage, 9);
+        assert_eq!(unit.armor, 3);
+    }
+
+    #[test]
+    fn unregistered_and_dead_modifiers_are_not_called() {
+        let mut modifiers = SpawnModifiers::default();
+        let boost = StatOverrides {
+            integrity: Some(30),
+            ..StatOverrides::default()
+        };
+        modifiers.register(FakeModifier::new("first", Some(boost)));
+        modifiers.register(FakeModifier::new("second", Some(boost)));
+
+        assert!(modifiers.unregister(&FakeModifier::new("first", None)));
+        assert!(!modifiers.unregister(&FakeModifier::new("first", None)));
+        modifiers.modifiers[0].alive.set(false);
+
+        assert_eq!(modifiers.apply(&stats()), stats().unit);
+        assert!(modifiers.modifiers.is_empty());
+    }
+
+    #[test]
+    fn pending_units_are_modified_once() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let catalog = UnitCatalog::from_json(DEFAULT_UNIT_CATALOG).unwrap().0;
+        let tank = spawn_unit_of_type(
+            &mut world,
+            &mut ids,
+            &catalog,
+            0,
+            Hexagon::zero(),
+            "Tank",
+            false,
+        )
+        .unwrap();
+        let mut modifiers = SpawnModifiers::default();
+        modifiers.register(FakeModifier::new(
+            "hardcore",
+            Some(StatOverrides {
+                integrity: Some(22),
+                ..StatOverrides::default()
+            }),
+        ));
+
+        modifiers.apply_pending(&mut world);
+        modifiers.apply_pending(&mut world);
+
+        let entry = world.entry_ref(tank).unwrap();
+        assert_eq!(entry.get_component::<Unit>().unwrap().integrity, 22);
+        assert!(entry.get_component::<PendingSpawn>().is_err());
+        assert_eq!(modifiers.modifiers[0].calls.get(), 1);
+    }
+}
@@ -0,0 +1,3248 @@
+use crate::abilities::{start_turn, use_ability};
+use crate::actionable::get_attackable_entities;
+use crate::buildings::{
+    building_at, enter_garrison, free_neighbour, garrison_at, is_free, leave_garrison,
+};
+use crate::combat_context::{combat_context, CombatContext};
+use crate::combat_feedback::{add_hitstop, combat_feedback};
+use crate::components::abilities::AbilityKind;
+use crate::components::building::Building;
+use crate::components::facing::Facing;
+use crate::components::field::Field;
+use crate::components::hexagon::{Direction, Hexagon};
+use crate::components::history::{attack_entries, Combatant, History, HistoryEntry};
+use crate::components::orders::{AttackOrder, MoveOrder};
+use crate::components::overwatch::Overwatching;
+use crate::components::perks::{PerkId, Perks};
+use crate::components::persistent_id::PersistentId;
+use crate::components::pickup::Pickup;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::supply::Supply;
+use crate::components::unit::{AttackError, AttackResult, CanMove, Unit};
+use crate::components::unit_type::UnitType;
+use crate::cover::{apply_cover, cover_count};
+use crate::economy::collect_income;
+use crate::edges::EdgeData;
+use crate::flanking::AttackArc;
+use crate::game_state::{force_state, set_state, GameState, State};
+use crate::ground_effects::{burn_on_entry, tick_ground_effects};
+use crate::initiative::InitiativeQueue;
+use crate::map_bounds::MapBounds;
+use crate::movement::{movement_type_of, MovementCosts, HEXAGON_COST};
+use crate::pickups::collect_pickup;
+use crate::planning::store_orders;
+use crate::player::Player;
+use crate::promotions::{award_kills, perks_of, promote, promotion_options};
+use crate::random_events::{start_round, RandomEventKind};
+use crate::rules::Ruleset;
+use crate::statistics::record_round;
+use crate::supply::{limit_movement, refresh_units, update_supply};
+use crate::systems::hexgrid::{
+    find_path_within, get_reachable_hexagons, is_line_of_sight_clear, is_occupied_by_unit,
+};
+use legion::{Entity, EntityStore, IntoQuery, World};
+use std::collections::vec_deque::VecDeque;
+
+pub const SECONDS_PER_MOVEMENT: f64 = 0.1f64;
+
+/// Things that happened while advancing the state machine, for the caller to present.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    TurnStarted {
+        round: u32,
+        player: usize,
+    },
+    /// With the simultaneous rules, all players plan their orders for `round`.
+    PlanningStarted {
+        round: u32,
+    },
+    /// With the initiative rules, the unit whose turn it is now.
+    UnitTurnStarted {
+        entity: Entity,
+        player: usize,
+        position: Hexagon,
+    },
+    UnitMoved {
+        entity: Entity,
+        from: Hexagon,
+        to: Hexagon,
+    },
+    UnitAttacked {
+        attacker: Entity,
+        defender: Entity,
+        attacker_player: Option<usize>,
+        defender_player: Option<usize>,
+        attacker_position: Hexagon,
+        defender_position: Hexagon,
+        /// The side of the defender that was hit.
+        arc: AttackArc,
+        damage: i32,
+        remaining_integrity: i32,
+    },
+    UnitDestroyed {
+        entity: Entity,
+        position: Hexagon,
+    },
+    AttackFailed {
+        attacker: Entity,
+        position: Hexagon,
+        error: AttackError,
+    },
+    /// An order could not be carried out completely, like a move whose path was blocked without
+    /// a way around. The unit stopped.
+    OrderInterrupted {
+        entity: Entity,
+        position: Hexagon,
+    },
+    /// How strongly the hit at `position` should shake the screen, and the seconds of hit pause
+    /// it added.
+    CombatFeedback {
+        position: Hexagon,
+        shake: f32,
+        hitstop: f64,
+    },
+    AbilityUsed {
+        entity: Entity,
+        kind: AbilityKind,
+        position: Hexagon,
+        target: Hexagon,
+    },
+    /// A unit started or stopped watching for enemies, either by firing or because its owner's
+    /// turn started again.
+    OverwatchChanged {
+        entity: Entity,
+        position: Hexagon,
+        active: bool,
+    },
+    /// The unit stepped onto `position` and collected the pickup lying there.
+    PickupCollected {
+        entity: Entity,
+        player: Option<usize>,
+        pickup: Pickup,
+        position: Hexagon,
+    },
+    /// A demolition unit hit the bridge between `from` and `to`.
+    BridgeAttacked {
+        attacker: Entity,
+        attacker_position: Hexagon,
+        from: Hexagon,
+        to: Hexagon,
+        damage: i32,
+        remaining_integrity: i32,
+    },
+    /// The bridge between `from` and `to` collapsed, the river there has to be crossed again.
+    BridgeDestroyed {
+        from: Hexagon,
+        to: Hexagon,
+    },
+    /// A unit moved into or left the building on `position`.
+    GarrisonChanged {
+        entity: Entity,
+        position: Hexagon,
+        active: bool,
+    },
+    /// A unit hit the building on `position`.
+    BuildingAttacked {
+        attacker: Entity,
+        attacker_position: Hexagon,
+        position: Hexagon,
+        damage: i32,
+        remaining_integrity: i32,
+    },
+    /// The building on `position` collapsed. A unit inside was hit and pushed out.
+    BuildingDestroyed {
+        position: Hexagon,
+    },
+    /// A unit turned in place to face `facing`.
+    FacingChanged {
+        entity: Entity,
+        position: Hexagon,
+        facing: Direction,
+    },
+    /// A unit got burnt by the fire on `position`, either at the start of the round or by moving
+    /// into it.
+    FireDamage {
+        entity: Entity,
+        position: Hexagon,
+        damage: i32,
+        remaining_integrity: i32,
+    },
+    /// The unit earned a promotion, its owner `player` has to pick one of `options`.
+    PromotionAvailable {
+        entity: Entity,
+        player: Option<usize>,
+        position: Hexagon,
+        options: Vec<PerkId>,
+    },
+    /// A random event happened at the start of the round. `player` is the player it concerned,
+    /// `position` where it happened.
+    RandomEvent {
+        kind: RandomEventKind,
+        player: Option<usize>,
+        position: Option<Hexagon>,
+    },
+    GameOver {
+        winner: Option<usize>,
+    },
+    Error(StateError),
+}
+
+/// Inconsistencies between the state and the world. The state machine recovers from all of
+/// them by falling back to a safe state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    AttackerNotInWorld,
+    AttackerHasNoUnit,
+    AttackerHasNoHexagon,
+    DefenderNotInWorld,
+    DefenderHasNoUnit,
+    DefenderHasNoHexagon,
+    MovingEntityNotInWorld,
+    MovingEntityHasNoUnit,
+    MovingEntityHasNoHexagon,
+    PathEmpty,
+    PathNotAdjacent,
+    SelectedEntityNotInWorld,
+    AbilityUserNotInWorld,
+    OverwatchingEntityNotInWorld,
+    GarrisonEntityNotInWorld,
+    TurningEntityNotInWorld,
+    /// The ability was accepted as an order, but could no longer be used when it resolved.
+    AbilityNotUsable,
+}
+
+impl StateError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            StateError::AttackerNotInWorld => "ATTACKING: Attacking entity not in world.",
+            StateError::AttackerHasNoUnit => "ATTACKING: Attacking entity had no unit component.",
+            StateError::AttackerHasNoHexagon => "ATTACKING: Attacking entity had no hexagon tag.",
+            StateError::DefenderNotInWorld => "ATTACKING: Defending entity not in world.",
+            StateError::DefenderHasNoUnit => "ATTACKING: Defending entity had no unit component.",
+            StateError::DefenderHasNoHexagon => "ATTACKING: Defending entity had no hexagon tag.",
+            StateError::MovingEntityNotInWorld => "MOVING: Entity to move does not exist in world.",
+            StateError::MovingEntityHasNoUnit => "MOVING: Entity to move has no unit component",
+            StateError::MovingEntityHasNoHexagon => "MOVING: Entity to move had no hexagon tag.",
+            StateError::PathEmpty => "MOVING: Path was empty",
+            StateError::PathNotAdjacent => {
+                "MOVING: Next point in path was not adjacent to current hexagon"
+            }
+            StateError::SelectedEntityNotInWorld => "SELECTED: Selected entity not in world.",
+            StateError::AbilityUserNotInWorld => "ABILITY: Entity using the ability not in world.",
+            StateError::AbilityNotUsable => "ABILITY: The ability could not be used.",
+            StateError::OverwatchingEntityNotInWorld => {
+                "OVERWATCH: Entity to enter overwatch not in world."
+            }
+            StateError::GarrisonEntityNotInWorld => {
+                "GARRISON: Entity to enter or leave a building not in world."
+            }
+            StateError::TurningEntityNotInWorld => "TURNING: Entity to turn not in world.",
+        }
+    }
+
+    /// Whether the error is expected to happen occasionally and only worth a warning.
+    pub fn is_warning(&self) -> bool {
+        matches!(self, StateError::PathEmpty)
+    }
+}
+
+/// Advances the state machine by `delta` seconds and returns what happened.
+pub fn advance_state(world: &mut World, state: &mut GameState, delta: f64) -> Vec<GameEvent> {
+    let mut events = Vec::new();
+    drop_removed_entities(world, state, &mut events);
+    if state.hitstop_timer > 0.0 {
+        state.hitstop_timer = (state.hitstop_timer - delta).max(0.0);
+        if let State::Moving(_) = state.state {
+            return events;
+        }
+    }
+    match state.state.clone() {
+        State::Startup => {
+            // Units are created with range and attacks, action points are handed out per round.
+            if state.rules.action_points {
+                for unit in <&mut Unit>::query().iter_mut(world) {
+                    unit.refresh(&state.rules);
+                }
+            }
+            if state.rules.simultaneous {
+                events.push(GameEvent::PlanningStarted { round: state.round });
+                set_state(state, State::Planning);
+            } else {
+                set_state(state, State::Waiting);
+            }
+        }
+        State::Planning => store_orders(world, std::mem::take(&mut state.planned_orders)),
+        State::Resolving => resolve_planned_orders(world, state, &mut events),
+        State::NewRound if state.rules.initiative => next_unit_turn(world, state, &mut events),
+        State::NewRound => start_next_turn(world, state, &mut events),
+        State::Attacking(attacker_entity, defender_entity) => {
+            resolve_attack(world, state, attacker_entity, defender_entity, &mut events)
+        }
+        State::Moving(entity) => {
+            state.movement.elapsed += delta;
+            advance_movement(world, state, entity, &mut events)
+        }
+        State::UsingAbility(entity, index, target) => {
+            resolve_ability(world, state, entity, index, target, &mut events)
+        }
+        State::EnteringOverwatch(entity) => enter_overwatch(world, state, entity, &mut events),
+        State::AttackingEdge(entity, from, to) => {
+            resolve_edge_attack(world, state, entity, from, to, &mut events)
+        }
+        State::Garrisoning(entity) => garrison(world, state, entity, &mut events),
+        State::Ungarrisoning(entity, target) => {
+            ungarrison(world, state, entity, target, &mut events)
+        }
+        State::AttackingBuilding(entity, target) => {
+            resolve_building_attack(world, state, entity, target, &mut events)
+        }
+        State::Turning(entity, facing) => turn(world, state, entity, facing, &mut events),
+        State::Promoting(entity, perk) => {
+            promote(world, entity, perk);
+            let next_state = state_after_attack(world, state, entity);
+            set_state(state, next_state);
+        }
+        State::TurnTransition(_) if state.awaiting_player => {}
+        State::TurnTransition(remaining) => {
+            if remaining - delta > 0.0 {
+                // The countdown is not a transition.
+                if let State::TurnTransition(left) = &mut state.state {
+                    *left = remaining - delta;
+                }
+            } else {
+                set_state(state, State::Waiting);
+            }
+        }
+        _ => {}
+    }
+    if state.rules.initiative && state.state.accepts_orders() {
+        follow_initiative(world, state, &mut events);
+    }
+    events
+}
+
+/// Falls back to a safe state if the state references an entity that is no longer in the world,
+/// like a selected unit destroyed by a counterattack. An attack without defender keeps the
+/// attacker selected. The overlays of the old state are cleared.
+fn drop_removed_entities(world: &mut World, state: &mut GameState, events: &mut Vec<GameEvent>) {
+    let exists = |entity: &Entity| world.entry_ref(*entity).is_ok();
+    let (next_state, error) = match &state.state {
+        State::Selected(entity) | State::Targeting(entity) if !exists(entity) => {
+            (State::Waiting, StateError::SelectedEntityNotInWorld)
+        }
+        State::GroupSelected(members) if !members.iter().all(exists) => (
+            State::from_selection(members.iter().copied().filter(exists).collect()),
+            StateError::SelectedEntityNotInWorld,
+        ),
+        State::Moving(entity) if !exists(entity) => {
+            (State::Waiting, StateError::MovingEntityNotInWorld)
+        }
+        State::UsingAbility(entity, _, _) if !exists(entity) => {
+            (State::Waiting, StateError::AbilityUserNotInWorld)
+        }
+        State::EnteringOverwatch(entity) if !exists(entity) => {
+            (State::Waiting, StateError::OverwatchingEntityNotInWorld)
+        }
+        State::AttackingEdge(entity, _, _) | State::AttackingBuilding(entity, _)
+            if !exists(entity) =>
+        {
+            (State::Waiting, StateError::AttackerNotInWorld)
+        }
+        State::Garrisoning(entity) | State::Ungarrisoning(entity, _) if !exists(entity) => {
+            (State::Waiting, StateError::GarrisonEntityNotInWorld)
+        }
+        State::Turning(entity, _) if !exists(entity) => {
+            (State::Waiting, StateError::TurningEntityNotInWorld)
+        }
+        State::ChoosingPromotion(entity, _) | State::Promoting(entity, _) if !exists(entity) => {
+            (State::Waiting, StateError::SelectedEntityNotInWorld)
+        }
+        State::Attacking(attacker, _) if !exists(attacker) => {
+            (State::Waiting, StateError::AttackerNotInWorld)
+        }
+        State::Attacking(attacker, defender) if !exists(defender) => {
+            (State::Selected(*attacker), StateError::DefenderNotInWorld)
+        }
+        _ => return,
+    };
+    events.push(GameEvent::Error(error));
+    force_state(state, next_state);
+    for field in <&mut Field>::query().iter_mut(world) {
+        field.moveable = false;
+        field.attackable = false;
+    }
+}
+
+fn start_next_turn(world: &mut World, state: &mut GameState, events: &mut Vec<GameEvent>) {
+    let (next_player, wrapped) = match next_active_player(&state.players, state.current_player) {
+        None => {
+            log_error!("No player can take a turn");
+            set_state(state, State::Waiting);
+            return;
+        }
+        Some(next) => next,
+    };
+    if wrapped {
+        next_round(world, state, events);
+    }
+    // Units are refreshed after the supply of a new round is known.
+    refresh_units(world, &state.rules);
+    state.current_player = Some(next_player);
+    start_turn(world, next_player);
+    end_overwatch(world, next_player, events);
+    events.push(GameEvent::TurnStarted {
+        round: state.round,
+        player: next_player,
+    });
+    hand_over(state);
+}
+
+fn next_round(world: &mut World, state: &mut GameState, events: &mut Vec<GameEvent>) {
+    state.round += 1;
+    state.commands_used.clear();
+    for history in <&mut History>::query().iter_mut(world) {
+        history.rounds_survived += 1;
+    }
+    // Effects tick before the events, so a new fire burns only from the next round on.
+    tick_ground_effects(world, events);
+    start_round(world, state, events);
+    update_supply(world, &state.rules);
+    collect_income(world, state);
+    record_round(world, state);
+}
+
+/// Starts the turns of all players at once, for the rules where a round is not split into turns.
+fn start_all_turns(world: &mut World, state: &GameState, events: &mut Vec<GameEvent>) {
+    for player in 0..state.players.len() {
+        if !state.players[player].is_observer() {
+            start_turn(world, player);
+            end_overwatch(world, player, events);
+        }
+    }
+}
+
+/// Shows the banner or the privacy screen for the player that takes over.
+fn hand_over(state: &mut GameState) {
+    if state.privacy_screen {
+        set_state(state, State::TurnTransition(state.banner_duration));
+        state.awaiting_player = true;
+    } else if state.banner_duration > 0.0 {
+        set_state(state, State::TurnTransition(state.banner_duration));
+    } else {
+        set_state(state, State::Waiting);
+    }
+}
+
+/// Passes the turn to the next unit of the initiative queue. Once every unit had its turn a new
+/// round starts: cooldowns, smoke and overwatch of all players run out at once.
+fn next_unit_turn(world: &mut World, state: &mut GameState, events: &mut Vec<GameEvent>) {
+    let (entity, new_round) = match state.initiative.advance(world) {
+        Some(entity) => (entity, false),
+        None => {
+            next_round(world, state, events);
+            start_all_turns(world, state, events);
+            state.initiative = InitiativeQueue::build(world);
+            match state.initiative.active() {
+                Some(entity) => (entity, true),
+                None => {
+                    log_error!("No unit can take a turn");
+                    set_state(state, State::Waiting);
+                    return;
+                }
+            }
+        }
+    };
+    activate_unit(world, state, entity, new_round, events);
+}
+
+/// Refreshes the unit whose turn starts and hands control to its owner. The turn banner is only
+/// shown when the player changes or a new round starts.
+fn activate_unit(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+    new_round: bool,
+    events: &mut Vec<GameEvent>,
+) {
+    let (player, position) = match world.entry(entity) {
+        Some(mut entry) => {
+            let supply = entry.get_component::<Supply>().ok().copied();
+            let perks = entry.get_component::<Perks>().ok().cloned();
+            if let Ok(unit) = entry.get_component_mut::<Unit>() {
+                unit.refresh(&state.rules);
+                if let Some(perks) = perks {
+                    perks.refresh(unit, &state.rules);
+                }
+                limit_movement(unit, supply.as_ref(), &state.rules);
+            }
+            let player = entry
+                .get_component::<PlayerComponent>()
+                .map_or(0, |player| player.0);
+            let position = entry.get_component::<Hexagon>().ok().copied();
+            (player, position.unwrap_or_else(Hexagon::zero))
+        }
+        None => {
+            set_state(state, State::NewRound);
+            return;
+        }
+    };
+    events.push(GameEvent::UnitTurnStarted {
+        entity,
+        player,
+        position,
+    });
+    let player_changed = state.current_player != Some(player);
+    state.current_player = Some(player);
+    if player_changed || new_round {
+        events.push(GameEvent::TurnStarted {
+            round: state.round,
+            player,
+        });
+        hand_over(state);
+    } else {
+        set_state(state, State::Waiting);
+    }
+    if state.state == State::Waiting {
+        set_state(state, State::UnitTurn(entity));
+    }
+}
+
+/// Keeps the state in line with the initiative queue whenever orders are accepted: the first
+/// unit is activated when the game starts or was loaded, and the turn passes on as soon as the
+/// active unit can do nothing more.
+fn follow_initiative(world: &mut World, state: &mut GameState, events: &mut Vec<GameEvent>) {
+    let active = match state.initiative.active() {
+        Some(active) => active,
+        None => {
+            state.initiative = InitiativeQueue::build(world);
+            if let Some(first) = state.initiative.active() {
+                activate_unit(world, state, first, true, events);
+            }
+            return;
+        }
+    };
+    let exhausted = world
+        .entry_ref(active)
+        .ok()
+        .and_then(|entry| entry.get_component::<Unit>().ok().copied())
+        .map_or(true, |unit| {
+            unit.movement_cost_left(&state.rules) <= 0 && !unit.can_attack(&state.rules)
+        });
+    if exhausted {
+        set_state(state, State::NewRound);
+    } else if state.state == State::Waiting {
+        set_state(state, State::UnitTurn(active));
+    }
+}
+
+/// Carries out the orders planned by all players and starts planning the next round.
+///
+/// Units move one hexagon per step, in initiative order. A unit whose next hexagon is taken stops
+/// where it is, so of two units heading for the same hexagon the one with the higher initiative
+/// gets there. Attacks follow once every unit stopped, again in initiative order, and only hit if
+/// the target can be attacked from where the attacker ended up.
+fn resolve_planned_orders(world: &mut World, state: &mut GameState, events: &mut Vec<GameEvent>) {
+    store_orders(world, std::mem::take(&mut state.planned_orders));
+    let order = InitiativeQueue::build(world).remaining().to_vec();
+
+    let mut moves: Vec<(Entity, VecDeque<Hexagon>)> = order
+        .iter()
+        .filter_map(|entity| {
+            let mut entry = world.entry(*entity)?;
+            let path = entry.get_component::<MoveOrder>().ok()?.path.clone();
+            entry.remove_component::<MoveOrder>();
+            Some((*entity, path.into_iter().collect()))
+        })
+        .collect();
+    while moves.iter().any(|(_, path)| !path.is_empty()) {
+        for (entity, path) in moves.iter_mut() {
+            let next = match path.pop_front() {
+                None => continue,
+                Some(next) => next,
+            };
+            let (unit, from) = match get_unit_and_hexagon(
+                world,
+                *entity,
+                (
+                    StateError::MovingEntityNotInWorld,
+                    StateError::MovingEntityHasNoUnit,
+                    StateError::MovingEntityHasNoHexagon,
+                ),
+            ) {
+                Err(error) => {
+                    events.push(GameEvent::Error(error));
+                    path.clear();
+                    continue;
+                }
+                Ok(data) => data,
+            };
+            if unit.movement_cost_left(&state.rules) <= 0 {
+                path.clear();
+            } else if !from.is_neighbour(&next) || is_occupied_by_unit(&next, world) {
+                events.push(GameEvent::OrderInterrupted {
+                    entity: *entity,
+                    position: from,
+                });
+                path.clear();
+            } else if move_entity_to_hexagon(*entity, &next, world, &state.rules, &state.edges) {
+                events.push(GameEvent::UnitMoved {
+                    entity: *entity,
+                    from,
+                    to: next,
+                });
+                events.extend(collect_pickup(world, state, *entity));
+                burn_on_entry(world, *entity, events);
+            } else {
+                path.clear();
+            }
+        }
+    }
+
+    for attacker in order {
+        let target = match world.entry(attacker) {
+            None => continue,
+            Some(mut entry) => {
+                let target = entry
+                    .get_component::<AttackOrder>()
+                    .ok()
+                    .map(|order| order.target);
+                entry.remove_component::<AttackOrder>();
+                target
+            }
+        };
+        let target = match target {
+            None => continue,
+            Some(target) => target,
+        };
+        if !get_attackable_entities(world, attacker, &state.rules, &state.map_bounds)
+            .contains(&target)
+        {
+            if let Ok((_, position)) = get_unit_and_hexagon(
+                world,
+                attacker,
+                (
+                    StateError::AttackerNotInWorld,
+                    StateError::AttackerHasNoUnit,
+                    StateError::AttackerHasNoHexagon,
+                ),
+            ) {
+                events.push(GameEvent::OrderInterrupted {
+                    entity: attacker,
+                    position,
+                });
+            }
+            continue;
+        }
+        resolve_attack(world, state, attacker, target, events);
+        if let State::GameOver(_) = state.state {
+            return;
+        }
+    }
+
+    next_round(world, state, events);
+    refresh_units(world, &state.rules);
+    start_all_turns(world, state, events);
+    events.push(GameEvent::PlanningStarted { round: state.round });
+    set_state(state, State::Planning);
+}
+
+/// Ends the handover pause early. Returns false if no turn transition is in progress.
+pub fn dismiss_turn_transition(state: &mut GameState) -> bool {
+    match state.state {
+        State::TurnTransition(_) => {
+            set_state(state, State::Waiting);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Returns the player after `current` that is not an observer, and whether the order wrapped
+/// around to the start, which begins a new round.
+fn next_active_player(players: &[Player], current: Option<usize>) -> Option<(usize, bool)> {
+    let start = current.map_or(0, |player| player + 1);
+    (start..start + players.len())
+        .find(|index| !players[index % players.len()].is_observer())
+        .map(|index| {
+            (
+                index % players.len(),
+                current.is_some() && index >= players.len(),
+            )
+        })
+}
+
+fn get_unit_and_hexagon<S: EntityStore>(
+    world: &S,
+    entity: Entity,
+    errors: (StateError, StateError, StateError),
+) -> Result<(Unit, Hexagon), StateError> {
+    let (not_in_world, no_unit, no_hexagon) = errors;
+    let entry = world.entry_ref(entity).map_err(|_| not_in_world)?;
+    let unit = *entry.get_component::<Unit>().map_err(|_| no_unit)?;
+    let hexagon = *entry.get_component::<Hexagon>().map_err(|_| no_hexagon)?;
+    Ok((unit, hexagon))
+}
+
+fn get_owner<S: EntityStore>(world: &S, entity: Entity) -> Option<usize> {
+    let entry = world.entry_ref(entity).ok()?;
+    entry
+        .get_component::<PlayerComponent>()
+        .ok()
+        .map(|player| player.0)
+}
+
+fn resolve_ability(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+    index: usize,
+    target: Hexagon,
+    events: &mut Vec<GameEvent>,
+) {
+    let position = match world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<Hexagon>().ok().copied())
+    {
+        None => {
+            events.push(GameEvent::Error(StateError::AbilityUserNotInWorld));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Some(position) => position,
+    };
+    match use_ability(world, &state.rules, entity, index, target) {
+        Err(_) => events.push(GameEvent::Error(StateError::AbilityNotUsable)),
+        Ok(kind) => events.push(GameEvent::AbilityUsed {
+            entity,
+            kind,
+            position,
+            target,
+        }),
+    }
+    set_state(state, State::Selected(entity));
+}
+
+/// Marks the unit as overwatching. It keeps what it needs for one attack, the rest of its
+/// movement is used up.
+fn enter_overwatch(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+    events: &mut Vec<GameEvent>,
+) {
+    let mut entry = match world.entry(entity) {
+        None => {
+            events.push(GameEvent::Error(StateError::OverwatchingEntityNotInWorld));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Some(entry) => entry,
+    };
+    let position = entry.get_component::<Hexagon>().ok().copied();
+    if let Ok(unit) = entry.get_component_mut::<Unit>() {
+        let reserved = if state.rules.action_points {
+            state.rules.attack_cost
+        } else {
+            0
+        };
+        let movement = (unit.movement_cost_left(&state.rules) - reserved * HEXAGON_COST).max(0);
+        unit.spend_movement_cost(movement, &state.rules);
+    }
+    entry.add_component(Overwatching);
+    if let Some(position) = position {
+        events.push(GameEvent::OverwatchChanged {
+            entity,
+            position,
+            active: true,
+        });
+    }
+    set_state(state, State::Waiting);
+}
+
+/// Lets a demolition unit fire at a bridge. A bridge without integrity left becomes a river,
+/// which interrupts the planned moves across it.
+fn resolve_edge_attack(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+    from: Hexagon,
+    to: Hexagon,
+    events: &mut Vec<GameEvent>,
+) {
+    let (unit, position) = match get_unit_and_hexagon(
+        world,
+        entity,
+        (
+            StateError::AttackerNotInWorld,
+            StateError::AttackerHasNoUnit,
+            StateError::AttackerHasNoHexagon,
+        ),
+    ) {
+        Err(error) => {
+            events.push(GameEvent::Error(error));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Ok(data) => data,
+    };
+    if let Some(error) = unit.attack_error(&state.rules) {
+        events.push(GameEvent::AttackFailed {
+            attacker: entity,
+            position,
+            error,
+        });
+        set_state(state, State::Selected(entity));
+        return;
+    }
+    let damage = state.rules.damage(unit.damage, 0);
+    let remaining_integrity = match state.edges.damage_bridge(&from, &to, damage) {
+        // The bridge went down since the order was given.
+        None => {
+            set_state(state, State::Selected(entity));
+            return;
+        }
+        Some(remaining) => remaining,
+    };
+    if let Some(mut entry) = world.entry(entity) {
+        if let Ok(unit) = entry.get_component_mut::<Unit>() {
+            unit.spend_attack(&state.rules);
+        }
+    }
+    events.push(GameEvent::BridgeAttacked {
+        attacker: entity,
+        attacker_position: position,
+        from,
+        to,
+        damage,
+        remaining_integrity,
+    });
+    if remaining_integrity <= 0 {
+        events.push(GameEvent::BridgeDestroyed { from, to });
+        interrupt_orders_across(world, from, to, events);
+    }
+    set_state(state, State::Selected(entity));
+}
+
+/// Moves the unit into the building on its hexagon. It keeps its attacks, but can not move
+/// until it leaves again.
+fn garrison(world: &mut World, state: &mut GameState, entity: Entity, events: &mut Vec<GameEvent>) {
+    let position = world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<Hexagon>().ok().copied());
+    let position = match position {
+        None => {
+            events.push(GameEvent::Error(StateError::GarrisonEntityNotInWorld));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Some(position) => position,
+    };
+    if building_at(world, &position).is_some() && enter_garrison(world, entity) {
+        events.push(GameEvent::GarrisonChanged {
+            entity,
+            position,
+            active: true,
+        });
+    }
+    set_state(state, State::Selected(entity));
+}
+
+/// Turns the unit in place to face `facing`, which costs the movement of one hexagon.
+fn turn(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+    facing: Direction,
+    events: &mut Vec<GameEvent>,
+) {
+    let mut entry = match world.entry(entity) {
+        None => {
+            events.push(GameEvent::Error(StateError::TurningEntityNotInWorld));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Some(entry) => entry,
+    };
+    let position = match entry.get_component::<Hexagon>() {
+        Err(_) => {
+            events.push(GameEvent::Error(StateError::TurningEntityNotInWorld));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Ok(position) => *position,
+    };
+    if let Ok(unit) = entry.get_component_mut::<Unit>() {
+        let cost = HEXAGON_COST.min(unit.movement_cost_left(&state.rules));
+        unit.spend_movement_cost(cost, &state.rules);
+    }
+    entry.add_component(Facing(facing));
+    events.push(GameEvent::FacingChanged {
+        entity,
+        position,
+        facing,
+    });
+    set_state(state, State::Selected(entity));
+}
+
+/// Lets the unit step out of its building onto `target`, which costs the movement of one
+/// hexagon.
+fn ungarrison(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+    target: Hexagon,
+    events: &mut Vec<GameEvent>,
+) {
+    let position = world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<Hexagon>().ok().copied());
+    let position = match position {
+        None => {
+            events.push(GameEvent::Error(StateError::GarrisonEntityNotInWorld));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Some(position) => position,
+    };
+    // The neighbour may have been taken since the order was given.
+    if !is_free(world, &target) || !leave_garrison(world, entity, target) {
+        set_state(state, State::Selected(entity));
+        return;
+    }
+    if let Some(mut entry) = world.entry(entity) {
+        if let Ok(unit) = entry.get_component_mut::<Unit>() {
+            let cost = HEXAGON_COST.min(unit.movement_cost_left(&state.rules));
+            unit.spend_movement_cost(cost, &state.rules);
+            unit.moved = true;
+        }
+        if let Some(direction) = position.direction_to(&target) {
+            entry.add_component(Facing(direction));
+        }
+    }
+    events.push(GameEvent::GarrisonChanged {
+        entity,
+        position,
+        active: false,
+    });
+    events.push(GameEvent::UnitMoved {
+        entity,
+        from: position,
+        to: target,
+    });
+    set_state(state, State::Selected(entity));
+}
+
+/// Lets a unit fire at a building. A building without integrity left collapses on the unit
+/// inside, see `eject_garrison`.
+fn resolve_building_attack(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+    target: Hexagon,
+    events: &mut Vec<GameEvent>,
+) {
+    let (unit, position) = match get_unit_and_hexagon(
+        world,
+        entity,
+        (
+            StateError::AttackerNotInWorld,
+            StateError::AttackerHasNoUnit,
+            StateError::AttackerHasNoHexagon,
+        ),
+    ) {
+        Err(error) => {
+            events.push(GameEvent::Error(error));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Ok(data) => data,
+    };
+    if let Some(error) = unit.attack_error(&state.rules) {
+        events.push(GameEvent::AttackFailed {
+            attacker: entity,
+            position,
+            error,
+        });
+        set_state(state, State::Selected(entity));
+        return;
+    }
+    let building_entity = match building_at(world, &target) {
+        // The building went down since the order was given.
+        None => {
+            set_state(state, State::Selected(entity));
+            return;
+        }
+        Some((building_entity, _)) => building_entity,
+    };
+    let garrison = garrison_at(world, &target);
+    let damage = state.rules.damage(unit.damage, 0);
+    let mut remaining_integrity = 0;
+    if let Some(mut entry) = world.entry(building_entity) {
+        if let Ok(building) = entry.get_component_mut::<Building>() {
+            building.integrity -= damage;
+            remaining_integrity = building.integrity;
+        }
+    }
+    if let Some(mut entry) = world.entry(entity) {
+        if let Ok(unit) = entry.get_component_mut::<Unit>() {
+            unit.spend_attack(&state.rules);
+        }
+    }
+    events.push(GameEvent::BuildingAttacked {
+        attacker: entity,
+        attacker_position: position,
+        position: target,
+        damage,
+        remaining_integrity,
+    });
+    if remaining_integrity <= 0 {
+        world.remove(building_entity);
+        events.push(GameEvent::BuildingDestroyed { position: target });
+        if let Some(garrison) = garrison {
+            eject_garrison(
+                world,
+                (entity, position),
+                garrison,
+                target,
+                damage / 2,
+                events,
+            );
+        }
+        if let Some(winner) = find_winner(world, state) {
+            events.push(GameEvent::GameOver { winner });
+            set_state(state, State::GameOver(winner));
+            return;
+        }
+    }
+    let next_state = state_after_attack(world, state, entity);
+    set_state(state, next_state);
+}
+
+/// Hits the unit of a collapsed building with `damage` and pushes it onto a free neighbour. A
+/// unit without a free neighbour is buried under the rubble.
+fn eject_garrison(
+    world: &mut World,
+    (attacker, attacker_position): (Entity, Hexagon),
+    entity: Entity,
+    position: Hexagon,
+    damage: i32,
+    events: &mut Vec<GameEvent>,
+) {
+    let integrity = match world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<Unit>().ok().copied())
+    {
+        None => return,
+        Some(unit) => unit.integrity - damage,
+    };
+    events.push(GameEvent::UnitAttacked {
+        attacker,
+        defender: entity,
+        attacker_player: get_owner(world, attacker),
+        defender_player: get_owner(world, entity),
+        attacker_position,
+        defender_position: position,
+        arc: AttackArc::Front,
+        damage,
+        remaining_integrity: integrity,
+    });
+    match free_neighbour(world, &position) {
+        Some(exit) if integrity > 0 => {
+            if let Some(mut entry) = world.entry(entity) {
+                if let Ok(unit) = entry.get_component_mut::<Unit>() {
+                    unit.integrity = integrity;
+                }
+            }
+            leave_garrison(world, entity, exit);
+            events.push(GameEvent::GarrisonChanged {
+                entity,
+                position,
+                active: false,
+            });
+            events.push(GameEvent::UnitMoved {
+                entity,
+                from: position,
+                to: exit,
+            });
+        }
+        _ => {
+            events.push(GameEvent::UnitDestroyed { entity, position });
+            world.remove(entity);
+        }
+    }
+}
+
+/// Drops the planned moves that cross the edge between `from` and `to`, the units stay where
+/// they are.
+fn interrupt_orders_across(
+    world: &mut World,
+    from: Hexagon,
+    to: Hexagon,
+    events: &mut Vec<GameEvent>,
+) {
+    let crosses = |start: &Hexagon, path: &[Hexagon]| {
+        std::iter::once(start)
+            .chain(path.iter())
+            .zip(path.iter())
+            .any(|(a, b)| (*a == from && *b == to) || (*a == to && *b == from))
+    };
+    let interrupted: Vec<(Entity, Hexagon)> = <(Entity, &Hexagon, &MoveOrder)>::query()
+        .iter(world)
+        .filter(|(_, position, order)| crosses(position, &order.path))
+        .map(|(entity, position, _)| (*entity, *position))
+        .collect();
+    for (entity, position) in interrupted {
+        if let Some(mut entry) = world.entry(entity) {
+            entry.remove_component::<MoveOrder>();
+        }
+        events.push(GameEvent::OrderInterrupted { entity, position });
+    }
+}
+
+/// Drops the overwatch of the units of `player` that did not get to fire.
+fn end_overwatch(world: &mut World, player: usize, events: &mut Vec<GameEvent>) {
+    let ended: Vec<(Entity, Hexagon)> =
+        <(Entity, &PlayerComponent, &Hexagon, &Overwatching)>::query()
+            .iter(world)
+            .filter(|(_, owner, _, _)| owner.0 == player)
+            .map(|(entity, _, hexagon, _)| (*entity, *hexagon))
+            .collect();
+    for (entity, position) in ended {
+        if let Some(mut entry) = world.entry(entity) {
+            entry.remove_component::<Overwatching>();
+        }
+        events.push(GameEvent::OverwatchChanged {
+            entity,
+            position,
+            active: false,
+        });
+    }
+}
+
+/// Lets every enemy overwatching `position` fire at the unit that just entered it, in the order
+/// of their persistent ids. Returns whether anyone fired.
+fn fire_overwatch(
+    world: &mut World,
+    state: &mut GameState,
+    mover: Entity,
+    position: Hexagon,
+    events: &mut Vec<GameEvent>,
+) -> bool {
+    let mover_player = get_owner(world, mover);
+    let mut overwatchers: Vec<Entity> =
+        <(Entity, &PlayerComponent, &Hexagon, &Unit, &Overwatching)>::query()
+            .iter(world)
+            .filter(|(_, owner, hexagon, unit, _)| {
+                Some(owner.0) != mover_player
+                    && unit.can_attack(&state.rules)
+                    && unit.is_in_attack_range(hexagon.distance_to(&position))
+                    && is_line_of_sight_clear(hexagon, &position, &state.map_bounds, world)
+            })
+            .map(|(entity, _, _, _, _)| *entity)
+            .collect();
+    overwatchers.sort_by_key(|entity| PersistentId::of_entity(world, *entity));
+
+    let mut fired = false;
+    for overwatcher in overwatchers {
+        let (overwatching_unit, overwatcher_hexagon) = match get_unit_and_hexagon(
+            world,
+            overwatcher,
+            (
+                StateError::AttackerNotInWorld,
+                StateError::AttackerHasNoUnit,
+                StateError::AttackerHasNoHexagon,
+            ),
+        ) {
+            Err(_) => continue,
+            Ok(data) => data,
+        };
+        let moving_unit = match world
+            .entry_ref(mover)
+            .ok()
+            .and_then(|entry| entry.get_component::<Unit>().ok().copied())
+        {
+            // An earlier shot destroyed the unit.
+            None => break,
+            Some(unit) => unit,
+        };
+        if let Some(mut entry) = world.entry(overwatcher) {
+            entry.remove_component::<Overwatching>();
+        }
+        // Reaction fire is seen coming, it always hits the front.
+        let context = CombatContext {
+            arc: AttackArc::Front,
+            cover: cover_count(world, &overwatcher_hexagon, &position),
+        };
+        let result = match overwatching_unit.attack(&moving_unit, context, &state.rules) {
+            Err(_) => continue,
+            Ok(result) => result,
+        };
+        fired = true;
+        events.push(GameEvent::OverwatchChanged {
+            entity: overwatcher,
+            position: overwatcher_hexagon,
+            active: false,
+        });
+        events.push(GameEvent::UnitAttacked {
+            attacker: overwatcher,
+            defender: mover,
+            attacker_player: get_owner(world, overwatcher),
+            defender_player: mover_player,
+            attacker_position: overwatcher_hexagon,
+            defender_position: position,
+            arc: AttackArc::Front,
+            damage: result.actual_damage,
+            remaining_integrity: result.defender.integrity,
+        });
+        let mover_max_integrity = max_integrity(world, state, mover, moving_unit.integrity);
+        add_combat_feedback(
+            state,
+            events,
+            position,
+            result.actual_damage,
+            mover_max_integrity,
+            result.defender.integrity <= 0,
+        );
+        if result.defender.integrity <= 0 {
+            events.push(GameEvent::UnitDestroyed {
+                entity: mover,
+                position,
+            });
+        }
+        handle_attack_result(world, state.round, overwatcher, mover, result);
+    }
+    fired
+}
+
+fn resolve_attack(
+    world: &mut World,
+    state: &mut GameState,
+    attacker_entity: Entity,
+    defender_entity: Entity,
+    events: &mut Vec<GameEvent>,
+) {
+    let (attacking_unit, attacker_hexagon) = match get_unit_and_hexagon(
+        world,
+        attacker_entity,
+        (
+            StateError::AttackerNotInWorld,
+            StateError::AttackerHasNoUnit,
+            StateError::AttackerHasNoHexagon,
+        ),
+    ) {
+        Err(error) => {
+            events.push(GameEvent::Error(error));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Ok(data) => data,
+    };
+    let (defending_unit, defender_hexagon) = match get_unit_and_hexagon(
+        world,
+        defender_entity,
+        (
+            StateError::DefenderNotInWorld,
+            StateError::DefenderHasNoUnit,
+            StateError::DefenderHasNoHexagon,
+        ),
+    ) {
+        Err(error) => {
+            events.push(GameEvent::Error(error));
+            set_state(state, State::Waiting);
+            return;
+        }
+        Ok(data) => data,
+    };
+
+    let attacker_player = get_owner(world, attacker_entity);
+    let defender_player = get_owner(world, defender_entity);
+
+    let context = combat_context(world, &attacker_hexagon, defender_entity);
+    let attacker_perks = perks_of(world, attacker_entity);
+    let defender_perks = perks_of(world, defender_entity);
+
+    match attacker_perks
+        .strengthen(attacking_unit)
+        .attack(&defending_unit, context, &state.rules)
+    {
+        Ok(mut result) => {
+            // The bonus of the perks only counts for this attack.
+            result.attacker.damage = attacking_unit.damage;
+            events.push(GameEvent::UnitAttacked {
+                attacker: attacker_entity,
+                defender: defender_entity,
+                attacker_player,
+                defender_player,
+                attacker_position: attacker_hexagon,
+                defender_position: defender_hexagon,
+                arc: context.arc,
+                damage: result.actual_damage,
+                remaining_integrity: result.defender.integrity,
+            });
+            let defender_max_integrity =
+                max_integrity(world, state, defender_entity, defending_unit.integrity);
+            add_combat_feedback(
+                state,
+                events,
+                defender_hexagon,
+                result.actual_damage,
+                defender_max_integrity,
+                result.defender.integrity <= 0,
+            );
+            if result.defender.integrity <= 0 {
+                events.push(GameEvent::UnitDestroyed {
+                    entity: defender_entity,
+                    position: defender_hexagon,
+                });
+            } else if state.rules.counterattack
+                && result
+                    .defender
+                    .is_in_attack_range(defender_hexagon.distance_to(&attacker_hexagon))
+            {
+                // The attacker faces its target, counterattacks hit its front.
+                let damage = apply_cover(
+                    state.rules.damage(
+                        defender_perks.strengthen(result.defender).damage,
+                        result.attacker.armor,
+                    ),
+                    cover_count(world, &defender_hexagon, &attacker_hexagon),
+                );
+                let attacker_max_integrity =
+                    max_integrity(world, state, attacker_entity, result.attacker.integrity);
+                result.attacker.integrity -= damage;
+                events.push(GameEvent::UnitAttacked {
+                    attacker: defender_entity,
+                    defender: attacker_entity,
+                    attacker_player: defender_player,
+                    defender_player: attacker_player,
+                    attacker_position: defender_hexagon,
+                    defender_position: attacker_hexagon,
+                    arc: AttackArc::Front,
+                    damage,
+                    remaining_integrity: result.attacker.integrity,
+                });
+                add_combat_feedback(
+                    state,
+                    events,
+                    attacker_hexagon,
+                    damage,
+                    attacker_max_integrity,
+                    result.attacker.integrity <= 0,
+                );
+                if result.attacker.integrity <= 0 {
+                    events.push(GameEvent::UnitDestroyed {
+                        entity: attacker_entity,
+                        position: attacker_hexagon,
+                    });
+                }
+            }
+            handle_attack_result(world, state.round, attacker_entity, defender_entity, result);
+            if let Some(winner) = find_winner(world, state) {
+                events.push(GameEvent::GameOver { winner });
+                set_state(state, State::GameOver(winner));
+                return;
+            }
+            // The simultaneous rules carry out all attacks in one go, there is no pause for it.
+            if !state.rules.simultaneous {
+                if let Some(options) = promotion_options(world, attacker_entity) {
+                    events.push(GameEvent::PromotionAvailable {
+                        entity: attacker_entity,
+                        player: attacker_player,
+                        position: attacker_hexagon,
+                        options: options.clone(),
+                    });
+                    set_state(state, State::ChoosingPromotion(attacker_entity, options));
+                    return;
+                }
+            }
+        }
+        Err(error) => events.push(GameEvent::AttackFailed {
+            attacker: attacker_entity,
+            position: attacker_hexagon,
+            error,
+        }),
+    }
+    let next_state = state_after_attack(world, state, attacker_entity);
+    set_state(state, next_state);
+}
+
+/// Keeps the attacker selected while it survived and can still move or attack.
+fn state_after_attack<S: EntityStore>(world: &S, state: &GameState, attacker: Entity) -> State {
+    let can_act = world
+        .entry_ref(attacker)
+        .ok()
+        .and_then(|entry| entry.get_component::<Unit>().ok().copied())
+        .map_or(false, |unit| {
+            unit.movement_left(&state.rules) > 0 || unit.can_attack(&state.rules)
+        });
+    if can_act {
+        State::Selected(attacker)
+    } else {
+        State::Waiting
+    }
+}
+
+/// The integrity of a new unit of the entity's type with the bonus of its perks, or `current` if
+/// that is unknown.
+pub fn max_integrity<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    entity: Entity,
+    current: i32,
+) -> i32 {
+    let from_catalog = world.entry_ref(entity).ok().and_then(|entry| {
+        entry
+            .get_component::<UnitType>()
+            .ok()
+            .and_then(|unit_type| state.unit_catalog.get(&unit_type.0))
+            .map(|definition| definition.integrity + perks_of(world, entity).integrity_bonus())
+    });
+    from_catalog.unwrap_or(current).max(current)
+}
+
+/// Reports how a hit should feel and pauses moving units for heavy hits.
+fn add_combat_feedback(
+    state: &mut GameState,
+    events: &mut Vec<GameEvent>,
+    position: Hexagon,
+    damage: i32,
+    max_integrity: i32,
+    lethal: bool,
+) {
+    let feedback = combat_feedback(damage, max_integrity, lethal);
+    state.hitstop_timer = add_hitstop(state.hitstop_timer, feedback.hitstop, state.hitstop_cap);
+    events.push(GameEvent::CombatFeedback {
+        position,
+        shake: feedback.shake,
+        hitstop: feedback.hitstop,
+    });
+}
+
+/// Takes the steps the time in `state.movement` pays for. The state only changes when a step was
+/// taken or the move ends, a frame between two steps just keeps the added time.
+fn advance_movement(
+    world: &mut World,
+    state: &mut GameState,
+    entity: Entity,
+    events: &mut Vec<GameEvent>,
+) {
+    let mut stepped = false;
+    while state.movement.elapsed > SECONDS_PER_MOVEMENT {
+        let (unit, hexagon) = match get_unit_and_hexagon(
+            world,
+            entity,
+            (
+                StateError::MovingEntityNotInWorld,
+                StateError::MovingEntityHasNoUnit,
+                StateError::MovingEntityHasNoHexagon,
+            ),
+        ) {
+            Err(error) => {
+                events.push(GameEvent::Error(error));
+                set_state(state, State::Waiting);
+                return;
+            }
+            Ok(data) => data,
+        };
+
+        if unit.movement_cost_left(&state.rules) <= 0 {
+            set_state(state, State::Selected(entity));
+            return;
+        }
+
+        let mut next_hexagon = match state.movement.path.pop_front() {
+            None => {
+                events.push(GameEvent::Error(StateError::PathEmpty));
+                set_state(state, State::Selected(entity));
+                return;
+            }
+            Some(hexagon) => hexagon,
+        };
+
+        if !hexagon.is_neighbour(&next_hexagon) {
+            events.push(GameEvent::Error(StateError::PathNotAdjacent));
+            set_state(state, State::Selected(entity));
+            return;
+        }
+
+        if is_occupied_by_unit(&next_hexagon, world) {
+            let path = &state.movement.path;
+            let destination = path.back().copied().unwrap_or(next_hexagon);
+            let max_length = 2 * (path.len() as i32 + 1);
+            match reroute(
+                world,
+                hexagon,
+                destination,
+                max_length,
+                &state.edges,
+                &state.map_bounds,
+            )
+            .and_then(|mut new_path| {
+                let next = new_path.pop_front()?;
+                Some((next, new_path))
+            }) {
+                None => {
+                    events.push(GameEvent::OrderInterrupted {
+                        entity,
+                        position: hexagon,
+                    });
+                    set_state(state, State::Selected(entity));
+                    return;
+                }
+                Some((next, new_path)) => {
+                    next_hexagon = next;
+                    state.movement.path = new_path;
+                }
+            }
+        }
+
+        // The rest of the movement may not pay for the next hexagon if it is off the road or
+        // behind a river, and cliffs can not be climbed at all.
+        if !move_entity_to_hexagon(entity, &next_hexagon, world, &state.rules, &state.edges) {
+            set_state(state, State::Selected(entity));
+            return;
+        }
+        events.push(GameEvent::UnitMoved {
+            entity,
+            from: hexagon,
+            to: next_hexagon,
+        });
+        events.extend(collect_pickup(world, state, entity));
+        burn_on_entry(world, entity, events);
+
+        state.movement.elapsed -= SECONDS_PER_MOVEMENT;
+        stepped = true;
+
+        // Overwatch fire interrupts the move, it goes on in a later step if the unit survived.
+        if fire_overwatch(world, state, entity, next_hexagon, events) {
+            if world.entry_ref(entity).is_err() {
+                match find_winner(world, state) {
+                    Some(winner) => {
+                        events.push(GameEvent::GameOver { winner });
+                        set_state(state, State::GameOver(winner));
+                    }
+                    None => {
+                        set_state(state, State::Waiting);
+                    }
+                }
+                return;
+            }
+            state.movement.elapsed = 0.0;
+            break;
+        }
+    }
+    if state.movement.path.is_empty() {
+        set_state(state, State::Selected(entity));
+    } else if stepped {
+        // Redraws the grid and the overlays for the new position.
+        set_state(state, State::Moving(entity));
+    }
+}
+
+/// Finds a new path from `start` to `destination` of at most `max_length` steps, for a unit whose
+/// path got blocked. If the destination itself is occupied, the free hexagon closest to it is
+/// used instead, but only if that is closer than `start`, so blocked units do not wander around.
+fn reroute<S: EntityStore>(
+    world: &S,
+    start: Hexagon,
+    destination: Hexagon,
+    max_length: i32,
+    edges: &EdgeData,
+    bounds: &MapBounds,
+) -> Option<VecDeque<Hexagon>> {
+    let target = if is_occupied_by_unit(&destination, world) {
+        let current_distance = start.distance_to(&destination);
+        get_reachable_hexagons(&start, max_length, bounds, world)
+            .into_iter()
+            .filter(|hexagon| hexagon.distance_to(&destination) < current_distance)
+            .min_by_key(|hexagon| {
+                (
+                    hexagon.distance_to(&destination),
+                    hexagon.distance_to(&start),
+                    hexagon.get_q(),
+                    hexagon.get_r(),
+                )
+            })?
+    } else {
+        destination
+    };
+    let path = find_path_within(&start, &target, max_length, edges, bounds, world);
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.into_iter().collect())
+    }
+}
+
+/// Returns `Some(winner)` once at most one player has units left, `winner` being `None` if no
+/// player has any units.
+pub fn find_winner<S: EntityStore>(world: &S, state: &GameState) -> Option<Option<usize>> {
+    let mut has_units = vec![false; state.players.len()];
+    for (player, _) in <(&PlayerComponent, &Unit)>::query().iter(world) {
+        if let Some(flag) = has_units.get_mut(player.0) {
+            *flag = true;
+        }
+    }
+    let mut remaining = has_units
+        .iter()
+        .enumerate()
+        .filter(|(_, has_units)| **has_units)
+        .map(|(player, _)| player);
+    match (remaining.next(), remaining.next()) {
+        (first, None) => Some(first),
+        _ => None,
+    }
+}
+
+/// Moves the unit to `hexagon` if its movement pays for it. A step to a neighbour costs what
+/// entering that hexagon and crossing the edge to it costs, longer jumps cost a full hexagon per
+/// hexagon of distance. Returns whether the unit moved.
+pub fn move_entity_to_hexagon(
+    entity: Entity,
+    hexagon: &Hexagon,
+    world: &mut World,
+    rules: &Ruleset,
+    edges: &EdgeData,
+) -> bool {
+    let costs = MovementCosts::new(world, movement_type_of(world, entity), edges);
+    let mut entry = match world.entry(entity) {
+        None => {
+            log_error!("Entity not found in world");
+            return false;
+        }
+        Some(e) => e,
+    };
+    let selected_unit = *entry.get_component::<Unit>().unwrap();
+    let selected_hexagon = *entry.get_component::<Hexagon>().unwrap();
+    let distance = selected_hexagon.distance_to(&hexagon);
+    let cost = if distance == 1 {
+        match costs.step_cost(&selected_hexagon, hexagon) {
+            None => return false,
+            Some(cost) => cost,
+        }
+    } else {
+        distance * HEXAGON_COST
+    };
+    match selected_unit.is_in_movement_range(cost, rules) {
+        CanMove::Yes(_) if distance > 0 => {
+            let updated_hexagon = Hexagon::new_axial(hexagon.get_q(), hexagon.get_r());
+            let mut updated_selected_unit = selected_unit;
+            updated_selected_unit.spend_movement_cost(cost, rules);
+            updated_selected_unit.moved = true;
+            entry.add_component(updated_selected_unit);
+            entry.add_component(updated_hexagon);
+            if let Some(direction) = selected_hexagon.direction_to(&updated_hexagon) {
+                entry.add_component(Facing(direction));
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The unit of `entity` as it takes part in an attack. None without a unit or a position.
+fn get_combatant(world: &World, entity: Entity) -> Option<Combatant> {
+    let entry = world.entry_ref(entity).ok()?;
+    Some(Combatant {
+        position: *entry.get_component::<Hexagon>().ok()?,
+        player: entry
+            .get_component::<PlayerComponent>()
+            .ok()
+            .map(|player| player.0),
+        integrity: entry.get_component::<Unit>().ok()?.integrity,
+    })
+}
+
+/// Appends the entries to the history of the entity, if it still exists and keeps one.
+fn append_history(world: &mut World, entity: Entity, entries: Vec<HistoryEntry>) {
+    if let Some(mut entry) = world.entry(entity) {
+        if let Ok(history) = entry.get_component_mut::<History>() {
+            for history_entry in entries {
+                history.push(history_entry);
+            }
+        }
+    }
+}
+
+pub fn handle_attack_result(
+    world: &mut World,
+    round: u32,
+    attacker: Entity,
+    defender: Entity,
+    result: AttackResult,
+) {
+    award_kills(world, attacker, defender, &result);
+    let combatants = get_combatant(world, attacker).zip(get_combatant(world, defender));
+    if let Some((attacking, defending)) = combatants {
+        let (attacker_entries, defender_entries) =
+            attack_entries(round, &attacking, &defending, &result);
+        append_history(world, attacker, attacker_entries);
+        append_history(world, defender, defender_entries);
+    }
+    match world.entry(attacker) {
+        None => {}
+        Some(mut e) => {
+            if result.attacker.integrity <= 0 {
+                world.remove(attacker);
+            } else {
+                e.add_component(result.attacker);
+            }
+        }
+    }
+
+    match world.entry(defender) {
+        None => {}
+        Some(mut e) => {
+            if result.defender.integrity <= 0 {
+                world.remove(defender);
+            } else {
+                e.add_component(result.defender);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buildings::{building_at, enter_garrison, spawn_building};
+    use crate::combat_feedback::{MAX_HITSTOP, MAX_SHAKE};
+    use crate::components::building::Building;
+    use crate::components::facing::Facing;
+    use crate::components::field::Field;
+    use crate::components::garrison::Garrisoned;
+    use crate::components::ground_effect::GroundEffect;
+    use crate::components::hexagon::{Direction, Hexagon};
+    use crate::components::history::History;
+    use crate::components::initiative::Initiative;
+    use crate::components::orders::MoveOrder;
+    use crate::components::overwatch::Overwatching;
+    use crate::components::perks::{PerkId, Perks};
+    use crate::components::persistent_id::PersistentId;
+    use crate::components::player::Player as PlayerComponent;
+    use crate::components::terrain::Terrain;
+    use crate::components::unit::{AttackError, AttackResult, Unit};
+    use crate::edges::{EdgeData, EdgeKind, BRIDGE_INTEGRITY};
+    use crate::flanking::AttackArc;
+    use crate::game_state::{force_move, GameState, MovementProgress, State};
+    use crate::ground_effects::spawn_ground_effect;
+    use crate::player::Player;
+    use crate::promotions::perks_of;
+    use crate::rules::Ruleset;
+    use crate::state_machine::*;
+    use crate::systems::hexgrid::find_path_within;
+    use gdnative::core_types::Color;
+    use legion::{Entity, World, WorldOptions};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::collections::vec_deque::VecDeque;
+
+    #[test]
+    fn handle_attack_result_updates_components() {
+        let mut world = World::new(WorldOptions::default());
+        let attacker = *world
+            .extend(vec![(Unit::new(1, 1, 0, 0, 0, 0, 0, 1),)])
+            .first()
+            .unwrap();
+        let defender = *world
+            .extend(vec![(Unit::new(2, 1, 0, 0, 0, 0, 0, 0),)])
+            .first()
+            .unwrap();
+        let result = AttackResult {
+            attacker: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
+            defender: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
+            actual_damage: 1,
+        };
+
+        handle_attack_result(&mut world, 1, attacker, defender, result);
+
+        let entry = world.entry(attacker).unwrap();
+        let changed_attacker = entry.get_component::<Unit>().unwrap();
+        assert_eq!(changed_attacker.remaining_attacks, 0);
+
+        let entry = world.entry(defender).unwrap();
+        let changed_defender = entry.get_component::<Unit>().unwrap();
+        assert_eq!(changed_defender.integrity, 1);
+    }
+
+    #[test]
+    fn handle_attack_result_records_history() {
+        let mut world = World::default();
+        let attacker = world.push((
+            Unit::new(1, 1, 0, 0, 0, 0, 0, 1),
+            Hexagon::new_axial(0, 0),
+            PlayerComponent(0),
+            History::default(),
+        ));
+        let defender = world.push((
+            Unit::new(2, 1, 0, 0, 0, 0, 0, 0),
+            Hexagon::new_axial(1, 0),
+            PlayerComponent(1),
+            History::default(),
+        ));
+        let result = AttackResult {
+            attacker: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
+            defender: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
+            actual_damage: 1,
+        };
+
+        handle_attack_result(&mut world, 4, attacker, defender, result);
+
+        let entry = world.entry(defender).unwrap();
+        let history = entry.get_component::<History>().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.iter().next().unwrap().round, 4);
+        let entry = world.entry(attacker).unwrap();
+        assert!(entry.get_component::<History>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn handle_attack_result_removes_defender_when_integrity_lower_or_eq_0() {
+        let mut world = World::new(WorldOptions::default());
+        let attacker = *world
+            .extend(vec![(Unit::new(1, 2, 0, 0, 0, 0, 0, 1),)])
+            .first()
+            .unwrap();
+        let defender = *world
+            .extend(vec![(Unit::new(2, 1, 0, 0, 0, 0, 0, 0),)])
+            .first()
+            .unwrap();
+        let result = AttackResult {
+            attacker: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
+            defender: Unit::new(0, 1, 0, 0, 0, 0, 0, 0),
+            actual_damage: 1,
+        };
+
+        handle_attack_result(&mut world, 1, attacker, defender, result);
+
+        assert!(!world.contains(defender));
+    }
+
+    #[test]
+    fn handle_attack_result_removes_attacker_when_integrity_lower_or_eq_0() {
+        let mut world = World::new(WorldOptions::default());
+        let attacker = world.push((Unit::new(1, 2, 0, 0, 0, 0, 0, 1),));
+        let defender = world.push((Unit::new(2, 1, 0, 0, 0, 0, 0, 0),));
+        let result = AttackResult {
+            attacker: Unit::new(0, 2, 0, 0, 0, 0, 0, 0),
+            defender: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
+            actual_damage: 1,
+        };
+
+        handle_attack_result(&mut world, 1, attacker, defender, result);
+
+        assert!(!world.contains(attacker));
+        assert!(world.contains(defender));
+    }
+
+    #[test]
+    fn handle_attack_results_only_changes_affected_fields() {
+        let mut world = World::new(WorldOptions::default());
+        let attacking_unit = Unit::new(1, 1, 2, 4, 5, 3, 0, 1);
+        let attacker = *world.extend(vec![(attacking_unit,)]).first().unwrap();
+        let defending_unit = Unit::new(2, 4, 5, 3, 2, 4, 0, 0);
+        let defender = *world.extend(vec![(defending_unit,)]).first().unwrap();
+        let result = AttackResult {
+            attacker: attacking_unit,
+            defender: defending_unit,
+            actual_damage: 1,
+        };
+
+        handle_attack_result(&mut world, 1, attacker, defender, result);
+
+        let entry = world.entry(attacker).unwrap();
+        let changed_attacker = entry.get_component::<Unit>().unwrap();
+        assert_eq!(changed_attacker.damage, attacking_unit.damage);
+        assert_eq!(
+            changed_attacker.max_attack_range,
+            attacking_unit.max_attack_range
+        );
+        assert_eq!(
+            changed_attacker.min_attack_range,
+            attacking_unit.min_attack_range
+        );
+        assert_eq!(changed_attacker.armor, attacking_unit.armor);
+        assert_eq!(changed_attacker.mobility, attacking_unit.mobility);
+
+        let entry = world.entry(defender).unwrap();
+        let changed_defender = entry.get_component::<Unit>().unwrap();
+        assert_eq!(changed_defender.damage, defending_unit.damage);
+        assert_eq!(
+            changed_defender.max_attack_range,
+            defending_unit.max_attack_range
+        );
+        assert_eq!(
+            changed_defender.min_attack_range,
+            defending_unit.min_attack_range
+        );
+        assert_eq!(changed_defender.armor, defending_unit.armor);
+        assert_eq!(changed_defender.mobility, defending_unit.mobility);
+    }
+
+    #[test]
+    fn move_entity_to_hexagon_updates_entity() {
+        let mut world = World::new(WorldOptions::default());
+        let entity = *world
+            .extend(vec![(
+                Hexagon::new_axial(0, 0),
+                Unit::new(0, 0, 0, 0, 0, 0, 2, 0),
+            )])
+            .first()
+            .unwrap();
+
+        move_entity_to_hexagon(
+            entity,
+            &Hexagon::new_axial(1, 1),
+            &mut world,
+            &Ruleset::default(),
+            &EdgeData::default(),
+        );
+
+        let entry = world.entry(entity).unwrap();
+        let hexagon = entry.get_component::<Hexagon>().unwrap();
+        assert_eq!(hexagon.get_q(), 1);
+        assert_eq!(hexagon.get_r(), 1);
+    }
+
+    #[test]
+    fn move_entity_to_hexagon_does_nothing_if_entity_cannot_move() {
+        let mut world = World::default();
+        let entity = *world
+            .extend(vec![(
+                Hexagon::new_axial(5, 5),
+                Unit::new(0, 0, 0, 0, 0, 0, 1, 0),
+            )])
+            .first()
+            .unwrap();
+
+        move_entity_to_hexagon(
+            entity,
+            &Hexagon::new_axial(1, 1),
+            &mut world,
+            &Ruleset::default(),
+            &EdgeData::default(),
+        );
+
+        let entry = world.entry(entity).unwrap();
+        let hexagon = entry.get_component::<Hexagon>().unwrap();
+        assert_eq!(hexagon.get_q(), 5);
+        assert_eq!(hexagon.get_r(), 5);
+    }
+
+    #[test]
+    fn moving_along_a_road_uses_up_the_range_exactly() {
+        let mut world = World::default();
+        for q in 1..=7 {
+            let mut field = Field::new(Hexagon::new_axial(q, 0));
+            field.terrain = Terrain::Road;
+            world.push((field,));
+        }
+        let entity = world.push((Hexagon::zero(), Unit::new(0, 0, 0, 0, 0, 3, 3, 0)));
+        let rules = Ruleset::default();
+
+        for q in 1..=6 {
+            assert!(move_entity_to_hexagon(
+                entity,
+                &Hexagon::new_axial(q, 0),
+                &mut world,
+                &rules,
+                &EdgeData::default()
+            ));
+        }
+        assert!(!move_entity_to_hexagon(
+            entity,
+            &Hexagon::new_axial(7, 0),
+            &mut world,
+            &rules,
+            &EdgeData::default()
+        ));
+
+        let entry = world.entry(entity).unwrap();
+        let unit = entry.get_component::<Unit>().unwrap();
+        assert_eq!(
+            *entry.get_component::<Hexagon>().unwrap(),
+            Hexagon::new_axial(6, 0)
+        );
+        assert_eq!((unit.remaining_range, unit.partial_movement), (0, 0));
+    }
+
+    #[test]
+    fn half_a_hexagon_does_not_pay_for_open_terrain() {
+        let mut world = World::default();
+        let mut field = Field::new(Hexagon::new_axial(1, 0));
+        field.terrain = Terrain::Road;
+        world.push((field,));
+        let entity = world.push((Hexagon::zero(), Unit::new(0, 0, 0, 0, 0, 1, 1, 0)));
+        let rules = Ruleset::default();
+
+        assert!(move_entity_to_hexagon(
+            entity,
+            &Hexagon::new_axial(1, 0),
+            &mut world,
+            &rules,
+            &EdgeData::default()
+        ));
+        assert!(!move_entity_to_hexagon(
+            entity,
+            &Hexagon::new_axial(2, 0),
+            &mut world,
+            &rules,
+            &EdgeData::default()
+        ));
+        let entry = world.entry(entity).unwrap();
+        assert_eq!(entry.get_component::<Unit>().unwrap().partial_movement, 1);
+    }
+
+    fn new_state(players: usize) -> GameState {
+        let mut state = GameState::new();
+        for index in 0..players {
+            state.players.push(Player::new(
+                format!("Player {}", index + 1),
+                Color::rgb(1.0, 1.0, 1.0),
+            ));
+        }
+        state.current_player = Some(0);
+        state
+    }
+
+    fn spawn(world: &mut World, player: usize, q: i32, r: i32, unit: Unit) -> Entity {
+        world.push((PlayerComponent(player), Hexagon::new_axial(q, r), unit))
+    }
+
+    fn removed_entity(world: &mut World) -> Entity {
+        let entity = world.push((Hexagon::zero(),));
+        world.remove(entity);
+        entity
+    }
+
+    fn path(hexagons: &[Hexagon]) -> VecDeque<Hexagon> {
+        hexagons.iter().copied().collect()
+    }
+
+    #[test]
+    fn advance_state_leaves_startup() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert!(events.is_empty());
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn new_round_refreshes_units_and_advances_player() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let unit = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 3, 0, 0));
+        state.current_player = Some(1);
+        state.state = State::NewRound;
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::TurnStarted {
+                round: 2,
+                player: 0
+            }]
+        );
+        assert_eq!(state.state, State::Waiting);
+        let entry = world.entry(unit).unwrap();
+        let unit = entry.get_component::<Unit>().unwrap();
+        assert_eq!(unit.remaining_range, 3);
+        assert_eq!(unit.remaining_attacks, 1);
+    }
+
+    #[test]
+    fn turn_transition_ends_after_banner_duration() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        state.banner_duration = 1.0;
+        state.state = State::NewRound;
+
+        advance_state(&mut world, &mut state, 0.0);
+        assert_eq!(state.state, State::TurnTransition(1.0));
+
+        advance_state(&mut world, &mut state, 0.75);
+        assert_eq!(state.state, State::TurnTransition(0.25));
+
+        advance_state(&mut world, &mut state, 0.5);
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn turn_transition_can_be_dismissed() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        state.banner_duration = 3.0;
+        state.state = State::NewRound;
+        advance_state(&mut world, &mut state, 0.0);
+
+        assert!(dismiss_turn_transition(&mut state));
+        assert_eq!(state.state, State::Waiting);
+        assert!(!dismiss_turn_transition(&mut state));
+    }
+
+    #[test]
+    fn privacy_screen_waits_for_confirmation() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        state.privacy_screen = true;
+        state.state = State::NewRound;
+
+        advance_state(&mut world, &mut state, 0.0);
+        advance_state(&mut world, &mut state, 10.0);
+
+        assert_eq!(state.state, State::TurnTransition(0.0));
+        assert!(state.is_view_hidden());
+
+        assert!(dismiss_turn_transition(&mut state));
+        assert_eq!(state.state, State::Waiting);
+        assert!(!state.is_view_hidden());
+    }
+
+    #[test]
+    fn new_round_skips_observers() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        state.players.insert(
+            1,
+            Player::new_observer("Observer".to_owned(), Color::rgb(1.0, 1.0, 1.0)),
+        );
+        state.current_player = Some(0);
+        state.state = State::NewRound;
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::TurnStarted {
+                round: 1,
+                player: 2
+            }]
+        );
+
+        state.players.insert(
+            0,
+            Player::new_observer("Observer".to_owned(), Color::rgb(1.0, 1.0, 1.0)),
+        );
+        state.current_player = Some(3);
+        state.state = State::NewRound;
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::TurnStarted {
+                round: 2,
+                player: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn attacking_with_missing_attacker_returns_to_waiting() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = removed_entity(&mut world);
+        let defender = spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        state.state = State::Attacking(attacker, defender);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Error(StateError::AttackerNotInWorld)]
+        );
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn attacking_with_attacker_without_unit_returns_to_waiting() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = world.push((PlayerComponent(0), Hexagon::zero()));
+        let defender = spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        state.state = State::Attacking(attacker, defender);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Error(StateError::AttackerHasNoUnit)]
+        );
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn attacking_with_missing_defender_keeps_attacker_selected() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        let defender = removed_entity(&mut world);
+        state.state = State::Attacking(attacker, defender);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Error(StateError::DefenderNotInWorld)]
+        );
+        assert_eq!(state.state, State::Selected(attacker));
+    }
+
+    #[test]
+    fn removed_selected_unit_returns_to_waiting_and_clears_overlays() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let selected = removed_entity(&mut world);
+        let enemy = spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        let mut field = Field::new(Hexagon::new_axial(1, 0));
+        field.moveable = true;
+        field.attackable = true;
+        let field = world.push((field,));
+        state.state = State::Selected(selected);
+        state.attackable_entities.insert(enemy);
+        state.current_path = vec![Hexagon::zero(), Hexagon::new_axial(1, 0)];
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Error(StateError::SelectedEntityNotInWorld)]
+        );
+        assert_eq!(state.state, State::Waiting);
+        assert!(state.attackable_entities.is_empty());
+        assert!(state.current_path.is_empty());
+        let field = *world
+            .entry(field)
+            .unwrap()
+            .get_component::<Field>()
+            .unwrap();
+        assert!(!field.moveable && !field.attackable);
+
+        assert!(advance_state(&mut world, &mut state, 0.0).is_empty());
+    }
+
+    #[test]
+    fn attacking_defender_without_unit_returns_to_waiting() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        let defender = world.push((PlayerComponent(1), Hexagon::new_axial(1, 0)));
+        state.state = State::Attacking(attacker, defender);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Error(StateError::DefenderHasNoUnit)]
+        );
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn attacking_reports_damage_and_destruction() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(5, 6, 1, 1, 0, 1, 1, 1));
+        let defender = spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        spawn(&mut world, 1, 3, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        state.state = State::Attacking(attacker, defender);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![
+                GameEvent::UnitAttacked {
+                    attacker,
+                    defender,
+                    attacker_player: Some(0),
+                    defender_player: Some(1),
+                    attacker_position: Hexagon::new_axial(0, 0),
+                    defender_position: Hexagon::new_axial(1, 0),
+                    arc: AttackArc::Front,
+                    damage: 6,
+                    remaining_integrity: -1,
+                },
+                GameEvent::CombatFeedback {
+                    position: Hexagon::new_axial(1, 0),
+                    shake: MAX_SHAKE,
+                    hitstop: MAX_HITSTOP,
+                },
+                GameEvent::UnitDestroyed {
+                    entity: defender,
+                    position: Hexagon::new_axial(1, 0),
+                },
+            ]
+        );
+        assert!(!world.contains(defender));
+        assert_eq!(state.hitstop_timer, MAX_HITSTOP);
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn attacking_without_attacks_left_reports_failure() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(5, 6, 1, 1, 0, 1, 1, 0));
+        let defender = spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        state.state = State::Attacking(attacker, defender);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::AttackFailed {
+                attacker,
+                position: Hexagon::zero(),
+                error: AttackError::NoAttacksLeft,
+            }]
+        );
+        // It can still move.
+        assert_eq!(state.state, State::Selected(attacker));
+    }
+
+    #[test]
+    fn attacker_with_actions_left_stays_selected() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 2));
+        let defender = spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        state.state = State::Attacking(attacker, defender);
+
+        advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(state.state, State::Selected(attacker));
+        assert_eq!(state.last_selected, Some(attacker));
+    }
+
+    fn artillery(unit: Unit) -> Unit {
+        Unit {
+            fire_after_move: false,
+            ..unit
+        }
+    }
+
+    fn move_to(world: &mut World, state: &mut GameState, entity: Entity, target: Hexagon) {
+        force_move(state, entity, path(&[target]));
+        for _ in 0..5 {
+            advance_state(world, state, SECONDS_PER_MOVEMENT);
+        }
+        assert_eq!(position_of(world, entity), target);
+        assert_eq!(state.state, State::Selected(entity));
+    }
+
+    #[test]
+    fn units_that_moved_cannot_fire_if_they_fire_before_moving() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(
+            &mut world,
+            0,
+            0,
+            0,
+            artillery(Unit::new(5, 6, 2, 1, 0, 2, 2, 1)),
+        );
+        let defender = spawn(&mut world, 1, 3, 0, Unit::new(20, 1, 1, 1, 0, 1, 1, 1));
+        move_to(&mut world, &mut state, attacker, Hexagon::new_axial(1, 0));
+
+        state.state = State::Attacking(attacker, defender);
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::AttackFailed {
+                attacker,
+                position: Hexagon::new_axial(1, 0),
+                error: AttackError::MovedBeforeFiring,
+            }]
+        );
+        assert_eq!(state.state, State::Selected(attacker));
+    }
+
+    #[test]
+    fn units_that_fire_before_moving_may_move_after_firing() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        state.rules = Ruleset {
+            action_points: true,
+            attack_cost: 1,
+            ..Ruleset::default()
+        };
+        let mut unit = artillery(Unit::new(5, 6, 2, 1, 0, 2, 2, 1));
+        unit.action_points = 3;
+        let attacker = spawn(&mut world, 0, 0, 0, unit);
+        let defender = spawn(&mut world, 1, 2, 0, Unit::new(20, 1, 1, 1, 0, 1, 1, 1));
+
+        state.state = State::Attacking(attacker, defender);
+        let events = advance_state(&mut world, &mut state, 0.0);
+        assert!(matches!(events[0], GameEvent::UnitAttacked { .. }));
+        assert_eq!(state.state, State::Selected(attacker));
+
+        move_to(&mut world, &mut state, attacker, Hexagon::new_axial(-1, 0));
+        // Having moved, it holds its fire for the rest of the round.
+        let unit = *world
+            .entry_ref(attacker)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap();
+        assert!(unit.moved);
+        assert_eq!(unit.action_points, 1);
+        assert_eq!(
+            unit.attack_error(&state.rules),
+            Some(AttackError::MovedBeforeFiring)
+        );
+    }
+
+    #[test]
+    fn attacker_destroyed_by_the_counterattack_returns_to_waiting() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        state.rules.counterattack = true;
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(1, 1, 1, 1, 0, 1, 1, 2));
+        spawn(&mut world, 0, -3, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        let defender = spawn(&mut world, 1, 1, 0, Unit::new(5, 5, 1, 1, 0, 1, 1, 1));
+        state.state = State::Attacking(attacker, defender);
+
+        advance_state(&mut world, &mut state, 0.0);
+
+        assert!(!world.contains(attacker));
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn the_second_kill_waits_for_a_promotion() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(5, 6, 1, 1, 0, 1, 1, 3));
+        let first = spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        let second = spawn(&mut world, 1, 0, 1, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        let third = spawn(&mut world, 1, -1, 0, Unit::new(10, 1, 1, 1, 0, 1, 1, 1));
+        state.state = State::Attacking(attacker, first);
+        advance_state(&mut world, &mut state, 0.0);
+        assert_eq!(state.state, State::Selected(attacker));
+
+        state.state = State::Attacking(attacker, second);
+        let events = advance_state(&mut world, &mut state, 0.0);
+        let options = vec![PerkId::Damage, PerkId::Integrity];
+        assert!(events.contains(&GameEvent::PromotionAvailable {
+            entity: attacker,
+            player: Some(0),
+            position: Hexagon::zero(),
+            options: options.clone(),
+        }));
+        assert_eq!(
+            state.state,
+            State::ChoosingPromotion(attacker, options.clone())
+        );
+        // The game waits for the pick.
+        advance_state(&mut world, &mut state, 1.0);
+        assert_eq!(state.state, State::ChoosingPromotion(attacker, options));
+
+        state.state = State::Promoting(attacker, PerkId::Damage);
+        advance_state(&mut world, &mut state, 0.0);
+        assert_eq!(state.state, State::Selected(attacker));
+        assert_eq!(perks_of(&world, attacker), Perks(vec![PerkId::Damage]));
+
+        state.state = State::Attacking(attacker, third);
+        let events = advance_state(&mut world, &mut state, 0.0);
+        assert!(matches!(
+            events[0],
+            GameEvent::UnitAttacked {
+                damage: 7,
+                remaining_integrity: 3,
+                ..
+            }
+        ));
+        // The bonus only counts while attacking.
+        let unit = *world
+            .entry_ref(attacker)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap();
+        assert_eq!(unit.damage, 6);
+    }
+
+    #[test]
+    fn destroying_the_last_enemy_ends_the_game() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(5, 6, 1, 1, 0, 1, 1, 1));
+        let defender = spawn(&mut world, 1, 1, 0, Unit::new(5, 1, 1, 1, 0, 1, 1, 1));
+        state.state = State::Attacking(attacker, defender);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events.last(),
+            Some(&GameEvent::GameOver { winner: Some(0) })
+        );
+        assert_eq!(state.state, State::GameOver(Some(0)));
+    }
+
+    #[test]
+    fn moving_waits_until_enough_time_has_passed() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 2, 2, 1));
+        let steps = path(&[Hexagon::new_axial(1, 0)]);
+        force_move(&mut state, entity, steps.clone());
+
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT / 2.0);
+
+        assert!(events.is_empty());
+        assert_eq!(state.state, State::Moving(entity));
+        assert_eq!(
+            state.movement,
+            MovementProgress {
+                path: steps,
+                elapsed: SECONDS_PER_MOVEMENT / 2.0,
+            }
+        );
+    }
+
+    #[test]
+    fn hit_pause_freezes_moving_units() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 2, 2, 1));
+        let steps = path(&[Hexagon::new_axial(1, 0)]);
+        force_move(&mut state, entity, steps.clone());
+        state.hitstop_timer = SECONDS_PER_MOVEMENT * 1.5;
+
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 2.0);
+
+        assert!(events.is_empty());
+        assert_eq!(state.state, State::Moving(entity));
+        assert_eq!(state.movement, MovementProgress::new(steps));
+        assert_eq!(state.hitstop_timer, 0.0);
+
+        advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 1.5);
+
+        assert_eq!(position_of(&world, entity), Hexagon::new_axial(1, 0));
+    }
+
+    #[test]
+    fn moving_steps_once_the_frames_add_up() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 5, 5, 1));
+        force_move(&mut state, entity, straight_path());
+        state.state_changes.clear();
+
+        let mut steps = Vec::new();
+        let mut redraws = Vec::new();
+        for frame in 1..=12 {
+            state.redraw_grid = false;
+            let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 0.35);
+            if !events.is_empty() {
+                steps.push(frame);
+            }
+            if state.redraw_grid {
+                redraws.push(frame);
+            }
+        }
+
+        // The time left over from a step counts towards the next one.
+        assert_eq!(steps, vec![3, 6, 9]);
+        assert_eq!(redraws, steps);
+        assert_eq!(position_of(&world, entity), Hexagon::new_axial(3, 0));
+        assert_eq!(state.state_changes, vec![("Moving", "Selected")]);
+    }
+
+    /// Counts the allocations of the current thread, the tests running next to it do not change
+    /// the count.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    #[test]
+    fn frames_between_steps_do_not_allocate() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 40, 40, 1));
+        let steps: VecDeque<Hexagon> = (1..=30).map(|q| Hexagon::new_axial(q, 0)).collect();
+        force_move(&mut state, entity, steps);
+
+        let mut idle_frames = 0;
+        let mut idle_allocations = 0;
+        for _ in 0..1000 {
+            if state.state != State::Moving(entity) {
+                break;
+            }
+            let before = allocations();
+            let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT / 16.0);
+            if events.is_empty() {
+                idle_frames += 1;
+                idle_allocations += allocations() - before;
+            }
+        }
+
+        assert_eq!(position_of(&world, entity), Hexagon::new_axial(30, 0));
+        assert!(idle_frames > 300);
+        assert_eq!(idle_allocations, 0);
+    }
+
+    #[test]
+    fn moving_steps_along_path_and_selects_unit_at_the_end() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 2, 2, 1));
+        force_move(
+            &mut state,
+            entity,
+            path(&[Hexagon::new_axial(1, 0), Hexagon::new_axial(2, 0)]),
+        );
+
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 2.5);
+
+        assert_eq!(
+            events,
+            vec![
+                GameEvent::UnitMoved {
+                    entity,
+                    from: Hexagon::new_axial(0, 0),
+                    to: Hexagon::new_axial(1, 0),
+                },
+                GameEvent::UnitMoved {
+                    entity,
+                    from: Hexagon::new_axial(1, 0),
+                    to: Hexagon::new_axial(2, 0),
+                },
+            ]
+        );
+        assert_eq!(state.state, State::Selected(entity));
+    }
+
+    #[test]
+    fn moving_missing_entity_returns_to_waiting() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = removed_entity(&mut world);
+        force_move(&mut state, entity, path(&[Hexagon::new_axial(1, 0)]));
+
+        let events = advance_state(&mut world, &mut state, 1.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Error(StateError::MovingEntityNotInWorld)]
+        );
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn moving_entity_without_unit_returns_to_waiting() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = world.push((PlayerComponent(0), Hexagon::zero()));
+        force_move(&mut state, entity, path(&[Hexagon::new_axial(1, 0)]));
+
+        let events = advance_state(&mut world, &mut state, 1.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Error(StateError::MovingEntityHasNoUnit)]
+        );
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn moving_entity_without_hexagon_returns_to_waiting() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = world.push((PlayerComponent(0), Unit::new(5, 1, 1, 1, 0, 2, 2, 1)));
+        force_move(&mut state, entity, path(&[Hexagon::new_axial(1, 0)]));
+
+        let events = advance_state(&mut world, &mut state, 1.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Error(StateError::MovingEntityHasNoHexagon)]
+        );
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn moving_without_remaining_range_selects_unit() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 2, 0, 1));
+        force_move(&mut state, entity, path(&[Hexagon::new_axial(1, 0)]));
+
+        let events = advance_state(&mut world, &mut state, 1.0);
+
+        assert!(events.is_empty());
+        assert_eq!(state.state, State::Selected(entity));
+    }
+
+    #[test]
+    fn moving_along_empty_path_selects_unit() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 2, 2, 1));
+        force_move(&mut state, entity, VecDeque::new());
+
+        let events = advance_state(&mut world, &mut state, 1.0);
+
+        assert_eq!(events, vec![GameEvent::Error(StateError::PathEmpty)]);
+        assert_eq!(state.state, State::Selected(entity));
+    }
+
+    #[test]
+    fn moving_to_non_adjacent_hexagon_selects_unit() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 2, 2, 1));
+        force_move(&mut state, entity, path(&[Hexagon::new_axial(2, 0)]));
+
+        let events = advance_state(&mut world, &mut state, 1.0);
+
+        assert_eq!(events, vec![GameEvent::Error(StateError::PathNotAdjacent)]);
+        assert_eq!(state.state, State::Selected(entity));
+        let entry = world.entry(entity).unwrap();
+        assert_eq!(
+            *entry.get_component::<Hexagon>().unwrap(),
+            Hexagon::new_axial(0, 0)
+        );
+    }
+
+    fn position_of(world: &World, entity: Entity) -> Hexagon {
+        *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Hexagon>()
+            .unwrap()
+    }
+
+    #[test]
+    fn moving_through_a_fire_burns_the_unit() {
+        let mut world = World::default();
+        let mut state = new_state(1);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(10, 1, 1, 1, 0, 5, 5, 1));
+        let fire = GroundEffect::Fire {
+            damage_per_round: 3,
+            rounds_left: 2,
+        };
+        spawn_ground_effect(&mut world, Hexagon::new_axial(1, 0), fire, false);
+        force_move(&mut state, entity, straight_path());
+
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 3.5);
+
+        assert_eq!(position_of(&world, entity), Hexagon::new_axial(3, 0));
+        let burns: Vec<&GameEvent> = events
+            .iter()
+            .filter(|event| matches!(event, GameEvent::FireDamage { .. }))
+            .collect();
+        assert_eq!(
+            burns,
+            vec![&GameEvent::FireDamage {
+                entity,
+                position: Hexagon::new_axial(1, 0),
+                damage: 3,
+                remaining_integrity: 7,
+            }]
+        );
+        let unit = *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap();
+        assert_eq!(unit.integrity, 7);
+    }
+
+    fn straight_path() -> VecDeque<Hexagon> {
+        path(&[
+            Hexagon::new_axial(1, 0),
+            Hexagon::new_axial(2, 0),
+            Hexagon::new_axial(3, 0),
+        ])
+    }
+
+    #[test]
+    fn blocked_path_is_recomputed_to_the_destination() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 5, 5, 1));
+        force_move(&mut state, entity, straight_path());
+        spawn(&mut world, 1, 2, 0, Unit::new(5, 1, 1, 1, 0, 5, 5, 1));
+
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 4.5);
+
+        assert_eq!(position_of(&world, entity), Hexagon::new_axial(3, 0));
+        assert_eq!(state.state, State::Selected(entity));
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, GameEvent::OrderInterrupted { .. })));
+        assert_eq!(events.len(), 4);
+    }
+
+    #[test]
+    fn occupied_destination_stops_at_the_closest_free_hexagon() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 5, 5, 1));
+        force_move(&mut state, entity, straight_path());
+        spawn(&mut world, 1, 2, 0, Unit::new(5, 1, 1, 1, 0, 5, 5, 1));
+        spawn(&mut world, 1, 3, 0, Unit::new(5, 1, 1, 1, 0, 5, 5, 1));
+
+        advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 3.5);
+
+        let position = position_of(&world, entity);
+        assert_eq!(position.distance_to(&Hexagon::new_axial(3, 0)), 1);
+        assert_eq!(state.state, State::Selected(entity));
+    }
+
+    #[test]
+    fn unit_stops_if_no_way_around_the_block_exists() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 5, 5, 1));
+        force_move(&mut state, entity, straight_path());
+        spawn(&mut world, 1, 3, 0, Unit::new(5, 1, 1, 1, 0, 5, 5, 1));
+
+        let events = advance_state(&mut world, &mut state, 1.0);
+
+        assert_eq!(position_of(&world, entity), Hexagon::new_axial(2, 0));
+        assert_eq!(state.state, State::Selected(entity));
+        assert_eq!(
+            events.last(),
+            Some(&GameEvent::OrderInterrupted {
+                entity,
+                position: Hexagon::new_axial(2, 0),
+            })
+        );
+    }
+
+    fn overwatcher(world: &mut World, player: usize, at: Hexagon, id: u64) -> Entity {
+        world.push((
+            PlayerComponent(player),
+            at,
+            Unit::new(5, 3, 2, 1, 0, 1, 1, 1),
+            Overwatching,
+            PersistentId(id),
+        ))
+    }
+
+    fn is_overwatching(world: &World, entity: Entity) -> bool {
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Overwatching>()
+            .is_ok()
+    }
+
+    fn attackers(events: &[GameEvent]) -> Vec<Entity> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                GameEvent::UnitAttacked { attacker, .. } => Some(*attacker),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn entering_overwatch_uses_up_the_movement_until_the_next_turn() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(5, 1, 1, 1, 0, 3, 3, 1));
+        state.state = State::EnteringOverwatch(entity);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::OverwatchChanged {
+                entity,
+                position: Hexagon::zero(),
+                active: true,
+            }]
+        );
+        assert!(is_overwatching(&world, entity));
+        let unit = *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap();
+        assert_eq!(unit.remaining_range, 0);
+        assert_eq!(unit.remaining_attacks, 1);
+        assert_eq!(state.state, State::Waiting);
+
+        state.current_player = Some(1);
+        state.state = State::NewRound;
+        advance_state(&mut world, &mut state, 0.0);
+
+        assert!(!is_overwatching(&world, entity));
+    }
+
+    #[test]
+    fn destroyed_bridge_becomes_a_river_and_interrupts_moves_across() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let (from, to) = (Hexagon::new_axial(1, 0), Hexagon::new_axial(2, 0));
+        state.edges.set(from, to, Some(EdgeKind::Bridge));
+        let demolition = spawn(
+            &mut world,
+            0,
+            -1,
+            0,
+            Unit::new(5, BRIDGE_INTEGRITY, 3, 1, 0, 3, 3, 1),
+        );
+        let mover = spawn(&mut world, 1, 0, 0, Unit::new(5, 1, 1, 1, 0, 3, 3, 1));
+        world.entry(mover).unwrap().add_component(MoveOrder {
+            path: vec![from, to, Hexagon::new_axial(3, 0)],
+        });
+        assert_eq!(
+            find_path_within(
+                &Hexagon::zero(),
+                &to,
+                2,
+                &state.edges,
+                &state.map_bounds,
+                &world
+            ),
+            vec![from, to]
+        );
+        state.state = State::AttackingEdge(demolition, from, to);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![
+                GameEvent::BridgeAttacked {
+                    attacker: demolition,
+                    attacker_position: Hexagon::new_axial(-1, 0),
+                    from,
+                    to,
+                    damage: BRIDGE_INTEGRITY,
+                    remaining_integrity: 0,
+                },
+                GameEvent::BridgeDestroyed { from, to },
+                GameEvent::OrderInterrupted {
+                    entity: mover,
+                    position: Hexagon::zero(),
+                },
+            ]
+        );
+        assert_eq!(state.edges.get(&from, &to), Some(EdgeKind::River));
+        assert_eq!(state.state, State::Selected(demolition));
+        let entry = world.entry_ref(mover).unwrap();
+        assert!(entry.get_component::<MoveOrder>().is_err());
+        let entry = world.entry_ref(demolition).unwrap();
+        assert_eq!(entry.get_component::<Unit>().unwrap().remaining_attacks, 0);
+        // Crossing the river now costs more than the two hexagons the bridge took.
+        assert!(find_path_within(
+            &Hexagon::zero(),
+            &to,
+            2,
+            &state.edges,
+            &state.map_bounds,
+            &world
+        )
+        .is_empty());
+    }
+
+    /// A building on the centre with a unit of player 1 inside and an attacker of player 0 that
+    /// brings it down with one shot. Player 1 has a second unit, so the game goes on.
+    fn garrisoned_building(world: &mut World, state: &mut GameState) -> (Entity, Entity) {
+        spawn_building(world, Hexagon::zero(), Building::new(4), false);
+        let garrison = spawn(world, 1, 0, 0, Unit::new(10, 1, 1, 1, 0, 3, 3, 1));
+        assert!(enter_garrison(world, garrison));
+        spawn(world, 1, 5, 0, Unit::new(10, 1, 1, 1, 0, 3, 3, 1));
+        let attacker = spawn(world, 0, -2, 0, Unit::new(5, 6, 3, 1, 0, 3, 3, 1));
+        state.state = State::AttackingBuilding(attacker, Hexagon::zero());
+        (garrison, attacker)
+    }
+
+    #[test]
+    fn collapsing_building_hits_and_ejects_the_garrison() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        // Only the west of the building is on the map.
+        let exit = Hexagon::new_axial(-1, 0);
+        world.push((Field::new(exit),));
+        let (garrison, attacker) = garrisoned_building(&mut world, &mut state);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events[0],
+            GameEvent::BuildingAttacked {
+                attacker,
+                attacker_position: Hexagon::new_axial(-2, 0),
+                position: Hexagon::zero(),
+                damage: 6,
+                remaining_integrity: -2,
+            }
+        );
+        assert!(events.contains(&GameEvent::BuildingDestroyed {
+            position: Hexagon::zero()
+        }));
+        assert!(events.contains(&GameEvent::UnitMoved {
+            entity: garrison,
+            from: Hexagon::zero(),
+            to: exit,
+        }));
+        assert!(building_at(&world, &Hexagon::zero()).is_none());
+        assert_eq!(position_of(&world, garrison), exit);
+        let entry = world.entry_ref(garrison).unwrap();
+        assert!(entry.get_component::<Garrisoned>().is_err());
+        let unit = entry.get_component::<Unit>().unwrap();
+        assert_eq!(unit.integrity, 7);
+        assert_eq!(unit.armor, 0);
+        assert_eq!(state.state, State::Selected(attacker));
+    }
+
+    #[test]
+    fn garrison_without_a_free_neighbour_dies_with_its_building() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let (garrison, _) = garrisoned_building(&mut world, &mut state);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert!(events.contains(&GameEvent::UnitDestroyed {
+            entity: garrison,
+            position: Hexagon::zero(),
+        }));
+        assert!(world.entry_ref(garrison).is_err());
+    }
+
+    #[test]
+    fn overwatch_interrupts_the_move_and_lets_it_resume() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(10, 1, 1, 1, 0, 3, 3, 1));
+        let watcher = overwatcher(&mut world, 1, Hexagon::new_axial(4, 0), 1);
+        force_move(&mut state, entity, straight_path());
+
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 3.5);
+
+        assert_eq!(position_of(&world, entity), Hexagon::new_axial(2, 0));
+        assert_eq!(attackers(&events), vec![watcher]);
+        assert_eq!(state.state, State::Moving(entity));
+        assert_eq!(
+            state.movement,
+            MovementProgress::new(path(&[Hexagon::new_axial(3, 0)]))
+        );
+        assert!(!is_overwatching(&world, watcher));
+
+        state.hitstop_timer = 0.0;
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 1.5);
+
+        // The watcher already fired, the rest of the move is not interrupted.
+        assert!(attackers(&events).is_empty());
+        assert_eq!(position_of(&world, entity), Hexagon::new_axial(3, 0));
+        assert_eq!(state.state, State::Selected(entity));
+    }
+
+    #[test]
+    fn overwatch_can_destroy_the_moving_unit() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(3, 1, 1, 1, 0, 3, 3, 1));
+        spawn(&mut world, 0, -5, 0, Unit::new(3, 1, 1, 1, 0, 3, 3, 1));
+        let first = overwatcher(&mut world, 1, Hexagon::new_axial(3, 0), 1);
+        let second = overwatcher(&mut world, 1, Hexagon::new_axial(1, -2), 2);
+        force_move(&mut state, entity, straight_path());
+
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 3.5);
+
+        assert!(!world.contains(entity));
+        assert_eq!(attackers(&events), vec![first]);
+        assert_eq!(
+            events.last(),
+            Some(&GameEvent::UnitDestroyed {
+                entity,
+                position: Hexagon::new_axial(1, 0),
+            })
+        );
+        // Nobody is left to shoot at, so the second watcher keeps watching.
+        assert!(is_overwatching(&world, second));
+        assert_eq!(state.state, State::Waiting);
+    }
+
+    #[test]
+    fn several_overwatchers_fire_in_the_order_of_their_ids() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(20, 1, 1, 1, 0, 3, 3, 1));
+        let later = overwatcher(&mut world, 1, Hexagon::new_axial(3, -1), 7);
+        let earlier = overwatcher(&mut world, 1, Hexagon::new_axial(1, -2), 2);
+        let friendly = overwatcher(&mut world, 0, Hexagon::new_axial(-1, 1), 3);
+        force_move(&mut state, entity, straight_path());
+
+        let events = advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 2.5);
+
+        assert_eq!(attackers(&events), vec![earlier, later]);
+        let unit = *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap();
+        assert_eq!(unit.integrity, 20 - 3 - 3);
+        assert!(is_overwatching(&world, friendly));
+    }
+
+    fn facing_of(world: &World, entity: Entity) -> Option<Direction> {
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Facing>()
+            .ok()
+            .map(|facing| facing.0)
+    }
+
+    #[test]
+    fn units_face_their_last_step_and_can_turn() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let entity = spawn(&mut world, 0, 0, 0, Unit::new(10, 1, 1, 1, 0, 5, 5, 1));
+        force_move(&mut state, entity, straight_path());
+
+        advance_state(&mut world, &mut state, SECONDS_PER_MOVEMENT * 6.0);
+
+        assert_eq!(state.state, State::Selected(entity));
+        assert_eq!(facing_of(&world, entity), Some(Direction::East));
+
+        state.state = State::Turning(entity, Direction::SouthWest);
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        assert_eq!(
+            events,
+            vec![GameEvent::FacingChanged {
+                entity,
+                position: Hexagon::new_axial(3, 0),
+                facing: Direction::SouthWest,
+            }]
+        );
+        assert_eq!(facing_of(&world, entity), Some(Direction::SouthWest));
+        let unit = *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap();
+        assert_eq!(unit.movement_left(&state.rules), 1);
+        assert_eq!(state.state, State::Selected(entity));
+    }
+
+    #[test]
+    fn attacks_on_the_rear_deal_more_damage() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        let attacker = spawn(&mut world, 0, 0, 0, Unit::new(5, 4, 1, 1, 0, 1, 1, 1));
+        let defender = spawn(&mut world, 1, 1, 0, Unit::new(20, 1, 1, 1, 0, 1, 1, 1));
+        world
+            .entry(defender)
+            .unwrap()
+            .add_component(Facing(Direction::East));
+        state.state = State::Attacking(attacker, defender);
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+
+        let damage = AttackArc::Rear.apply(state.rules.damage(4, 0));
+        assert!(events.contains(&GameEvent::UnitAttacked {
+            attacker,
+            defender,
+            attacker_player: Some(0),
+            defender_player: Some(1),
+            attacker_position: Hexagon::zero(),
+            defender_position: Hexagon::new_axial(1, 0),
+            arc: AttackArc::Rear,
+            damage,
+            remaining_integrity: 20 - damage,
+        }));
+    }
+
+    #[test]
+    fn initiative_hands_the_turn_from_unit_to_unit() {
+        let mut world = World::default();
+        let mut state = new_state(2);
+        state.rules.initiative = true;
+        state.state = State::Waiting;
+        let slow = world.push((
+            PlayerComponent(0),
+            Hexagon::new_axial(0, 0),
+            Unit::new(5, 1, 1, 1, 0, 3, 0, 0),
+            Initiative(1),
+            PersistentId(1),
+        ));
+        let fast = world.push((
+            PlayerComponent(1),
+            Hexagon::new_axial(4, 0),
+            Unit::new(5, 1, 1, 1, 0, 3, 0, 0),
+            Initiative(4),
+            PersistentId(2),
+        ));
+
+        let events = advance_state(&mut world, &mut state, 0.0);
+        assert_eq!(state.state, State::UnitTurn(fast));
+        assert_eq!(state.current_player, Some(1));
+        assert!(events.contains(&GameEvent::UnitTurnStarted {
+            entity: fast,
+            player: 1,
+            position: Hexagon::new_axial(4, 0),
+        }));
+
+        // Passing, like ending the turn does, hands over to the next unit.
+        state.state = State::NewRound;
+        let events = advance_state(&mut world, &mut state, 0.0);
+        assert_eq!(state.state, State::UnitTurn(slow));
+        assert_eq!(
+            events,
+            vec![
+                GameEvent::UnitTurnStarted {
+                    entity: slow,
+                    player: 0,
+                    position: Hexagon::new_axial(0, 0),
+                },
+                GameEvent::TurnStarted {
+                    round: 1,
+                    player: 0
+                }
+            ]
+        );
+        let entry = world.entry(slow).unwrap();
+        assert_eq!(entry.get_component::<Unit>().unwrap().remaining_range, 3);
+
+        // A unit that used up its actions passes on its own, which ends the round.
+        if let Some(mut entry) = world.entry(slow) {
+            let unit = entry.get_component_mut::<Unit>().unwrap();
+            unit.remaining_range = 0;
+            unit.remaining_attacks = 0;
+        }
+        advance_state(&mut world, &mut state, 0.0);
+        assert_eq!(state.state, State::NewRound);
+        advance_state(&mut world, &mut state, 0.0);
+        assert_eq!(state.state, State::UnitTurn(fast));
+        assert_eq!(state.round, 2);
+        assert_eq!(state.current_player, Some(1));
+    }
+}
@@ -0,0 +1,258 @@
+//! Per-round statistics for the graphs after a match. A snapshot of every player is taken at the
+//! start of each round. Long matches keep only every k-th round, doubling k whenever the series
+//! grows beyond its limit, so the save stays small.
+
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::game_state::GameState;
+use crate::systems::hexgrid::compute_influence;
+use gdnative::prelude::*;
+use legion::{EntityStore, IntoQuery};
+use serde::{Deserialize, Serialize};
+
+/// Snapshots kept before the series is thinned out, unless the game world sets another limit.
+pub const DEFAULT_STATISTICS_LIMIT: usize = 200;
+
+/// What one player had at the start of a round.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub units: u32,
+    pub integrity: i32,
+    pub resources: i32,
+    /// Hexagons the influence of the player dominates.
+    pub territory: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoundSnapshot {
+    pub round: u32,
+    /// One entry per player, observers included.
+    pub players: Vec<PlayerSnapshot>,
+}
+
+impl RoundSnapshot {
+    pub fn from_game<S: EntityStore>(world: &S, state: &GameState) -> Self {
+        let mut players: Vec<PlayerSnapshot> = state
+            .players
+            .iter()
+            .map(|player| PlayerSnapshot {
+                resources: player.get_resources(),
+                ..PlayerSnapshot::default()
+            })
+            .collect();
+        for (unit, owner) in <(&Unit, &PlayerComponent)>::query().iter(world) {
+            if let Some(snapshot) = players.get_mut(owner.0) {
+                if unit.integrity > 0 {
+                    snapshot.units += 1;
+                    snapshot.integrity += unit.integrity;
+                }
+            }
+        }
+        for (player, _) in compute_influence(world).values() {
+            if let Some(snapshot) = players.get_mut(*player) {
+                snapshot.territory += 1;
+            }
+        }
+        RoundSnapshot {
+            round: state.round,
+            players,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatisticsSeries {
+    snapshots: Vec<RoundSnapshot>,
+    /// Only rounds divisible by the stride are kept.
+    stride: u32,
+}
+
+impl Default for StatisticsSeries {
+    fn default() -> Self {
+        StatisticsSeries {
+            snapshots: Vec::new(),
+            stride: 1,
+        }
+    }
+}
+
+impl StatisticsSeries {
+    pub fn snapshots(&self) -> &[RoundSnapshot] {
+        &self.snapshots
+    }
+
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    /// Appends the snapshot if its round is kept and thins out the series to at most `limit`
+    /// snapshots.
+    pub fn record(&mut self, snapshot: RoundSnapshot, limit: usize) {
+        if snapshot.round % self.stride == 0 {
+            self.snapshots.push(snapshot);
+        }
+        self.downsample(limit);
+    }
+
+    /// Doubles the stride until no more than `limit` snapshots are left, at least one.
+    pub fn downsample(&mut self, limit: usize) {
+        let limit = limit.max(1);
+        while self.snapshots.len() > limit {
+            self.stride *= 2;
+            let stride = self.stride;
+            self.snapshots
+                .retain(|snapshot| snapshot.round % stride == 0);
+        }
+    }
+
+    /// The "rounds" of the snapshots and for every metric an array with an array per player:
+    /// "units", "integrity", "resources" and "territory".
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        let rounds = VariantArray::new();
+        for snapshot in &self.snapshots {
+            rounds.push(snapshot.round);
+        }
+        dict.insert("rounds", rounds.into_shared());
+        let players = self
+            .snapshots
+            .iter()
+            .map(|snapshot| snapshot.players.len())
+            .max()
+            .unwrap_or(0);
+        let metrics: [(&str, fn(&PlayerSnapshot) -> i64); 4] = [
+            ("units", |snapshot| snapshot.units as i64),
+            ("integrity", |snapshot| snapshot.integrity as i64),
+            ("resources", |snapshot| snapshot.resources as i64),
+            ("territory", |snapshot| snapshot.territory as i64),
+        ];
+        for (name, metric) in metrics.iter() {
+            let per_player = VariantArray::new();
+            for player in 0..players {
+                let values = VariantArray::new();
+                for snapshot in &self.snapshots {
+                    values.push(snapshot.players.get(player).map_or(0, metric));
+                }
+                per_player.push(values.into_shared());
+            }
+            dict.insert(*name, per_player.into_shared());
+        }
+        dict
+    }
+}
+
+/// Takes the snapshot of the round that just started.
+pub fn record_round<S: EntityStore>(world: &S, state: &mut GameState) {
+    let snapshot = RoundSnapshot::from_game(world, state);
+    let limit = state.statistics_limit;
+    state.statistics.record(snapshot, limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::player::Player;
+    use gdnative::core_types::Color;
+    use legion::World;
+
+    fn snapshot(round: u32) -> RoundSnapshot {
+        RoundSnapshot {
+            round,
+            players: vec![PlayerSnapshot::default()],
+        }
+    }
+
+    fn rounds(series: &StatisticsSeries) -> Vec<u32> {
+        series
+            .snapshots()
+            .iter()
+            .map(|snapshot| snapshot.round)
+            .collect()
+    }
+
+    #[test]
+    fn snapshots_count_units_integrity_resources_and_territory() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        for name in &["Player 1", "Player 2", "Player 3"] {
+            state
+                .players
+                .push(Player::new((*name).to_owned(), Color::rgb(0.0, 0.0, 1.0)));
+        }
+        state.players[1].set_resources(40);
+        state.round = 3;
+        world.push((
+            Hexagon::zero(),
+            Unit::new(10, 1, 1, 1, 0, 1, 1, 1),
+            PlayerComponent(0),
+        ));
+        world.push((
+            Hexagon::new_axial(1, 0),
+            Unit::new(5, 1, 1, 1, 0, 1, 1, 1),
+            PlayerComponent(0),
+        ));
+        world.push((
+            Hexagon::new_axial(8, 0),
+            Unit::new(7, 1, 1, 1, 0, 0, 0, 0),
+            PlayerComponent(1),
+        ));
+        // Wrecks are not counted.
+        world.push((
+            Hexagon::new_axial(-8, 0),
+            Unit::new(0, 1, 1, 1, 0, 1, 1, 1),
+            PlayerComponent(1),
+        ));
+
+        record_round(&world, &mut state);
+
+        let snapshot = &state.statistics.snapshots()[0];
+        assert_eq!(snapshot.round, 3);
+        assert_eq!(snapshot.players.len(), 3);
+        assert_eq!(
+            (snapshot.players[0].units, snapshot.players[0].integrity),
+            (2, 15)
+        );
+        assert_eq!(
+            (snapshot.players[1].units, snapshot.players[1].integrity),
+            (1, 7)
+        );
+        assert_eq!(snapshot.players[1].resources, 40);
+        let territory = compute_influence(&world);
+        let claimed = |player: usize| {
+            territory
+                .values()
+                .filter(|(owner, _)| *owner == player)
+                .count() as u32
+        };
+        assert!(snapshot.players[0].territory > snapshot.players[1].territory);
+        assert_eq!(snapshot.players[0].territory, claimed(0));
+        assert_eq!(snapshot.players[1].territory, claimed(1));
+        assert_eq!(snapshot.players[2], PlayerSnapshot::default());
+    }
+
+    #[test]
+    fn long_series_keep_every_kth_round() {
+        let mut series = StatisticsSeries::default();
+        for round in 1..=4 {
+            series.record(snapshot(round), 4);
+        }
+        assert_eq!(rounds(&series), vec![1, 2, 3, 4]);
+
+        series.record(snapshot(5), 4);
+        assert_eq!(series.stride(), 2);
+        assert_eq!(rounds(&series), vec![2, 4]);
+        for round in 6..=9 {
+            series.record(snapshot(round), 4);
+        }
+        assert_eq!(rounds(&series), vec![2, 4, 6, 8]);
+        series.record(snapshot(10), 4);
+        assert_eq!(series.stride(), 4);
+        assert_eq!(rounds(&series), vec![4, 8]);
+
+        // A lower limit thins out what is there.
+        series.downsample(1);
+        assert_eq!(rounds(&series), vec![8]);
+        assert_eq!(series.stride(), 8);
+    }
+}
@@ -0,0 +1,241 @@
+//! Supply lines, with the supply rule. At the start of every round a unit is in supply if a path
+//! of at most `supply_range` hexagons without enemy units leads from a supply source of its
+//! player to it. Units out of supply move only half as far, can not be repaired and suffer
+//! attrition once they stayed cut off for `ATTRITION_ROUNDS` rounds.
+
+use crate::components::field::Field;
+use crate::components::hexagon::Hexagon;
+use crate::components::perks::Perks;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::supply::{Supply, SupplySource};
+use crate::components::unit::Unit;
+use crate::rules::Ruleset;
+use crate::systems::hexgrid::get_neighbours;
+use legion::{Entity, EntityStore, IntoQuery, World};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Rounds in a row a unit has to start out of supply before attrition sets in.
+pub const ATTRITION_ROUNDS: u32 = 2;
+
+/// Integrity a unit loses every round it suffers attrition.
+pub const ATTRITION_DAMAGE: i32 = 1;
+
+pub fn spawn_supply_source(world: &mut World, hexagon: Hexagon, player: usize) -> Entity {
+    world.push((hexagon, SupplySource { player }))
+}
+
+/// The hexagons `player` can supply: every hexagon of the map at most `range` steps away from one
+/// of their supply sources, found by a search from all sources at once. Hexagons with units of
+/// other players block the way.
+pub fn supplied_hexagons<S: EntityStore>(world: &S, player: usize, range: i32) -> HashSet<Hexagon> {
+    let map: HashSet<Hexagon> = <&Field>::query()
+        .iter(world)
+        .map(|field| field.location)
+        .collect();
+    let blocked: HashSet<Hexagon> = <(&Hexagon, &Unit, &PlayerComponent)>::query()
+        .iter(world)
+        .filter(|(_, _, owner)| owner.0 != player)
+        .map(|(position, _, _)| *position)
+        .collect();
+    let mut supplied = HashSet::new();
+    let mut queue = VecDeque::new();
+    for (position, source) in <(&Hexagon, &SupplySource)>::query().iter(world) {
+        if source.player == player && !blocked.contains(position) && supplied.insert(*position) {
+            queue.push_back((*position, 0));
+        }
+    }
+    while let Some((hexagon, distance)) = queue.pop_front() {
+        if distance >= range {
+            continue;
+        }
+        for neighbour in get_neighbours(&hexagon) {
+            if map.contains(&neighbour)
+                && !blocked.contains(&neighbour)
+                && supplied.insert(neighbour)
+            {
+                queue.push_back((neighbour, distance + 1));
+            }
+        }
+    }
+    supplied
+}
+
+/// Updates the supply of every unit on the map for a new round and lets the units that stayed
+/// cut off for too long suffer attrition. Does nothing without the supply rule.
+pub fn update_supply(world: &mut World, rules: &Ruleset) {
+    if !rules.supply {
+        return;
+    }
+    let units: Vec<(Entity, Hexagon, usize)> =
+        <(Entity, &Hexagon, &Unit, &PlayerComponent)>::query()
+            .iter(world)
+            .map(|(entity, position, _, owner)| (*entity, *position, owner.0))
+            .collect();
+    let mut supplied: HashMap<usize, HashSet<Hexagon>> = HashMap::new();
+    for (entity, position, player) in units {
+        let in_supply = supplied
+            .entry(player)
+            .or_insert_with(|| supplied_hexagons(&*world, player, rules.supply_range))
+            .contains(&position);
+        let mut entry = match world.entry(entity) {
+            None => continue,
+            Some(entry) => entry,
+        };
+        let mut supply = entry
+            .get_component::<Supply>()
+            .ok()
+            .copied()
+            .unwrap_or_default();
+        supply.record(in_supply);
+        if supply.unsupplied_rounds >= ATTRITION_ROUNDS {
+            if let Ok(unit) = entry.get_component_mut::<Unit>() {
+                // Attrition wears a unit down, it never destroys it.
+                unit.integrity = (unit.integrity - ATTRITION_DAMAGE).max(1);
+            }
+        }
+        entry.add_component(supply);
+    }
+}
+
+/// Halves the movement a unit out of supply got from its refresh.
+pub fn limit_movement(unit: &mut Unit, supply: Option<&Supply>, rules: &Ruleset) {
+    if supply.map_or(true, |supply| supply.in_supply) {
+        return;
+    }
+    if rules.action_points {
+        unit.action_points /= 2;
+    } else {
+        unit.remaining_range /= 2;
+    }
+}
+
+/// Refills the budgets of every unit for a new round, see `Unit::refresh`, with the bonus of its
+/// perks.
+pub fn refresh_units(world: &mut World, rules: &Ruleset) {
+    for (unit, perks, supply) in
+        <(&mut Unit, Option<&Perks>, Option<&Supply>)>::query().iter_mut(world)
+    {
+        unit.refresh(rules);
+        if let Some(perks) = perks {
+            perks.refresh(unit, rules);
+        }
+        limit_movement(unit, supply, rules);
+    }
+}
+
+/// Whether the unit may be repaired, units out of supply can not.
+pub fn can_be_repaired<S: EntityStore>(world: &S, entity: Entity) -> bool {
+    world.entry_ref(entity).map_or(true, |entry| {
+        entry
+            .get_component::<Supply>()
+            .map_or(true, |supply| supply.in_supply)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Ruleset {
+        Ruleset {
+            supply: true,
+            ..Ruleset::default()
+        }
+    }
+
+    fn supply_of(world: &World, entity: Entity) -> Supply {
+        *world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Supply>()
+            .unwrap()
+    }
+
+    #[test]
+    fn an_enemy_line_cuts_supply() {
+        let mut world = World::default();
+        for q in -1..=6 {
+            for r in -1..=1 {
+                world.push((Field::new(Hexagon::new_axial(q, r)),));
+            }
+        }
+        spawn_supply_source(&mut world, Hexagon::zero(), 0);
+        let unit = world.push((
+            Hexagon::new_axial(4, 0),
+            Unit::new(10, 1, 1, 1, 0, 4, 4, 1),
+            PlayerComponent(0),
+        ));
+
+        assert!(supplied_hexagons(&world, 0, 6).contains(&Hexagon::new_axial(4, 0)));
+        assert!(!supplied_hexagons(&world, 0, 3).contains(&Hexagon::new_axial(4, 0)));
+        assert!(supplied_hexagons(&world, 1, 6).is_empty());
+        update_supply(&mut world, &rules());
+        assert!(supply_of(&world, unit).in_supply);
+
+        for r in -1..=1 {
+            world.push((
+                Hexagon::new_axial(2, r),
+                Unit::new(10, 1, 1, 1, 0, 4, 4, 1),
+                PlayerComponent(1),
+            ));
+        }
+        let supplied = supplied_hexagons(&world, 0, 6);
+        assert!(supplied.contains(&Hexagon::new_axial(1, 0)));
+        assert!(!supplied.contains(&Hexagon::new_axial(2, 0)));
+        assert!(!supplied.contains(&Hexagon::new_axial(4, 0)));
+        update_supply(&mut world, &rules());
+        assert!(!supply_of(&world, unit).in_supply);
+    }
+
+    #[test]
+    fn units_suffer_attrition_after_two_rounds_without_supply() {
+        let mut world = World::default();
+        let position = Hexagon::zero();
+        world.push((Field::new(position),));
+        let unit = world.push((
+            position,
+            Unit::new(10, 1, 1, 1, 0, 4, 4, 1),
+            PlayerComponent(0),
+        ));
+        let integrity = |world: &World| {
+            world
+                .entry_ref(unit)
+                .unwrap()
+                .get_component::<Unit>()
+                .unwrap()
+                .integrity
+        };
+
+        update_supply(&mut world, &Ruleset::default());
+        assert!(world
+            .entry_ref(unit)
+            .unwrap()
+            .get_component::<Supply>()
+            .is_err());
+
+        update_supply(&mut world, &rules());
+        assert_eq!(supply_of(&world, unit).unsupplied_rounds, 1);
+        assert_eq!(integrity(&world), 10);
+        refresh_units(&mut world, &rules());
+        let range = world
+            .entry_ref(unit)
+            .unwrap()
+            .get_component::<Unit>()
+            .unwrap()
+            .remaining_range;
+        assert_eq!(range, 2);
+        assert!(!can_be_repaired(&world, unit));
+
+        update_supply(&mut world, &rules());
+        assert_eq!(supply_of(&world, unit).unsupplied_rounds, 2);
+        assert_eq!(integrity(&world), 10 - ATTRITION_DAMAGE);
+        update_supply(&mut world, &rules());
+        assert_eq!(integrity(&world), 10 - 2 * ATTRITION_DAMAGE);
+
+        spawn_supply_source(&mut world, position, 0);
+        update_supply(&mut world, &rules());
+        assert_eq!(supply_of(&world, unit), Supply::default());
+        assert_eq!(integrity(&world), 10 - 2 * ATTRITION_DAMAGE);
+        assert!(can_be_repaired(&world, unit));
+    }
+}
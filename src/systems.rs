@@ -1,53 +1,132 @@
+use crate::action_log::{replay_to, unit_dictionaries, ActionLog, ReplayError};
+use crate::actionable::{current_actionable_units, AutoEndTurn};
+use crate::ai::{best_actions, next_command, AiProfile};
+use crate::clicks::{Click, ClickTracker};
+use crate::commands::{apply_command, apply_local_command, Command, CommandError};
+use crate::components::abilities::BlocksVision;
+use crate::components::building::Building;
 use crate::components::field::Field;
-use crate::components::hexagon::Hexagon;
+use crate::components::ground_effect::GroundEffect;
+use crate::components::hexagon::{Direction, Hexagon};
+use crate::components::history::History;
+use crate::components::movement_type::MovementType;
 use crate::components::node_component::NodeComponent;
-use crate::components::node_template::NodeTemplate;
+use crate::components::node_template::{NodeTemplate, UNIT_CONTAINER, UNIT_GROUP};
+use crate::components::persistent_id::PersistentId;
+use crate::components::pickup::Pickup;
 use crate::components::player::Player as PlayerComponent;
-use crate::components::unit::{AttackError, AttackResult, CanMove, Unit};
-use crate::game_state::{GameState, State};
+use crate::components::supply::SupplySource;
+use crate::components::unit::Unit;
+use crate::cover::cover_count;
+use crate::diagnostics::{validate_world, WorldDiagnostics};
+use crate::economy::EconomyReport;
+use crate::edges::{EdgeData, EdgeKind};
+use crate::entity_query::{query_entities, EntityFilter};
+use crate::flanking::attack_preview;
+use crate::focus::{focus_position, FocusTracker};
+use crate::game_state::{
+    force_state, set_state, setup_match, start_move, GameState, HoverChange, State,
+};
+use crate::godot_convert::{event_signal, Fields};
+use crate::group_move::{assign_slots, next_group_move, units_in_box};
+use crate::handicap::{HandicapChanges, HandicapError};
+use crate::hex_cursor::Orientation;
+use crate::input_actions::{
+    current_bindings, list_actions, ActionBindings, ATTACK_MODE, CURSOR_ACCEPT, CURSOR_CANCEL,
+    RESELECT_LAST, TOGGLE_HELP,
+};
+use crate::input_source::InputSource;
+use crate::measurement::measure;
+use crate::messages;
+use crate::movement::{movement_type_of, MovementCosts};
+use crate::node_sync::MatchGeneration;
+use crate::nodes::buildings::update_buildings_system;
+use crate::nodes::pickups::update_pickups_system;
 use crate::nodes::units::update_units_system;
-use crate::player::Player;
+use crate::nodes::y_sort::update_y_sort_system;
+use crate::palette::desaturate;
+use crate::panic_guard::{recover_state, try_lock_recovering};
+use crate::path_preview::{build_curve, curve_points, path_preview, PathTracker};
+use crate::planning::orders_to_array;
+use crate::profiler;
+use crate::random::Rng;
+use crate::random_events::describe;
+use crate::retreat::next_retreat;
+use crate::roster::Roster;
+use crate::rules::Ruleset;
+use crate::savegame::{load_game, save_game, SaveData};
+use crate::scenario::{Scenario, ScenarioAi, ScenarioAiProfile};
+use crate::simulation::simulate_frame;
+use crate::sounds::{SoundCue, SoundCues};
+use crate::spawn::{
+    replace_grid, resize_grid, spawn_units, validate_spawns, GridResizeError, UnitSpawn, MAP_RADIUS,
+};
+use crate::spawn_modifiers::{ScriptModifier, SpawnModifiers};
+use crate::state_machine::{dismiss_turn_transition, GameEvent};
+use crate::supply::supplied_hexagons;
 use crate::systems::hexgrid::{
-    calculate_hexagon_points, create_grid, find_path, get_2d_position_from_hex,
-    get_entities_at_hexagon, is_hexagon_visible_for_attack,
+    calculate_hexagon_points, find_path, find_path_with_costs, get_2d_position_from_hex,
+    get_entities_at_hexagon, hexfield_scale, is_hexagon_visible_for_attack,
+};
+use crate::targeting::{begin_targeting, cancel_targeting, target_validity, TargetValidity};
+use crate::touch::{Gesture, TouchTracker};
+use crate::triggers::json_to_variant;
+use crate::turn_file::{export_turn, import_turn, TurnFile, TurnRejection};
+use crate::tutorial::TutorialConstraint;
+use crate::unit_catalog::{apply_catalog_changes, UnitCatalog};
+use crate::unit_names::{rename_unit, RenameError};
+use crossbeam::channel::Receiver;
+use crossbeam::crossbeam_channel;
+use dynamic_nodes::{
+    clear_node_creation_failures, create_node_system, LoadingProgress, LoadingStep, NodeBudget,
+    NodeCreationPolicy,
 };
-use dynamic_nodes::create_node_system;
 use gdnative::api::input_event_mouse::InputEventMouse;
 use gdnative::api::input_event_mouse_button::InputEventMouseButton;
 use gdnative::api::input_event_mouse_motion::InputEventMouseMotion;
 use gdnative::api::Camera2D;
+use gdnative::api::Curve2D;
 use gdnative::api::GlobalConstants;
+use gdnative::api::Input;
+use gdnative::api::InputEventJoypadMotion;
+use gdnative::api::InputEventScreenDrag;
+use gdnative::api::InputEventScreenTouch;
 use gdnative::api::Physics2DDirectSpaceState;
 use gdnative::prelude::*;
 use lazy_static::lazy_static;
-use legion::systems::CommandBuffer;
-use legion::world::{EntryRef, SubWorld};
+use legion::world::{EntryRef, Event, SubWorld};
 use legion::{
     component, system, Entity, EntityStore, IntoQuery, Resources, Schedule, SystemBuilder, World,
 };
-use std::borrow::Borrow;
 use std::collections::vec_deque::VecDeque;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 pub mod dynamic_nodes;
 pub mod hexgrid;
 
+/// Room the help overlay leaves to the right edge of the screen for its lines.
+const HELP_OVERLAY_WIDTH: f32 = 420.0;
+
 pub struct WorldNode(Ref<Node2D>);
 pub struct MainCamera(TRef<'static, Camera2D>);
 pub struct UINode(TRef<'static, Control>);
 pub struct HexfieldSize(pub f32);
 pub struct Delta(pub f64);
 
-const SECONDS_PER_MOVEMENT: f64 = 0.1f64;
-
 lazy_static! {
     static ref WORLD: Mutex<World> = Mutex::new(World::default());
 }
 
+/// Runs `f` with the world, unless it is in use already. A panic while the world was in use does
+/// not lock it out for good.
 pub fn with_world<F>(mut f: F)
 where
     F: FnMut(&mut World),
 {
-    let _result = WORLD.try_lock().map(|mut state| f(&mut state));
+    if let Some(mut world) = try_lock_recovering(&WORLD) {
+        f(&mut world);
+    }
 }
 
 pub fn find_entity_of_instance(instance_id: i64, world: &World) -> Option<Entity> {
@@ -73,159 +152,226 @@ pub fn find_entity_of_instance(instance_id: i64, world: &World) -> Option<Entity
     None
 }
 
-pub fn set_state(state: &mut GameState, game_state: State) {
-    match game_state {
-        State::NewRound => {}
-        State::Startup => {}
-        State::Waiting => {}
-        State::Selected(_) => {
-            state.update_fields = true;
-        }
-        State::Attacking(_, _) => {}
-        State::Moving(_, _, _) => {}
+fn get_player_of_entity(entry: &EntryRef<'_>) -> Option<usize> {
+    match entry.get_component::<PlayerComponent>() {
+        Err(_) => None,
+        Ok(player) => Some(player.0),
     }
-    state.state = game_state;
-    state.current_path = Vec::new();
-    state.redraw_grid = true;
 }
 
-fn move_entity_to_hexagon(entity: Entity, hexagon: &Hexagon, world: &mut World) {
-    let mut entry = match world.entry(entity) {
-        None => {
-            godot_error!("Entity not found in world");
-            return;
-        }
-        Some(e) => e,
-    };
-    let selected_unit = *entry.get_component::<Unit>().unwrap();
-    let selected_hexagon = *entry.get_component::<Hexagon>().unwrap();
-    let distance = selected_hexagon.distance_to(&hexagon);
-    let can_move = selected_unit.is_in_movement_range(distance);
-    match can_move {
-        CanMove::Yes(remaining_range) => {
-            let updated_hexagon = Hexagon::new_axial(hexagon.get_q(), hexagon.get_r());
-            let updated_selected_unit = Unit::new(
-                selected_unit.integrity,
-                selected_unit.damage,
-                selected_unit.max_attack_range,
-                selected_unit.min_attack_range,
-                selected_unit.armor,
-                selected_unit.mobility,
-                remaining_range,
-                selected_unit.remaining_attacks,
-            );
-            entry.add_component(updated_selected_unit);
-            entry.add_component(updated_hexagon);
-        }
-        CanMove::No => {}
+fn actionable_units_to_array<S: EntityStore>(world: &S, state: &GameState) -> VariantArray {
+    let units = VariantArray::new();
+    for unit in current_actionable_units(world, state) {
+        units.push(unit.to_dictionary().owned_to_variant());
     }
+    units.into_shared()
 }
 
-fn get_player_of_entity(entry: &EntryRef<'_>) -> Option<usize> {
-    match entry.get_component::<PlayerComponent>() {
-        Err(_) => None,
-        Ok(player) => Some(player.0),
+/// Data for the turn banner. There are no resources yet, so only the units are counted.
+fn round_started_payload<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    round: u32,
+    player: usize,
+) -> Fields {
+    let units = <&PlayerComponent>::query()
+        .iter(world)
+        .filter(|owner| owner.0 == player)
+        .count();
+    let payload = Fields::new()
+        .with("round", round)
+        .with("player", player)
+        .with("units", units);
+    match state.players.get(player) {
+        None => payload,
+        Some(player_data) => Fields::from(player_data).merge(payload),
+    }
+}
+
+pub(crate) fn emit_fields(root: &Node2D, signal: &str, payload: &Fields) {
+    unsafe {
+        root.call_deferred(
+            "emit_signal",
+            &[
+                GodotString::from_str(signal).to_variant(),
+                payload.to_dictionary().owned_to_variant(),
+            ],
+        );
+    }
+}
+
+fn emit_sound(root: &Node2D, cue: &SoundCue, hexfield_size: f32) {
+    let position = match cue.position {
+        None => Vector2::zero(),
+        Some(hexagon) => get_2d_position_from_hex(&hexagon, hexfield_size),
+    };
+    let payload = Fields::new()
+        .with("event", cue.event)
+        .with("position", position)
+        .with("player", cue.player.map_or(-1, |player| player as i64))
+        .with("magnitude", cue.magnitude);
+    emit_fields(root, "play_sound", &payload);
+}
+
+/// Emits "hex_mouse_exited" and "hex_mouse_entered" with the hexagons, and "hex_hover_changed"
+/// with the hexagon under the mouse, without q and r if there is none.
+fn emit_hover_change(root: &Node2D, change: &HoverChange) {
+    if let Some(exited) = &change.exited {
+        emit_fields(root, "hex_mouse_exited", &Fields::from(exited));
+    }
+    if let Some(entered) = &change.entered {
+        emit_fields(root, "hex_mouse_entered", &Fields::from(entered));
     }
+    let hovered = change
+        .entered
+        .as_ref()
+        .map_or_else(Fields::new, Fields::from)
+        .with("hovered", change.entered.is_some());
+    emit_fields(root, "hex_hover_changed", &hovered);
 }
 
-fn handle_attack_result(
-    world: &mut World,
-    attacker: Entity,
-    defender: Entity,
-    result: AttackResult,
+/// Emits "target_validity_changed" with the hexagon, whether the unit picking a target could
+/// attack it and the reason if not. Nothing outside of the attack mode.
+fn emit_target_validity<S: EntityStore>(
+    root: &Node2D,
+    world: &S,
+    state: &GameState,
+    hexagon: &Hexagon,
 ) {
-    match world.entry(attacker) {
-        None => {}
-        Some(mut e) => {
-            e.add_component(result.attacker);
-        }
+    if let State::Targeting(attacker) = state.state {
+        let (validity, _) =
+            target_validity(world, &state.rules, &state.map_bounds, attacker, hexagon);
+        let payload = Fields::from(hexagon)
+            .with("valid", validity.is_valid())
+            .with("reason", validity.reason());
+        emit_fields(root, "target_validity_changed", &payload);
     }
+}
 
-    match world.entry(defender) {
-        None => {}
-        Some(mut e) => {
-            if result.defender.integrity <= 0 {
-                world.remove(defender);
-            } else {
-                e.add_component(result.defender);
-            }
-        }
+fn emit_input_error(root: &Node2D, reason: &str) {
+    unsafe {
+        root.call_deferred(
+            "emit_signal",
+            &[
+                GodotString::from_str("input_error").to_variant(),
+                reason.to_variant(),
+            ],
+        );
+    }
+}
+
+/// Returns the command that leads to the given state, for states that change the game.
+fn command_for_state<S: EntityStore>(
+    world: &S,
+    current_player: Option<usize>,
+    state: &State,
+) -> Option<Command> {
+    let player = current_player?;
+    match state {
+        State::Attacking(attacker, defender) => Some(Command::Attack {
+            player,
+            attacker: PersistentId::of_entity(world, *attacker)?,
+            defender: PersistentId::of_entity(world, *defender)?,
+        }),
+        State::Selected(entity) => Some(Command::Select {
+            player,
+            unit: Some(PersistentId::of_entity(world, *entity)?),
+        }),
+        _ => None,
     }
 }
 
 #[system]
 pub fn finalize(#[resource] state: &mut GameState) {
+    let _timer = profiler::scope("finalize");
     state.update_fields = false;
 }
 
-#[system(par_for_each)]
+/// Marks the fields the selected unit can move to and attack. Moves are measured in movement
+/// cost, so the overlay reaches further along roads. While the unit picks a target only the
+/// attack overlay is shown.
+#[system]
 #[read_component(Hexagon)]
 #[read_component(Unit)]
 #[read_component(PlayerComponent)]
+#[read_component(BlocksVision)]
+#[read_component(MovementType)]
+#[write_component(Field)]
 pub fn update_field(
-    world: &SubWorld<'_>,
-    field: &mut Field,
+    world: &mut SubWorld<'_>,
     #[resource] state: &GameState,
     #[resource] hexfield_size: &HexfieldSize,
     #[resource] physic_state: &Ref<Physics2DDirectSpaceState>,
 ) {
-    if let State::Selected(entity) = state.state.clone() {
+    let _timer = profiler::scope("update_field");
+    let selected = match state.state {
+        State::Selected(entity) => Some((entity, false)),
+        State::Targeting(entity) => Some((entity, true)),
+        _ => None,
+    };
+    if let Some((entity, targeting)) = selected {
         if !state.update_fields {
             return;
         }
-        let entry = match world.entry_ref(entity) {
-            Err(_) => return,
-            Ok(entity) => entity,
-        };
-
-        let hexagon = match entry.get_component::<Hexagon>() {
-            Err(_) => return,
-            Ok(hexagon) => *hexagon,
-        };
-
-        let unit = match entry.get_component::<Unit>() {
+        let (selected_unit, selected_hexagon) = match world.entry_ref(entity) {
             Err(_) => return,
-            Ok(unit) => *unit,
+            Ok(entry) => match (
+                entry.get_component::<Unit>(),
+                entry.get_component::<Hexagon>(),
+            ) {
+                (Ok(unit), Ok(hexagon)) => (*unit, *hexagon),
+                _ => return,
+            },
         };
-
-        let selected_data = Some((entity, unit, hexagon));
-
-        if let Some(data) = selected_data {
-            let (selected_entity, selected_unit, selected_hexagon) = (data.0, data.1, data.2);
-            let can_move = selected_hexagon.distance_to(&field.location)
-                <= selected_unit.remaining_range
-                && match selected_unit.is_in_movement_range(
-                    find_path(&selected_hexagon, &field.location, world).len() as i32,
-                ) {
-                    CanMove::Yes(_) => true,
-                    CanMove::No => false,
-                };
-
-            let can_attack = selected_unit.remaining_attacks > 0
+        // The roads are collected before the fields are borrowed for writing.
+        let costs = MovementCosts::new(world, movement_type_of(world, entity), &state.edges)
+            .with_bounds(&state.map_bounds);
+        let budget = selected_unit.movement_cost_left(&state.rules);
+        let (mut fields, world) = world.split::<&mut Field>();
+        <&mut Field>::query().par_for_each_mut(&mut fields, |field| {
+            let can_move = !targeting
+                && selected_hexagon.distance_to(&field.location) * costs.cheapest_step() <= budget
+                && !find_path_with_costs(
+                    &selected_hexagon,
+                    &field.location,
+                    budget,
+                    &costs,
+                    &world,
+                )
+                .is_empty();
+
+            let can_attack = selected_unit.can_attack(&state.rules)
                 && is_hexagon_visible_for_attack(
                     physic_state,
-                    world,
+                    &world,
                     hexfield_size.0,
-                    selected_entity,
+                    entity,
                     field.location,
+                    state.rules.line_of_sight,
                 );
             field.moveable = can_move;
             field.attackable = can_attack;
-        };
+        });
     } else {
-        field.attackable = false;
-        field.moveable = false;
+        <&mut Field>::query().par_for_each_mut(world, |field| {
+            field.attackable = false;
+            field.moveable = false;
+        });
     }
 }
 
 #[system]
 #[read_component(Field)]
+#[read_component(Hexagon)]
+#[read_component(Unit)]
+#[read_component(PlayerComponent)]
 pub fn draw_grid(
     world: &mut SubWorld<'_>,
     #[resource] state: &mut GameState,
     #[resource] hexfield_size: &HexfieldSize,
     #[resource] node: &WorldNode,
 ) {
+    let _timer = profiler::scope("draw_grid");
     let mut query = <&Field>::query();
     let hexfield_size = hexfield_size.0;
     let field_polygon: Vec<Vector2> = calculate_hexagon_points(hexfield_size);
@@ -238,6 +384,12 @@ pub fn draw_grid(
     let width = 3.0_f32.sqrt() * hexfield_size;
     let height = 2.0 * hexfield_size;
     let mut rect = Rect2::new(Point2::zero(), Size2::new(width, height));
+    let view_hidden = state.is_view_hidden();
+    let influence = if state.influence_layer && !view_hidden {
+        state.influence.claims(&*world).clone()
+    } else {
+        BTreeMap::new()
+    };
 
     for field in query.iter(world) {
         let pos = get_2d_position_from_hex(&field.location, hexfield_size);
@@ -252,8 +404,8 @@ pub fn draw_grid(
             adjusted_polygon.push(*point + pos);
         }
 
-        let draw_blue = field.moveable && state.blue_layer;
-        let draw_red = field.attackable && state.red_layer;
+        let draw_blue = field.moveable && state.blue_layer && !view_hidden;
+        let draw_red = field.attackable && state.red_layer && !view_hidden;
         if draw_blue || draw_red {
             if draw_blue {
                 node.draw_colored_polygon(
@@ -278,7 +430,22 @@ pub fn draw_grid(
         } else {
             node.draw_colored_polygon(
                 Vector2Array::from_vec(adjusted_polygon.clone()),
-                Color::rgba(0.5, 0.5, 0.5, 1.0),
+                field.terrain.colour(),
+                Vector2Array::new(),
+                Texture::null(),
+                Texture::null(),
+                false,
+            );
+        }
+
+        if let Some(player) = influence
+            .get(&field.location)
+            .and_then(|(player, _)| state.players.get(*player))
+        {
+            let colour = player.get_colour();
+            node.draw_colored_polygon(
+                Vector2Array::from_vec(adjusted_polygon.clone()),
+                Color::rgba(colour.r, colour.g, colour.b, 0.2),
                 Vector2Array::new(),
                 Texture::null(),
                 Texture::null(),
@@ -287,7 +454,7 @@ pub fn draw_grid(
         }
 
         if let Some(hovered_hexagon) = state.hovered_hexagon {
-            if hovered_hexagon == field.location {
+            if hovered_hexagon == field.location && !view_hidden {
                 node.draw_colored_polygon(
                     Vector2Array::from_vec(adjusted_polygon.clone()),
                     Color::rgba(1.0, 1.0, 1.0, 0.5),
@@ -306,6 +473,26 @@ pub fn draw_grid(
             false,
         );
     }
+
+    if state.edge_layer && !view_hidden {
+        draw_edges(&node, &state.edges, hexfield_size);
+    }
+}
+
+/// Draws rivers, bridges and cliffs on the side the two hexagons of each edge share.
+fn draw_edges(node: &Node2D, edges: &EdgeData, hexfield_size: f32) {
+    for (key, kind) in edges.iter() {
+        let (first, second) = key.hexagons();
+        let first = get_2d_position_from_hex(&first, hexfield_size);
+        let second = get_2d_position_from_hex(&second, hexfield_size);
+        let centre = (first + second) / 2.0;
+        let across = (second - first).normalize();
+        // The shared side is as long as the radius of a hexagon and runs across the line
+        // between the centres.
+        let along = Vector2::new(-across.y, across.x) * (hexfield_size / 2.0);
+        let width = if *kind == EdgeKind::Bridge { 6.0 } else { 3.0 };
+        node.draw_line(centre - along, centre + along, kind.colour(), width, true);
+    }
 }
 
 #[system]
@@ -315,8 +502,19 @@ fn draw_path(
     #[resource] state: &GameState,
     #[resource] hexfield_size: &HexfieldSize,
     #[resource] node: &WorldNode,
+    #[resource] ui_node: &UINode,
 ) {
+    let _timer = profiler::scope("draw_path");
     let node = unsafe { node.0.assume_safe() };
+    if profiler::is_enabled() {
+        draw_profiler_overlay(&node, &ui_node.0);
+    }
+    if state.show_help {
+        draw_help_overlay(&node, &ui_node.0);
+    }
+    if state.is_view_hidden() {
+        return;
+    }
     if let State::Selected(selected) = state.state {
         let selected_entry = match world.entry_ref(selected) {
             Err(_) => {
@@ -346,167 +544,162 @@ fn draw_path(
             last_point = current_point;
         }
     }
-}
 
-#[system]
-#[write_component(Unit)]
-#[read_component(Hexagon)]
-pub fn update_state(
-    cmd: &mut CommandBuffer,
-    world: &mut SubWorld<'_>,
-    #[resource] state: &mut GameState,
-    #[resource] delta: &Delta,
-) {
-    let delta = delta.0;
-    match state.state.clone() {
-        State::Startup => {
-            state.state = State::Waiting;
-        }
-        State::NewRound => {
-            for mut unit in <&mut Unit>::query().iter_mut(world) {
-                unit.remaining_attacks = 1;
-                unit.remaining_range = unit.mobility;
-            }
-            let next_player = match state.current_player {
-                None => 0,
-                Some(mut player) => {
-                    player += 1;
-                    if player >= state.players.len() {
-                        player = 0;
-                    }
-                    player
-                }
-            };
-            state.current_player = Some(next_player);
-            set_state(state, State::Waiting);
-        }
-        State::Attacking(attacker_entity, defender_entity) => {
-            let attacking_unit = {
-                let attacker_entry = match world.entry_mut(attacker_entity) {
-                    Err(_) => {
-                        godot_error!("ATTACKING: Attacking entity not in world.");
-                        set_state(state, State::Waiting);
-                        return;
-                    }
-                    Ok(entry) => entry,
-                };
-                let attacking_unit = attacker_entry.get_component::<Unit>();
-                match attacking_unit {
-                    Err(_error) => {
-                        godot_error!("ATTACKING: Attacking entity had no unit component.",);
-                        set_state(state, State::Waiting);
-                        return;
-                    }
-                    Ok(unit) => *unit,
-                }
-            };
-            let defending_unit = {
-                let defender_entry = match world.entry_mut(defender_entity) {
-                    Err(_) => {
-                        godot_error!("ATTACKING: Defending entity not in world.");
-                        set_state(state, State::Waiting);
-                        return;
-                    }
-                    Ok(entry) => entry,
-                };
-                match defender_entry.get_component::<Unit>() {
-                    Err(_) => {
-                        godot_error!("ATTACKING: Defending entity had no unit component.");
-                        set_state(state, State::Waiting);
-                        return;
-                    }
-                    Ok(unit) => *unit,
-                }
-            };
-            let result = { attacking_unit.attack(defending_unit.borrow()) };
-
-            match result {
-                Ok(result) => {
-                    godot_print!("Damage dealt: {}", result.actual_damage);
-                    godot_print!("Remaining integrity: {}", result.defender.integrity);
-                    cmd.exec_mut(move |world| {
-                        handle_attack_result(world, attacker_entity, defender_entity, result);
-                    });
-                }
-                Err(error) => match error {
-                    AttackError::NoAttacksLeft => godot_print!("Attacker has no attacks left"),
-                },
-            }
-            set_state(state, State::Waiting);
-        }
-        State::Moving(entity, path, mut total_time) => {
-            let mut path = path.clone();
-            total_time += delta;
-            while total_time > SECONDS_PER_MOVEMENT {
-                let entry = match world.entry_mut(entity) {
-                    Err(_) => {
-                        godot_error!("MOVING: Entity to move does not exist in world.");
-                        set_state(state, State::Waiting);
-                        return;
-                    }
-                    Ok(e) => e,
-                };
+    if let Some((first, second)) = state.selection_box {
+        let top_left = Point2::new(first.x.min(second.x), first.y.min(second.y));
+        let size = Size2::new((first.x - second.x).abs(), (first.y - second.y).abs());
+        node.draw_rect(
+            Rect2::new(top_left, size),
+            Color::rgb(1.0, 1.0, 1.0),
+            false,
+            1.0,
+            false,
+        );
+    }
 
-                let unit = {
-                    let unit = entry.get_component::<Unit>();
-                    match unit {
-                        Err(_) => {
-                            godot_error!("MOVING: Entity to move has no unit component");
-                            set_state(state, State::Waiting);
-                            return;
-                        }
-                        Ok(unit) => *unit,
-                    }
-                };
+    if state.hex_cursor.visible {
+        let centre = get_2d_position_from_hex(&state.hex_cursor.hexagon, hexfield_size.0);
+        let outline: Vec<Vector2> = calculate_hexagon_points(hexfield_size.0)
+            .into_iter()
+            .map(|point| point + centre)
+            .collect();
+        node.draw_polyline(
+            Vector2Array::from_vec(outline),
+            Color::rgb(1.0, 1.0, 0.0),
+            3.0,
+            false,
+        );
+    }
 
-                if unit.remaining_range <= 0 {
-                    {
-                        set_state(state, State::Selected(entity));
-                    }
-                    return;
-                }
+    if state.rules.fog_of_war {
+        let ghost_polygon: Vec<Vector2> = calculate_hexagon_points(hexfield_size.0 * 0.5);
+        let ghosts = state
+            .current_player
+            .map_or(&[][..], |viewer| state.last_seen.ghosts(viewer));
+        for ghost in ghosts {
+            let colour = match state.players.get(ghost.player) {
+                None => Color::rgb(0.5, 0.5, 0.5),
+                Some(player) => desaturate(player.get_colour(), 0.8),
+            };
+            let centre = get_2d_position_from_hex(&ghost.hexagon, hexfield_size.0);
+            let points: Vec<Vector2> = ghost_polygon.iter().map(|point| *point + centre).collect();
+            node.draw_colored_polygon(
+                Vector2Array::from_vec(points),
+                Color::rgba(colour.r, colour.g, colour.b, 0.5),
+                Vector2Array::new(),
+                Texture::null(),
+                Texture::null(),
+                false,
+            );
+        }
+    }
 
-                let hexagon = match entry.get_component::<Hexagon>() {
-                    Err(_) => {
-                        godot_error!("MOVING: Entity to move had no hexagon tag.");
-                        set_state(state, State::Waiting);
-                        return;
-                    }
-                    Ok(hexagon) => hexagon,
-                };
+    if let Some(constraint) = state.tutorial.constraint() {
+        let pulse = (state.tutorial.elapsed * 2.0 * std::f64::consts::PI).sin() as f32;
+        let centre = get_2d_position_from_hex(&constraint.hexagon(), hexfield_size.0);
+        let outline: Vec<Vector2> = calculate_hexagon_points(hexfield_size.0 * (0.9 + 0.1 * pulse))
+            .into_iter()
+            .map(|point| point + centre)
+            .collect();
+        node.draw_polyline(
+            Vector2Array::from_vec(outline),
+            Color::rgba(1.0, 1.0, 1.0, 0.7 + 0.3 * pulse),
+            3.0,
+            false,
+        );
+    }
 
-                let next_hexagon = match path.pop_front() {
-                    None => {
-                        godot_warn!("MOVING: Path was empty");
-                        set_state(state, State::Selected(entity));
-                        return;
-                    }
-                    Some(hexagon) => hexagon,
-                };
+    for ping in state.pings.iter() {
+        let mut colour = match state.players.get(ping.player) {
+            None => Color::rgb(1.0, 1.0, 1.0),
+            Some(player) => player.get_colour(),
+        };
+        let pulse = (ping.ttl * 2.0 * std::f64::consts::PI).sin() as f32;
+        colour.a = 0.6 + 0.2 * pulse;
+        node.draw_circle(
+            get_2d_position_from_hex(&ping.hexagon, hexfield_size.0),
+            (hexfield_size.0 * (0.4 + 0.1 * pulse)).into(),
+            colour,
+        );
+    }
 
-                if !hexagon.is_neighbour(&next_hexagon) {
-                    godot_error!("MOVING: Next point in path was not adjacent to current hexagon");
-                    set_state(state, State::Selected(entity));
-                    return;
-                }
+    if let Some(measurement) = state.measurement {
+        let from = get_2d_position_from_hex(&measurement.from, hexfield_size.0);
+        let to = get_2d_position_from_hex(&measurement.to, hexfield_size.0);
+        let colour = if measurement.line_of_sight {
+            Color::rgb(1.0, 1.0, 0.0)
+        } else {
+            Color::rgb(1.0, 0.5, 0.0)
+        };
+        node.draw_line(from, to, colour, 2.0, false);
+        if let Some(font) = ui_node.0.get_font("font", "") {
+            let text = match measurement.path_cost {
+                None => format!("{}", measurement.distance),
+                Some(cost) => format!("{} ({})", measurement.distance, cost),
+            };
+            node.draw_string(font, (from + to) / 2.0, text, colour, -1);
+        }
+    }
+}
 
-                cmd.exec_mut(move |world| {
-                    move_entity_to_hexagon(entity, &next_hexagon, world);
-                });
+/// Lists the frame timings in the top left corner of the screen.
+fn draw_profiler_overlay(node: &Node2D, ui_node: &Control) {
+    let lines: Vec<String> = profiler::with_profiler(|profiler| {
+        profiler
+            .buckets()
+            .map(|(name, bucket)| {
+                format!(
+                    "{}: {:.2} ms (avg {:.2}, max {:.2})",
+                    name,
+                    bucket.last_ms,
+                    bucket.avg_ms(),
+                    bucket.max_ms
+                )
+            })
+            .collect()
+    })
+    .unwrap_or_default();
+    draw_text_column(node, ui_node, 10.0, &lines);
+}
 
-                total_time -= SECONDS_PER_MOVEMENT;
-            }
-            if !path.is_empty() {
-                set_state(state, State::Moving(entity, path, total_time));
-            } else {
-                set_state(state, State::Selected(entity));
-            }
-        }
-        _ => {}
+/// Lists the controls with the keys and buttons bound to them along the right side of the
+/// screen.
+fn draw_help_overlay(node: &Node2D, ui_node: &Control) {
+    let lines: Vec<String> = list_actions(current_bindings)
+        .iter()
+        .map(ActionBindings::line)
+        .collect();
+    draw_text_column(node, ui_node, ui_node.size().x - HELP_OVERLAY_WIDTH, &lines);
+}
+
+/// Draws the lines below each other from the top of the screen, `left` is in screen
+/// coordinates.
+fn draw_text_column(node: &Node2D, ui_node: &Control, left: f32, lines: &[String]) {
+    let font = match ui_node.get_font("font", "") {
+        None => return,
+        Some(font) => font,
+    };
+    let screen_to_world = match node.get_global_transform_with_canvas().inverse() {
+        None => return,
+        Some(transform) => transform,
+    };
+    for (index, line) in lines.iter().enumerate() {
+        let position = screen_to_world
+            .transform_point(Point2::new(left, 20.0 + 16.0 * index as f32))
+            .to_vector();
+        node.draw_string(
+            font.clone(),
+            position,
+            line.as_str(),
+            Color::rgb(1.0, 1.0, 1.0),
+            -1,
+        );
     }
 }
+
 #[system]
 fn update_ui(#[resource] state: &GameState, #[resource] ui_node: &UINode) {
+    let _timer = profiler::scope("update_ui");
     let ui_node = &ui_node.0;
     let player_name = match state.current_player {
         None => "None".to_owned(),
@@ -531,15 +724,83 @@ fn update_ui(#[resource] state: &GameState, #[resource] ui_node: &UINode) {
         Some(label) => label,
     };
 
-    player_name_label.set_text(format!("Current player: {}", player_name));
+    player_name_label.set_text(messages::current_player(&player_name).translate(ui_node));
     player_name_label.add_color_override("font_color", player_colour);
 }
 
+fn dummy_unit_template() -> NodeTemplate {
+    NodeTemplate::new("res://DummyUnit.tscn")
+        .z_index(1)
+        .visible(false)
+        .group(UNIT_GROUP)
+        .parent(UNIT_CONTAINER)
+        .y_sort()
+}
+
+/// The systems run every frame. Systems keep state, like the failed node creations, so a new
+/// match gets a new schedule.
+fn process_schedule(world_node: Ref<Node2D>) -> Schedule {
+    Schedule::builder()
+        .add_system(
+            SystemBuilder::new("process")
+                .with_query(<(&mut NodeComponent, &Hexagon)>::query())
+                .with_query(<(&NodeComponent, &Field)>::query())
+                .read_resource::<HexfieldSize>()
+                .build(|_, world, hexfield_size, (query, field_query)| {
+                    let _timer = profiler::scope("process");
+                    for (node, position) in query.iter_mut(world) {
+                        unsafe {
+                            let position = get_2d_position_from_hex(&position, hexfield_size.0);
+                            node.node.assume_safe().set_position(position);
+                        }
+                    }
+                    // Fields only get nodes if the map was created with a hexagon scene.
+                    for (node, field) in field_query.iter(world) {
+                        unsafe {
+                            let position =
+                                get_2d_position_from_hex(&field.location, hexfield_size.0);
+                            node.node.assume_safe().set_position(position);
+                        }
+                    }
+                }),
+        )
+        .add_thread_local(update_units_system())
+        .add_thread_local(update_pickups_system())
+        .add_thread_local(update_buildings_system())
+        .add_thread_local(update_y_sort_system())
+        .add_system(update_field_system())
+        .add_thread_local(create_node_system(
+            world_node,
+            NodeCreationPolicy::default(),
+            HashMap::new(),
+        ))
+        .add_thread_local(update_ui_system())
+        .flush()
+        .add_system(finalize_system())
+        .build()
+}
+
 pub struct UpdateNodes {
     resources: Resources,
     process_schedule: Schedule,
     draw_schedule: Schedule,
     input_queue: VecDeque<Ref<InputEvent>>,
+    auto_end_turn: AutoEndTurn,
+    /// Insertions and removals of entities with a PersistentId, to keep the id map up to date.
+    id_events: Receiver<Event>,
+    /// Where the left button went down. Releasing it far away selects the units in the box.
+    drag_start: Option<Vector2>,
+    touch: TouchTracker,
+    clicks: ClickTracker,
+    /// Seconds since the start, to time touches and sounds.
+    input_time: f64,
+    focus: FocusTracker,
+    path: PathTracker,
+    sound_cues: SoundCues,
+    spawn_modifiers: SpawnModifiers<ScriptModifier>,
+    /// Whether the hexagon under the mouse is picked here or reported by the hex signals of the
+    /// parent of the game world.
+    input_source: InputSource,
 }
 
 impl UpdateNodes {
@@ -547,148 +808,1845 @@ impl UpdateNodes {
         let mut resources = Resources::default();
 
         let mut state = GameState::new();
+        state.spawn_nodes = true;
 
-        state.players.push(Player::new(
-            "Player 1".to_owned(),
-            Color::rgb(0f32, 0f32, 1f32),
-        ));
-        state.players.push(Player::new(
-            "Player 2".to_owned(),
-            Color::rgb(1f32, 0f32, 0f32),
-        ));
-
+        let (id_sender, id_events) = crossbeam_channel::unbounded();
         with_world(|world| {
-            world.extend(vec![(
-                PlayerComponent(0),
-                Hexagon::new_axial(2, 0),
-                NodeTemplate {
-                    scene_file: "res://DummyUnit.tscn".to_owned(),
-                    scale_x: 1.0,
-                    scale_y: 1.0,
-                    z_index: 1,
-                },
-                Unit::new(20, 5, 2, 1, 3, 5, 5, 1),
-            )]);
-            world.extend(vec![(
-                PlayerComponent(0),
-                Hexagon::new_axial(2, 1),
-                NodeTemplate {
-                    scene_file: "res://DummyUnit.tscn".to_owned(),
-                    scale_x: 1.0,
-                    scale_y: 1.0,
-                    z_index: 1,
-                },
-                Unit::new(10, 10, 4, 2, 1, 2, 2, 1),
-            )]);
-
-            world.extend(vec![(
-                PlayerComponent(1),
-                Hexagon::new_axial(-2, 0),
-                NodeTemplate {
-                    scene_file: "res://DummyUnit.tscn".to_owned(),
-                    scale_x: 1.0,
-                    scale_y: 1.0,
-                    z_index: 1,
-                },
-                Unit::new(20, 5, 2, 1, 3, 5, 5, 1),
-            )]);
-            world.extend(vec![(
-                PlayerComponent(1),
-                Hexagon::new_axial(-2, -1),
-                NodeTemplate {
-                    scene_file: "res://DummyUnit.tscn".to_owned(),
-                    scale_x: 1.0,
-                    scale_y: 1.0,
-                    z_index: 1,
-                },
-                Unit::new(10, 10, 4, 2, 1, 2, 2, 1),
-            )]);
-
-            for field in create_grid(128) {
-                world.extend(vec![(Field::new(field),)]);
+            world.subscribe(id_sender.clone(), component::<PersistentId>());
+            for unit_type in setup_match(world, &mut state, None) {
+                godot_error!("Unit type {} is not in the catalog", unit_type);
             }
         });
+        force_state(&mut state, State::Loading);
 
-        state.current_player = Some(0);
         resources.insert(WorldNode(world_node));
+        resources.insert(NodeBudget::default());
+        resources.insert(LoadingProgress::default());
         resources.insert(HexfieldSize(hexfield_size));
         resources.insert(state);
         resources.insert(Delta(0f64));
 
-        let process_schedule = Schedule::builder()
-            .add_thread_local(update_state_system())
-            .flush()
-            .add_system(
-                SystemBuilder::new("process")
-                    .with_query(<(&mut NodeComponent, &Hexagon)>::query())
-                    .read_resource::<HexfieldSize>()
-                    .build(|_, world, hexfield_size, query| {
-                        for (node, position) in query.iter_mut(world) {
-                            unsafe {
-                                let position = get_2d_position_from_hex(&position, hexfield_size.0);
-                                node.node.assume_safe().set_position(position);
-                            }
-                        }
-                    }),
-            )
-            .add_thread_local(update_units_system())
-            .add_system(update_field_system())
-            .add_thread_local(create_node_system(world_node))
-            .add_thread_local(update_ui_system())
-            .flush()
-            .add_system(finalize_system())
-            .build();
         let draw_schedule = Schedule::builder()
             .add_system(draw_grid_system())
             .flush()
-            .add_system(draw_path_system())
+            .add_thread_local(draw_path_system())
             .flush()
             .build();
         Self {
             resources,
-            process_schedule,
+            process_schedule: process_schedule(world_node),
             draw_schedule,
             input_queue: VecDeque::new(),
+            auto_end_turn: AutoEndTurn::new(),
+            id_events,
+            drag_start: None,
+            touch: TouchTracker::default(),
+            clicks: ClickTracker::default(),
+            input_time: 0.0,
+            focus: FocusTracker::default(),
+            path: PathTracker::default(),
+            sound_cues: SoundCues::default(),
+            spawn_modifiers: SpawnModifiers::default(),
+            input_source: InputSource::default(),
         }
     }
 
-    pub fn new_round(&mut self) {
+    /// Where the clicks on hexagons come from. With the signals the mouse and touches are left
+    /// to the parent, except for panning and the selection box.
+    pub fn set_input_source(&mut self, source: InputSource) {
+        self.input_source = source;
+    }
+
+    fn picks_hexagons(&self) -> bool {
+        self.input_source == InputSource::Direct
+    }
+
+    /// A left click on `hex` reported by the parent, counted towards double clicks like one of
+    /// the mouse.
+    pub fn signal_left_click(&mut self, root: &Node2D, hex: Hexagon) {
+        if self.input_source != InputSource::Signals || !self.is_on_map(&hex) {
+            return;
+        }
+        with_world(|world| self.click_at(root, world, hex));
+    }
+
+    /// A right click on `hex` reported by the parent.
+    pub fn signal_right_click(&mut self, root: &Node2D, hex: Hexagon) {
+        if self.input_source != InputSource::Signals {
+            return;
+        }
+        with_world(|world| self.right_click_hexagon(root, world, hex));
+    }
+
+    /// The mouse entered `hex` as reported by the parent.
+    pub fn signal_hover(&mut self, root: &Node2D, hex: Hexagon) {
+        if self.input_source != InputSource::Signals {
+            return;
+        }
+        let shift = Input::godot_singleton().is_key_pressed(GlobalConstants::KEY_SHIFT);
+        with_world(|world| self.hover_hexagon(root, world, hex, shift));
+    }
+
+    /// The mouse left `hex` as reported by the parent. Another hexagon may be hovered already.
+    pub fn signal_hover_end(&mut self, root: &Node2D, hex: Hexagon) {
+        if self.input_source != InputSource::Signals {
+            return;
+        }
+        let hovered = self
+            .resources
+            .get::<GameState>()
+            .and_then(|state| state.hovered_hexagon);
+        if hovered == Some(hex) {
+            self.clear_hover(root);
+        }
+    }
+
+    /// Ends the current match and sets up a new one, on the default map or from the scenario.
+    /// The systems start over with their state, the nodes of the old entities are left to the
+    /// caller.
+    pub fn new_game(&mut self, scenario: Option<&Scenario>) {
         let mut state = match self.resources.get_mut::<GameState>() {
             None => {
-                godot_error!("new_round: No GameState");
+                godot_error!("new_game: No GameState");
                 return;
             }
             Some(state) => state,
         };
+        let id_events = &self.id_events;
+        with_world(|world| {
+            state.reset(world);
+            // Removals of the old entities, the id map was replaced already.
+            for _ in id_events.try_iter() {}
+            for unit_type in setup_match(world, &mut state, scenario) {
+                godot_warn!("Unit type {} is not in the catalog", unit_type);
+            }
+        });
+        if state.spawn_nodes {
+            force_state(&mut state, State::Loading);
+        }
+        drop(state);
+        self.resources.insert(LoadingProgress::default());
+        if let Some(world_node) = self.resources.get::<WorldNode>().map(|node| node.0) {
+            self.process_schedule = process_schedule(world_node);
+        }
+        self.input_queue.clear();
+        self.auto_end_turn = AutoEndTurn::new();
+        self.drag_start = None;
+        self.touch = TouchTracker::default();
+        self.clicks = ClickTracker {
+            double_click_time: self.clicks.double_click_time,
+            ..ClickTracker::default()
+        };
+        self.focus = FocusTracker::default();
+        self.path = PathTracker::default();
+        self.sound_cues = SoundCues::default();
+    }
 
-        state.state = State::NewRound;
+    pub fn set_node_budget(&mut self, budget: NodeBudget) {
+        self.resources.insert(budget);
     }
 
-    pub fn execute(
-        &mut self,
-        root: &Node2D,
-        ui_node: TRef<'static, Control>,
-        camera_node: TRef<'static, Camera2D>,
-        delta: f64,
-    ) {
-        with_world(|mut world| {
-            self.resources.insert(Delta(delta));
-            if let Some(world) = root.get_world_2d() {
-                if let Some(state) = unsafe { world.assume_safe().direct_space_state() } {
-                    self.resources.insert(state);
-                }
+    /// Tells the scripts how far node creation got. The first backlog to empty ends the loading
+    /// of the world and lets the match start.
+    fn announce_loading(&mut self, root: &Node2D) {
+        let step = match self.resources.get::<LoadingProgress>() {
+            None => return,
+            Some(progress) => progress.last_step,
+        };
+        let ready = match step {
+            LoadingStep::Idle => true,
+            LoadingStep::Progress { created, total } => {
+                let payload = Fields::new().with("created", created).with("total", total);
+                emit_fields(root, "world_loading_progress", &payload);
+                false
             }
-            self.resources.insert(UINode(ui_node));
-            self.resources.insert(MainCamera(camera_node));
+            LoadingStep::Finished { total } => {
+                let payload = Fields::new().with("created", total).with("total", total);
+                emit_fields(root, "world_loading_progress", &payload);
+                true
+            }
+        };
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            if ready && state.state == State::Loading {
+                set_state(&mut state, State::Startup);
+                emit_fields(root, "world_ready", &Fields::new());
+            }
+        }
+    }
 
-            self.process_schedule
-                .execute(&mut world, &mut self.resources);
+    pub fn new_round(&mut self, root: &Node2D) {
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("new_round: No GameState");
+                    return;
+                }
+                Some(state) => state,
+            };
+            let player = match state.current_player {
+                None => {
+                    godot_error!("new_round: No current player");
+                    return;
+                }
+                Some(player) => player,
+            };
+            UpdateNodes::issue_command(root, world, &mut state, Command::EndTurn { player });
+        });
+    }
 
-            while let Some::<Ref<InputEvent>>(event) = self.input_queue.pop_front() {
-                if let Some(event) = event.clone().cast::<InputEventMouse>() {
-                    let mut event = unsafe { event.assume_safe() };
-                    let button_index = if event.is_pressed() {
-                        Some(event.button_mask())
+    /// Ends the turn of the active unit with the initiative rules. Returns false in the classic
+    /// turn order, where whole turns are ended with `new_round`.
+    pub fn pass_turn(&mut self, root: &Node2D) -> bool {
+        let mut passed = false;
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("pass_turn: No GameState");
+                    return;
+                }
+                Some(state) => state,
+            };
+            let player = match state.current_player {
+                Some(player) if state.rules.initiative => player,
+                _ => return,
+            };
+            UpdateNodes::issue_command(root, world, &mut state, Command::EndTurn { player });
+            passed = state.state == State::NewRound;
+        });
+        passed
+    }
+
+    /// Orders the selected unit to use its ability `index` on `target`. Returns false if no unit
+    /// is selected or the ability cannot be used there.
+    pub fn use_ability(&mut self, root: &Node2D, index: usize, target: Hexagon) -> bool {
+        let mut used = false;
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("use_ability: No GameState");
+                    return;
+                }
+                Some(state) => state,
+            };
+            let (entity, player) = match (&state.state, state.current_player) {
+                (State::Selected(entity), Some(player))
+                | (State::UnitTurn(entity), Some(player)) => (*entity, player),
+                _ => return,
+            };
+            let unit = match PersistentId::of_entity(world, entity) {
+                None => return,
+                Some(unit) => unit,
+            };
+            let command = Command::UseAbility {
+                player,
+                unit,
+                ability: index,
+                target,
+            };
+            UpdateNodes::issue_command(root, world, &mut state, command);
+            used = matches!(state.state, State::UsingAbility(_, _, _));
+        });
+        used
+    }
+
+    /// Orders the selected unit to watch for enemies. Returns false if no unit is selected or it
+    /// has no attack left.
+    pub fn overwatch_selected(&mut self, root: &Node2D) -> bool {
+        let mut ordered = false;
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("overwatch_selected: No GameState");
+                    return;
+                }
+                Some(state) => state,
+            };
+            let (entity, player) = match (&state.state, state.current_player) {
+                (State::Selected(entity), Some(player))
+                | (State::UnitTurn(entity), Some(player)) => (*entity, player),
+                _ => return,
+            };
+            let unit = match PersistentId::of_entity(world, entity) {
+                None => return,
+                Some(unit) => unit,
+            };
+            let command = Command::Overwatch { player, unit };
+            UpdateNodes::issue_command(root, world, &mut state, command);
+            ordered = matches!(state.state, State::EnteringOverwatch(_));
+        });
+        ordered
+    }
+
+    /// Orders the selected unit to attack the bridge between two hexagons. Returns false if no
+    /// demolition unit is selected, there is no bridge or it is out of range.
+    pub fn attack_edge(&mut self, root: &Node2D, from: Hexagon, to: Hexagon) -> bool {
+        let mut ordered = false;
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("attack_edge: No GameState");
+                    return;
+                }
+                Some(state) => state,
+            };
+            let (entity, player) = match (&state.state, state.current_player) {
+                (State::Selected(entity), Some(player))
+                | (State::UnitTurn(entity), Some(player)) => (*entity, player),
+                _ => return,
+            };
+            let unit = match PersistentId::of_entity(world, entity) {
+                None => return,
+                Some(unit) => unit,
+            };
+            let command = Command::AttackEdge {
+                player,
+                unit,
+                from,
+                to,
+            };
+            UpdateNodes::issue_command(root, world, &mut state, command);
+            ordered = matches!(state.state, State::AttackingEdge(_, _, _));
+        });
+        ordered
+    }
+
+    /// Orders the selected unit to move into the building it stands on. Returns false if there
+    /// is no building or the unit is inside already.
+    pub fn garrison_selected(&mut self, root: &Node2D) -> bool {
+        self.order_selected(
+            root,
+            "garrison_selected",
+            |player, unit| Command::Garrison { player, unit },
+            |state| matches!(state, State::Garrisoning(_)),
+        )
+    }
+
+    /// Orders the selected unit to leave its building for the neighbour `target`. Returns false
+    /// if the unit is not inside a building or `target` is not free.
+    pub fn ungarrison(&mut self, root: &Node2D, target: Hexagon) -> bool {
+        self.order_selected(
+            root,
+            "ungarrison",
+            |player, unit| Command::Ungarrison {
+                player,
+                unit,
+                target,
+            },
+            |state| matches!(state, State::Ungarrisoning(_, _)),
+        )
+    }
+
+    /// Orders the selected unit to attack the building on `target`. Returns false if there is
+    /// no building in its range or an own unit is inside.
+    pub fn attack_building(&mut self, root: &Node2D, target: Hexagon) -> bool {
+        self.order_selected(
+            root,
+            "attack_building",
+            |player, unit| Command::AttackBuilding {
+                player,
+                unit,
+                target,
+            },
+            |state| matches!(state, State::AttackingBuilding(_, _)),
+        )
+    }
+
+    /// Orders the selected unit to turn in place to face `facing`. Returns false if it has no
+    /// movement left.
+    pub fn rotate_selected(&mut self, root: &Node2D, facing: Direction) -> bool {
+        self.order_selected(
+            root,
+            "rotate_selected",
+            |player, unit| Command::Rotate {
+                player,
+                unit,
+                facing,
+            },
+            |state| matches!(state, State::Turning(_, _)),
+        )
+    }
+
+    /// Orders the selected units to fall back to `rally`, each to its own slot around it, and
+    /// emits "retreat_ordered" with their ids. Returns false without a selection of the local
+    /// player.
+    pub fn retreat_all(&mut self, root: &Node2D, rally: Hexagon) -> bool {
+        let mut state = match self.resources.get_mut::<GameState>() {
+            None => {
+                godot_error!("retreat_all: No GameState");
+                return false;
+            }
+            Some(state) => state,
+        };
+        let members = state.state.selection();
+        if members.is_empty() || !state.state.accepts_orders() || !state.is_local_turn() {
+            return false;
+        }
+        let ids = VariantArray::new();
+        with_world(|world| {
+            state.cancel_group_orders();
+            state.retreats = assign_slots(&*world, &members, rally).into_iter().collect();
+            for (entity, _) in &state.retreats {
+                if let Some(id) = PersistentId::of_entity(&*world, *entity) {
+                    ids.push(id.0 as i64);
+                }
+            }
+        });
+        unsafe {
+            root.call_deferred(
+                "emit_signal",
+                &[
+                    GodotString::from_str("retreat_ordered").to_variant(),
+                    ids.into_shared().to_variant(),
+                ],
+            );
+        }
+        true
+    }
+
+    /// What an attack of the selected unit on the unit on `target` would deal and which side of
+    /// it the attack would hit. Empty without a selected unit or a unit on `target`, only the
+    /// "reason" if the unit moved and cannot fire after moving.
+    pub fn preview_attack(&self, target: Hexagon) -> Dictionary {
+        let mut dict = Dictionary::new().into_shared();
+        let state = match self.resources.get::<GameState>() {
+            None => return dict,
+            Some(state) => state,
+        };
+        let attacker = match state.state {
+            State::Selected(entity) | State::Targeting(entity) | State::UnitTurn(entity) => entity,
+            _ => return dict,
+        };
+        with_world(|world| {
+            let preview = <(Entity, &Hexagon, &Unit)>::query()
+                .iter(world)
+                .find(|(_, position, _)| **position == target)
+                .and_then(|(defender, _, _)| {
+                    attack_preview(world, &state.rules, attacker, *defender)
+                });
+            if let Some(preview) = preview {
+                dict = preview.to_fields().to_dictionary().into_shared();
+            } else {
+                let (validity, _) =
+                    target_validity(world, &state.rules, &state.map_bounds, attacker, &target);
+                if validity == TargetValidity::MovedBeforeFiring {
+                    dict = Fields::new()
+                        .with("reason", validity.reason())
+                        .to_dictionary()
+                        .into_shared();
+                }
+            }
+        });
+        dict
+    }
+
+    /// Issues the command made for the selected unit and returns whether the state machine took
+    /// it up, which `ordered` recognizes by the state.
+    fn order_selected<C, O>(&mut self, root: &Node2D, name: &str, command: C, ordered: O) -> bool
+    where
+        C: Fn(usize, PersistentId) -> Command,
+        O: Fn(&State) -> bool,
+    {
+        let mut accepted = false;
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("{}: No GameState", name);
+                    return;
+                }
+                Some(state) => state,
+            };
+            let (entity, player) = match (&state.state, state.current_player) {
+                (State::Selected(entity), Some(player))
+                | (State::UnitTurn(entity), Some(player)) => (*entity, player),
+                _ => return,
+            };
+            let unit = match PersistentId::of_entity(world, entity) {
+                None => return,
+                Some(unit) => unit,
+            };
+            UpdateNodes::issue_command(root, world, &mut state, command(player, unit));
+            accepted = ordered(&state.state);
+        });
+        accepted
+    }
+
+    /// Applies a command received from another client. Returns false if the command could not be
+    /// parsed or is not legal in the current state.
+    pub fn apply_remote_command(&mut self, json: &str) -> bool {
+        let command = match Command::from_json(json) {
+            Err(error) => {
+                godot_warn!("Could not parse remote command: {}", error);
+                return false;
+            }
+            Ok(command) => command,
+        };
+        let mut applied = false;
+        with_world(|world| match self.resources.get_mut::<GameState>() {
+            None => godot_error!("apply_remote_command: No GameState"),
+            Some(mut state) => match apply_command(world, &mut state, &command) {
+                Err(error) => godot_warn!("Remote command rejected: {:?}", error),
+                Ok(()) => applied = true,
+            },
+        });
+        applied
+    }
+
+    /// Applies a command produced by local input and announces it, so it can be sent to the other
+    /// clients.
+    fn issue_command(root: &Node2D, world: &World, state: &mut GameState, command: Command) {
+        if let Err(error) = apply_local_command(world, state, &command) {
+            if error == CommandError::NotYourTurn {
+                emit_input_error(root, "not_your_turn");
+            }
+            if error == CommandError::NoCommandPointsLeft {
+                emit_input_error(root, "no_command_points_left");
+            }
+            if let (CommandError::TutorialBlocked, Some(constraint)) =
+                (error, state.tutorial.constraint())
+            {
+                let payload = constraint.to_dictionary();
+                payload.insert("reason", constraint.reason().translate(root));
+                unsafe {
+                    root.call_deferred(
+                        "emit_signal",
+                        &[
+                            GodotString::from_str("tutorial_blocked").to_variant(),
+                            payload.owned_to_variant(),
+                        ],
+                    );
+                }
+            }
+            godot_warn!("Command rejected: {:?}", error);
+            return;
+        }
+        match command.to_json() {
+            Err(error) => godot_error!("Could not serialize command: {}", error),
+            Ok(json) => unsafe {
+                root.call_deferred(
+                    "emit_signal",
+                    &[
+                        GodotString::from_str("local_command_issued").to_variant(),
+                        json.to_variant(),
+                    ],
+                );
+            },
+        }
+    }
+
+    pub fn get_log_entries(&self, owner: &Node2D, count: usize) -> VariantArray {
+        let entries = VariantArray::new();
+        if let Some(state) = self.resources.get::<GameState>() {
+            for entry in state.combat_log.latest(count) {
+                entries.push(entry.to_dictionary(owner).owned_to_variant());
+            }
+        }
+        entries.into_shared()
+    }
+
+    /// The name of the state the state machine is in, see `State::name`.
+    pub fn get_state_name(&self) -> String {
+        self.resources
+            .get::<GameState>()
+            .map_or_else(String::new, |state| state.state.name().to_owned())
+    }
+
+    pub fn get_state_checksum(&self) -> u64 {
+        let mut checksum = 0;
+        match self.resources.get::<GameState>() {
+            None => godot_error!("get_state_checksum: No GameState"),
+            Some(state) => with_world(|world| checksum = state.compute_checksum(world)),
+        }
+        checksum
+    }
+
+    pub fn save_game(&self) -> Option<String> {
+        let state = match self.resources.get::<GameState>() {
+            None => {
+                godot_error!("save_game: No GameState");
+                return None;
+            }
+            Some(state) => state,
+        };
+        let mut data = None;
+        with_world(|world| data = Some(SaveData::from_game(world, &state)));
+        match save_game(&data?) {
+            Err(error) => {
+                godot_error!("Could not serialize save: {}", error);
+                None
+            }
+            Ok(json) => Some(json),
+        }
+    }
+
+    /// Replaces the current game with the saved one. On failure the game is left as it was and
+    /// the "load_failed" signal is emitted with the reason.
+    pub fn load_game(&mut self, root: &Node2D, json: &str) -> bool {
+        let data = match load_game(json) {
+            Err(error) => {
+                unsafe {
+                    root.call_deferred(
+                        "emit_signal",
+                        &[
+                            GodotString::from_str("load_failed").to_variant(),
+                            error.message().to_variant(),
+                        ],
+                    );
+                }
+                return false;
+            }
+            Ok(data) => data,
+        };
+        let mut state = match self.resources.get_mut::<GameState>() {
+            None => {
+                godot_error!("load_game: No GameState");
+                return false;
+            }
+            Some(state) => state,
+        };
+        with_world(|world| {
+            let replaced: Vec<Entity> = <(Entity, &Unit)>::query()
+                .iter(world)
+                .map(|(entity, _)| *entity)
+                .chain(
+                    <(Entity, &Pickup)>::query()
+                        .iter(world)
+                        .map(|(entity, _)| *entity),
+                )
+                .chain(
+                    <(Entity, &Building)>::query()
+                        .iter(world)
+                        .map(|(entity, _)| *entity),
+                )
+                .chain(
+                    <(Entity, &SupplySource)>::query()
+                        .iter(world)
+                        .map(|(entity, _)| *entity),
+                )
+                .chain(
+                    <(Entity, &GroundEffect)>::query()
+                        .iter(world)
+                        .map(|(entity, _)| *entity),
+                )
+                .collect();
+            for entity in replaced {
+                world.remove(entity);
+            }
+            data.spawn(
+                world,
+                &mut state.persistent_ids,
+                Some(dummy_unit_template()),
+            );
+            state.refresh_map_bounds(&*world);
+        });
+        state.action_log = ActionLog::default();
+        state.influence.mark_dirty();
+        data.restore_state(&mut state);
+        with_world(|world| data.restore_promotion(&*world, &mut state));
+        true
+    }
+
+    /// Runs a map edit with the world and the state. If it changed anything, the selection is
+    /// cleared and the grid redrawn.
+    pub fn edit_map<F>(&mut self, mut edit: F) -> bool
+    where
+        F: FnMut(&mut World, &mut GameState) -> bool,
+    {
+        let mut state = match self.resources.get_mut::<GameState>() {
+            None => {
+                godot_error!("edit_map: No GameState");
+                return false;
+            }
+            Some(state) => state,
+        };
+        let mut changed = false;
+        let spawn_modifiers = &mut self.spawn_modifiers;
+        with_world(|world| {
+            changed = edit(world, &mut state);
+            spawn_modifiers.apply_pending(world);
+            if changed {
+                state.refresh_map_bounds(&*world);
+            }
+        });
+        if changed {
+            state.influence.mark_dirty();
+            // The map changed under whatever the state machine was doing.
+            force_state(&mut state, State::Waiting);
+        }
+        changed
+    }
+
+    /// Spawns the units in one go, see `spawn::spawn_units`. The entries are the units read
+    /// from the script or why they could not be read. Every entry is checked first and nothing
+    /// is spawned if one of them is invalid. Returns the error of every entry.
+    pub fn spawn_units_bulk(
+        &mut self,
+        entries: &[Result<UnitSpawn, String>],
+    ) -> Vec<Option<String>> {
+        let spawns: Vec<UnitSpawn> = entries
+            .iter()
+            .filter_map(|entry| entry.as_ref().ok().cloned())
+            .collect();
+        let mut errors = Vec::new();
+        self.edit_map(|world, state| {
+            let mut validation = validate_spawns(world, &state.unit_catalog, &spawns).into_iter();
+            errors = entries
+                .iter()
+                .map(|entry| match entry {
+                    Err(error) => Some(error.clone()),
+                    Ok(spawn) => {
+                        let valid = validation.next();
+                        if spawn.player >= state.players.len() {
+                            Some(format!("Unknown player {}", spawn.player))
+                        } else {
+                            valid.and_then(Result::err).map(|error| error.to_string())
+                        }
+                    }
+                })
+                .collect();
+            if spawns.is_empty() || errors.iter().any(Option::is_some) {
+                return false;
+            }
+            let handicaps = state.handicaps();
+            spawn_units(
+                world,
+                &mut state.persistent_ids,
+                &state.unit_catalog,
+                &handicaps,
+                &spawns,
+                state.spawn_nodes,
+            );
+            true
+        });
+        errors
+    }
+
+    /// Adds a modifier for the stats of new units after those registered before. Returns false
+    /// if it was registered already.
+    pub fn register_spawn_modifier(&mut self, modifier: ScriptModifier) -> bool {
+        self.spawn_modifiers.register(modifier)
+    }
+
+    /// Returns false if the modifier was not registered.
+    pub fn unregister_spawn_modifier(&mut self, modifier: &ScriptModifier) -> bool {
+        self.spawn_modifiers.unregister(modifier)
+    }
+
+    /// Replaces the unit catalog and applies changed stats to the existing units.
+    pub fn set_unit_catalog(&mut self, catalog: UnitCatalog) {
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("set_unit_catalog: No GameState"),
+            Some(mut state) => {
+                with_world(|world| {
+                    apply_catalog_changes(world, &state.unit_catalog, &catalog);
+                    clear_node_creation_failures(world);
+                });
+                state.unit_catalog = catalog;
+            }
+        }
+    }
+
+    /// Where the camera should look, see `focus_position`. Without anything to show this is
+    /// where it was last sent.
+    pub fn get_focus_position(&self) -> Vector2 {
+        let mut focus = None;
+        if let (Some(state), Some(hexfield_size)) = (
+            self.resources.get::<GameState>(),
+            self.resources.get::<HexfieldSize>(),
+        ) {
+            with_world(|world| focus = focus_position(&*world, &state, hexfield_size.0));
+        }
+        focus
+            .or_else(|| self.focus.last())
+            .unwrap_or_else(Vector2::zero)
+    }
+
+    pub fn get_hexagon_position(&self, hexagon: Hexagon) -> Vector2 {
+        match self.resources.get::<HexfieldSize>() {
+            None => Vector2::zero(),
+            Some(hexfield_size) => get_2d_position_from_hex(&hexagon, hexfield_size.0),
+        }
+    }
+
+    pub fn get_actionable_units(&self) -> VariantArray {
+        let mut units = VariantArray::new().into_shared();
+        if let Some(state) = self.resources.get::<GameState>() {
+            with_world(|world| units = actionable_units_to_array(&*world, &state));
+        }
+        units
+    }
+
+    /// The entities passing the filter as dictionaries. Nothing is shown while the view is
+    /// hidden, and under the fog of war only what the current player sees.
+    pub fn query_entities(&self, filter: &EntityFilter) -> VariantArray {
+        let rows = VariantArray::new();
+        if let Some(state) = self.resources.get::<GameState>() {
+            if state.is_view_hidden() {
+                return rows.into_shared();
+            }
+            with_world(|world| {
+                for row in query_entities(&*world, filter) {
+                    let hidden_by_fog = state.rules.fog_of_war
+                        && state
+                            .current_player
+                            .map_or(false, |viewer| match row.player {
+                                Some(owner) => state.last_seen.hides(viewer, owner, &row.position),
+                                None => !state.last_seen.sees(viewer, &row.position),
+                            });
+                    if !hidden_by_fog {
+                        rows.push(row.to_dictionary().into_shared());
+                    }
+                }
+            });
+        }
+        rows.into_shared()
+    }
+
+    /// The planned orders of the players controlled on this machine. Observers see none.
+    pub fn get_planned_orders(&self) -> VariantArray {
+        let mut orders = VariantArray::new().into_shared();
+        if let Some(state) = self.resources.get::<GameState>() {
+            if !state.observing {
+                let mut players: Vec<usize> = state.local_players.iter().copied().collect();
+                players.sort_unstable();
+                with_world(|world| {
+                    orders = orders_to_array(&*world, &state.persistent_ids, &players)
+                });
+            }
+        }
+        orders
+    }
+
+    /// Plans a move of the unit to `target` along the shortest path. Returns false outside of
+    /// the planning phase or if the move is not legal.
+    pub fn plan_move(&mut self, root: &Node2D, unit: PersistentId, target: Hexagon) -> bool {
+        let mut planned = false;
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("plan_move: No GameState");
+                    return;
+                }
+                Some(state) => state,
+            };
+            let owner_and_start = state
+                .persistent_ids
+                .find(world, unit)
+                .and_then(|entity| world.entry_ref(entity).ok())
+                .and_then(|entry| {
+                    let start = *entry.get_component::<Hexagon>().ok()?;
+                    Some((get_player_of_entity(&entry)?, start))
+                });
+            let (player, start) = match owner_and_start {
+                None => return,
+                Some(owner_and_start) => owner_and_start,
+            };
+            let path = find_path(&start, &target, &state.edges, &state.map_bounds, world);
+            if state.state != State::Planning || path.is_empty() {
+                return;
+            }
+            let orders = state.planned_orders.len();
+            UpdateNodes::issue_command(
+                root,
+                world,
+                &mut state,
+                Command::Move { player, unit, path },
+            );
+            planned = state.planned_orders.len() > orders;
+        });
+        planned
+    }
+
+    /// Plans an attack of the unit on `target`, carried out after all moves. Returns false
+    /// outside of the planning phase or if the attack is not legal.
+    pub fn plan_attack(&mut self, root: &Node2D, unit: PersistentId, target: PersistentId) -> bool {
+        let mut planned = false;
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("plan_attack: No GameState");
+                    return;
+                }
+                Some(state) => state,
+            };
+            let player = match state
+                .persistent_ids
+                .find(world, unit)
+                .and_then(|entity| world.entry_ref(entity).ok())
+                .and_then(|entry| get_player_of_entity(&entry))
+            {
+                None => return,
+                Some(player) => player,
+            };
+            if state.state != State::Planning {
+                return;
+            }
+            let orders = state.planned_orders.len();
+            let command = Command::Attack {
+                player,
+                attacker: unit,
+                defender: target,
+            };
+            UpdateNodes::issue_command(root, world, &mut state, command);
+            planned = state.planned_orders.len() > orders;
+        });
+        planned
+    }
+
+    /// Ends the planning phase and carries out the orders of all players. The round is resolved
+    /// with a command of a local player, so it is logged and reaches the other clients. Returns
+    /// false if nobody is planning.
+    pub fn resolve_round(&mut self, root: &Node2D) -> bool {
+        let mut resolved = false;
+        with_world(|world| {
+            let mut state = match self.resources.get_mut::<GameState>() {
+                None => {
+                    godot_error!("resolve_round: No GameState");
+                    return;
+                }
+                Some(state) => state,
+            };
+            let player = match state.local_players.iter().copied().min() {
+                Some(player) if state.state == State::Planning => player,
+                _ => return,
+            };
+            UpdateNodes::issue_command(root, world, &mut state, Command::ResolveRound { player });
+            resolved = state.state == State::Resolving;
+        });
+        resolved
+    }
+
+    /// Changes the handicap of `player` before the match. Fails once the first round is over.
+    pub fn set_player_handicap(
+        &mut self,
+        player: usize,
+        changes: &HandicapChanges,
+    ) -> Result<(), HandicapError> {
+        let mut result = Ok(());
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("set_player_handicap: No GameState"),
+            Some(mut state) => with_world(|world| {
+                let current = match state.players.get(player) {
+                    None => {
+                        result = Err(HandicapError::UnknownPlayer(player));
+                        return;
+                    }
+                    Some(data) => data.get_handicap(),
+                };
+                result = changes
+                    .apply(&current)
+                    .and_then(|handicap| state.set_handicap(world, player, handicap));
+            }),
+        }
+        result
+    }
+
+    pub fn rename_unit(&mut self, id: PersistentId, name: &str) -> Result<(), RenameError> {
+        let mut result = Err(RenameError::UnknownUnit(id));
+        match self.resources.get::<GameState>() {
+            None => godot_error!("rename_unit: No GameState"),
+            Some(state) => with_world(|world| {
+                result = rename_unit(world, &state.persistent_ids, id, name);
+            }),
+        }
+        result
+    }
+
+    /// Seeds the random events, so a match can be played again with the same events.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("set_random_seed: No GameState"),
+            Some(mut state) => state.rng = Rng::new(seed),
+        }
+    }
+
+    /// Sets the step of a tutorial, `None` lifts the constraint.
+    pub fn set_tutorial_constraint(&mut self, constraint: Option<TutorialConstraint>) {
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("set_tutorial_constraint: No GameState"),
+            Some(mut state) => state.tutorial.set_constraint(constraint),
+        }
+    }
+
+    /// The map and the units as a scenario, together with the triggers of the loaded scenario.
+    pub fn scenario(&self) -> Option<Scenario> {
+        let state = match self.resources.get::<GameState>() {
+            None => {
+                godot_error!("scenario: No GameState");
+                return None;
+            }
+            Some(state) => state,
+        };
+        let mut scenario = None;
+        with_world(|world| scenario = Some(Scenario::from_world(world)));
+        scenario.map(|mut scenario| {
+            scenario.triggers = state.triggers.definitions().to_vec();
+            scenario.edges = state.edges.to_list();
+            scenario.ai_players = state
+                .players
+                .iter()
+                .enumerate()
+                .filter_map(|(player, data)| {
+                    data.get_ai_profile().map(|profile| ScenarioAi {
+                        player,
+                        profile: ScenarioAiProfile::Weights(profile),
+                    })
+                })
+                .collect();
+            scenario
+        })
+    }
+
+    /// Lets this session control the game again, or only watch it if `player` is negative or an
+    /// observer.
+    pub fn set_local_controller(&mut self, player: i64) {
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("set_local_controller: No GameState"),
+            Some(mut state) => {
+                state.observing = player < 0
+                    || state
+                        .players
+                        .get(player as usize)
+                        .map_or(true, |controlled| controlled.is_observer());
+                if state.observing {
+                    state.current_path = Vec::new();
+                    if let State::Selected(_) = state.state {
+                        set_state(&mut state, State::Waiting);
+                    }
+                } else {
+                    state.local_players = std::iter::once(player as usize).collect();
+                }
+            }
+        }
+    }
+
+    /// Restricts local input to the given players. Hotseat games control every player.
+    pub fn set_local_players(&mut self, players: HashSet<usize>) {
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("set_local_players: No GameState"),
+            Some(mut state) => state.local_players = players,
+        }
+    }
+
+    /// The planned path of the selected unit as a curve, see `path_preview::curve_points` for
+    /// `smoothing`. Empty if no path is planned.
+    pub fn get_current_path_curve(&self, smoothing: f32) -> Ref<Curve2D, Unique> {
+        let mut points = Vec::new();
+        if let (Some(state), Some(hexfield_size)) = (
+            self.resources.get::<GameState>(),
+            self.resources.get::<HexfieldSize>(),
+        ) {
+            with_world(|world| points = path_preview(world, &state, hexfield_size.0).points);
+        }
+        build_curve(&curve_points(&points, smoothing))
+    }
+
+    /// Measures between two hexagons, see `measurement::measure`. The path cost is included
+    /// while a unit is selected.
+    pub fn measure(&self, from: Hexagon, to: Hexagon) -> Dictionary {
+        let mut dict = Dictionary::new().into_shared();
+        let hexfield_size = match self.resources.get::<HexfieldSize>() {
+            None => return dict,
+            Some(hexfield_size) => hexfield_size.0,
+        };
+        if let Some(state) = self.resources.get::<GameState>() {
+            let unit_selected = matches!(state.state, State::Selected(_));
+            with_world(|world| {
+                dict = measure(
+                    world,
+                    &state.edges,
+                    &state.map_bounds,
+                    from,
+                    to,
+                    unit_selected,
+                    hexfield_size,
+                )
+                .to_dictionary()
+                .into_shared()
+            });
+        }
+        dict
+    }
+
+    /// Pings the hexagon for the current player, or the first player controlled on this machine
+    /// while it is someone else's turn.
+    pub fn place_ping(&mut self, root: &Node2D, hexagon: Hexagon, kind: String) {
+        with_world(|world| match self.resources.get_mut::<GameState>() {
+            None => godot_error!("place_ping: No GameState"),
+            Some(mut state) => {
+                let player = match state.current_player {
+                    Some(player) if state.local_players.contains(&player) => Some(player),
+                    _ => state.local_players.iter().min().copied(),
+                };
+                match player {
+                    None => godot_warn!("place_ping: No local player"),
+                    Some(player) => UpdateNodes::issue_command(
+                        root,
+                        world,
+                        &mut state,
+                        Command::Ping {
+                            player,
+                            hexagon,
+                            kind: kind.clone(),
+                        },
+                    ),
+                }
+            }
+        });
+    }
+
+    /// The targets of the selected unit with `cover`, the number of hexagons covering them
+    /// against its attack.
+    pub fn get_attackable_targets(&self) -> VariantArray {
+        let targets = VariantArray::new();
+        if let Some(state) = self.resources.get::<GameState>() {
+            with_world(|world| {
+                let position_of = |entity: Entity| {
+                    world
+                        .entry_ref(entity)
+                        .ok()
+                        .and_then(|entry| entry.get_component::<Hexagon>().ok().copied())
+                };
+                let attacker = match state.state {
+                    State::Selected(selected) | State::Targeting(selected) => position_of(selected),
+                    _ => None,
+                };
+                for entity in &state.attackable_entities {
+                    if let Some(hexagon) = position_of(*entity) {
+                        let id = state.persistent_ids.id(*entity);
+                        let cover = attacker
+                            .map_or(0, |attacker| cover_count(&*world, &attacker, &hexagon));
+                        let position = Fields::from(&hexagon)
+                            .with("id", id.map_or(-1, |id| id.0 as i64))
+                            .with("cover", cover);
+                        targets.push(position.to_dictionary().owned_to_variant());
+                    }
+                }
+            });
+        }
+        targets.into_shared()
+    }
+
+    /// History of the unit at the hexagon, oldest entry first. Empty if there is no unit.
+    pub fn get_unit_history(&self, owner: &Node2D, hexagon: Hexagon) -> VariantArray {
+        let entries = VariantArray::new();
+        with_world(|world| {
+            for entity in get_entities_at_hexagon(&hexagon, world) {
+                if let Ok(entry) = world.entry_ref(entity) {
+                    if let Ok(history) = entry.get_component::<History>() {
+                        for history_entry in history.iter() {
+                            entries.push(history_entry.to_dictionary(owner).owned_to_variant());
+                        }
+                    }
+                }
+            }
+        });
+        entries.into_shared()
+    }
+
+    /// Puts the game state back into a state that takes orders after a panic.
+    pub fn recover_from_panic(&self) {
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("recover_from_panic: No GameState"),
+            Some(mut state) => recover_state(&mut state),
+        }
+    }
+
+    /// The logged actions grouped by round and player, oldest first.
+    pub fn get_turn_summaries(&self, owner: &Node2D) -> VariantArray {
+        let summaries = VariantArray::new();
+        if let Some(state) = self.resources.get::<GameState>() {
+            let log = &state.action_log;
+            for summary in log.turn_summaries() {
+                let dict = Dictionary::new();
+                dict.insert("round", summary.round);
+                dict.insert("player", summary.player as i64);
+                let actions = VariantArray::new();
+                for index in summary.actions {
+                    actions.push(
+                        log.actions[index]
+                            .to_dictionary(owner, index)
+                            .owned_to_variant(),
+                    );
+                }
+                dict.insert("actions", actions.owned_to_variant());
+                summaries.push(dict.owned_to_variant());
+            }
+        }
+        summaries.into_shared()
+    }
+
+    /// The units as they were right after the logged action with the index. The log is replayed
+    /// into a scratch world, the running game is not touched. Empty if there is no game, the error
+    /// if the replay did not get there.
+    pub fn preview_state_at(&self, action_index: usize) -> Result<VariantArray, ReplayError> {
+        let mut units = Ok(VariantArray::new());
+        if let Some(state) = self.resources.get::<GameState>() {
+            with_world(|world| {
+                units = replay_to(&*world, &state, action_index)
+                    .map(|(replayed, _)| unit_dictionaries(&replayed))
+            });
+        }
+        units.map(|units| units.into_shared())
+    }
+
+    /// The best command for the current player, empty if it is not the turn of a local player.
+    pub fn get_hint(&self) -> Dictionary<Unique> {
+        let mut hint = Dictionary::new();
+        if let Some(state) = self.resources.get::<GameState>() {
+            match state.current_player {
+                Some(player) if state.is_local_turn() => with_world(|world| {
+                    if let Some(best) = best_actions(world, &state, player, 1).first() {
+                        hint = best.to_dictionary();
+                    }
+                }),
+                _ => {}
+            }
+        }
+        hint
+    }
+
+    /// Lets the computer play for `player` with the given profile, `None` hands the player back
+    /// to a human. Returns false for unknown players.
+    pub fn set_ai_profile(&mut self, player: usize, profile: Option<AiProfile>) -> bool {
+        match self.resources.get_mut::<GameState>() {
+            None => {
+                godot_error!("set_ai_profile: No GameState");
+                false
+            }
+            Some(mut state) => match state.players.get_mut(player) {
+                None => false,
+                Some(data) => {
+                    data.set_ai_profile(profile);
+                    true
+                }
+            },
+        }
+    }
+
+    /// How the last order of the unit was scored, empty if it got none yet.
+    pub fn explain_move(&self, unit: PersistentId) -> Dictionary<Unique> {
+        self.resources
+            .get::<GameState>()
+            .and_then(|state| {
+                state
+                    .explanations
+                    .get(&unit)
+                    .map(|suggestion| suggestion.to_dictionary())
+            })
+            .unwrap_or_else(Dictionary::new)
+    }
+
+    /// The player dominating the hexagon and their influence there, empty if no player claims
+    /// it.
+    pub fn get_influence(&mut self, hexagon: Hexagon) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            with_world(|world| {
+                if let Some((player, influence)) = state.influence.claim(world, &hexagon) {
+                    dict.insert("player", player as i64);
+                    dict.insert("influence", influence);
+                }
+            });
+        }
+        dict
+    }
+
+    /// The hexagons the player can supply as dictionaries with q and r, ordered by position. Empty
+    /// without the supply rule and while the view is hidden.
+    pub fn get_supply_overlay(&self, player: usize) -> VariantArray {
+        let array = VariantArray::new();
+        let range = match self.resources.get::<GameState>() {
+            Some(state) if state.rules.supply && !state.is_view_hidden() => {
+                state.rules.supply_range
+            }
+            _ => return array.into_shared(),
+        };
+        let mut hexagons: Vec<Hexagon> = Vec::new();
+        with_world(|world| {
+            hexagons = supplied_hexagons(&*world, player, range)
+                .into_iter()
+                .collect()
+        });
+        hexagons.sort_by_key(|hexagon| (hexagon.get_q(), hexagon.get_r()));
+        for hexagon in hexagons {
+            let dict = Dictionary::new();
+            dict.insert("q", hexagon.get_q());
+            dict.insert("r", hexagon.get_r());
+            array.push(dict.into_shared());
+        }
+        array.into_shared()
+    }
+
+    /// Entity counts of the world and the current state.
+    pub fn get_world_diagnostics(&self) -> Dictionary<Unique> {
+        let mut diagnostics = WorldDiagnostics::default();
+        with_world(|world| diagnostics = WorldDiagnostics::collect(world));
+        let dict = diagnostics.to_dictionary();
+        if let Some(state) = self.resources.get::<GameState>() {
+            dict.insert("state", state.state.to_string());
+        }
+        dict
+    }
+
+    pub fn validate_world(&self, node_entities: &HashSet<Entity>) -> Vec<String> {
+        let mut violations = Vec::new();
+        if let Some(state) = self.resources.get::<GameState>() {
+            with_world(|world| violations = validate_world(world, &state, node_entities));
+        }
+        violations
+    }
+
+    /// Timings of the profiled buckets, by bucket name.
+    pub fn get_frame_timings(&self) -> Dictionary {
+        let timings = Dictionary::new();
+        profiler::with_profiler(|profiler| {
+            for (name, bucket) in profiler.buckets() {
+                let timing = Dictionary::new();
+                timing.insert("last_ms", bucket.last_ms);
+                timing.insert("avg_ms", bucket.avg_ms());
+                timing.insert("max_ms", bucket.max_ms);
+                timings.insert(name, timing.owned_to_variant());
+            }
+        });
+        timings.into_shared()
+    }
+
+    pub fn set_color_palette(&mut self, name: &str) -> bool {
+        match self.resources.get_mut::<GameState>() {
+            None => {
+                godot_error!("set_color_palette: No GameState");
+                false
+            }
+            Some(mut state) => state.set_palette(name),
+        }
+    }
+
+    pub fn set_banner_duration(&mut self, duration: f64) {
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            state.banner_duration = duration;
+        }
+    }
+
+    pub fn set_privacy_screen(&mut self, enabled: bool) {
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            state.privacy_screen = enabled;
+        }
+    }
+
+    pub fn set_touch_thresholds(&mut self, long_press_duration: f64, drag_distance: f32) {
+        self.touch.long_press_duration = long_press_duration;
+        self.touch.drag_distance = drag_distance;
+    }
+
+    pub fn set_double_click_time(&mut self, seconds: f64) {
+        self.clicks.double_click_time = seconds;
+    }
+
+    /// Thins out the statistics series right away if it is longer than `limit`.
+    pub fn set_statistics_limit(&mut self, limit: usize) {
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            state.statistics_limit = limit;
+            state.statistics.downsample(limit);
+        }
+    }
+
+    pub fn get_statistics_series(&self) -> Dictionary<Unique> {
+        self.resources
+            .get::<GameState>()
+            .map(|state| state.statistics.to_dictionary())
+            .unwrap_or_else(Dictionary::new)
+    }
+
+    /// The generation counter of the matches, for tagging the world events with it.
+    pub fn match_generation(&self) -> MatchGeneration {
+        self.resources
+            .get::<GameState>()
+            .map(|state| state.match_generation.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_hitstop_cap(&mut self, cap: f64) {
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            state.hitstop_cap = cap;
+        }
+    }
+
+    /// Resizes the hexagons. The nodes move to their new positions on the next frame, their scale
+    /// follows right away.
+    pub fn set_hexfield_size(&mut self, hexfield_size: f32) {
+        match self.resources.get_mut::<HexfieldSize>() {
+            Some(mut size) if (size.0 - hexfield_size).abs() > f32::EPSILON => {
+                size.0 = hexfield_size
+            }
+            _ => return,
+        }
+        let scale = hexfield_scale(hexfield_size);
+        with_world(|world| {
+            for (node, template) in <(&NodeComponent, Option<&NodeTemplate>)>::query().iter(world) {
+                if let Some(node) = node.get_node() {
+                    let (x, y) = template
+                        .map_or((1.0, 1.0), |template| (template.scale_x, template.scale_y));
+                    node.set_scale(Vector2::new(x * scale, y * scale));
+                }
+            }
+        });
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            state.redraw_grid = true;
+        }
+    }
+
+    /// Grows or shrinks the map of the running game to `radius`, see `resize_grid`. New games
+    /// get a map of the radius as well.
+    pub fn set_grid_radius(&mut self, radius: u32) -> Result<(), GridResizeError> {
+        let mut state = match self.resources.get_mut::<GameState>() {
+            Some(state) if state.grid_radius != radius => state,
+            _ => return Ok(()),
+        };
+        let mut result = Ok(());
+        with_world(|world| {
+            result = resize_grid(world, radius);
+            state.refresh_map_bounds(&*world);
+        });
+        result?;
+        state.grid_radius = radius;
+        state.hex_cursor.set_map_radius(radius);
+        state.influence.mark_dirty();
+        state.redraw_grid = true;
+        Ok(())
+    }
+
+    /// Replaces the map of the running game with fields on `hexagons`, see `replace_grid`. The
+    /// grid radius becomes the distance to the farthest of them, so the cursor reaches every one.
+    pub fn replace_grid(
+        &mut self,
+        hexagons: &[Hexagon],
+        template: Option<NodeTemplate>,
+    ) -> Result<(), GridResizeError> {
+        let mut state = match self.resources.get_mut::<GameState>() {
+            None => return Ok(()),
+            Some(state) => state,
+        };
+        let mut result = Ok(());
+        with_world(|world| {
+            result = replace_grid(world, hexagons, template.as_ref());
+            state.refresh_map_bounds(&*world);
+        });
+        result?;
+        let radius = hexagons
+            .iter()
+            .map(|hexagon| hexagon.distance_to(&Hexagon::zero()) as u32)
+            .max()
+            .unwrap_or(0);
+        state.grid_radius = radius;
+        state.hex_cursor.set_map_radius(radius);
+        state.influence.mark_dirty();
+        state.redraw_grid = true;
+        Ok(())
+    }
+
+    /// The size of the lake carved into the maps of new games.
+    pub fn set_lake_size(&mut self, size: u32) {
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            state.lake_size = size;
+        }
+    }
+
+    pub fn grid_radius(&self) -> u32 {
+        self.resources
+            .get::<GameState>()
+            .map_or(MAP_RADIUS, |state| state.grid_radius)
+    }
+
+    pub fn set_cursor_orientation(&mut self, orientation: Orientation) {
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            state.hex_cursor.orientation = orientation;
+        }
+    }
+
+    /// Reveals the view of the incoming player once they sit at the screen.
+    pub fn confirm_player_ready(&mut self) -> bool {
+        match self.resources.get_mut::<GameState>() {
+            Some(mut state) if state.is_view_hidden() => dismiss_turn_transition(&mut state),
+            _ => false,
+        }
+    }
+
+    pub fn dismiss_banner(&mut self) -> bool {
+        match self.resources.get_mut::<GameState>() {
+            None => false,
+            Some(mut state) => dismiss_turn_transition(&mut state),
+        }
+    }
+
+    pub fn set_auto_end_turn(&mut self, enabled: bool, delay: f64) {
+        self.auto_end_turn.enabled = enabled;
+        self.auto_end_turn.delay = delay;
+    }
+
+    pub fn set_rules(&mut self, rules: Ruleset) {
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("set_rules: No GameState"),
+            Some(mut state) => {
+                state
+                    .map_bounds
+                    .set_holes_block_sight(rules.holes_block_sight);
+                state.rules = rules;
+            }
+        }
+    }
+
+    pub fn get_active_rules(&self) -> Dictionary {
+        match self.resources.get::<GameState>() {
+            None => Dictionary::new().into_shared(),
+            Some(state) => state.rules.to_dictionary().into_shared(),
+        }
+    }
+
+    pub fn set_log_capacity(&mut self, capacity: usize) {
+        match self.resources.get_mut::<GameState>() {
+            None => godot_error!("set_log_capacity: No GameState"),
+            Some(mut state) => state.combat_log.set_capacity(capacity),
+        }
+    }
+
+    pub fn execute(
+        &mut self,
+        root: &Node2D,
+        ui_node: TRef<'static, Control>,
+        camera_node: TRef<'static, Camera2D>,
+        delta: f64,
+    ) {
+        with_world(|mut world| {
+            let frame_timer = profiler::scope("frame");
+            self.input_time += delta;
+            self.resources.insert(Delta(delta));
+            if let Some(world) = root.get_world_2d() {
+                if let Some(state) = unsafe { world.assume_safe().direct_space_state() } {
+                    self.resources.insert(state);
+                }
+            }
+            self.resources.insert(UINode(ui_node));
+            self.resources.insert(MainCamera(camera_node));
+
+            if let Some(mut state) = self.resources.get_mut::<GameState>() {
+                for event in self.id_events.try_iter() {
+                    match event {
+                        Event::EntityInserted(entity, _) | Event::EntityRemoved(entity, _) => {
+                            state.persistent_ids.sync_entity(world, entity)
+                        }
+                        _ => {}
+                    }
+                }
+                let selected_hexagon = state.state.selection().first().and_then(|entity| {
+                    world
+                        .entry_ref(*entity)
+                        .ok()
+                        .and_then(|entry| entry.get_component::<Hexagon>().ok().copied())
+                });
+                {
+                    let state: &mut GameState = &mut *state;
+                    state.hex_cursor.follow_selection(
+                        state.selection_generation,
+                        selected_hexagon,
+                        &state.map_bounds,
+                    );
+                }
+                let was_resolving = state.state.is_resolving();
+                let events = {
+                    let _timer = profiler::scope("simulate");
+                    simulate_frame(&mut world, &mut state, delta)
+                };
+                self.spawn_modifiers.apply_pending(&mut world);
+                let action_resolved = was_resolving && !state.state.is_resolving();
+                let mut actionable_units_changed = action_resolved;
+                for event in events {
+                    let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+                    for cue in
+                        self.sound_cues
+                            .translate(&event, state.current_player, self.input_time)
+                    {
+                        emit_sound(root, &cue, hexfield_size);
+                    }
+                    if let Some((signal, payload)) = event_signal(&event, &state.persistent_ids) {
+                        emit_fields(root, signal, &payload);
+                    }
+                    match event {
+                        GameEvent::TurnStarted { round, player } => {
+                            actionable_units_changed = true;
+                            state.input_buffer.clear();
+                            state.cancel_group_orders();
+                            self.auto_end_turn.cancel();
+                            let payload = Fields::new()
+                                .with("round", round)
+                                .with("player", player)
+                                .with("checksum", state.compute_checksum(world) as i64);
+                            emit_fields(root, "turn_changed", &payload);
+                            emit_fields(
+                                root,
+                                "round_started",
+                                &round_started_payload(&*world, &state, round, player),
+                            );
+                            if state.is_view_hidden() {
+                                let mut payload = Fields::new().with("player", player);
+                                if let Some(player_data) = state.players.get(player) {
+                                    payload = payload.with("name", player_data.get_name());
+                                }
+                                emit_fields(root, "awaiting_player", &payload);
+                            }
+                        }
+                        GameEvent::PlanningStarted { .. } => {
+                            actionable_units_changed = true;
+                            state.input_buffer.clear();
+                            state.cancel_group_orders();
+                            self.auto_end_turn.cancel();
+                        }
+                        GameEvent::UnitTurnStarted { .. } => {
+                            actionable_units_changed = true;
+                            self.auto_end_turn.cancel();
+                        }
+                        GameEvent::OrderInterrupted { .. }
+                        | GameEvent::AbilityUsed { .. }
+                        | GameEvent::OverwatchChanged { .. }
+                        | GameEvent::GarrisonChanged { .. }
+                        | GameEvent::FacingChanged { .. }
+                        | GameEvent::PickupCollected { .. } => actionable_units_changed = true,
+                        GameEvent::CombatFeedback {
+                            position,
+                            shake,
+                            hitstop,
+                        } => {
+                            let payload = Fields::new()
+                                .with(
+                                    "position",
+                                    get_2d_position_from_hex(&position, hexfield_size),
+                                )
+                                .with("shake", shake)
+                                .with("hitstop", hitstop);
+                            emit_fields(root, "combat_feedback", &payload);
+                        }
+                        GameEvent::RandomEvent {
+                            kind,
+                            player,
+                            position,
+                        } => {
+                            let player_name = player
+                                .and_then(|player| state.players.get(player))
+                                .map(|player| player.get_name());
+                            let mut payload = Fields::new()
+                                .with("id", kind.id())
+                                .with(
+                                    "description",
+                                    describe(kind, player_name.as_deref(), position.as_ref())
+                                        .translate(root),
+                                )
+                                .with("player", player.map_or(-1, |player| player as i64))
+                                .with("weather", state.weather.name());
+                            if let Some(position) = position {
+                                payload = payload.merge(Fields::from(&position));
+                            }
+                            emit_fields(root, "random_event", &payload);
+                        }
+                        _ => {}
+                    }
+                }
+                for (old, new) in state.state_changes.drain(..) {
+                    let payload = Fields::new().with("old", old).with("new", new);
+                    emit_fields(root, "state_changed", &payload);
+                }
+                if actionable_units_changed {
+                    let units = actionable_units_to_array(&*world, &state);
+                    if action_resolved && units.is_empty() && state.is_local_turn() {
+                        self.auto_end_turn.schedule();
+                    }
+                    unsafe {
+                        root.call_deferred(
+                            "emit_signal",
+                            &[
+                                GodotString::from_str("actionable_units_changed").to_variant(),
+                                units.to_variant(),
+                            ],
+                        );
+                    }
+                }
+                if self.auto_end_turn.update(delta, &state.state) {
+                    if let Some(player) = state.current_player {
+                        UpdateNodes::issue_command(
+                            root,
+                            world,
+                            &mut state,
+                            Command::EndTurn { player },
+                        );
+                    }
+                }
+            }
+
+            self.process_schedule
+                .execute(&mut world, &mut self.resources);
+            self.announce_loading(root);
+
+            if let Some(mut state) = self.resources.get_mut::<GameState>() {
+                state.pings.update(delta);
+                for ping in state.pings.take_unannounced() {
+                    unsafe {
+                        root.call_deferred(
+                            "emit_signal",
+                            &[
+                                GodotString::from_str("ping_placed").to_variant(),
+                                ping.to_dictionary().owned_to_variant(),
+                            ],
+                        );
+                    }
+                }
+                for message in std::mem::take(&mut state.notifications) {
+                    log_print!("{}", message.translate(root));
+                }
+                for entry in state.combat_log.take_unannounced() {
+                    unsafe {
+                        root.call_deferred(
+                            "emit_signal",
+                            &[
+                                GodotString::from_str("log_entry_added").to_variant(),
+                                entry.to_dictionary(root).owned_to_variant(),
+                            ],
+                        );
+                    }
+                }
+                for step in state.tutorial.take_completed() {
+                    unsafe {
+                        root.call_deferred(
+                            "emit_signal",
+                            &[
+                                GodotString::from_str("tutorial_step_done").to_variant(),
+                                step.to_dictionary().owned_to_variant(),
+                            ],
+                        );
+                    }
+                }
+                for event in state.triggers.take_unannounced() {
+                    unsafe {
+                        root.call_deferred(
+                            "emit_signal",
+                            &[
+                                GodotString::from_str("scenario_event").to_variant(),
+                                event.name.to_variant(),
+                                json_to_variant(&event.payload),
+                            ],
+                        );
+                    }
+                }
+                for unlock in state.achievements.take_unannounced() {
+                    unsafe {
+                        root.call_deferred(
+                            "emit_signal",
+                            &[
+                                GodotString::from_str("achievement_unlocked").to_variant(),
+                                unlock.achievement.id().to_variant(),
+                                unlock.to_dictionary().owned_to_variant(),
+                            ],
+                        );
+                    }
+                }
+            }
+
+            let pending_click = match self.resources.get_mut::<GameState>() {
+                None => None,
+                Some(mut state) => {
+                    let state: &mut GameState = &mut *state;
+                    state.input_buffer.take_ready(&*world, &state.state)
+                }
+            };
+            if let Some(hexagon) = pending_click {
+                self.click_hexagon(root, world, hexagon, false);
+            }
+            if let Some(click) = self.clicks.poll(self.input_time) {
+                self.handle_click(root, world, click);
+            }
+
+            if let Some(mut state) = self.resources.get_mut::<GameState>() {
+                let state: &mut GameState = &mut *state;
+                let next_move = if state.state.accepts_orders() {
+                    next_group_move(
+                        &*world,
+                        &state.edges,
+                        &state.map_bounds,
+                        &mut state.group_moves,
+                    )
+                    .or_else(|| {
+                        next_retreat(
+                            &*world,
+                            &state.edges,
+                            &state.map_bounds,
+                            &mut state.retreats,
+                        )
+                    })
+                } else {
+                    None
+                };
+                if let Some((entity, path)) = next_move {
+                    if let (Some(player), Some(unit)) = (
+                        state.current_player,
+                        PersistentId::of_entity(&*world, entity),
+                    ) {
+                        UpdateNodes::issue_command(
+                            root,
+                            world,
+                            state,
+                            Command::Move { player, unit, path },
+                        );
+                    }
+                } else if state.is_local_turn() {
+                    // Computer players give one order per frame, the state machine carries it
+                    // out before the next one.
+                    if let Some(command) = next_command(&*world, state) {
+                        UpdateNodes::issue_command(root, world, state, command);
+                    }
+                }
+            }
+
+            let loading = self
+                .resources
+                .get::<GameState>()
+                .map_or(false, |state| state.state == State::Loading);
+            if loading {
+                self.input_queue.clear();
+            }
+            while let Some::<Ref<InputEvent>>(event) = self.input_queue.pop_front() {
+                if self.handle_cursor_input(root, world, &event) {
+                    continue;
+                }
+                if unsafe { event.assume_safe() }.is_action_pressed(TOGGLE_HELP.name(), false) {
+                    self.toggle_help();
+                    continue;
+                }
+                if unsafe { event.assume_safe() }.is_action_pressed(RESELECT_LAST.name(), false) {
+                    self.reselect_last(root, world);
+                    continue;
+                }
+                if unsafe { event.assume_safe() }.is_action_pressed(ATTACK_MODE.name(), false) {
+                    self.enter_targeting(root, world);
+                    continue;
+                }
+                if let Some(event) = event.clone().cast::<InputEventScreenTouch>() {
+                    let event = unsafe { event.assume_safe() };
+                    let gesture = if event.is_pressed() {
+                        self.touch
+                            .press(event.index(), event.position(), self.input_time);
+                        None
+                    } else {
+                        self.touch.release(event.index(), self.input_time)
+                    };
+                    if let Some(gesture) = gesture {
+                        self.handle_gesture(root, world, gesture);
+                    }
+                } else if let Some(event) = event.clone().cast::<InputEventScreenDrag>() {
+                    let event = unsafe { event.assume_safe() };
+                    if let Some(gesture) = self.touch.drag(event.index(), event.position()) {
+                        self.handle_gesture(root, world, gesture);
+                    }
+                } else if let Some(event) = event.clone().cast::<InputEventMouse>() {
+                    let mut event = unsafe { event.assume_safe() };
+                    let button_index = if event.is_pressed() {
+                        Some(event.button_mask())
                     } else {
                         None
                     };
@@ -697,76 +2655,514 @@ impl UpdateNodes {
                     } else if let Some(event) = event.cast::<InputEventMouseButton>() {
                         if let Some(button_index) = button_index {
                             if button_index == GlobalConstants::BUTTON_MASK_RIGHT {
-                                self.handle_right_click(root, event)
+                                self.handle_right_click(root, world, event)
                             } else if button_index == GlobalConstants::BUTTON_MASK_LEFT {
-                                self.handle_left_click(root, world, event)
+                                self.handle_left_press(event)
+                            }
+                        } else if event.button_index() == GlobalConstants::BUTTON_LEFT {
+                            self.handle_left_release(root, world, event)
+                        }
+                    }
+                } else if let Some(event) = event.clone().cast::<InputEventKey>() {
+                    let mut state: &mut GameState =
+                        &mut *self.resources.get_mut::<GameState>().unwrap();
+                    let event: TRef<'_, InputEventKey> = unsafe { event.assume_safe() };
+                    if !event.is_echo() && event.is_pressed() {
+                        let scancode = event.scancode();
+                        match scancode {
+                            GlobalConstants::KEY_R => {
+                                state.red_layer = !state.red_layer;
+                                state.redraw_grid = true;
                             }
+                            GlobalConstants::KEY_G => {
+                                state.green_layer = !state.green_layer;
+                                state.redraw_grid = true;
+                            }
+                            GlobalConstants::KEY_B => {
+                                state.blue_layer = !state.blue_layer;
+                                state.redraw_grid = true;
+                            }
+                            GlobalConstants::KEY_E => {
+                                state.edge_layer = !state.edge_layer;
+                                state.redraw_grid = true;
+                            }
+                            GlobalConstants::KEY_I => {
+                                state.influence_layer = !state.influence_layer;
+                                state.redraw_grid = true;
+                            }
+                            GlobalConstants::KEY_H => match self.resources.get::<MainCamera>() {
+                                None => {}
+                                Some(camera) => {
+                                    let camera = camera.0;
+                                    camera.set_position(Vector2::zero());
+                                    match root.get_viewport() {
+                                        None => {}
+                                        Some(viewport) => {
+                                            let viewport = unsafe { viewport.assume_safe() };
+                                            viewport.warp_mouse(viewport.get_mouse_position())
+                                        }
+                                    }
+                                }
+                            },
+                            _ => {}
                         }
                     }
-                } else if let Some(event) = event.clone().cast::<InputEventKey>() {
-                    let mut state: &mut GameState =
-                        &mut *self.resources.get_mut::<GameState>().unwrap();
-                    let event: TRef<'_, InputEventKey> = unsafe { event.assume_safe() };
-                    if !event.is_echo() && event.is_pressed() {
-                        let scancode = event.scancode();
-                        match scancode {
-                            GlobalConstants::KEY_R => {
-                                state.red_layer = !state.red_layer;
-                                state.redraw_grid = true;
-                            }
-                            GlobalConstants::KEY_G => {
-                                state.green_layer = !state.green_layer;
-                                state.redraw_grid = true;
-                            }
-                            GlobalConstants::KEY_B => {
-                                state.blue_layer = !state.blue_layer;
-                                state.redraw_grid = true;
-                            }
-                            GlobalConstants::KEY_H => match self.resources.get::<MainCamera>() {
-                                None => {}
-                                Some(camera) => {
-                                    let camera = camera.0;
-                                    camera.set_position(Vector2::zero());
-                                    match root.get_viewport() {
-                                        None => {}
-                                        Some(viewport) => {
-                                            let viewport = unsafe { viewport.assume_safe() };
-                                            viewport.warp_mouse(viewport.get_mouse_position())
-                                        }
-                                    }
-                                }
-                            },
-                            _ => {}
+                }
+            }
+
+            if let Some(state) = self.resources.get::<GameState>() {
+                let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+                let focus = focus_position(&*world, &state, hexfield_size);
+                if self.focus.update(focus, hexfield_size) {
+                    let position = self.focus.last().unwrap_or_else(Vector2::zero);
+                    emit_fields(
+                        root,
+                        "focus_changed",
+                        &Fields::new().with("position", position),
+                    );
+                }
+                let preview = path_preview(&*world, &state, hexfield_size);
+                let payload = preview.to_fields();
+                if self.path.update(preview) {
+                    emit_fields(root, "path_changed", &payload);
+                }
+            }
+            drop(frame_timer);
+            profiler::end_frame();
+        });
+    }
+
+    fn handle_left_press(&mut self, event: TRef<'_, InputEventMouseButton>) {
+        let camera = match self.resources.get_mut::<MainCamera>() {
+            None => {
+                return;
+            }
+            Some(camera) => camera.0,
+        };
+        self.drag_start = Some(UpdateNodes::to_view_pos(&camera, event.global_position()));
+    }
+
+    /// A short drag counts as a click on the hexagon where it started, a longer one selects the
+    /// own units in the box. Holding control adds to the selection instead of replacing it.
+    fn handle_left_release(
+        &mut self,
+        root: &Node2D,
+        world: &mut World,
+        event: TRef<'_, InputEventMouseButton>,
+    ) {
+        let camera = match self.resources.get_mut::<MainCamera>() {
+            None => {
+                return;
+            }
+            Some(camera) => camera.0,
+        };
+        let start = match self.drag_start.take() {
+            None => return,
+            Some(start) => start,
+        };
+        let mouse_pos = UpdateNodes::to_view_pos(&camera, event.global_position());
+        let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+        if (mouse_pos - start).length() < hexfield_size / 2.0 {
+            let hex = Hexagon::from_vector2(start, hexfield_size);
+            if !self.is_on_map(&hex) {
+                return;
+            }
+            if !self.picks_hexagons() {
+                return;
+            }
+            if event.control() {
+                self.click_hexagon(root, world, hex, true);
+                return;
+            }
+            self.click_at(root, world, hex);
+        } else {
+            self.select_box(root, world, (start, mouse_pos), event.control());
+        }
+    }
+
+    /// Counts a click on `hex` towards single and double clicks and acts on those it makes.
+    fn click_at(&mut self, root: &Node2D, world: &mut World, hex: Hexagon) {
+        let on_unit = get_entities_at_hexagon(&hex, world).iter().any(|entity| {
+            world
+                .entry_ref(*entity)
+                .map_or(false, |entry| entry.get_component::<Unit>().is_ok())
+        });
+        for click in self.clicks.click(hex, self.input_time, on_unit) {
+            self.handle_click(root, world, click);
+        }
+    }
+
+    /// The commands since the last turn file as a new one, `None` if there are none.
+    pub fn export_turn(&mut self) -> Option<String> {
+        let mut state = self.resources.get_mut::<GameState>()?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        match export_turn(&mut state, timestamp)?.to_json() {
+            Ok(json) => Some(json),
+            Err(error) => {
+                godot_error!("Could not serialize turn: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Checks the turn file against the game and carries its commands out one by one.
+    pub fn import_turn(&mut self, json: &str) -> Result<(), TurnRejection> {
+        let file = TurnFile::from_json(json).map_err(|error| TurnRejection {
+            reason: "unreadable",
+            differences: vec![error.to_string()],
+        })?;
+        let busy = TurnRejection {
+            reason: "busy",
+            differences: Vec::new(),
+        };
+        let mut state = match self.resources.get_mut::<GameState>() {
+            None => return Err(busy),
+            Some(state) => state,
+        };
+        let mut result = Err(busy);
+        with_world(|world| result = import_turn(&*world, &mut state, &file));
+        result
+    }
+
+    /// The resources, income, upkeep and affordable unit types of `player` for the economy
+    /// panel, empty for players that do not exist.
+    pub fn get_economy_report(&self, player: usize) -> Dictionary<Unique> {
+        let mut dict = Dictionary::new();
+        if let Some(state) = self.resources.get::<GameState>() {
+            if player < state.players.len() {
+                with_world(|world| {
+                    dict = EconomyReport::new(&*world, &state, player).to_dictionary()
+                });
+            }
+        }
+        dict
+    }
+
+    /// The surviving units of `player` as a roster for the next scenario.
+    pub fn export_roster(&self, player: usize) -> Option<String> {
+        let state = self.resources.get::<GameState>()?;
+        let mut roster = None;
+        with_world(|world| roster = Some(Roster::from_world(&*world, &state, player)));
+        match roster?.to_json() {
+            Ok(json) => Some(json),
+            Err(error) => {
+                godot_error!("Could not serialize roster: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Keeps the roster for the `from_roster` unit slots of the next scenario `player` plays.
+    pub fn import_roster(&mut self, player: usize, json: &str) -> bool {
+        let roster = match Roster::from_json(json) {
+            Ok(roster) => roster,
+            Err(error) => {
+                godot_error!("Could not read roster: {}", error);
+                return false;
+            }
+        };
+        match self.resources.get_mut::<GameState>() {
+            None => false,
+            Some(mut state) => {
+                state.rosters.insert(player, roster);
+                true
+            }
+        }
+    }
+
+    /// Whether `hex` belongs to the map. Clicks on hexagons off the map are ignored.
+    pub fn is_on_map(&self, hex: &Hexagon) -> bool {
+        self.resources
+            .get::<GameState>()
+            .map_or(false, |state| state.map_bounds.contains(hex))
+    }
+
+    /// Selects the unit selected last again, if it is still around.
+    fn reselect_last(&mut self, root: &Node2D, world: &mut World) {
+        let state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
+        if state.observing || !state.is_local_turn() || !state.state.accepts_orders() {
+            return;
+        }
+        if let Some(entity) = state.take_reselect_target(&*world) {
+            let next_state = State::Selected(entity);
+            match command_for_state(world, state.current_player, &next_state) {
+                Some(command) => UpdateNodes::issue_command(root, world, state, command),
+                None => {
+                    set_state(state, next_state);
+                }
+            }
+        }
+    }
+
+    /// Shows or hides the list of controls. Returns whether it is shown now.
+    pub fn toggle_help(&mut self) -> bool {
+        let state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
+        state.show_help = !state.show_help;
+        state.show_help
+    }
+
+    /// The actions the game responds to with their description and current bindings, see
+    /// `ActionBindings::to_dictionary`.
+    pub fn get_input_bindings(&self) -> VariantArray {
+        let bindings = VariantArray::new();
+        for action in list_actions(current_bindings) {
+            bindings.push(action.to_dictionary().into_shared());
+        }
+        bindings.into_shared()
+    }
+
+    /// Lets the selected unit of the local player pick the target of an attack. Returns false
+    /// without such a unit or if it has no attack left.
+    pub fn begin_targeting(&mut self, root: &Node2D) -> bool {
+        let mut started = false;
+        with_world(|world| started = self.enter_targeting(root, world));
+        started
+    }
+
+    fn enter_targeting(&mut self, root: &Node2D, world: &World) -> bool {
+        let mut state = match self.resources.get_mut::<GameState>() {
+            None => {
+                godot_error!("begin_targeting: No GameState");
+                return false;
+            }
+            Some(state) => state,
+        };
+        if state.observing || !state.is_local_turn() || !begin_targeting(world, &mut state) {
+            return false;
+        }
+        if let Some(hovered) = state.hovered_hexagon {
+            emit_target_validity(root, world, &state, &hovered);
+        }
+        true
+    }
+
+    /// Picks the perk with the index `perk` for the unit of the local player waiting for its
+    /// promotion. Returns false without such a unit or if the index is not offered.
+    pub fn choose_promotion(&mut self, root: &Node2D, perk: usize) -> bool {
+        let mut state = match self.resources.get_mut::<GameState>() {
+            None => {
+                godot_error!("choose_promotion: No GameState");
+                return false;
+            }
+            Some(state) => state,
+        };
+        let state: &mut GameState = &mut *state;
+        let mut chosen = false;
+        with_world(|world| {
+            let command =
+                match (&state.state, state.current_player) {
+                    (State::ChoosingPromotion(entity, _), Some(player)) => {
+                        PersistentId::of_entity(&*world, *entity)
+                            .map(|unit| Command::ChoosePromotion { player, unit, perk })
+                    }
+                    _ => None,
+                };
+            if let Some(command) = command {
+                UpdateNodes::issue_command(root, world, state, command);
+                chosen = matches!(state.state, State::Promoting(_, _));
+            }
+        });
+        chosen
+    }
+
+    fn handle_click(&mut self, root: &Node2D, world: &mut World, click: Click) {
+        match click {
+            Click::Single(hex) => self.click_hexagon(root, world, hex, false),
+            Click::Double(hex) => self.double_click_hexagon(root, world, hex),
+        }
+    }
+
+    /// Selects the own unit on `hex`, centres the camera on it and shows where it can move if
+    /// it has actions left. On other hexagons a double click is a single click.
+    fn double_click_hexagon(&mut self, root: &Node2D, world: &mut World, hex: Hexagon) {
+        let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+        let selected = {
+            let state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
+            let own_unit = get_entities_at_hexagon(&hex, world)
+                .into_iter()
+                .find_map(|entity| {
+                    let entry = world.entry_ref(entity).ok()?;
+                    let unit = *entry.get_component::<Unit>().ok()?;
+                    if get_player_of_entity(&entry) != state.current_player {
+                        return None;
+                    }
+                    Some((entity, unit))
+                });
+            match own_unit {
+                Some((entity, unit))
+                    if state.is_local_turn()
+                        && !state.observing
+                        && state.state.accepts_orders() =>
+                {
+                    let next_state = State::Selected(entity);
+                    match command_for_state(world, state.current_player, &next_state) {
+                        Some(command) => UpdateNodes::issue_command(root, world, state, command),
+                        None => {
+                            set_state(state, next_state);
                         }
                     }
+                    if unit.movement_left(&state.rules) > 0 || unit.can_attack(&state.rules) {
+                        state.blue_layer = true;
+                        state.redraw_grid = true;
+                    }
+                    state.state == State::Selected(entity)
                 }
+                _ => false,
             }
-        });
+        };
+        if !selected {
+            self.click_hexagon(root, world, hex, false);
+            return;
+        }
+        let position = get_2d_position_from_hex(&hex, hexfield_size);
+        self.focus.jump_to(position);
+        emit_fields(
+            root,
+            "focus_changed",
+            &Fields::new().with("position", position),
+        );
     }
 
-    fn handle_left_click(
-        &mut self,
-        root: &Node2D,
-        mut world: &mut World,
-        event: TRef<'_, InputEventMouseButton>,
-    ) {
-        let camera = match self.resources.get_mut::<MainCamera>() {
+    fn select_box(&mut self, root: &Node2D, world: &World, corners: (Vector2, Vector2), add: bool) {
+        let state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
+        let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+        state.selection_box = None;
+        state.redraw_grid = true;
+        if state.observing || !state.is_local_turn() || !state.state.accepts_orders() {
+            return;
+        }
+        let player = match state.current_player {
+            None => return,
+            Some(player) => player,
+        };
+        let mut members = if add {
+            state.state.selection()
+        } else {
+            Vec::new()
+        };
+        for entity in units_in_box(world, player, corners, hexfield_size) {
+            if !members.contains(&entity) {
+                members.push(entity);
+            }
+        }
+        state.cancel_group_orders();
+        let next_state = State::from_selection(members);
+        match command_for_state(world, state.current_player, &next_state) {
+            Some(command) => UpdateNodes::issue_command(root, world, state, command),
             None => {
-                return;
+                set_state(state, next_state);
             }
-            Some(camera) => camera.0,
-        };
-        let mut mouse_pos = UpdateNodes::to_view_pos(&camera, event.global_position());
+        }
+    }
+
+    /// Acts on a left click on `hex`. While an action resolves the click is buffered instead.
+    /// With `toggle` a click on an own unit adds it to or removes it from the selection.
+    fn click_hexagon(&mut self, root: &Node2D, mut world: &mut World, hex: Hexagon, toggle: bool) {
         let mut state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
         let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
-        let hex = Hexagon::from_vector2(mouse_pos, hexfield_size);
-        let value_dict = Dictionary::new();
-        value_dict.insert("q", hex.get_q());
-        value_dict.insert("r", hex.get_r());
-        let value_dict = value_dict.owned_to_variant();
+        if let State::GameOver(_) = state.state {
+            return;
+        }
+        if state.observing {
+            return;
+        }
+        // A pending promotion has to be picked with `choose_promotion` first.
+        if let State::Loading | State::TurnTransition(_) | State::ChoosingPromotion(_, _) =
+            state.state
+        {
+            return;
+        }
+        if !state.is_local_turn() {
+            emit_input_error(root, "not_your_turn");
+            return;
+        }
+        if state.state.is_resolving() {
+            if !toggle {
+                state.input_buffer.buffer(&*world, hex);
+            }
+            return;
+        }
+        state.cancel_group_orders();
         let mut possible_states = Vec::new();
+        // The path of a proposed move, every move proposal of a click leads to the same hexagon.
+        let mut proposed_path = Vec::new();
+
+        if let State::Targeting(attacker) = state.state {
+            let (validity, target) =
+                target_validity(&*world, &state.rules, &state.map_bounds, attacker, &hex);
+            match target {
+                Some(defender) if validity.is_valid() => {
+                    let next_state = State::Attacking(attacker, defender);
+                    if let Some(command) =
+                        command_for_state(world, state.current_player, &next_state)
+                    {
+                        UpdateNodes::issue_command(root, world, state, command);
+                    }
+                }
+                _ => emit_input_error(root, validity.reason()),
+            }
+            emit_fields(root, "hex_left_clicked", &Fields::from(&hex));
+            return;
+        }
 
         let entities_at_hexagon = get_entities_at_hexagon(&hex, world);
+        let clicked_unit = entities_at_hexagon.iter().copied().find(|entity| {
+            world
+                .entry_ref(*entity)
+                .map_or(false, |entry| entry.get_component::<Unit>().is_ok())
+        });
+        let owner_of_clicked_unit = clicked_unit
+            .and_then(|entity| world.entry_ref(entity).ok())
+            .and_then(|entry| get_player_of_entity(&entry));
+        let clicked_own_unit = match clicked_unit {
+            Some(entity) if owner_of_clicked_unit == state.current_player => Some(entity),
+            _ => None,
+        };
+
+        if toggle {
+            if let Some(entity) = clicked_own_unit {
+                let next_state = state.state.toggle_selection(entity);
+                match command_for_state(world, state.current_player, &next_state) {
+                    Some(command) => UpdateNodes::issue_command(root, world, state, command),
+                    None => {
+                        set_state(state, next_state);
+                    }
+                }
+            }
+            return;
+        }
+
+        if let State::GroupSelected(members) = state.state.clone() {
+            match clicked_unit {
+                None => {
+                    state.group_moves = assign_slots(&*world, &members, hex).into_iter().collect();
+                    return;
+                }
+                Some(_) if clicked_own_unit.is_none() => {
+                    // Groups do not attack together, the member closest to the target takes over.
+                    let closest = members
+                        .iter()
+                        .filter_map(|member| {
+                            let entry = world.entry_ref(*member).ok()?;
+                            let hexagon = *entry.get_component::<Hexagon>().ok()?;
+                            Some((hexagon.distance_to(&hex), *member))
+                        })
+                        .min();
+                    if let Some((_, member)) = closest {
+                        let next_state = State::Selected(member);
+                        if let Some(command) =
+                            command_for_state(world, state.current_player, &next_state)
+                        {
+                            UpdateNodes::issue_command(root, world, state, command);
+                        }
+                    }
+                    return;
+                }
+                Some(_) => {}
+            }
+        }
 
         if entities_at_hexagon.is_empty() {
             if let State::Selected(selected_entity) = state.state {
@@ -801,16 +3197,19 @@ impl UpdateNodes {
                         Ok(hexagon) => *hexagon,
                     }
                 };
-                let path = find_path(&selected_hexagon, &hex, world);
+                let path = find_path(
+                    &selected_hexagon,
+                    &hex,
+                    &state.edges,
+                    &state.map_bounds,
+                    world,
+                );
 
                 if path.is_empty() {
                     godot_warn!("Path from entity to target not found.",);
                 } else {
-                    possible_states.push(State::Moving(
-                        selected_entity,
-                        VecDeque::from(path),
-                        0f64,
-                    ));
+                    proposed_path = path;
+                    possible_states.push(State::Moving(selected_entity));
                 }
             }
         } else {
@@ -818,6 +3217,7 @@ impl UpdateNodes {
                 possible_states.push(State::Selected(entity));
                 match state.state {
                     State::NewRound => {}
+                    State::Loading => {}
                     State::Startup => {}
                     State::Waiting => {}
                     State::Selected(selected_entity) => {
@@ -855,6 +3255,16 @@ impl UpdateNodes {
                                         if current_player_id != selected_player_id {
                                             return;
                                         }
+                                        if selected_entry
+                                            .get_component::<Unit>()
+                                            .map_or(false, |unit| unit.must_hold_fire())
+                                        {
+                                            emit_input_error(
+                                                root,
+                                                TargetValidity::MovedBeforeFiring.reason(),
+                                            );
+                                            return;
+                                        }
                                         let is_visible = match root.get_world_2d() {
                                             None => false,
                                             Some(godot_world) => {
@@ -869,6 +3279,7 @@ impl UpdateNodes {
                                                             hexfield_size,
                                                             selected_entity,
                                                             hex,
+                                                            state.rules.line_of_sight,
                                                         )
                                                     }
                                                 }
@@ -895,16 +3306,19 @@ impl UpdateNodes {
                                                 Ok(hexagon) => *hexagon,
                                             }
                                         };
-                                        let path = find_path(&selected_hexagon, &hex, world);
+                                        let path = find_path(
+                                            &selected_hexagon,
+                                            &hex,
+                                            &state.edges,
+                                            &state.map_bounds,
+                                            world,
+                                        );
 
                                         if path.is_empty() {
                                             godot_warn!("Path from entity to target not found.",);
                                         } else {
-                                            possible_states.push(State::Moving(
-                                                selected_entity,
-                                                VecDeque::from(path),
-                                                0f64,
-                                            ));
+                                            proposed_path = path;
+                                            possible_states.push(State::Moving(selected_entity));
                                         }
                                     }
                                 }
@@ -912,63 +3326,214 @@ impl UpdateNodes {
                             }
                         }
                     }
+                    State::Targeting(_) => {}
+                    State::GroupSelected(_) => {}
                     State::Attacking(_, _) => {}
-                    State::Moving(_, _, _) => {}
+                    State::Moving(_) => {}
+                    State::UsingAbility(_, _, _) => {}
+                    State::EnteringOverwatch(_) => {}
+                    State::AttackingEdge(_, _, _) => {}
+                    State::Garrisoning(_) => {}
+                    State::Ungarrisoning(_, _) => {}
+                    State::AttackingBuilding(_, _) => {}
+                    State::Turning(_, _) => {}
+                    State::ChoosingPromotion(_, _) => {}
+                    State::Promoting(_, _) => {}
+                    State::UnitTurn(_) => {}
+                    State::Planning => {}
+                    State::Resolving => {}
+                    State::TurnTransition(_) => {}
+                    State::GameOver(_) => {}
                 }
             }
         }
 
-        match possible_states.last() {
-            Some(last_state) => {
-                set_state(state, last_state.clone());
+        match possible_states.pop() {
+            Some(State::Moving(entity)) => {
+                match (state.current_player, PersistentId::of_entity(world, entity)) {
+                    (Some(player), Some(unit)) => UpdateNodes::issue_command(
+                        root,
+                        world,
+                        state,
+                        Command::Move {
+                            player,
+                            unit,
+                            path: proposed_path,
+                        },
+                    ),
+                    _ => {
+                        start_move(state, entity, proposed_path.into());
+                    }
+                }
             }
+            Some(next_state) => match command_for_state(world, state.current_player, &next_state) {
+                Some(command) => UpdateNodes::issue_command(root, world, state, command),
+                None => {
+                    set_state(state, next_state);
+                }
+            },
             None => {
-                set_state(state, State::Waiting);
+                let selected = matches!(state.state, State::Selected(_));
+                match state.current_player {
+                    Some(player) if selected => UpdateNodes::issue_command(
+                        root,
+                        world,
+                        state,
+                        Command::Select { player, unit: None },
+                    ),
+                    _ => {
+                        set_state(state, State::Waiting);
+                    }
+                }
             }
         }
 
-        unsafe {
-            root.call_deferred(
-                "emit_signal",
-                &[
-                    GodotString::from_str("hex_left_clicked").to_variant(),
-                    value_dict,
-                ],
+        emit_fields(root, "hex_left_clicked", &Fields::from(&hex));
+    }
+
+    /// Taps click, long presses cancel like a right click, drags pan the camera. Zooming is left
+    /// to the scripts.
+    fn handle_gesture(&mut self, root: &Node2D, world: &mut World, gesture: Gesture) {
+        let camera = match self.resources.get_mut::<MainCamera>() {
+            None => {
+                return;
+            }
+            Some(camera) => camera.0,
+        };
+        let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+        match gesture {
+            Gesture::Tap(_) | Gesture::LongPress(_) if !self.picks_hexagons() => {}
+            Gesture::Tap(position) => {
+                let view_pos = UpdateNodes::to_view_pos(&camera, position);
+                let hex = Hexagon::from_vector2(view_pos, hexfield_size);
+                if self.is_on_map(&hex) {
+                    self.click_hexagon(root, world, hex, false);
+                }
+            }
+            Gesture::LongPress(position) => {
+                let view_pos = UpdateNodes::to_view_pos(&camera, position);
+                let hex = Hexagon::from_vector2(view_pos, hexfield_size);
+                self.right_click_hexagon(root, world, hex);
+            }
+            Gesture::Drag(relative) => {
+                camera.move_local_x((-relative.x).into(), false);
+                camera.move_local_y((-relative.y).into(), false);
+            }
+            Gesture::Pinch { factor, centre } => {
+                let payload = Fields::new().with("factor", factor).with("centre", centre);
+                emit_fields(root, "zoom_requested", &payload);
+            }
+        }
+    }
+
+    /// Moves the hexagon cursor or acts on its hexagon. Returns whether `event` was meant for
+    /// the cursor.
+    fn handle_cursor_input(
+        &mut self,
+        root: &Node2D,
+        world: &mut World,
+        event: &Ref<InputEvent>,
+    ) -> bool {
+        let event = unsafe { event.assume_safe() };
+        if let Some(motion) = event.cast::<InputEventJoypadMotion>() {
+            let axis = motion.axis();
+            if axis != GlobalConstants::JOY_ANALOG_LX && axis != GlobalConstants::JOY_ANALOG_LY {
+                return false;
+            }
+            let input = Input::godot_singleton();
+            let device = motion.device();
+            let stick = Vector2::new(
+                input.get_joy_axis(device, GlobalConstants::JOY_ANALOG_LX) as f32,
+                input.get_joy_axis(device, GlobalConstants::JOY_ANALOG_LY) as f32,
             );
+            let state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
+            let direction = state.hex_cursor.orientation.direction_for_stick(stick);
+            if state.hex_cursor.tilt_stick(direction, &state.map_bounds) {
+                state.redraw_grid = true;
+            }
+            return true;
         }
+
+        let accept = event.is_action_pressed(CURSOR_ACCEPT.name(), false);
+        let cancel = event.is_action_pressed(CURSOR_CANCEL.name(), false);
+        let hexagon = {
+            let state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
+            let direction = state
+                .hex_cursor
+                .orientation
+                .move_actions()
+                .iter()
+                .find(|(action, _)| event.is_action_pressed(action.name(), true))
+                .map(|(_, direction)| *direction);
+            match direction {
+                Some(direction) => state.hex_cursor.move_towards(direction, &state.map_bounds),
+                None if accept || cancel => state.hex_cursor.visible = true,
+                None => return false,
+            }
+            state.redraw_grid = true;
+            state.hex_cursor.hexagon
+        };
+        if accept {
+            self.click_hexagon(root, world, hexagon, false);
+        } else if cancel {
+            self.right_click_hexagon(root, world, hexagon);
+        }
+        true
     }
 
-    fn handle_right_click(&mut self, root: &Node2D, event: TRef<'_, InputEventMouseButton>) {
+    fn handle_right_click(
+        &mut self,
+        root: &Node2D,
+        world: &World,
+        event: TRef<'_, InputEventMouseButton>,
+    ) {
+        if !self.picks_hexagons() {
+            return;
+        }
         let camera = match self.resources.get_mut::<MainCamera>() {
             None => {
                 return;
             }
             Some(camera) => camera.0,
         };
-        let mut mouse_pos = UpdateNodes::to_view_pos(&camera, event.global_position());
+        let mouse_pos = UpdateNodes::to_view_pos(&camera, event.global_position());
         let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
         let hex = Hexagon::from_vector2(mouse_pos, hexfield_size);
-        let value_dict = Dictionary::new();
-        value_dict.insert("q", hex.get_q());
-        value_dict.insert("r", hex.get_r());
-        let value_dict = value_dict.owned_to_variant();
+        self.right_click_hexagon(root, world, hex);
+    }
+
+    /// Clears the selection and pending orders, then reports the click on `hex`. In the attack
+    /// mode only the mode is left and the unit stays selected.
+    fn right_click_hexagon(&mut self, root: &Node2D, world: &World, hex: Hexagon) {
         let mut state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
-        set_state(state, State::Waiting);
-        unsafe {
-            root.call_deferred(
-                "emit_signal",
-                &[
-                    GodotString::from_str("hex_right_clicked").to_variant(),
-                    value_dict,
-                ],
-            );
+        let in_transition = matches!(
+            state.state,
+            State::Loading | State::TurnTransition(_) | State::ChoosingPromotion(_, _)
+        );
+        state.input_buffer.clear();
+        state.cancel_group_orders();
+        let left_targeting = cancel_targeting(state);
+        if !left_targeting && !state.observing && !in_transition && state.is_local_turn() {
+            let selected = matches!(state.state, State::Selected(_));
+            match state.current_player {
+                Some(player) if selected => UpdateNodes::issue_command(
+                    root,
+                    world,
+                    state,
+                    Command::Select { player, unit: None },
+                ),
+                _ => {
+                    set_state(state, State::Waiting);
+                }
+            }
         }
+        emit_fields(root, "hex_right_clicked", &Fields::from(&hex));
     }
 
     fn handle_mouse_motion(
         &mut self,
         root: &Node2D,
-        mut world: &mut World,
+        world: &mut World,
         event: TRef<'_, InputEventMouseMotion>,
     ) {
         let camera = match self.resources.get_mut::<MainCamera>() {
@@ -979,7 +3544,17 @@ impl UpdateNodes {
         };
         let button_mask = event.button_mask();
         let mouse_pos = UpdateNodes::to_view_pos(&camera, event.global_position());
-        let mut state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
+        let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+
+        if button_mask == GlobalConstants::BUTTON_MASK_LEFT {
+            if let Some(start) = self.drag_start {
+                if (mouse_pos - start).length() >= hexfield_size / 2.0 {
+                    let mut state = self.resources.get_mut::<GameState>().unwrap();
+                    state.selection_box = Some((start, mouse_pos));
+                    state.redraw_grid = true;
+                }
+            }
+        }
 
         match button_mask {
             GlobalConstants::BUTTON_MASK_MIDDLE => {
@@ -987,51 +3562,34 @@ impl UpdateNodes {
                 camera.move_local_x((-pos.x).into(), false);
                 camera.move_local_y((-pos.y).into(), false);
             }
-            _ => {
-                let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+            _ if self.picks_hexagons() => {
                 let hex = Hexagon::from_vector2(mouse_pos, hexfield_size);
-                if match state.hovered_hexagon {
-                    Some(hovered_hexagon) => {
-                        if hex != hovered_hexagon {
-                            let value_dict = Dictionary::new();
-                            value_dict.insert("q", hovered_hexagon.get_q());
-                            value_dict.insert("r", hovered_hexagon.get_r());
-                            let value_dict = value_dict.owned_to_variant();
-                            unsafe {
-                                state.current_path = Vec::new();
-                                root.call_deferred(
-                                    "emit_signal",
-                                    &[
-                                        GodotString::from_str("hex_mouse_exited").to_variant(),
-                                        value_dict,
-                                    ],
-                                );
-                            }
-                            true
-                        } else {
-                            false
-                        }
-                    }
-                    None => true,
-                } {
-                    UpdateNodes::update_path(world, state, &hex);
-
-                    let value_dict = Dictionary::new();
-                    value_dict.insert("q", hex.get_q());
-                    value_dict.insert("r", hex.get_r());
-                    let value_dict = value_dict.owned_to_variant();
-                    state.hovered_hexagon = Some(hex);
-                    state.redraw_grid = true;
-                    unsafe {
-                        root.call_deferred(
-                            "emit_signal",
-                            &[
-                                GodotString::from_str("hex_mouse_entered").to_variant(),
-                                value_dict,
-                            ],
-                        );
-                    }
-                }
+                self.hover_hexagon(root, world, hex, event.shift());
+            }
+            _ => {}
+        }
+    }
+
+    /// Hovers `hex`: shows the path to it and, with `shift`, the distance from the selected unit.
+    fn hover_hexagon(&mut self, root: &Node2D, mut world: &mut World, hex: Hexagon, shift: bool) {
+        let hexfield_size = self.resources.get::<HexfieldSize>().unwrap().0;
+        let mut state: &mut GameState = &mut *self.resources.get_mut::<GameState>().unwrap();
+        if let Some(change) = state.set_hovered_hexagon(Some(hex)) {
+            state.current_path = Vec::new();
+            UpdateNodes::update_path(world, state, &hex);
+            UpdateNodes::update_measurement(world, state, &hex, shift, hexfield_size);
+            state.hex_cursor.visible = false;
+            emit_hover_change(root, &change);
+            emit_target_validity(root, &*world, state, &hex);
+        }
+    }
+
+    /// Forgets the hovered hexagon, for when the mouse left the viewport.
+    pub fn clear_hover(&mut self, root: &Node2D) {
+        if let Some(mut state) = self.resources.get_mut::<GameState>() {
+            if let Some(change) = state.set_hovered_hexagon(None) {
+                state.current_path = Vec::new();
+                emit_hover_change(root, &change);
             }
         }
     }
@@ -1045,7 +3603,7 @@ impl UpdateNodes {
 
     fn update_path<S: EntityStore>(world: &S, mut state: &mut GameState, hex: &Hexagon) {
         let selected_entity = match state.state {
-            State::Selected(index) => index,
+            State::Selected(index) if !state.observing => index,
             _ => {
                 state.current_path = Vec::new();
                 return;
@@ -1087,7 +3645,40 @@ impl UpdateNodes {
             Ok(hexagon) => *hexagon,
         };
 
-        state.current_path = find_path(&selected_hexagon, &hex, world);
+        state.current_path = find_path(
+            &selected_hexagon,
+            &hex,
+            &state.edges,
+            &state.map_bounds,
+            world,
+        );
+    }
+
+    fn update_measurement<S: EntityStore>(
+        world: &S,
+        state: &mut GameState,
+        hex: &Hexagon,
+        modifier_held: bool,
+        hexfield_size: f32,
+    ) {
+        let selected_hexagon = match state.state {
+            State::Selected(selected) if modifier_held => world
+                .entry_ref(selected)
+                .ok()
+                .and_then(|entry| entry.get_component::<Hexagon>().ok().copied()),
+            _ => None,
+        };
+        state.measurement = selected_hexagon.map(|selected_hexagon| {
+            measure(
+                world,
+                &state.edges,
+                &state.map_bounds,
+                selected_hexagon,
+                *hex,
+                true,
+                hexfield_size,
+            )
+        });
     }
 
     pub fn execute_draw(&mut self) {
@@ -1097,146 +3688,9 @@ impl UpdateNodes {
     }
 
     pub fn queue_input(&mut self, event: Ref<InputEvent>) {
+        if unsafe { event.assume_safe() }.is_pressed() {
+            self.auto_end_turn.cancel();
+        }
         self.input_queue.push_back(event);
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use crate::components::hexagon::Hexagon;
-    use crate::components::unit::{AttackResult, Unit};
-    use crate::systems::*;
-    use legion::{World, WorldOptions};
-
-    #[test]
-    fn handle_attack_result_updates_components() {
-        let mut world = World::new(WorldOptions::default());
-        let attacker = *world
-            .extend(vec![(Unit::new(1, 1, 0, 0, 0, 0, 0, 1),)])
-            .first()
-            .unwrap();
-        let defender = *world
-            .extend(vec![(Unit::new(2, 1, 0, 0, 0, 0, 0, 0),)])
-            .first()
-            .unwrap();
-        let result = AttackResult {
-            attacker: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
-            defender: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
-            actual_damage: 1,
-        };
-
-        handle_attack_result(&mut world, attacker, defender, result);
-
-        let entry = world.entry(attacker).unwrap();
-        let changed_attacker = entry.get_component::<Unit>().unwrap();
-        assert_eq!(changed_attacker.remaining_attacks, 0);
-
-        let entry = world.entry(defender).unwrap();
-        let changed_defender = entry.get_component::<Unit>().unwrap();
-        assert_eq!(changed_defender.integrity, 1);
-    }
-
-    #[test]
-    fn handle_attack_result_removes_defender_when_integrity_lower_or_eq_0() {
-        let mut world = World::new(WorldOptions::default());
-        let attacker = *world
-            .extend(vec![(Unit::new(1, 2, 0, 0, 0, 0, 0, 1),)])
-            .first()
-            .unwrap();
-        let defender = *world
-            .extend(vec![(Unit::new(2, 1, 0, 0, 0, 0, 0, 0),)])
-            .first()
-            .unwrap();
-        let result = AttackResult {
-            attacker: Unit::new(1, 1, 0, 0, 0, 0, 0, 0),
-            defender: Unit::new(0, 1, 0, 0, 0, 0, 0, 0),
-            actual_damage: 1,
-        };
-
-        handle_attack_result(&mut world, attacker, defender, result);
-
-        assert!(!world.contains(defender));
-    }
-
-    #[test]
-    fn handle_attack_results_only_changes_affected_fields() {
-        let mut world = World::new(WorldOptions::default());
-        let attacking_unit = Unit::new(1, 1, 2, 4, 5, 3, 0, 1);
-        let attacker = *world.extend(vec![(attacking_unit,)]).first().unwrap();
-        let defending_unit = Unit::new(2, 4, 5, 3, 2, 4, 0, 0);
-        let defender = *world.extend(vec![(defending_unit,)]).first().unwrap();
-        let result = AttackResult {
-            attacker: attacking_unit,
-            defender: defending_unit,
-            actual_damage: 1,
-        };
-
-        handle_attack_result(&mut world, attacker, defender, result);
-
-        let entry = world.entry(attacker).unwrap();
-        let changed_attacker = entry.get_component::<Unit>().unwrap();
-        assert_eq!(changed_attacker.damage, attacking_unit.damage);
-        assert_eq!(
-            changed_attacker.max_attack_range,
-            attacking_unit.max_attack_range
-        );
-        assert_eq!(
-            changed_attacker.min_attack_range,
-            attacking_unit.min_attack_range
-        );
-        assert_eq!(changed_attacker.armor, attacking_unit.armor);
-        assert_eq!(changed_attacker.mobility, attacking_unit.mobility);
-
-        let entry = world.entry(defender).unwrap();
-        let changed_defender = entry.get_component::<Unit>().unwrap();
-        assert_eq!(changed_defender.damage, defending_unit.damage);
-        assert_eq!(
-            changed_defender.max_attack_range,
-            defending_unit.max_attack_range
-        );
-        assert_eq!(
-            changed_defender.min_attack_range,
-            defending_unit.min_attack_range
-        );
-        assert_eq!(changed_defender.armor, defending_unit.armor);
-        assert_eq!(changed_defender.mobility, defending_unit.mobility);
-    }
-
-    #[test]
-    fn move_entity_to_hexagon_updates_entity() {
-        let mut world = World::new(WorldOptions::default());
-        let entity = *world
-            .extend(vec![(
-                Hexagon::new_axial(0, 0),
-                Unit::new(0, 0, 0, 0, 0, 0, 2, 0),
-            )])
-            .first()
-            .unwrap();
-
-        move_entity_to_hexagon(entity, &Hexagon::new_axial(1, 1), &mut world);
-
-        let entry = world.entry(entity).unwrap();
-        let hexagon = entry.get_component::<Hexagon>().unwrap();
-        assert_eq!(hexagon.get_q(), 1);
-        assert_eq!(hexagon.get_r(), 1);
-    }
-
-    #[test]
-    fn move_entity_to_hexagon_does_nothing_if_entity_cannot_move() {
-        let mut world = World::default();
-        let entity = *world
-            .extend(vec![(
-                Hexagon::new_axial(5, 5),
-                Unit::new(0, 0, 0, 0, 0, 0, 1, 0),
-            )])
-            .first()
-            .unwrap();
-
-        move_entity_to_hexagon(entity, &Hexagon::new_axial(1, 1), &mut world);
-
-        let entry = world.entry(entity).unwrap();
-        let hexagon = entry.get_component::<Hexagon>().unwrap();
-        assert_eq!(hexagon.get_q(), 5);
-        assert_eq!(hexagon.get_r(), 5);
-    }
-}
@@ -1,57 +1,507 @@
+use crate::components::hexagon::Hexagon;
 use crate::components::node_component::NodeComponent;
+use crate::components::node_creation_failed::NodeCreationFailed;
 use crate::components::node_template::NodeTemplate;
+use crate::components::persistent_id::PersistentId;
+use crate::components::y_sort_by_hex::YSortByHex;
+use crate::godot_convert::Fields;
+use crate::profiler;
+use crate::systems::hexgrid::hexfield_scale;
+use crate::systems::{emit_fields, HexfieldSize};
+use gdnative::api::Label;
 use gdnative::prelude::*;
 use legion::systems::CommandBuffer;
-use legion::{component, system, Entity, World};
-use std::process::Command;
+use legion::world::SubWorld;
+use legion::{component, system, Entity, EntityStore, IntoQuery, World};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Failed attempts in a row after which `create_node` gives up on an entity.
+pub const MAX_NODE_CREATION_ATTEMPTS: u32 = 3;
+pub const DEFAULT_NODES_PER_FRAME: usize = 50;
+pub const DEFAULT_NODE_MS_PER_FRAME: f64 = 4.0;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ManageErrs {
+    CouldNotLoadScene,
     CouldNotMakeInstance,
     RootClassNotSpatial(String),
 }
 
-#[system(for_each)]
-#[filter(!component::<NodeComponent>())]
+/// Makes the nodes of entities, so the retry rules can be tested without scenes.
+pub trait SceneInstancer {
+    type Node;
+
+    fn instance(&self, template: &NodeTemplate) -> Result<Self::Node, ManageErrs>;
+
+    /// A node that stands in for a scene that could not be instanced.
+    fn placeholder(&self, template: &NodeTemplate) -> Self::Node;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeCreationPolicy {
+    pub max_attempts: u32,
+    /// Whether entities that gave up get a placeholder node, so they can still be seen and
+    /// clicked.
+    pub placeholder: bool,
+}
+
+impl Default for NodeCreationPolicy {
+    fn default() -> Self {
+        NodeCreationPolicy {
+            max_attempts: MAX_NODE_CREATION_ATTEMPTS,
+            placeholder: true,
+        }
+    }
+}
+
+/// How much of a frame `create_node` may spend, so a large map is loaded over several frames
+/// instead of freezing the game. The entities left over wait for the next frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeBudget {
+    pub max_nodes: usize,
+    pub max_ms: f64,
+}
+
+impl Default for NodeBudget {
+    fn default() -> Self {
+        NodeBudget {
+            max_nodes: DEFAULT_NODES_PER_FRAME,
+            max_ms: DEFAULT_NODE_MS_PER_FRAME,
+        }
+    }
+}
+
+impl NodeBudget {
+    /// Whether another attempt fits into a frame that made `attempts` in `elapsed_ms`. The first
+    /// attempt always fits, so loading goes on however small the budget is.
+    pub fn allows(&self, attempts: usize, elapsed_ms: f64) -> bool {
+        attempts == 0 || (attempts < self.max_nodes && elapsed_ms < self.max_ms)
+    }
+}
+
+/// Works through the waiting entities in order until the budget is used up. `create` makes one
+/// attempt and returns whether the entity is done with, `elapsed_ms` reads the time spent in the
+/// frame so far. Returns the number of entities done with.
+pub fn create_within_budget<T>(
+    waiting: &[T],
+    budget: &NodeBudget,
+    mut elapsed_ms: impl FnMut() -> f64,
+    mut create: impl FnMut(&T) -> bool,
+) -> usize {
+    let mut done = 0;
+    for (attempts, entry) in waiting.iter().enumerate() {
+        if !budget.allows(attempts, elapsed_ms()) {
+            break;
+        }
+        if create(entry) {
+            done += 1;
+        }
+    }
+    done
+}
+
+/// What a frame of `create_node` did to the backlog of entities waiting for a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadingStep {
+    /// Nothing was waiting.
+    Idle,
+    Progress {
+        created: usize,
+        total: usize,
+    },
+    /// The backlog is empty, the next one starts counting from zero.
+    Finished {
+        total: usize,
+    },
+}
+
+/// Counts the nodes made since the backlog started, for the loading bar of the scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadingProgress {
+    pub created: usize,
+    pub total: usize,
+    pub last_step: LoadingStep,
+}
+
+impl Default for LoadingProgress {
+    fn default() -> Self {
+        LoadingProgress {
+            created: 0,
+            total: 0,
+            last_step: LoadingStep::Idle,
+        }
+    }
+}
+
+impl LoadingProgress {
+    /// Takes a frame that found `waiting` entities without a node and was done with `done` of
+    /// them. Entities that appear while loading grow the total.
+    pub fn record(&mut self, waiting: usize, done: usize) -> LoadingStep {
+        let step = if waiting == 0 && self.total == 0 {
+            LoadingStep::Idle
+        } else {
+            self.total = self.created + waiting;
+            self.created += done;
+            if self.created >= self.total {
+                let total = self.total;
+                self.created = 0;
+                self.total = 0;
+                LoadingStep::Finished { total }
+            } else {
+                LoadingStep::Progress {
+                    created: self.created,
+                    total: self.total,
+                }
+            }
+        };
+        self.last_step = step;
+        step
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NodeCreation<N> {
+    Created(N),
+    /// The attempt failed, the next frame tries again.
+    Retry(ManageErrs),
+    /// The attempt failed once too often, with the placeholder if the policy asks for one.
+    GaveUp(ManageErrs, Option<N>),
+}
+
+/// One attempt to make the node of an entity. `failures` counts the failed attempts in a row and
+/// is reset when the entity succeeds or gives up.
+pub fn try_create_node<I: SceneInstancer>(
+    instancer: &I,
+    template: &NodeTemplate,
+    policy: &NodeCreationPolicy,
+    failures: &mut u32,
+) -> NodeCreation<I::Node> {
+    match instancer.instance(template) {
+        Ok(node) => {
+            *failures = 0;
+            NodeCreation::Created(node)
+        }
+        Err(err) => {
+            *failures += 1;
+            if *failures < policy.max_attempts {
+                return NodeCreation::Retry(err);
+            }
+            *failures = 0;
+            let placeholder = if policy.placeholder {
+                Some(instancer.placeholder(template))
+            } else {
+                None
+            };
+            NodeCreation::GaveUp(err, placeholder)
+        }
+    }
+}
+
+/// The node properties a template sets, so applying it can be tested without nodes.
+pub trait TemplateTarget {
+    fn set_z_index(&self, z_index: i64);
+    fn set_scale(&self, x: f32, y: f32);
+    fn set_rotation_degrees(&self, degrees: f32);
+    fn set_modulate(&self, colour: Color);
+    fn set_visible(&self, visible: bool);
+    fn add_to_group(&self, group: &str);
+}
+
+impl TemplateTarget for Node2D {
+    fn set_z_index(&self, z_index: i64) {
+        Node2D::set_z_index(self, z_index);
+        self.set_z_as_relative(false);
+    }
+
+    fn set_scale(&self, x: f32, y: f32) {
+        Node2D::set_scale(self, Vector2::new(x, y));
+    }
+
+    fn set_rotation_degrees(&self, degrees: f32) {
+        Node2D::set_rotation_degrees(self, f64::from(degrees));
+    }
+
+    fn set_modulate(&self, colour: Color) {
+        CanvasItem::set_modulate(self, colour);
+    }
+
+    fn set_visible(&self, visible: bool) {
+        CanvasItem::set_visible(self, visible);
+    }
+
+    fn add_to_group(&self, group: &str) {
+        Node::add_to_group(self, group, false);
+    }
+}
+
+pub fn apply_template<T: TemplateTarget + ?Sized>(node: &T, template: &NodeTemplate) {
+    node.set_z_index(template.z_index);
+    node.set_scale(template.scale_x, template.scale_y);
+    node.set_rotation_degrees(template.rotation_degrees);
+    node.set_modulate(template.modulate);
+    node.set_visible(template.visible);
+    for group in &template.groups {
+        node.add_to_group(group);
+    }
+}
+
+/// A node that can hold the nodes of entities, so finding the parent can be tested without nodes.
+pub trait ContainerNode: Clone {
+    fn child(&self, name: &str) -> Option<Self>;
+
+    /// Creates an empty container below the node, `None` if the name is taken by a node that
+    /// can not be a container.
+    fn add_container(&self, name: &str) -> Option<Self>;
+}
+
+impl ContainerNode for Ref<Node2D> {
+    fn child(&self, name: &str) -> Option<Self> {
+        let node = unsafe { self.assume_safe_if_sane() }?;
+        if !node.has_node(name) {
+            return None;
+        }
+        let child = node.get_node(name)?;
+        let child = unsafe { child.assume_safe_if_sane() }?;
+        child.cast::<Node2D>().map(|child| child.claim())
+    }
+
+    fn add_container(&self, name: &str) -> Option<Self> {
+        let node = unsafe { self.assume_safe_if_sane() }?;
+        if node.has_node(name) {
+            godot_warn!("Node {} is in the way of a container", name);
+            return None;
+        }
+        let container = Node2D::new();
+        container.set_name(name);
+        let container = container.into_shared();
+        node.add_child(container, false);
+        Some(container)
+    }
+}
+
+/// Walks the path below `root`, creating the containers that are missing. Without a path, and
+/// wherever a container can not be created, the nodes go into the last container found.
+pub fn resolve_parent<N: ContainerNode>(root: &N, path: Option<&str>) -> N {
+    let mut parent = root.clone();
+    let names = path
+        .into_iter()
+        .flat_map(|path| path.split('/'))
+        .filter(|name| !name.is_empty());
+    for name in names {
+        parent = match parent.child(name).or_else(|| parent.add_container(name)) {
+            Some(container) => container,
+            None => break,
+        };
+    }
+    parent
+}
+
+/// Instances the scenes through the resource loader.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GodotInstancer;
+
+impl SceneInstancer for GodotInstancer {
+    type Node = Ref<Node2D, Unique>;
+
+    fn instance(&self, template: &NodeTemplate) -> Result<Self::Node, ManageErrs> {
+        let scene = load_scene(&template.scene_file).ok_or(ManageErrs::CouldNotLoadScene)?;
+        instance_scene::<Node2D>(&scene)
+    }
+
+    fn placeholder(&self, template: &NodeTemplate) -> Self::Node {
+        let node = Node2D::new();
+        let label = Label::new();
+        label.set_text(format!("Missing: {}", template.scene_file));
+        node.add_child(label.into_shared(), false);
+        node
+    }
+}
+
+/// Makes the nodes of the entities that wait for one, as many as the budget allows. They are
+/// taken by persistent id and then by position, so a backlog is worked off in the same order
+/// whatever happens to the archetypes in between.
+#[system]
+#[read_component(NodeTemplate)]
+#[read_component(NodeComponent)]
+#[read_component(NodeCreationFailed)]
+#[read_component(PersistentId)]
+#[read_component(Hexagon)]
 pub fn create_node(
+    world: &SubWorld<'_>,
     cmd: &mut CommandBuffer,
+    #[resource] budget: &NodeBudget,
+    #[resource] progress: &mut LoadingProgress,
+    #[resource] hexfield_size: &HexfieldSize,
     #[state] unit_node: &Ref<Node2D>,
-    entity: &Entity,
-    template_data: &NodeTemplate,
+    #[state] policy: &NodeCreationPolicy,
+    #[state] failures: &mut HashMap<Entity, u32>,
 ) {
-    let units_node = match unsafe { unit_node.assume_safe_if_sane() } {
+    let _timer = profiler::scope("create_node");
+    let started = Instant::now();
+    let scale = hexfield_scale(hexfield_size.0);
+    let world_node = match unsafe { unit_node.assume_safe_if_sane() } {
         Some(node) => node,
         None => return,
     };
 
-    let template = load_scene(&template_data.scene_file);
+    let mut waiting: Vec<(Option<PersistentId>, Option<Hexagon>, Entity)> =
+        <(Entity, Option<&PersistentId>, Option<&Hexagon>)>::query()
+            .filter(
+                component::<NodeTemplate>()
+                    & !component::<NodeComponent>()
+                    & !component::<NodeCreationFailed>(),
+            )
+            .iter(world)
+            .map(|(entity, id, hexagon)| (id.copied(), hexagon.copied(), *entity))
+            .collect();
+    waiting.sort_by_key(|(id, hexagon, _)| (id.is_none(), *id, *hexagon));
 
-    let template = if let Some(template) = &template {
-        template
-    } else {
-        godot_print!("Could not load scene: {}", &template_data.scene_file);
-        return;
+    let done = create_within_budget(
+        &waiting,
+        budget,
+        || started.elapsed().as_secs_f64() * 1000.0,
+        |(id, _, entity)| {
+            let template_data = match world.entry_ref(*entity) {
+                Err(_) => return false,
+                Ok(entry) => match entry.get_component::<NodeTemplate>() {
+                    Err(_) => return false,
+                    // Scenes are drawn for the default hexfield size.
+                    Ok(template_data) => template_data
+                        .clone()
+                        .scale(template_data.scale_x * scale, template_data.scale_y * scale),
+                },
+            };
+            create_entity_node(
+                cmd,
+                unit_node,
+                &world_node,
+                policy,
+                failures,
+                *entity,
+                &template_data,
+                id.as_ref(),
+            )
+        },
+    );
+    progress.record(waiting.len(), done);
+}
+
+/// One attempt to make the node of the entity. Returns whether the entity is done with, with a
+/// node or after giving up on it.
+#[allow(clippy::too_many_arguments)]
+fn create_entity_node(
+    cmd: &mut CommandBuffer,
+    unit_node: &Ref<Node2D>,
+    world_node: &Node2D,
+    policy: &NodeCreationPolicy,
+    failures: &mut HashMap<Entity, u32>,
+    entity: Entity,
+    template_data: &NodeTemplate,
+    id: Option<&PersistentId>,
+) -> bool {
+    let parent = resolve_parent(unit_node, template_data.parent_path.as_deref());
+    let parent = match unsafe { parent.assume_safe_if_sane() } {
+        Some(node) => node,
+        None => return false,
     };
 
-    match instance_scene::<Node2D>(template) {
-        Ok(node2d) => {
-            let node2d: Ref<Node2D> = node2d.into_shared();
-            unsafe {
-                let node2d = node2d.assume_safe_if_sane().unwrap();
-                node2d.set_z_index(template_data.z_index);
-                node2d.set_z_as_relative(false);
-                node2d.set_scale(Vector2::new(template_data.scale_x, template_data.scale_y));
+    let entity_failures = failures.entry(entity).or_insert(0);
+    let (node2d, failed) =
+        match try_create_node(&GodotInstancer, template_data, policy, entity_failures) {
+            NodeCreation::Created(node2d) => (Some(node2d), None),
+            NodeCreation::Retry(err) => {
+                godot_print!(
+                    "Could not instance {}: {:?}",
+                    &template_data.scene_file,
+                    err
+                );
+                return false;
             }
-            units_node.add_child(node2d, false);
+            NodeCreation::GaveUp(err, placeholder) => {
+                godot_warn!(
+                    "Giving up on instancing {}: {:?}",
+                    &template_data.scene_file,
+                    err
+                );
+                let failed = NodeCreationFailed {
+                    scene_file: template_data.scene_file.clone(),
+                    placeholder: placeholder.is_some(),
+                };
+                emit_node_creation_failed(world_node, &failed, id);
+                (placeholder, Some(failed))
+            }
+        };
+    failures.remove(&entity);
+
+    let node2d: Option<Ref<Node2D>> = node2d.map(|node2d| {
+        let node2d = node2d.into_shared();
+        unsafe {
+            apply_template(&*node2d.assume_safe_if_sane().unwrap(), template_data);
+        }
+        parent.add_child(node2d, false);
+        node2d
+    });
 
-            let entity = *entity;
-            cmd.exec_mut(move |world| {
-                let mut entry = world.entry(entity).unwrap();
-                entry.add_component(NodeComponent { node: node2d });
-            })
+    let y_sort = if template_data.y_sort {
+        Some(YSortByHex::new(template_data.z_index))
+    } else {
+        None
+    };
+    cmd.exec_mut(move |world| {
+        // The entity may be removed in the same frame, before the buffer is flushed.
+        let mut entry = match world.entry(entity) {
+            Some(entry) => entry,
+            None => {
+                if let Some(node) = node2d {
+                    unsafe { node.assume_safe() }.queue_free();
+                }
+                return;
+            }
+        };
+        if let Some(node) = node2d {
+            entry.add_component(NodeComponent { node });
+            if let Some(y_sort) = y_sort {
+                entry.add_component(y_sort);
+            }
+        }
+        if let Some(failed) = failed {
+            entry.add_component(failed);
+        }
+    });
+    true
+}
+
+fn emit_node_creation_failed(
+    root: &Node2D,
+    failed: &NodeCreationFailed,
+    id: Option<&PersistentId>,
+) {
+    let payload = Fields::new()
+        .with("scene_file", failed.scene_file.as_str())
+        .with("entity", id.map_or(-1, |id| id.0 as i64))
+        .with("placeholder", failed.placeholder);
+    emit_fields(root, "node_creation_failed", &payload);
+}
+
+/// Lets `create_node` try the entities it gave up on again, for example after the unit catalog
+/// changed. Placeholder nodes are dropped, so the real scene can take their place. Returns the
+/// number of entities that are tried again.
+pub fn clear_node_creation_failures(world: &mut World) -> usize {
+    let failed: Vec<(Entity, bool)> = <(Entity, &NodeCreationFailed)>::query()
+        .iter(world)
+        .map(|(entity, failed)| (*entity, failed.placeholder))
+        .collect();
+    for (entity, placeholder) in &failed {
+        if let Some(mut entry) = world.entry(*entity) {
+            entry.remove_component::<NodeCreationFailed>();
+            if *placeholder {
+                // The world node frees the node once the component is gone.
+                entry.remove_component::<NodeComponent>();
+            }
         }
-        Err(err) => godot_print!("Could not instance Child : {:?}", err),
     }
+    failed.len()
 }
 
 pub fn load_scene(path: &str) -> Option<Ref<PackedScene, ThreadLocal>> {
@@ -76,3 +526,403 @@ where
         .try_cast::<Root>()
         .map_err(|instance| ManageErrs::RootClassNotSpatial(instance.name().to_string()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    /// Fails the first `failures` attempts and names its nodes after the scene.
+    struct FakeInstancer {
+        failures: u32,
+        attempts: Cell<u32>,
+    }
+
+    impl FakeInstancer {
+        fn new(failures: u32) -> Self {
+            FakeInstancer {
+                failures,
+                attempts: Cell::new(0),
+            }
+        }
+    }
+
+    impl SceneInstancer for FakeInstancer {
+        type Node = String;
+
+        fn instance(&self, template: &NodeTemplate) -> Result<String, ManageErrs> {
+            self.attempts.set(self.attempts.get() + 1);
+            if self.attempts.get() <= self.failures {
+                Err(ManageErrs::CouldNotLoadScene)
+            } else {
+                Ok(template.scene_file.clone())
+            }
+        }
+
+        fn placeholder(&self, template: &NodeTemplate) -> String {
+            format!("placeholder for {}", template.scene_file)
+        }
+    }
+
+    /// Records what is set on it.
+    #[derive(Default)]
+    struct FakeNode {
+        properties: RefCell<Vec<String>>,
+    }
+
+    impl FakeNode {
+        fn record(&self, property: String) {
+            self.properties.borrow_mut().push(property);
+        }
+    }
+
+    impl TemplateTarget for FakeNode {
+        fn set_z_index(&self, z_index: i64) {
+            self.record(format!("z_index {}", z_index));
+        }
+
+        fn set_scale(&self, x: f32, y: f32) {
+            self.record(format!("scale {} {}", x, y));
+        }
+
+        fn set_rotation_degrees(&self, degrees: f32) {
+            self.record(format!("rotation {}", degrees));
+        }
+
+        fn set_modulate(&self, colour: Color) {
+            self.record(format!("modulate {} {} {}", colour.r, colour.g, colour.b));
+        }
+
+        fn set_visible(&self, visible: bool) {
+            self.record(format!("visible {}", visible));
+        }
+
+        fn add_to_group(&self, group: &str) {
+            self.record(format!("group {}", group));
+        }
+    }
+
+    /// A container in a tree of containers that counts how many were created. Below the root,
+    /// "Blocked" is taken by a node that is no container.
+    #[derive(Clone, Debug)]
+    struct FakeContainer {
+        path: String,
+        tree: Rc<RefCell<Vec<String>>>,
+        created: Rc<Cell<u32>>,
+    }
+
+    impl FakeContainer {
+        fn root() -> Self {
+            FakeContainer {
+                path: String::new(),
+                tree: Rc::new(RefCell::new(Vec::new())),
+                created: Rc::new(Cell::new(0)),
+            }
+        }
+
+        fn at(&self, name: &str) -> Self {
+            FakeContainer {
+                path: format!("{}/{}", self.path, name),
+                ..self.clone()
+            }
+        }
+    }
+
+    impl ContainerNode for FakeContainer {
+        fn child(&self, name: &str) -> Option<Self> {
+            let child = self.at(name);
+            if child.path == "/Blocked" {
+                return None;
+            }
+            if self.tree.borrow().contains(&child.path) {
+                Some(child)
+            } else {
+                None
+            }
+        }
+
+        fn add_container(&self, name: &str) -> Option<Self> {
+            let child = self.at(name);
+            if child.path == "/Blocked" {
+                return None;
+            }
+            self.tree.borrow_mut().push(child.path.clone());
+            self.created.set(self.created.get() + 1);
+            Some(child)
+        }
+    }
+
+    fn template() -> NodeTemplate {
+        NodeTemplate::new("res://Missing.tscn")
+    }
+
+    #[test]
+    fn containers_are_created_on_demand_and_reused() {
+        let root = FakeContainer::root();
+
+        assert_eq!(resolve_parent(&root, Some("Units")).path, "/Units");
+        assert_eq!(root.created.get(), 1);
+        assert_eq!(resolve_parent(&root, Some("Units")).path, "/Units");
+        assert_eq!(root.created.get(), 1);
+
+        assert_eq!(resolve_parent(&root, Some("Units/Air")).path, "/Units/Air");
+        assert_eq!(root.created.get(), 2);
+    }
+
+    #[test]
+    fn parent_falls_back_to_the_root() {
+        let root = FakeContainer::root();
+
+        assert_eq!(resolve_parent(&root, None).path, "");
+        assert_eq!(resolve_parent(&root, Some("")).path, "");
+        assert_eq!(resolve_parent(&root, Some("Blocked/Units")).path, "");
+        assert_eq!(root.created.get(), 0);
+    }
+
+    #[test]
+    fn template_sets_every_property() {
+        let node = FakeNode::default();
+        let template = NodeTemplate::new("res://Unit.tscn")
+            .scale(2.0, 0.5)
+            .z_index(3)
+            .rotation_degrees(90.0)
+            .modulate(Color::rgb(1.0, 0.0, 0.5))
+            .visible(false)
+            .group("units")
+            .group("selectable");
+
+        apply_template(&node, &template);
+
+        assert_eq!(
+            *node.properties.borrow(),
+            vec![
+                "z_index 3",
+                "scale 2 0.5",
+                "rotation 90",
+                "modulate 1 0 0.5",
+                "visible false",
+                "group units",
+                "group selectable",
+            ]
+        );
+    }
+
+    #[test]
+    fn templates_without_the_new_fields_keep_their_defaults() {
+        let node = FakeNode::default();
+
+        apply_template(&node, &template());
+
+        assert_eq!(
+            *node.properties.borrow(),
+            vec![
+                "z_index 0",
+                "scale 1 1",
+                "rotation 0",
+                "modulate 1 1 1",
+                "visible true",
+            ]
+        );
+    }
+
+    #[test]
+    fn gives_up_after_the_retry_cap() {
+        let instancer = FakeInstancer::new(u32::MAX);
+        let policy = NodeCreationPolicy {
+            max_attempts: 3,
+            placeholder: false,
+        };
+        let mut failures = 0;
+
+        for _ in 0..2 {
+            assert_eq!(
+                try_create_node(&instancer, &template(), &policy, &mut failures),
+                NodeCreation::Retry(ManageErrs::CouldNotLoadScene)
+            );
+        }
+        assert_eq!(
+            try_create_node(&instancer, &template(), &policy, &mut failures),
+            NodeCreation::GaveUp(ManageErrs::CouldNotLoadScene, None)
+        );
+        assert_eq!(instancer.attempts.get(), 3);
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn success_resets_the_failures() {
+        let instancer = FakeInstancer::new(2);
+        let policy = NodeCreationPolicy::default();
+        let mut failures = 0;
+
+        try_create_node(&instancer, &template(), &policy, &mut failures);
+        try_create_node(&instancer, &template(), &policy, &mut failures);
+        assert_eq!(failures, 2);
+        assert_eq!(
+            try_create_node(&instancer, &template(), &policy, &mut failures),
+            NodeCreation::Created("res://Missing.tscn".to_owned())
+        );
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn placeholder_takes_the_place_of_the_scene() {
+        let instancer = FakeInstancer::new(u32::MAX);
+        let policy = NodeCreationPolicy {
+            max_attempts: 1,
+            placeholder: true,
+        };
+        let mut failures = 0;
+
+        assert_eq!(
+            try_create_node(&instancer, &template(), &policy, &mut failures),
+            NodeCreation::GaveUp(
+                ManageErrs::CouldNotLoadScene,
+                Some("placeholder for res://Missing.tscn".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn the_budget_limits_the_nodes_per_frame() {
+        let instancer = FakeInstancer::new(0);
+        let policy = NodeCreationPolicy::default();
+        let budget = NodeBudget {
+            max_nodes: 50,
+            max_ms: 4.0,
+        };
+        let waiting: Vec<NodeTemplate> = (0..120)
+            .map(|index| NodeTemplate::new(&format!("res://Unit{}.tscn", index)))
+            .collect();
+        let mut created = Vec::new();
+        let mut frames = 0;
+        let mut start = 0;
+
+        while start < waiting.len() {
+            frames += 1;
+            start += create_within_budget(
+                &waiting[start..],
+                &budget,
+                || 0.0,
+                |template| match try_create_node(&instancer, template, &policy, &mut 0) {
+                    NodeCreation::Created(node) => {
+                        created.push(node);
+                        true
+                    }
+                    _ => false,
+                },
+            );
+        }
+
+        assert_eq!(frames, 3);
+        assert_eq!(instancer.attempts.get(), 120);
+        assert_eq!(created.first().unwrap(), "res://Unit0.tscn");
+        assert_eq!(created.last().unwrap(), "res://Unit119.tscn");
+    }
+
+    #[test]
+    fn the_budget_stops_when_the_time_is_up() {
+        let budget = NodeBudget {
+            max_nodes: 50,
+            max_ms: 4.0,
+        };
+        let clock = Cell::new(0.0);
+        let done = create_within_budget(
+            &[(); 10],
+            &budget,
+            || clock.get(),
+            |_| {
+                clock.set(clock.get() + 1.5);
+                true
+            },
+        );
+
+        assert_eq!(done, 3);
+        // However slow a node is, every frame makes at least one.
+        let slow = create_within_budget(&[(); 10], &budget, || 100.0, |_| true);
+        assert_eq!(slow, 1);
+    }
+
+    #[test]
+    fn retries_use_the_budget_without_counting_as_done() {
+        let instancer = FakeInstancer::new(1);
+        let policy = NodeCreationPolicy::default();
+        let budget = NodeBudget {
+            max_nodes: 3,
+            max_ms: 4.0,
+        };
+        let waiting = vec![template(); 5];
+        let mut failures = 0;
+
+        let done = create_within_budget(
+            &waiting,
+            &budget,
+            || 0.0,
+            |template| {
+                !matches!(
+                    try_create_node(&instancer, template, &policy, &mut failures),
+                    NodeCreation::Retry(_)
+                )
+            },
+        );
+
+        assert_eq!(done, 2);
+        assert_eq!(instancer.attempts.get(), 3);
+    }
+
+    #[test]
+    fn progress_counts_towards_the_total() {
+        let mut progress = LoadingProgress::default();
+
+        assert_eq!(progress.record(0, 0), LoadingStep::Idle);
+        assert_eq!(
+            progress.record(120, 50),
+            LoadingStep::Progress {
+                created: 50,
+                total: 120
+            }
+        );
+        // Ten more entities appear while loading.
+        assert_eq!(
+            progress.record(80, 50),
+            LoadingStep::Progress {
+                created: 100,
+                total: 130
+            }
+        );
+        assert_eq!(
+            progress.record(30, 30),
+            LoadingStep::Finished { total: 130 }
+        );
+        assert_eq!(progress.last_step, LoadingStep::Finished { total: 130 });
+        assert_eq!(progress.record(0, 0), LoadingStep::Idle);
+        // The next backlog starts from zero.
+        assert_eq!(
+            progress.record(4, 1),
+            LoadingStep::Progress {
+                created: 1,
+                total: 4
+            }
+        );
+    }
+
+    #[test]
+    fn clearing_the_failures_allows_a_retry() {
+        let mut world = World::default();
+        let entity = world.push((
+            template(),
+            NodeCreationFailed {
+                scene_file: "res://Missing.tscn".to_owned(),
+                placeholder: false,
+            },
+        ));
+
+        assert_eq!(clear_node_creation_failures(&mut world), 1);
+        assert!(world
+            .entry(entity)
+            .unwrap()
+            .get_component::<NodeCreationFailed>()
+            .is_err());
+        assert_eq!(clear_node_creation_failures(&mut world), 0);
+    }
+}
@@ -1,15 +1,21 @@
+use crate::components::abilities::BlocksVision;
 use crate::components::hexagon::Direction;
 use crate::components::hexagon::Hexagon;
 use crate::components::node_component::NodeComponent;
+use crate::components::persistent_id::PersistentId;
 use crate::components::player::Player;
 use crate::components::unit::Unit;
+use crate::edges::EdgeData;
 use crate::legion::entity_has_component;
-use core::cmp::Reverse;
+use crate::map_bounds::MapBounds;
+use crate::movement::{MovementCosts, HEXAGON_COST};
+use crate::profiler;
+use core::cmp::{Ordering, Reverse};
 use gdnative::api::Physics2DDirectSpaceState;
 use gdnative::prelude::*;
 use legion::{Entity, EntityStore, IntoQuery};
 use priority_queue::PriorityQueue;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 const GROUND_BIT: i64 = 0;
 const UNIT_BIT: i64 = 1;
@@ -31,6 +37,30 @@ pub fn create_grid(radius: u32) -> Vec<Hexagon> {
     field
 }
 
+/// The hexagons of a map `width` hexagons wide and `height` rows high around the centre, with
+/// the rows of a pointy-top map shifted so its edges are straight.
+pub fn create_rect_grid(width: u32, height: u32) -> Vec<Hexagon> {
+    let top = -(height as i32) / 2;
+    let left = -(width as i32) / 2;
+    let mut field = Vec::new();
+    for r in top..top + height as i32 {
+        // Every second row starts half a hexagon further right.
+        let shift = (r - (r & 1)) / 2;
+        for column in left..left + width as i32 {
+            field.push(Hexagon::new_axial(column - shift, r));
+        }
+    }
+    field
+}
+
+/// The hexfield size the node scenes are drawn for.
+pub const DEFAULT_HEXFIELD_SIZE: f32 = 40.0;
+
+/// How much the nodes are scaled for hexagons of `hexfield_size`.
+pub fn hexfield_scale(hexfield_size: f32) -> f32 {
+    hexfield_size / DEFAULT_HEXFIELD_SIZE
+}
+
 pub fn get_2d_position_from_hex(hex: &Hexagon, hexfield_size: f32) -> Vector2 {
     let x = hexfield_size
         * (3.0_f32.sqrt() * (hex.get_q() as f32) + 3.0_f32.sqrt() / 2.0 * (hex.get_r() as f32));
@@ -49,71 +79,257 @@ pub fn get_neighbours(hexagon: &Hexagon) -> Vec<Hexagon> {
     ]
 }
 
+/// The entities on `hexagon`, ordered by persistent id so the result does not depend on the order
+/// the entities were allocated in. Entities without an id, like pickups and smoke, come first,
+/// which leaves the unit last.
 pub fn get_entities_at_hexagon<S: EntityStore>(hexagon: &Hexagon, world: &S) -> Vec<Entity> {
-    <&Hexagon>::query()
-        .iter_chunks(world)
-        .flat_map(|chunk| {
-            chunk.into_iter_entities().filter_map(|tuple| {
-                if tuple.1 == hexagon {
-                    Some(tuple.0)
-                } else {
-                    None
+    let _timer = profiler::scope("get_entities_at_hexagon");
+    let mut entities: Vec<(Option<PersistentId>, Entity)> =
+        <(Entity, &Hexagon, Option<&PersistentId>)>::query()
+            .iter(world)
+            .filter(|(_, position, _)| *position == hexagon)
+            .map(|(entity, _, id)| (id.copied(), *entity))
+            .collect();
+    entities.sort_by_key(|(id, _)| *id);
+    entities.into_iter().map(|(_, entity)| entity).collect()
+}
+
+pub fn is_occupied_by_unit<S: EntityStore>(hexagon: &Hexagon, world: &S) -> bool {
+    get_entities_at_hexagon(hexagon, world)
+        .iter()
+        .any(|entity| entity_has_component::<Unit, S>(world, entity))
+}
+
+/// Whether something on the hexagon blocks the line of sight through it, like smoke.
+pub fn is_vision_blocked<S: EntityStore>(hexagon: &Hexagon, world: &S) -> bool {
+    get_entities_at_hexagon(hexagon, world)
+        .iter()
+        .any(|entity| entity_has_component::<BlocksVision, S>(world, entity))
+}
+
+/// Returns every hexagon of the map that can be reached from `start` in at most `range` steps
+/// without passing through other units. `start` itself is not included.
+pub fn get_reachable_hexagons<S: EntityStore>(
+    start: &Hexagon,
+    range: i32,
+    bounds: &MapBounds,
+    world: &S,
+) -> Vec<Hexagon> {
+    let mut reachable = Vec::new();
+    let mut visited = HashSet::new();
+    visited.insert(*start);
+    let mut frontier = vec![*start];
+    for _ in 0..range {
+        let mut next_frontier = Vec::new();
+        for hexagon in frontier {
+            for next in get_neighbours(&hexagon) {
+                if visited.contains(&next)
+                    || !bounds.contains(&next)
+                    || is_occupied_by_unit(&next, world)
+                {
+                    continue;
                 }
-            })
+                visited.insert(next);
+                reachable.push(next);
+                next_frontier.push(next);
+            }
+        }
+        frontier = next_frontier;
+    }
+    reachable
+}
+
+/// Returns the ring of hexagons with a distance between `min_range` and `max_range` to
+/// `center`, which is the area a unit can attack.
+pub fn get_hexagons_in_range(center: &Hexagon, min_range: i32, max_range: i32) -> Vec<Hexagon> {
+    let mut hexagons = Vec::new();
+    for q in -max_range..=max_range {
+        for r in -max_range..=max_range {
+            let hexagon = Hexagon::new_axial(center.get_q() + q, center.get_r() + r);
+            let distance = hexagon.distance_to(center);
+            if (min_range..=max_range).contains(&distance) {
+                hexagons.push(hexagon);
+            }
+        }
+    }
+    hexagons
+}
+
+/// Players whose influence on a hexagon differs by less than this are equally strong.
+const INFLUENCE_TOLERANCE: f32 = 1e-4;
+
+/// How strongly a unit influences a hexagon `distance` away: 1 on its own hexagon, falling
+/// linearly to 0 just beyond `radius`.
+pub fn influence_decay(distance: i32, radius: i32) -> f32 {
+    if distance > radius || radius < 0 {
+        return 0.0;
+    }
+    (radius + 1 - distance) as f32 / (radius + 1) as f32
+}
+
+/// The player that dominates each hexagon, with the influence they have there. Every unit
+/// influences the hexagons within its mobility and attack range, see `influence_decay`. Hexagons
+/// where the strongest players are equally strong are not claimed.
+pub fn compute_influence<S: EntityStore>(world: &S) -> BTreeMap<Hexagon, (usize, f32)> {
+    let mut totals: BTreeMap<Hexagon, HashMap<usize, f32>> = BTreeMap::new();
+    for (position, unit, player) in <(&Hexagon, &Unit, &Player)>::query().iter(world) {
+        let radius = unit.mobility + unit.max_attack_range;
+        for hexagon in get_hexagons_in_range(position, 0, radius) {
+            *totals
+                .entry(hexagon)
+                .or_default()
+                .entry(player.0)
+                .or_insert(0.0) += influence_decay(position.distance_to(&hexagon), radius);
+        }
+    }
+    totals
+        .into_iter()
+        .filter_map(|(hexagon, players)| {
+            let mut ranked: Vec<(usize, f32)> = players.into_iter().collect();
+            ranked.sort_by(|a, b| {
+                b.1.partial_cmp(&a.1)
+                    .unwrap_or(Ordering::Equal)
+                    .then(a.0.cmp(&b.0))
+            });
+            let (player, influence) = ranked[0];
+            match ranked.get(1) {
+                Some((_, second)) if influence - second < INFLUENCE_TOLERANCE => None,
+                _ => Some((hexagon, (player, influence))),
+            }
         })
         .collect()
 }
 
-pub fn find_path<S: EntityStore>(start: &Hexagon, target: &Hexagon, world: &S) -> Vec<Hexagon> {
-    match get_entities_at_hexagon(target, world)
+/// Whether no unit or smoke stands between the two hexagons and, if they block the sight, no hole
+/// of the map lies between them. Units on the end points do not block.
+pub fn is_line_of_sight_clear<S: EntityStore>(
+    start: &Hexagon,
+    end: &Hexagon,
+    bounds: &MapBounds,
+    world: &S,
+) -> bool {
+    start
+        .line_to(end)
         .iter()
-        .find(|entity| entity_has_component::<Unit, S>(world, entity))
-    {
-        None => {}
-        Some(_) => return Vec::new(),
+        .filter(|hexagon| *hexagon != start && *hexagon != end)
+        .all(|hexagon| {
+            !bounds.blocks_sight(hexagon)
+                && !is_occupied_by_unit(hexagon, world)
+                && !is_vision_blocked(hexagon, world)
+        })
+}
+
+/// Finds the cheapest path for the unit on `start` that stays within `bounds`, see
+/// `MovementCosts::for_unit_at`.
+pub fn find_path<S: EntityStore>(
+    start: &Hexagon,
+    target: &Hexagon,
+    edges: &EdgeData,
+    bounds: &MapBounds,
+    world: &S,
+) -> Vec<Hexagon> {
+    find_path_within(start, target, i32::MAX, edges, bounds, world)
+}
+
+/// Like `find_path`, but gives up on paths that cost more than `max_length` hexagons of open
+/// terrain. The search only visits hexagons within that cost, so it ends even if `target` is
+/// walled in.
+pub fn find_path_within<S: EntityStore>(
+    start: &Hexagon,
+    target: &Hexagon,
+    max_length: i32,
+    edges: &EdgeData,
+    bounds: &MapBounds,
+    world: &S,
+) -> Vec<Hexagon> {
+    let costs = MovementCosts::for_unit_at(world, start, edges).with_bounds(bounds);
+    find_path_with_costs(
+        start,
+        target,
+        max_length.saturating_mul(HEXAGON_COST),
+        &costs,
+        world,
+    )
+}
+
+/// A hexagon of the path search with the direction the path entered it in, `None` for the start.
+type PathNode = (Hexagon, Option<Direction>);
+
+/// Finds the cheapest path from `start` to `target` that costs at most `max_cost`. Returns an
+/// empty path if there is none.
+///
+/// Among the paths of the same cost the one with the fewest turns wins, and among those the one
+/// through the lower hexagons, so the same query always gives the same, straight path. Turns are
+/// only compared after the cost and never make a path more expensive.
+pub fn find_path_with_costs<S: EntityStore>(
+    start: &Hexagon,
+    target: &Hexagon,
+    max_cost: i32,
+    costs: &MovementCosts,
+    world: &S,
+) -> Vec<Hexagon> {
+    let _timer = profiler::scope("find_path");
+    if is_occupied_by_unit(target, world) {
+        return Vec::new();
     }
+    let origin: PathNode = (*start, None);
     let mut frontier = PriorityQueue::new();
-    frontier.push(*start, Reverse(0));
-    let mut came_from = HashMap::new();
-    let mut cost_so_far = HashMap::new();
-    came_from.insert(*start, None);
-    cost_so_far.insert(*start, 0);
-    while !frontier.is_empty() {
-        let (current, _) = frontier.pop().unwrap();
+    frontier.push(origin, Reverse((0, 0, *start, *start)));
+    let mut came_from: HashMap<PathNode, PathNode> = HashMap::new();
+    let mut cost_so_far: HashMap<PathNode, (i32, u32)> = HashMap::new();
+    cost_so_far.insert(origin, (0, 0));
+    let mut reached = None;
+    while let Some((node, _)) = frontier.pop() {
+        let (current, heading) = node;
         if current == *target {
+            reached = Some(node);
             break;
         }
-        for next in get_neighbours(&current) {
-            if get_entities_at_hexagon(&next, world)
-                .iter()
-                .any(|entity| entity_has_component::<Unit, S>(world, entity))
-            {
+        let (cost, turns) = cost_so_far[&node];
+        for direction in Direction::ALL.iter().copied() {
+            let next = current.get_neighbour(direction);
+            if is_occupied_by_unit(&next, world) {
                 continue;
             }
 
-            let new_cost = cost_so_far[&current] + 1;
-            if !cost_so_far.contains_key(&next) || new_cost < cost_so_far[&next] {
-                cost_so_far.insert(next, new_cost);
-                let priority = new_cost + next.distance_to(target);
-                frontier.push(next, Reverse(priority));
-                came_from.insert(next, Some(current));
+            let step_cost = match costs.step_cost(&current, &next) {
+                None => continue,
+                Some(step_cost) => step_cost,
+            };
+            let new_cost = cost + step_cost;
+            if new_cost > max_cost {
+                continue;
+            }
+            let turned = heading.map_or(false, |heading| heading != direction);
+            let new_turns = turns + u32::from(turned);
+            let next_node = (next, Some(direction));
+            match cost_so_far.get(&next_node) {
+                Some(known) if *known < (new_cost, new_turns) => continue,
+                Some(known) if *known == (new_cost, new_turns) => {
+                    if came_from[&next_node].0 > current {
+                        came_from.insert(next_node, node);
+                    }
+                    continue;
+                }
+                _ => {}
             }
+            cost_so_far.insert(next_node, (new_cost, new_turns));
+            came_from.insert(next_node, node);
+            let estimate = new_cost + next.distance_to(target) * costs.cheapest_step();
+            frontier.push(next_node, Reverse((estimate, new_turns, next, current)));
         }
     }
 
-    let mut path = Vec::new();
-
-    let mut current = match came_from[target] {
+    let mut node = match reached {
         None => return Vec::new(),
-        Some(hexagon) => hexagon,
+        Some(node) => node,
     };
-
-    path.insert(0, *target);
-    while current != *start {
-        path.insert(0, current);
-        current = came_from[&current].unwrap();
+    let mut path = Vec::new();
+    while node.0 != *start {
+        path.push(node.0);
+        node = came_from[&node];
     }
-
+    path.reverse();
     path
 }
 
@@ -123,9 +339,15 @@ pub fn is_hexagon_visible_for_attack<S: EntityStore>(
     hexfield_size: f32,
     selected_entity: Entity,
     target_hexagon: Hexagon,
+    line_of_sight: bool,
 ) -> bool {
     let (selected_unit, selected_hexagon, select_unit_player) = {
-        let entry = legion_world.entry_ref(selected_entity).unwrap();
+        let entry = match legion_world.entry_ref(selected_entity) {
+            Err(_) => {
+                return false;
+            }
+            Ok(entry) => entry,
+        };
         let hexagon = match entry.get_component::<Hexagon>() {
             Err(_) => {
                 return false;
@@ -180,6 +402,21 @@ pub fn is_hexagon_visible_for_attack<S: EntityStore>(
         };
 
         if !same_player {
+            if !line_of_sight {
+                return true;
+            }
+            // Smoke has no collision shape, so the ray cast below does not see it.
+            let smoke_between = selected_hexagon
+                .line_to(&target_hexagon)
+                .iter()
+                .any(|hexagon| {
+                    *hexagon != selected_hexagon
+                        && *hexagon != target_hexagon
+                        && is_vision_blocked(hexagon, legion_world)
+                });
+            if smoke_between {
+                return false;
+            }
             let physic_state = unsafe { physic_state.assume_safe() };
             let self_position = get_2d_position_from_hex(&target_hexagon, hexfield_size);
             let selected_position = get_2d_position_from_hex(&selected_hexagon, hexfield_size);
@@ -277,8 +514,27 @@ pub fn calculate_hexagon_points(hexfield_size: f32) -> Vec<Vector2> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::components::field::Field;
+    use crate::components::movement_type::MovementType;
+    use crate::components::terrain::Terrain;
+    use crate::edges::EdgeKind;
     use legion::{World, WorldOptions};
 
+    #[test]
+    fn positions_and_scale_follow_the_hexfield_size() {
+        for hexagon in create_grid(3) {
+            let doubled = get_2d_position_from_hex(&hexagon, 2.0 * DEFAULT_HEXFIELD_SIZE);
+            let original = get_2d_position_from_hex(&hexagon, DEFAULT_HEXFIELD_SIZE);
+            assert!((doubled - original * 2.0).length() < 1e-3);
+        }
+        assert_eq!(
+            get_2d_position_from_hex(&Hexagon::zero(), 80.0),
+            Vector2::zero()
+        );
+        assert_eq!(hexfield_scale(DEFAULT_HEXFIELD_SIZE), 1.0);
+        assert_eq!(hexfield_scale(20.0), 0.5);
+    }
+
     //noinspection DuplicatedCode
     #[test]
     fn create_grid_creates_grid_of_correct_size() {
@@ -329,4 +585,408 @@ mod tests {
         }));
         assert_eq!(result.len(), 4);
     }
+
+    #[test]
+    fn get_reachable_hexagons_stops_at_range() {
+        let world = World::default();
+
+        let reachable = get_reachable_hexagons(&Hexagon::zero(), 2, &MapBounds::default(), &world);
+
+        assert_eq!(reachable.len(), 18);
+        assert!(!reachable.contains(&Hexagon::zero()));
+        assert!(reachable
+            .iter()
+            .all(|hexagon| hexagon.distance_to(&Hexagon::zero()) <= 2));
+    }
+
+    #[test]
+    fn get_reachable_hexagons_does_not_pass_units() {
+        let mut world = World::default();
+        for neighbour in get_neighbours(&Hexagon::zero()) {
+            world.push((neighbour, Unit::new(1, 1, 1, 1, 1, 1, 1, 1)));
+        }
+
+        assert!(
+            get_reachable_hexagons(&Hexagon::zero(), 3, &MapBounds::default(), &world).is_empty()
+        );
+    }
+
+    #[test]
+    fn influence_decays_with_distance() {
+        assert_eq!(influence_decay(0, 3), 1.0);
+        assert_eq!(influence_decay(1, 3), 0.75);
+        assert_eq!(influence_decay(3, 3), 0.25);
+        assert_eq!(influence_decay(4, 3), 0.0);
+        assert_eq!(influence_decay(0, 0), 1.0);
+    }
+
+    #[test]
+    fn equal_influence_claims_nothing() {
+        let mut world = World::default();
+        // Mobility 1 and range 1 reach two hexagons.
+        let unit = Unit::new(1, 1, 1, 1, 0, 1, 1, 1);
+        world.push((Hexagon::new_axial(-2, 0), unit, Player(0)));
+        world.push((Hexagon::new_axial(2, 0), unit, Player(1)));
+
+        let influence = compute_influence(&world);
+
+        assert_eq!(influence.get(&Hexagon::zero()), None);
+        assert_eq!(
+            influence.get(&Hexagon::new_axial(-1, 0)),
+            Some(&(0, influence_decay(1, 2)))
+        );
+        assert_eq!(influence.get(&Hexagon::new_axial(2, 0)), Some(&(1, 1.0)));
+        assert_eq!(influence.get(&Hexagon::new_axial(5, 0)), None);
+    }
+
+    #[test]
+    fn get_hexagons_in_range_returns_ring() {
+        let ring = get_hexagons_in_range(&Hexagon::new_axial(2, -1), 2, 3);
+
+        assert_eq!(ring.len(), 12 + 18);
+        assert!(ring
+            .iter()
+            .all(|hexagon| { (2..=3).contains(&hexagon.distance_to(&Hexagon::new_axial(2, -1))) }));
+    }
+
+    #[test]
+    fn line_of_sight_is_blocked_by_units_between_the_ends() {
+        let mut world = World::default();
+        world.push((Hexagon::new_axial(0, 0), Unit::new(1, 1, 1, 1, 0, 1, 1, 1)));
+        world.push((Hexagon::new_axial(3, 0), Unit::new(1, 1, 1, 1, 0, 1, 1, 1)));
+
+        assert!(is_line_of_sight_clear(
+            &Hexagon::new_axial(0, 0),
+            &Hexagon::new_axial(3, 0),
+            &MapBounds::default(),
+            &world
+        ));
+
+        world.push((Hexagon::new_axial(2, 0), Unit::new(1, 1, 1, 1, 0, 1, 1, 1)));
+        assert!(!is_line_of_sight_clear(
+            &Hexagon::new_axial(0, 0),
+            &Hexagon::new_axial(3, 0),
+            &MapBounds::default(),
+            &world
+        ));
+        assert!(is_line_of_sight_clear(
+            &Hexagon::new_axial(0, 0),
+            &Hexagon::new_axial(0, 3),
+            &MapBounds::default(),
+            &world
+        ));
+    }
+
+    #[test]
+    fn find_path_within_gives_up_on_walled_in_targets() {
+        let mut world = World::default();
+        let target = Hexagon::new_axial(3, 0);
+        for neighbour in get_neighbours(&target) {
+            world.push((neighbour, Unit::new(1, 1, 1, 1, 1, 1, 1, 1)));
+        }
+
+        assert!(find_path_within(
+            &Hexagon::zero(),
+            &target,
+            10,
+            &EdgeData::default(),
+            &MapBounds::default(),
+            &world
+        )
+        .is_empty());
+        assert_eq!(
+            find_path_within(
+                &Hexagon::zero(),
+                &Hexagon::new_axial(0, 3),
+                3,
+                &EdgeData::default(),
+                &MapBounds::default(),
+                &world
+            )
+            .len(),
+            3
+        );
+        assert!(find_path_within(
+            &Hexagon::zero(),
+            &Hexagon::new_axial(0, 3),
+            2,
+            &EdgeData::default(),
+            &MapBounds::default(),
+            &world
+        )
+        .is_empty());
+    }
+
+    fn axial_path(coordinates: &[(i32, i32)]) -> Vec<Hexagon> {
+        coordinates
+            .iter()
+            .map(|(q, r)| Hexagon::new_axial(*q, *r))
+            .collect()
+    }
+
+    #[test]
+    fn equal_paths_go_straight_through_the_lower_hexagons() {
+        let mut world = World::default();
+        let edges = EdgeData::default();
+        let bounds = MapBounds::default();
+        let cases = [
+            ((0, 0), (3, -1), axial_path(&[(1, -1), (2, -1), (3, -1)])),
+            (
+                (0, 0),
+                (2, 2),
+                axial_path(&[(0, 1), (0, 2), (1, 2), (2, 2)]),
+            ),
+            ((0, 0), (-2, 3), axial_path(&[(-1, 1), (-2, 2), (-2, 3)])),
+            (
+                (1, -2),
+                (-2, 2),
+                axial_path(&[(0, -1), (-1, 0), (-2, 1), (-2, 2)]),
+            ),
+        ];
+        for ((start_q, start_r), (target_q, target_r), expected) in cases.iter() {
+            let start = Hexagon::new_axial(*start_q, *start_r);
+            let target = Hexagon::new_axial(*target_q, *target_r);
+
+            let path = find_path(&start, &target, &edges, &bounds, &world);
+            assert_eq!(&path, expected);
+            assert_eq!(find_path(&start, &target, &edges, &bounds, &world), path);
+            // Preferring straight paths costs nothing, the path still fits its own length.
+            let length = path.len() as i32;
+            assert_eq!(
+                find_path_within(&start, &target, length, &edges, &bounds, &world),
+                path
+            );
+            assert!(
+                find_path_within(&start, &target, length - 1, &edges, &bounds, &world).is_empty()
+            );
+        }
+
+        // Both ways around the unit turn twice, the one through the lower hexagons is taken.
+        world.push((Hexagon::new_axial(2, 0), Unit::new(1, 1, 1, 1, 1, 1, 1, 1)));
+        assert_eq!(
+            find_path(
+                &Hexagon::zero(),
+                &Hexagon::new_axial(4, 0),
+                &edges,
+                &bounds,
+                &world
+            ),
+            axial_path(&[(0, 1), (1, 1), (2, 1), (3, 1), (4, 0)])
+        );
+    }
+
+    #[test]
+    fn find_path_takes_a_road_detour_over_a_shorter_path() {
+        let mut world = World::default();
+        let road = vec![
+            Hexagon::new_axial(0, 1),
+            Hexagon::new_axial(1, 1),
+            Hexagon::new_axial(2, 1),
+            Hexagon::new_axial(3, 0),
+        ];
+        for hexagon in &road {
+            let mut field = Field::new(*hexagon);
+            field.terrain = Terrain::Road;
+            world.push((field,));
+        }
+        let target = Hexagon::new_axial(3, 0);
+
+        assert_eq!(
+            find_path(
+                &Hexagon::zero(),
+                &target,
+                &EdgeData::default(),
+                &MapBounds::default(),
+                &world
+            ),
+            road
+        );
+        assert_eq!(
+            find_path_within(
+                &Hexagon::zero(),
+                &target,
+                2,
+                &EdgeData::default(),
+                &MapBounds::default(),
+                &world
+            ),
+            road
+        );
+
+        world.push((
+            Hexagon::zero(),
+            Unit::new(1, 1, 1, 1, 1, 1, 1, 1),
+            MovementType::Tracked,
+        ));
+        assert_eq!(
+            find_path(
+                &Hexagon::zero(),
+                &target,
+                &EdgeData::default(),
+                &MapBounds::default(),
+                &world
+            )
+            .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn find_path_does_not_shortcut_outside_a_concave_map() {
+        // A map of radius 3 with a notch cut in from the south along the column q = 0, the two
+        // halves only meet in the north.
+        let mut world = World::default();
+        for hexagon in create_grid(3) {
+            if hexagon.get_q() != 0 || hexagon.get_r() < -1 {
+                world.push((Field::new(hexagon),));
+            }
+        }
+        let bounds = MapBounds::from_world(&world);
+        let (start, target) = (Hexagon::new_axial(-2, 1), Hexagon::new_axial(2, 0));
+
+        let unbounded = find_path(
+            &start,
+            &target,
+            &EdgeData::default(),
+            &MapBounds::default(),
+            &world,
+        );
+        let path = find_path(&start, &target, &EdgeData::default(), &bounds, &world);
+
+        assert_eq!(unbounded.len(), 4);
+        assert!(path.len() > unbounded.len());
+        assert_eq!(path.last(), Some(&target));
+        assert!(path.iter().all(|hexagon| bounds.contains(hexagon)));
+        assert!(
+            find_path_within(&start, &target, 4, &EdgeData::default(), &bounds, &world).is_empty()
+        );
+        assert!(get_reachable_hexagons(&start, 3, &bounds, &world)
+            .iter()
+            .all(|hexagon| bounds.contains(hexagon)));
+    }
+
+    #[test]
+    fn paths_go_around_a_donut_hole_and_sight_crosses_it_if_allowed() {
+        let mut world = World::default();
+        for hexagon in create_grid(3) {
+            if hexagon.distance_to(&Hexagon::zero()) > 1 {
+                world.push((Field::new(hexagon),));
+            }
+        }
+        let mut bounds = MapBounds::from_world(&world);
+        let (start, target) = (Hexagon::new_axial(-2, 0), Hexagon::new_axial(2, 0));
+
+        let path = find_path(&start, &target, &EdgeData::default(), &bounds, &world);
+        assert_eq!(path.len(), 6);
+        assert!(path.iter().all(|hexagon| bounds.contains(hexagon)));
+        assert!(get_reachable_hexagons(&start, 1, &bounds, &world)
+            .iter()
+            .all(|hexagon| hexagon.distance_to(&Hexagon::zero()) > 1));
+
+        assert!(is_line_of_sight_clear(&start, &target, &bounds, &world));
+        bounds.set_holes_block_sight(true);
+        assert!(!is_line_of_sight_clear(&start, &target, &bounds, &world));
+        // Lines along the ring do not cross the hole.
+        assert!(is_line_of_sight_clear(
+            &start,
+            &Hexagon::new_axial(0, -2),
+            &bounds,
+            &world
+        ));
+    }
+
+    /// Edges of the kind between the column q = 0 and the column q = 1, from r = -3 to r = 3.
+    fn edge_line(kind: EdgeKind) -> EdgeData {
+        let mut edges = EdgeData::default();
+        for r in -3..=3 {
+            let left = Hexagon::new_axial(0, r);
+            edges.set(left, Hexagon::new_axial(1, r), Some(kind));
+            edges.set(left, Hexagon::new_axial(1, r - 1), Some(kind));
+        }
+        edges
+    }
+
+    #[test]
+    fn find_path_goes_around_a_cliff_line() {
+        let world = World::default();
+        let edges = edge_line(EdgeKind::Cliff);
+        let target = Hexagon::new_axial(2, 0);
+
+        let path = find_path(
+            &Hexagon::zero(),
+            &target,
+            &edges,
+            &MapBounds::default(),
+            &world,
+        );
+
+        assert!(path.len() > 2);
+        assert_eq!(path.last(), Some(&target));
+        let mut from = Hexagon::zero();
+        for hexagon in &path {
+            assert_ne!(edges.get(&from, hexagon), Some(EdgeKind::Cliff));
+            from = *hexagon;
+        }
+        assert!(find_path_within(
+            &Hexagon::zero(),
+            &target,
+            4,
+            &edges,
+            &MapBounds::default(),
+            &world
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn find_path_crosses_rivers_on_bridges() {
+        let world = World::default();
+        let mut edges = edge_line(EdgeKind::River);
+        edges.set(
+            Hexagon::zero(),
+            Hexagon::new_axial(1, 0),
+            Some(EdgeKind::Bridge),
+        );
+        let target = Hexagon::new_axial(2, 0);
+        let straight = vec![Hexagon::new_axial(1, 0), target];
+
+        assert_eq!(
+            find_path_within(
+                &Hexagon::zero(),
+                &target,
+                2,
+                &edges,
+                &MapBounds::default(),
+                &world
+            ),
+            straight
+        );
+
+        edges.set(
+            Hexagon::zero(),
+            Hexagon::new_axial(1, 0),
+            Some(EdgeKind::River),
+        );
+        assert!(find_path_within(
+            &Hexagon::zero(),
+            &target,
+            3,
+            &edges,
+            &MapBounds::default(),
+            &world
+        )
+        .is_empty());
+        assert_eq!(
+            find_path_within(
+                &Hexagon::zero(),
+                &target,
+                4,
+                &edges,
+                &MapBounds::default(),
+                &world
+            )
+            .len(),
+            2
+        );
+    }
 }
@@ -0,0 +1,224 @@
+//! The explicit attack mode. A selected unit that enters it picks its target with the cursor,
+//! every hovered hexagon tells whether an attack on it would go through and why not. Clicks in
+//! this mode attack instead of moving, cancelling returns to the selection.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::game_state::{set_state, GameState, State};
+use crate::map_bounds::MapBounds;
+use crate::rules::Ruleset;
+use crate::systems::hexgrid::is_line_of_sight_clear;
+use legion::{Entity, EntityStore, IntoQuery};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetValidity {
+    Valid,
+    /// No unit stands on the hexagon.
+    Empty,
+    /// The unit on the hexagon belongs to the attacker.
+    Friendly,
+    /// The attacker moved this round and cannot fire after moving.
+    MovedBeforeFiring,
+    OutOfRange,
+    /// With the line of sight rule, something stands between the attacker and the target.
+    NoLineOfSight,
+}
+
+impl TargetValidity {
+    pub fn is_valid(&self) -> bool {
+        *self == TargetValidity::Valid
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TargetValidity::Valid => "valid",
+            TargetValidity::Empty => "empty",
+            TargetValidity::Friendly => "friendly",
+            TargetValidity::MovedBeforeFiring => "moved_before_firing",
+            TargetValidity::OutOfRange => "out_of_range",
+            TargetValidity::NoLineOfSight => "no_line_of_sight",
+        }
+    }
+}
+
+/// Whether `attacker` can attack the unit on `hexagon`, along with that unit. The reasons are
+/// checked in the order of the variants, so an empty hexagon is empty even if it is out of range.
+pub fn target_validity<S: EntityStore>(
+    world: &S,
+    rules: &Ruleset,
+    bounds: &MapBounds,
+    attacker: Entity,
+    hexagon: &Hexagon,
+) -> (TargetValidity, Option<Entity>) {
+    let target = <(Entity, &Hexagon, &Unit, Option<&PlayerComponent>)>::query()
+        .iter(world)
+        .find(|(_, position, _, _)| *position == hexagon)
+        .map(|(entity, _, _, player)| (*entity, player.map(|player| player.0)));
+    let (target, target_player) = match target {
+        None => return (TargetValidity::Empty, None),
+        Some(target) => target,
+    };
+    let (unit, position, player) = match world.entry_ref(attacker) {
+        Err(_) => return (TargetValidity::Empty, None),
+        Ok(entry) => match (
+            entry.get_component::<Unit>(),
+            entry.get_component::<Hexagon>(),
+        ) {
+            (Ok(unit), Ok(position)) => (
+                *unit,
+                *position,
+                entry
+                    .get_component::<PlayerComponent>()
+                    .ok()
+                    .map(|player| player.0),
+            ),
+            _ => return (TargetValidity::Empty, None),
+        },
+    };
+    let validity = if target == attacker || (player.is_some() && target_player == player) {
+        TargetValidity::Friendly
+    } else if unit.must_hold_fire() {
+        TargetValidity::MovedBeforeFiring
+    } else if !unit.is_in_attack_range(position.distance_to(hexagon)) {
+        TargetValidity::OutOfRange
+    } else if rules.line_of_sight && !is_line_of_sight_clear(&position, hexagon, bounds, world) {
+        TargetValidity::NoLineOfSight
+    } else {
+        TargetValidity::Valid
+    };
+    (validity, Some(target))
+}
+
+/// Lets the selected unit pick a target, if it has an attack left. Returns whether the attack
+/// mode was entered.
+pub fn begin_targeting<S: EntityStore>(world: &S, state: &mut GameState) -> bool {
+    let entity = match state.state {
+        State::Selected(entity) => entity,
+        _ => return false,
+    };
+    let can_attack = world.entry_ref(entity).map_or(false, |entry| {
+        entry
+            .get_component::<Unit>()
+            .map_or(false, |unit| unit.can_attack(&state.rules))
+    });
+    if !can_attack {
+        return false;
+    }
+    set_state(state, State::Targeting(entity));
+    true
+}
+
+/// Leaves the attack mode for the selection of the unit. Returns false outside of the attack mode.
+pub fn cancel_targeting(state: &mut GameState) -> bool {
+    match state.state {
+        State::Targeting(entity) => {
+            set_state(state, State::Selected(entity));
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_state::force_state;
+    use legion::World;
+
+    fn spawn(world: &mut World, player: usize, q: i32, r: i32) -> Entity {
+        world.push((
+            PlayerComponent(player),
+            Hexagon::new_axial(q, r),
+            Unit::new(10, 4, 2, 1, 1, 3, 3, 1),
+        ))
+    }
+
+    #[test]
+    fn every_target_gets_a_reason() {
+        let mut world = World::default();
+        let rules = Ruleset {
+            line_of_sight: true,
+            ..Ruleset::default()
+        };
+        let attacker = spawn(&mut world, 0, 0, 0);
+        let enemy = spawn(&mut world, 1, 0, 1);
+        let friend = spawn(&mut world, 0, 1, 0);
+        let far_enemy = spawn(&mut world, 1, 4, 0);
+        // The friend stands between the attacker and this one.
+        let hidden_enemy = spawn(&mut world, 1, 2, 0);
+        let bounds = MapBounds::default();
+        let check =
+            |q, r| target_validity(&world, &rules, &bounds, attacker, &Hexagon::new_axial(q, r));
+
+        assert_eq!(check(0, 1), (TargetValidity::Valid, Some(enemy)));
+        assert_eq!(check(-1, 0), (TargetValidity::Empty, None));
+        assert_eq!(check(1, 0), (TargetValidity::Friendly, Some(friend)));
+        assert_eq!(check(0, 0), (TargetValidity::Friendly, Some(attacker)));
+        assert_eq!(check(4, 0), (TargetValidity::OutOfRange, Some(far_enemy)));
+        assert_eq!(
+            check(2, 0),
+            (TargetValidity::NoLineOfSight, Some(hidden_enemy))
+        );
+        assert_eq!(
+            target_validity(
+                &world,
+                &Ruleset::default(),
+                &bounds,
+                attacker,
+                &Hexagon::new_axial(2, 0)
+            ),
+            (TargetValidity::Valid, Some(hidden_enemy))
+        );
+        assert!(check(0, 1).0.is_valid());
+        assert_eq!(TargetValidity::NoLineOfSight.reason(), "no_line_of_sight");
+    }
+
+    #[test]
+    fn units_that_moved_hold_their_fire_if_they_fire_before_moving() {
+        let mut world = World::default();
+        let attacker = spawn(&mut world, 0, 0, 0);
+        let enemy = spawn(&mut world, 1, 0, 1);
+        let mut state = GameState::new();
+        force_state(&mut state, State::Selected(attacker));
+        {
+            let mut entry = world.entry(attacker).unwrap();
+            let unit = entry.get_component_mut::<Unit>().unwrap();
+            unit.fire_after_move = false;
+            unit.moved = true;
+        }
+
+        assert_eq!(
+            target_validity(
+                &world,
+                &state.rules,
+                &state.map_bounds,
+                attacker,
+                &Hexagon::new_axial(0, 1)
+            ),
+            (TargetValidity::MovedBeforeFiring, Some(enemy))
+        );
+        assert_eq!(
+            TargetValidity::MovedBeforeFiring.reason(),
+            "moved_before_firing"
+        );
+        assert!(!begin_targeting(&world, &mut state));
+        assert_eq!(state.state, State::Selected(attacker));
+    }
+
+    #[test]
+    fn cancelling_returns_to_the_selection() {
+        let mut world = World::default();
+        let unit = spawn(&mut world, 0, 0, 0);
+        let mut state = GameState::new();
+
+        assert!(!begin_targeting(&world, &mut state));
+        assert!(!cancel_targeting(&mut state));
+        force_state(&mut state, State::Selected(unit));
+        assert!(begin_targeting(&world, &mut state));
+        assert_eq!(state.state, State::Targeting(unit));
+        assert!(state.state.accepts_orders());
+        assert!(cancel_targeting(&mut state));
+        assert_eq!(state.state, State::Selected(unit));
+    }
+}
@@ -0,0 +1,173 @@
+//! Turns touch screen events into gestures. A short tap clicks, holding a finger down past the
+//! long press duration cancels like a right click and moving it further than the drag distance
+//! pans the view. Two fingers moving apart or together request a zoom.
+
+use gdnative::core_types::Vector2;
+use std::collections::BTreeMap;
+
+pub const DEFAULT_LONG_PRESS_DURATION: f64 = 0.5;
+pub const DEFAULT_DRAG_DISTANCE: f32 = 20.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gesture {
+    Tap(Vector2),
+    LongPress(Vector2),
+    /// The finger moved by `relative` while panning.
+    Drag(Vector2),
+    /// The distance between two fingers changed by `factor` around `centre`.
+    Pinch {
+        factor: f32,
+        centre: Vector2,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Touch {
+    start: Vector2,
+    position: Vector2,
+    pressed_at: f64,
+    /// Set once the finger moved too far or took part in a pinch, it no longer taps.
+    moved: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TouchTracker {
+    pub long_press_duration: f64,
+    pub drag_distance: f32,
+    touches: BTreeMap<i64, Touch>,
+}
+
+impl Default for TouchTracker {
+    fn default() -> Self {
+        TouchTracker {
+            long_press_duration: DEFAULT_LONG_PRESS_DURATION,
+            drag_distance: DEFAULT_DRAG_DISTANCE,
+            touches: BTreeMap::new(),
+        }
+    }
+}
+
+impl TouchTracker {
+    pub fn press(&mut self, index: i64, position: Vector2, time: f64) {
+        let pinching = !self.touches.is_empty();
+        for touch in self.touches.values_mut() {
+            touch.moved = true;
+        }
+        self.touches.insert(
+            index,
+            Touch {
+                start: position,
+                position,
+                pressed_at: time,
+                moved: pinching,
+            },
+        );
+    }
+
+    pub fn drag(&mut self, index: i64, position: Vector2) -> Option<Gesture> {
+        let before = self.pinch_span();
+        let drag_distance = self.drag_distance;
+        let touch = self.touches.get_mut(&index)?;
+        let relative = position - touch.position;
+        touch.position = position;
+        if (position - touch.start).length() >= drag_distance {
+            touch.moved = true;
+        }
+        let moved = touch.moved;
+
+        match (before, self.pinch_span()) {
+            (Some((before, _)), Some((after, centre))) if before > 0.0 => Some(Gesture::Pinch {
+                factor: after / before,
+                centre,
+            }),
+            (None, None) if moved => Some(Gesture::Drag(relative)),
+            _ => None,
+        }
+    }
+
+    /// Ends a touch. Fingers that did not move give a tap or, held long enough, a long press.
+    pub fn release(&mut self, index: i64, time: f64) -> Option<Gesture> {
+        let touch = self.touches.remove(&index)?;
+        if touch.moved {
+            None
+        } else if time - touch.pressed_at >= self.long_press_duration {
+            Some(Gesture::LongPress(touch.start))
+        } else {
+            Some(Gesture::Tap(touch.start))
+        }
+    }
+
+    /// Distance and centre between the first two fingers.
+    fn pinch_span(&self) -> Option<(f32, Vector2)> {
+        let mut touches = self.touches.values();
+        let first = touches.next()?.position;
+        let second = touches.next()?.position;
+        Some(((second - first).length(), (first + second) / 2.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32, y: f32) -> Vector2 {
+        Vector2::new(x, y)
+    }
+
+    #[test]
+    fn short_touch_is_a_tap() {
+        let mut tracker = TouchTracker::default();
+        tracker.press(0, at(100.0, 50.0), 1.0);
+        // Small jitter does not turn a tap into a drag.
+        assert_eq!(tracker.drag(0, at(105.0, 52.0)), None);
+
+        assert_eq!(tracker.release(0, 1.2), Some(Gesture::Tap(at(100.0, 50.0))));
+    }
+
+    #[test]
+    fn held_touch_is_a_long_press() {
+        let mut tracker = TouchTracker::default();
+        tracker.long_press_duration = 0.8;
+        tracker.press(0, at(10.0, 10.0), 2.0);
+
+        assert_eq!(
+            tracker.release(0, 2.9),
+            Some(Gesture::LongPress(at(10.0, 10.0)))
+        );
+    }
+
+    #[test]
+    fn moved_touch_drags_instead_of_clicking() {
+        let mut tracker = TouchTracker::default();
+        tracker.press(0, at(0.0, 0.0), 0.0);
+
+        assert_eq!(tracker.drag(0, at(10.0, 0.0)), None);
+        assert_eq!(
+            tracker.drag(0, at(25.0, 0.0)),
+            Some(Gesture::Drag(at(15.0, 0.0)))
+        );
+        // Once dragging, even small moves pan.
+        assert_eq!(
+            tracker.drag(0, at(20.0, 0.0)),
+            Some(Gesture::Drag(at(-5.0, 0.0)))
+        );
+        assert_eq!(tracker.release(0, 1.0), None);
+    }
+
+    #[test]
+    fn two_fingers_pinch() {
+        let mut tracker = TouchTracker::default();
+        tracker.press(0, at(0.0, 0.0), 0.0);
+        tracker.press(1, at(100.0, 0.0), 0.1);
+
+        assert_eq!(
+            tracker.drag(1, at(200.0, 0.0)),
+            Some(Gesture::Pinch {
+                factor: 2.0,
+                centre: at(100.0, 0.0)
+            })
+        );
+        assert_eq!(tracker.release(1, 0.5), None);
+        assert_eq!(tracker.release(0, 0.6), None);
+    }
+}
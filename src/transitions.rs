@@ -0,0 +1,150 @@
+//! The transitions the state machine may make. `set_state` refuses any transition that is not in
+//! the table and keeps the old state, `force_state` skips the check for the paths that recover
+//! from a broken state: loading a game, starting a new one, panics and entities that vanished.
+
+use crate::game_state::State;
+
+/// Where a state that takes orders can go.
+const FROM_ORDERS: &[&str] = &[
+    "Waiting",
+    "Selected",
+    "Targeting",
+    "GroupSelected",
+    "UnitTurn",
+    "Attacking",
+    "Moving",
+    "UsingAbility",
+    "EnteringOverwatch",
+    "AttackingEdge",
+    "Garrisoning",
+    "Ungarrisoning",
+    "AttackingBuilding",
+    "Turning",
+    "NewRound",
+    "TurnTransition",
+    "Planning",
+    "GameOver",
+];
+
+/// Where a state carrying out an order can go. Only a move goes on, as a move.
+const FROM_ACTION: &[&str] = &["Waiting", "Selected", "GameOver"];
+
+/// The states every state may change to, by the names of the states.
+pub const TRANSITIONS: &[(&str, &[&str])] = &[
+    ("Loading", &["Startup"]),
+    ("Startup", &["Waiting", "Planning", "GameOver"]),
+    (
+        "NewRound",
+        &[
+            "NewRound",
+            "Waiting",
+            "UnitTurn",
+            "TurnTransition",
+            "GameOver",
+        ],
+    ),
+    ("Waiting", FROM_ORDERS),
+    ("Selected", FROM_ORDERS),
+    ("Targeting", FROM_ORDERS),
+    ("GroupSelected", FROM_ORDERS),
+    ("UnitTurn", FROM_ORDERS),
+    (
+        "Attacking",
+        &["Waiting", "Selected", "ChoosingPromotion", "GameOver"],
+    ),
+    ("Moving", &["Waiting", "Selected", "Moving", "GameOver"]),
+    ("UsingAbility", FROM_ACTION),
+    ("EnteringOverwatch", FROM_ACTION),
+    ("AttackingEdge", FROM_ACTION),
+    ("Garrisoning", FROM_ACTION),
+    ("Ungarrisoning", FROM_ACTION),
+    ("AttackingBuilding", FROM_ACTION),
+    ("Turning", FROM_ACTION),
+    ("ChoosingPromotion", &["Promoting", "GameOver"]),
+    ("Promoting", FROM_ACTION),
+    ("Planning", &["Resolving", "GameOver"]),
+    // The attacks of a resolution select their attackers in between.
+    (
+        "Resolving",
+        &["Waiting", "Selected", "Planning", "GameOver"],
+    ),
+    ("TurnTransition", &["Waiting", "GameOver"]),
+    ("GameOver", &[]),
+];
+
+/// Whether the state machine may go from `from` to `to`.
+pub fn is_allowed(from: &State, to: &State) -> bool {
+    TRANSITIONS
+        .iter()
+        .find(|(name, _)| *name == from.name())
+        .map_or(false, |(_, targets)| targets.contains(&to.name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::game_state::{force_state, set_state, GameState};
+    use legion::World;
+    use std::collections::HashSet;
+
+    #[test]
+    fn the_table_covers_every_state_once() {
+        let names: Vec<&str> = TRANSITIONS.iter().map(|(name, _)| *name).collect();
+        let unique: HashSet<&str> = names.iter().copied().collect();
+        assert_eq!(names.len(), 23);
+        assert_eq!(unique.len(), names.len());
+        for (from, targets) in TRANSITIONS {
+            for target in targets.iter() {
+                assert!(unique.contains(target), "{} -> {}", from, target);
+            }
+        }
+        // Only the end of the game is final.
+        for (from, targets) in TRANSITIONS {
+            assert_eq!(targets.is_empty(), *from == "GameOver");
+        }
+    }
+
+    #[test]
+    fn forbidden_transitions_keep_the_old_state() {
+        let mut world = World::default();
+        let unit = world.push((Hexagon::zero(),));
+        let other = world.push((Hexagon::new_axial(1, 0),));
+        let moving = State::Moving(unit);
+        let forbidden = vec![
+            (moving.clone(), State::Attacking(unit, other)),
+            (moving, State::UsingAbility(unit, 0, Hexagon::zero())),
+            (State::Planning, State::Waiting),
+            (State::Loading, State::Selected(unit)),
+            (State::GameOver(None), State::Waiting),
+            (State::ChoosingPromotion(unit, Vec::new()), State::Waiting),
+            (State::TurnTransition(1.0), State::Selected(unit)),
+        ];
+        for (from, to) in forbidden {
+            let mut state = GameState::new();
+            force_state(&mut state, from.clone());
+            state.state_changes.clear();
+
+            assert!(!is_allowed(&from, &to));
+            assert!(!set_state(&mut state, to));
+            assert_eq!(state.state, from);
+            assert!(state.state_changes.is_empty());
+        }
+
+        let mut state = GameState::new();
+        assert!(set_state(&mut state, State::Waiting));
+        assert!(set_state(&mut state, State::Selected(unit)));
+        assert!(set_state(&mut state, State::Moving(unit)));
+        assert_eq!(
+            state.state_changes,
+            vec![
+                ("Startup", "Waiting"),
+                ("Waiting", "Selected"),
+                ("Selected", "Moving")
+            ]
+        );
+        // Recovery may go anywhere.
+        force_state(&mut state, State::Loading);
+        assert_eq!(state.state, State::Loading);
+    }
+}
@@ -0,0 +1,513 @@
+//! Triggers of scenarios. Every trigger has a condition checked once per frame against the events
+//! of the frame and the state of the game, and actions that run when the condition is met.
+//! Triggers fire once, unless they are marked as repeating.
+
+use crate::components::hexagon::Hexagon;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::terrain::Terrain;
+use crate::components::unit::Unit;
+use crate::components::unit_name::UnitName;
+use crate::components::unit_type::UnitType;
+use crate::editor;
+use crate::game_state::{set_state, GameState, State};
+use crate::handicap::apply_integrity_bonus;
+use crate::player::Player;
+use crate::scenario::ScenarioUnit;
+use crate::spawn::spawn_unit_of_type;
+use crate::state_machine::GameEvent;
+use crate::systems::hexgrid::is_occupied_by_unit;
+use gdnative::prelude::*;
+use legion::{Entity, EntityStore, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TriggerCondition {
+    /// A unit moves onto `hexagon`. Only units of `player` count if it is given.
+    UnitEntersHex {
+        #[serde(default)]
+        player: Option<usize>,
+        hexagon: Hexagon,
+    },
+    /// A round from `round` on starts.
+    RoundReached { round: u32 },
+    /// A unit created from the catalog entry `unit_type` is destroyed. Only units of `player`
+    /// count if it is given.
+    UnitDestroyedOfType {
+        unit_type: String,
+        #[serde(default)]
+        player: Option<usize>,
+    },
+    /// The resources of `player` reach `amount`. Fires again only after they dropped below.
+    PlayerResourcesAtLeast { player: usize, amount: i32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TriggerAction {
+    /// Spawns units from the catalog. Units on occupied hexagons are left out.
+    SpawnUnits {
+        units: Vec<ScenarioUnit>,
+    },
+    /// Emits the "scenario_event" signal with `name` and `payload`, for cutscenes and dialogue.
+    EmitSignal {
+        name: String,
+        #[serde(default)]
+        payload: Value,
+    },
+    SetTerrain {
+        position: Hexagon,
+        terrain: Terrain,
+    },
+    /// Ends the game, without a winner if `winner` is not given.
+    EndGame {
+        #[serde(default)]
+        winner: Option<usize>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trigger {
+    pub condition: TriggerCondition,
+    pub actions: Vec<TriggerAction>,
+    #[serde(default)]
+    pub repeating: bool,
+}
+
+/// What an `EmitSignal` action sends to the scripts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScenarioEvent {
+    pub name: String,
+    pub payload: Value,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct TriggerState {
+    fired: bool,
+    /// Whether the condition held at the last check, so lasting conditions fire when they start
+    /// to hold instead of every frame.
+    holding: bool,
+}
+
+/// The triggers of the loaded scenario and which of them fired. It is saved with the game, so a
+/// loaded match does not fire anything a second time.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+    states: Vec<TriggerState>,
+    /// Round of the last check.
+    round: u32,
+    /// Owner and type of the units at the last check, as destroyed units are gone from the world
+    /// once their event is checked.
+    #[serde(skip)]
+    units: HashMap<Entity, (usize, Option<String>)>,
+    #[serde(skip)]
+    unannounced: Vec<ScenarioEvent>,
+}
+
+impl Triggers {
+    pub fn new(triggers: Vec<Trigger>) -> Self {
+        Triggers {
+            states: vec![TriggerState::default(); triggers.len()],
+            triggers,
+            ..Triggers::default()
+        }
+    }
+
+    pub fn definitions(&self) -> &[Trigger] {
+        &self.triggers
+    }
+
+    /// Checks the triggers against the events of one frame. `world` is the state after the
+    /// frame. Returns the actions of the triggers that fired, in the order of the triggers.
+    pub fn evaluate<S: EntityStore>(
+        &mut self,
+        world: &S,
+        players: &[Player],
+        round: u32,
+        events: &[GameEvent],
+    ) -> Vec<TriggerAction> {
+        let (units, last_round) = (&self.units, self.round);
+        let mut actions = Vec::new();
+        for (trigger, state) in self.triggers.iter().zip(self.states.iter_mut()) {
+            let met = match &trigger.condition {
+                TriggerCondition::UnitEntersHex { player, hexagon } => {
+                    events.iter().any(|event| match *event {
+                        GameEvent::UnitMoved { entity, to, .. } if to == *hexagon => {
+                            let owner = world
+                                .entry_ref(entity)
+                                .ok()
+                                .and_then(|entry| {
+                                    entry
+                                        .get_component::<PlayerComponent>()
+                                        .ok()
+                                        .map(|owner| owner.0)
+                                })
+                                .or_else(|| units.get(&entity).map(|(owner, _)| *owner));
+                            player.map_or(true, |player| owner == Some(player))
+                        }
+                        _ => false,
+                    })
+                }
+                TriggerCondition::RoundReached { round: first } => {
+                    round != last_round && round >= *first
+                }
+                TriggerCondition::UnitDestroyedOfType { unit_type, player } => {
+                    events.iter().any(|event| match event {
+                        GameEvent::UnitDestroyed { entity, .. } => match units.get(entity) {
+                            Some((owner, Some(destroyed_type))) => {
+                                destroyed_type == unit_type
+                                    && player.map_or(true, |player| *owner == player)
+                            }
+                            _ => false,
+                        },
+                        _ => false,
+                    })
+                }
+                TriggerCondition::PlayerResourcesAtLeast { player, amount } => {
+                    let holds = players
+                        .get(*player)
+                        .map_or(false, |player| player.get_resources() >= *amount);
+                    let started = holds && !state.holding;
+                    state.holding = holds;
+                    started
+                }
+            };
+            if met && (trigger.repeating || !state.fired) {
+                state.fired = true;
+                actions.extend(trigger.actions.iter().cloned());
+            }
+        }
+        self.round = round;
+        self.units = <(Entity, &PlayerComponent, &Unit, Option<&UnitType>)>::query()
+            .iter(world)
+            .map(|(entity, owner, _, unit_type)| {
+                (
+                    *entity,
+                    (owner.0, unit_type.map(|unit_type| unit_type.0.clone())),
+                )
+            })
+            .collect();
+        actions
+    }
+
+    /// Returns the scenario events since the last call, so they can be emitted as signals.
+    pub fn take_unannounced(&mut self) -> Vec<ScenarioEvent> {
+        std::mem::take(&mut self.unannounced)
+    }
+}
+
+/// Carries out the actions of fired triggers. Ending the game is reported in `events`.
+pub fn apply_trigger_actions(
+    world: &mut World,
+    state: &mut GameState,
+    actions: Vec<TriggerAction>,
+    events: &mut Vec<GameEvent>,
+) {
+    for action in actions {
+        match action {
+            TriggerAction::SpawnUnits { units } => {
+                for unit in units {
+                    if is_occupied_by_unit(&unit.position, world) {
+                        log_warn!(
+                            "Trigger could not spawn {} on the occupied hexagon {}",
+                            unit.unit_type,
+                            unit.position
+                        );
+                        continue;
+                    }
+                    match spawn_unit_of_type(
+                        world,
+                        &mut state.persistent_ids,
+                        &state.unit_catalog,
+                        unit.player,
+                        unit.position,
+                        &unit.unit_type,
+                        state.spawn_nodes,
+                    ) {
+                        None => log_warn!("Unit type {} is not in the catalog", unit.unit_type),
+                        Some(entity) => {
+                            state.influence.mark_dirty();
+                            if let (Some(name), Some(mut entry)) = (&unit.name, world.entry(entity))
+                            {
+                                entry.add_component(UnitName(name.clone()));
+                            }
+                            if let Some(player) = state.players.get(unit.player) {
+                                apply_integrity_bonus(world, entity, &player.get_handicap());
+                            }
+                        }
+                    }
+                }
+            }
+            TriggerAction::EmitSignal { name, payload } => state
+                .triggers
+                .unannounced
+                .push(ScenarioEvent { name, payload }),
+            TriggerAction::SetTerrain { position, terrain } => {
+                if editor::set_terrain(world, &position, terrain) {
+                    state.redraw_grid = true;
+                } else {
+                    log_warn!("Trigger could not set the terrain of {}", position);
+                }
+            }
+            TriggerAction::EndGame { winner } => {
+                if !matches!(state.state, State::GameOver(_)) {
+                    events.push(GameEvent::GameOver { winner });
+                    set_state(state, State::GameOver(winner));
+                }
+            }
+        }
+    }
+}
+
+/// Converts a payload of a scenario event for the scripts.
+pub fn json_to_variant(value: &Value) -> Variant {
+    match value {
+        Value::Null => Variant::new(),
+        Value::Bool(value) => value.to_variant(),
+        Value::Number(number) => match number.as_i64() {
+            Some(integer) => integer.to_variant(),
+            None => number.as_f64().unwrap_or_default().to_variant(),
+        },
+        Value::String(text) => text.to_variant(),
+        Value::Array(values) => {
+            let array = VariantArray::new();
+            for value in values {
+                array.push(json_to_variant(value));
+            }
+            array.owned_to_variant()
+        }
+        Value::Object(map) => {
+            let dict = Dictionary::new();
+            for (key, value) in map {
+                dict.insert(key.as_str(), json_to_variant(value));
+            }
+            dict.owned_to_variant()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::field::Field;
+    use crate::scenario::Scenario;
+    use gdnative::core_types::Color;
+
+    fn players() -> Vec<Player> {
+        vec![
+            Player::new("Player 1".to_owned(), Color::rgb(0.0, 0.0, 1.0)),
+            Player::new("Player 2".to_owned(), Color::rgb(1.0, 0.0, 0.0)),
+        ]
+    }
+
+    fn spawn(world: &mut World, player: usize, q: i32, unit_type: &str) -> Entity {
+        world.push((
+            PlayerComponent(player),
+            Hexagon::new_axial(q, 0),
+            Unit::new(5, 1, 1, 1, 0, 3, 3, 1),
+            UnitType(unit_type.to_owned()),
+        ))
+    }
+
+    fn end_game(winner: usize) -> Vec<TriggerAction> {
+        vec![TriggerAction::EndGame {
+            winner: Some(winner),
+        }]
+    }
+
+    #[test]
+    fn unit_entering_a_hexagon_fires_once() {
+        let mut world = World::default();
+        let scout = spawn(&mut world, 0, 0, "Scout");
+        let enemy = spawn(&mut world, 1, 4, "Scout");
+        let goal = Hexagon::new_axial(5, -2);
+        let mut triggers = Triggers::new(vec![Trigger {
+            condition: TriggerCondition::UnitEntersHex {
+                player: Some(0),
+                hexagon: goal,
+            },
+            actions: end_game(0),
+            repeating: false,
+        }]);
+        let moved = |entity| GameEvent::UnitMoved {
+            entity,
+            from: Hexagon::new_axial(4, -1),
+            to: goal,
+        };
+
+        assert!(triggers
+            .evaluate(&world, &players(), 1, &[moved(enemy)])
+            .is_empty());
+        assert_eq!(
+            triggers.evaluate(&world, &players(), 1, &[moved(scout)]),
+            end_game(0)
+        );
+        assert!(triggers
+            .evaluate(&world, &players(), 1, &[moved(scout)])
+            .is_empty());
+    }
+
+    #[test]
+    fn round_reached_fires_when_the_round_starts() {
+        let world = World::default();
+        let mut triggers = Triggers::new(vec![
+            Trigger {
+                condition: TriggerCondition::RoundReached { round: 2 },
+                actions: end_game(0),
+                repeating: false,
+            },
+            Trigger {
+                condition: TriggerCondition::RoundReached { round: 2 },
+                actions: end_game(1),
+                repeating: true,
+            },
+        ]);
+
+        assert!(triggers.evaluate(&world, &players(), 1, &[]).is_empty());
+        let fired = triggers.evaluate(&world, &players(), 2, &[]);
+        assert_eq!(fired, [end_game(0), end_game(1)].concat());
+        // Nothing fires again during the round, only the repeating one in the next round.
+        assert!(triggers.evaluate(&world, &players(), 2, &[]).is_empty());
+        assert_eq!(triggers.evaluate(&world, &players(), 3, &[]), end_game(1));
+    }
+
+    #[test]
+    fn destroyed_units_are_matched_by_type_and_owner() {
+        let mut world = World::default();
+        let scout = spawn(&mut world, 1, 0, "Scout");
+        let tank = spawn(&mut world, 1, 1, "Tank");
+        let own_tank = spawn(&mut world, 0, 2, "Tank");
+        let mut triggers = Triggers::new(vec![Trigger {
+            condition: TriggerCondition::UnitDestroyedOfType {
+                unit_type: "Tank".to_owned(),
+                player: Some(1),
+            },
+            actions: end_game(0),
+            repeating: true,
+        }]);
+        triggers.evaluate(&world, &players(), 1, &[]);
+        let destroyed = |entity| GameEvent::UnitDestroyed {
+            entity,
+            position: Hexagon::zero(),
+        };
+
+        world.remove(scout);
+        world.remove(own_tank);
+        assert!(triggers
+            .evaluate(
+                &world,
+                &players(),
+                1,
+                &[destroyed(scout), destroyed(own_tank)]
+            )
+            .is_empty());
+        world.remove(tank);
+        assert_eq!(
+            triggers.evaluate(&world, &players(), 1, &[destroyed(tank)]),
+            end_game(0)
+        );
+    }
+
+    #[test]
+    fn resources_fire_when_they_reach_the_amount() {
+        let world = World::default();
+        let mut players = players();
+        let mut triggers = Triggers::new(vec![Trigger {
+            condition: TriggerCondition::PlayerResourcesAtLeast {
+                player: 1,
+                amount: 100,
+            },
+            actions: end_game(1),
+            repeating: true,
+        }]);
+
+        players[1].set_resources(99);
+        assert!(triggers.evaluate(&world, &players, 1, &[]).is_empty());
+        players[1].set_resources(120);
+        assert_eq!(triggers.evaluate(&world, &players, 1, &[]), end_game(1));
+        assert!(triggers.evaluate(&world, &players, 1, &[]).is_empty());
+        players[1].set_resources(50);
+        triggers.evaluate(&world, &players, 1, &[]);
+        players[1].set_resources(100);
+        assert_eq!(triggers.evaluate(&world, &players, 1, &[]), end_game(1));
+    }
+
+    #[test]
+    fn actions_spawn_units_change_terrain_and_end_the_game() {
+        let mut world = World::default();
+        let mut state = GameState::new();
+        state.players = players();
+        state.state = State::Waiting;
+        world.push((Field::new(Hexagon::new_axial(1, 0)),));
+        spawn(&mut world, 0, 3, "Tank");
+        let actions = vec![
+            TriggerAction::SpawnUnits {
+                units: vec![
+                    ScenarioUnit {
+                        player: 1,
+                        position: Hexagon::new_axial(2, 0),
+                        unit_type: "Tank".to_owned(),
+                        name: Some("Reinforcement".to_owned()),
+                        from_roster: false,
+                    },
+                    ScenarioUnit {
+                        player: 1,
+                        position: Hexagon::new_axial(3, 0),
+                        unit_type: "Tank".to_owned(),
+                        name: None,
+                        from_roster: false,
+                    },
+                ],
+            },
+            TriggerAction::SetTerrain {
+                position: Hexagon::new_axial(1, 0),
+                terrain: Terrain::Forest,
+            },
+            TriggerAction::EmitSignal {
+                name: "reinforcements".to_owned(),
+                payload: serde_json::json!({"speaker": "HQ"}),
+            },
+            TriggerAction::EndGame { winner: None },
+        ];
+        let mut events = Vec::new();
+
+        apply_trigger_actions(&mut world, &mut state, actions, &mut events);
+
+        let scenario = Scenario::from_world(&world);
+        // Only the spawned unit has a persistent id, the one it was blocked by is left out.
+        assert_eq!(scenario.units.len(), 1);
+        assert_eq!(scenario.units[0].position, Hexagon::new_axial(2, 0));
+        assert_eq!(scenario.fields[0].terrain, Terrain::Forest);
+        let names: Vec<&UnitName> = <&UnitName>::query().iter(&world).collect();
+        assert_eq!(names, vec![&UnitName("Reinforcement".to_owned())]);
+        assert_eq!(state.triggers.take_unannounced()[0].name, "reinforcements");
+        assert_eq!(events, vec![GameEvent::GameOver { winner: None }]);
+        assert_eq!(state.state, State::GameOver(None));
+    }
+
+    #[test]
+    fn triggers_are_read_from_scenarios() {
+        let json = r#"{
+            "fields": [],
+            "units": [],
+            "triggers": [{
+                "condition": {"type": "UnitEntersHex", "player": 0, "hexagon": {"q": 5, "r": -2, "s": -3}},
+                "actions": [{"type": "EmitSignal", "name": "dialogue", "payload": {"line": 3}}]
+            }]
+        }"#;
+        let scenario = Scenario::from_json(json).unwrap();
+
+        assert_eq!(scenario.triggers.len(), 1);
+        assert!(!scenario.triggers[0].repeating);
+        assert_eq!(
+            scenario.triggers[0].condition,
+            TriggerCondition::UnitEntersHex {
+                player: Some(0),
+                hexagon: Hexagon::new_axial(5, -2),
+            }
+        );
+    }
+}
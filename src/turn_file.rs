@@ -0,0 +1,427 @@
+//! Turn files for play-by-email. A player exports the commands of their turn together with the
+//! checksum of the state the turn started from, the opponent imports them once their game is in
+//! that same state. The commands are tried out on a copy of the game first, so a file that does
+//! not fit the local game or was tampered with is rejected before anything changes.
+
+use crate::action_log::{is_settled, run_until_settled, scratch_game};
+use crate::commands::{apply_command, Command};
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::PersistentId;
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::game_state::GameState;
+use crate::savegame::SaveData;
+use gdnative::prelude::*;
+use legion::{EntityStore, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitSummary {
+    pub id: PersistentId,
+    pub player: usize,
+    pub position: Hexagon,
+    pub integrity: i32,
+}
+
+/// What the players see of a game, to tell them where two games went apart when their checksums
+/// do not match.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateSummary {
+    pub round: u32,
+    pub current_player: Option<usize>,
+    /// Sorted by their ids.
+    pub units: Vec<UnitSummary>,
+}
+
+impl StateSummary {
+    pub fn from_game<S: EntityStore>(world: &S, state: &GameState) -> Self {
+        let mut units: Vec<UnitSummary> =
+            <(&PersistentId, &PlayerComponent, &Hexagon, &Unit)>::query()
+                .iter(world)
+                .map(|(id, player, position, unit)| UnitSummary {
+                    id: *id,
+                    player: player.0,
+                    position: *position,
+                    integrity: unit.integrity,
+                })
+                .collect();
+        units.sort_by_key(|unit| unit.id);
+        StateSummary {
+            round: state.round,
+            current_player: state.current_player,
+            units,
+        }
+    }
+
+    /// Describes how `theirs` differs from this summary, one line per difference.
+    pub fn differences(&self, theirs: &StateSummary) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.round != theirs.round {
+            differences.push(format!(
+                "Round {} here, {} in the file",
+                self.round, theirs.round
+            ));
+        }
+        if self.current_player != theirs.current_player {
+            differences.push(format!(
+                "Current player {:?} here, {:?} in the file",
+                self.current_player, theirs.current_player
+            ));
+        }
+        let by_id = |units: &[UnitSummary]| -> BTreeMap<PersistentId, UnitSummary> {
+            units.iter().map(|unit| (unit.id, unit.clone())).collect()
+        };
+        let (ours_by_id, theirs_by_id) = (by_id(&self.units), by_id(&theirs.units));
+        for (id, ours) in &ours_by_id {
+            match theirs_by_id.get(id) {
+                None => differences.push(format!("Unit {} is missing in the file", id.0)),
+                Some(unit) if unit != ours => differences.push(format!(
+                    "Unit {}: player {} at {} with {} integrity here, player {} at {} with {} \
+                     integrity in the file",
+                    id.0,
+                    ours.player,
+                    ours.position,
+                    ours.integrity,
+                    unit.player,
+                    unit.position,
+                    unit.integrity
+                )),
+                Some(_) => {}
+            }
+        }
+        for id in theirs_by_id.keys() {
+            if !ours_by_id.contains_key(id) {
+                differences.push(format!("Unit {} is missing here", id.0));
+            }
+        }
+        differences
+    }
+}
+
+/// The state a turn started from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TurnStart {
+    pub checksum: u64,
+    pub summary: StateSummary,
+}
+
+/// Where the game stands with the turn files.
+#[derive(Clone, Debug, Default)]
+pub struct TurnHandoff {
+    /// Logged actions before this index were exported or imported already.
+    pub handed_off: usize,
+    /// Taken when the first command after the last handoff is logged.
+    pub start: Option<TurnStart>,
+    /// Commands of an imported turn, carried out one after the other.
+    pub incoming: VecDeque<Command>,
+}
+
+/// The commands of a turn, in the representation the network uses.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TurnFile {
+    pub round: u32,
+    pub player: usize,
+    /// Seconds since the Unix epoch when the file was written.
+    pub timestamp: u64,
+    /// Checksum of the state the turn started from.
+    pub checksum: u64,
+    pub summary: StateSummary,
+    /// Checksum of the state the commands left, `None` if they were still being carried out.
+    pub result_checksum: Option<u64>,
+    pub commands: Vec<Command>,
+}
+
+impl TurnFile {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Why a turn file was not imported.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TurnRejection {
+    /// "unreadable", "busy", "checksum_mismatch", "command_rejected" or "result_mismatch".
+    pub reason: &'static str,
+    pub differences: Vec<String>,
+}
+
+impl TurnRejection {
+    fn new(reason: &'static str, differences: Vec<String>) -> Self {
+        TurnRejection {
+            reason,
+            differences,
+        }
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("reason", self.reason);
+        let differences = VariantArray::new();
+        for difference in &self.differences {
+            differences.push(difference.as_str());
+        }
+        dict.insert("differences", differences.into_shared());
+        dict
+    }
+}
+
+/// Remembers the state the turn starts from before the first command after a handoff is carried
+/// out. The selection is not part of the checksum, so it is the state the last handoff left.
+pub fn note_turn_start<S: EntityStore>(world: &S, state: &mut GameState) {
+    if state.turn_handoff.start.is_none()
+        && state.action_log.actions.len() >= state.turn_handoff.handed_off
+    {
+        state.turn_handoff.start = Some(TurnStart {
+            checksum: state.compute_checksum(world),
+            summary: StateSummary::from_game(world, state),
+        });
+    }
+}
+
+/// The commands logged since the last handoff, `None` if there are none.
+pub fn export_turn(state: &mut GameState, timestamp: u64) -> Option<TurnFile> {
+    let start = state.turn_handoff.start.as_ref()?;
+    let actions = state
+        .action_log
+        .actions
+        .get(state.turn_handoff.handed_off..)?;
+    let first = actions.first()?;
+    let file = TurnFile {
+        round: first.round,
+        player: first.player,
+        timestamp,
+        checksum: start.checksum,
+        summary: start.summary.clone(),
+        result_checksum: actions.last().and_then(|action| action.checksum),
+        commands: actions
+            .iter()
+            .map(|action| action.command.clone())
+            .collect(),
+    };
+    state.turn_handoff.handed_off = state.action_log.actions.len();
+    state.turn_handoff.start = None;
+    Some(file)
+}
+
+/// Tries the commands of `file` out on a copy of the game.
+pub fn verify_turn<S: EntityStore>(
+    world: &S,
+    state: &GameState,
+    file: &TurnFile,
+) -> Result<(), TurnRejection> {
+    if !is_settled(&state.state) || !state.turn_handoff.incoming.is_empty() {
+        return Err(TurnRejection::new("busy", Vec::new()));
+    }
+    if state.compute_checksum(world) != file.checksum {
+        let ours = StateSummary::from_game(world, state);
+        return Err(TurnRejection::new(
+            "checksum_mismatch",
+            ours.differences(&file.summary),
+        ));
+    }
+    let (mut scratch_world, mut scratch) =
+        scratch_game(world, state, &SaveData::from_game(world, state));
+    for (index, command) in file.commands.iter().enumerate() {
+        if let Err(error) = apply_command(&scratch_world, &mut scratch, command) {
+            return Err(TurnRejection::new(
+                "command_rejected",
+                vec![format!(
+                    "Command {} ({}) was rejected: {:?}",
+                    index,
+                    command.name(),
+                    error
+                )],
+            ));
+        }
+        if !run_until_settled(&mut scratch_world, &mut scratch) {
+            return Err(TurnRejection::new(
+                "command_rejected",
+                vec![format!(
+                    "Command {} ({}) was not carried out",
+                    index,
+                    command.name()
+                )],
+            ));
+        }
+    }
+    let result = scratch.compute_checksum(&scratch_world);
+    match file.result_checksum {
+        Some(expected) if expected != result => Err(TurnRejection::new(
+            "result_mismatch",
+            vec![format!(
+                "The commands lead to checksum {}, the file expects {}",
+                result, expected
+            )],
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Verifies `file` and queues its commands, `apply_incoming_command` carries them out. The
+/// commands count as handed off right away, so they are not exported again.
+pub fn import_turn<S: EntityStore>(
+    world: &S,
+    state: &mut GameState,
+    file: &TurnFile,
+) -> Result<(), TurnRejection> {
+    verify_turn(world, state, file)?;
+    state.turn_handoff.handed_off = state.action_log.actions.len() + file.commands.len();
+    state.turn_handoff.start = None;
+    state.turn_handoff.incoming = file.commands.iter().cloned().collect();
+    Ok(())
+}
+
+/// Carries out the next command of an imported turn once the last one is done.
+pub fn apply_incoming_command(world: &World, state: &mut GameState) {
+    if !is_settled(&state.state) || state.action_log.is_unsettled() {
+        return;
+    }
+    if let Some(command) = state.turn_handoff.incoming.pop_front() {
+        if let Err(error) = apply_command(world, state, &command) {
+            log_warn!("Imported command rejected: {:?}", error);
+            state.turn_handoff.incoming.clear();
+            // The rest of the turn is dropped, what was carried out stays handed off.
+            state.turn_handoff.handed_off = state.action_log.actions.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_log::{self, MAX_REPLAY_FRAMES, REPLAY_FRAME_DELTA};
+    use crate::simulation::simulate_frame;
+
+    fn game() -> (World, GameState, PersistentId) {
+        let (world, state, ids) = action_log::tests::game();
+        (world, state, ids[0])
+    }
+
+    fn run(world: &mut World, state: &mut GameState, command: Command) {
+        apply_command(world, state, &command).unwrap();
+        assert!(run_until_settled(world, state));
+    }
+
+    /// Runs frames until the imported commands are carried out.
+    fn run_incoming(world: &mut World, state: &mut GameState) {
+        for _ in 0..MAX_REPLAY_FRAMES {
+            if state.turn_handoff.incoming.is_empty() && !state.action_log.is_unsettled() {
+                return;
+            }
+            simulate_frame(world, state, REPLAY_FRAME_DELTA);
+        }
+        panic!("The imported commands were not carried out");
+    }
+
+    fn play_turn(world: &mut World, state: &mut GameState, mover: PersistentId) -> TurnFile {
+        run(
+            world,
+            state,
+            Command::Move {
+                player: 0,
+                unit: mover,
+                path: vec![Hexagon::new_axial(1, 0)],
+            },
+        );
+        run(world, state, Command::EndTurn { player: 0 });
+        export_turn(state, 1_600_000_000).unwrap()
+    }
+
+    #[test]
+    fn turns_travel_between_games() {
+        let (mut sender_world, mut sender, mover) = game();
+        let (mut receiver_world, mut receiver, _) = game();
+
+        let file = play_turn(&mut sender_world, &mut sender, mover);
+        let file = TurnFile::from_json(&file.to_json().unwrap()).unwrap();
+        assert_eq!((file.round, file.player, file.commands.len()), (1, 0, 2));
+        assert!(export_turn(&mut sender, 0).is_none());
+
+        assert_eq!(import_turn(&receiver_world, &mut receiver, &file), Ok(()));
+        run_incoming(&mut receiver_world, &mut receiver);
+        assert_eq!(
+            receiver.compute_checksum(&receiver_world),
+            sender.compute_checksum(&sender_world)
+        );
+        assert_eq!(receiver.current_player, Some(1));
+
+        // The answer only holds the commands of the receiver.
+        run(
+            &mut receiver_world,
+            &mut receiver,
+            Command::EndTurn { player: 1 },
+        );
+        let answer = export_turn(&mut receiver, 1_600_000_100).unwrap();
+        assert_eq!(answer.commands, vec![Command::EndTurn { player: 1 }]);
+        assert_eq!(import_turn(&sender_world, &mut sender, &answer), Ok(()));
+        run_incoming(&mut sender_world, &mut sender);
+        assert_eq!(
+            sender.compute_checksum(&sender_world),
+            receiver.compute_checksum(&receiver_world)
+        );
+    }
+
+    #[test]
+    fn tampered_turns_are_rejected() {
+        let (mut sender_world, mut sender, mover) = game();
+        let (receiver_world, mut receiver, _) = game();
+        let file = play_turn(&mut sender_world, &mut sender, mover);
+        let checksum = receiver.compute_checksum(&receiver_world);
+
+        let mut tampered = file.clone();
+        tampered.commands[0] = Command::Move {
+            player: 0,
+            unit: mover,
+            path: vec![Hexagon::new_axial(-1, 0)],
+        };
+        let rejection = import_turn(&receiver_world, &mut receiver, &tampered).unwrap_err();
+        assert_eq!(rejection.reason, "result_mismatch");
+        assert!(receiver.turn_handoff.incoming.is_empty());
+        assert_eq!(receiver.compute_checksum(&receiver_world), checksum);
+
+        let mut tampered = file;
+        tampered.summary.round = 2;
+        tampered.checksum ^= 1;
+        let rejection = import_turn(&receiver_world, &mut receiver, &tampered).unwrap_err();
+        assert_eq!(rejection.reason, "checksum_mismatch");
+        assert_eq!(
+            rejection.differences,
+            vec!["Round 1 here, 2 in the file".to_owned()]
+        );
+    }
+
+    #[test]
+    fn rejected_incoming_commands_are_not_counted_as_handed_off() {
+        let (mut sender_world, mut sender, mover) = game();
+        let (mut receiver_world, mut receiver, _) = game();
+        let file = play_turn(&mut sender_world, &mut sender, mover);
+        assert_eq!(import_turn(&receiver_world, &mut receiver, &file), Ok(()));
+
+        // The game changed after the turn was verified, the move no longer fits.
+        let entity = receiver
+            .persistent_ids
+            .find(&receiver_world, mover)
+            .unwrap();
+        receiver_world.remove(entity);
+        apply_incoming_command(&receiver_world, &mut receiver);
+        assert!(receiver.turn_handoff.incoming.is_empty());
+        assert_eq!(
+            receiver.turn_handoff.handed_off,
+            receiver.action_log.actions.len()
+        );
+
+        // Commands of the receiver are exported again.
+        run(
+            &mut receiver_world,
+            &mut receiver,
+            Command::EndTurn { player: 0 },
+        );
+        let answer = export_turn(&mut receiver, 1_600_000_100).unwrap();
+        assert_eq!(answer.commands, vec![Command::EndTurn { player: 0 }]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Tutorial constraints. While one is set, local input can only take the action the tutorial
+//! expects, the expected hexagon pulses on the map. The constraint clears itself once the action
+//! is done.
+
+use crate::commands::Command;
+use crate::components::hexagon::Hexagon;
+use crate::components::persistent_id::{PersistentId, PersistentIds};
+use crate::messages::{self, Message};
+use gdnative::prelude::*;
+use legion::EntityStore;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TutorialConstraint {
+    /// Select the unit on the hexagon.
+    Select(Hexagon),
+    /// Move the selected unit to the hexagon.
+    MoveTo(Hexagon),
+    /// Attack the unit on the hexagon.
+    Attack(Hexagon),
+}
+
+impl TutorialConstraint {
+    pub fn hexagon(&self) -> Hexagon {
+        match *self {
+            TutorialConstraint::Select(hexagon)
+            | TutorialConstraint::MoveTo(hexagon)
+            | TutorialConstraint::Attack(hexagon) => hexagon,
+        }
+    }
+
+    pub fn action(&self) -> &'static str {
+        match self {
+            TutorialConstraint::Select(_) => "select",
+            TutorialConstraint::MoveTo(_) => "move",
+            TutorialConstraint::Attack(_) => "attack",
+        }
+    }
+
+    /// Tells the player what the tutorial expects.
+    pub fn reason(&self) -> Message {
+        match self {
+            TutorialConstraint::Select(hexagon) => messages::tutorial_select(hexagon),
+            TutorialConstraint::MoveTo(hexagon) => messages::tutorial_move(hexagon),
+            TutorialConstraint::Attack(hexagon) => messages::tutorial_attack(hexagon),
+        }
+    }
+
+    /// Whether `command` is the expected action.
+    pub fn is_expected<S: EntityStore>(
+        &self,
+        world: &S,
+        ids: &PersistentIds,
+        command: &Command,
+    ) -> bool {
+        let at_hexagon = |id: &PersistentId| {
+            ids.find(world, *id)
+                .and_then(|entity| world.entry_ref(entity).ok())
+                .and_then(|entry| entry.get_component::<Hexagon>().ok().copied())
+                == Some(self.hexagon())
+        };
+        match (self, command) {
+            (
+                TutorialConstraint::Select(_),
+                Command::Select {
+                    unit: Some(unit), ..
+                },
+            ) => at_hexagon(unit),
+            (TutorialConstraint::MoveTo(hexagon), Command::Move { path, .. }) => {
+                path.last() == Some(hexagon)
+            }
+            (TutorialConstraint::Attack(_), Command::Attack { defender, .. }) => {
+                at_hexagon(defender)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `command` may be issued while the tutorial waits for its action. Moves and
+    /// attacks need a selected unit, so selections are allowed for them. Pings never change
+    /// the game and are always allowed.
+    pub fn allows<S: EntityStore>(
+        &self,
+        world: &S,
+        ids: &PersistentIds,
+        command: &Command,
+    ) -> bool {
+        match command {
+            Command::Ping { .. } => true,
+            // The game waits for the pick, blocking it would stall the tutorial.
+            Command::ChoosePromotion { .. } => true,
+            Command::Select { .. } if !matches!(self, TutorialConstraint::Select(_)) => true,
+            _ => self.is_expected(world, ids, command),
+        }
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary<Unique> {
+        let dict = Dictionary::new();
+        dict.insert("action", self.action());
+        dict.insert("q", self.hexagon().get_q());
+        dict.insert("r", self.hexagon().get_r());
+        dict
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Tutorial {
+    constraint: Option<TutorialConstraint>,
+    /// Set once the expected action was accepted. The step is done when it resolved.
+    accepted: bool,
+    /// Seconds since the constraint was set, for the pulse of its overlay.
+    pub elapsed: f64,
+    completed: Vec<TutorialConstraint>,
+}
+
+impl Tutorial {
+    pub fn constraint(&self) -> Option<TutorialConstraint> {
+        self.constraint
+    }
+
+    /// Replaces the constraint, `None` lifts it.
+    pub fn set_constraint(&mut self, constraint: Option<TutorialConstraint>) {
+        self.constraint = constraint;
+        self.accepted = false;
+        self.elapsed = 0.0;
+    }
+
+    /// Records that the expected action was accepted.
+    pub fn accept(&mut self) {
+        self.accepted = self.constraint.is_some();
+    }
+
+    /// Finishes the step once its action resolved.
+    pub fn update(&mut self, delta: f64, resolving: bool) {
+        self.elapsed += delta;
+        if self.accepted && !resolving {
+            if let Some(constraint) = self.constraint.take() {
+                self.completed.push(constraint);
+            }
+            self.accepted = false;
+        }
+    }
+
+    /// Returns the steps done since the last call, so they can be announced.
+    pub fn take_completed(&mut self) -> Vec<TutorialConstraint> {
+        std::mem::take(&mut self.completed)
+    }
+}
@@ -0,0 +1,381 @@
+//! Unit definitions loaded from a JSON data file instead of being compiled in.
+
+use crate::components::abilities::{Abilities, AbilityInstance, AbilityKind};
+use crate::components::movement_type::MovementType;
+use crate::components::node_template::{NodeTemplate, UNIT_CONTAINER, UNIT_GROUP};
+use crate::components::unit::{fires_after_moving, Unit};
+use crate::components::unit_nodes::UnitNodePaths;
+use crate::components::unit_type::UnitType;
+use legion::{IntoQuery, World};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const DEFAULT_UNIT_CATALOG: &str = include_str!("../rules/units.json");
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnitDefinition {
+    pub name: String,
+    pub integrity: i32,
+    pub damage: i32,
+    pub max_attack_range: i32,
+    pub min_attack_range: i32,
+    pub armor: i32,
+    pub mobility: i32,
+    /// Units with a higher initiative act first in the initiative rules.
+    #[serde(default)]
+    pub initiative: i32,
+    pub scene: String,
+    #[serde(default)]
+    pub cost: i32,
+    /// "ground", "tracked" or "wheeled", units without one move on the ground.
+    #[serde(default)]
+    pub movement_type: String,
+    /// Whether the unit can attack bridges.
+    #[serde(default)]
+    pub demolition: bool,
+    /// Whether the model of the unit turns with its facing.
+    #[serde(default)]
+    pub rotatable: bool,
+    /// Whether the unit may attack in a round it moved in, artillery that sets up first may not.
+    #[serde(default = "fires_after_moving")]
+    pub fire_after_move: bool,
+    #[serde(default)]
+    pub weapons: Vec<String>,
+    /// Names of the active abilities, like "sprint".
+    #[serde(default)]
+    pub abilities: Vec<String>,
+    /// Scale of the unit node, 1 if missing.
+    #[serde(default)]
+    pub scale: Option<f32>,
+    #[serde(default)]
+    pub rotation_degrees: f32,
+    /// Groups the unit node is added to besides "units".
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Where the scene keeps the nodes the units system fills in, if not at the default paths.
+    #[serde(default)]
+    pub node_paths: Option<UnitNodePaths>,
+}
+
+impl UnitDefinition {
+    pub fn validate(&self) -> Result<(), String> {
+        let non_negative = [
+            ("integrity", self.integrity),
+            ("damage", self.damage),
+            ("max_attack_range", self.max_attack_range),
+            ("min_attack_range", self.min_attack_range),
+            ("armor", self.armor),
+            ("mobility", self.mobility),
+            ("cost", self.cost),
+        ];
+        for (field, value) in non_negative.iter() {
+            if *value < 0 {
+                return Err(format!("'{}' must not be negative", field));
+            }
+        }
+        if self.min_attack_range > self.max_attack_range {
+            return Err("'min_attack_range' must not be above 'max_attack_range'".to_owned());
+        }
+        if let Some(name) = self
+            .abilities
+            .iter()
+            .find(|name| AbilityKind::from_name(name).is_none())
+        {
+            return Err(format!("Unknown ability '{}'", name));
+        }
+        if self.scale.map_or(false, |scale| scale <= 0.0) {
+            return Err("'scale' must be positive".to_owned());
+        }
+        if MovementType::from_name(&self.movement_type).is_none() {
+            return Err(format!("Unknown movement type '{}'", self.movement_type));
+        }
+        Ok(())
+    }
+
+    /// Creates a fresh unit that can move and attack this round.
+    pub fn create_unit(&self) -> Unit {
+        let mut unit = Unit::new(
+            self.integrity,
+            self.damage,
+            self.max_attack_range,
+            self.min_attack_range,
+            self.armor,
+            self.mobility,
+            self.mobility,
+            1,
+        );
+        unit.fire_after_move = self.fire_after_move;
+        unit
+    }
+
+    pub fn movement(&self) -> MovementType {
+        MovementType::from_name(&self.movement_type).unwrap_or_default()
+    }
+
+    /// The abilities of a fresh unit, none of them cooling down.
+    pub fn create_abilities(&self) -> Abilities {
+        Abilities(
+            self.abilities
+                .iter()
+                .filter_map(|name| AbilityKind::from_name(name))
+                .map(AbilityInstance::new)
+                .collect(),
+        )
+    }
+
+    pub fn template(&self) -> NodeTemplate {
+        let scale = self.scale.unwrap_or(1.0);
+        let template = NodeTemplate::new(&self.scene)
+            .scale(scale, scale)
+            .z_index(1)
+            .rotation_degrees(self.rotation_degrees)
+            // Hidden until the units system decided whether fog covers the unit.
+            .visible(false)
+            .group(UNIT_GROUP)
+            .parent(UNIT_CONTAINER)
+            .y_sort();
+        self.groups
+            .iter()
+            .fold(template, |template, group| template.group(group))
+    }
+}
+
+/// An entry of the catalog file that was skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CatalogEntryError {
+    pub index: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnitCatalog {
+    definitions: Vec<UnitDefinition>,
+}
+
+impl UnitCatalog {
+    /// Parses a JSON array of unit definitions. Malformed or invalid entries are skipped and
+    /// reported, only a file that is not an array at all is an error.
+    pub fn from_json(
+        text: &str,
+    ) -> Result<(UnitCatalog, Vec<CatalogEntryError>), serde_json::Error> {
+        let entries: Vec<Value> = serde_json::from_str(text)?;
+        let mut catalog = UnitCatalog::default();
+        let mut errors = Vec::new();
+        for (index, entry) in entries.into_iter().enumerate() {
+            let result = serde_json::from_value::<UnitDefinition>(entry)
+                .map_err(|error| error.to_string())
+                .and_then(|definition| {
+                    definition.validate()?;
+                    if catalog.get(&definition.name).is_some() {
+                        return Err(format!("'{}' is defined more than once", definition.name));
+                    }
+                    Ok(definition)
+                });
+            match result {
+                Err(message) => errors.push(CatalogEntryError { index, message }),
+                Ok(definition) => catalog.definitions.push(definition),
+            }
+        }
+        Ok((catalog, errors))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&UnitDefinition> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.name == name)
+    }
+
+    pub fn definitions(&self) -> &[UnitDefinition] {
+        &self.definitions
+    }
+}
+
+/// Applies changed base stats of `new` to every unit created from `old`. Integrity is scaled, so
+/// a unit keeps the same share of damage it had taken, remaining range is capped at the new
+/// mobility.
+pub fn apply_catalog_changes(world: &mut World, old: &UnitCatalog, new: &UnitCatalog) {
+    for (unit_type, unit) in <(&UnitType, &mut Unit)>::query().iter_mut(world) {
+        let (old_definition, new_definition) = match (old.get(&unit_type.0), new.get(&unit_type.0))
+        {
+            (Some(old_definition), Some(new_definition)) => (old_definition, new_definition),
+            _ => continue,
+        };
+        if old_definition == new_definition {
+            continue;
+        }
+        if old_definition.integrity > 0 {
+            unit.integrity = (i64::from(unit.integrity) * i64::from(new_definition.integrity)
+                / i64::from(old_definition.integrity)) as i32;
+        }
+        unit.damage = new_definition.damage;
+        unit.max_attack_range = new_definition.max_attack_range;
+        unit.min_attack_range = new_definition.min_attack_range;
+        unit.armor = new_definition.armor;
+        unit.mobility = new_definition.mobility;
+        unit.fire_after_move = new_definition.fire_after_move;
+        unit.remaining_range = unit.remaining_range.min(new_definition.mobility);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::components::persistent_id::PersistentIds;
+    use crate::spawn::spawn_unit_of_type;
+
+    const FIXTURE: &str = include_str!("../fixtures/unit_catalog.json");
+
+    fn default_catalog() -> UnitCatalog {
+        let (catalog, errors) = UnitCatalog::from_json(DEFAULT_UNIT_CATALOG).unwrap();
+        assert!(errors.is_empty());
+        catalog
+    }
+
+    #[test]
+    fn fixture_skips_malformed_entries() {
+        let (catalog, errors) = UnitCatalog::from_json(FIXTURE).unwrap();
+
+        let names: Vec<&str> = catalog
+            .definitions()
+            .iter()
+            .map(|definition| definition.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Scout", "Tank"]);
+        let scout = catalog.get("Scout").unwrap();
+        assert_eq!(scout.mobility, 6);
+        assert_eq!(scout.weapons, vec!["machine_gun".to_owned()]);
+
+        let indices: Vec<usize> = errors.iter().map(|error| error.index).collect();
+        assert_eq!(indices, vec![1, 2]);
+        assert_eq!(
+            errors[1].message,
+            "'min_attack_range' must not be above 'max_attack_range'"
+        );
+    }
+
+    #[test]
+    fn unknown_abilities_are_rejected() {
+        let text = r#"[{"name": "A", "integrity": 1, "damage": 1, "max_attack_range": 1,
+            "min_attack_range": 1, "armor": 0, "mobility": 1, "scene": "",
+            "abilities": ["sprint", "teleport"]}]"#;
+        let (catalog, errors) = UnitCatalog::from_json(text).unwrap();
+
+        assert!(catalog.definitions().is_empty());
+        assert_eq!(errors[0].message, "Unknown ability 'teleport'");
+        assert_eq!(
+            default_catalog().get("Tank").unwrap().create_abilities(),
+            Abilities(vec![
+                AbilityInstance::new(AbilityKind::Sprint),
+                AbilityInstance::new(AbilityKind::Overdrive)
+            ])
+        );
+    }
+
+    #[test]
+    fn units_fire_after_moving_unless_the_definition_says_otherwise() {
+        let text = r#"[{"name": "A", "integrity": 1, "damage": 1, "max_attack_range": 3,
+            "min_attack_range": 2, "armor": 0, "mobility": 1, "scene": "",
+            "fire_after_move": false}]"#;
+        let (catalog, errors) = UnitCatalog::from_json(text).unwrap();
+
+        assert!(errors.is_empty());
+        assert!(!catalog.get("A").unwrap().create_unit().fire_after_move);
+        for definition in default_catalog().definitions() {
+            assert!(definition.fire_after_move);
+            assert!(definition.create_unit().fire_after_move);
+        }
+    }
+
+    #[test]
+    fn templates_carry_the_node_settings() {
+        let text = r#"[{"name": "A", "integrity": 1, "damage": 1, "max_attack_range": 1,
+            "min_attack_range": 1, "armor": 0, "mobility": 1, "scene": "res://A.tscn",
+            "scale": 1.5, "rotation_degrees": 30, "groups": ["aircraft"]},
+            {"name": "B", "integrity": 1, "damage": 1, "max_attack_range": 1,
+            "min_attack_range": 1, "armor": 0, "mobility": 1, "scene": "", "scale": 0}]"#;
+        let (catalog, errors) = UnitCatalog::from_json(text).unwrap();
+
+        assert_eq!(errors[0].message, "'scale' must be positive");
+        let template = catalog.get("A").unwrap().template();
+        assert_eq!(template.scene_file, "res://A.tscn");
+        assert_eq!((template.scale_x, template.scale_y), (1.5, 1.5));
+        assert_eq!(template.rotation_degrees, 30.0);
+        assert!(!template.visible);
+        assert_eq!(
+            template.groups,
+            vec![UNIT_GROUP.to_owned(), "aircraft".to_owned()]
+        );
+
+        let tank = default_catalog().get("Tank").unwrap().template();
+        assert_eq!((tank.scale_x, tank.z_index), (1.0, 1));
+        assert_eq!(tank.groups, vec![UNIT_GROUP.to_owned()]);
+        assert_eq!(tank.parent_path.as_deref(), Some(UNIT_CONTAINER));
+    }
+
+    #[test]
+    fn file_that_is_not_a_list_is_an_error() {
+        assert!(UnitCatalog::from_json("{}").is_err());
+    }
+
+    #[test]
+    fn duplicate_names_are_skipped() {
+        let text = format!(
+            "[{0}, {0}]",
+            r#"{"name": "A", "integrity": 1, "damage": 1, "max_attack_range": 1,
+                "min_attack_range": 1, "armor": 0, "mobility": 1, "scene": ""}"#
+        );
+        let (catalog, errors) = UnitCatalog::from_json(&text).unwrap();
+        assert_eq!(catalog.definitions().len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, 1);
+    }
+
+    #[test]
+    fn changes_are_applied_proportionally() {
+        let old = default_catalog();
+        let (new, _) = UnitCatalog::from_json(FIXTURE).unwrap();
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let tank = spawn_unit_of_type(
+            &mut world,
+            &mut ids,
+            &old,
+            0,
+            Hexagon::zero(),
+            "Tank",
+            false,
+        )
+        .unwrap();
+        let artillery = spawn_unit_of_type(
+            &mut world,
+            &mut ids,
+            &old,
+            1,
+            Hexagon::new_axial(1, 0),
+            "Artillery",
+            false,
+        )
+        .unwrap();
+        if let Some(mut entry) = world.entry(tank) {
+            let unit = entry.get_component_mut::<Unit>().unwrap();
+            unit.integrity = 10;
+            unit.remaining_range = 4;
+        }
+
+        apply_catalog_changes(&mut world, &old, &new);
+
+        let entry = world.entry(tank).unwrap();
+        let unit = entry.get_component::<Unit>().unwrap();
+        assert_eq!(unit.integrity, 15);
+        assert_eq!(unit.damage, 6);
+        assert_eq!(unit.armor, 4);
+        assert_eq!(unit.mobility, 3);
+        assert_eq!(unit.remaining_range, 3);
+
+        let entry = world.entry(artillery).unwrap();
+        let unit = entry.get_component::<Unit>().unwrap();
+        assert_eq!(*unit, old.get("Artillery").unwrap().create_unit());
+    }
+}
@@ -0,0 +1,184 @@
+//! Names of the units, so the players can tell them apart in the combat log. A new unit is named
+//! after its type with an ordinal that counts the units of the type per player, like "Tank 3".
+//! Scenarios and scripts can name units themselves.
+
+use crate::components::persistent_id::{PersistentId, PersistentIds};
+use crate::components::player::Player as PlayerComponent;
+use crate::components::unit::Unit;
+use crate::components::unit_name::UnitName;
+use crate::components::unit_type::UnitType;
+use legion::{component, Entity, EntityStore, IntoQuery, World};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Name of units without a name, and the type of units without a type.
+pub const UNNAMED_UNIT: &str = "Unit";
+
+/// Hands out the names of new units.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitNames {
+    /// Last ordinal handed out per player and unit type.
+    ordinals: BTreeMap<usize, BTreeMap<String, u32>>,
+}
+
+impl UnitNames {
+    pub fn next_name(&mut self, player: usize, unit_type: &str) -> String {
+        let ordinal = self
+            .ordinals
+            .entry(player)
+            .or_default()
+            .entry(unit_type.to_owned())
+            .or_insert(0);
+        *ordinal += 1;
+        format!("{} {}", unit_type, ordinal)
+    }
+}
+
+/// Names the units that have no name yet, in the order of their persistent ids. Returns the
+/// number of units named.
+pub fn name_new_units(world: &mut World, names: &mut UnitNames) -> usize {
+    let mut unnamed: Vec<(Option<PersistentId>, Entity, usize, String)> = <(
+        Entity,
+        Option<&PersistentId>,
+        &PlayerComponent,
+        Option<&UnitType>,
+    )>::query()
+    .filter(component::<Unit>() & !component::<UnitName>())
+    .iter(world)
+    .map(|(entity, id, player, unit_type)| {
+        let unit_type = unit_type.map_or(UNNAMED_UNIT, |unit_type| unit_type.0.as_str());
+        (id.copied(), *entity, player.0, unit_type.to_owned())
+    })
+    .collect();
+    unnamed.sort_by_key(|(id, _, _, _)| (id.is_none(), *id));
+    for (_, entity, player, unit_type) in &unnamed {
+        let name = names.next_name(*player, unit_type);
+        if let Some(mut entry) = world.entry(*entity) {
+            entry.add_component(UnitName(name));
+        }
+    }
+    unnamed.len()
+}
+
+/// The names of all named units, to describe the units that are gone by the time their events
+/// are recorded.
+pub fn unit_names<S: EntityStore>(world: &S) -> HashMap<Entity, String> {
+    <(Entity, &UnitName)>::query()
+        .iter(world)
+        .map(|(entity, name)| (*entity, name.0.clone()))
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RenameError {
+    UnknownUnit(PersistentId),
+    /// The name is empty or only white space.
+    EmptyName,
+}
+
+/// Renames the unit with the persistent id. Leading and trailing white space is dropped.
+pub fn rename_unit(
+    world: &mut World,
+    ids: &PersistentIds,
+    id: PersistentId,
+    name: &str,
+) -> Result<(), RenameError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(RenameError::EmptyName);
+    }
+    let mut entry = ids
+        .find(world, id)
+        .and_then(|entity| world.entry(entity))
+        .filter(|entry| entry.get_component::<Unit>().is_ok())
+        .ok_or(RenameError::UnknownUnit(id))?;
+    entry.add_component(UnitName(name.to_owned()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::hexagon::Hexagon;
+    use crate::spawn::{spawn_unit, spawn_unit_of_type};
+    use crate::unit_catalog::{UnitCatalog, DEFAULT_UNIT_CATALOG};
+
+    fn spawn(world: &mut World, ids: &mut PersistentIds, player: usize, q: i32, unit_type: &str) {
+        let (catalog, _) = UnitCatalog::from_json(DEFAULT_UNIT_CATALOG).unwrap();
+        spawn_unit_of_type(
+            world,
+            ids,
+            &catalog,
+            player,
+            Hexagon::new_axial(q, 0),
+            unit_type,
+            false,
+        )
+        .unwrap();
+    }
+
+    fn names_by_id(world: &World) -> Vec<String> {
+        let mut names: Vec<(PersistentId, String)> = <(&PersistentId, &UnitName)>::query()
+            .iter(world)
+            .map(|(id, name)| (*id, name.0.clone()))
+            .collect();
+        names.sort();
+        names.into_iter().map(|(_, name)| name).collect()
+    }
+
+    #[test]
+    fn ordinals_count_per_type_and_player() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let mut names = UnitNames::default();
+        spawn(&mut world, &mut ids, 0, 0, "Tank");
+        spawn(&mut world, &mut ids, 0, 1, "Artillery");
+        spawn(&mut world, &mut ids, 1, 2, "Tank");
+        spawn(&mut world, &mut ids, 0, 3, "Tank");
+        spawn_unit(
+            &mut world,
+            &mut ids,
+            1,
+            Hexagon::new_axial(4, 0),
+            Unit::new(1, 1, 1, 1, 0, 1, 1, 1),
+            None,
+        );
+
+        assert_eq!(name_new_units(&mut world, &mut names), 5);
+        assert_eq!(
+            names_by_id(&world),
+            vec!["Tank 1", "Artillery 1", "Tank 1", "Tank 2", "Unit 1"]
+        );
+
+        // Named units keep their name, later units go on counting.
+        spawn(&mut world, &mut ids, 0, 5, "Tank");
+        assert_eq!(name_new_units(&mut world, &mut names), 1);
+        assert_eq!(names_by_id(&world)[5], "Tank 3");
+        assert_eq!(name_new_units(&mut world, &mut names), 0);
+    }
+
+    #[test]
+    fn units_can_be_renamed() {
+        let mut world = World::default();
+        let mut ids = PersistentIds::default();
+        let mut names = UnitNames::default();
+        spawn(&mut world, &mut ids, 0, 0, "Tank");
+        name_new_units(&mut world, &mut names);
+        let entity = ids.find(&world, PersistentId(0)).unwrap();
+
+        assert_eq!(
+            rename_unit(&mut world, &ids, PersistentId(0), "  Old Faithful "),
+            Ok(())
+        );
+        assert_eq!(unit_names(&world)[&entity], "Old Faithful");
+        assert_eq!(
+            rename_unit(&mut world, &ids, PersistentId(0), " "),
+            Err(RenameError::EmptyName)
+        );
+        assert_eq!(
+            rename_unit(&mut world, &ids, PersistentId(7), "Ghost"),
+            Err(RenameError::UnknownUnit(PersistentId(7)))
+        );
+        assert_eq!(unit_names(&world)[&entity], "Old Faithful");
+    }
+}
@@ -0,0 +1,37 @@
+//! Weather of the current round. It is clear unless a random event changed it.
+
+use crate::fog::VISION_RANGE;
+use serde::{Deserialize, Serialize};
+
+/// How many hexagons units see in a sandstorm.
+pub const SANDSTORM_VISION_RANGE: i32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weather {
+    Clear,
+    /// Units see less far under the fog of war.
+    Sandstorm,
+}
+
+impl Weather {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Weather::Clear => "clear",
+            Weather::Sandstorm => "sandstorm",
+        }
+    }
+
+    pub fn vision_range(&self) -> i32 {
+        match self {
+            Weather::Clear => VISION_RANGE,
+            Weather::Sandstorm => SANDSTORM_VISION_RANGE,
+        }
+    }
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Weather::Clear
+    }
+}